@@ -67,6 +67,15 @@ export default defineConfig({
           items: [
             { label: "spawn init", slug: "cli/init" },
             { label: "spawn check", slug: "cli/check" },
+            { label: "spawn stats", slug: "cli/stats" },
+            { label: "spawn metrics", slug: "cli/metrics" },
+            { label: "spawn keygen", slug: "cli/keygen" },
+            { label: "spawn import", slug: "cli/import" },
+            { label: "spawn export", slug: "cli/export" },
+            { label: "spawn agent", slug: "cli/agent" },
+            { label: "spawn serve", slug: "cli/serve" },
+            { label: "spawn ui", slug: "cli/ui" },
+            { label: "spawn scrub", slug: "cli/scrub" },
             {
               label: "Migration",
               items: [
@@ -75,12 +84,37 @@ export default defineConfig({
                 { label: "spawn migration build", slug: "cli/migration-build" },
                 { label: "spawn migration apply", slug: "cli/migration-apply" },
                 { label: "spawn migration adopt", slug: "cli/migration-adopt" },
+                { label: "spawn migration gate", slug: "cli/migration-gate" },
+                {
+                  label: "spawn migration locks",
+                  slug: "cli/migration-locks",
+                },
+                {
+                  label: "spawn migration explain",
+                  slug: "cli/migration-explain",
+                },
+                {
+                  label: "spawn migration diff-envs",
+                  slug: "cli/migration-diff-envs",
+                },
                 {
                   label: "spawn migration status",
                   slug: "cli/migration-status",
                 },
+                {
+                  label: "spawn migration archive",
+                  slug: "cli/migration-archive",
+                },
+                {
+                  label: "spawn migration submit",
+                  slug: "cli/migration-submit",
+                },
               ],
             },
+            {
+              label: "Release",
+              items: [{ label: "spawn release apply", slug: "cli/release-apply" }],
+            },
             {
               label: "Test",
               items: [
@@ -89,6 +123,56 @@ export default defineConfig({
                 { label: "spawn test run", slug: "cli/test-run" },
                 { label: "spawn test compare", slug: "cli/test-compare" },
                 { label: "spawn test expect", slug: "cli/test-expect" },
+                { label: "spawn test record", slug: "cli/test-record" },
+                { label: "spawn test coverage", slug: "cli/test-coverage" },
+              ],
+            },
+            {
+              label: "Pin",
+              items: [
+                { label: "spawn pin stats", slug: "cli/pin-stats" },
+                { label: "spawn pin diff", slug: "cli/pin-diff" },
+                { label: "spawn pin export", slug: "cli/pin-export" },
+                { label: "spawn pin import", slug: "cli/pin-import" },
+                { label: "spawn pin tag", slug: "cli/pin-tag" },
+              ],
+            },
+            {
+              label: "Package",
+              items: [
+                { label: "spawn package update", slug: "cli/package-update" },
+                { label: "spawn package list", slug: "cli/package-list" },
+              ],
+            },
+            {
+              label: "Schema",
+              items: [{ label: "spawn schema docs", slug: "cli/schema-docs" }],
+            },
+            {
+              label: "Telemetry",
+              items: [
+                { label: "spawn telemetry status", slug: "cli/telemetry-status" },
+                { label: "spawn telemetry disable", slug: "cli/telemetry-disable" },
+              ],
+            },
+            {
+              label: "System",
+              items: [
+                { label: "spawn system upgrade", slug: "cli/system-upgrade" },
+              ],
+            },
+            {
+              label: "Audit",
+              items: [{ label: "spawn audit show", slug: "cli/audit-show" }],
+            },
+            {
+              label: "Vars",
+              items: [{ label: "spawn vars show", slug: "cli/vars-show" }],
+            },
+            {
+              label: "Workspace",
+              items: [
+                { label: "spawn workspace status", slug: "cli/workspace-status" },
               ],
             },
           ],
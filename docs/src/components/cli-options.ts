@@ -9,7 +9,51 @@ export const globalOptions: CLIOption[] = [
     flag: "--config-file <path>",
     description: "Path to config file. Defaults to spawn.toml.",
   },
+  {
+    flag: "--project <name>",
+    description:
+      "Use the config of this project of the workspace instead of `--config-file`.",
+  },
+  {
+    flag: "--workspace-file <path>",
+    description: "Path to the workspace file. Defaults to spawn.workspace.toml.",
+  },
   { flag: "-d, --debug", description: "Turn on debug output." },
+  {
+    flag: "--no-cache",
+    description:
+      "Read pinned objects directly from the pinned store, bypassing the local pin cache.",
+  },
+  {
+    flag: "--no-notify",
+    description:
+      "Do not post apply and adopt outcomes to the webhooks in `[notifications]`.",
+  },
+  {
+    flag: "--annotate <format>",
+    description:
+      "Also report problems as CI annotations (`github` or `none`). Defaults to `github` when `CI=true`.",
+  },
+  {
+    flag: "--strict",
+    description:
+      "Treat warnings as failures, such as pending migrations in `migration status` or building a pinned migration without `--pinned`.",
+  },
+  {
+    flag: "--deterministic",
+    description:
+      "Fail renders that use functions whose output differs each time, such as `gen_uuid_v4()` or `now()`, so builds are reproducible.",
+  },
+  {
+    flag: "-q, --quiet",
+    description:
+      "Only print the command's primary output (SQL, tables, JSON), warnings and errors.",
+  },
+  {
+    flag: "--color <when>",
+    description:
+      "When to color output: `auto` (the default) colors output written to a terminal unless `NO_COLOR` is set, `always` or `never`.",
+  },
 ];
 
 /** The --target flag. Relevant to commands that read or validate the target config. */
@@ -33,6 +77,10 @@ export const variablesOption: CLIOption[] = [
   {
     flag: "--variables <path>",
     description:
-      "Path to variables file (JSON, TOML, or YAML). Values are available in templates under {{ variables }}.",
+      "Path to variables file (JSON, TOML, or YAML), or `-` to read JSON or YAML from stdin. Values are available in templates under {{ variables }}, overriding every other source of variables.",
+  },
+  {
+    flag: "--var-json <json>",
+    description: "Variables as inline JSON, merged over those from `--variables`.",
   },
 ];
@@ -0,0 +1,268 @@
+//! Lock advisor for `migration apply`. Before anything is applied, finds
+//! statements that hold long locks on large tables, such as `CREATE INDEX`
+//! without `CONCURRENTLY`, and warns about them. Table sizes are read from
+//! the target database, so tables that do not exist yet, or are smaller than
+//! `min_table_size_mb`, are not reported. Opt-in with `[lock_advisor]` or
+//! `migration apply --advise-locks`.
+
+use crate::commands::migration::render_migration;
+use crate::commands::stats::format_bytes;
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::escape::EscapedLiteral;
+use crate::policy::{code, first_line, statements};
+use crate::sql_query;
+use crate::variables::Variables;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Settings for the lock advisor, in the `[lock_advisor]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LockAdvisorConfig {
+    /// Advise on every `migration apply`, as `--advise-locks` does
+    #[serde(default)]
+    pub enabled: bool,
+    /// Tables smaller than this, in MB, are not reported
+    #[serde(default = "default_min_table_size_mb")]
+    pub min_table_size_mb: u64,
+}
+
+fn default_min_table_size_mb() -> u64 {
+    100
+}
+
+impl Default for LockAdvisorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_table_size_mb: default_min_table_size_mb(),
+        }
+    }
+}
+
+/// Statements that lock a whole table for as long as they take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockRiskKind {
+    /// `CREATE INDEX` without `CONCURRENTLY` blocks writes to the table
+    /// while the index builds
+    CreateIndex,
+    /// `SET NOT NULL` scans the table under an ACCESS EXCLUSIVE lock
+    SetNotNull,
+}
+
+/// A statement that locks `table`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LockRisk {
+    pub kind: LockRiskKind,
+    /// The table as the statement names it
+    pub table: String,
+    /// The first line of the statement
+    pub statement: String,
+}
+
+/// A lock risk on a table large enough to report.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LockWarning {
+    pub migration: String,
+    pub risk: LockRisk,
+    /// Size of the table and its indexes
+    pub bytes: u64,
+}
+
+impl fmt::Display for LockWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = format_bytes(self.bytes);
+        match self.risk.kind {
+            LockRiskKind::CreateIndex => write!(
+                f,
+                "migration {} creates an index on {} ({}) without CONCURRENTLY, blocking writes to it until the index is built: {}",
+                self.migration, self.risk.table, size, self.risk.statement
+            ),
+            LockRiskKind::SetNotNull => write!(
+                f,
+                "migration {} sets NOT NULL on {} ({}), scanning it under an ACCESS EXCLUSIVE lock; add a CHECK (... IS NOT NULL) NOT VALID constraint and VALIDATE it first: {}",
+                self.migration, self.risk.table, size, self.risk.statement
+            ),
+        }
+    }
+}
+
+/// Words of `code`, with `(`, `)` and `,` as words of their own and `;`
+/// left out.
+fn tokens(code: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    for word in code.split(|c: char| c.is_whitespace() || c == ';') {
+        let mut start = 0;
+        for (i, c) in word.char_indices() {
+            if matches!(c, '(' | ')' | ',') {
+                if start < i {
+                    tokens.push(&word[start..i]);
+                }
+                tokens.push(&word[i..i + 1]);
+                start = i + 1;
+            }
+        }
+        if start < word.len() {
+            tokens.push(&word[start..]);
+        }
+    }
+    tokens
+}
+
+/// The lock risk of `statement`, if it has one.
+fn lock_risk(statement: &str) -> Option<LockRisk> {
+    let code = code(statement);
+    let tokens = tokens(&code);
+    let is = |i: usize, word: &str| tokens.get(i).is_some_and(|t| t.eq_ignore_ascii_case(word));
+    // The table named at token `i`, after any IF EXISTS and ONLY.
+    let table_at = |mut i: usize| -> Option<String> {
+        if is(i, "IF") && is(i + 1, "EXISTS") {
+            i += 2;
+        }
+        if is(i, "ONLY") {
+            i += 1;
+        }
+        tokens
+            .get(i)
+            .filter(|t| !matches!(**t, "(" | ")" | ","))
+            .map(|t| t.to_string())
+    };
+
+    let kind_and_table = if is(0, "CREATE") && (is(1, "INDEX") || is(1, "UNIQUE") && is(2, "INDEX"))
+    {
+        let on = tokens.iter().position(|t| t.eq_ignore_ascii_case("ON"))?;
+        if tokens[..on]
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case("CONCURRENTLY"))
+        {
+            return None;
+        }
+        (LockRiskKind::CreateIndex, table_at(on + 1)?)
+    } else if is(0, "ALTER")
+        && is(1, "TABLE")
+        && (0..tokens.len()).any(|i| is(i, "SET") && is(i + 1, "NOT") && is(i + 2, "NULL"))
+    {
+        (LockRiskKind::SetNotNull, table_at(2)?)
+    } else {
+        return None;
+    };
+
+    Some(LockRisk {
+        kind: kind_and_table.0,
+        table: kind_and_table.1,
+        statement: first_line(statement).to_string(),
+    })
+}
+
+/// Every statement of `sql` that locks a table, in order.
+pub fn lock_risks(sql: &str) -> Vec<LockRisk> {
+    statements(sql)
+        .iter()
+        .filter_map(|s| lock_risk(s))
+        .collect()
+}
+
+/// Size in bytes of each of `tables` that exists in the target database,
+/// including its indexes and TOAST.
+pub async fn table_sizes(engine: &dyn Engine, tables: &[String]) -> Result<BTreeMap<String, u64>> {
+    let mut sizes = BTreeMap::new();
+    for table in tables {
+        let query = sql_query!(
+            "SELECT pg_total_relation_size(c) AS bytes FROM to_regclass({}) AS c WHERE c IS NOT NULL;",
+            EscapedLiteral::new(table)
+        );
+        let rows = engine
+            .query(&query)
+            .await
+            .context(format!("could not read the size of {}", table))?;
+        let bytes = rows.iter().next().and_then(|row| row.get("bytes"));
+        if let Some(bytes) = bytes {
+            sizes.insert(table.clone(), bytes.parse()?);
+        }
+    }
+    Ok(sizes)
+}
+
+/// Renders each of `migrations` and reports the statements that would lock
+/// a table of at least `min_table_size_mb`, in order.
+pub async fn advise(
+    config: &Config,
+    engine: &dyn Engine,
+    migrations: &[String],
+    pinned: bool,
+    variables: Option<Variables>,
+) -> Result<Vec<LockWarning>> {
+    let mut rendered = Vec::new();
+    for migration in migrations {
+        let sql = render_migration(config, migration, pinned, variables.clone()).await?;
+        rendered.push((migration.clone(), sql));
+    }
+    advise_rendered(config, engine, &rendered).await
+}
+
+/// As [`advise`], for migrations already rendered, given as pairs of the
+/// migration's name and its SQL.
+pub async fn advise_rendered(
+    config: &Config,
+    engine: &dyn Engine,
+    rendered: &[(String, String)],
+) -> Result<Vec<LockWarning>> {
+    let mut risks = Vec::new();
+    for (migration, sql) in rendered {
+        for risk in lock_risks(sql) {
+            risks.push((migration.clone(), risk));
+        }
+    }
+    if risks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut tables: Vec<String> = risks.iter().map(|(_, risk)| risk.table.clone()).collect();
+    tables.sort();
+    tables.dedup();
+    let sizes = table_sizes(engine, &tables).await?;
+    let min_bytes = config.lock_advisor.min_table_size_mb * 1024 * 1024;
+    Ok(risks
+        .into_iter()
+        .filter_map(|(migration, risk)| {
+            let bytes = *sizes.get(&risk.table)?;
+            (bytes >= min_bytes).then_some(LockWarning {
+                migration,
+                risk,
+                bytes,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_risks() {
+        let sql = "CREATE INDEX users_email_idx ON users (email);
+CREATE UNIQUE INDEX IF NOT EXISTS orders_ref ON ONLY public.orders(ref);
+CREATE INDEX CONCURRENTLY users_name_idx ON users (name);
+ALTER TABLE IF EXISTS accounts ALTER COLUMN email SET NOT NULL;
+ALTER TABLE accounts ALTER COLUMN email DROP NOT NULL;
+-- CREATE INDEX x ON commented_out (y);
+INSERT INTO log VALUES ('CREATE INDEX x ON quoted (y)');
+";
+        let risks: Vec<(LockRiskKind, String)> = lock_risks(sql)
+            .into_iter()
+            .map(|r| (r.kind, r.table))
+            .collect();
+        assert_eq!(
+            vec![
+                (LockRiskKind::CreateIndex, "users".to_string()),
+                (LockRiskKind::CreateIndex, "public.orders".to_string()),
+                (LockRiskKind::SetNotNull, "accounts".to_string()),
+            ],
+            risks
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Two-phase applies: CI renders a migration and signs it as an apply
+//! request with `spawn migration submit`, and an agent running beside the
+//! database (`spawn agent`) verifies and applies it, so that CI never needs
+//! credentials for the database.
+//!
+//! Requests and results are JSON files in the agent queue, a folder of the
+//! spawn folder's store set with `[agent] queue`:
+//!
+//! ```text
+//! agent/requests/<id>.json
+//! agent/results/<id>.json
+//! ```
+//!
+//! A request is pending until a result with its id has been written.
+
+use crate::config::Config;
+use crate::signing::{encode_public_key, sign_message, verify_message};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use ed25519_dalek::SigningKey;
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// Version of the apply request format written by this spawn.
+pub const APPLY_REQUEST_VERSION: u32 = 1;
+
+/// Folder of the queue when `queue` is not set, relative to the spawn
+/// folder.
+const DEFAULT_QUEUE: &str = "agent";
+
+/// Configuration for `spawn migration submit` and `spawn agent`, in the
+/// `[agent]` section.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AgentConfig {
+    /// Folder holding the requests and results, relative to the spawn
+    /// folder. Defaults to `agent`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<String>,
+}
+
+/// A rendered migration to apply to a target, signed by whoever submitted
+/// it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ApplyRequest {
+    pub version: u32,
+    /// Unique name of the request, also the name of its file in the queue
+    pub id: String,
+    pub migration: String,
+    /// Target the migration was rendered for
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    /// When the request was submitted (RFC 3339)
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    /// The rendered SQL to run
+    pub sql: String,
+    pub sql_sha256: String,
+    /// Base64 public key of the signer
+    #[serde(default)]
+    pub key: String,
+    /// Base64 ed25519 signature of the request
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl ApplyRequest {
+    /// An unsigned request to apply `sql` as `migration` to the config's
+    /// target.
+    pub fn new(config: &Config, migration: &str, sql: String) -> Result<Self> {
+        let target = config.target.clone().ok_or(anyhow!("no target selected"))?;
+        let now = Utc::now();
+        Ok(ApplyRequest {
+            version: APPLY_REQUEST_VERSION,
+            id: format!("{}-{}-{}", now.format("%Y%m%d%H%M%S%3f"), target, migration),
+            migration: migration.to_string(),
+            target,
+            environment: config.policy_environment(),
+            created_at: now.to_rfc3339(),
+            created_by: config.operator_identity(),
+            sql_sha256: format!("{:x}", Sha256::digest(sql.as_bytes())),
+            sql,
+            key: String::new(),
+            signature: String::new(),
+        })
+    }
+
+    /// The bytes that are signed. The SQL is covered through its hash.
+    fn message(&self) -> Vec<u8> {
+        format!(
+            "spawn-apply-request-v{}\nid:{}\nmigration:{}\ntarget:{}\nsql:{}\n",
+            self.version, self.id, self.migration, self.target, self.sql_sha256
+        )
+        .into_bytes()
+    }
+
+    pub fn sign(mut self, key: &SigningKey) -> Self {
+        self.key = encode_public_key(&key.verifying_key());
+        self.signature = sign_message(&self.message(), key);
+        self
+    }
+
+    /// Checks that the request is signed by one of the trusted keys and that
+    /// its SQL has not changed since.
+    pub fn verify(&self, trusted_keys: &[String]) -> Result<()> {
+        if self.version != APPLY_REQUEST_VERSION {
+            return Err(anyhow!(
+                "request is version {}, but this version of spawn reads version {}",
+                self.version,
+                APPLY_REQUEST_VERSION
+            ));
+        }
+        if format!("{:x}", Sha256::digest(self.sql.as_bytes())) != self.sql_sha256 {
+            return Err(anyhow!("SQL has changed since the request was signed"));
+        }
+        verify_message(&self.message(), &self.key, &self.signature, trusted_keys)
+    }
+}
+
+/// How an agent handled a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyResultStatus {
+    Applied,
+    /// The migration had already been applied, so nothing was run
+    Skipped,
+    Failed,
+    /// The request was not trusted or not meant for this agent
+    Rejected,
+}
+
+/// What an agent writes back for a request.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ApplyResult {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<String>,
+    pub status: ApplyResultStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the agent finished with the request (RFC 3339)
+    pub finished_at: String,
+    /// Who ran the agent, from [`Config::operator_identity`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+}
+
+impl ApplyResult {
+    pub fn new(
+        config: &Config,
+        id: &str,
+        migration: Option<&str>,
+        status: ApplyResultStatus,
+        error: Option<String>,
+    ) -> Self {
+        ApplyResult {
+            id: id.to_string(),
+            migration: migration.map(str::to_string),
+            status,
+            error,
+            finished_at: Utc::now().to_rfc3339(),
+            agent: config.operator_identity(),
+        }
+    }
+}
+
+/// A request file found in the queue, which may not have parsed.
+pub struct QueuedRequest {
+    /// Name of the file without `.json`
+    pub id: String,
+    pub request: Result<ApplyRequest>,
+}
+
+/// The requests and results of the agent queue.
+pub struct AgentQueue {
+    op: Operator,
+    folder: String,
+}
+
+impl AgentQueue {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            op: config.operator().clone(),
+            folder: format!(
+                "{}/{}",
+                config.pather().spawn_folder_path(),
+                config.agent.queue.as_deref().unwrap_or(DEFAULT_QUEUE)
+            ),
+        }
+    }
+
+    fn request_path(&self, id: &str) -> String {
+        format!("{}/requests/{}.json", self.folder, id)
+    }
+
+    fn result_path(&self, id: &str) -> String {
+        format!("{}/results/{}.json", self.folder, id)
+    }
+
+    /// Adds `request` to the queue, returning the path it was written to.
+    pub async fn submit(&self, request: &ApplyRequest) -> Result<String> {
+        let path = self.request_path(&request.id);
+        self.op
+            .write(&path, serde_json::to_vec_pretty(request)?)
+            .await
+            .context(format!("could not write request {}", path))?;
+        Ok(path)
+    }
+
+    /// Names of the `.json` files in `kind` (`requests` or `results`).
+    async fn ids(&self, kind: &str) -> Result<Vec<String>> {
+        let folder = format!("{}/{}/", self.folder, kind);
+        match self.op.list(&folder).await {
+            Ok(entries) => Ok(entries
+                .iter()
+                .filter_map(|e| e.name().strip_suffix(".json").map(str::to_string))
+                .filter(|id| !id.is_empty())
+                .collect()),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).context(format!("could not list {}", folder)),
+        }
+    }
+
+    /// Requests without a result, oldest first.
+    pub async fn pending(&self) -> Result<Vec<QueuedRequest>> {
+        let done: HashSet<String> = self.ids("results").await?.into_iter().collect();
+        let mut ids: Vec<String> = self
+            .ids("requests")
+            .await?
+            .into_iter()
+            .filter(|id| !done.contains(id))
+            .collect();
+        ids.sort();
+
+        let mut pending = Vec::new();
+        for id in ids {
+            let path = self.request_path(&id);
+            let request = match self.op.read(&path).await {
+                Ok(bytes) => serde_json::from_slice(&bytes.to_vec())
+                    .context(format!("request {} is not valid", path)),
+                Err(e) => Err(e).context(format!("could not read request {}", path)),
+            };
+            pending.push(QueuedRequest { id, request });
+        }
+        Ok(pending)
+    }
+
+    pub async fn write_result(&self, result: &ApplyResult) -> Result<String> {
+        let path = self.result_path(&result.id);
+        self.op
+            .write(&path, serde_json::to_vec_pretty(result)?)
+            .await
+            .context(format!("could not write result {}", path))?;
+        Ok(path)
+    }
+
+    /// The result of request `id`, or None if no agent has handled it yet.
+    pub async fn result(&self, id: &str) -> Result<Option<ApplyResult>> {
+        let path = self.result_path(id);
+        match self.op.read(&path).await {
+            Ok(bytes) => Ok(Some(
+                serde_json::from_slice(&bytes.to_vec())
+                    .context(format!("result {} is not valid", path))?,
+            )),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("could not read result {}", path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::generate_key;
+
+    fn request() -> ApplyRequest {
+        let sql = "CREATE TABLE users (id int);\n".to_string();
+        ApplyRequest {
+            version: APPLY_REQUEST_VERSION,
+            id: "20240101000000000-prod-20240101-users".to_string(),
+            migration: "20240101-users".to_string(),
+            target: "prod".to_string(),
+            environment: None,
+            created_at: "2024-01-01T00:00:00+00:00".to_string(),
+            created_by: None,
+            sql_sha256: format!("{:x}", Sha256::digest(sql.as_bytes())),
+            sql,
+            key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_apply_request_signature() {
+        let key = generate_key();
+        let trusted = vec![encode_public_key(&key.verifying_key())];
+        let signed = request().sign(&key);
+        signed.verify(&trusted).unwrap();
+
+        let mut tampered = signed.clone();
+        tampered.sql.push_str("DROP TABLE users;\n");
+        assert!(tampered.verify(&trusted).is_err());
+
+        let mut retargeted = signed.clone();
+        retargeted.target = "staging".to_string();
+        assert!(retargeted.verify(&trusted).is_err());
+
+        let other = vec![encode_public_key(&generate_key().verifying_key())];
+        assert!(signed.verify(&other).is_err());
+        assert!(request().verify(&trusted).is_err());
+    }
+}
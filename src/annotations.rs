@@ -0,0 +1,170 @@
+//! Annotations that CI systems show inline on pull requests, shared by the
+//! commands that report problems in project files.
+
+use clap::ValueEnum;
+
+/// How problems are additionally reported for a CI system.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum AnnotationFormat {
+    /// Only print the usual human readable output
+    #[default]
+    None,
+    /// GitHub Actions workflow commands (`::error file=...,line=...::`)
+    Github,
+}
+
+impl AnnotationFormat {
+    /// The format chosen with `--annotate`, or GitHub annotations when
+    /// running under CI (`CI=true`).
+    pub fn detect(flag: Option<AnnotationFormat>) -> AnnotationFormat {
+        match flag {
+            Some(format) => format,
+            None if std::env::var("CI").is_ok_and(|v| v.eq_ignore_ascii_case("true")) => {
+                AnnotationFormat::Github
+            }
+            None => AnnotationFormat::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnotationLevel {
+    Error,
+    Warning,
+}
+
+/// A problem, optionally tied to a file and line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub level: AnnotationLevel,
+    pub file: Option<String>,
+    /// 1-based line number within `file`
+    pub line: Option<usize>,
+    pub title: Option<String>,
+    pub message: String,
+}
+
+impl Annotation {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            level: AnnotationLevel::Error,
+            file: None,
+            line: None,
+            title: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            level: AnnotationLevel::Warning,
+            ..Self::error(message)
+        }
+    }
+
+    /// Ties the annotation to a file. Leading `./` and `/` are removed, since
+    /// annotation paths are relative to the repository root.
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(
+            file.trim_start_matches("./")
+                .trim_start_matches('/')
+                .to_string(),
+        );
+        self
+    }
+
+    pub fn with_line(mut self, line: usize) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Renders the annotation in `format`, or None when the format has no
+    /// annotations.
+    pub fn render(&self, format: AnnotationFormat) -> Option<String> {
+        match format {
+            AnnotationFormat::None => None,
+            AnnotationFormat::Github => Some(self.render_github()),
+        }
+    }
+
+    fn render_github(&self) -> String {
+        let command = match self.level {
+            AnnotationLevel::Error => "error",
+            AnnotationLevel::Warning => "warning",
+        };
+
+        let mut properties = Vec::new();
+        if let Some(file) = &self.file {
+            properties.push(format!("file={}", escape_property(file)));
+        }
+        if let Some(line) = self.line {
+            properties.push(format!("line={}", line));
+        }
+        if let Some(title) = &self.title {
+            properties.push(format!("title={}", escape_property(title)));
+        }
+
+        if properties.is_empty() {
+            format!("::{}::{}", command, escape_data(&self.message))
+        } else {
+            format!(
+                "::{} {}::{}",
+                command,
+                properties.join(","),
+                escape_data(&self.message)
+            )
+        }
+    }
+
+    /// Prints the annotation to stdout, where CI systems look for it.
+    pub fn emit(&self, format: AnnotationFormat) {
+        if let Some(line) = self.render(format) {
+            println!("{}", line);
+        }
+    }
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_error_with_file_and_line() {
+        let annotation = Annotation::error("expected output differs")
+            .with_file("./tests/users/expected")
+            .with_line(12)
+            .with_title("Test users failed");
+        assert_eq!(
+            Some(
+                "::error file=tests/users/expected,line=12,title=Test users failed::expected output differs"
+                    .to_string()
+            ),
+            annotation.render(AnnotationFormat::Github)
+        );
+        assert_eq!(None, annotation.render(AnnotationFormat::None));
+    }
+
+    #[test]
+    fn test_github_escaping() {
+        let annotation = Annotation::warning("50% done\nnext line").with_title("a, b: c");
+        assert_eq!(
+            "::warning title=a%2C b%3A c::50%25 done%0Anext line",
+            annotation.render(AnnotationFormat::Github).unwrap()
+        );
+    }
+}
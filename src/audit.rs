@@ -0,0 +1,188 @@
+//! Audit log of the commands run against this project: every command that
+//! changes the project or a database is appended to `.audit.jsonl` in the
+//! spawn folder with who ran it, when, against which target, the migrations
+//! it touched and how it ended. Unlike the migration history in the
+//! database, this also records commands that never reached the database,
+//! such as an apply refused by a policy or a migration pinned locally.
+//! Shown with `spawn audit show`.
+
+use crate::config::Config;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// File the audit log is written to when `path` is not set, relative to the
+/// spawn folder.
+const DEFAULT_AUDIT_FILE: &str = ".audit.jsonl";
+
+/// Configuration for the audit log, in the `[audit]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditConfig {
+    /// Set to false to stop writing the audit log.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// File to append to, relative to the spawn folder. Defaults to
+    /// `.audit.jsonl`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+        }
+    }
+}
+
+/// How an audited command ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// One line of the audit log.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AuditEntry {
+    /// When the command finished (RFC 3339)
+    pub timestamp: String,
+    /// Who ran the command, from [`Config::operator_identity`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// The command, such as `migration apply`
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    /// Migrations the command created, pinned, applied or adopted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub migrations: Vec<String>,
+    pub outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// An entry for `command`, run now by the current user against the
+    /// selected target, with the migrations recorded in the config's audit
+    /// session.
+    pub fn new(config: &Config, command: &str, result: Result<(), &anyhow::Error>) -> Self {
+        AuditEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            user: config.operator_identity(),
+            command: command.to_string(),
+            target: config.target.clone(),
+            environment: config.policy_environment(),
+            migrations: config.audit_session.migrations(),
+            outcome: match result {
+                Ok(()) => AuditOutcome::Success,
+                Err(_) => AuditOutcome::Failure,
+            },
+            error: result.err().map(|e| format!("{:#}", e)),
+        }
+    }
+}
+
+/// Collects the migrations touched by the running command, for its audit
+/// entry.
+#[derive(Clone, Debug, Default)]
+pub struct AuditSession {
+    migrations: Arc<Mutex<Vec<String>>>,
+}
+
+impl AuditSession {
+    /// Notes that the command touched `migration`.
+    pub fn record_migration(&self, migration: &str) {
+        let mut migrations = self.migrations.lock().unwrap();
+        if !migrations.iter().any(|m| m == migration) {
+            migrations.push(migration.to_string());
+        }
+    }
+
+    pub fn migrations(&self) -> Vec<String> {
+        self.migrations.lock().unwrap().clone()
+    }
+}
+
+/// The user running spawn, as far as the environment says.
+pub fn current_user() -> Option<String> {
+    ["USER", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .filter(|user| !user.trim().is_empty())
+}
+
+/// Path of the audit log on the config's operator, or None when it is
+/// disabled.
+pub fn audit_path(config: &Config) -> Option<String> {
+    let audit = config.audit.as_ref()?;
+    Some(format!(
+        "{}/{}",
+        config.pather().spawn_folder_path(),
+        audit.path.as_deref().unwrap_or(DEFAULT_AUDIT_FILE)
+    ))
+}
+
+/// Appends `entry` to the audit log, unless it is disabled.
+pub async fn append_audit_entry(config: &Config, entry: &AuditEntry) -> Result<()> {
+    let Some(path) = audit_path(config) else {
+        return Ok(());
+    };
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    let op = config.operator();
+    let written = if op.info().full_capability().write_can_append {
+        op.write_with(&path, line).append(true).await
+    } else {
+        // Stores that cannot append are rewritten whole.
+        let mut contents = match op.read(&path).await {
+            Ok(existing) => existing.to_vec(),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context(format!("could not read audit log {}", path)),
+        };
+        contents.extend_from_slice(line.as_bytes());
+        op.write(&path, contents).await
+    };
+    written.context(format!("could not write audit log {}", path))?;
+    Ok(())
+}
+
+/// Every entry of the audit log, oldest first. Lines that cannot be read
+/// are skipped with a warning.
+pub async fn read_audit_log(config: &Config) -> Result<Vec<AuditEntry>> {
+    let Some(path) = audit_path(config) else {
+        return Ok(Vec::new());
+    };
+    let bytes = match config.operator().read(&path).await {
+        Ok(bytes) => bytes.to_vec(),
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(format!("could not read audit log {}", path)),
+    };
+
+    let mut entries = Vec::new();
+    for (i, line) in String::from_utf8_lossy(&bytes).lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => config.events.on_warning(&format!(
+                "{}:{}: skipping audit entry: {}",
+                path,
+                i + 1,
+                e
+            )),
+        }
+    }
+    Ok(entries)
+}
@@ -1,12 +1,31 @@
+use crate::annotations::AnnotationFormat;
+use crate::audit::{append_audit_entry, AuditEntry};
+use crate::commands::export::ExportFormat;
+use crate::commands::import::ImportSource;
+use crate::commands::migration::StatusFilter;
 use crate::commands::{
-    AdoptMigration, ApplyMigration, BuildMigration, BuildTest, Check, Command, CompareTests,
-    ExpectTest, Init, MigrationStatus, NewMigration, NewTest, Outcome, PinMigration, RunTest,
-    TelemetryDescribe, TelemetryInfo,
+    AdoptMigration, Agent, ApplyMigration, ArchiveMigrations, AuditShow, BuildAllMigrations,
+    BuildMigration, BuildTest, Check, CheckBuiltMigrations, Command, CompareTests, ExpectTest,
+    ExplainMigration, Export, Import, Init, Keygen, Metrics, MigrationDiffEnvs, MigrationGate,
+    MigrationLocks, MigrationStatus, NewMigration, NewTest, Outcome, PackageList, PackageUpdate,
+    PinDiff, PinExport, PinImport, PinMigration, PinStats, PinTag, PinTags, RecordTest,
+    ReleaseApply, RunTest, SchemaDocs, Scrub, Serve, Stats, SubmitMigration, SystemUpgrade,
+    TelemetryDescribe, TelemetryDisable, TelemetryInfo, TelemetryStatus, TestCoverage, Ui,
+    VarsShow, WorkspaceStatus,
 };
 use crate::config::Config;
+use crate::errors::ErrorKind;
+use crate::events::{ConsoleEvents, Events};
+use crate::notify::NotificationsConfig;
+use crate::output::ColorChoice;
+use crate::schedule::{MaintenanceWindow, Schedule};
+use crate::telemetry::TelemetrySession;
+use crate::variables::Variables;
+use crate::workspace::{Workspace, WORKSPACE_FILE};
 use opendal::Operator;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
@@ -22,6 +41,50 @@ pub struct Cli {
     #[arg(global = true, long)]
     pub target: Option<String>,
 
+    /// Run the command in this project of the workspace, using its config
+    /// instead of --config-file
+    #[arg(global = true, long)]
+    pub project: Option<String>,
+
+    /// Workspace file listing the projects for --project and `workspace`
+    #[arg(global = true, long, default_value = WORKSPACE_FILE)]
+    pub workspace_file: String,
+
+    /// Read pinned objects directly from the pinned store, bypassing the
+    /// local cache
+    #[arg(global = true, long)]
+    pub no_cache: bool,
+
+    /// Do not post apply and adopt outcomes to the webhooks in
+    /// [notifications]
+    #[arg(global = true, long)]
+    pub no_notify: bool,
+
+    /// Also report problems as CI annotations. Defaults to github when the
+    /// CI environment variable is true.
+    #[arg(global = true, long, value_enum)]
+    pub annotate: Option<AnnotationFormat>,
+
+    /// Treat warnings as failures, e.g. pending migrations in `migration
+    /// status` or building a pinned migration without --pinned
+    #[arg(global = true, long)]
+    pub strict: bool,
+
+    /// Only print each command's primary output (SQL, tables, JSON),
+    /// warnings and errors
+    #[arg(global = true, short, long)]
+    pub quiet: bool,
+
+    /// When to color output. `auto` colors output written to a terminal
+    /// unless the NO_COLOR environment variable is set
+    #[arg(global = true, long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Fail renders that use functions whose output differs each time, such
+    /// as gen_uuid_v4() or now(), so builds are reproducible
+    #[arg(global = true, long)]
+    pub deterministic: bool,
+
     /// Internal flag for telemetry child process (hidden)
     #[arg(long, hide = true)]
     pub internal_telemetry: bool,
@@ -50,16 +113,219 @@ pub enum Commands {
     },
     /// Check for potential issues (unpinned migrations, etc.)
     Check,
+    /// Show aggregate statistics about the project and its migration history
+    Stats {
+        /// Output statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print Prometheus metrics about the target's migrations (applied,
+    /// pending, failed, last apply), for the node_exporter textfile collector
+    Metrics {
+        /// Write the metrics to this file instead of stdout, such as
+        /// /var/lib/node_exporter/textfile/spawn.prom
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Generate an ed25519 key pair for signing migrations
+    Keygen {
+        /// Path to write the private key to
+        #[arg(long, default_value = "spawn-signing.key")]
+        out: String,
+    },
+    /// Convert the migrations of Flyway, sqitch or golang-migrate into
+    /// spawn migrations, and print a report of what was done
+    Import {
+        /// Tool the migrations were written for
+        #[arg(long, value_enum)]
+        from: ImportSource,
+
+        /// Folder holding the tool's migrations (for sqitch, the folder with
+        /// sqitch.plan)
+        dir: String,
+
+        /// Adopt the migrations the tool's history table on the target says
+        /// are applied
+        #[arg(long)]
+        adopt: bool,
+
+        /// The tool's history table, such as public.flyway_schema_history
+        #[arg(long, requires = "adopt")]
+        history_table: Option<String>,
+
+        /// Also write the report to this file
+        #[arg(long, value_name = "FILE")]
+        report: Option<String>,
+
+        /// Skip confirmation prompt
+        #[arg(long)]
+        yes: bool,
+
+        /// With --yes, adopt on a protected target without typing its name.
+        /// Also needs SPAWN_ALLOW_PROTECTED to be set.
+        #[arg(long, requires = "yes")]
+        force: bool,
+    },
+    /// Render every migration into a folder of numbered plain SQL files
+    /// with an index.toml, for other tools or review outside spawn
+    Export {
+        /// Format to write
+        #[arg(long, value_enum, default_value_t = ExportFormat::PlainSql)]
+        format: ExportFormat,
+
+        /// Folder to write to. Files from an earlier export to it are
+        /// replaced.
+        dir: String,
+
+        /// Whether to use pinned components
+        #[arg(long)]
+        pinned: bool,
+
+        /// Path to a variables file (JSON, TOML, or YAML) to use for
+        /// templating, or `-` to read JSON or YAML from stdin
+        #[arg(long)]
+        variables: Option<String>,
+
+        /// Variables as inline JSON, merged over those from --variables
+        #[arg(long, value_name = "JSON")]
+        var_json: Option<String>,
+    },
+    /// Apply the signed requests of `migration submit` to the target,
+    /// polling the agent queue, and write back how each went
+    Agent {
+        /// Handle the pending requests once and exit
+        #[arg(long)]
+        once: bool,
+        /// Seconds to wait between polls of the queue
+        #[arg(long, default_value_t = 10)]
+        interval: u64,
+        /// Read one request from stdin and print its result instead of
+        /// using the queue
+        #[arg(long, conflicts_with_all = ["once", "interval"])]
+        stdin: bool,
+    },
+    /// Serve an HTTP API for status, build, apply and test runs, for
+    /// platforms that drive spawn without shelling out
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: String,
+    },
+    /// Anonymize a copy of a database with the rules in scrub.toml
+    Scrub {
+        /// Rules file, relative to the spawn folder. Defaults to [scrub]
+        /// rules, or scrub.toml
+        #[arg(long)]
+        rules: Option<String>,
+        /// Print the SQL instead of running it
+        #[arg(long)]
+        print: bool,
+        /// Skip the confirmation prompt
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Open a terminal dashboard of migration status, history and test
+    /// results, with keys to apply, pin and inspect migrations
+    Ui,
     Migration {
         #[command(subcommand)]
         command: Option<MigrationCommands>,
         #[arg(short, long, global = true)]
         environment: Option<String>,
     },
+    Release {
+        #[command(subcommand)]
+        command: Option<ReleaseCommands>,
+        #[arg(short, long, global = true)]
+        environment: Option<String>,
+    },
     Test {
         #[command(subcommand)]
         command: Option<TestCommands>,
     },
+    /// Manage the pinned component store
+    Pin {
+        #[command(subcommand)]
+        command: Option<PinCommands>,
+    },
+    /// Fetch and inspect external component packages
+    Package {
+        #[command(subcommand)]
+        command: Option<PackageCommands>,
+    },
+    /// Document the target database's tables
+    Schema {
+        #[command(subcommand)]
+        command: Option<SchemaCommands>,
+    },
+    /// Show or change anonymous usage telemetry
+    Telemetry {
+        #[command(subcommand)]
+        command: Option<TelemetryCommands>,
+    },
+    /// Manage spawn's own schema in the target database
+    System {
+        #[command(subcommand)]
+        command: Option<SystemCommands>,
+    },
+    /// Show the local log of commands that changed the project or a
+    /// database
+    Audit {
+        #[command(subcommand)]
+        command: Option<AuditCommands>,
+    },
+    /// Inspect the variables given to templates
+    Vars {
+        #[command(subcommand)]
+        command: Option<VarsCommands>,
+        #[arg(short, long, global = true)]
+        environment: Option<String>,
+    },
+    /// Work across the projects of a spawn.workspace.toml
+    Workspace {
+        #[command(subcommand)]
+        command: Option<WorkspaceCommands>,
+    },
+}
+
+impl Commands {
+    /// Whether the command changes the project or a database, and so is
+    /// written to the audit log.
+    pub fn changes_state(&self) -> bool {
+        match self {
+            Commands::Migration {
+                command: Some(command),
+                ..
+            } => match command {
+                MigrationCommands::New { .. }
+                | MigrationCommands::Apply { .. }
+                | MigrationCommands::Adopt { .. } => true,
+                MigrationCommands::Archive { dry_run, .. } => !dry_run,
+                MigrationCommands::Submit { stdout, .. } => !stdout,
+                MigrationCommands::Locks { steal, .. } => *steal,
+                _ => false,
+            },
+            Commands::Release {
+                command: Some(ReleaseCommands::Apply { .. }),
+                ..
+            }
+            | Commands::System {
+                command: Some(SystemCommands::Upgrade),
+            }
+            | Commands::Pin {
+                command: Some(PinCommands::Import { .. } | PinCommands::Tag { .. }),
+            }
+            | Commands::Package {
+                command: Some(PackageCommands::Update { .. }),
+            }
+            | Commands::Import { .. }
+            | Commands::Agent { .. }
+            | Commands::Serve { .. }
+            | Commands::Ui
+            | Commands::Scrub { print: false, .. } => true,
+            _ => false,
+        }
+    }
 }
 
 impl TelemetryDescribe for Commands {
@@ -67,6 +333,46 @@ impl TelemetryDescribe for Commands {
         match self {
             Commands::Init { .. } => TelemetryInfo::new("init"),
             Commands::Check => TelemetryInfo::new("check"),
+            Commands::Stats { json } => {
+                TelemetryInfo::new("stats").with_properties(vec![("opt_json", json.to_string())])
+            }
+            Commands::Metrics { output } => TelemetryInfo::new("metrics")
+                .with_properties(vec![("has_output", output.is_some().to_string())]),
+            Commands::Keygen { .. } => TelemetryInfo::new("keygen"),
+            Commands::Export {
+                format,
+                pinned,
+                variables,
+                var_json,
+                ..
+            } => TelemetryInfo::new("export").with_properties(vec![
+                ("format", format.to_string()),
+                ("opt_pinned", pinned.to_string()),
+                (
+                    "has_variables",
+                    (variables.is_some() || var_json.is_some()).to_string(),
+                ),
+            ]),
+            Commands::Import { from, adopt, .. } => {
+                TelemetryInfo::new("import").with_properties(vec![
+                    ("from", from.to_string()),
+                    ("opt_adopt", adopt.to_string()),
+                ])
+            }
+            Commands::Agent { once, stdin, .. } => {
+                TelemetryInfo::new("agent").with_properties(vec![
+                    ("opt_once", once.to_string()),
+                    ("opt_stdin", stdin.to_string()),
+                ])
+            }
+            Commands::Serve { .. } => TelemetryInfo::new("serve"),
+            Commands::Ui => TelemetryInfo::new("ui"),
+            Commands::Scrub { print, yes, .. } => {
+                TelemetryInfo::new("scrub").with_properties(vec![
+                    ("opt_print", print.to_string()),
+                    ("opt_yes", yes.to_string()),
+                ])
+            }
             Commands::Migration { command, .. } => match command {
                 Some(cmd) => {
                     let mut info = cmd.telemetry();
@@ -75,6 +381,14 @@ impl TelemetryDescribe for Commands {
                 }
                 None => TelemetryInfo::new("migration"),
             },
+            Commands::Release { command, .. } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("release {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("release"),
+            },
             Commands::Test { command } => match command {
                 Some(cmd) => {
                     let mut info = cmd.telemetry();
@@ -83,6 +397,70 @@ impl TelemetryDescribe for Commands {
                 }
                 None => TelemetryInfo::new("test"),
             },
+            Commands::Pin { command } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("pin {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("pin"),
+            },
+            Commands::Package { command } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("package {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("package"),
+            },
+            Commands::Schema { command } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("schema {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("schema"),
+            },
+            Commands::Telemetry { command } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("telemetry {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("telemetry"),
+            },
+            Commands::System { command } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("system {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("system"),
+            },
+            Commands::Audit { command } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("audit {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("audit"),
+            },
+            Commands::Vars { command, .. } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("vars {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("vars"),
+            },
+            Commands::Workspace { command } => match command {
+                Some(cmd) => {
+                    let mut info = cmd.telemetry();
+                    info.label = format!("workspace {}", info.label);
+                    info
+                }
+                None => TelemetryInfo::new("workspace"),
+            },
         }
     }
 }
@@ -93,11 +471,41 @@ pub enum MigrationCommands {
     New {
         /// Name of the migration.
         name: String,
+        /// Start from a template in the templates folder instead of an empty
+        /// transaction
+        #[arg(long)]
+        template: Option<String>,
+        /// Value for a template placeholder, as NAME=VALUE. Placeholders
+        /// without a value are prompted for.
+        #[arg(
+            long,
+            value_name = "NAME=VALUE",
+            value_parser = crate::scaffold::parse_assignment,
+            requires = "template"
+        )]
+        set: Vec<(String, String)>,
+        /// Open the new up.sql in $VISUAL or $EDITOR. Can also be enabled
+        /// with `edit_new_migrations = true` in spawn.toml.
+        #[arg(long, conflicts_with = "no_edit")]
+        edit: bool,
+        /// Do not open the editor, even if `edit_new_migrations` is set
+        #[arg(long)]
+        no_edit: bool,
+        /// Ticket or issue the migration is for, recorded in migration.toml
+        #[arg(long)]
+        ticket: Option<String>,
+        /// Description of the migration, recorded in migration.toml
+        #[arg(long)]
+        description: Option<String>,
     },
     /// Pin a migration with current components
     Pin {
         /// Migration to pin
         migration: String,
+        /// Pin the components of a tag created by `spawn pin tag` instead of
+        /// the current ones
+        #[arg(long)]
+        tag: Option<String>,
     },
     /// Build a migration into SQL
     Build {
@@ -106,11 +514,40 @@ pub enum MigrationCommands {
         pinned: bool,
         /// Migration to build.  Looks for up.sql inside this specified
         /// migration folder.
-        migration: String,
-        /// Path to a variables file (JSON, TOML, or YAML) to use for templating.
-        /// Overrides the variables_file setting in spawn.toml.
+        #[arg(required_unless_present_any = ["all", "check"])]
+        migration: Option<String>,
+        /// Path to a variables file (JSON, TOML, or YAML) to use for templating,
+        /// or `-` to read JSON or YAML from stdin. Merged over every other
+        /// source of variables.
         #[arg(long)]
         variables: Option<String>,
+        /// Variables as inline JSON, merged over those from --variables
+        #[arg(long, value_name = "JSON")]
+        var_json: Option<String>,
+        /// Restrict the `tenants` template global to this tenant. May be
+        /// given more than once.
+        #[arg(long)]
+        tenant: Vec<String>,
+        /// Sign the pinned migration with the key from SPAWN_SIGNING_KEY or
+        /// signing.key_file, writing signature.toml in the migration folder
+        /// and ending the built SQL with a signature line
+        #[arg(long, requires = "pinned", conflicts_with = "all")]
+        sign: bool,
+        /// Build every migration to --out-dir, one SQL file per migration
+        /// plus a manifest.toml listing them in order
+        #[arg(long, conflicts_with = "migration", requires = "out_dir")]
+        all: bool,
+        /// With --all, only build migrations not yet applied to the target
+        #[arg(long, requires = "all")]
+        pending: bool,
+        /// Folder to write the files built with --all to
+        #[arg(long, requires = "all")]
+        out_dir: Option<String>,
+        /// Build every migration and compare it with its golden file in
+        /// this folder, as written by --all --out-dir, failing on any
+        /// difference
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["migration", "all", "sign"])]
+        check: Option<String>,
     },
     /// Apply will apply this migration to the database if not already applied,
     /// or all migrations if called without argument.
@@ -121,15 +558,25 @@ pub enum MigrationCommands {
 
         migration: Option<String>,
 
-        /// Path to a variables file (JSON, TOML, or YAML) to use for templating.
-        /// Overrides the variables_file setting in spawn.toml.
+        /// Path to a variables file (JSON, TOML, or YAML) to use for templating,
+        /// or `-` to read JSON or YAML from stdin. Merged over every other
+        /// source of variables.
         #[arg(long)]
         variables: Option<String>,
 
+        /// Variables as inline JSON, merged over those from --variables
+        #[arg(long, value_name = "JSON")]
+        var_json: Option<String>,
+
         /// Skip confirmation prompt
         #[arg(long)]
         yes: bool,
 
+        /// With --yes, apply to a protected target without typing its name.
+        /// Also needs SPAWN_ALLOW_PROTECTED to be set.
+        #[arg(long, requires = "yes")]
+        force: bool,
+
         /// Retry a previously failed migration
         #[arg(long)]
         retry: bool,
@@ -138,6 +585,87 @@ pub enum MigrationCommands {
         /// Can significantly speed up applying many migrations.
         #[arg(long)]
         reuse_connection: bool,
+
+        /// Pin unpinned migrations before applying them for the first time.
+        /// Can also be enabled with `pin_on_apply = true` in spawn.toml.
+        #[arg(long, conflicts_with = "no_pin")]
+        pin_on_apply: bool,
+
+        /// Apply migrations that are older than already applied ones.
+        /// Can also be enabled with `allow_out_of_order = true` in spawn.toml.
+        #[arg(long)]
+        allow_out_of_order: bool,
+
+        /// Apply destructive statements, such as DROP TABLE, to a protected
+        /// target even when a migration does not acknowledge them with
+        /// `destructive = true` in its migration.toml.
+        #[arg(long)]
+        allow_destructive: bool,
+
+        /// Before applying, warn about statements that would lock large
+        /// tables, such as CREATE INDEX without CONCURRENTLY. Can also be
+        /// enabled with `[lock_advisor] enabled = true` in spawn.toml.
+        #[arg(long)]
+        advise_locks: bool,
+
+        /// Restrict the `tenants` template global to this tenant. May be
+        /// given more than once.
+        #[arg(long)]
+        tenant: Vec<String>,
+
+        /// Only apply migrations signed by a key in signing.trusted_keys
+        #[arg(long, conflicts_with = "no_pin")]
+        require_signature: bool,
+
+        /// Apply a SQL file produced by `spawn migration build` instead of
+        /// rendering the migration. Requires --name.
+        #[arg(
+            long,
+            requires = "name",
+            conflicts_with_all = ["migration", "variables", "var_json", "tenant", "pin_on_apply"]
+        )]
+        from_file: Option<String>,
+
+        /// Migration name to record when applying with --from-file
+        #[arg(long, requires = "from_file")]
+        name: Option<String>,
+
+        /// Show each migration's rendered SQL in a pager, then choose to
+        /// apply it, skip it or stop
+        #[arg(long, conflicts_with_all = ["yes", "from_file"])]
+        interactive: bool,
+
+        /// Refuse to apply migrations that have not already succeeded on
+        /// this target, or the one target of this environment, e.g. staging
+        /// before prod
+        #[arg(long, value_name = "ENV")]
+        from: Option<String>,
+
+        /// Wait until this time before applying, such as
+        /// 2024-05-01T02:00Z. The time zone is required.
+        #[arg(long, value_name = "TIME", value_parser = crate::schedule::parse_at)]
+        at: Option<DateTime<Utc>>,
+
+        /// Only start applying inside this daily UTC window, such as
+        /// 02:00-04:00, waiting for it to open. Overrides the environment's
+        /// `maintenance_window`.
+        #[arg(long, value_name = "HH:MM-HH:MM")]
+        window: Option<MaintenanceWindow>,
+
+        /// Fail instead of waiting when it is before --at or outside the
+        /// maintenance window
+        #[arg(long)]
+        no_wait: bool,
+
+        /// When another apply holds the migration lock, wait up to this
+        /// long for it, such as 5m, instead of failing at once
+        #[arg(long, value_name = "TIMEOUT", value_parser = crate::schedule::parse_wait)]
+        wait_for_lock: Option<std::time::Duration>,
+
+        /// Stop with an error once a migration the server sent a WARNING
+        /// for has been applied
+        #[arg(long)]
+        deny_warnings: bool,
     },
     /// Mark a migration as applied without actually running it.
     /// Useful when a migration was applied manually and needs to be recorded.
@@ -149,78 +677,712 @@ pub enum MigrationCommands {
         #[arg(long)]
         yes: bool,
 
+        /// With --yes, adopt on a protected target without typing its name.
+        /// Also needs SPAWN_ALLOW_PROTECTED to be set.
+        #[arg(long, requires = "yes")]
+        force: bool,
+
         /// Description of why the migration is being adopted
         #[arg(long)]
         description: Option<String>,
     },
+    /// Fail when migrations would conflict once merged: duplicate timestamps
+    /// or names, migrations modified after being applied, or pending
+    /// migrations that are not pinned. Intended for pull request CI.
+    Gate {
+        /// History exported from a tracked environment with
+        /// `spawn migration status --history --json`. May be given more
+        /// than once.
+        #[arg(long, value_name = "FILE")]
+        applied: Vec<String>,
+        /// Status exported from a tracked environment with
+        /// `spawn migration status --export`, for environments CI cannot
+        /// reach. May be given more than once.
+        #[arg(long, value_name = "FILE")]
+        against: Vec<String>,
+        /// Path to a variables file (JSON, TOML, or YAML) to use for templating,
+        /// or `-` to read JSON or YAML from stdin. Merged over every other
+        /// source of variables.
+        #[arg(long)]
+        variables: Option<String>,
+        /// Variables as inline JSON, merged over those from --variables
+        #[arg(long, value_name = "JSON")]
+        var_json: Option<String>,
+    },
+    /// Show which apply holds the migration lock: its host, process,
+    /// migration and last heartbeat
+    Locks {
+        /// End the session of a holder that stopped sending heartbeats,
+        /// releasing the lock
+        #[arg(long)]
+        steal: bool,
+
+        /// Skip the confirmation prompt of --steal
+        #[arg(long, requires = "steal")]
+        yes: bool,
+    },
+    /// Show the query plans of the INSERT, UPDATE, DELETE and MERGE
+    /// statements of a migration, to gauge the impact of a backfill
+    Explain {
+        /// Migration to explain
+        migration: String,
+        /// Whether to use pinned components
+        #[arg(long)]
+        pinned: bool,
+        /// Path to a variables file (JSON, TOML, or YAML) to use for templating,
+        /// or `-` to read JSON or YAML from stdin
+        #[arg(long)]
+        variables: Option<String>,
+        /// Variables as inline JSON, merged over those from --variables
+        #[arg(long, value_name = "JSON")]
+        var_json: Option<String>,
+        /// Run each statement with EXPLAIN ANALYZE, inside a transaction
+        /// that is rolled back. Needs --scratch.
+        #[arg(long, requires = "scratch")]
+        analyze: bool,
+        /// Target or environment to explain against instead of the selected
+        /// target, such as a copy of production. Must not be protected.
+        #[arg(long, value_name = "TARGET")]
+        scratch: Option<String>,
+        /// Output the plans as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare the migrations applied to two targets, reporting those
+    /// missing from either or applied with different checksums
+    DiffEnvs {
+        /// Target, or environment with a single target, to compare
+        #[arg(long, value_name = "ENV")]
+        a: String,
+        /// Target, or environment with a single target, to compare it with
+        #[arg(long, value_name = "ENV")]
+        b: String,
+        /// Output the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Show the status of all migrations
-    Status,
+    Status {
+        /// Show every recorded activity, including checksum, pin hash, author
+        /// and description
+        #[arg(long)]
+        history: bool,
+        /// Print the migrations as JSON, or with --history the entries, e.g.
+        /// for `migration gate`
+        #[arg(long, conflicts_with = "compact")]
+        json: bool,
+        /// Write the applied migrations to FILE in a stable JSON format, for
+        /// `migration gate --against` where the database cannot be reached
+        #[arg(long, value_name = "FILE", conflicts_with = "history")]
+        export: Option<String>,
+        /// Include migrations moved to the archive by `migration archive`
+        #[arg(long)]
+        archived: bool,
+        /// Only show pending migrations (with --failed, either)
+        #[arg(long)]
+        pending: bool,
+        /// Only show migrations whose last attempt failed or did not finish
+        #[arg(long)]
+        failed: bool,
+        /// Only show migrations whose timestamp is on or after this date
+        /// (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<chrono::NaiveDate>,
+        /// Only show migrations whose name contains this text
+        #[arg(long)]
+        name_contains: Option<String>,
+        /// Only show the newest N of the migrations left by the other filters
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+        /// Print one line per migration instead of the table
+        #[arg(long)]
+        compact: bool,
+    },
+    /// Move migrations applied before a date into the migrations archive,
+    /// where `migration status` leaves them out unless asked
+    Archive {
+        /// Archive migrations whose timestamp is before this date
+        /// (YYYY-MM-DD)
+        #[arg(long)]
+        before: chrono::NaiveDate,
+        /// List the migrations that would be archived without moving them
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip confirmation prompt
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Render a migration and sign it as an apply request for `spawn
+    /// agent`, adding it to the agent queue
+    Submit {
+        /// Migration to submit
+        migration: String,
+        /// Whether to use pinned components
+        #[arg(long)]
+        pinned: bool,
+        /// Path to a variables file (JSON, TOML, or YAML) to use for
+        /// templating, or `-` to read JSON or YAML from stdin
+        #[arg(long)]
+        variables: Option<String>,
+        /// Variables as inline JSON, merged over those from --variables
+        #[arg(long, value_name = "JSON")]
+        var_json: Option<String>,
+        /// Print the request instead of queueing it, to pipe it to `spawn
+        /// agent --stdin`
+        #[arg(long)]
+        stdout: bool,
+    },
 }
 
 impl TelemetryDescribe for MigrationCommands {
     fn telemetry(&self) -> TelemetryInfo {
         match self {
-            MigrationCommands::New { .. } => TelemetryInfo::new("new"),
-            MigrationCommands::Pin { .. } => TelemetryInfo::new("pin"),
+            MigrationCommands::New { template, edit, .. } => TelemetryInfo::new("new")
+                .with_properties(vec![
+                    ("opt_template", template.is_some().to_string()),
+                    ("opt_edit", edit.to_string()),
+                ]),
+            MigrationCommands::Pin { tag, .. } => TelemetryInfo::new("pin")
+                .with_properties(vec![("has_tag", tag.is_some().to_string())]),
             MigrationCommands::Build {
-                pinned, variables, ..
+                pinned,
+                variables,
+                var_json,
+                tenant,
+                sign,
+                all,
+                pending,
+                check,
+                ..
             } => TelemetryInfo::new("build").with_properties(vec![
                 ("opt_pinned", pinned.to_string()),
-                ("has_variables", variables.is_some().to_string()),
+                (
+                    "has_variables",
+                    (variables.is_some() || var_json.is_some()).to_string(),
+                ),
+                ("has_tenant_filter", (!tenant.is_empty()).to_string()),
+                ("opt_sign", sign.to_string()),
+                ("opt_all", all.to_string()),
+                ("opt_pending", pending.to_string()),
+                ("opt_check", check.is_some().to_string()),
             ]),
             MigrationCommands::Apply {
                 no_pin,
                 variables,
+                var_json,
                 migration,
                 retry,
                 reuse_connection,
+                pin_on_apply,
+                allow_out_of_order,
+                tenant,
+                require_signature,
+                from_file,
+                interactive,
+                from,
+                allow_destructive,
+                advise_locks,
+                at,
+                window,
+                no_wait,
+                wait_for_lock,
+                deny_warnings,
                 ..
             } => TelemetryInfo::new("apply").with_properties(vec![
+                ("opt_allow_out_of_order", allow_out_of_order.to_string()),
                 ("opt_no_pin", no_pin.to_string()),
                 ("opt_retry", retry.to_string()),
-                ("has_variables", variables.is_some().to_string()),
+                (
+                    "has_variables",
+                    (variables.is_some() || var_json.is_some()).to_string(),
+                ),
                 ("apply_all", migration.is_none().to_string()),
                 ("opt_reuse_connection", reuse_connection.to_string()),
+                ("opt_pin_on_apply", pin_on_apply.to_string()),
+                ("has_tenant_filter", (!tenant.is_empty()).to_string()),
+                ("opt_require_signature", require_signature.to_string()),
+                ("opt_from_file", from_file.is_some().to_string()),
+                ("opt_interactive", interactive.to_string()),
+                ("has_promote_from", from.is_some().to_string()),
+                ("opt_allow_destructive", allow_destructive.to_string()),
+                ("opt_advise_locks", advise_locks.to_string()),
+                ("has_at", at.is_some().to_string()),
+                ("has_window", window.is_some().to_string()),
+                ("opt_no_wait", no_wait.to_string()),
+                ("has_wait_for_lock", wait_for_lock.is_some().to_string()),
+                ("opt_deny_warnings", deny_warnings.to_string()),
             ]),
             MigrationCommands::Adopt { .. } => TelemetryInfo::new("adopt"),
-            MigrationCommands::Status => TelemetryInfo::new("status"),
+            MigrationCommands::Explain {
+                pinned,
+                analyze,
+                scratch,
+                json,
+                ..
+            } => TelemetryInfo::new("explain").with_properties(vec![
+                ("opt_pinned", pinned.to_string()),
+                ("opt_analyze", analyze.to_string()),
+                ("has_scratch", scratch.is_some().to_string()),
+                ("opt_json", json.to_string()),
+            ]),
+            MigrationCommands::DiffEnvs { json, .. } => TelemetryInfo::new("diff-envs")
+                .with_properties(vec![("opt_json", json.to_string())]),
+            MigrationCommands::Locks { steal, .. } => {
+                TelemetryInfo::new("locks").with_properties(vec![("opt_steal", steal.to_string())])
+            }
+            MigrationCommands::Gate {
+                applied,
+                against,
+                variables,
+                var_json,
+            } => TelemetryInfo::new("gate").with_properties(vec![
+                ("has_applied", (!applied.is_empty()).to_string()),
+                ("has_against", (!against.is_empty()).to_string()),
+                (
+                    "has_variables",
+                    (variables.is_some() || var_json.is_some()).to_string(),
+                ),
+            ]),
+            MigrationCommands::Status {
+                history,
+                json,
+                export,
+                archived,
+                pending,
+                failed,
+                since,
+                name_contains,
+                limit,
+                compact,
+            } => TelemetryInfo::new("status").with_properties(vec![
+                ("opt_history", history.to_string()),
+                ("opt_json", json.to_string()),
+                ("opt_export", export.is_some().to_string()),
+                ("opt_archived", archived.to_string()),
+                ("opt_pending", pending.to_string()),
+                ("opt_failed", failed.to_string()),
+                ("has_since", since.is_some().to_string()),
+                ("has_name_contains", name_contains.is_some().to_string()),
+                ("has_limit", limit.is_some().to_string()),
+                ("opt_compact", compact.to_string()),
+            ]),
+            MigrationCommands::Archive { dry_run, .. } => TelemetryInfo::new("archive")
+                .with_properties(vec![("opt_dry_run", dry_run.to_string())]),
+            MigrationCommands::Submit {
+                pinned,
+                variables,
+                var_json,
+                stdout,
+                ..
+            } => TelemetryInfo::new("submit").with_properties(vec![
+                ("opt_pinned", pinned.to_string()),
+                (
+                    "has_variables",
+                    (variables.is_some() || var_json.is_some()).to_string(),
+                ),
+                ("opt_stdout", stdout.to_string()),
+            ]),
         }
     }
 }
 
 #[derive(Subcommand)]
-pub enum TestCommands {
-    /// Create a new test with the provided name
-    New {
-        /// Name of the test
-        name: String,
-    },
-    Build {
-        name: String,
-    },
-    /// Run a particular test, or all tests if no name provided.
-    Run {
-        name: Option<String>,
-    },
-    /// Run tests and compare to expected.  Runs all tests if no name provided.
-    Compare {
-        name: Option<String>,
-    },
-    Expect {
-        name: String,
+pub enum ReleaseCommands {
+    /// Apply exactly the migrations listed in releases/<release>.toml, in order
+    Apply {
+        /// Name of the release file, without the .toml extension
+        release: String,
+
+        /// Skip confirmation prompt
+        #[arg(long)]
+        yes: bool,
+
+        /// With --yes, apply to a protected target without typing its name.
+        /// Also needs SPAWN_ALLOW_PROTECTED to be set.
+        #[arg(long, requires = "yes")]
+        force: bool,
+
+        /// Retry a previously failed migration
+        #[arg(long)]
+        retry: bool,
+
+        /// Apply migrations that are older than already applied ones.
+        /// Can also be enabled with `allow_out_of_order = true` in spawn.toml.
+        #[arg(long)]
+        allow_out_of_order: bool,
+
+        /// Apply destructive statements, such as DROP TABLE, to a protected
+        /// target even when a migration does not acknowledge them with
+        /// `destructive = true` in its migration.toml.
+        #[arg(long)]
+        allow_destructive: bool,
     },
 }
 
-impl TelemetryDescribe for TestCommands {
+impl TelemetryDescribe for ReleaseCommands {
     fn telemetry(&self) -> TelemetryInfo {
         match self {
-            TestCommands::New { .. } => TelemetryInfo::new("new"),
-            TestCommands::Build { .. } => TelemetryInfo::new("build"),
-            TestCommands::Run { name } => TelemetryInfo::new("run")
-                .with_properties(vec![("run_all", name.is_none().to_string())]),
-            TestCommands::Compare { name } => TelemetryInfo::new("compare")
-                .with_properties(vec![("compare_all", name.is_none().to_string())]),
+            ReleaseCommands::Apply {
+                retry,
+                allow_destructive,
+                ..
+            } => TelemetryInfo::new("apply").with_properties(vec![
+                ("opt_retry", retry.to_string()),
+                ("opt_allow_destructive", allow_destructive.to_string()),
+            ]),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum PinCommands {
+    /// Show object counts, sizes and deduplication for the pinned store
+    Stats {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Pack a pinned migration and the store objects it uses into an archive
+    Export {
+        /// Name of the migration to export
+        migration: String,
+        /// Path of the archive to write
+        #[arg(long)]
+        out: String,
+    },
+    /// Show what re-pinning a migration would change, without pinning
+    Diff {
+        /// Migration to compare with the current components
+        migration: String,
+    },
+    /// Restore a migration from an archive written by `spawn pin export`
+    Import {
+        /// Path of the archive to read
+        bundle: String,
+    },
+    /// Pin the current components under a tag, for includes such as
+    /// `{% include "users.sql@v1.2.0" %}`
+    Tag {
+        /// Name of the tag, such as v1.2.0
+        tag: String,
+    },
+    /// List the tags of the pinned store
+    Tags {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl TelemetryDescribe for PinCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            PinCommands::Stats { json } => {
+                TelemetryInfo::new("stats").with_properties(vec![("opt_json", json.to_string())])
+            }
+            PinCommands::Export { .. } => TelemetryInfo::new("export"),
+            PinCommands::Diff { .. } => TelemetryInfo::new("diff"),
+            PinCommands::Import { .. } => TelemetryInfo::new("import"),
+            PinCommands::Tag { .. } => TelemetryInfo::new("tag"),
+            PinCommands::Tags { json } => {
+                TelemetryInfo::new("tags").with_properties(vec![("opt_json", json.to_string())])
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum PackageCommands {
+    /// Fetch packages into vendor/ and record them in packages.lock
+    Update {
+        /// Packages to update. Updates every package when none are given
+        packages: Vec<String>,
+    },
+    /// List packages and whether each is vendored as packages.lock says
+    List {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl TelemetryDescribe for PackageCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            PackageCommands::Update { packages } => TelemetryInfo::new("update")
+                .with_properties(vec![("opt_all", packages.is_empty().to_string())]),
+            PackageCommands::List { json } => {
+                TelemetryInfo::new("list").with_properties(vec![("opt_json", json.to_string())])
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum SchemaCommands {
+    /// Write Markdown docs of the tables, columns and foreign keys, with a
+    /// Mermaid ER diagram
+    Docs {
+        /// File to write, relative to the spawn folder. Defaults to
+        /// [schema_docs] output, or schema.md
+        #[arg(long, conflicts_with = "stdout")]
+        output: Option<String>,
+        /// Schema to document; may be given more than once. Defaults to
+        /// [schema_docs] schemas, or public
+        #[arg(long = "schema")]
+        schemas: Vec<String>,
+        /// Print the docs instead of writing them
+        #[arg(long)]
+        stdout: bool,
+    },
+}
+
+impl TelemetryDescribe for SchemaCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            SchemaCommands::Docs { stdout, .. } => {
+                TelemetryInfo::new("docs").with_properties(vec![("opt_stdout", stdout.to_string())])
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum TelemetryCommands {
+    /// Show whether telemetry is enabled, why, and where it is sent
+    Status,
+    /// Disable telemetry by setting `telemetry = false` in spawn.toml
+    Disable,
+}
+
+impl TelemetryDescribe for TelemetryCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            TelemetryCommands::Status => TelemetryInfo::new("status"),
+            TelemetryCommands::Disable => TelemetryInfo::new("disable"),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum SystemCommands {
+    /// Apply pending migrations to spawn's internal schema
+    Upgrade,
+}
+
+impl TelemetryDescribe for SystemCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            SystemCommands::Upgrade => TelemetryInfo::new("upgrade"),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    /// Show who ran which commands, against which target, and how they
+    /// ended, oldest first
+    Show {
+        /// Only show this many of the latest entries
+        #[arg(long, short = 'n')]
+        limit: Option<usize>,
+        /// Only show entries that touched this migration
+        #[arg(long)]
+        migration: Option<String>,
+        /// Print each entry as a line of JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl TelemetryDescribe for AuditCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            AuditCommands::Show {
+                limit,
+                migration,
+                json,
+            } => TelemetryInfo::new("show").with_properties(vec![
+                ("opt_json", json.to_string()),
+                ("has_limit", limit.is_some().to_string()),
+                ("has_migration", migration.is_some().to_string()),
+            ]),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum VarsCommands {
+    /// Print the context a migration is rendered with, after merging every
+    /// source of variables. Values that look like secrets are masked.
+    Show {
+        /// Name of the migration
+        #[arg(long)]
+        migration: String,
+        /// Read the migration's variables files from its pin
+        #[arg(long)]
+        pinned: bool,
+        /// Path to a variables file (JSON, TOML, or YAML) to use for templating,
+        /// or `-` to read JSON or YAML from stdin. Merged over every other
+        /// source of variables.
+        #[arg(long)]
+        variables: Option<String>,
+        /// Variables as inline JSON, merged over those from --variables
+        #[arg(long, value_name = "JSON")]
+        var_json: Option<String>,
+    },
+}
+
+impl TelemetryDescribe for VarsCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            VarsCommands::Show {
+                pinned,
+                variables,
+                var_json,
+                ..
+            } => TelemetryInfo::new("show").with_properties(vec![
+                ("opt_pinned", pinned.to_string()),
+                (
+                    "has_variables",
+                    (variables.is_some() || var_json.is_some()).to_string(),
+                ),
+            ]),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum WorkspaceCommands {
+    /// Show how many migrations are applied, pending and failed in each
+    /// project of the workspace
+    Status {
+        /// Output the status of each project as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl TelemetryDescribe for WorkspaceCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            WorkspaceCommands::Status { json } => {
+                TelemetryInfo::new("status").with_properties(vec![("opt_json", json.to_string())])
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum TestCommands {
+    /// Create a new test with the provided name
+    New {
+        /// Name of the test
+        name: String,
+        /// Create a render test, which compares the rendered SQL instead of
+        /// running it against the database
+        #[arg(long)]
+        render: bool,
+    },
+    Build {
+        name: String,
+    },
+    /// Run the named tests, or all tests if no name provided.
+    Run {
+        /// Tests to run, by name or pattern such as 'reports/*'
+        names: Vec<String>,
+        /// Run each test in a copy of the database that is dropped afterwards
+        #[arg(long)]
+        isolate: bool,
+        /// Fail when the server sends a WARNING while running a test
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+    /// Run tests and compare to expected.  Runs all tests if no name provided.
+    Compare {
+        /// Tests to compare, by name or pattern such as 'reports/*'
+        names: Vec<String>,
+        /// Only compare render tests, skipping tests that need a database
+        #[arg(long)]
+        render_only: bool,
+        /// Re-run a failing test up to this many times before reporting it
+        /// as failed
+        #[arg(long, default_value_t = 0)]
+        retries: u32,
+        /// Run each test in a copy of the database that is dropped afterwards,
+        /// so tests cannot affect each other
+        #[arg(long)]
+        isolate: bool,
+        /// Fail tests the server sends a WARNING for, even when their
+        /// output matches
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+    Expect {
+        name: String,
+    },
+    /// Create a test from an ad-hoc query, running it now and saving its
+    /// output as the expected output
+    Record {
+        /// Name of the test
+        name: String,
+        /// File holding the query. Reads stdin when omitted or `-`
+        #[arg(long)]
+        file: Option<String>,
+        /// Replace the test if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Render every test and migration, and report how many of each use
+    /// every component, listing components no test uses
+    Coverage {
+        /// Output the coverage as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl TelemetryDescribe for TestCommands {
+    fn telemetry(&self) -> TelemetryInfo {
+        match self {
+            TestCommands::New { render, .. } => {
+                TelemetryInfo::new("new").with_properties(vec![("opt_render", render.to_string())])
+            }
+            TestCommands::Build { .. } => TelemetryInfo::new("build"),
+            TestCommands::Run {
+                names,
+                isolate,
+                deny_warnings,
+            } => TelemetryInfo::new("run").with_properties(vec![
+                ("run_all", names.is_empty().to_string()),
+                ("opt_isolate", isolate.to_string()),
+                ("opt_deny_warnings", deny_warnings.to_string()),
+            ]),
+            TestCommands::Compare {
+                names,
+                render_only,
+                retries,
+                isolate,
+                deny_warnings,
+            } => TelemetryInfo::new("compare").with_properties(vec![
+                ("compare_all", names.is_empty().to_string()),
+                ("opt_render_only", render_only.to_string()),
+                ("retries", retries.to_string()),
+                ("opt_isolate", isolate.to_string()),
+                ("opt_deny_warnings", deny_warnings.to_string()),
+            ]),
             TestCommands::Expect { .. } => TelemetryInfo::new("expect"),
+            TestCommands::Record { file, force, .. } => TelemetryInfo::new("record")
+                .with_properties(vec![
+                    (
+                        "from_stdin",
+                        file.as_deref().is_none_or(|f| f == "-").to_string(),
+                    ),
+                    ("opt_force", force.to_string()),
+                ]),
+            TestCommands::Coverage { json } => {
+                TelemetryInfo::new("coverage").with_properties(vec![("opt_json", json.to_string())])
+            }
         }
     }
 }
@@ -232,9 +1394,16 @@ pub struct CliResult {
     pub project_id: Option<String>,
     /// Whether telemetry is enabled in config
     pub telemetry_enabled: bool,
+    /// Where telemetry is sent, from `telemetry.endpoint` in config
+    pub telemetry_endpoint: Option<String>,
+    /// Sub-operations recorded by the command, sent with its telemetry
+    pub telemetry_session: TelemetrySession,
 }
 
 pub async fn run_cli(cli: Cli, base_op: &Operator) -> CliResult {
+    crate::output::set_quiet(cli.quiet);
+    crate::output::set_color(cli.color);
+
     // Handle init command separately as it doesn't require existing config
     if let Some(Commands::Init { docker }) = &cli.command {
         let init_cmd = Init {
@@ -247,6 +1416,8 @@ pub async fn run_cli(cli: Cli, base_op: &Operator) -> CliResult {
                     outcome: Ok(outcome),
                     project_id: Some(project_id),
                     telemetry_enabled: true,
+                    telemetry_endpoint: None,
+                    telemetry_session: TelemetrySession::default(),
                 };
             }
             Err(e) => {
@@ -254,16 +1425,84 @@ pub async fn run_cli(cli: Cli, base_op: &Operator) -> CliResult {
                     outcome: Err(e),
                     project_id: None,
                     telemetry_enabled: true,
+                    telemetry_endpoint: None,
+                    telemetry_session: TelemetrySession::default(),
                 };
             }
         }
     }
 
+    // Disabling telemetry edits the config file directly, so that it works
+    // even when the config does not load. Nothing is sent for it.
+    if let Some(Commands::Telemetry {
+        command: Some(TelemetryCommands::Disable),
+    }) = &cli.command
+    {
+        let config_file = Config::find(&cli.config_file, base_op)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| cli.config_file.clone());
+        let disable_cmd = TelemetryDisable { config_file };
+        return CliResult {
+            outcome: disable_cmd.execute(base_op).await,
+            project_id: None,
+            telemetry_enabled: false,
+            telemetry_endpoint: None,
+            telemetry_session: TelemetrySession::default(),
+        };
+    }
+
+    // Each project of a workspace has its own config, so there is no one
+    // config to load. Nothing is sent for it.
+    if let Some(Commands::Workspace { command }) = &cli.command {
+        let outcome = match command {
+            Some(WorkspaceCommands::Status { json }) => {
+                WorkspaceStatus {
+                    workspace_file: cli.workspace_file.clone(),
+                    target: cli.target.clone(),
+                    json: *json,
+                    strict: cli.strict,
+                }
+                .execute(base_op)
+                .await
+            }
+            None => {
+                eprintln!("No workspace subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        };
+        return CliResult {
+            outcome,
+            project_id: None,
+            telemetry_enabled: false,
+            telemetry_endpoint: None,
+            telemetry_session: TelemetrySession::default(),
+        };
+    }
+
+    // With --project, the config is the project's, found through the
+    // workspace file.
+    let loaded = match &cli.project {
+        Some(project) => match Workspace::load(&cli.workspace_file, base_op).await {
+            Ok(workspace) => {
+                workspace
+                    .load_config(project, base_op, cli.target.clone())
+                    .await
+            }
+            Err(e) => Err(e),
+        },
+        None => Config::load(&cli.config_file, base_op, cli.target.clone())
+            .await
+            .context(format!("could not load config from {}", &cli.config_file)),
+    };
+
     // Check if config file exists to show telemetry notice
-    let config_exists = base_op.exists(&cli.config_file).await.unwrap_or(false);
+    let config_exists = cli.project.is_some()
+        || matches!(Config::find(&cli.config_file, base_op).await, Ok(Some(_)));
 
     // Load config from file (required for all other commands)
-    let mut main_config = match Config::load(&cli.config_file, base_op, cli.target.clone()).await {
+    let mut main_config = match loaded {
         Ok(cfg) => cfg,
         Err(e) => {
             // If config doesn't exist, show helpful message
@@ -272,16 +1511,20 @@ pub async fn run_cli(cli: Cli, base_op: &Operator) -> CliResult {
                 eprintln!("No spawn.toml configuration file found.");
                 eprintln!("Run `spawn init` to create a new spawn project.");
                 return CliResult {
-                    outcome: Err(anyhow!("Configuration file not found")),
+                    outcome: Err(ErrorKind::Config.wrap(anyhow!("Configuration file not found"))),
                     project_id: None,
                     telemetry_enabled: false,
+                    telemetry_endpoint: None,
+                    telemetry_session: TelemetrySession::default(),
                 };
             }
 
             return CliResult {
-                outcome: Err(e.context(format!("could not load config from {}", &cli.config_file))),
+                outcome: Err(ErrorKind::Config.wrap(e)),
                 project_id: None,
                 telemetry_enabled: false, // Default disabled if we can't load config
+                telemetry_endpoint: None,
+                telemetry_session: TelemetrySession::default(),
             };
         }
     };
@@ -289,104 +1532,584 @@ pub async fn run_cli(cli: Cli, base_op: &Operator) -> CliResult {
     // Extract telemetry info from config
     let project_id = main_config.project_id.clone();
     let telemetry_enabled = main_config.telemetry;
+    let telemetry_endpoint = main_config.telemetry_endpoint.clone();
+
+    if cli.no_cache {
+        main_config.pin_cache = None;
+    }
+    if cli.no_notify {
+        main_config.notifications = NotificationsConfig::default();
+    }
+    main_config.annotate = AnnotationFormat::detect(cli.annotate);
+    main_config.strict = cli.strict;
+    if cli.deterministic {
+        main_config.template.deterministic = true;
+    }
+    main_config.events = Events::new(ConsoleEvents);
+    // `check` lists these itself.
+    if !matches!(cli.command, Some(Commands::Check)) {
+        for diagnostic in main_config.validate() {
+            main_config.events.on_warning(&diagnostic.to_string());
+        }
+    }
 
     // Run the actual command
+    let command = cli.telemetry().label;
+    let audited = cli.command.as_ref().is_some_and(Commands::changes_state);
     let outcome = run_command(cli, &mut main_config).await;
 
+    if audited {
+        let entry = AuditEntry::new(&main_config, &command, outcome.as_ref().map(|_| ()));
+        if let Err(e) = append_audit_entry(&main_config, &entry).await {
+            main_config.events.on_warning(&format!("{:#}", e));
+        }
+    }
+
+    let attributes = [
+        ("spawn.target", main_config.target.clone()),
+        ("spawn.environment", main_config.policy_environment()),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key.to_string(), value?)))
+    .collect();
+    let error = outcome.as_ref().err().map(|e| format!("{:#}", e));
+    if let Err(e) = main_config.tracer.finish(&command, attributes, error).await {
+        main_config.events.on_warning(&format!("{:#}", e));
+    }
+
     CliResult {
         outcome,
         project_id,
         telemetry_enabled,
+        telemetry_endpoint,
+        telemetry_session: main_config.telemetry_session.clone(),
+    }
+}
+
+/// Loads the variables given with `--variables`, a path or `-` for stdin,
+/// and `--var-json`, the inline JSON taking precedence.
+async fn load_variables(
+    config: &Config,
+    path: Option<String>,
+    json: Option<String>,
+) -> Result<Option<Variables>> {
+    let file = match path.as_deref() {
+        Some("-") => Some(
+            Variables::from_reader(std::io::stdin().lock())
+                .context("Failed to read variables from stdin")?,
+        ),
+        Some(path) => Some(config.load_variables_from_path(path).await?),
+        None => None,
+    };
+    let inline = match json {
+        Some(json) => {
+            Some(Variables::from_str("json", &json).context("Failed to parse --var-json")?)
+        }
+        None => None,
+    };
+
+    match (file, inline) {
+        (Some(file), Some(inline)) => Ok(Some(file.overlay(inline)?)),
+        (file, inline) => Ok(inline.or(file)),
     }
 }
 
 async fn run_command(cli: Cli, config: &mut Config) -> Result<Outcome> {
+    if let Some(
+        Commands::Migration { environment, .. }
+        | Commands::Release { environment, .. }
+        | Commands::Vars { environment, .. },
+    ) = &cli.command
+    {
+        config.environment = environment.clone();
+    }
+    config.check_command_allowed(&cli.telemetry().label)?;
+
     match cli.command {
-        Some(Commands::Init { .. }) => unreachable!(), // Already handled in run_cli
+        // Already handled in run_cli
+        Some(Commands::Init { .. } | Commands::Workspace { .. }) => unreachable!(),
         Some(Commands::Check) => Check.execute(config).await,
-        Some(Commands::Migration {
-            command,
-            environment,
+        Some(Commands::Stats { json }) => Stats { json }.execute(config).await,
+        Some(Commands::Metrics { output }) => Metrics { output }.execute(config).await,
+        Some(Commands::Keygen { out }) => Keygen { out }.execute(config).await,
+        Some(Commands::Export {
+            format,
+            dir,
+            pinned,
+            variables,
+            var_json,
         }) => {
-            config.environment = environment;
-            match command {
-                Some(MigrationCommands::New { name }) => {
-                    NewMigration { name }.execute(config).await
-                }
-                Some(MigrationCommands::Pin { migration }) => {
-                    PinMigration { migration }.execute(config).await
+            let variables = load_variables(config, variables, var_json).await?;
+            Export {
+                format,
+                dir,
+                pinned,
+                variables,
+            }
+            .execute(config)
+            .await
+        }
+        Some(Commands::Agent {
+            once,
+            interval,
+            stdin,
+        }) => {
+            Agent {
+                once,
+                interval,
+                stdin,
+            }
+            .execute(config)
+            .await
+        }
+        Some(Commands::Serve { listen }) => Serve { listen }.execute(config).await,
+        Some(Commands::Ui) => Ui.execute(config).await,
+        Some(Commands::Scrub { rules, print, yes }) => {
+            Scrub { rules, print, yes }.execute(config).await
+        }
+        Some(Commands::Import {
+            from,
+            dir,
+            adopt,
+            history_table,
+            report,
+            yes,
+            force,
+        }) => {
+            Import {
+                from,
+                dir,
+                adopt,
+                history_table,
+                report,
+                yes,
+                force,
+            }
+            .execute(config)
+            .await
+        }
+        Some(Commands::Migration { command, .. }) => match command {
+            Some(MigrationCommands::New {
+                name,
+                template,
+                set,
+                edit,
+                no_edit,
+                ticket,
+                description,
+            }) => {
+                NewMigration {
+                    name,
+                    template,
+                    set,
+                    edit: !no_edit && (edit || config.edit_new_migrations),
+                    ticket,
+                    description,
                 }
-                Some(MigrationCommands::Build {
-                    migration,
-                    pinned,
-                    variables,
-                }) => {
-                    let vars = match variables {
-                        Some(vars_path) => Some(config.load_variables_from_path(&vars_path).await?),
-                        None => None,
-                    };
-                    BuildMigration {
-                        migration,
-                        pinned,
-                        variables: vars,
+                .execute(config)
+                .await
+            }
+            Some(MigrationCommands::Pin { migration, tag }) => {
+                PinMigration { migration, tag }.execute(config).await
+            }
+            Some(MigrationCommands::Build {
+                migration,
+                pinned,
+                variables,
+                var_json,
+                tenant,
+                sign,
+                all,
+                pending,
+                out_dir,
+                check,
+            }) => {
+                config.tenant_filter = tenant;
+                let vars = load_variables(config, variables, var_json).await?;
+                match (migration, out_dir, check) {
+                    (_, _, Some(dir)) => {
+                        CheckBuiltMigrations {
+                            pinned,
+                            variables: vars,
+                            dir,
+                        }
+                        .execute(config)
+                        .await
                     }
-                    .execute(config)
-                    .await
+                    (Some(migration), _, None) => {
+                        BuildMigration {
+                            migration,
+                            pinned,
+                            variables: vars,
+                            sign,
+                        }
+                        .execute(config)
+                        .await
+                    }
+                    (None, Some(out_dir), None) if all => {
+                        BuildAllMigrations {
+                            pinned,
+                            variables: vars,
+                            pending,
+                            out_dir,
+                        }
+                        .execute(config)
+                        .await
+                    }
+                    _ => Err(anyhow!("a migration name or --all is required")),
                 }
-                Some(MigrationCommands::Apply {
-                    migration,
-                    no_pin,
-                    variables,
+            }
+            Some(MigrationCommands::Apply {
+                migration,
+                no_pin,
+                variables,
+                var_json,
+                yes,
+                force,
+                retry,
+                reuse_connection,
+                pin_on_apply,
+                allow_out_of_order,
+                tenant,
+                require_signature,
+                from_file,
+                name,
+                interactive,
+                from,
+                allow_destructive,
+                advise_locks,
+                at,
+                window,
+                no_wait,
+                wait_for_lock,
+                deny_warnings,
+            }) => {
+                config.tenant_filter = tenant;
+                config.wait_for_lock = wait_for_lock;
+                config.deny_warnings = deny_warnings;
+                if advise_locks {
+                    config.lock_advisor.enabled = true;
+                }
+                if pin_on_apply {
+                    config.pin_on_apply = true;
+                }
+                if allow_out_of_order {
+                    config.allow_out_of_order = true;
+                }
+                let vars = load_variables(config, variables, var_json).await?;
+                ApplyMigration {
+                    migration: name.or(migration),
+                    pinned: !no_pin,
+                    variables: vars,
                     yes,
+                    force,
                     retry,
                     reuse_connection,
-                }) => {
-                    let vars = match variables {
-                        Some(vars_path) => Some(config.load_variables_from_path(&vars_path).await?),
-                        None => None,
-                    };
-                    ApplyMigration {
-                        migration,
-                        pinned: !no_pin,
-                        variables: vars,
-                        yes,
-                        retry,
-                        reuse_connection,
-                    }
-                    .execute(config)
-                    .await
+                    require_signature,
+                    from_file,
+                    interactive,
+                    promote_from: from,
+                    allow_destructive,
+                    schedule: Schedule {
+                        at,
+                        window,
+                        no_wait,
+                    },
                 }
-                Some(MigrationCommands::Adopt {
+                .execute(config)
+                .await
+            }
+            Some(MigrationCommands::Adopt {
+                migration,
+                yes,
+                force,
+                description,
+            }) => {
+                AdoptMigration {
                     migration,
                     yes,
+                    force,
                     description,
-                }) => {
-                    AdoptMigration {
-                        migration,
-                        yes,
-                        description,
-                    }
-                    .execute(config)
-                    .await
                 }
-                Some(MigrationCommands::Status) => MigrationStatus.execute(config).await,
-                None => {
-                    eprintln!("No migration subcommand specified");
-                    Ok(Outcome::Unimplemented)
+                .execute(config)
+                .await
+            }
+            Some(MigrationCommands::Explain {
+                migration,
+                pinned,
+                variables,
+                var_json,
+                analyze,
+                scratch,
+                json,
+            }) => {
+                let vars = load_variables(config, variables, var_json).await?;
+                ExplainMigration {
+                    migration,
+                    pinned,
+                    variables: vars,
+                    analyze,
+                    scratch,
+                    json,
+                }
+                .execute(config)
+                .await
+            }
+            Some(MigrationCommands::DiffEnvs { a, b, json }) => {
+                MigrationDiffEnvs { a, b, json }.execute(config).await
+            }
+            Some(MigrationCommands::Locks { steal, yes }) => {
+                MigrationLocks { steal, yes }.execute(config).await
+            }
+            Some(MigrationCommands::Gate {
+                applied,
+                against,
+                variables,
+                var_json,
+            }) => {
+                let vars = load_variables(config, variables, var_json).await?;
+                MigrationGate {
+                    applied,
+                    against,
+                    variables: vars,
                 }
+                .execute(config)
+                .await
             }
-        }
+            Some(MigrationCommands::Status {
+                history,
+                json,
+                export,
+                archived,
+                pending,
+                failed,
+                since,
+                name_contains,
+                limit,
+                compact,
+            }) => {
+                MigrationStatus {
+                    history,
+                    json,
+                    export,
+                    filter: StatusFilter {
+                        pending,
+                        failed,
+                        since,
+                        name_contains,
+                        limit,
+                        include_archived: archived,
+                    },
+                    compact,
+                }
+                .execute(config)
+                .await
+            }
+            Some(MigrationCommands::Archive {
+                before,
+                dry_run,
+                yes,
+            }) => {
+                ArchiveMigrations {
+                    before,
+                    dry_run,
+                    yes,
+                }
+                .execute(config)
+                .await
+            }
+            Some(MigrationCommands::Submit {
+                migration,
+                pinned,
+                variables,
+                var_json,
+                stdout,
+            }) => {
+                let variables = load_variables(config, variables, var_json).await?;
+                SubmitMigration {
+                    migration,
+                    pinned,
+                    variables,
+                    stdout,
+                }
+                .execute(config)
+                .await
+            }
+            None => {
+                eprintln!("No migration subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
         Some(Commands::Test { command }) => match command {
-            Some(TestCommands::New { name }) => NewTest { name }.execute(config).await,
+            Some(TestCommands::New { name, render }) => {
+                NewTest { name, render }.execute(config).await
+            }
             Some(TestCommands::Build { name }) => BuildTest { name }.execute(config).await,
-            Some(TestCommands::Run { name }) => RunTest { name }.execute(config).await,
-            Some(TestCommands::Compare { name }) => CompareTests { name }.execute(config).await,
+            Some(TestCommands::Run {
+                names,
+                isolate,
+                deny_warnings,
+            }) => {
+                RunTest {
+                    names,
+                    isolate,
+                    deny_warnings,
+                }
+                .execute(config)
+                .await
+            }
+            Some(TestCommands::Compare {
+                names,
+                render_only,
+                retries,
+                isolate,
+                deny_warnings,
+            }) => {
+                CompareTests {
+                    names,
+                    render_only,
+                    retries,
+                    isolate,
+                    deny_warnings,
+                }
+                .execute(config)
+                .await
+            }
             Some(TestCommands::Expect { name }) => ExpectTest { name }.execute(config).await,
+            Some(TestCommands::Record { name, file, force }) => {
+                RecordTest { name, file, force }.execute(config).await
+            }
+            Some(TestCommands::Coverage { json }) => TestCoverage { json }.execute(config).await,
             None => {
                 eprintln!("No test subcommand specified");
                 Ok(Outcome::Unimplemented)
             }
         },
+        Some(Commands::Release { command, .. }) => match command {
+            Some(ReleaseCommands::Apply {
+                release,
+                yes,
+                force,
+                retry,
+                allow_out_of_order,
+                allow_destructive,
+            }) => {
+                if allow_out_of_order {
+                    config.allow_out_of_order = true;
+                }
+                ReleaseApply {
+                    release,
+                    yes,
+                    force,
+                    retry,
+                    allow_destructive,
+                }
+                .execute(config)
+                .await
+            }
+            None => {
+                eprintln!("No release subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
+        Some(Commands::Pin { command }) => match command {
+            Some(PinCommands::Stats { json }) => PinStats { json }.execute(config).await,
+            Some(PinCommands::Export { migration, out }) => {
+                PinExport { migration, out }.execute(config).await
+            }
+            Some(PinCommands::Diff { migration }) => PinDiff { migration }.execute(config).await,
+            Some(PinCommands::Import { bundle }) => PinImport { bundle }.execute(config).await,
+            Some(PinCommands::Tag { tag }) => PinTag { tag }.execute(config).await,
+            Some(PinCommands::Tags { json }) => PinTags { json }.execute(config).await,
+            None => {
+                eprintln!("No pin subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
+        Some(Commands::Package { command }) => match command {
+            Some(PackageCommands::Update { packages }) => {
+                PackageUpdate { packages }.execute(config).await
+            }
+            Some(PackageCommands::List { json }) => PackageList { json }.execute(config).await,
+            None => {
+                eprintln!("No package subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
+        Some(Commands::Schema { command }) => match command {
+            Some(SchemaCommands::Docs {
+                output,
+                schemas,
+                stdout,
+            }) => {
+                SchemaDocs {
+                    output,
+                    schemas,
+                    stdout,
+                }
+                .execute(config)
+                .await
+            }
+            None => {
+                eprintln!("No schema subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
+        Some(Commands::Telemetry { command }) => match command {
+            Some(TelemetryCommands::Status) => TelemetryStatus.execute(config).await,
+            Some(TelemetryCommands::Disable) => unreachable!(), // Already handled in run_cli
+            None => {
+                eprintln!("No telemetry subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
+        Some(Commands::System { command }) => match command {
+            Some(SystemCommands::Upgrade) => SystemUpgrade.execute(config).await,
+            None => {
+                eprintln!("No system subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
+        Some(Commands::Audit { command }) => match command {
+            Some(AuditCommands::Show {
+                limit,
+                migration,
+                json,
+            }) => {
+                AuditShow {
+                    limit,
+                    migration,
+                    json,
+                }
+                .execute(config)
+                .await
+            }
+            None => {
+                eprintln!("No audit subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
+        Some(Commands::Vars { command, .. }) => match command {
+            Some(VarsCommands::Show {
+                migration,
+                pinned,
+                variables,
+                var_json,
+            }) => {
+                let vars = load_variables(config, variables, var_json).await?;
+                VarsShow {
+                    migration,
+                    pinned,
+                    variables: vars,
+                }
+                .execute(config)
+                .await
+            }
+            None => {
+                eprintln!("No vars subcommand specified");
+                Ok(Outcome::Unimplemented)
+            }
+        },
         None => Ok(Outcome::Unimplemented),
     }
 }
@@ -0,0 +1,188 @@
+use crate::agent::{AgentQueue, ApplyRequest, ApplyResult, ApplyResultStatus};
+use crate::commands::migration::check_migration_order;
+use crate::commands::{ApplyMigration, Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::interrupt;
+use crate::schedule::Schedule;
+use crate::status;
+use anyhow::{anyhow, Context, Result};
+use std::io::Read;
+use std::time::Duration;
+
+/// Applies the signed requests of `spawn migration submit` to the selected
+/// target, from the agent queue or from stdin, and writes back how each
+/// went.
+pub struct Agent {
+    /// Handle the pending requests once and exit instead of polling
+    pub once: bool,
+    /// Seconds to wait between polls of the queue
+    pub interval: u64,
+    /// Read one request from stdin and print its result, without using the
+    /// queue
+    pub stdin: bool,
+}
+
+impl TelemetryDescribe for Agent {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("agent").with_properties(vec![
+            ("opt_once", self.once.to_string()),
+            ("opt_stdin", self.stdin.to_string()),
+        ])
+    }
+}
+
+impl Command for Agent {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        interrupt::install();
+        if self.stdin {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .context("could not read the request from stdin")?;
+            let request = serde_json::from_str::<ApplyRequest>(&input)
+                .context("request on stdin is not valid");
+            let id = request.as_ref().map(|r| r.id.clone()).unwrap_or_default();
+            let result = handle_request(config, &id, request).await?;
+            println!("{}", serde_json::to_string_pretty(&result)?);
+            return finished(&[result]);
+        }
+
+        let queue = AgentQueue::new(config);
+        loop {
+            let results = poll(config, &queue).await?;
+            if self.once {
+                return finished(&results);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(self.interval)) => {}
+                _ = interrupt::interrupted() => return Ok(Outcome::Success),
+            }
+        }
+    }
+}
+
+/// Fails if any request failed or was rejected.
+fn finished(results: &[ApplyResult]) -> Result<Outcome> {
+    let unsuccessful = results
+        .iter()
+        .filter(|r| {
+            matches!(
+                r.status,
+                ApplyResultStatus::Failed | ApplyResultStatus::Rejected
+            )
+        })
+        .count();
+    if unsuccessful > 0 {
+        return Err(anyhow!(
+            "{} request{} failed or {} rejected",
+            unsuccessful,
+            if unsuccessful == 1 { "" } else { "s" },
+            if unsuccessful == 1 { "was" } else { "were" }
+        ));
+    }
+    Ok(Outcome::Success)
+}
+
+/// Handles each pending request for the selected target, writing its
+/// result to the queue. Requests for other targets are left for their own
+/// agents.
+async fn poll(config: &Config, queue: &AgentQueue) -> Result<Vec<ApplyResult>> {
+    let mut results = Vec::new();
+    for queued in queue.pending().await? {
+        if let Ok(request) = &queued.request {
+            if Some(&request.target) != config.target.as_ref() {
+                continue;
+            }
+        }
+        let result = handle_request(config, &queued.id, queued.request).await?;
+        queue.write_result(&result).await?;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Verifies and applies a request. Only being interrupted is an error: the
+/// request is then left without a result to be tried again.
+async fn handle_request(
+    config: &Config,
+    id: &str,
+    request: Result<ApplyRequest>,
+) -> Result<ApplyResult> {
+    let rejected = |migration: Option<&str>, e: anyhow::Error| {
+        let result = ApplyResult::new(
+            config,
+            id,
+            migration,
+            ApplyResultStatus::Rejected,
+            Some(format!("{:#}", e)),
+        );
+        config
+            .events
+            .on_warning(&format!("rejected request {}: {:#}", id, e));
+        Ok(result)
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return rejected(None, e),
+    };
+    let migration = Some(request.migration.as_str());
+    if request.id != id {
+        return rejected(
+            migration,
+            anyhow!("request is named {} but has id {}", id, request.id),
+        );
+    }
+    if let Err(e) = request.verify(&config.signing.trusted_keys) {
+        return rejected(migration, e);
+    }
+    if Some(&request.target) != config.target.as_ref() {
+        return rejected(
+            migration,
+            anyhow!(
+                "request is for target {}, not {}",
+                request.target,
+                config.target.as_deref().unwrap_or("(none)")
+            ),
+        );
+    }
+    // Held to the same checks as `migration apply --from-file`.
+    if let Err(e) = config.check_command_allowed("migration apply") {
+        return rejected(migration, e);
+    }
+    if let Err(e) = check_migration_order(config, std::slice::from_ref(&request.migration)).await {
+        return rejected(migration, e);
+    }
+
+    status!("Applying {} from request {}", request.migration, id);
+    // The signature is the approval, so nothing is confirmed here.
+    let apply = ApplyMigration {
+        migration: Some(request.migration.clone()),
+        pinned: false,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Schedule::default(),
+    };
+    let (status, error) = match apply
+        .apply_rendered(
+            config,
+            &request.migration,
+            request.sql.into_bytes(),
+            "spawn.agent_request",
+        )
+        .await
+    {
+        Ok(report) if report.applied().is_empty() => (ApplyResultStatus::Skipped, None),
+        Ok(_) => (ApplyResultStatus::Applied, None),
+        Err(e) if interrupt::is_interrupted() => return Err(e),
+        Err(e) => (ApplyResultStatus::Failed, Some(format!("{:#}", e))),
+    };
+    Ok(ApplyResult::new(config, id, migration, status, error))
+}
@@ -0,0 +1,120 @@
+use crate::audit::{audit_path, read_audit_log, AuditEntry, AuditOutcome};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::status;
+use anyhow::Result;
+use console::style;
+use tabled::settings::Style;
+use tabled::{Table, Tabled};
+
+/// Shows the audit log of commands run against the project.
+pub struct AuditShow {
+    /// Only show this many of the latest entries
+    pub limit: Option<usize>,
+    /// Only show entries that touched this migration
+    pub migration: Option<String>,
+    pub json: bool,
+}
+
+impl TelemetryDescribe for AuditShow {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("audit show").with_properties(vec![
+            ("opt_json", self.json.to_string()),
+            ("has_limit", self.limit.is_some().to_string()),
+            ("has_migration", self.migration.is_some().to_string()),
+        ])
+    }
+}
+
+#[derive(Tabled)]
+struct AuditEntryDisplay {
+    #[tabled(rename = "Time")]
+    timestamp: String,
+    #[tabled(rename = "User")]
+    user: String,
+    #[tabled(rename = "Command")]
+    command: String,
+    #[tabled(rename = "Target")]
+    target: String,
+    #[tabled(rename = "Migrations")]
+    migrations: String,
+    #[tabled(rename = "Outcome")]
+    outcome: String,
+}
+
+fn or_dash(value: Option<String>) -> String {
+    value.unwrap_or_else(|| style("-").dim().to_string())
+}
+
+impl AuditShow {
+    /// The entries to show, oldest first.
+    pub fn select(&self, entries: Vec<AuditEntry>) -> Vec<AuditEntry> {
+        let mut entries: Vec<AuditEntry> = entries
+            .into_iter()
+            .filter(|entry| {
+                self.migration
+                    .as_ref()
+                    .is_none_or(|m| entry.migrations.contains(m))
+            })
+            .collect();
+        if let Some(limit) = self.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        entries
+    }
+}
+
+impl Command for AuditShow {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let Some(path) = audit_path(config) else {
+            status!("The audit log is disabled (audit.enabled = false in spawn.toml).");
+            return Ok(Outcome::Success);
+        };
+        let entries = self.select(read_audit_log(config).await?);
+
+        if self.json {
+            for entry in &entries {
+                println!("{}", serde_json::to_string(entry)?);
+            }
+            return Ok(Outcome::Success);
+        }
+        if entries.is_empty() {
+            status!("No audit entries in {}.", path);
+            return Ok(Outcome::Success);
+        }
+
+        let display_rows: Vec<AuditEntryDisplay> = entries
+            .into_iter()
+            .map(|entry| {
+                let target = match (entry.target, entry.environment) {
+                    (Some(target), Some(environment)) if target != environment => {
+                        Some(format!("{} ({})", target, environment))
+                    }
+                    (target, environment) => target.or(environment),
+                };
+                let outcome = match (entry.outcome, entry.error) {
+                    (AuditOutcome::Success, _) => style("✓ Success").green().to_string(),
+                    (AuditOutcome::Failure, Some(error)) => {
+                        let first = error.lines().next().unwrap_or_default().to_string();
+                        style(format!("✗ {}", first)).red().to_string()
+                    }
+                    (AuditOutcome::Failure, None) => style("✗ Failure").red().to_string(),
+                };
+                AuditEntryDisplay {
+                    timestamp: entry.timestamp,
+                    user: or_dash(entry.user),
+                    command: entry.command,
+                    target: or_dash(target),
+                    migrations: or_dash(
+                        Some(entry.migrations.join("\n")).filter(|m| !m.is_empty()),
+                    ),
+                    outcome,
+                }
+            })
+            .collect();
+        let mut table = Table::new(display_rows);
+        table.with(Style::sharp());
+        println!("\n{}\n", table);
+        Ok(Outcome::Success)
+    }
+}
@@ -1,5 +1,9 @@
+use crate::annotations::Annotation;
+use crate::commands::migration::render_migration;
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
+use crate::migrator::Migrator;
+use crate::policy::{destructive_statements, RuleSeverity};
 use crate::store::list_migration_fs_status;
 use anyhow::Result;
 use console::style;
@@ -21,12 +25,117 @@ impl Command for Check {
 
         let mut warnings: Vec<String> = Vec::new();
 
+        for diagnostic in config.validate() {
+            warnings.push(diagnostic.to_string());
+            let mut annotation = Annotation::error(diagnostic.message.clone())
+                .with_file(&diagnostic.file)
+                .with_title("Config");
+            if let Some(line) = diagnostic.line {
+                annotation = annotation.with_line(line);
+            }
+            annotation.emit(config.annotate);
+        }
+
         // Grab status from store
         let fs_status = list_migration_fs_status(config.operator(), &config.pather(), None).await?;
 
         for (name, status) in &fs_status {
             if status.has_up_sql && !status.has_lock_toml {
                 warnings.push(format!("Migration {} is not pinned", style(name).yellow()));
+                Annotation::error(format!(
+                    "Migration {} is not pinned. Run `spawn migration pin {}`.",
+                    name, name
+                ))
+                .with_file(&config.pather().migration_script_file_path(name))
+                .with_title("Unpinned migration")
+                .emit(config.annotate);
+            }
+
+            let violations = config.naming.violations_for_migration(name)?;
+            if !violations.is_empty() {
+                let message = config.naming.describe(name, &violations);
+                warnings.push(message.clone());
+                Annotation::error(message)
+                    .with_file(&config.pather().migration_script_file_path(name))
+                    .with_title("Migration name")
+                    .emit(config.annotate);
+            }
+        }
+
+        // Rendering needs a target, whose environment picks the policies and
+        // whether destructive statements need acknowledging.
+        if config.target.is_some() {
+            let policy = config.meta_command_policy()?;
+            let protected = config.target_config()?.protected;
+            let environment = config.policy_environment();
+            let has_rules = config
+                .policy
+                .rules
+                .iter()
+                .any(|rule| rule.applies_in(environment.as_deref()));
+            if !policy.is_empty() || protected || has_rules {
+                for (name, status) in &fs_status {
+                    if !status.has_up_sql {
+                        continue;
+                    }
+                    let guarded = protected
+                        && !Migrator::new(config, name, false)
+                            .load_metadata()
+                            .await?
+                            .destructive;
+                    if policy.is_empty() && !guarded && !has_rules {
+                        continue;
+                    }
+                    let sql = match render_migration(config, name, status.has_lock_toml, None).await
+                    {
+                        Ok(sql) => sql,
+                        Err(e) => {
+                            warnings.push(format!(
+                                "Migration {} could not be rendered to check it: {}",
+                                style(name).yellow(),
+                                e
+                            ));
+                            continue;
+                        }
+                    };
+                    for violation in policy.violations(&sql) {
+                        let message = format!(
+                            "Migration {} uses a forbidden psql meta-command on line {} of its rendered SQL: {}",
+                            name, violation.line, violation.message
+                        );
+                        warnings.push(message.clone());
+                        Annotation::error(message)
+                            .with_file(&config.pather().migration_script_file_path(name))
+                            .with_title("Forbidden meta-command")
+                            .emit(config.annotate);
+                    }
+                    if guarded {
+                        for statement in destructive_statements(&sql) {
+                            let message = format!(
+                                "Migration {} has an unacknowledged destructive statement ({}). Set `destructive = true` in its migration.toml.",
+                                name, statement
+                            );
+                            warnings.push(message.clone());
+                            Annotation::error(message)
+                                .with_file(&config.pather().migration_script_file_path(name))
+                                .with_title("Destructive statement")
+                                .emit(config.annotate);
+                        }
+                    }
+                    for violation in config.policy.violations(environment.as_deref(), &sql)? {
+                        let message =
+                            format!("Migration {} breaks policy rule {}", name, violation);
+                        warnings.push(message.clone());
+                        let annotation = match violation.severity {
+                            RuleSeverity::Warn => Annotation::warning(message),
+                            RuleSeverity::Block => Annotation::error(message),
+                        };
+                        annotation
+                            .with_file(&config.pather().migration_script_file_path(name))
+                            .with_title("Policy rule")
+                            .emit(config.annotate);
+                    }
+                }
             }
         }
 
@@ -0,0 +1,132 @@
+use crate::commands::migration::{build_to_folder, migrations_to_build, BuildManifest};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::status;
+use crate::variables::Variables;
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use std::fmt;
+use std::path::Path;
+
+/// Name of the manifest written alongside the SQL files by `spawn export`,
+/// listing them in the order they are to be applied.
+pub const EXPORT_INDEX_NAME: &str = "index.toml";
+
+/// Formats `spawn export` writes migrations in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// One rendered `.sql` file per migration, numbered in the order they
+    /// are applied, with an `index.toml`
+    PlainSql,
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExportFormat::PlainSql => "plain-sql",
+        })
+    }
+}
+
+/// Renders every migration into a folder that other tools can read without
+/// spawn.
+pub struct Export {
+    pub format: ExportFormat,
+    /// Folder to write to. Files from an earlier export to it are replaced.
+    pub dir: String,
+    pub pinned: bool,
+    pub variables: Option<Variables>,
+}
+
+impl TelemetryDescribe for Export {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("export").with_properties(vec![
+            ("format", self.format.to_string()),
+            ("opt_pinned", self.pinned.to_string()),
+            ("has_variables", self.variables.is_some().to_string()),
+        ])
+    }
+}
+
+/// `0001-20240501120000-add-users.sql`, numbered from 1 with at least four
+/// digits so the files sort in the order they are applied.
+fn numbered_file(index: usize, total: usize, migration: &str) -> String {
+    let width = total.to_string().len().max(4);
+    format!("{:0width$}-{}.sql", index + 1, migration, width = width)
+}
+
+/// Removes the files of an earlier export to `dir`, refusing to write into a
+/// folder holding anything else.
+fn clear_previous_export(dir: &Path) -> Result<()> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let files: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let index_path = dir.join(EXPORT_INDEX_NAME);
+    let index: BuildManifest = match std::fs::read_to_string(&index_path) {
+        Ok(contents) => toml::from_str(&contents)
+            .context(format!("could not parse {}", index_path.display()))?,
+        Err(_) => {
+            return Err(anyhow!(
+                "{} is not empty and was not written by spawn export",
+                dir.display()
+            ))
+        }
+    };
+    for file in index.migrations.iter().map(|m| &m.file) {
+        if files.contains(file) {
+            std::fs::remove_file(dir.join(file)).context(format!("could not remove {}", file))?;
+        }
+    }
+    Ok(())
+}
+
+impl Command for Export {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let migrations = migrations_to_build(config, self.pinned, false).await?;
+        let dir = Path::new(&self.dir);
+        clear_previous_export(dir)?;
+
+        let total = migrations.len();
+        let index = build_to_folder(
+            config,
+            &migrations,
+            self.pinned,
+            self.variables.clone(),
+            dir,
+            |i, migration| numbered_file(i, total, migration),
+        )
+        .await?;
+        std::fs::write(dir.join(EXPORT_INDEX_NAME), toml::to_string_pretty(&index)?)
+            .context(format!("could not write {}", EXPORT_INDEX_NAME))?;
+
+        status!(
+            "Exported {} migration{} to {}",
+            total,
+            if total == 1 { "" } else { "s" },
+            self.dir
+        );
+        Ok(Outcome::Success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_file() {
+        assert_eq!(
+            "0001-20240501120000-add-users.sql",
+            numbered_file(0, 3, "20240501120000-add-users")
+        );
+        assert_eq!("00012-m.sql", numbered_file(11, 12000, "m"));
+    }
+}
@@ -0,0 +1,654 @@
+use crate::commands::migration::{confirm_protected_target, DEFAULT_NAMESPACE};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::engine::{Engine, HistoryDetails, MigrationError};
+use crate::escape::{EscapedIdentifier, InsecureRawSql};
+use crate::migrator::{MigrationMetadata, Migrator};
+use crate::sql_query;
+use crate::status;
+use crate::store::list_migration_fs_status;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use std::path::Path;
+
+/// Migration tools that `spawn import` reads projects of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ImportSource {
+    /// `V<version>__<description>.sql` files and `flyway_schema_history`
+    Flyway,
+    /// A `sqitch.plan` with `deploy/<change>.sql` scripts and
+    /// `sqitch.changes`
+    Sqitch,
+    /// `<version>_<title>.up.sql` files and `schema_migrations`
+    GolangMigrate,
+}
+
+impl fmt::Display for ImportSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ImportSource::Flyway => "flyway",
+            ImportSource::Sqitch => "sqitch",
+            ImportSource::GolangMigrate => "golang-migrate",
+        })
+    }
+}
+
+impl ImportSource {
+    /// Table the tool records applied migrations in.
+    fn default_history_table(&self) -> &'static str {
+        match self {
+            ImportSource::Flyway => "flyway_schema_history",
+            ImportSource::Sqitch => "sqitch.changes",
+            ImportSource::GolangMigrate => "schema_migrations",
+        }
+    }
+}
+
+/// Converts the migrations of another tool into spawn migrations, adopting
+/// those its history table says are applied.
+pub struct Import {
+    pub from: ImportSource,
+    /// Folder holding the other tool's migrations
+    pub dir: String,
+    /// Adopt the migrations the other tool has applied to the target
+    pub adopt: bool,
+    /// The other tool's history table, such as `public.flyway_schema_history`
+    pub history_table: Option<String>,
+    /// Write the report to this file as well as printing it
+    pub report: Option<String>,
+    pub yes: bool,
+    /// With `yes`, change a protected target without typing its name
+    pub force: bool,
+}
+
+impl TelemetryDescribe for Import {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("import").with_properties(vec![
+            ("from", self.from.to_string()),
+            ("opt_adopt", self.adopt.to_string()),
+        ])
+    }
+}
+
+/// A migration of the other tool, ready to be written as a spawn migration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceMigration {
+    /// How the other tool's history table names the migration: the version
+    /// for Flyway and golang-migrate, the change for sqitch
+    pub version: String,
+    /// Name of the spawn migration, without its timestamp
+    pub name: String,
+    /// When the migration was planned (`YYYYMMDDHHMMSS`), if the other tool
+    /// says
+    pub timestamp: Option<String>,
+    pub description: Option<String>,
+    /// Path of the script, relative to the imported folder
+    pub path: String,
+    pub script: String,
+}
+
+/// The migrations found in a folder of another tool, oldest first, and the
+/// files that were left out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceProject {
+    pub migrations: Vec<SourceMigration>,
+    /// Each file left out, with why
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Reads the migrations of a `from` project in `dir`.
+pub fn read_source(from: ImportSource, dir: &Path) -> Result<SourceProject> {
+    match from {
+        ImportSource::Flyway => read_flyway(dir),
+        ImportSource::Sqitch => read_sqitch(dir),
+        ImportSource::GolangMigrate => read_golang_migrate(dir),
+    }
+}
+
+/// Every `.sql` file beneath `dir`, as paths relative to it.
+fn sql_files(dir: &Path, recursive: bool) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut folders = vec![dir.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        for entry in std::fs::read_dir(&folder)
+            .with_context(|| format!("could not read {}", folder.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive {
+                    folders.push(path);
+                }
+            } else if path.extension().is_some_and(|e| e == "sql") {
+                let relative = path.strip_prefix(dir).unwrap_or(&path);
+                files.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_script(dir: &Path, path: &str) -> Result<String> {
+    std::fs::read_to_string(dir.join(path)).with_context(|| format!("could not read {}", path))
+}
+
+/// A migration name from a description such as `Add_users table`.
+fn slug(description: &str) -> String {
+    description
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
+/// Orders versions such as `1.10` after `1.9`.
+fn version_key(version: &str) -> Vec<u128> {
+    version
+        .split('.')
+        .map(|part| part.parse().unwrap_or(u128::MAX))
+        .collect()
+}
+
+fn read_flyway(dir: &Path) -> Result<SourceProject> {
+    let versioned = Regex::new(r"^V([0-9][0-9._]*)__(.+)\.sql$")?;
+    let mut project = SourceProject::default();
+    for path in sql_files(dir, true)? {
+        let file = path.rsplit('/').next().unwrap_or(&path);
+        let Some(captures) = versioned.captures(file) else {
+            let reason = if file.starts_with("R__") {
+                "repeatable migrations are not imported"
+            } else if file
+                .strip_prefix('U')
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+            {
+                "undo migrations are not imported"
+            } else {
+                "not named like a Flyway migration (V<version>__<description>.sql)"
+            };
+            project.skipped.push((path, reason.to_string()));
+            continue;
+        };
+        // Flyway records V1_1 as version 1.1.
+        let version = captures[1].replace('_', ".");
+        let description = captures[2].replace('_', " ");
+        project.migrations.push(SourceMigration {
+            name: format!("v{}-{}", version.replace('.', "_"), slug(&description)),
+            version,
+            timestamp: None,
+            description: Some(description),
+            script: read_script(dir, &path)?,
+            path,
+        });
+    }
+    project.migrations.sort_by_key(|m| version_key(&m.version));
+    Ok(project)
+}
+
+fn read_golang_migrate(dir: &Path) -> Result<SourceProject> {
+    let up = Regex::new(r"^([0-9]+)_(.+)\.(up|down)\.sql$")?;
+    let mut project = SourceProject::default();
+    for path in sql_files(dir, false)? {
+        let Some(captures) = up.captures(&path) else {
+            project.skipped.push((
+                path,
+                "not named like a golang-migrate migration (<version>_<title>.up.sql)".to_string(),
+            ));
+            continue;
+        };
+        if &captures[3] == "down" {
+            project
+                .skipped
+                .push((path, "down migrations are not imported".to_string()));
+            continue;
+        }
+        // schema_migrations keeps the version as a number.
+        let version = captures[1].trim_start_matches('0');
+        let version = if version.is_empty() { "0" } else { version }.to_string();
+        let title = slug(&captures[2]);
+        let timestamp = Some(captures[1].to_string()).filter(|v| v.len() == 14);
+        project.migrations.push(SourceMigration {
+            name: match timestamp {
+                Some(_) => title,
+                None => format!("v{}-{}", version, title),
+            },
+            version,
+            timestamp,
+            description: None,
+            script: read_script(dir, &path)?,
+            path,
+        });
+    }
+    project.migrations.sort_by_key(|m| version_key(&m.version));
+    Ok(project)
+}
+
+/// One change of a `sqitch.plan`: `name [deps] time planner <email> # note`.
+fn parse_plan_line(line: &str) -> Option<(String, Option<String>, Option<String>)> {
+    let (name, mut rest) = line.split_once(char::is_whitespace)?;
+    rest = rest.trim_start();
+    if let Some(after) = rest.strip_prefix('[') {
+        rest = after.split_once(']')?.1.trim_start();
+    }
+    let (time, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let timestamp = DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|t| t.with_timezone(&Utc).format("%Y%m%d%H%M%S").to_string());
+    let note = rest
+        .split_once('>')
+        .and_then(|(_, after)| after.trim_start().strip_prefix('#'))
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty());
+    Some((name.to_string(), timestamp, note))
+}
+
+fn read_sqitch(dir: &Path) -> Result<SourceProject> {
+    let plan =
+        std::fs::read_to_string(dir.join("sqitch.plan")).context("could not read sqitch.plan")?;
+    let mut project = SourceProject::default();
+    let mut seen = BTreeSet::new();
+    for line in plan.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', '%', '@']) {
+            continue;
+        }
+        let Some((change, timestamp, note)) = parse_plan_line(line) else {
+            continue;
+        };
+        let path = format!("deploy/{}.sql", change);
+        if !seen.insert(change.clone()) {
+            project.skipped.push((
+                path,
+                format!("change {} is reworked, which is not imported", change),
+            ));
+            continue;
+        }
+        if !dir.join(&path).is_file() {
+            project
+                .skipped
+                .push((path, "deploy script not found".to_string()));
+            continue;
+        }
+        project.migrations.push(SourceMigration {
+            name: slug(&change),
+            version: change,
+            timestamp,
+            description: note,
+            script: read_script(dir, &path)?,
+            path,
+        });
+    }
+    Ok(project)
+}
+
+/// The script as a spawn migration. Scripts that would be read as template
+/// syntax are kept verbatim.
+fn to_template(script: &str) -> String {
+    if ["{{", "{%", "{#"].iter().any(|s| script.contains(s)) {
+        format!("{{% raw %}}\n{}{{% endraw %}}\n", script)
+    } else {
+        script.to_string()
+    }
+}
+
+/// `table`, optionally qualified by its schema, quoted for SQL.
+fn table_sql(table: &str) -> InsecureRawSql {
+    let quoted: Vec<String> = table
+        .split('.')
+        .map(|part| EscapedIdentifier::new(part).to_string())
+        .collect();
+    // Built only from quoted identifiers.
+    InsecureRawSql::new(&quoted.join("."))
+}
+
+impl Import {
+    /// The versions the other tool's history table says are applied.
+    async fn applied_versions(
+        &self,
+        engine: &dyn Engine,
+        project: &SourceProject,
+    ) -> Result<BTreeSet<String>> {
+        let table = self
+            .history_table
+            .as_deref()
+            .unwrap_or(self.from.default_history_table());
+        let query = match self.from {
+            ImportSource::Flyway => sql_query!(
+                "SELECT version FROM {} WHERE success AND version IS NOT NULL;",
+                table_sql(table)
+            ),
+            ImportSource::Sqitch => sql_query!("SELECT change FROM {};", table_sql(table)),
+            ImportSource::GolangMigrate => {
+                sql_query!("SELECT version, dirty FROM {};", table_sql(table))
+            }
+        };
+        let rows = engine
+            .query(&query)
+            .await
+            .with_context(|| format!("could not read the {} history table {}", self.from, table))?;
+
+        if self.from != ImportSource::GolangMigrate {
+            return Ok(rows
+                .rows
+                .into_iter()
+                .filter_map(|row| row.into_iter().next().flatten())
+                .collect());
+        }
+        // golang-migrate keeps one row: the latest version applied.
+        let Some(row) = rows.rows.into_iter().next() else {
+            return Ok(BTreeSet::new());
+        };
+        let version = row.first().cloned().flatten().unwrap_or_default();
+        if row.get(1).cloned().flatten().as_deref() == Some("t") {
+            return Err(anyhow!(
+                "{} is dirty at version {}, fix it with `migrate force` before importing",
+                table,
+                version
+            ));
+        }
+        Ok(project
+            .migrations
+            .iter()
+            .filter(|m| version_key(&m.version) <= version_key(&version))
+            .map(|m| m.version.clone())
+            .collect())
+    }
+}
+
+impl Command for Import {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let dir = Path::new(&self.dir);
+        let project = read_source(self.from, dir)?;
+        if project.migrations.is_empty() {
+            return Err(anyhow!("no {} migrations found in {}", self.from, self.dir));
+        }
+
+        let adopting = if self.adopt {
+            if !confirm_protected_target(config, "adopt", self.yes, self.force)? {
+                return Ok(Outcome::Success);
+            }
+            let engine = config.new_engine().await?;
+            let applied = self.applied_versions(engine.as_ref(), &project).await?;
+            Some((engine, applied))
+        } else {
+            None
+        };
+
+        // Migrations imported before keep their names, so importing again
+        // only adds what is new.
+        let existing: BTreeMap<String, String> =
+            list_migration_fs_status(config.operator(), &config.pather(), None)
+                .await?
+                .into_keys()
+                .filter_map(|name| {
+                    let suffix = name.split_once('-')?.1.to_string();
+                    Some((suffix, name))
+                })
+                .collect();
+
+        // Migrations without a timestamp of their own are given one a second
+        // apart, ending now, so they keep their order and sort before any
+        // created afterwards.
+        let total = project.migrations.len() as i64;
+        let start = Utc::now() - Duration::seconds(total);
+
+        let mut report = Report::new(self.from, &self.dir);
+        for (i, migration) in project.migrations.iter().enumerate() {
+            let name = match existing.get(&migration.name) {
+                Some(name) => {
+                    report.existing.push((migration.path.clone(), name.clone()));
+                    name.clone()
+                }
+                None => {
+                    let timestamp = migration.timestamp.clone().unwrap_or_else(|| {
+                        (start + Duration::seconds(i as i64))
+                            .format("%Y%m%d%H%M%S")
+                            .to_string()
+                    });
+                    let name = format!("{}-{}", timestamp, migration.name);
+                    let script = to_template(&migration.script);
+                    if script != migration.script {
+                        report.raw.push(name.clone());
+                    }
+                    let mg = Migrator::new(config, &name, false);
+                    mg.create_migration_with(&script).await?;
+                    mg.write_metadata(&MigrationMetadata {
+                        description: Some(match &migration.description {
+                            Some(description) => format!(
+                                "{} (imported from {} {})",
+                                description, self.from, migration.path
+                            ),
+                            None => format!("Imported from {} {}", self.from, migration.path),
+                        }),
+                        ..Default::default()
+                    })
+                    .await?;
+                    config.audit_session.record_migration(&name);
+                    report.imported.push((migration.path.clone(), name.clone()));
+                    name
+                }
+            };
+
+            let Some((engine, applied)) = &adopting else {
+                continue;
+            };
+            if !applied.contains(&migration.version) {
+                continue;
+            }
+            let details = HistoryDetails {
+                created_by: config.operator_identity(),
+                description: Some(format!(
+                    "Imported from {}, where {} was applied",
+                    self.from, migration.version
+                )),
+                ..Default::default()
+            };
+            config.audit_session.record_migration(&name);
+            match engine
+                .migration_adopt(&name, DEFAULT_NAMESPACE, details)
+                .await
+            {
+                Ok(_) => report.adopted.push(name),
+                Err(MigrationError::AlreadyApplied { .. }) => report.already_adopted.push(name),
+                Err(e) => {
+                    return Err(anyhow!(e).context(format!("Failed adopting migration '{}'", name)))
+                }
+            }
+        }
+        if let Some((_, applied)) = &adopting {
+            let known: BTreeSet<&String> = project.migrations.iter().map(|m| &m.version).collect();
+            report.missing = applied
+                .iter()
+                .filter(|version| !known.contains(version))
+                .cloned()
+                .collect();
+        }
+        report.skipped = project.skipped;
+
+        let text = report.to_markdown();
+        print!("{}", text);
+        if let Some(path) = &self.report {
+            std::fs::write(path, &text).with_context(|| format!("could not write {}", path))?;
+            status!("Report written to {}", path);
+        }
+        Ok(Outcome::Success)
+    }
+}
+
+/// What `spawn import` did, printed as Markdown.
+#[derive(Debug, Default)]
+struct Report {
+    from: String,
+    dir: String,
+    /// Source file and the migration written for it
+    imported: Vec<(String, String)>,
+    /// Source file and the migration it was imported as before
+    existing: Vec<(String, String)>,
+    /// Migrations wrapped in `{% raw %}` as they hold template syntax
+    raw: Vec<String>,
+    adopted: Vec<String>,
+    already_adopted: Vec<String>,
+    /// Versions in the history table with no file
+    missing: Vec<String>,
+    skipped: Vec<(String, String)>,
+}
+
+impl Report {
+    fn new(from: ImportSource, dir: &str) -> Self {
+        Report {
+            from: from.to_string(),
+            dir: dir.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Import from {} ({})\n", self.from, self.dir);
+        let _ = writeln!(
+            out,
+            "{} imported, {} already imported, {} adopted, {} skipped.\n",
+            self.imported.len(),
+            self.existing.len(),
+            self.adopted.len(),
+            self.skipped.len()
+        );
+        let mut section = |title: &str, lines: Vec<String>| {
+            if lines.is_empty() {
+                return;
+            }
+            let _ = writeln!(out, "## {}\n", title);
+            for line in lines {
+                let _ = writeln!(out, "- {}", line);
+            }
+            let _ = writeln!(out);
+        };
+        let arrow = |pairs: &[(String, String)]| {
+            pairs
+                .iter()
+                .map(|(from, to)| format!("`{}` → `{}`", from, to))
+                .collect()
+        };
+        let code = |names: &[String]| names.iter().map(|n| format!("`{}`", n)).collect();
+        section("Imported", arrow(&self.imported));
+        section("Already imported", arrow(&self.existing));
+        section("Adopted", code(&self.adopted));
+        section("Already adopted", code(&self.already_adopted));
+        section(
+            "Kept verbatim",
+            self.raw
+                .iter()
+                .map(|n| {
+                    format!(
+                        "`{}` holds template syntax, so is wrapped in `{{% raw %}}`",
+                        n
+                    )
+                })
+                .collect(),
+        );
+        section(
+            "Applied without a file",
+            self.missing
+                .iter()
+                .map(|v| format!("`{}` is in the {} history but was not found", v, self.from))
+                .collect(),
+        );
+        section(
+            "Skipped",
+            self.skipped
+                .iter()
+                .map(|(path, reason)| format!("`{}`: {}", path, reason))
+                .collect(),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plan_line() {
+        assert_eq!(
+            Some((
+                "users".to_string(),
+                Some("20240501120000".to_string()),
+                Some("Adds the users table".to_string())
+            )),
+            parse_plan_line(
+                "users [appschema] 2024-05-01T12:00:00Z Jo Bloggs <jo@example.com> # Adds the users table"
+            )
+        );
+        assert_eq!(
+            Some((
+                "appschema".to_string(),
+                Some("20240430090000".to_string()),
+                None
+            )),
+            parse_plan_line("appschema 2024-04-30T09:00:00Z Jo Bloggs <jo@example.com>")
+        );
+    }
+
+    #[test]
+    fn test_version_order() {
+        let mut versions = vec!["1.10", "2", "1.9", "1.1"];
+        versions.sort_by_key(|v| version_key(v));
+        assert_eq!(vec!["1.1", "1.9", "1.10", "2"], versions);
+        assert_eq!("add-users-table", slug("Add_users table"));
+    }
+
+    #[test]
+    fn test_read_golang_migrate_and_sqitch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |path: &str, contents: &str| std::fs::write(dir.path().join(path), contents);
+        write(
+            "000002_add_email.up.sql",
+            "ALTER TABLE users ADD email text;",
+        )?;
+        write("000002_add_email.down.sql", "ALTER TABLE users DROP email;")?;
+        write(
+            "20240501120000_create_users.up.sql",
+            "CREATE TABLE users (id int);",
+        )?;
+        let project = read_source(ImportSource::GolangMigrate, dir.path())?;
+        let names: Vec<(&str, &str, Option<&str>)> = project
+            .migrations
+            .iter()
+            .map(|m| (m.version.as_str(), m.name.as_str(), m.timestamp.as_deref()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("2", "v2-add-email", None),
+                ("20240501120000", "create-users", Some("20240501120000")),
+            ],
+            names
+        );
+        assert_eq!(1, project.skipped.len());
+
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("deploy"))?;
+        std::fs::write(
+            dir.path().join("sqitch.plan"),
+            "%project=app\n\nusers 2024-05-01T12:00:00Z Jo <jo@example.com> # Users\n@v1 2024-05-02T00:00:00Z Jo <jo@example.com>\nposts [users] 2024-05-03T12:00:00Z Jo <jo@example.com>\n",
+        )?;
+        std::fs::write(
+            dir.path().join("deploy/users.sql"),
+            "CREATE TABLE users ();",
+        )?;
+        let project = read_source(ImportSource::Sqitch, dir.path())?;
+        assert_eq!(1, project.migrations.len());
+        assert_eq!(Some("Users".to_string()), project.migrations[0].description);
+        assert_eq!(
+            vec![(
+                "deploy/posts.sql".to_string(),
+                "deploy script not found".to_string()
+            )],
+            project.skipped
+        );
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::commands::{Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::ConfigLoaderSaver;
 use crate::engine::{CommandSpec, EngineType, TargetConfig};
+use crate::status;
 use anyhow::{anyhow, Result};
 use opendal::Operator;
 use std::collections::HashMap;
@@ -71,6 +72,12 @@ impl Init {
                         db_name.clone(),
                     ],
                 }),
+                url: None,
+                session: None,
+                protected: false,
+                history: None,
+                retry: None,
+                wait_for_lock: None,
             },
         );
 
@@ -82,6 +89,29 @@ impl Init {
             targets: Some(targets),
             project_id: Some(project_id.clone()),
             telemetry: None,
+            tenants: None,
+            template: None,
+            pin_on_apply: None,
+            edit_new_migrations: None,
+            naming: None,
+            allow_out_of_order: None,
+            identity: None,
+            pin_cache: None,
+            signing: None,
+            transcripts: None,
+            environments: None,
+            variables: None,
+            packages: None,
+            meta_commands: None,
+            policy: None,
+            lock_advisor: None,
+            notifications: None,
+            tracing: None,
+            audit: None,
+            layout: None,
+            agent: None,
+            schema_docs: None,
+            scrub: None,
         };
 
         // Save the config
@@ -132,20 +162,20 @@ impl Init {
                     anyhow::Error::from(e).context("Failed to create docker-compose.yaml")
                 })?;
 
-            println!("Created docker-compose.yaml for database '{}'", db_name);
-            println!("Start the database with: docker compose up -d");
-            println!();
+            status!("Created docker-compose.yaml for database '{}'", db_name);
+            status!("Start the database with: docker compose up -d");
+            status!();
         }
 
         // Show telemetry notice
         crate::show_telemetry_notice();
 
-        println!("Initialized spawn project with project_id: {}", project_id);
-        println!("Created directories:");
+        status!("Initialized spawn project with project_id: {}", project_id);
+        status!("Created directories:");
         for folder in &created_folders {
-            println!("{}", folder);
+            status!("{}", folder);
         }
-        println!(
+        status!(
             "\nEdit {} to configure your database connection.",
             &self.config_file
         );
@@ -0,0 +1,52 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::signing::{encode_public_key, encode_signing_key, generate_key};
+use crate::status;
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+
+pub struct Keygen {
+    /// Path to write the private key to
+    pub out: String,
+}
+
+impl TelemetryDescribe for Keygen {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("keygen")
+    }
+}
+
+impl Command for Keygen {
+    async fn execute(&self, _config: &Config) -> Result<Outcome> {
+        if std::path::Path::new(&self.out).exists() {
+            return Err(anyhow!(
+                "'{}' already exists. Use a different path or remove the existing file.",
+                &self.out
+            ));
+        }
+
+        let key = generate_key();
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&self.out)
+            .context(format!("could not create {}", &self.out))?;
+        writeln!(file, "{}", encode_signing_key(&key))?;
+
+        let public = encode_public_key(&key.verifying_key());
+        status!("Private key written to {}. Keep it secret.", &self.out);
+        status!();
+        status!("Add the public key to spawn.toml to trust it:");
+        status!();
+        println!("[signing]");
+        println!("trusted_keys = [\"{}\"]", public);
+
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,252 @@
+use crate::commands::migration::{get_combined_migration_status, DEFAULT_NAMESPACE};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::engine::{MigrationActivity, MigrationHistoryEntry, MigrationStatus};
+use crate::status;
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use std::fmt::Write;
+
+pub struct Metrics {
+    /// Write the metrics to this file instead of stdout, replacing it in one
+    /// step so a collector never reads a partial file
+    pub output: Option<String>,
+}
+
+impl TelemetryDescribe for Metrics {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("metrics")
+            .with_properties(vec![("has_output", self.output.is_some().to_string())])
+    }
+}
+
+/// Migration metrics of the selected target, as exported by `spawn metrics`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MigrationMetrics {
+    /// Migrations whose latest activity succeeded
+    pub applied: u64,
+    /// Migrations in the project with no history on the target
+    pub pending: u64,
+    /// Migrations whose latest activity failed or did not finish
+    pub failed: u64,
+    /// Successful applies recorded in the history
+    pub successful_applies: u64,
+    /// Failed applies recorded in the history
+    pub failed_applies: u64,
+    /// When the latest successful apply was recorded, in seconds since the
+    /// Unix epoch
+    pub last_apply_timestamp: Option<i64>,
+    /// How long the latest successful apply took, in seconds
+    pub last_apply_seconds: Option<f64>,
+}
+
+impl MigrationMetrics {
+    fn add_history(&mut self, history: &[MigrationHistoryEntry]) {
+        let applies = history
+            .iter()
+            .filter(|e| e.activity == MigrationActivity::Apply.as_str());
+        let mut last: Option<(i64, f64)> = None;
+        for entry in applies {
+            match entry.status {
+                Some(MigrationStatus::Success) => {
+                    self.successful_applies += 1;
+                    let Ok(at) = DateTime::parse_from_rfc3339(&entry.created_at) else {
+                        continue;
+                    };
+                    if last.is_none_or(|(latest, _)| at.timestamp() >= latest) {
+                        last = Some((at.timestamp(), entry.execution_time));
+                    }
+                }
+                Some(MigrationStatus::Failure) => self.failed_applies += 1,
+                _ => {}
+            }
+        }
+        self.last_apply_timestamp = last.map(|(at, _)| at);
+        self.last_apply_seconds = last.map(|(_, seconds)| seconds);
+    }
+
+    /// The metrics in the Prometheus text format, each with `labels`.
+    pub fn to_prometheus(&self, labels: &[(&str, &str)]) -> String {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+            .collect();
+        let with = |extra: Option<String>| {
+            let all: Vec<&str> = labels
+                .iter()
+                .map(String::as_str)
+                .chain(extra.as_deref())
+                .collect();
+            if all.is_empty() {
+                String::new()
+            } else {
+                format!("{{{}}}", all.join(","))
+            }
+        };
+
+        let mut out = String::new();
+        let mut metric =
+            |name: &str, kind: &str, help: &str, values: &[(Option<String>, String)]| {
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "# TYPE {} {}", name, kind);
+                for (extra, value) in values {
+                    let _ = writeln!(out, "{}{} {}", name, with(extra.clone()), value);
+                }
+            };
+        metric(
+            "spawn_migrations_applied",
+            "gauge",
+            "Migrations whose latest activity succeeded.",
+            &[(None, self.applied.to_string())],
+        );
+        metric(
+            "spawn_migrations_pending",
+            "gauge",
+            "Migrations in the project that have not been applied.",
+            &[(None, self.pending.to_string())],
+        );
+        metric(
+            "spawn_migrations_failed",
+            "gauge",
+            "Migrations whose latest activity failed or did not finish.",
+            &[(None, self.failed.to_string())],
+        );
+        metric(
+            "spawn_migration_applies_total",
+            "counter",
+            "Applies recorded in the migration history, by result.",
+            &[
+                (
+                    Some("result=\"success\"".to_string()),
+                    self.successful_applies.to_string(),
+                ),
+                (
+                    Some("result=\"failure\"".to_string()),
+                    self.failed_applies.to_string(),
+                ),
+            ],
+        );
+        if let (Some(at), Some(seconds)) = (self.last_apply_timestamp, self.last_apply_seconds) {
+            metric(
+                "spawn_migration_last_apply_timestamp_seconds",
+                "gauge",
+                "When the latest successful apply was recorded.",
+                &[(None, at.to_string())],
+            );
+            metric(
+                "spawn_migration_last_apply_duration_seconds",
+                "gauge",
+                "How long the latest successful apply took.",
+                &[(None, seconds.to_string())],
+            );
+        }
+        out
+    }
+}
+
+/// Escapes a label value as the Prometheus text format requires.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Collects the migration metrics of the selected target.
+pub async fn collect_metrics(config: &Config) -> Result<MigrationMetrics> {
+    let mut metrics = MigrationMetrics::default();
+    for row in get_combined_migration_status(config, Some(DEFAULT_NAMESPACE)).await? {
+        match row.last_status {
+            Some(MigrationStatus::Success) => metrics.applied += 1,
+            Some(_) => metrics.failed += 1,
+            None if row.exists_in_filesystem => metrics.pending += 1,
+            None => {}
+        }
+    }
+    let history = config
+        .new_engine()
+        .await?
+        .get_migration_history(Some(DEFAULT_NAMESPACE))
+        .await?;
+    metrics.add_history(&history);
+    Ok(metrics)
+}
+
+impl Command for Metrics {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let metrics = collect_metrics(config).await?;
+        let target = config.target.clone().unwrap_or_default();
+        let environment = config.policy_environment().unwrap_or_default();
+        let text = metrics.to_prometheus(&[("target", &target), ("environment", &environment)]);
+
+        match &self.output {
+            Some(path) => {
+                let partial = format!("{}.tmp", path);
+                std::fs::write(&partial, &text)
+                    .with_context(|| format!("could not write {}", partial))?;
+                std::fs::rename(&partial, path)
+                    .with_context(|| format!("could not write {}", path))?;
+                status!("Metrics written to {}", path);
+            }
+            None => print!("{}", text),
+        }
+        Ok(Outcome::Success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(status: MigrationStatus, created_at: &str, seconds: f64) -> MigrationHistoryEntry {
+        MigrationHistoryEntry {
+            migration_name: "m".to_string(),
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            activity: MigrationActivity::Apply.as_str().to_string(),
+            status: Some(status),
+            created_at: created_at.to_string(),
+            execution_time: seconds,
+            checksum: None,
+            pin_hash: None,
+            created_by: None,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_to_prometheus() {
+        let mut metrics = MigrationMetrics {
+            applied: 2,
+            pending: 1,
+            ..Default::default()
+        };
+        metrics.add_history(&[
+            entry(MigrationStatus::Success, "2024-05-01T02:00:00+00:00", 1.5),
+            entry(MigrationStatus::Failure, "2024-05-02T02:00:00+00:00", 0.2),
+            entry(MigrationStatus::Success, "2024-05-03T02:00:00+00:00", 0.25),
+        ]);
+        assert_eq!(
+            "# HELP spawn_migrations_applied Migrations whose latest activity succeeded.
+# TYPE spawn_migrations_applied gauge
+spawn_migrations_applied{target=\"prod \\\"eu\\\"\"} 2
+# HELP spawn_migrations_pending Migrations in the project that have not been applied.
+# TYPE spawn_migrations_pending gauge
+spawn_migrations_pending{target=\"prod \\\"eu\\\"\"} 1
+# HELP spawn_migrations_failed Migrations whose latest activity failed or did not finish.
+# TYPE spawn_migrations_failed gauge
+spawn_migrations_failed{target=\"prod \\\"eu\\\"\"} 0
+# HELP spawn_migration_applies_total Applies recorded in the migration history, by result.
+# TYPE spawn_migration_applies_total counter
+spawn_migration_applies_total{target=\"prod \\\"eu\\\"\",result=\"success\"} 2
+spawn_migration_applies_total{target=\"prod \\\"eu\\\"\",result=\"failure\"} 1
+# HELP spawn_migration_last_apply_timestamp_seconds When the latest successful apply was recorded.
+# TYPE spawn_migration_last_apply_timestamp_seconds gauge
+spawn_migration_last_apply_timestamp_seconds{target=\"prod \\\"eu\\\"\"} 1714701600
+# HELP spawn_migration_last_apply_duration_seconds How long the latest successful apply took.
+# TYPE spawn_migration_last_apply_duration_seconds gauge
+spawn_migration_last_apply_duration_seconds{target=\"prod \\\"eu\\\"\"} 0.25
+",
+            metrics.to_prometheus(&[("target", "prod \"eu\"")])
+        );
+    }
+}
@@ -1,13 +1,18 @@
-use crate::commands::migration::get_pending_and_confirm;
+use crate::commands::migration::{confirm_protected_target, get_pending_and_confirm};
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
-use crate::engine::MigrationError;
+use crate::engine::{HistoryDetails, MigrationError};
+use crate::notify::{notify, Notification, NotifyStatus};
+use crate::status;
 use anyhow::{anyhow, Result};
 use dialoguer::Editor;
+use std::time::Instant;
 
 pub struct AdoptMigration {
     pub migration: Option<String>,
     pub yes: bool,
+    /// With `yes`, change a protected target without typing its name
+    pub force: bool,
     pub description: Option<String>,
 }
 
@@ -42,6 +47,10 @@ fn prompt_description() -> Result<String> {
 
 impl Command for AdoptMigration {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
+        if !confirm_protected_target(config, "adopt", self.yes, self.force)? {
+            return Ok(Outcome::AdoptedMigration);
+        }
+
         let migrations = match &self.migration {
             Some(migration) => vec![migration.clone()],
             None => match get_pending_and_confirm(config, "adopt", self.yes).await? {
@@ -63,6 +72,11 @@ impl Command for AdoptMigration {
         };
 
         let engine = config.new_engine().await?;
+        let details = HistoryDetails {
+            created_by: config.operator_identity(),
+            description: Some(description),
+            ..Default::default()
+        };
 
         let total = migrations.len();
         for (i, migration) in migrations.iter().enumerate() {
@@ -76,23 +90,43 @@ impl Command for AdoptMigration {
             } else {
                 String::new()
             };
+            let started = Instant::now();
+            config.audit_session.record_migration(migration);
             match engine
-                .migration_adopt(migration, super::DEFAULT_NAMESPACE, &description)
+                .migration_adopt(migration, super::DEFAULT_NAMESPACE, details.clone())
                 .await
             {
                 Ok(msg) => {
-                    println!("{}{}", counter, msg);
+                    status!("{}{}", counter, msg);
+                    let adopted = Notification::new(
+                        config,
+                        migration,
+                        NotifyStatus::Adopted,
+                        started.elapsed(),
+                    );
+                    notify(config, adopted).await;
                 }
                 Err(MigrationError::AlreadyApplied { info, .. }) => {
-                    println!(
+                    status!(
                         "{}Migration '{}' already applied (status: {}, activity: {})",
-                        counter, migration, info.last_status, info.last_activity
+                        counter,
+                        migration,
+                        info.last_status,
+                        info.last_activity
                     );
                 }
                 Err(e) => {
-                    return Err(
-                        anyhow!(e).context(format!("Failed adopting migration '{}'", migration))
-                    );
+                    let e =
+                        anyhow!(e).context(format!("Failed adopting migration '{}'", migration));
+                    let failed = Notification::new(
+                        config,
+                        migration,
+                        NotifyStatus::Failed,
+                        started.elapsed(),
+                    )
+                    .with_error(&e);
+                    notify(config, failed).await;
+                    return Err(e);
                 }
             }
         }
@@ -1,18 +1,103 @@
-use crate::commands::migration::get_pending_and_confirm;
+use crate::advisor::{advise, advise_rendered, LockWarning};
+use crate::commands::migration::progress::{ApplyProgress, ApplyStats, StepResult};
+use crate::commands::migration::{
+    check_migration_order, confirm_migrations, confirm_protected_target, get_pending_migrations,
+    pin_migration,
+};
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
-use crate::engine::{Engine, MigrationError};
-use crate::migrator::Migrator;
+use crate::engine::{
+    server_messages, Engine, HistoryDetails, MigrationError, MigrationResult, MigrationStatus,
+    WriterFn,
+};
+use crate::errors::ErrorKind;
+use crate::events::StatementWriter;
+use crate::interrupt;
+use crate::migrator::{MigrationMetadata, Migrator};
+use crate::notify::{notify, Notification, NotifyStatus};
+use crate::policy::{check_destructive, enforce_rules};
+use crate::schedule::Schedule;
+use crate::schema_docs;
+use crate::signing::{verify_artifact, verify_migration};
+use crate::status;
+use crate::store::get_migration_fs_status;
+use crate::telemetry::CommandStatus;
+use crate::tracing::{Span, SpanKind};
+use crate::transcript::{SharedBufWriter, TranscriptRecorder};
 use crate::variables::Variables;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use console::style;
+use dialoguer::Select;
+use std::collections::HashSet;
+use std::io::{IsTerminal, Write};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 pub struct ApplyMigration {
     pub migration: Option<String>,
     pub pinned: bool,
     pub variables: Option<Variables>,
     pub yes: bool,
+    /// With `yes`, change a protected target without typing its name
+    pub force: bool,
     pub retry: bool,
     pub reuse_connection: bool,
+    /// Refuse to apply migrations without a valid signature from a trusted key
+    pub require_signature: bool,
+    /// Apply this previously built SQL file as `migration` instead of
+    /// rendering the migration's template
+    pub from_file: Option<String>,
+    /// Show each migration's SQL and ask whether to apply, skip or abort
+    pub interactive: bool,
+    /// Refuse migrations that have not already succeeded on this target (or
+    /// the target of this environment), to promote them through environments
+    pub promote_from: Option<String>,
+    /// Apply destructive statements to a protected target without each
+    /// migration acknowledging them
+    pub allow_destructive: bool,
+    /// When to start: waits for `--at` and the maintenance window after
+    /// confirming, before anything is applied
+    pub schedule: Schedule,
+}
+
+/// How an apply went: each migration attempted, in order, with how it
+/// ended. Migrations after a failure are not attempted, and the apply
+/// returns an error instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyReport {
+    pub migrations: Vec<(String, StepResult)>,
+    /// The user declined to go ahead, or aborted with `--interactive`, so
+    /// some migrations were not applied
+    pub aborted: bool,
+}
+
+impl ApplyReport {
+    fn aborted() -> Self {
+        Self {
+            migrations: Vec::new(),
+            aborted: true,
+        }
+    }
+
+    /// Migrations that were applied, not counting those already applied or
+    /// skipped.
+    pub fn applied(&self) -> Vec<&str> {
+        self.migrations
+            .iter()
+            .filter(|(_, result)| *result == StepResult::Applied)
+            .map(|(migration, _)| migration.as_str())
+            .collect()
+    }
+}
+
+/// What to do with a migration, as chosen with `--interactive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Choice {
+    Apply,
+    Skip,
+    Abort,
 }
 
 impl TelemetryDescribe for ApplyMigration {
@@ -22,22 +107,355 @@ impl TelemetryDescribe for ApplyMigration {
             ("has_variables", self.variables.is_some().to_string()),
             ("apply_all", self.migration.is_none().to_string()),
             ("opt_reuse_connection", self.reuse_connection.to_string()),
+            ("opt_require_signature", self.require_signature.to_string()),
+            ("opt_from_file", self.from_file.is_some().to_string()),
+            ("opt_interactive", self.interactive.to_string()),
+            ("opt_force", self.force.to_string()),
+            ("has_promote_from", self.promote_from.is_some().to_string()),
+            ("opt_allow_destructive", self.allow_destructive.to_string()),
+            ("has_at", self.schedule.at.is_some().to_string()),
+            ("has_window", self.schedule.window.is_some().to_string()),
+            ("opt_no_wait", self.schedule.no_wait.to_string()),
         ])
     }
 }
 
+/// Whether `migration` has no lock file and no history in the database, and
+/// so is pinned with `pin_on_apply` before its first apply, so that the
+/// apply is always recorded against a pin.
+async fn never_applied(config: &Config, engine: &dyn Engine, migration: &str) -> Result<bool> {
+    let fs_status = get_migration_fs_status(config.operator(), &config.pather(), migration).await?;
+    if fs_status.has_lock_toml || !fs_status.has_up_sql {
+        return Ok(false);
+    }
+
+    let previously_seen = engine
+        .get_migrations_from_db(Some(super::DEFAULT_NAMESPACE))
+        .await?
+        .iter()
+        .any(|m| m.migration_name == migration);
+    Ok(!previously_seen)
+}
+
+/// Refuses `migrations` unless each has already succeeded on `source`, a
+/// target or environment, so that a migration only reaches the selected
+/// target after being applied to the one before it.
+async fn check_promoted(config: &Config, source: &str, migrations: &[String]) -> Result<()> {
+    let source_config = config.for_target(source)?;
+    if source_config.target == config.target {
+        return Err(anyhow!(
+            "--from {} names the target being applied to",
+            source
+        ));
+    }
+
+    let engine = source_config.new_engine().await.context(format!(
+        "could not connect to '{}' to check promotion",
+        source
+    ))?;
+    let succeeded: HashSet<String> = engine
+        .get_migrations_from_db(Some(super::DEFAULT_NAMESPACE))
+        .await?
+        .into_iter()
+        .filter(|m| m.last_status == Some(MigrationStatus::Success))
+        .map(|m| m.migration_name)
+        .collect();
+
+    let missing: Vec<&str> = migrations
+        .iter()
+        .filter(|m| !succeeded.contains(m.as_str()))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "refusing to apply migrations that have not succeeded on '{}':\n  {}\n\
+             Apply them there first.",
+            source,
+            missing.join("\n  ")
+        ));
+    }
+    Ok(())
+}
+
+/// Tells the webhooks in `[notifications]` that `migration` was applied
+/// (`Ok(true)`) or failed. Nothing is sent for `Ok(false)`, such as a
+/// migration that was skipped or already applied.
+async fn notify_step(
+    config: &Config,
+    migration: &str,
+    outcome: Result<bool, &anyhow::Error>,
+    started: Instant,
+) {
+    let notification = match outcome {
+        Ok(false) => return,
+        Ok(true) => Notification::new(config, migration, NotifyStatus::Applied, started.elapsed()),
+        Err(e) => Notification::new(config, migration, NotifyStatus::Failed, started.elapsed())
+            .with_error(e),
+    };
+    notify(config, notification).await;
+}
+
+fn report_lock_warnings(config: &Config, warnings: &[LockWarning]) {
+    for warning in warnings {
+        config.events.on_warning(&warning.to_string());
+    }
+}
+
+/// Prints the id of the trace the apply's spans are sent in, so that it
+/// can be found from the deploy's output.
+fn announce_trace(config: &Config) {
+    if let Some(trace_id) = config.tracer.trace_id() {
+        status!("Trace ID: {}", trace_id);
+    }
+}
+
+/// Starts the span of applying `migration`.
+fn migration_span(config: &Config, migration: &str, pinned: bool) -> Span {
+    config
+        .tracer
+        .span(&format!("migration {}", migration), SpanKind::Internal)
+        .with_attribute("spawn.migration", migration)
+        .with_attribute("spawn.pinned", pinned.to_string())
+}
+
+/// Reports the result of applying a migration, turning failures into errors
+/// that explain how to recover.
+fn report_apply_result(
+    progress: &ApplyProgress,
+    migration: &str,
+    result: MigrationResult<String>,
+) -> Result<()> {
+    match result {
+        Ok(_) => {
+            progress.record(migration, StepResult::Applied);
+            if !progress.is_drawn() {
+                status!(
+                    "{}Migration '{}' applied successfully",
+                    progress.counter(),
+                    migration
+                );
+            }
+        }
+        Err(MigrationError::AlreadyApplied { info, .. }) => {
+            progress.record(migration, StepResult::AlreadyApplied);
+            if !progress.is_drawn() {
+                status!(
+                    "{}Migration '{}' already applied (status: {}, checksum: {})",
+                    progress.counter(),
+                    migration,
+                    info.last_status,
+                    info.checksum
+                );
+            }
+        }
+        Err(MigrationError::PreviousAttemptFailed { status, info, .. }) => {
+            return Err(anyhow!(
+                "Migration '{}' has a previous {} attempt (checksum: {}).\n\
+                 Use `spawn migration apply --retry {}` to retry.",
+                migration,
+                status,
+                info.checksum,
+                migration,
+            ));
+        }
+        Err(MigrationError::Database(e)) => {
+            return Err(e.context(format!("Failed applying migration {}", migration)));
+        }
+        Err(MigrationError::AdvisoryLock(e)) => {
+            return Err(ErrorKind::LockContention
+                .wrap(anyhow!("Unable to obtain advisory lock for migration").context(e)));
+        }
+        Err(MigrationError::Interrupted { recorded, .. }) => {
+            let history = if recorded {
+                "The attempt is recorded as ATTEMPTED in the migration history."
+            } else {
+                "The attempt could not be recorded in the migration history."
+            };
+            return Err(ErrorKind::Interrupted.wrap(anyhow!(
+                "Migration '{}' was interrupted and psql was stopped. {}\n\
+                 Some of its changes may have been applied if it does not run in a single transaction. \
+                 Check the database, then either run `spawn migration apply --retry {}` \
+                 or, if it was fully applied, `spawn migration adopt {}`.",
+                migration,
+                history,
+                migration,
+                migration,
+            )));
+        }
+        Err(e @ MigrationError::NotRecorded { .. }) => {
+            return Err(anyhow!("{}", e));
+        }
+    }
+    Ok(())
+}
+
+/// Shows a migration's rendered SQL in a pager and asks what to do with it.
+fn review_migration(counter: &str, migration: &str, sql: &[u8]) -> Result<Choice> {
+    show_in_pager(sql)?;
+
+    let choices = [Choice::Apply, Choice::Skip, Choice::Abort];
+    let selected = Select::new()
+        .with_prompt(format!("{}Migration '{}'", counter, migration))
+        .items(&["Apply", "Skip", "Abort"])
+        .default(0)
+        .interact()?;
+    Ok(choices[selected])
+}
+
+/// Shows `text` in the user's pager (`$PAGER`, or `less`), or prints it if
+/// the pager cannot be run.
+fn show_in_pager(text: &[u8]) -> Result<()> {
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    let args = shlex::split(&pager).unwrap_or_default();
+
+    if let Some((program, rest)) = args.split_first() {
+        if let Ok(mut child) = std::process::Command::new(program)
+            .args(rest)
+            .stdin(Stdio::piped())
+            .spawn()
+        {
+            if let Some(mut stdin) = child.stdin.take() {
+                // Quitting the pager early closes its input, which is fine.
+                let _ = stdin.write_all(text);
+            }
+            child.wait()?;
+            return Ok(());
+        }
+    }
+
+    std::io::stdout().write_all(text)?;
+    Ok(())
+}
+
 impl Command for ApplyMigration {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
+        if self.run(config).await?.aborted {
+            return Ok(Outcome::Aborted);
+        }
+        Ok(Outcome::AppliedMigrations)
+    }
+}
+
+impl ApplyMigration {
+    /// Applies the migration, or every pending migration, returning how
+    /// each went.
+    pub async fn run(&self, config: &Config) -> Result<ApplyReport> {
+        if !confirm_protected_target(config, "apply", self.yes, self.force)? {
+            return Ok(ApplyReport::aborted());
+        }
+
+        if let Some(path) = &self.from_file {
+            let migration = self
+                .migration
+                .as_ref()
+                .ok_or(anyhow!("a migration name is required with --from-file"))?;
+            let sql = self.read_artifact(config, migration, path)?;
+            check_migration_order(config, std::slice::from_ref(migration)).await?;
+            if let Some(source) = &self.promote_from {
+                check_promoted(config, source, std::slice::from_ref(migration)).await?;
+            }
+            if config.lock_advisor.enabled {
+                let engine = config.new_engine().await?;
+                let rendered = [(
+                    migration.clone(),
+                    String::from_utf8_lossy(&sql).into_owned(),
+                )];
+                let warnings = advise_rendered(config, engine.as_ref(), &rendered)
+                    .await
+                    .context("lock advisor failed")?;
+                report_lock_warnings(config, &warnings);
+            }
+            self.schedule.wait(config).await?;
+            announce_trace(config);
+            let started = Instant::now();
+            let result = self
+                .apply_rendered(config, migration, sql, "spawn.from_file")
+                .await;
+            let outcome = result.as_ref().map(|report| !report.applied().is_empty());
+            notify_step(config, migration, outcome, started).await;
+            return result;
+        }
+
+        if config.strict && !self.pinned {
+            return Err(anyhow!(
+                "applying unpinned migrations is not allowed with --strict"
+            ));
+        }
+
+        if self.require_signature && !self.pinned {
+            return Err(anyhow!(
+                "--require-signature only applies to pinned migrations"
+            ));
+        }
+
+        if self.interactive && !std::io::stdin().is_terminal() {
+            return Err(anyhow!("--interactive needs a terminal"));
+        }
+
         let migrations = match &self.migration {
-            Some(migration) => vec![migration.clone()],
-            None => match get_pending_and_confirm(config, "apply", self.yes).await? {
-                Some(pending) => pending,
-                None => return Ok(Outcome::AppliedMigrations),
-            },
+            Some(migration) => {
+                check_migration_order(config, std::slice::from_ref(migration)).await?;
+                if let Some(source) = &self.promote_from {
+                    check_promoted(config, source, std::slice::from_ref(migration)).await?;
+                }
+                self.advise_locks(config, std::slice::from_ref(migration))
+                    .await?;
+                vec![migration.clone()]
+            }
+            None => {
+                let pending = get_pending_migrations(config).await?;
+                if pending.is_empty() {
+                    status!("No pending migrations to apply.");
+                    return Ok(ApplyReport::default());
+                }
+                check_migration_order(config, &pending).await?;
+                if let Some(source) = &self.promote_from {
+                    check_promoted(config, source, &pending).await?;
+                }
+                self.advise_locks(config, &pending).await?;
+                // Each migration is confirmed on its own with --interactive.
+                if !confirm_migrations(config, "apply", &pending, self.yes || self.interactive)? {
+                    return Ok(ApplyReport::aborted());
+                }
+                pending
+            }
         };
 
-        let total = migrations.len();
+        self.schedule.wait(config).await?;
+        announce_trace(config);
+        self.apply_migrations(config, migrations).await
+    }
 
+    /// With the lock advisor enabled, warns about statements of
+    /// `migrations` that would lock large tables, before anything is
+    /// confirmed or applied.
+    async fn advise_locks(&self, config: &Config, migrations: &[String]) -> Result<()> {
+        if !config.lock_advisor.enabled {
+            return Ok(());
+        }
+        let engine = config.new_engine().await?;
+        let warnings = advise(
+            config,
+            engine.as_ref(),
+            migrations,
+            self.pinned,
+            self.variables.clone(),
+        )
+        .await
+        .context("lock advisor failed")?;
+        report_lock_warnings(config, &warnings);
+        Ok(())
+    }
+
+    /// Applies each of `migrations` in order, stopping at the first failure.
+    pub(crate) async fn apply_migrations(
+        &self,
+        config: &Config,
+        migrations: Vec<String>,
+    ) -> Result<ApplyReport> {
         // Optionally reuse the same engine (database connection) across all migrations
         let shared_engine = if self.reuse_connection {
             Some(config.new_engine().await?)
@@ -45,88 +463,423 @@ impl Command for ApplyMigration {
             None
         };
 
+        interrupt::install();
+
+        let progress = ApplyProgress::new(migrations.len(), !self.interactive);
+        let outcome = self
+            .apply_each(config, migrations, shared_engine.as_deref(), &progress)
+            .await;
+        let results = progress.finish();
+        let report = outcome.map(|aborted| ApplyReport {
+            migrations: results,
+            aborted,
+        })?;
+        if !report.applied().is_empty() {
+            schema_docs::after_apply(config).await;
+        }
+        Ok(report)
+    }
+
+    async fn apply_each(
+        &self,
+        config: &Config,
+        migrations: Vec<String>,
+        shared_engine: Option<&dyn Engine>,
+        progress: &ApplyProgress,
+    ) -> Result<bool> {
+        let total = migrations.len();
         for (i, migration) in migrations.into_iter().enumerate() {
-            let counter = if total > 1 {
-                format!(
-                    "[{:>width$}/{}] ",
-                    i + 1,
-                    total,
-                    width = total.to_string().len()
-                )
-            } else {
-                String::new()
+            if interrupt::is_interrupted() {
+                return Err(ErrorKind::Interrupted.wrap(anyhow!(
+                    "Interrupted before applying migration '{}'",
+                    migration
+                )));
+            }
+            progress.begin(i, &migration);
+            config.events.on_migration_start(&migration, i, total);
+            config.audit_session.record_migration(&migration);
+            // Use shared engine if reuse_connection is enabled, otherwise create new
+            let new_engine: Option<Box<dyn Engine>>;
+            let engine: &dyn Engine = match shared_engine {
+                Some(e) => e,
+                None => {
+                    new_engine = Some(config.new_engine().await?);
+                    new_engine.as_ref().unwrap().as_ref()
+                }
             };
-            let mgrtr = Migrator::new(config, &migration, self.pinned);
-            match mgrtr.generate_streaming(self.variables.clone()).await {
-                Ok(streaming) => {
-                    // Use shared engine if reuse_connection is enabled, otherwise create new
-                    let new_engine: Option<Box<dyn Engine>>;
-                    let engine: &dyn Engine = match &shared_engine {
-                        Some(e) => e.as_ref(),
-                        None => {
-                            new_engine = Some(config.new_engine().await?);
-                            new_engine.as_ref().unwrap().as_ref()
-                        }
+
+            let started = Instant::now();
+            let span = migration_span(config, &migration, self.pinned);
+            let result = self
+                .apply_one(config, engine, &migration, progress, &span)
+                .await;
+            let outcome = result.as_ref().map(|choice| {
+                *choice == Choice::Apply
+                    && progress.result_of(&migration) == Some(StepResult::Applied)
+            });
+            match &result {
+                Ok(choice) => {
+                    let step = match choice {
+                        Choice::Apply => progress.result_of(&migration),
+                        Choice::Skip | Choice::Abort => Some(StepResult::Skipped),
                     };
-                    let write_fn = streaming.into_writer_fn();
-                    match engine
-                        .migration_apply(
-                            &migration,
-                            write_fn,
-                            None,
-                            super::DEFAULT_NAMESPACE,
-                            self.retry,
-                        )
-                        .await
-                    {
-                        Ok(_) => {
-                            println!("{}Migration '{}' applied successfully", counter, &migration);
-                        }
-                        Err(MigrationError::AlreadyApplied { info, .. }) => {
-                            println!(
-                                "{}Migration '{}' already applied (status: {}, checksum: {})",
-                                counter, &migration, info.last_status, info.checksum
-                            );
-                        }
-                        Err(MigrationError::PreviousAttemptFailed { status, info, .. }) => {
-                            return Err(anyhow!(
-                                "Migration '{}' has a previous {} attempt (checksum: {}).\n\
-                                 Use `spawn migration apply --retry {}` to retry.",
-                                &migration,
-                                status,
-                                info.checksum,
-                                &migration,
-                            ));
-                        }
-                        Err(MigrationError::Database(e)) => {
-                            return Err(
-                                e.context(format!("Failed applying migration {}", &migration))
-                            );
-                        }
-                        Err(MigrationError::AdvisoryLock(e)) => {
-                            return Err(
-                                anyhow!("Unable to obtain advisory lock for migration").context(e)
-                            );
+                    let step = step.map_or("", |step| step.as_str());
+                    span.with_attribute("spawn.result", step).end(None)
+                }
+                Err(e) => span.end(Some(format!("{:#}", e))),
+            }
+            notify_step(config, &migration, outcome, started).await;
+            config.telemetry_session.record(
+                TelemetryInfo::new("migration apply step")
+                    .with_properties(vec![("opt_pinned", self.pinned.to_string())]),
+                started,
+                CommandStatus::of(&result),
+            );
+            // A migration applied before failing with --deny-warnings is
+            // already recorded.
+            let choice = result.inspect_err(|_| {
+                if progress.result_of(&migration).is_none() {
+                    progress.record(&migration, StepResult::Failed)
+                }
+            })?;
+            match choice {
+                Choice::Apply => config.telemetry_session.count("migrations_applied", 1),
+                Choice::Skip => {
+                    progress.record(&migration, StepResult::Skipped);
+                    status!("{}Skipped migration '{}'", progress.counter(), migration);
+                }
+                Choice::Abort => {
+                    status!("Aborted.");
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Pins (with `pin_on_apply`), verifies, renders and applies a single
+    /// migration. With `--interactive`, the rendered SQL is shown first and
+    /// the migration is only applied if the user chooses to. A migration to
+    /// be pinned on apply is then reviewed from its working files and only
+    /// pinned once accepted.
+    async fn apply_one(
+        &self,
+        config: &Config,
+        engine: &dyn Engine,
+        migration: &str,
+        progress: &ApplyProgress,
+        span: &Span,
+    ) -> Result<Choice> {
+        let pin_first =
+            self.pinned && config.pin_on_apply && never_applied(config, engine, migration).await?;
+        let pin_after_review = pin_first && self.interactive;
+        if pin_first && !pin_after_review {
+            self.pin_on_apply(config, migration, progress).await?;
+        }
+
+        if self.require_signature && !pin_after_review {
+            verify_migration(config, migration).await?;
+        }
+
+        let mgrtr = Migrator::new(config, migration, self.pinned && !pin_after_review);
+        match mgrtr.generate_streaming(self.variables.clone()).await {
+            Ok(streaming) => {
+                let metadata = mgrtr.load_metadata().await?;
+                let guarded_target = self.destructive_guard(config, &metadata)?;
+                let policy = config.meta_command_policy()?;
+                let write_fn = if self.interactive
+                    || !policy.is_empty()
+                    || guarded_target.is_some()
+                    || !config.policy.rules.is_empty()
+                {
+                    // Render once, so that what is applied is exactly what
+                    // was reviewed and checked.
+                    let mut sql = Vec::new();
+                    streaming
+                        .render_to_writer(&mut sql)
+                        .map_err(|e| ErrorKind::Render.wrap(e))?;
+                    let text = String::from_utf8_lossy(&sql);
+                    policy.check(migration, &text)?;
+                    if let Some(target) = &guarded_target {
+                        check_destructive(migration, target, &text)?;
+                    }
+                    enforce_rules(config, migration, &text)?;
+                    if self.interactive {
+                        match review_migration(&progress.counter(), migration, &sql)? {
+                            Choice::Apply => {}
+                            choice => return Ok(choice),
                         }
-                        Err(e @ MigrationError::NotRecorded { .. }) => {
-                            return Err(anyhow!("{}", e));
+                    }
+                    if pin_after_review {
+                        self.pin_on_apply(config, migration, progress).await?;
+                        if self.require_signature {
+                            verify_migration(config, migration).await?;
                         }
                     }
+                    Box::new(move |writer: &mut dyn std::io::Write| writer.write_all(&sql))
+                } else {
+                    streaming.into_writer_fn()
+                };
+                let pin_hash = if self.pinned {
+                    let lock_path = config.pather().migration_lock_file_path(migration);
+                    Some(config.load_lock_file(&lock_path).await?.pin)
+                } else {
+                    None
+                };
+                let details = config
+                    .tracer
+                    .annotate(metadata.history_details(pin_hash, config.operator_identity()));
+                self.run_apply(config, engine, migration, write_fn, details, progress, span)
+                    .await?;
+            }
+            Err(e) => {
+                let context = if self.pinned {
+                    anyhow!(
+                        "Failed to generate migration '{}'. Is it pinned? \
+                         Run `spawn migration pin {}` or use `--no-pin` to apply without pinning.",
+                        migration,
+                        migration
+                    )
+                } else {
+                    anyhow!("failed to generate migration '{}'", migration)
+                };
+                return Err(ErrorKind::Render.wrap(e.context(context)));
+            }
+        };
+
+        Ok(Choice::Apply)
+    }
+
+    /// Pins `migration` before its first apply and notes the new pin.
+    async fn pin_on_apply(
+        &self,
+        config: &Config,
+        migration: &str,
+        progress: &ApplyProgress,
+    ) -> Result<()> {
+        let hash = pin_migration(config, migration).await?;
+        progress.note(&format!(
+            "{}Pinned migration '{}' ({})",
+            progress.counter(),
+            migration,
+            hash
+        ));
+        Ok(())
+    }
+
+    /// The name of the target when it is protected and the destructive
+    /// statements of `metadata`'s migration must be refused: neither
+    /// acknowledged in its migration.toml nor allowed with
+    /// `--allow-destructive`.
+    fn destructive_guard(
+        &self,
+        config: &Config,
+        metadata: &MigrationMetadata,
+    ) -> Result<Option<String>> {
+        if self.allow_destructive || metadata.destructive || !config.target_config()?.protected {
+            return Ok(None);
+        }
+        Ok(config.target.clone())
+    }
+
+    /// Reads a previously built SQL file to apply as `migration` without
+    /// rendering any templates. With `--require-signature`, the file must
+    /// carry a signature for `migration` by a trusted key, which is removed.
+    /// The migration is still locked and recorded with a checksum as usual,
+    /// but without a pin hash since nothing is read from the pinned store.
+    fn read_artifact(&self, config: &Config, migration: &str, path: &str) -> Result<Vec<u8>> {
+        let sql = std::fs::read(path).context(format!("could not read {}", path))?;
+        if !self.require_signature {
+            return Ok(sql);
+        }
+        let text = String::from_utf8(sql).context(format!("{} is not UTF-8", path))?;
+        let verified = verify_artifact(migration, &text, &config.signing.trusted_keys)
+            .context(format!("refusing to apply {}", path))?;
+        Ok(verified.as_bytes().to_vec())
+    }
+
+    /// Applies `sql`, already rendered elsewhere, as `migration`. It is
+    /// checked against the meta-command policy and policy rules as a
+    /// rendered migration would be. `source` is added to the migration's
+    /// span to say where the SQL came from.
+    pub async fn apply_rendered(
+        &self,
+        config: &Config,
+        migration: &str,
+        sql: Vec<u8>,
+        source: &str,
+    ) -> Result<ApplyReport> {
+        config.audit_session.record_migration(migration);
+        let metadata = Migrator::new(config, migration, false)
+            .load_metadata()
+            .await?;
+        let text = String::from_utf8_lossy(&sql);
+        config.meta_command_policy()?.check(migration, &text)?;
+        if let Some(target) = self.destructive_guard(config, &metadata)? {
+            check_destructive(migration, &target, &text)?;
+        }
+        enforce_rules(config, migration, &text)?;
+
+        let engine = config.new_engine().await?;
+        interrupt::install();
+        let write_fn: WriterFn =
+            Box::new(move |writer: &mut dyn std::io::Write| writer.write_all(&sql));
+        let details = config
+            .tracer
+            .annotate(metadata.history_details(None, config.operator_identity()));
+        let progress = ApplyProgress::new(1, false);
+        progress.begin(0, migration);
+        let span = migration_span(config, migration, false).with_attribute(source, "true");
+        let result = self
+            .run_apply(
+                config,
+                engine.as_ref(),
+                migration,
+                write_fn,
+                details,
+                &progress,
+                &span,
+            )
+            .await;
+        span.end(result.as_ref().err().map(|e| format!("{:#}", e)));
+        result?;
+
+        let report = ApplyReport {
+            migrations: progress.finish(),
+            aborted: false,
+        };
+        if !report.applied().is_empty() {
+            schema_docs::after_apply(config).await;
+        }
+        Ok(report)
+    }
+
+    /// Applies the migration written by `write_fn` and reports the result,
+    /// writing a transcript of the apply when transcripts are enabled. Each
+    /// statement is a span under `span`, lasting until the next statement is
+    /// sent. The notices and warnings the server sent are shown, and go to
+    /// `progress` with the statements sent for the summary. With
+    /// `--deny-warnings`, a migration the server warned about fails the
+    /// apply once it has been applied.
+    #[allow(clippy::too_many_arguments)]
+    async fn run_apply(
+        &self,
+        config: &Config,
+        engine: &dyn Engine,
+        migration: &str,
+        write_fn: WriterFn,
+        details: HistoryDetails,
+        progress: &ApplyProgress,
+        span: &Span,
+    ) -> Result<()> {
+        let events = config.events.clone();
+        let name = migration.to_string();
+        let span = span.clone();
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counted = sent.clone();
+        let write_fn: WriterFn = Box::new(move |writer: &mut dyn std::io::Write| {
+            let mut current: Option<Span> = None;
+            let mut statements = StatementWriter::new(writer, |statement| {
+                events.on_statement(&name, statement);
+                counted.fetch_add(1, Ordering::Relaxed);
+                if let Some(previous) = current.take() {
+                    previous.end(None);
                 }
-                Err(e) => {
-                    let context = if self.pinned {
-                        anyhow!(
-                            "Failed to generate migration '{}'. Is it pinned? \
-                             Run `spawn migration pin {}` or use `--no-pin` to apply without pinning.",
-                            &migration, &migration
-                        )
-                    } else {
-                        anyhow!("failed to generate migration '{}'", &migration)
-                    };
-                    return Err(e.context(context));
-                }
-            };
+                current = Some(
+                    span.child("statement", SpanKind::Client)
+                        .with_attribute("db.statement", statement),
+                );
+            });
+            let result = write_fn(&mut statements).and_then(|()| statements.finish());
+            if let Some(last) = current {
+                last.end(None);
+            }
+            result
+        });
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let (result, transcript_path) = match &config.transcripts {
+            None => {
+                let result = engine
+                    .migration_apply(
+                        migration,
+                        write_fn,
+                        details,
+                        super::DEFAULT_NAMESPACE,
+                        self.retry,
+                        Some(Box::new(SharedBufWriter(output.clone()))),
+                    )
+                    .await;
+                (result, None)
+            }
+            Some(transcripts) => {
+                let (recorder, write_fn, recorded) = TranscriptRecorder::start(write_fn);
+                let result = engine
+                    .migration_apply(
+                        migration,
+                        write_fn,
+                        details,
+                        super::DEFAULT_NAMESPACE,
+                        self.retry,
+                        recorded,
+                    )
+                    .await;
+
+                // Nothing was run when the migration was skipped.
+                let transcript_path = match &result {
+                    Err(MigrationError::AlreadyApplied { .. })
+                    | Err(MigrationError::PreviousAttemptFailed { .. }) => None,
+                    _ => {
+                        let transcript = recorder.finish(
+                            migration,
+                            config.target.as_deref().unwrap_or_default(),
+                            result.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+                        );
+                        output
+                            .lock()
+                            .unwrap()
+                            .extend_from_slice(transcript.output.as_bytes());
+                        match transcript.save(config, transcripts).await {
+                            Ok(path) => Some(path),
+                            Err(e) => {
+                                config.events.on_warning(&format!("{:#}", e));
+                                None
+                            }
+                        }
+                    }
+                };
+                (result, transcript_path)
+            }
+        };
+
+        let messages = server_messages(&String::from_utf8_lossy(&output.lock().unwrap()));
+        for message in &messages {
+            progress.note(&format!(
+                "{}{}",
+                progress.counter(),
+                style(&message.text).yellow().for_stderr()
+            ));
         }
-        Ok(Outcome::AppliedMigrations)
+        let stats = ApplyStats::new(sent.load(Ordering::Relaxed), &messages);
+        progress.set_stats(stats);
+
+        let reported = report_apply_result(progress, migration, result);
+        if let Some(path) = transcript_path {
+            progress.note(&format!(
+                "{}Transcript written to {}",
+                progress.counter(),
+                path
+            ));
+        }
+        reported?;
+        if config.deny_warnings && stats.warnings > 0 {
+            return Err(anyhow!(
+                "migration '{}' was applied, but the server sent {} warning{} and --deny-warnings is given",
+                migration,
+                stats.warnings,
+                if stats.warnings == 1 { "" } else { "s" }
+            ));
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,153 @@
+use crate::commands::migration::{get_combined_migration_status, DEFAULT_NAMESPACE};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::{Config, FolderPather};
+use crate::engine::MigrationHistoryStatus;
+use crate::status;
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use dialoguer::Confirm;
+use futures::TryStreamExt;
+use opendal::Operator;
+
+/// Moves migrations applied before a date into the migrations archive, where
+/// `migration status` leaves them out unless asked.
+pub struct ArchiveMigrations {
+    /// Archive migrations whose timestamp is before this date
+    pub before: NaiveDate,
+    /// List the migrations that would be archived without moving them
+    pub dry_run: bool,
+    pub yes: bool,
+}
+
+impl TelemetryDescribe for ArchiveMigrations {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration archive")
+            .with_properties(vec![("opt_dry_run", self.dry_run.to_string())])
+    }
+}
+
+/// Migrations whose name starts with a timestamp before `before` and that
+/// were applied successfully to the target, oldest first. Migrations that
+/// are pending, failed or already archived are left where they are.
+pub async fn archive_candidates(config: &Config, before: NaiveDate) -> Result<Vec<String>> {
+    let before = before.format("%Y%m%d").to_string();
+    Ok(
+        get_combined_migration_status(config, Some(DEFAULT_NAMESPACE))
+            .await?
+            .into_iter()
+            .filter(|row| {
+                row.exists_in_filesystem
+                    && !row.archived
+                    && row.last_status == Some(MigrationHistoryStatus::Success)
+            })
+            .map(|row| row.migration_name)
+            .filter(|name| {
+                name.get(..8)
+                    .filter(|date| date.bytes().all(|b| b.is_ascii_digit()))
+                    .is_some_and(|date| date < before.as_str())
+            })
+            .collect(),
+    )
+}
+
+/// Moves every file of `migration` from where `from` keeps it to where `to`
+/// does: its folder, or with the flat layout its files and the folder named
+/// after it.
+async fn move_migration(
+    op: &Operator,
+    from: &FolderPather,
+    to: &FolderPather,
+    migration: &str,
+) -> Result<()> {
+    let mut moves: Vec<(String, String)> = Vec::new();
+    if from.layout.flat {
+        for file in from.migration_folder_excludes() {
+            let path = from.migration_file_path(migration, file);
+            if op.exists(&path).await? {
+                moves.push((path, to.migration_file_path(migration, file)));
+            }
+        }
+    }
+
+    let folder = format!("{}/", from.migration_folder(migration));
+    let target = format!("{}/", to.migration_folder(migration));
+    // Listed paths have opendal's leading "./" and "/" stripped.
+    let prefix = folder.trim_start_matches("./").trim_start_matches('/');
+    let mut lister = op.lister_with(&folder).recursive(true).await?;
+    while let Some(entry) = lister.try_next().await? {
+        let path = entry.path();
+        if path.ends_with('/') {
+            continue;
+        }
+        if let Some(relative) = path.strip_prefix(prefix) {
+            moves.push((path.to_string(), format!("{}{}", target, relative)));
+        }
+    }
+
+    for (source, destination) in &moves {
+        let contents = op.read(source).await?;
+        op.write(destination, contents)
+            .await
+            .context(format!("could not write {}", destination))?;
+    }
+    for (source, _) in &moves {
+        op.delete(source).await?;
+    }
+    op.remove_all(&folder).await?;
+    Ok(())
+}
+
+impl Command for ArchiveMigrations {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let migrations = archive_candidates(config, self.before).await?;
+        if migrations.is_empty() {
+            status!(
+                "No applied migrations from before {} to archive.",
+                self.before
+            );
+            return Ok(Outcome::Success);
+        }
+
+        let pather = config.pather();
+        let archive = pather.archive();
+        eprintln!(
+            "{} {} migration{} to {}:",
+            if self.dry_run {
+                "Would archive"
+            } else {
+                "Archiving"
+            },
+            migrations.len(),
+            if migrations.len() == 1 { "" } else { "s" },
+            archive.migrations_folder()
+        );
+        for migration in &migrations {
+            eprintln!("  {}", migration);
+        }
+        if self.dry_run {
+            return Ok(Outcome::Success);
+        }
+        if !self.yes
+            && !Confirm::new()
+                .with_prompt("Move these migrations to the archive?")
+                .default(false)
+                .interact()?
+        {
+            status!("Aborted.");
+            return Ok(Outcome::Success);
+        }
+
+        for migration in &migrations {
+            move_migration(config.operator(), &pather, &archive, migration)
+                .await
+                .context(format!("could not archive {}", migration))?;
+            config.audit_session.record_migration(migration);
+        }
+        status!(
+            "Archived {} migration{}.",
+            migrations.len(),
+            if migrations.len() == 1 { "" } else { "s" }
+        );
+        Ok(Outcome::Success)
+    }
+}
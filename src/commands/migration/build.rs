@@ -1,14 +1,32 @@
+use crate::annotations::Annotation;
+use crate::commands::migration::get_pending_migrations;
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
+use crate::errors::{ErrorKind, WithErrorKind};
 use crate::migrator::Migrator;
-use crate::store::get_migration_fs_status;
+use crate::policy::enforce_rules;
+use crate::signing::{load_signing_key, sign_artifact, sign_migration};
+use crate::sqltest::{diff_lines, first_difference_line};
+use crate::status;
+use crate::store::{get_migration_fs_status, list_migration_fs_status};
 use crate::variables::Variables;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Name of the manifest written alongside the SQL files by
+/// `migration build --all`.
+pub const BUILD_MANIFEST_NAME: &str = "manifest.toml";
 
 pub struct BuildMigration {
     pub migration: String,
     pub pinned: bool,
     pub variables: Option<Variables>,
+    /// Sign the pinned migration, writing its signature.toml, and sign the
+    /// built SQL for `apply --from-file --require-signature`
+    pub sign: bool,
 }
 
 impl TelemetryDescribe for BuildMigration {
@@ -16,14 +34,109 @@ impl TelemetryDescribe for BuildMigration {
         TelemetryInfo::new("migration build").with_properties(vec![
             ("opt_pinned", self.pinned.to_string()),
             ("has_variables", self.variables.is_some().to_string()),
+            ("opt_sign", self.sign.to_string()),
+        ])
+    }
+}
+
+/// Builds many migrations at once, writing one SQL file per migration and a
+/// manifest to `out_dir`.
+pub struct BuildAllMigrations {
+    pub pinned: bool,
+    pub variables: Option<Variables>,
+    /// Only build migrations that have not been applied to the target
+    pub pending: bool,
+    /// Folder to write the SQL files and manifest to
+    pub out_dir: String,
+}
+
+impl TelemetryDescribe for BuildAllMigrations {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration build").with_properties(vec![
+            ("opt_pinned", self.pinned.to_string()),
+            ("has_variables", self.variables.is_some().to_string()),
+            ("opt_all", true.to_string()),
+            ("opt_pending", self.pending.to_string()),
+        ])
+    }
+}
+
+/// Renders every migration and compares it with its golden file in `dir`,
+/// as written by `migration build --all --out-dir <dir>` and committed, so
+/// that changes to the rendered SQL of old migrations are caught.
+pub struct CheckBuiltMigrations {
+    pub pinned: bool,
+    pub variables: Option<Variables>,
+    /// Folder holding a `<migration>.sql` golden file for each migration
+    pub dir: String,
+}
+
+impl TelemetryDescribe for CheckBuiltMigrations {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration build").with_properties(vec![
+            ("opt_pinned", self.pinned.to_string()),
+            ("has_variables", self.variables.is_some().to_string()),
+            ("opt_check", true.to_string()),
         ])
     }
 }
 
+/// A migration whose rendered SQL does not match the golden files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoldenMismatch {
+    /// The migration renders differently from its golden file
+    Differs {
+        migration: String,
+        diff: String,
+        /// 1-based line of the golden file where the output first differs
+        first_diff_line: Option<usize>,
+    },
+    /// The migration has no golden file
+    Missing { migration: String },
+    /// A golden file has no migration
+    Orphaned { migration: String },
+}
+
+/// Describes the files written by `migration build --all`, listed in the
+/// order they are to be applied.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BuildManifest {
+    pub pinned: bool,
+    #[serde(default)]
+    pub migrations: Vec<BuiltFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BuiltFile {
+    pub migration: String,
+    /// File name within the output folder
+    pub file: String,
+    pub sha256: String,
+    /// Root hash of the pinned components it was built from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+}
+
+/// Renders a migration to a string.
+pub async fn render_migration(
+    config: &Config,
+    migration: &str,
+    pinned: bool,
+    variables: Option<Variables>,
+) -> Result<String> {
+    let gen = Migrator::new(config, migration, pinned)
+        .generate_streaming(variables)
+        .await
+        .map_err(|e| ErrorKind::Render.wrap(e))?;
+    let mut buffer = Vec::new();
+    gen.render_to_writer(&mut buffer)
+        .map_err(std::io::Error::other)
+        .with_kind(ErrorKind::Render)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
 impl Command for BuildMigration {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
-        let mgrtr = Migrator::new(config, &self.migration, self.pinned);
-
         // Check if lock file exists when not using --pinned
         let pinned_warn = if !self.pinned {
             let fs_status =
@@ -33,20 +146,254 @@ impl Command for BuildMigration {
         } else {
             false
         };
+        if pinned_warn && config.strict {
+            return Err(anyhow!(
+                "migration {} is pinned; build it with --pinned",
+                &self.migration
+            ));
+        }
+
+        let content =
+            render_migration(config, &self.migration, self.pinned, self.variables.clone()).await?;
+        enforce_rules(config, &self.migration, &content)?;
 
-        match mgrtr.generate_streaming(self.variables.clone()).await {
-            Ok(gen) => {
-                let mut buffer = Vec::new();
-                gen.render_to_writer(&mut buffer)
-                    .map_err(std::io::Error::other)?;
-                let content = String::from_utf8(buffer)?;
-
-                Ok(Outcome::BuiltMigration {
-                    content,
-                    pinned_warn,
-                })
+        let content = if self.sign {
+            let key = load_signing_key(&config.signing)?;
+            sign_migration(config, &self.migration, &key).await?;
+            status!("Signed migration {}", &self.migration);
+            sign_artifact(&self.migration, &content, &key)
+        } else {
+            content
+        };
+
+        Ok(Outcome::BuiltMigration {
+            content,
+            pinned_warn,
+        })
+    }
+}
+
+/// The migrations `migration build --all` builds, checking that with
+/// `pinned` each is pinned, and otherwise warning about those that are.
+pub(crate) async fn migrations_to_build(
+    config: &Config,
+    pinned: bool,
+    pending: bool,
+) -> Result<Vec<String>> {
+    let fs_status = list_migration_fs_status(config.operator(), &config.pather(), None).await?;
+    let migrations: Vec<String> = if pending {
+        get_pending_migrations(config).await?
+    } else {
+        fs_status
+            .iter()
+            .filter(|(_, status)| status.has_up_sql)
+            .map(|(name, _)| name.clone())
+            .collect()
+    };
+
+    let is_pinned = |name: &String| fs_status.get(name).is_some_and(|s| s.has_lock_toml);
+    if pinned {
+        let unpinned: Vec<&str> = migrations
+            .iter()
+            .filter(|m| !is_pinned(m))
+            .map(String::as_str)
+            .collect();
+        if !unpinned.is_empty() {
+            return Err(anyhow!(
+                "cannot build with --pinned, these migrations are not pinned: {}",
+                unpinned.join(", ")
+            ));
+        }
+    } else {
+        let pinned = migrations.iter().filter(|m| is_pinned(m)).count();
+        if pinned > 0 && config.strict {
+            return Err(anyhow!(
+                "{} of the migrations are pinned; build them with --pinned",
+                pinned
+            ));
+        }
+        if pinned > 0 {
+            config.events.on_warning(&format!(
+                "{} of the migrations are pinned. Run with --pinned to build them as they will be applied.",
+                pinned
+            ));
+        }
+    }
+    Ok(migrations)
+}
+
+/// Renders the migrations as `migration build --all` would and compares
+/// each with `<dir>/<migration>.sql`. Golden files for migrations that no
+/// longer exist are reported too.
+pub async fn compare_golden(
+    config: &Config,
+    dir: &str,
+    pinned: bool,
+    variables: Option<Variables>,
+) -> Result<Vec<GoldenMismatch>> {
+    let migrations = migrations_to_build(config, pinned, false).await?;
+    let dir_path = Path::new(dir);
+
+    let mut golden = BTreeSet::new();
+    for entry in
+        std::fs::read_dir(dir_path).context(format!("could not read golden folder {}", dir))?
+    {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(migration) = name.strip_suffix(".sql") {
+            golden.insert(migration.to_string());
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    for migration in &migrations {
+        if !golden.remove(migration) {
+            mismatches.push(GoldenMismatch::Missing {
+                migration: migration.clone(),
+            });
+            continue;
+        }
+        let content = render_migration(config, migration, pinned, variables.clone())
+            .await
+            .context(format!("failed to build migration {}", migration))?;
+        let file = dir_path.join(format!("{}.sql", migration));
+        let expected =
+            std::fs::read_to_string(&file).context(format!("could not read {}", file.display()))?;
+        if let Some(diff) = diff_lines(&expected, &content) {
+            mismatches.push(GoldenMismatch::Differs {
+                migration: migration.clone(),
+                diff,
+                first_diff_line: first_difference_line(&expected, &content),
+            });
+        }
+    }
+    mismatches.extend(
+        golden
+            .into_iter()
+            .map(|migration| GoldenMismatch::Orphaned { migration }),
+    );
+
+    Ok(mismatches)
+}
+
+impl Command for CheckBuiltMigrations {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let mismatches =
+            compare_golden(config, &self.dir, self.pinned, self.variables.clone()).await?;
+        if mismatches.is_empty() {
+            println!("All migrations match the golden files in {}", self.dir);
+            return Ok(Outcome::Success);
+        }
+
+        for mismatch in &mismatches {
+            match mismatch {
+                GoldenMismatch::Differs {
+                    migration,
+                    diff,
+                    first_diff_line,
+                } => {
+                    let file = format!("{}/{}.sql", self.dir.trim_end_matches('/'), migration);
+                    let mut annotation = Annotation::error(
+                        "The migration no longer builds to its golden file. Revert the change to its templates or variables, or rebuild the golden files with `spawn migration build --all`.",
+                    )
+                    .with_file(&file)
+                    .with_title(format!("Migration {} changed", migration));
+                    if let Some(line) = first_diff_line {
+                        annotation = annotation.with_line(*line);
+                    }
+                    annotation.emit(config.annotate);
+                    println!("[CHANGED] {}", migration);
+                    config.events.on_diff(migration, diff);
+                }
+                GoldenMismatch::Missing { migration } => {
+                    println!("[MISSING] {} has no golden file", migration);
+                }
+                GoldenMismatch::Orphaned { migration } => {
+                    println!("[ORPHANED] golden file for {} has no migration", migration);
+                }
             }
-            Err(e) => Err(e),
         }
+        println!(
+            "\n{} migration{} do not match the golden files in {}",
+            mismatches.len(),
+            if mismatches.len() == 1 { "" } else { "s" },
+            self.dir
+        );
+        Ok(Outcome::CheckFailed)
+    }
+}
+
+/// Renders `migrations` into `out_dir`, naming the file of the `i`th with
+/// `file_name(i, migration)`, and returns the manifest listing them.
+pub async fn build_to_folder(
+    config: &Config,
+    migrations: &[String],
+    pinned: bool,
+    variables: Option<Variables>,
+    out_dir: &Path,
+    file_name: impl Fn(usize, &str) -> String,
+) -> Result<BuildManifest> {
+    std::fs::create_dir_all(out_dir).context(format!(
+        "could not create output folder {}",
+        out_dir.display()
+    ))?;
+
+    let mut manifest = BuildManifest {
+        pinned,
+        migrations: Vec::new(),
+    };
+    for (i, migration) in migrations.iter().enumerate() {
+        let content = render_migration(config, migration, pinned, variables.clone())
+            .await
+            .context(format!("failed to build migration {}", migration))?;
+        enforce_rules(config, migration, &content)?;
+        let file = file_name(i, migration);
+        std::fs::write(out_dir.join(&file), &content)
+            .context(format!("could not write {}", file))?;
+
+        let pin = if pinned {
+            let lock_path = config.pather().migration_lock_file_path(migration);
+            Some(config.load_lock_file(&lock_path).await?.pin)
+        } else {
+            None
+        };
+        manifest.migrations.push(BuiltFile {
+            migration: migration.clone(),
+            file,
+            sha256: format!("{:x}", Sha256::digest(content.as_bytes())),
+            pin,
+        });
+    }
+    Ok(manifest)
+}
+
+impl Command for BuildAllMigrations {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let migrations = migrations_to_build(config, self.pinned, self.pending).await?;
+
+        let out_dir = Path::new(&self.out_dir);
+        let manifest = build_to_folder(
+            config,
+            &migrations,
+            self.pinned,
+            self.variables.clone(),
+            out_dir,
+            |_, migration| format!("{}.sql", migration),
+        )
+        .await?;
+
+        std::fs::write(
+            out_dir.join(BUILD_MANIFEST_NAME),
+            toml::to_string_pretty(&manifest)?,
+        )
+        .context(format!("could not write {}", BUILD_MANIFEST_NAME))?;
+
+        status!(
+            "Built {} migration{} to {}",
+            migrations.len(),
+            if migrations.len() == 1 { "" } else { "s" },
+            self.out_dir
+        );
+
+        Ok(Outcome::Success)
     }
 }
@@ -0,0 +1,164 @@
+//! Compares the migrations applied to two targets, to find environments
+//! that have fallen behind or drifted from each other.
+
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::engine::{MigrationDbInfo, MigrationStatus};
+use anyhow::{Context, Result};
+use console::style;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use tabled::builder::Builder;
+use tabled::settings::Style;
+
+pub struct MigrationDiffEnvs {
+    /// Target or environment to compare
+    pub a: String,
+    /// Target or environment to compare it with
+    pub b: String,
+    pub json: bool,
+}
+
+impl TelemetryDescribe for MigrationDiffEnvs {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration diff-envs")
+            .with_properties(vec![("opt_json", self.json.to_string())])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvDifference {
+    /// Applied to b, but not to a
+    MissingInA,
+    /// Applied to a, but not to b
+    MissingInB,
+    /// Applied to both, but with different checksums
+    Drifted,
+}
+
+impl EnvDifference {
+    fn describe(self, a: &str, b: &str) -> String {
+        match self {
+            EnvDifference::MissingInA => format!("missing in {}", a),
+            EnvDifference::MissingInB => format!("missing in {}", b),
+            EnvDifference::Drifted => "checksums differ".to_string(),
+        }
+    }
+}
+
+/// A migration applied differently to the two targets. Checksums are those
+/// of the last successful apply, if any.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationEnvDiff {
+    pub migration: String,
+    pub a_checksum: Option<String>,
+    pub b_checksum: Option<String>,
+    pub difference: EnvDifference,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvDiff {
+    pub a: String,
+    pub b: String,
+    /// Differing migrations, by name
+    pub migrations: Vec<MigrationEnvDiff>,
+}
+
+/// Migrations that have succeeded on the target of `config`, with their
+/// checksums.
+async fn applied(config: &Config, name: &str) -> Result<HashMap<String, Option<String>>> {
+    let engine = config
+        .new_engine()
+        .await
+        .context(format!("could not connect to '{}'", name))?;
+    Ok(engine
+        .get_migrations_from_db(Some(super::DEFAULT_NAMESPACE))
+        .await?
+        .into_iter()
+        .filter(|m| m.last_status == Some(MigrationStatus::Success))
+        .map(
+            |MigrationDbInfo {
+                 migration_name,
+                 checksum,
+                 ..
+             }| (migration_name, checksum),
+        )
+        .collect())
+}
+
+/// Compares the migrations applied to the targets `a` and `b`, each a
+/// target or environment. Adopted migrations have no checksum, so are never
+/// reported as drifted.
+pub async fn diff_envs(config: &Config, a: &str, b: &str) -> Result<EnvDiff> {
+    let applied_a = applied(&config.for_target(a)?, a).await?;
+    let applied_b = applied(&config.for_target(b)?, b).await?;
+
+    let mut migrations: BTreeMap<&String, MigrationEnvDiff> = BTreeMap::new();
+    for name in applied_a.keys().chain(applied_b.keys()) {
+        let a_checksum = applied_a.get(name);
+        let b_checksum = applied_b.get(name);
+        let difference = match (a_checksum, b_checksum) {
+            (None, _) => EnvDifference::MissingInA,
+            (_, None) => EnvDifference::MissingInB,
+            (Some(Some(a)), Some(Some(b))) if a != b => EnvDifference::Drifted,
+            _ => continue,
+        };
+        migrations.insert(
+            name,
+            MigrationEnvDiff {
+                migration: name.clone(),
+                a_checksum: a_checksum.cloned().flatten(),
+                b_checksum: b_checksum.cloned().flatten(),
+                difference,
+            },
+        );
+    }
+
+    Ok(EnvDiff {
+        a: a.to_string(),
+        b: b.to_string(),
+        migrations: migrations.into_values().collect(),
+    })
+}
+
+impl Command for MigrationDiffEnvs {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let diff = diff_envs(config, &self.a, &self.b).await?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        } else if diff.migrations.is_empty() {
+            println!(
+                "{} and {} have the same migrations applied.",
+                self.a, self.b
+            );
+        } else {
+            let checksum = |c: &Option<String>, applied: bool| match (c, applied) {
+                (_, false) => style("✗").red().to_string(),
+                (Some(c), true) => c.clone(),
+                (None, true) => style("✓").green().to_string(),
+            };
+            let mut builder = Builder::new();
+            builder.push_record(["Migration", &self.a, &self.b, "Difference"]);
+            for m in &diff.migrations {
+                builder.push_record([
+                    m.migration.clone(),
+                    checksum(&m.a_checksum, m.difference != EnvDifference::MissingInA),
+                    checksum(&m.b_checksum, m.difference != EnvDifference::MissingInB),
+                    m.difference.describe(&self.a, &self.b),
+                ]);
+            }
+
+            let mut table = builder.build();
+            table.with(Style::sharp());
+            println!("\n{}\n", table);
+        }
+
+        if diff.migrations.is_empty() {
+            Ok(Outcome::Success)
+        } else {
+            Ok(Outcome::DriftDetected)
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Previews the query plans of the data changes in a migration, to gauge
+//! the impact of a backfill before running it.
+
+use crate::commands::migration::render_migration;
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::escape::InsecureRawSql;
+use crate::policy::{code, first_line, statements};
+use crate::sql_query;
+use crate::status;
+use crate::variables::Variables;
+use anyhow::{anyhow, Context, Result};
+use console::style;
+use serde::Serialize;
+
+pub struct ExplainMigration {
+    pub migration: String,
+    pub pinned: bool,
+    pub variables: Option<Variables>,
+    /// Run each statement with EXPLAIN ANALYZE, in a transaction that is
+    /// rolled back. Needs `scratch`.
+    pub analyze: bool,
+    /// Target to explain against instead of the selected one
+    pub scratch: Option<String>,
+    pub json: bool,
+}
+
+impl TelemetryDescribe for ExplainMigration {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration explain").with_properties(vec![
+            ("opt_pinned", self.pinned.to_string()),
+            ("opt_analyze", self.analyze.to_string()),
+            ("has_scratch", self.scratch.is_some().to_string()),
+            ("opt_json", self.json.to_string()),
+        ])
+    }
+}
+
+/// The plan of one data changing statement of a migration.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainedStatement {
+    /// The statement, without its trailing semicolon
+    pub statement: String,
+    /// Lines of the plan, as EXPLAIN prints them
+    pub plan: Vec<String>,
+    /// Estimated total cost of the statement
+    pub cost: Option<f64>,
+    /// Estimated number of rows
+    pub rows: Option<u64>,
+    /// Why the statement could not be explained, such as a table that an
+    /// earlier statement of the migration creates
+    pub error: Option<String>,
+}
+
+/// Whether `statement` changes data: an INSERT, UPDATE, DELETE or MERGE,
+/// on its own or in a WITH query.
+fn is_dml(statement: &str) -> bool {
+    const DML: [&str; 4] = ["INSERT", "UPDATE", "DELETE", "MERGE"];
+    let code = code(statement).to_uppercase();
+    let mut words = code
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty());
+    match words.next() {
+        Some(first) if DML.contains(&first) => true,
+        Some("WITH") => words.any(|w| DML.contains(&w)),
+        _ => false,
+    }
+}
+
+/// The data changing statements of `sql`, in order, without their trailing
+/// semicolons.
+pub fn dml_statements(sql: &str) -> Vec<String> {
+    statements(sql)
+        .iter()
+        .filter(|statement| is_dml(statement))
+        .map(|statement| {
+            statement
+                .trim()
+                .trim_end_matches(';')
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+/// The total cost and rows estimated on the first line of a plan, such as
+/// `Update on users  (cost=0.00..431.00 rows=10000 width=38)`.
+fn estimate(line: &str) -> (Option<f64>, Option<u64>) {
+    let value = |key: &str| {
+        let start = line.find(key)? + key.len();
+        let rest = &line[start..];
+        let end = rest.find([' ', ')']).unwrap_or(rest.len());
+        Some(&rest[..end])
+    };
+    let cost = value("cost=")
+        .and_then(|cost| cost.split("..").nth(1))
+        .and_then(|total| total.parse().ok());
+    let rows = value(" rows=").and_then(|rows| rows.parse().ok());
+    (cost, rows)
+}
+
+/// Explains `statement` on `engine`. With `analyze` the statement is run,
+/// inside a transaction that is rolled back.
+async fn explain_statement(
+    engine: &dyn Engine,
+    statement: &str,
+    analyze: bool,
+) -> ExplainedStatement {
+    // The statement comes from the migration, which is trusted as it is
+    // when applied.
+    let raw = InsecureRawSql::new(statement);
+    let query = if analyze {
+        sql_query!("BEGIN;\nEXPLAIN (ANALYZE) {};\nROLLBACK;", raw)
+    } else {
+        sql_query!("EXPLAIN {};", raw)
+    };
+    let mut explained = ExplainedStatement {
+        statement: statement.to_string(),
+        plan: Vec::new(),
+        cost: None,
+        rows: None,
+        error: None,
+    };
+    match engine.query(&query).await {
+        Ok(rows) => {
+            explained.plan = rows
+                .rows
+                .into_iter()
+                .filter_map(|row| row.into_iter().next().flatten())
+                .collect();
+            if let Some(first) = explained.plan.first() {
+                (explained.cost, explained.rows) = estimate(first);
+            }
+        }
+        Err(e) => explained.error = Some(e.root_cause().to_string().trim().to_string()),
+    }
+    explained
+}
+
+/// Explains each data changing statement of `sql` on `engine`, in order.
+pub async fn explain_sql(engine: &dyn Engine, sql: &str, analyze: bool) -> Vec<ExplainedStatement> {
+    let mut explained = Vec::new();
+    for statement in dml_statements(sql) {
+        explained.push(explain_statement(engine, &statement, analyze).await);
+    }
+    explained
+}
+
+impl Command for ExplainMigration {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let explain_config = match &self.scratch {
+            Some(scratch) => {
+                let scratch_config = config.for_target(scratch)?;
+                if scratch_config.target_config()?.protected {
+                    return Err(anyhow!(
+                        "target '{}' is protected and cannot be used as a scratch database",
+                        scratch
+                    ));
+                }
+                scratch_config
+            }
+            None if self.analyze => {
+                return Err(anyhow!(
+                    "--analyze runs the statements, so it needs a scratch target given with --scratch"
+                ));
+            }
+            None => config.clone(),
+        };
+
+        let sql =
+            render_migration(config, &self.migration, self.pinned, self.variables.clone()).await?;
+        if dml_statements(&sql).is_empty() {
+            if self.json {
+                println!("[]");
+            } else {
+                println!(
+                    "No data changes (INSERT, UPDATE, DELETE or MERGE) in migration {}",
+                    self.migration
+                );
+            }
+            return Ok(Outcome::Success);
+        }
+
+        let engine = explain_config
+            .new_engine()
+            .await
+            .context("could not connect to explain the migration")?;
+        let explained = explain_sql(engine.as_ref(), &sql, self.analyze).await;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&explained)?);
+            return Ok(Outcome::Success);
+        }
+        for (i, statement) in explained.iter().enumerate() {
+            println!(
+                "{}. {}",
+                i + 1,
+                style(first_line(&statement.statement)).bold()
+            );
+            match &statement.error {
+                Some(error) => println!("   {}", style(format!("error: {}", error)).red()),
+                None => {
+                    for line in &statement.plan {
+                        println!("   {}", line);
+                    }
+                }
+            }
+            println!();
+        }
+        let failed = explained.iter().filter(|s| s.error.is_some()).count();
+        if failed > 0 {
+            status!(
+                "{} statement{} could not be explained. Statements that use tables or columns created earlier in the migration can only be explained once it is applied.",
+                failed,
+                if failed == 1 { "" } else { "s" }
+            );
+        }
+
+        Ok(Outcome::Success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dml_statements() {
+        let sql = "BEGIN;
+ALTER TABLE users ADD COLUMN email_lower text;
+UPDATE users SET email_lower = lower(email);
+WITH moved AS (DELETE FROM old_orders RETURNING *) INSERT INTO orders SELECT * FROM moved;
+WITH recent AS (SELECT 1) SELECT * FROM recent;
+CREATE FUNCTION f() RETURNS void AS $$ DELETE FROM t; $$ LANGUAGE sql;
+insert into log values ('update');
+COMMIT;
+";
+        assert_eq!(
+            vec![
+                "UPDATE users SET email_lower = lower(email)",
+                "WITH moved AS (DELETE FROM old_orders RETURNING *) INSERT INTO orders SELECT * FROM moved",
+                "insert into log values ('update')",
+            ],
+            dml_statements(sql)
+        );
+    }
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(
+            (Some(431.0), Some(10000)),
+            estimate("Update on users  (cost=0.00..431.00 rows=10000 width=38)")
+        );
+        assert_eq!(
+            (Some(12.5), Some(3)),
+            estimate("Delete on t  (cost=0.00..12.50 rows=3 width=6) (actual time=0.1..0.1 rows=0 loops=1)")
+        );
+        assert_eq!((None, None), estimate("Planning Time: 0.1 ms"));
+    }
+}
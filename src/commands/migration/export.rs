@@ -0,0 +1,163 @@
+//! The applied migrations of an environment, written by
+//! `spawn migration status --export` where the database can be reached and
+//! read by `spawn migration gate --against` where it cannot.
+//!
+//! The format is versioned: fields may be added within a version, but
+//! anything that would change how an existing field is read needs a new one.
+
+use crate::commands::migration::HistoryRecord;
+use crate::engine::{MigrationActivity, MigrationStatus as EngineStatus};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Version of the format written by this spawn.
+pub const STATUS_EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StatusExport {
+    pub version: u32,
+    /// When the export was taken (RFC 3339, UTC)
+    pub exported_at: String,
+    pub namespace: String,
+    /// Migrations applied or adopted, in the order they were applied
+    pub migrations: Vec<AppliedMigration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AppliedMigration {
+    pub migration: String,
+    /// `APPLY` or `ADOPT`
+    pub activity: String,
+    /// When it was applied (RFC 3339, UTC)
+    pub applied_at: String,
+    /// Checksum of the SQL that was run. Adopted migrations have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Pin the SQL was rendered from, if it was applied pinned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_hash: Option<String>,
+}
+
+impl StatusExport {
+    /// The migrations applied according to `history`, taking the last
+    /// successful entry of each. Migrations whose last successful activity
+    /// was a revert are left out.
+    pub fn from_history(namespace: &str, history: Vec<HistoryRecord>) -> Self {
+        let mut migrations: Vec<AppliedMigration> = Vec::new();
+        for record in history {
+            if record.namespace != namespace
+                || record.status.as_deref() != Some(EngineStatus::Success.as_str())
+            {
+                continue;
+            }
+            migrations.retain(|m| m.migration != record.migration);
+            if record.activity == MigrationActivity::Revert.as_str() {
+                continue;
+            }
+            migrations.push(AppliedMigration {
+                migration: record.migration,
+                activity: record.activity,
+                applied_at: record.created_at,
+                checksum: record.checksum,
+                pin_hash: record.pin_hash,
+            });
+        }
+
+        Self {
+            version: STATUS_EXPORT_VERSION,
+            exported_at: chrono::Utc::now().to_rfc3339(),
+            namespace: namespace.to_string(),
+            migrations,
+        }
+    }
+
+    /// Reads an export, refusing versions this spawn does not know.
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = std::fs::read(path).context(format!("could not read {}", path))?;
+        let value: serde_json::Value =
+            serde_json::from_slice(&contents).context(format!("{} is not valid JSON", path))?;
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(v) if v == STATUS_EXPORT_VERSION as u64 => {}
+            Some(v) => {
+                return Err(anyhow!(
+                    "{} is a version {} status export, but this spawn reads version {}",
+                    path,
+                    v,
+                    STATUS_EXPORT_VERSION
+                ))
+            }
+            None => {
+                return Err(anyhow!(
+                    "{} is not a status export, expected the output of `spawn migration status --export`",
+                    path
+                ))
+            }
+        }
+        serde_json::from_value(value).context(format!("could not parse {}", path))
+    }
+
+    /// Writes the export as pretty-printed JSON.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n").context(format!("could not write {}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(migration: &str, activity: &str, status: &str) -> HistoryRecord {
+        HistoryRecord {
+            migration: migration.to_string(),
+            namespace: "default".to_string(),
+            activity: activity.to_string(),
+            status: Some(status.to_string()),
+            created_at: "2024-01-01T00:00:00+00:00".to_string(),
+            execution_time: 0.1,
+            checksum: Some(format!("{}-{}", migration, activity)),
+            pin_hash: None,
+            created_by: None,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_from_history_keeps_last_success() {
+        let export = StatusExport::from_history(
+            "default",
+            vec![
+                record("a", "APPLY", "FAILURE"),
+                record("a", "APPLY", "SUCCESS"),
+                record("b", "ADOPT", "SUCCESS"),
+                record("c", "APPLY", "SUCCESS"),
+                record("c", "REVERT", "SUCCESS"),
+                record("d", "APPLY", "ATTEMPTED"),
+            ],
+        );
+        let applied: Vec<(&str, &str)> = export
+            .migrations
+            .iter()
+            .map(|m| (m.migration.as_str(), m.activity.as_str()))
+            .collect();
+        assert_eq!(vec![("a", "APPLY"), ("b", "ADOPT")], applied);
+    }
+
+    #[test]
+    fn test_load_refuses_other_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status.json");
+        let path = path.to_str().unwrap();
+
+        let export = StatusExport::from_history("default", vec![record("a", "APPLY", "SUCCESS")]);
+        export.write(path).unwrap();
+        assert_eq!(export, StatusExport::load(path).unwrap());
+
+        std::fs::write(path, r#"{"version": 2, "migrations": []}"#).unwrap();
+        let err = StatusExport::load(path).unwrap_err();
+        assert!(err.to_string().contains("version 2"));
+
+        std::fs::write(path, "[]").unwrap();
+        assert!(StatusExport::load(path).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! Checks for a pull request's CI, catching migration problems that only
+//! show up once branches are merged: two migrations with the same timestamp
+//! or name, migrations changed after being applied, and pending migrations
+//! that were never pinned.
+
+use crate::annotations::Annotation;
+use crate::commands::migration::{AppliedMigration, HistoryRecord, StatusExport};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::engine::postgres_psql::migration_checksum;
+use crate::engine::MigrationActivity;
+use crate::migrator::Migrator;
+use crate::naming::split_timestamp;
+use crate::store::list_migration_fs_status;
+use crate::variables::Variables;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+
+pub struct MigrationGate {
+    /// History exported from each tracked environment with
+    /// `spawn migration status --history --json`
+    pub applied: Vec<String>,
+    /// Status exported from each tracked environment with
+    /// `spawn migration status --export`
+    pub against: Vec<String>,
+    pub variables: Option<Variables>,
+}
+
+impl TelemetryDescribe for MigrationGate {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration gate").with_properties(vec![
+            ("has_applied", (!self.applied.is_empty()).to_string()),
+            ("has_against", (!self.against.is_empty()).to_string()),
+            ("has_variables", self.variables.is_some().to_string()),
+        ])
+    }
+}
+
+/// Reads a history export into the migrations it shows as applied.
+fn load_history(path: &str) -> Result<StatusExport> {
+    let contents = std::fs::read(path).context(format!("could not read {}", path))?;
+    let records: Vec<HistoryRecord> = serde_json::from_slice(&contents).context(format!(
+        "could not parse {}, expected the output of `spawn migration status --history --json`",
+        path
+    ))?;
+    Ok(StatusExport::from_history(
+        super::DEFAULT_NAMESPACE,
+        records,
+    ))
+}
+
+fn by_migration(export: StatusExport) -> HashMap<String, AppliedMigration> {
+    export
+        .migrations
+        .into_iter()
+        .map(|m| (m.migration.clone(), m))
+        .collect()
+}
+
+/// Groups migrations by `key`, returning the groups with more than one.
+fn collisions<'a>(
+    migrations: impl Iterator<Item = &'a String>,
+    key: impl Fn(&'a str) -> Option<&'a str>,
+) -> Vec<(&'a str, Vec<&'a str>)> {
+    let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for migration in migrations {
+        if let Some(k) = key(migration) {
+            groups.entry(k).or_default().push(migration);
+        }
+    }
+    groups.into_iter().filter(|(_, g)| g.len() > 1).collect()
+}
+
+impl MigrationGate {
+    /// Why `record`, exported from `export`, no longer matches the migration
+    /// on disk, if it does not.
+    async fn modified(
+        &self,
+        config: &Config,
+        migration: &str,
+        export: &str,
+        record: &AppliedMigration,
+    ) -> Result<Option<String>> {
+        if let Some(pin_hash) = &record.pin_hash {
+            let lock_path = config.pather().migration_lock_file_path(migration);
+            if !config.operator().exists(&lock_path).await? {
+                return Ok(Some(format!(
+                    "Migration {} was applied pinned in {}, but is no longer pinned",
+                    migration, export
+                )));
+            }
+            if &config.load_lock_file(&lock_path).await?.pin != pin_hash {
+                return Ok(Some(format!(
+                    "Migration {} was re-pinned after being applied in {}",
+                    migration, export
+                )));
+            }
+        }
+
+        // Adopted migrations were never run by spawn, so have no checksum.
+        let Some(checksum) = &record.checksum else {
+            return Ok(None);
+        };
+        let mut sql = Vec::new();
+        Migrator::new(config, migration, record.pin_hash.is_some())
+            .generate_streaming(self.variables.clone())
+            .await
+            .context(format!("could not render migration {}", migration))?
+            .render_to_writer(&mut sql)?;
+        if &migration_checksum(&sql) != checksum {
+            return Ok(Some(format!(
+                "Migration {} was modified after being applied in {}",
+                migration, export
+            )));
+        }
+        Ok(None)
+    }
+}
+
+impl Command for MigrationGate {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let mut problems: Vec<String> = Vec::new();
+        let mut report = |migration: &str, title: &str, message: String| {
+            Annotation::error(message.clone())
+                .with_file(&config.pather().migration_script_file_path(migration))
+                .with_title(title)
+                .emit(config.annotate);
+            problems.push(message);
+        };
+
+        let fs_status = list_migration_fs_status(config.operator(), &config.pather(), None).await?;
+        let migrations: Vec<&String> = fs_status
+            .iter()
+            .filter(|(_, status)| status.has_up_sql)
+            .map(|(name, _)| name)
+            .collect();
+
+        for (timestamp, group) in collisions(migrations.iter().copied(), |m| split_timestamp(m).0) {
+            report(
+                group[0],
+                "Duplicate timestamp",
+                format!(
+                    "Migrations {} share the timestamp {}",
+                    group.join(", "),
+                    timestamp
+                ),
+            );
+        }
+        for (name, group) in collisions(migrations.iter().copied(), |m| Some(split_timestamp(m).1))
+        {
+            report(
+                group[0],
+                "Duplicate name",
+                format!("Migrations {} share the name {}", group.join(", "), name),
+            );
+        }
+
+        let mut exports = Vec::new();
+        for path in &self.applied {
+            exports.push((path.as_str(), by_migration(load_history(path)?)));
+        }
+        for path in &self.against {
+            exports.push((path.as_str(), by_migration(StatusExport::load(path)?)));
+        }
+
+        for migration in &migrations {
+            let mut pending = exports.is_empty();
+            for (path, applied) in &exports {
+                let Some(record) = applied.get(migration.as_str()) else {
+                    pending = true;
+                    continue;
+                };
+                if record.activity != MigrationActivity::Apply.as_str() {
+                    continue;
+                }
+                if let Some(message) = self.modified(config, migration, path, record).await? {
+                    report(migration, "Migration modified after apply", message);
+                }
+            }
+
+            if pending && !fs_status[migration.as_str()].has_lock_toml {
+                report(
+                    migration,
+                    "Unpinned migration",
+                    format!(
+                        "Pending migration {} is not pinned. Run `spawn migration pin {}`.",
+                        migration, migration
+                    ),
+                );
+            }
+        }
+
+        if problems.is_empty() {
+            println!("No issues found.");
+            Ok(Outcome::Success)
+        } else {
+            println!(
+                "\nFound {} problem{}:\n",
+                problems.len(),
+                if problems.len() == 1 { "" } else { "s" }
+            );
+            for (i, problem) in problems.iter().enumerate() {
+                println!("  {}. {}", i + 1, problem);
+            }
+            println!();
+            Ok(Outcome::CheckFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collisions() {
+        let migrations: Vec<String> = vec![
+            "20240101000000-users".to_string(),
+            "20240101000000-orders".to_string(),
+            "20240102000000-users".to_string(),
+            "20240103000000-items".to_string(),
+        ];
+        assert_eq!(
+            vec![(
+                "20240101000000",
+                vec!["20240101000000-users", "20240101000000-orders"]
+            )],
+            collisions(migrations.iter(), |m| split_timestamp(m).0)
+        );
+        assert_eq!(
+            vec![(
+                "users",
+                vec!["20240101000000-users", "20240102000000-users"]
+            )],
+            collisions(migrations.iter(), |m| Some(split_timestamp(m).1))
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::locks::{lock_holders, steal, STALE_AFTER};
+use crate::status;
+use anyhow::{anyhow, Result};
+use dialoguer::Confirm;
+
+/// Shows who holds the migration lock, and with `steal` ends the session
+/// of a holder that has crashed.
+pub struct MigrationLocks {
+    pub steal: bool,
+    pub yes: bool,
+}
+
+impl TelemetryDescribe for MigrationLocks {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration locks")
+            .with_properties(vec![("opt_steal", self.steal.to_string())])
+    }
+}
+
+impl Command for MigrationLocks {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let target = config.target_config()?;
+        let engine = config.new_engine().await?;
+        let holders = lock_holders(engine.as_ref(), &target.spawn_schema).await?;
+        let Some(holder) = holders.first() else {
+            println!("No apply holds the migration lock.");
+            return Ok(Outcome::Success);
+        };
+        println!("The migration lock is held by {}", holder.describe());
+        if !self.steal {
+            return Ok(Outcome::Success);
+        }
+
+        if holder.apply.is_none() {
+            return Err(anyhow!(
+                "session {} did not record who it is, so there is no telling whether it crashed. \
+                 If you are sure it has, end it with SELECT pg_terminate_backend({})",
+                holder.backend_pid,
+                holder.backend_pid
+            ));
+        }
+        if !holder.is_stale() {
+            return Err(anyhow!(
+                "the holder sent a heartbeat in the last {}s, so it is still running. \
+                 Only holders that stopped sending heartbeats can be stolen from",
+                STALE_AFTER.as_secs()
+            ));
+        }
+        if !self.yes
+            && !Confirm::new()
+                .with_prompt(format!(
+                    "End session {}, releasing the lock? Any migration it was applying is rolled back as far as its transactions allow",
+                    holder.backend_pid
+                ))
+                .default(false)
+                .interact()?
+        {
+            status!("Aborted.");
+            return Ok(Outcome::Success);
+        }
+        steal(engine.as_ref(), holder).await?;
+        status!(
+            "Ended session {}. Check the history of the migration it was applying before applying again.",
+            holder.backend_pid
+        );
+        Ok(Outcome::Success)
+    }
+}
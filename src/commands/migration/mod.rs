@@ -1,28 +1,60 @@
 mod adopt;
 mod apply;
+mod archive;
 mod build;
+mod diff_envs;
+mod explain;
+mod export;
+mod gate;
+mod locks;
 mod new;
 mod pin;
+mod progress;
 mod status;
+mod submit;
 
 pub use adopt::AdoptMigration;
-pub use apply::ApplyMigration;
-pub use build::BuildMigration;
+pub use apply::{ApplyMigration, ApplyReport};
+pub use archive::{archive_candidates, ArchiveMigrations};
+pub(crate) use build::migrations_to_build;
+pub use build::{
+    build_to_folder, compare_golden, render_migration, BuildAllMigrations, BuildManifest,
+    BuildMigration, BuiltFile, CheckBuiltMigrations, GoldenMismatch, BUILD_MANIFEST_NAME,
+};
+pub use diff_envs::{diff_envs, EnvDiff, EnvDifference, MigrationDiffEnvs, MigrationEnvDiff};
+pub use explain::{dml_statements, explain_sql, ExplainMigration, ExplainedStatement};
+pub use export::{AppliedMigration, StatusExport, STATUS_EXPORT_VERSION};
+pub use gate::MigrationGate;
+pub use locks::MigrationLocks;
 pub use new::NewMigration;
-pub use pin::PinMigration;
-pub use status::MigrationStatus;
+pub use pin::{pin_migration, pin_migration_to_tag, PinMigration};
+pub(crate) use progress::format_duration;
+pub use progress::StepResult;
+pub(crate) use status::status_label;
+pub use status::{migration_history, HistoryRecord, MigrationStatus};
+pub use submit::SubmitMigration;
 
 pub const DEFAULT_NAMESPACE: &str = "default";
 
 use crate::config::Config;
 use crate::engine::{MigrationDbInfo, MigrationHistoryStatus};
+use crate::output;
+use crate::status;
 use crate::store::list_migration_fs_status;
-use anyhow::Result;
-use dialoguer::Confirm;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use dialoguer::{Confirm, Input};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
 
-/// Combined status of a migration from both filesystem and database
-#[derive(Debug, Clone)]
+/// Must be set, along with `--yes --force`, to change a protected target
+/// without typing its name.
+pub const ALLOW_PROTECTED_ENV: &str = "SPAWN_ALLOW_PROTECTED";
+
+/// Combined status of a migration from both filesystem and database, as
+/// printed by `status --json`
+#[derive(Debug, Clone, Serialize)]
 pub struct MigrationStatusRow {
     pub migration_name: String,
     pub exists_in_filesystem: bool,
@@ -31,6 +63,8 @@ pub struct MigrationStatusRow {
     pub last_status: Option<MigrationHistoryStatus>,
     pub last_activity: Option<String>,
     pub checksum: Option<String>,
+    /// Moved to the migrations archive by `spawn migration archive`
+    pub archived: bool,
 }
 
 /// Get the combined status of all migrations from both filesystem and database.
@@ -44,6 +78,8 @@ pub async fn get_combined_migration_status(
 
     // Get filesystem status
     let fs_status = list_migration_fs_status(config.operator(), &config.pather(), None).await?;
+    let archived =
+        list_migration_fs_status(config.operator(), &config.pather().archive(), None).await?;
 
     // Get all migrations from database with their latest history entry
     let db_migrations_list = engine.get_migrations_from_db(namespace).await?;
@@ -57,6 +93,7 @@ pub async fn get_combined_migration_status(
     // Combine both sources
     let all_migration_names: HashSet<String> = fs_status
         .keys()
+        .chain(archived.keys())
         .chain(db_migrations.keys())
         .cloned()
         .collect();
@@ -64,7 +101,8 @@ pub async fn get_combined_migration_status(
     let mut results: Vec<MigrationStatusRow> = all_migration_names
         .into_iter()
         .map(|name| {
-            let fs = fs_status.get(&name);
+            let is_archived = !fs_status.contains_key(&name) && archived.contains_key(&name);
+            let fs = fs_status.get(&name).or(archived.get(&name));
             let db_info = db_migrations.get(&name);
 
             MigrationStatusRow {
@@ -75,6 +113,7 @@ pub async fn get_combined_migration_status(
                 last_status: db_info.and_then(|info| info.last_status),
                 last_activity: db_info.and_then(|info| info.last_activity.clone()),
                 checksum: db_info.and_then(|info| info.checksum.clone()),
+                archived: is_archived,
             }
         })
         .collect();
@@ -85,6 +124,154 @@ pub async fn get_combined_migration_status(
     Ok(results)
 }
 
+/// Narrows the rows of [`get_combined_migration_status`], for
+/// `migration status`. The default keeps every migration that is not
+/// archived.
+#[derive(Debug, Clone, Default)]
+pub struct StatusFilter {
+    /// Keep pending migrations. With `failed`, keeps either.
+    pub pending: bool,
+    /// Keep migrations whose last attempt failed or did not finish
+    pub failed: bool,
+    /// Keep migrations whose name's timestamp is on or after this date
+    pub since: Option<NaiveDate>,
+    /// Keep migrations whose name contains this
+    pub name_contains: Option<String>,
+    /// Keep only this many of the newest migrations left by the others
+    pub limit: Option<usize>,
+    /// Keep archived migrations
+    pub include_archived: bool,
+}
+
+impl StatusFilter {
+    pub fn matches(&self, row: &MigrationStatusRow) -> bool {
+        let is_pending = row.exists_in_filesystem && row.last_status.is_none();
+        let is_failed = matches!(
+            row.last_status,
+            Some(MigrationHistoryStatus::Failure | MigrationHistoryStatus::Attempted)
+        );
+        let by_status = match (self.pending, self.failed) {
+            (false, false) => true,
+            (pending, failed) => (pending && is_pending) || (failed && is_failed),
+        };
+        let since = self.since.map(|date| date.format("%Y%m%d").to_string());
+        by_status
+            && (self.include_archived || !row.archived)
+            && since.is_none_or(|since| {
+                row.migration_name
+                    .get(..8)
+                    .filter(|date| date.bytes().all(|b| b.is_ascii_digit()))
+                    .is_some_and(|date| date >= since.as_str())
+            })
+            && self
+                .name_contains
+                .as_ref()
+                .is_none_or(|part| row.migration_name.contains(part.as_str()))
+    }
+
+    /// The rows this filter keeps, in the order given.
+    pub fn apply(&self, rows: Vec<MigrationStatusRow>) -> Vec<MigrationStatusRow> {
+        let mut rows: Vec<MigrationStatusRow> =
+            rows.into_iter().filter(|row| self.matches(row)).collect();
+        if let Some(limit) = self.limit {
+            rows.drain(..rows.len().saturating_sub(limit));
+        }
+        rows
+    }
+}
+
+/// [`get_combined_migration_status`] narrowed by `filter`.
+pub async fn get_filtered_migration_status(
+    config: &Config,
+    namespace: Option<&str>,
+    filter: &StatusFilter,
+) -> Result<Vec<MigrationStatusRow>> {
+    Ok(filter.apply(get_combined_migration_status(config, namespace).await?))
+}
+
+/// Migrations that exist on the filesystem but have no status in the
+/// database, in the order they are applied.
+pub async fn get_pending_migrations(config: &Config) -> Result<Vec<String>> {
+    let status_rows = get_combined_migration_status(config, Some(DEFAULT_NAMESPACE)).await?;
+
+    Ok(status_rows
+        .into_iter()
+        .filter(|row| row.last_status.is_none() && row.exists_in_filesystem)
+        .map(|row| row.migration_name)
+        .collect())
+}
+
+/// Migrations among `migrations` that sort before an already applied one,
+/// each with the applied migrations that are newer than it. Migration names
+/// start with their timestamp, so name order is creation order.
+fn out_of_order(migrations: &[String], applied: &[String]) -> Vec<(String, Vec<String>)> {
+    migrations
+        .iter()
+        .filter(|m| !applied.contains(m))
+        .filter_map(|migration| {
+            let newer: Vec<String> = applied
+                .iter()
+                .filter(|a| a.as_str() > migration.as_str())
+                .cloned()
+                .collect();
+            (!newer.is_empty()).then(|| (migration.clone(), newer))
+        })
+        .collect()
+}
+
+/// Refuses to apply migrations older than ones already applied, which
+/// usually means they were merged after a newer migration was deployed,
+/// unless `allow_out_of_order` is set (or `--allow-out-of-order` given), in
+/// which case they are only reported.
+pub async fn check_migration_order(config: &Config, migrations: &[String]) -> Result<()> {
+    let mut applied: Vec<String> = get_combined_migration_status(config, Some(DEFAULT_NAMESPACE))
+        .await?
+        .into_iter()
+        .filter(|row| row.last_status == Some(MigrationHistoryStatus::Success))
+        .map(|row| row.migration_name)
+        .collect();
+    applied.sort();
+
+    let found = out_of_order(migrations, &applied);
+    if found.is_empty() {
+        return Ok(());
+    }
+
+    let details: Vec<String> = found
+        .iter()
+        .map(|(migration, newer)| {
+            format!("  {} is older than applied {}", migration, newer.join(", "))
+        })
+        .collect();
+
+    if config.allow_out_of_order {
+        config.events.on_warning(&format!(
+            "applying migrations out of order:\n{}",
+            details.join("\n")
+        ));
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} migration{} would be applied out of order:\n{}\n\
+         This usually means {} merged after newer migrations were applied. \
+         Check {} not conflict with them, then rerun with --allow-out-of-order \
+         (or set allow_out_of_order = true in spawn.toml).",
+        found.len(),
+        if found.len() == 1 { "" } else { "s" },
+        details.join("\n"),
+        if found.len() == 1 {
+            "it was"
+        } else {
+            "they were"
+        },
+        if found.len() == 1 {
+            "it does"
+        } else {
+            "they do"
+        },
+    ))
+}
+
 /// Get pending migrations (no status, exists on filesystem) and prompt the user
 /// to confirm. Returns `Ok(Some(migrations))` if confirmed, `Ok(None)` if
 /// aborted or empty.
@@ -93,45 +280,61 @@ pub async fn get_pending_and_confirm(
     action: &str,
     yes: bool,
 ) -> Result<Option<Vec<String>>> {
-    let status_rows = get_combined_migration_status(config, Some(DEFAULT_NAMESPACE)).await?;
-
-    let pending: Vec<String> = status_rows
-        .into_iter()
-        .filter(|row| row.last_status.is_none() && row.exists_in_filesystem)
-        .map(|row| row.migration_name)
-        .collect();
+    let pending = get_pending_migrations(config).await?;
 
     if pending.is_empty() {
-        println!("No pending migrations to {}.", action);
+        status!("No pending migrations to {}.", action);
         return Ok(None);
     }
 
+    if !confirm_migrations(config, action, &pending, yes)? {
+        return Ok(None);
+    }
+    Ok(Some(pending))
+}
+
+/// Print the target and the migrations about to be applied or adopted, and
+/// ask the user to confirm unless `yes` is set. Returns whether to go ahead.
+pub fn confirm_migrations(
+    config: &Config,
+    action: &str,
+    pending: &[String],
+    yes: bool,
+) -> Result<bool> {
     let target_config = config.target_config()?;
     let target = config.target.as_deref().unwrap_or("unknown");
     let env = &target_config.environment;
 
-    println!();
-    println!("TARGET: {}", target);
-    if env.starts_with("prod") {
-        println!("ENVIRONMENT: {} \u{26a0}\u{fe0f}", env);
-    } else {
-        println!("ENVIRONMENT: {}", env);
-    }
-    println!();
-    println!(
-        "The following {} migration{} will be {}:",
-        pending.len(),
-        if pending.len() == 1 { "" } else { "s" },
-        if action == "apply" {
-            "applied"
+    // The list is what is being confirmed, so --quiet only leaves it out
+    // when nothing is asked.
+    if !(yes && output::is_quiet()) {
+        eprintln!();
+        if target_config.protected {
+            eprintln!("TARGET: {} (protected)", target);
         } else {
-            "adopted"
-        },
-    );
-    for (i, name) in pending.iter().enumerate() {
-        println!("  {}. {}", i + 1, name);
+            eprintln!("TARGET: {}", target);
+        }
+        if env.starts_with("prod") {
+            eprintln!("ENVIRONMENT: {} \u{26a0}\u{fe0f}", env);
+        } else {
+            eprintln!("ENVIRONMENT: {}", env);
+        }
+        eprintln!();
+        eprintln!(
+            "The following {} migration{} will be {}:",
+            pending.len(),
+            if pending.len() == 1 { "" } else { "s" },
+            if action == "apply" {
+                "applied"
+            } else {
+                "adopted"
+            },
+        );
+        for (i, name) in pending.iter().enumerate() {
+            eprintln!("  {}. {}", i + 1, name);
+        }
+        eprintln!();
     }
-    println!();
 
     if !yes {
         let prompt = format!("Do you want to {} these migrations?", action);
@@ -141,11 +344,176 @@ pub async fn get_pending_and_confirm(
             .interact()?;
 
         if !confirmed {
-            println!("Aborted.");
-            return Ok(None);
+            status!("Aborted.");
+            return Ok(false);
         }
     }
 
-    println!();
-    Ok(Some(pending))
+    status!();
+    Ok(true)
+}
+
+/// Before changing a protected target, asks for its name to be typed, as a
+/// plain yes is too easy to give by habit. With `yes`, the check is only
+/// skipped when `force` is also given and `SPAWN_ALLOW_PROTECTED` is set.
+/// Returns whether to go ahead.
+pub fn confirm_protected_target(
+    config: &Config,
+    action: &str,
+    yes: bool,
+    force: bool,
+) -> Result<bool> {
+    let target_config = config.target_config()?;
+    if !target_config.protected {
+        return Ok(true);
+    }
+    let target = config.target.as_deref().unwrap_or("unknown");
+
+    if yes {
+        let allowed = std::env::var(ALLOW_PROTECTED_ENV).is_ok_and(|v| !v.is_empty());
+        if force && allowed {
+            eprintln!(
+                "Target '{}' is protected, continuing because of --force.",
+                target
+            );
+            return Ok(true);
+        }
+        return Err(anyhow!(
+            "target '{}' is protected. Run without --yes to confirm by typing its name, \
+             or use --yes --force with {} set.",
+            target,
+            ALLOW_PROTECTED_ENV
+        ));
+    }
+
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "target '{}' is protected and its name must be typed to {} migrations, which needs a terminal",
+            target,
+            action
+        ));
+    }
+
+    eprintln!(
+        "Target '{}' (environment {}) is protected.",
+        target, target_config.environment
+    );
+    let typed: String = Input::new()
+        .with_prompt(format!("Type '{}' to {} migrations", target, action))
+        .allow_empty(true)
+        .interact_text()?;
+    if typed.trim() != target {
+        status!("Aborted.");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let applied = names(&["20240101000000-a", "20240301000000-c"]);
+
+        assert!(out_of_order(&names(&["20240401000000-d"]), &applied).is_empty());
+        // Already applied migrations are not reported.
+        assert!(out_of_order(&names(&["20240101000000-a"]), &applied).is_empty());
+
+        assert_eq!(
+            vec![
+                (
+                    "20231201000000-z".to_string(),
+                    names(&["20240101000000-a", "20240301000000-c"])
+                ),
+                ("20240201000000-b".to_string(), names(&["20240301000000-c"])),
+            ],
+            out_of_order(
+                &names(&["20231201000000-z", "20240201000000-b", "20240401000000-d"]),
+                &applied
+            )
+        );
+    }
+
+    #[test]
+    fn test_status_filter() {
+        let row = |name: &str, status: Option<MigrationHistoryStatus>, archived: bool| {
+            MigrationStatusRow {
+                migration_name: name.to_string(),
+                exists_in_filesystem: true,
+                is_pinned: true,
+                exists_in_db: status.is_some(),
+                last_status: status,
+                last_activity: None,
+                checksum: None,
+                archived,
+            }
+        };
+        let rows = vec![
+            row(
+                "20220101000000-old",
+                Some(MigrationHistoryStatus::Success),
+                true,
+            ),
+            row(
+                "20240101000000-users",
+                Some(MigrationHistoryStatus::Success),
+                false,
+            ),
+            row(
+                "20240201000000-orders",
+                Some(MigrationHistoryStatus::Failure),
+                false,
+            ),
+            row("20240301000000-user-emails", None, false),
+            row("base-tables", None, false),
+        ];
+        let kept = |filter: StatusFilter| -> Vec<String> {
+            filter
+                .apply(rows.clone())
+                .into_iter()
+                .map(|row| row.migration_name)
+                .collect()
+        };
+
+        assert_eq!(4, kept(StatusFilter::default()).len());
+        assert_eq!(
+            5,
+            kept(StatusFilter {
+                include_archived: true,
+                ..Default::default()
+            })
+            .len()
+        );
+        assert_eq!(
+            names(&["20240301000000-user-emails", "base-tables"]),
+            kept(StatusFilter {
+                pending: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            names(&["20240201000000-orders", "20240301000000-user-emails"]),
+            kept(StatusFilter {
+                pending: true,
+                failed: true,
+                since: NaiveDate::from_ymd_opt(2024, 2, 1),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            names(&["20240301000000-user-emails"]),
+            kept(StatusFilter {
+                name_contains: Some("user".to_string()),
+                limit: Some(1),
+                ..Default::default()
+            })
+        );
+    }
 }
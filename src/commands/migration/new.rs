@@ -1,10 +1,25 @@
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
-use crate::migrator::Migrator;
-use anyhow::Result;
+use crate::migrator::{git_author, MigrationMetadata, Migrator};
+use crate::scaffold;
+use crate::status;
+use anyhow::{anyhow, Result};
+use dialoguer::{Editor, Input};
+use std::collections::HashMap;
+use std::io::IsTerminal;
 
 pub struct NewMigration {
     pub name: String,
+    /// Template in the project's `templates/` folder to start from
+    pub template: Option<String>,
+    /// Placeholder values for the template, from `--set NAME=VALUE`
+    pub set: Vec<(String, String)>,
+    /// Open the new `up.sql` in the user's editor once it is written
+    pub edit: bool,
+    /// Ticket or issue recorded in `migration.toml`
+    pub ticket: Option<String>,
+    /// Description recorded in `migration.toml`
+    pub description: Option<String>,
 }
 
 impl TelemetryDescribe for NewMigration {
@@ -13,16 +28,102 @@ impl TelemetryDescribe for NewMigration {
     }
 }
 
+impl NewMigration {
+    /// Fills in the template, prompting for placeholders that were not given
+    /// with `--set`.
+    async fn scaffold(&self, config: &Config, template: &str) -> Result<String> {
+        let contents = scaffold::load_template(config, template).await?;
+        let placeholders = scaffold::placeholders(&contents);
+
+        let mut values: HashMap<String, String> = HashMap::new();
+        for (name, value) in &self.set {
+            if !placeholders.contains(name) {
+                return Err(anyhow!(
+                    "template '{}' has no placeholder '{}' (it has: {})",
+                    template,
+                    name,
+                    placeholders.join(", ")
+                ));
+            }
+            values.insert(name.clone(), value.clone());
+        }
+
+        let missing: Vec<&String> = placeholders
+            .iter()
+            .filter(|p| !values.contains_key(*p))
+            .collect();
+        if !missing.is_empty() && !std::io::stdin().is_terminal() {
+            return Err(anyhow!(
+                "no value for {} in template '{}'. Pass each with --set NAME=VALUE.",
+                missing
+                    .iter()
+                    .map(|m| m.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                template
+            ));
+        }
+        for name in missing {
+            let value: String = Input::new().with_prompt(name).interact_text()?;
+            values.insert(name.clone(), value);
+        }
+
+        scaffold::fill(&contents, &values)
+    }
+}
+
 impl Command for NewMigration {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
+        config.naming.check(&self.name)?;
+
+        let script = match &self.template {
+            Some(template) => Some(self.scaffold(config, template).await?),
+            None => None,
+        };
+
         let migration_name = format!(
             "{}-{}",
             chrono::Utc::now().format("%Y%m%d%H%M%S"),
             self.name
         );
-        println!("creating migration with name {}", &migration_name);
+        status!("creating migration with name {}", &migration_name);
+        config.audit_session.record_migration(&migration_name);
         let mg = Migrator::new(config, &migration_name, false);
 
-        Ok(Outcome::NewMigration(mg.create_migration().await?))
+        let name = match script {
+            Some(script) => mg.create_migration_with(&script).await?,
+            None => mg.create_migration().await?,
+        };
+        mg.write_metadata(&MigrationMetadata {
+            author: git_author(),
+            ticket: self.ticket.clone(),
+            description: self.description.clone(),
+            ..Default::default()
+        })
+        .await?;
+        if self.edit {
+            edit_script(config, &name).await?;
+        }
+        Ok(Outcome::NewMigration(name))
+    }
+}
+
+/// Opens a migration's `up.sql` in `$VISUAL`/`$EDITOR`, writing back the
+/// result if it was saved.
+async fn edit_script(config: &Config, migration: &str) -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        eprintln!("Not opening an editor as there is no terminal.");
+        return Ok(());
+    }
+
+    let path = config.pather().migration_script_file_path(migration);
+    let script = String::from_utf8(config.operator().read(&path).await?.to_vec())?;
+    if let Some(edited) = Editor::new()
+        .extension(".sql")
+        .require_save(true)
+        .edit(&script)?
+    {
+        config.operator().write(&path, edited).await?;
     }
+    Ok(())
 }
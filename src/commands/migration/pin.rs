@@ -1,42 +1,92 @@
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
 use crate::pinfile::LockData;
+use crate::store::pinner::refs::resolve_ref;
 use crate::store::pinner::spawn::Spawn;
-use crate::store::pinner::Pinner;
+use crate::store::pinner::{snapshot_migration_folder, Pinner};
 use anyhow::{Context, Result};
 
 pub struct PinMigration {
     pub migration: String,
+    /// Pin the components of this tag instead of the current ones
+    pub tag: Option<String>,
 }
 
 impl TelemetryDescribe for PinMigration {
     fn telemetry(&self) -> TelemetryInfo {
         TelemetryInfo::new("migration pin")
+            .with_properties(vec![("has_tag", self.tag.is_some().to_string())])
     }
 }
 
+/// Snapshot the current components (and any other files in the migration
+/// folder) into the pinned store and write the migration's `lock.toml`.
+/// Returns the root hash of the pinned components.
+pub async fn pin_migration(config: &Config, migration: &str) -> Result<String> {
+    let mut pinner = Spawn::new(
+        config.pather().pinned_folder(),
+        config.pather().components_folder(),
+    )
+    .context("could not get pinned_folder")?
+    .with_packages_path(config.pather().vendor_folder());
+
+    let root = pinner
+        .snapshot(config.operator())
+        .await
+        .context("error calling pinner snapshot")?;
+
+    write_lock(config, migration, root, None).await
+}
+
+/// Pin a migration to the components tagged `tag` by `spawn pin tag`,
+/// rather than the current ones. Returns the root hash the tag refers to.
+pub async fn pin_migration_to_tag(config: &Config, migration: &str, tag: &str) -> Result<String> {
+    let root = resolve_ref(config.operator(), &config.pather().pinned_folder(), tag).await?;
+    write_lock(config, migration, root, Some(tag.to_string())).await
+}
+
+/// Snapshot the other files in the migration folder and write the
+/// migration's `lock.toml`, pinning components to `root`.
+async fn write_lock(
+    config: &Config,
+    migration: &str,
+    root: String,
+    tag: Option<String>,
+) -> Result<String> {
+    let pather = config.pather();
+    let local = snapshot_migration_folder(
+        config.operator(),
+        &pather.pinned_folder(),
+        &pather.migration_folder(migration),
+        &pather.migration_folder_excludes(),
+    )
+    .await
+    .context("error pinning migration folder")?;
+
+    let lock_file_path = config.pather().migration_lock_file_path(migration);
+    let toml_str = toml::to_string_pretty(&LockData {
+        pin: root.clone(),
+        tag,
+        local,
+    })
+    .context("could not not convert pin data to toml")?;
+
+    config
+        .operator()
+        .write(&lock_file_path, toml_str)
+        .await
+        .context("failed writing migration lockfile")?;
+
+    Ok(root)
+}
+
 impl Command for PinMigration {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
-        let mut pinner = Spawn::new(
-            config.pather().pinned_folder(),
-            config.pather().components_folder(),
-        )
-        .context("could not get pinned_folder")?;
-
-        let root = pinner
-            .snapshot(config.operator())
-            .await
-            .context("error calling pinner snapshot")?;
-
-        let lock_file_path = config.pather().migration_lock_file_path(&self.migration);
-        let toml_str = toml::to_string_pretty(&LockData { pin: root.clone() })
-            .context("could not not convert pin data to toml")?;
-
-        config
-            .operator()
-            .write(&lock_file_path, toml_str)
-            .await
-            .context("failed writing migration lockfile")?;
+        config.audit_session.record_migration(&self.migration);
+        let root = match &self.tag {
+            Some(tag) => pin_migration_to_tag(config, &self.migration, tag).await?,
+            None => pin_migration(config, &self.migration).await?,
+        };
 
         Ok(Outcome::PinnedMigration { hash: root })
     }
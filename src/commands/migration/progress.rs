@@ -0,0 +1,401 @@
+//! Progress of applying a list of migrations: a progress bar with the
+//! current migration and its elapsed time when stderr is a terminal, or a
+//! line per migration otherwise. Either way a table of how each migration
+//! went is printed at the end.
+
+use crate::engine::{ServerMessage, ServerMessageLevel};
+use crate::output;
+use crate::status;
+use console::{style, Term};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// How often the bar is redrawn to update the elapsed time.
+const TICK: Duration = Duration::from_millis(250);
+const BAR_WIDTH: usize = 24;
+
+/// How one migration of the list ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    Applied,
+    AlreadyApplied,
+    Skipped,
+    Failed,
+}
+
+impl StepResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepResult::Applied => "applied",
+            StepResult::AlreadyApplied => "already_applied",
+            StepResult::Skipped => "skipped",
+            StepResult::Failed => "failed",
+        }
+    }
+}
+
+/// What applying one migration sent and got back, for the summary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct ApplyStats {
+    /// Statements sent to the server
+    pub statements: usize,
+    pub notices: usize,
+    pub warnings: usize,
+}
+
+impl ApplyStats {
+    /// Stats of an apply that sent `statements` and got `messages` back.
+    pub(super) fn new(statements: usize, messages: &[ServerMessage]) -> Self {
+        let count = |level| messages.iter().filter(|m| m.level == level).count();
+        Self {
+            statements,
+            notices: count(ServerMessageLevel::Notice),
+            warnings: count(ServerMessageLevel::Warning),
+        }
+    }
+}
+
+struct Step {
+    migration: String,
+    result: StepResult,
+    elapsed: Duration,
+    stats: Option<ApplyStats>,
+}
+
+struct Current {
+    /// Number of migrations finished so far
+    done: usize,
+    migration: String,
+    started: Instant,
+}
+
+struct Bar {
+    term: Term,
+    current: Arc<Mutex<Current>>,
+    ticker: JoinHandle<()>,
+}
+
+pub(super) struct ApplyProgress {
+    total: usize,
+    counter: Mutex<String>,
+    bar: Option<Bar>,
+    results: Mutex<Vec<Step>>,
+    started: Instant,
+    step_started: Mutex<Instant>,
+    stats: Mutex<Option<ApplyStats>>,
+}
+
+impl ApplyProgress {
+    /// Starts reporting on `total` migrations. The bar is only drawn for more
+    /// than one migration, when `allow_bar` is set (it is not while prompting
+    /// with `--interactive`), stderr is a terminal and `--quiet` is not given.
+    pub(super) fn new(total: usize, allow_bar: bool) -> Self {
+        let term = Term::stderr();
+        let bar = (total > 1 && allow_bar && term.is_term() && !output::is_quiet()).then(|| {
+            let current = Arc::new(Mutex::new(Current {
+                done: 0,
+                migration: String::new(),
+                started: Instant::now(),
+            }));
+            let ticker = {
+                let term = term.clone();
+                let current = current.clone();
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(TICK);
+                    loop {
+                        interval.tick().await;
+                        draw(&term, total, &current.lock().unwrap());
+                    }
+                })
+            };
+            Bar {
+                term,
+                current,
+                ticker,
+            }
+        });
+
+        Self {
+            total,
+            counter: Mutex::new(String::new()),
+            bar,
+            results: Mutex::new(Vec::new()),
+            started: Instant::now(),
+            step_started: Mutex::new(Instant::now()),
+            stats: Mutex::new(None),
+        }
+    }
+
+    /// Whether the bar is drawn, in which case the line per migration is
+    /// left out.
+    pub(super) fn is_drawn(&self) -> bool {
+        self.bar.is_some()
+    }
+
+    /// Moves on to the `index`th migration (from 0).
+    pub(super) fn begin(&self, index: usize, migration: &str) {
+        *self.counter.lock().unwrap() = if self.total > 1 {
+            format!(
+                "[{:>width$}/{}] ",
+                index + 1,
+                self.total,
+                width = self.total.to_string().len()
+            )
+        } else {
+            String::new()
+        };
+        *self.step_started.lock().unwrap() = Instant::now();
+        *self.stats.lock().unwrap() = None;
+
+        if let Some(bar) = &self.bar {
+            let mut current = bar.current.lock().unwrap();
+            current.done = index;
+            current.migration = migration.to_string();
+            current.started = Instant::now();
+            draw(&bar.term, self.total, &current);
+        }
+    }
+
+    /// `[ 3/10] ` style prefix for messages about the current migration,
+    /// empty when there is only one.
+    pub(super) fn counter(&self) -> String {
+        self.counter.lock().unwrap().clone()
+    }
+
+    /// Prints a status message, above the bar when it is drawn.
+    pub(super) fn note(&self, message: &str) {
+        match &self.bar {
+            Some(bar) => {
+                let current = bar.current.lock().unwrap();
+                let _ = bar.term.clear_line();
+                eprintln!("{}", message);
+                draw(&bar.term, self.total, &current);
+            }
+            None => status!("{}", message),
+        }
+    }
+
+    /// Sets what applying the current migration sent and got back, to be
+    /// shown with its result.
+    pub(super) fn set_stats(&self, stats: ApplyStats) {
+        *self.stats.lock().unwrap() = Some(stats);
+    }
+
+    /// Records how the current migration ended, for the summary.
+    pub(super) fn record(&self, migration: &str, result: StepResult) {
+        self.results.lock().unwrap().push(Step {
+            migration: migration.to_string(),
+            result,
+            elapsed: self.step_started.lock().unwrap().elapsed(),
+            stats: self.stats.lock().unwrap().take(),
+        });
+    }
+
+    /// How `migration` ended, if it has been recorded.
+    pub(super) fn result_of(&self, migration: &str) -> Option<StepResult> {
+        self.results
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|step| step.migration == migration)
+            .map(|step| step.result)
+    }
+
+    /// Removes the bar and prints a summary of each migration's result,
+    /// unless `--quiet` is given or nothing was run and the bar was not
+    /// drawn. Returns the results in the order recorded.
+    pub(super) fn finish(self) -> Vec<(String, StepResult)> {
+        let results = self.results.into_inner().unwrap();
+        let drawn = self.bar.is_some();
+        if let Some(bar) = self.bar {
+            bar.ticker.abort();
+            let _ = bar.term.clear_line();
+        }
+        let ran = results
+            .iter()
+            .any(|step| matches!(step.result, StepResult::Applied | StepResult::Failed));
+        if (drawn || ran) && !output::is_quiet() {
+            for line in summary(self.total, self.started.elapsed(), &results) {
+                eprintln!("{}", line);
+            }
+        }
+        results
+            .into_iter()
+            .map(|step| (step.migration, step.result))
+            .collect()
+    }
+}
+
+/// The summary table printed once the list is done.
+fn summary(total: usize, elapsed: Duration, results: &[Step]) -> Vec<String> {
+    let applied = results
+        .iter()
+        .filter(|step| step.result == StepResult::Applied)
+        .count();
+    let width = results
+        .iter()
+        .map(|step| step.migration.len())
+        .chain(["Migration".len()])
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![
+        format!(
+            "Applied {} of {} migration{} in {}:",
+            applied,
+            total,
+            if total == 1 { "" } else { "s" },
+            format_duration(elapsed)
+        ),
+        format!(
+            "    {:<width$}  {:>8}  {:>10}  {:>7}  {:>8}",
+            "Migration", "Time", "Statements", "Notices", "Warnings"
+        ),
+    ];
+    for step in results {
+        let mark = match step.result {
+            StepResult::Applied => style("✓").green(),
+            StepResult::AlreadyApplied => style("=").dim(),
+            StepResult::Skipped => style("-").dim(),
+            StepResult::Failed => style("✗").red(),
+        }
+        .for_stderr();
+        let line = match (step.result, step.stats) {
+            (StepResult::AlreadyApplied, _) => {
+                format!("{:<width$}  already applied", step.migration)
+            }
+            (StepResult::Skipped, _) => format!("{:<width$}  skipped", step.migration),
+            (_, stats) => {
+                let count =
+                    |n: Option<usize>| n.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string());
+                format!(
+                    "{:<width$}  {:>8}  {:>10}  {:>7}  {:>8}{}",
+                    step.migration,
+                    format_duration(step.elapsed),
+                    count(stats.map(|s| s.statements)),
+                    count(stats.map(|s| s.notices)),
+                    count(stats.map(|s| s.warnings)),
+                    if step.result == StepResult::Failed {
+                        "  failed"
+                    } else {
+                        ""
+                    }
+                )
+            }
+        };
+        lines.push(format!("  {} {}", mark, line));
+    }
+    let unfinished = total.saturating_sub(results.len());
+    if unfinished > 0 {
+        lines.push(format!("  {} not attempted", unfinished));
+    }
+    lines
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        self.ticker.abort();
+    }
+}
+
+fn draw(term: &Term, total: usize, current: &Current) {
+    let filled = BAR_WIDTH * current.done / total.max(1);
+    let line = format!(
+        "[{}{}] {}/{} {} ({})",
+        "=".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled),
+        current.done + 1,
+        total,
+        current.migration,
+        format_duration(current.started.elapsed())
+    );
+    let width = term.size().1 as usize;
+    let line: String = line.chars().take(width.saturating_sub(1)).collect();
+    let _ = term.clear_line();
+    let _ = term.write_str(&line);
+}
+
+/// Formats a duration as e.g. `0.4s`, `12s` or `2m05s`.
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else if secs >= 10 {
+        format!("{}s", secs)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!("0.4s", format_duration(Duration::from_millis(400)));
+        assert_eq!("12s", format_duration(Duration::from_millis(12_900)));
+        assert_eq!("2m05s", format_duration(Duration::from_secs(125)));
+    }
+
+    #[test]
+    fn test_counter_without_bar() {
+        let progress = ApplyProgress::new(12, false);
+        assert!(!progress.is_drawn());
+        progress.begin(2, "20240101000000-users");
+        assert_eq!("[ 3/12] ", progress.counter());
+
+        let single = ApplyProgress::new(1, true);
+        single.begin(0, "20240101000000-users");
+        assert_eq!("", single.counter());
+    }
+
+    #[test]
+    fn test_summary() {
+        let step = |migration: &str, result, stats| Step {
+            migration: migration.to_string(),
+            result,
+            elapsed: Duration::from_millis(2_100),
+            stats,
+        };
+        let lines = summary(
+            4,
+            Duration::from_secs(41),
+            &[
+                step("20240101000000-users", StepResult::AlreadyApplied, None),
+                step(
+                    "20240102000000-orders",
+                    StepResult::Applied,
+                    Some(ApplyStats {
+                        statements: 12,
+                        notices: 1,
+                        warnings: 0,
+                    }),
+                ),
+                step(
+                    "20240103000000-backfill",
+                    StepResult::Failed,
+                    Some(ApplyStats {
+                        statements: 3,
+                        notices: 0,
+                        warnings: 2,
+                    }),
+                ),
+            ],
+        );
+        assert_eq!("Applied 1 of 4 migrations in 41s:", lines[0]);
+        assert_eq!(
+            "    Migration                    Time  Statements  Notices  Warnings",
+            lines[1]
+        );
+        assert!(lines[2].ends_with(" 20240101000000-users     already applied"));
+        assert!(
+            lines[3].ends_with(" 20240102000000-orders        2.1s          12        1         0")
+        );
+        assert!(lines[4].ends_with(
+            " 20240103000000-backfill      2.1s           3        0         2  failed"
+        ));
+        assert_eq!("  1 not attempted", lines[5]);
+    }
+}
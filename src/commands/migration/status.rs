@@ -1,9 +1,13 @@
-use crate::commands::migration::get_combined_migration_status;
+use crate::commands::migration::{
+    get_combined_migration_status, MigrationStatusRow, StatusExport, StatusFilter,
+};
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
-use crate::engine::MigrationStatus as EngineStatus;
+use crate::engine::{MigrationHistoryEntry, MigrationStatus as EngineStatus};
+use crate::status;
 use anyhow::Result;
 use console::style;
+use serde::{Deserialize, Serialize};
 use tabled::settings::Style;
 use tabled::{Table, Tabled};
 
@@ -21,79 +25,314 @@ struct MigrationStatusDisplay {
     status: String,
 }
 
-pub struct MigrationStatus;
+#[derive(Tabled)]
+struct MigrationHistoryDisplay {
+    #[tabled(rename = "Migration")]
+    name: String,
+    #[tabled(rename = "Activity")]
+    activity: String,
+    #[tabled(rename = "Status")]
+    status: String,
+    #[tabled(rename = "When (UTC)")]
+    created_at: String,
+    #[tabled(rename = "Duration")]
+    duration: String,
+    #[tabled(rename = "Checksum")]
+    checksum: String,
+    #[tabled(rename = "Pin")]
+    pin_hash: String,
+    #[tabled(rename = "Applied by")]
+    created_by: String,
+    #[tabled(rename = "Description")]
+    description: String,
+}
+
+/// A migration history entry as printed by `status --history --json`, and
+/// read back by `migration gate --applied`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct HistoryRecord {
+    pub migration: String,
+    pub namespace: String,
+    pub activity: String,
+    pub status: Option<String>,
+    /// When the entry was recorded (RFC 3339, UTC)
+    pub created_at: String,
+    /// Execution time in seconds
+    pub execution_time: f64,
+    pub checksum: Option<String>,
+    pub pin_hash: Option<String>,
+    #[serde(default)]
+    pub created_by: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl From<MigrationHistoryEntry> for HistoryRecord {
+    fn from(entry: MigrationHistoryEntry) -> Self {
+        Self {
+            migration: entry.migration_name,
+            namespace: entry.namespace,
+            activity: entry.activity,
+            status: entry.status.map(|s| s.as_str().to_string()),
+            created_at: entry.created_at,
+            execution_time: entry.execution_time,
+            checksum: entry.checksum.filter(|c| !c.is_empty()),
+            pin_hash: entry.pin_hash,
+            created_by: entry.created_by,
+            description: entry.description,
+        }
+    }
+}
+
+pub struct MigrationStatus {
+    /// Show every recorded activity, including checksum, pin hash, author
+    /// and description, instead of the per-migration summary
+    pub history: bool,
+    /// Print the migrations as JSON, or with `history` the entries
+    pub json: bool,
+    /// Write the applied migrations to this file as a [`StatusExport`]
+    pub export: Option<String>,
+    /// Which migrations to show
+    pub filter: StatusFilter,
+    /// Print one line per migration instead of the table
+    pub compact: bool,
+}
 
 impl TelemetryDescribe for MigrationStatus {
     fn telemetry(&self) -> TelemetryInfo {
-        TelemetryInfo::new("migration status")
+        TelemetryInfo::new("migration status").with_properties(vec![
+            ("opt_history", self.history.to_string()),
+            ("opt_json", self.json.to_string()),
+            ("opt_export", self.export.is_some().to_string()),
+            ("opt_archived", self.filter.include_archived.to_string()),
+            ("opt_pending", self.filter.pending.to_string()),
+            ("opt_failed", self.filter.failed.to_string()),
+            ("has_since", self.filter.since.is_some().to_string()),
+            (
+                "has_name_contains",
+                self.filter.name_contains.is_some().to_string(),
+            ),
+            ("has_limit", self.filter.limit.is_some().to_string()),
+            ("opt_compact", self.compact.to_string()),
+        ])
+    }
+}
+
+/// Shortens a hash for display, keeping enough to look it up.
+fn short_hash(hash: Option<String>) -> String {
+    match hash {
+        Some(h) if !h.is_empty() => h.chars().take(12).collect(),
+        _ => style("-").dim().to_string(),
+    }
+}
+
+fn or_dash(value: Option<String>) -> String {
+    value.unwrap_or_else(|| style("-").dim().to_string())
+}
+
+/// Every recorded activity in the default namespace, oldest first.
+pub async fn migration_history(config: &Config) -> Result<Vec<HistoryRecord>> {
+    let engine = config.new_engine().await?;
+    let history = engine
+        .get_migration_history(Some(super::DEFAULT_NAMESPACE))
+        .await?;
+    Ok(history.into_iter().map(HistoryRecord::from).collect())
+}
+
+async fn print_history(config: &Config, json: bool) -> Result<Outcome> {
+    if json {
+        let records = migration_history(config).await?;
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(Outcome::Success);
+    }
+
+    let engine = config.new_engine().await?;
+    let history = engine
+        .get_migration_history(Some(super::DEFAULT_NAMESPACE))
+        .await?;
+
+    if history.is_empty() {
+        println!("No migration history found");
+        return Ok(Outcome::Success);
+    }
+
+    let display_rows: Vec<MigrationHistoryDisplay> = history
+        .into_iter()
+        .map(|entry| {
+            let status = match entry.status {
+                Some(EngineStatus::Success) => style("SUCCESS").green().to_string(),
+                Some(EngineStatus::Attempted) => style("ATTEMPTED").yellow().to_string(),
+                Some(EngineStatus::Failure) => style("FAILURE").red().to_string(),
+                None => style("-").dim().to_string(),
+            };
+
+            MigrationHistoryDisplay {
+                name: entry.migration_name,
+                activity: entry.activity,
+                status,
+                created_at: entry.created_at,
+                duration: format!("{:.2}s", entry.execution_time),
+                checksum: short_hash(entry.checksum),
+                pin_hash: short_hash(entry.pin_hash),
+                created_by: or_dash(entry.created_by),
+                description: or_dash(entry.description),
+            }
+        })
+        .collect();
+
+    let mut table = Table::new(display_rows);
+    table.with(Style::sharp());
+    println!("\n{}\n", table);
+
+    Ok(Outcome::Success)
+}
+
+async fn export_status(config: &Config, path: &str) -> Result<Outcome> {
+    let export =
+        StatusExport::from_history(super::DEFAULT_NAMESPACE, migration_history(config).await?);
+    export.write(path)?;
+    status!(
+        "Exported {} applied migration{} to {}",
+        export.migrations.len(),
+        if export.migrations.len() == 1 {
+            ""
+        } else {
+            "s"
+        },
+        path
+    );
+    Ok(Outcome::Success)
+}
+
+/// The styled status of a migration, such as `✓ Applied`, based on whether
+/// it was applied and how.
+pub(crate) fn status_label(row: &MigrationStatusRow) -> String {
+    let status = match (
+        row.exists_in_db,
+        row.last_status,
+        row.last_activity.as_deref(),
+    ) {
+        (true, Some(EngineStatus::Success), Some("APPLY")) => {
+            style("✓ Applied").green().to_string()
+        }
+        (true, Some(EngineStatus::Success), Some("ADOPT")) => style("⊙ Adopted").cyan().to_string(),
+        (true, Some(EngineStatus::Attempted), _) => style("⚠ Attempted").yellow().to_string(),
+        (true, Some(EngineStatus::Failure), _) => style("✗ Failed").red().to_string(),
+        (false, _, _) => style("○ Pending").dim().to_string(),
+        _ => style("-").dim().to_string(),
+    };
+    if row.archived {
+        format!("{} {}", status, style("(archived)").dim())
+    } else {
+        status
     }
 }
 
 impl Command for MigrationStatus {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
-        let status_rows =
+        if let Some(path) = &self.export {
+            return export_status(config, path).await;
+        }
+        if self.history {
+            return print_history(config, self.json).await;
+        }
+
+        let all_rows =
             get_combined_migration_status(config, Some(super::DEFAULT_NAMESPACE)).await?;
+        // --strict fails on any pending migration, shown or not.
+        let pending = all_rows
+            .iter()
+            .filter(|row| row.exists_in_filesystem && row.last_status.is_none())
+            .count();
+        let hidden = if self.filter.include_archived {
+            0
+        } else {
+            all_rows.iter().filter(|row| row.archived).count()
+        };
+        let total = all_rows.len();
+        let status_rows = self.filter.apply(all_rows);
 
-        if status_rows.is_empty() {
-            println!("No migrations found");
+        if status_rows.is_empty() && !self.json {
+            if total > hidden {
+                println!("No migrations match the filters");
+            } else {
+                println!("No migrations found");
+            }
             return Ok(Outcome::Success);
         }
 
-        let display_rows: Vec<MigrationStatusDisplay> = status_rows
-            .into_iter()
-            .map(|row| {
-                let on_filesystem = if row.exists_in_filesystem {
-                    style("✓").green().to_string()
-                } else {
-                    style("✗").red().to_string()
-                };
-
-                let pinned = if row.is_pinned {
-                    style("✓").green().to_string()
-                } else {
-                    style("✗").red().to_string()
-                };
-
-                let in_database = if row.exists_in_db {
-                    style("✓").green().to_string()
-                } else {
-                    style("✗").red().to_string()
-                };
-
-                // Determine status display based on whether it's applied and how
-                let status = match (
-                    row.exists_in_db,
-                    row.last_status,
-                    row.last_activity.as_deref(),
-                ) {
-                    (true, Some(EngineStatus::Success), Some("APPLY")) => {
-                        style("✓ Applied").green().to_string()
-                    }
-                    (true, Some(EngineStatus::Success), Some("ADOPT")) => {
-                        style("⊙ Adopted").cyan().to_string()
-                    }
-                    (true, Some(EngineStatus::Attempted), _) => {
-                        style("⚠ Attempted").yellow().to_string()
-                    }
-                    (true, Some(EngineStatus::Failure), _) => style("✗ Failed").red().to_string(),
-                    (false, _, _) => style("○ Pending").dim().to_string(),
-                    _ => style("-").dim().to_string(),
-                };
-
-                MigrationStatusDisplay {
-                    name: row.migration_name,
-                    on_filesystem,
-                    pinned,
-                    in_database,
-                    status,
-                }
-            })
-            .collect();
-
-        let mut table = Table::new(display_rows);
-        table.with(Style::sharp());
-        println!("\n{}\n", table);
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&status_rows)?);
+        } else if self.compact {
+            let width = status_rows
+                .iter()
+                .map(|row| row.migration_name.len())
+                .max()
+                .unwrap_or_default();
+            for row in &status_rows {
+                println!(
+                    "{:width$}  {}",
+                    row.migration_name,
+                    status_label(row),
+                    width = width
+                );
+            }
+        } else {
+            print_status_table(status_rows);
+        }
+        if hidden > 0 {
+            status!(
+                "{} archived migration{} not shown. Use --archived to include {}.",
+                hidden,
+                if hidden == 1 { "" } else { "s" },
+                if hidden == 1 { "it" } else { "them" }
+            );
+        }
+
+        if config.strict && pending > 0 {
+            return Ok(Outcome::PendingMigrations(pending));
+        }
 
         Ok(Outcome::Success)
     }
 }
+
+/// Prints the rows as the full status table.
+fn print_status_table(status_rows: Vec<MigrationStatusRow>) {
+    let display_rows: Vec<MigrationStatusDisplay> = status_rows
+        .into_iter()
+        .map(|row| {
+            let on_filesystem = if row.exists_in_filesystem {
+                style("✓").green().to_string()
+            } else {
+                style("✗").red().to_string()
+            };
+
+            let pinned = if row.is_pinned {
+                style("✓").green().to_string()
+            } else {
+                style("✗").red().to_string()
+            };
+
+            let in_database = if row.exists_in_db {
+                style("✓").green().to_string()
+            } else {
+                style("✗").red().to_string()
+            };
+
+            let status = status_label(&row);
+
+            MigrationStatusDisplay {
+                name: row.migration_name,
+                on_filesystem,
+                pinned,
+                in_database,
+                status,
+            }
+        })
+        .collect();
+
+    let mut table = Table::new(display_rows);
+    table.with(Style::sharp());
+    println!("\n{}\n", table);
+}
@@ -0,0 +1,54 @@
+use crate::agent::{AgentQueue, ApplyRequest};
+use crate::commands::migration::render_migration;
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::policy::enforce_rules;
+use crate::signing::load_signing_key;
+use crate::status;
+use crate::variables::Variables;
+use anyhow::Result;
+
+/// Renders a migration and signs it as an apply request for `spawn agent`,
+/// which applies it from beside the database.
+pub struct SubmitMigration {
+    pub migration: String,
+    pub pinned: bool,
+    pub variables: Option<Variables>,
+    /// Print the request instead of adding it to the queue, to pipe it to
+    /// `spawn agent --stdin`
+    pub stdout: bool,
+}
+
+impl TelemetryDescribe for SubmitMigration {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("migration submit").with_properties(vec![
+            ("opt_pinned", self.pinned.to_string()),
+            ("has_variables", self.variables.is_some().to_string()),
+            ("opt_stdout", self.stdout.to_string()),
+        ])
+    }
+}
+
+impl Command for SubmitMigration {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let sql =
+            render_migration(config, &self.migration, self.pinned, self.variables.clone()).await?;
+        enforce_rules(config, &self.migration, &sql)?;
+        let key = load_signing_key(&config.signing)?;
+        let request = ApplyRequest::new(config, &self.migration, sql)?.sign(&key);
+
+        if self.stdout {
+            println!("{}", serde_json::to_string_pretty(&request)?);
+            return Ok(Outcome::Success);
+        }
+        let path = AgentQueue::new(config).submit(&request).await?;
+        config.audit_session.record_migration(&self.migration);
+        status!(
+            "Submitted {} for target {} as {}",
+            self.migration,
+            request.target,
+            path
+        );
+        Ok(Outcome::Success)
+    }
+}
@@ -1,17 +1,56 @@
 use crate::config::Config;
+use crate::errors::ErrorKind;
 use anyhow::Result;
 
+pub mod agent;
+pub mod audit;
 pub mod check;
+pub mod export;
+pub mod import;
 pub mod init;
+pub mod keygen;
+pub mod metrics;
 pub mod migration;
+pub mod package;
+pub mod pin;
+pub mod release;
+pub mod schema;
+pub mod scrub;
+pub mod serve;
+pub mod stats;
+pub mod system;
+pub mod telemetry;
 pub mod test;
+pub mod ui;
+pub mod vars;
+pub mod workspace;
 
+pub use agent::Agent;
+pub use audit::AuditShow;
 pub use check::Check;
+pub use export::Export;
+pub use import::Import;
 pub use init::Init;
+pub use keygen::Keygen;
+pub use metrics::Metrics;
 pub use migration::{
-    AdoptMigration, ApplyMigration, BuildMigration, MigrationStatus, NewMigration, PinMigration,
+    AdoptMigration, ApplyMigration, ArchiveMigrations, BuildAllMigrations, BuildMigration,
+    CheckBuiltMigrations, ExplainMigration, MigrationDiffEnvs, MigrationGate, MigrationLocks,
+    MigrationStatus, NewMigration, PinMigration, SubmitMigration,
 };
-pub use test::{BuildTest, CompareTests, ExpectTest, NewTest, RunTest};
+pub use package::{PackageList, PackageUpdate};
+pub use pin::{PinDiff, PinExport, PinImport, PinStats, PinTag, PinTags};
+pub use release::ReleaseApply;
+pub use schema::SchemaDocs;
+pub use scrub::Scrub;
+pub use serve::Serve;
+pub use stats::Stats;
+pub use system::SystemUpgrade;
+pub use telemetry::{TelemetryDisable, TelemetryStatus};
+pub use test::{BuildTest, CompareTests, ExpectTest, NewTest, RecordTest, RunTest, TestCoverage};
+pub use ui::Ui;
+pub use vars::VarsShow;
+pub use workspace::WorkspaceStatus;
 
 /// Telemetry information for a command.
 #[derive(Debug, Clone, Default)]
@@ -53,13 +92,68 @@ pub trait Command: TelemetryDescribe {
 }
 
 pub enum Outcome {
+    /// The user declined to go ahead, or aborted part way through
+    Aborted,
     AdoptedMigration,
     AppliedMigrations,
-    BuiltMigration { content: String, pinned_warn: bool },
+    BuiltMigration {
+        content: String,
+        pinned_warn: bool,
+    },
     CheckFailed,
+    /// Applied migrations differ from what the project would apply now
+    DriftDetected,
     NewMigration(String),
     NewTest(String),
-    PinnedMigration { hash: String },
+    /// Migrations are waiting to be applied (reported with `--strict`)
+    PendingMigrations(usize),
+    PinnedMigration {
+        hash: String,
+    },
     Success,
+    /// One or more tests did not produce their expected output
+    TestsFailed,
     Unimplemented,
 }
+
+/// Process exit codes, so that scripts can tell failure classes apart.
+/// Code 2 is left to clap, which uses it for invalid arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Any error without a more specific code
+    Error = 1,
+    CheckFailed = 3,
+    PendingMigrations = 4,
+    DriftDetected = 5,
+    TestsFailed = 6,
+    /// The database could not be reached
+    ConnectionFailed = 7,
+    /// Stopped with Ctrl-C (128 + SIGINT)
+    Interrupted = 130,
+}
+
+impl Outcome {
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Outcome::Aborted => ExitCode::Error,
+            Outcome::CheckFailed => ExitCode::CheckFailed,
+            Outcome::DriftDetected => ExitCode::DriftDetected,
+            Outcome::PendingMigrations(_) => ExitCode::PendingMigrations,
+            Outcome::TestsFailed => ExitCode::TestsFailed,
+            _ => ExitCode::Success,
+        }
+    }
+}
+
+impl ExitCode {
+    /// The exit code for a failed command. Failures to reach the database
+    /// are told apart from other errors.
+    pub fn for_error(error: &anyhow::Error) -> ExitCode {
+        match ErrorKind::classify(error) {
+            ErrorKind::DbConnection => ExitCode::ConnectionFailed,
+            ErrorKind::Interrupted => ExitCode::Interrupted,
+            _ => ExitCode::Error,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::packages::{update_package, vendored_hash, PackagesLock, PACKAGES_LOCK_FILE};
+use crate::status;
+use anyhow::{anyhow, Result};
+use console::style;
+use serde::Serialize;
+use tabled::settings::Style;
+use tabled::{Table, Tabled};
+
+/// Fetches packages into `vendor/` and records them in `packages.lock`.
+pub struct PackageUpdate {
+    /// Packages to update. Empty updates every package.
+    pub packages: Vec<String>,
+}
+
+impl TelemetryDescribe for PackageUpdate {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("package update")
+            .with_properties(vec![("opt_all", self.packages.is_empty().to_string())])
+    }
+}
+
+impl Command for PackageUpdate {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        if config.packages.is_empty() {
+            return Err(anyhow!("no [packages] are declared in spawn.toml"));
+        }
+        for name in &self.packages {
+            if !config.packages.contains_key(name) {
+                return Err(anyhow!("no package named '{}' in spawn.toml", name));
+            }
+        }
+
+        let mut lock = PackagesLock::load(config).await?;
+        if self.packages.is_empty() {
+            lock.packages
+                .retain(|name, _| config.packages.contains_key(name));
+        }
+        for (name, package) in &config.packages {
+            if !self.packages.is_empty() && !self.packages.contains(name) {
+                continue;
+            }
+            let locked = update_package(config, name, package).await?;
+            status!("Updated {} to {}", name, locked.hash);
+            lock.packages.insert(name.clone(), locked);
+            // Save as we go, so the lock matches vendor/ if a later package
+            // fails.
+            lock.save(config).await?;
+        }
+
+        Ok(Outcome::Success)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageState {
+    /// Vendored as recorded in the lock file
+    Ok,
+    /// Not yet fetched with `spawn package update`
+    Missing,
+    /// The vendored files no longer match the lock file
+    Modified,
+    /// The lock file does not match the source or `hash` in spawn.toml
+    Outdated,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageStatus {
+    pub package: String,
+    pub source: String,
+    pub commit: Option<String>,
+    /// Hash recorded in the lock file
+    pub hash: Option<String>,
+    pub state: PackageState,
+}
+
+#[derive(Tabled)]
+struct PackageStatusDisplay {
+    #[tabled(rename = "Package")]
+    package: String,
+    #[tabled(rename = "Source")]
+    source: String,
+    #[tabled(rename = "Commit")]
+    commit: String,
+    #[tabled(rename = "Hash")]
+    hash: String,
+    #[tabled(rename = "Status")]
+    status: String,
+}
+
+/// The state of each package declared in spawn.toml, in name order.
+pub async fn package_status(config: &Config) -> Result<Vec<PackageStatus>> {
+    let lock = PackagesLock::load(config).await?;
+    let mut statuses = Vec::new();
+    for (name, package) in &config.packages {
+        let locked = lock.packages.get(name);
+        let vendored = vendored_hash(config, name).await?;
+        let state = match (locked, vendored) {
+            (None, _) | (_, None) => PackageState::Missing,
+            (Some(locked), Some(vendored)) if locked.hash != vendored => PackageState::Modified,
+            (Some(locked), _)
+                if package.hash.as_ref().is_some_and(|h| *h != locked.hash)
+                    || locked.source != package.source()? =>
+            {
+                PackageState::Outdated
+            }
+            _ => PackageState::Ok,
+        };
+        statuses.push(PackageStatus {
+            package: name.clone(),
+            source: package.source()?,
+            commit: locked.and_then(|l| l.commit.clone()),
+            hash: locked.map(|l| l.hash.clone()),
+            state,
+        });
+    }
+    Ok(statuses)
+}
+
+/// Lists the packages declared in spawn.toml and whether each is vendored
+/// as its lock file says.
+pub struct PackageList {
+    pub json: bool,
+}
+
+impl TelemetryDescribe for PackageList {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("package list")
+            .with_properties(vec![("opt_json", self.json.to_string())])
+    }
+}
+
+impl Command for PackageList {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let statuses = package_status(config).await?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&statuses)?);
+        } else if statuses.is_empty() {
+            println!("No packages declared");
+        } else {
+            let display_rows: Vec<PackageStatusDisplay> = statuses
+                .iter()
+                .map(|s| PackageStatusDisplay {
+                    package: s.package.clone(),
+                    source: s.source.clone(),
+                    commit: s
+                        .commit
+                        .as_deref()
+                        .map(|c| c[..12.min(c.len())].to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    hash: s.hash.clone().unwrap_or_else(|| "-".to_string()),
+                    status: match s.state {
+                        PackageState::Ok => style("✓ Ok").green().to_string(),
+                        PackageState::Missing => style("○ Missing").dim().to_string(),
+                        PackageState::Modified => style("✗ Modified").red().to_string(),
+                        PackageState::Outdated => style("○ Outdated").yellow().to_string(),
+                    },
+                })
+                .collect();
+            let mut table = Table::new(display_rows);
+            table.with(Style::sharp());
+            println!("\n{}\n", table);
+        }
+
+        let stale = statuses
+            .iter()
+            .filter(|s| s.state != PackageState::Ok)
+            .count();
+        if stale > 0 {
+            config.events.on_warning(&format!(
+                "{} package(s) do not match {}, run `spawn package update`",
+                stale, PACKAGES_LOCK_FILE
+            ));
+            if config.strict {
+                return Ok(Outcome::CheckFailed);
+            }
+        }
+        Ok(Outcome::Success)
+    }
+}
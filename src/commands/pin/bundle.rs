@@ -0,0 +1,293 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::pinfile::LockData;
+use crate::status;
+use crate::store::pinner::{hash_to_path, reachable_objects, read_hash_bytes};
+use anyhow::{anyhow, Context, Result};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use twox_hash::xxhash3_128;
+
+/// Name of the manifest describing the bundle, always the first entry.
+const MANIFEST_NAME: &str = "spawn-bundle.toml";
+/// Folder in the archive holding the migration's own files.
+const MIGRATION_PREFIX: &str = "migration/";
+/// Folder in the archive holding pinned store objects, laid out as in the
+/// store itself.
+const PINNED_PREFIX: &str = "pinned/";
+
+pub struct PinExport {
+    pub migration: String,
+    /// Path of the archive to write
+    pub out: String,
+}
+
+impl TelemetryDescribe for PinExport {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("pin export")
+    }
+}
+
+pub struct PinImport {
+    /// Path of the archive to read
+    pub bundle: String,
+}
+
+impl TelemetryDescribe for PinImport {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("pin import")
+    }
+}
+
+/// Describes what a bundle contains.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BundleManifest {
+    pub migration: String,
+    /// Root hash of the pinned components, as in the migration's `lock.toml`
+    pub pin: String,
+    /// Root hash of the pinned files kept in the migration folder, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
+    /// Number of pinned store objects in the bundle
+    pub objects: usize,
+}
+
+/// Write a tar archive holding a pinned migration's folder (including its
+/// `lock.toml`) and every store object its pins refer to, so that the exact
+/// migration can be restored elsewhere with [`import_bundle`].
+pub async fn export_bundle<W: Write>(
+    config: &Config,
+    migration: &str,
+    out: W,
+) -> Result<BundleManifest> {
+    let op = config.operator();
+    let pather = config.pather();
+    require_migration_folders(config)?;
+    let lock = config
+        .load_lock_file(&pather.migration_lock_file_path(migration))
+        .await
+        .context("could not load lock file, has this migration been pinned?")?;
+
+    let pinned_folder = pather.pinned_folder();
+    let mut roots = vec![lock.pin.clone()];
+    roots.extend(lock.local.clone());
+    let mut objects: Vec<String> = reachable_objects(op, &pinned_folder, &roots)
+        .await?
+        .objects
+        .into_iter()
+        .collect();
+    objects.sort();
+
+    let manifest = BundleManifest {
+        migration: migration.to_string(),
+        pin: lock.pin,
+        local: lock.local,
+        objects: objects.len(),
+    };
+
+    let mut builder = tar::Builder::new(out);
+    append_file(
+        &mut builder,
+        MANIFEST_NAME,
+        toml::to_string_pretty(&manifest)?.as_bytes(),
+    )?;
+
+    for (name, contents) in read_folder(config, &pather.migration_folder(migration)).await? {
+        append_file(
+            &mut builder,
+            &format!("{}{}", MIGRATION_PREFIX, name),
+            &contents,
+        )?;
+    }
+
+    for hash in &objects {
+        let contents = read_hash_bytes(op, &pinned_folder, hash)
+            .await
+            .context(format!("could not read pinned object {}", hash))?;
+        append_file(
+            &mut builder,
+            &format!("{}{}", PINNED_PREFIX, hash_to_path(hash)?),
+            &contents,
+        )?;
+    }
+
+    builder.into_inner()?.flush()?;
+
+    Ok(manifest)
+}
+
+/// Restore a bundle written by [`export_bundle`]: store objects are added to
+/// the pinned store and the migration folder is recreated. Importing the
+/// same bundle twice is harmless, but a migration that already exists with a
+/// different pin is left untouched and an error returned.
+pub async fn import_bundle<R: Read>(config: &Config, bundle: R) -> Result<BundleManifest> {
+    let op = config.operator();
+    let pather = config.pather();
+    require_migration_folders(config)?;
+
+    let mut manifest: Option<BundleManifest> = None;
+    let mut files = BTreeMap::new();
+    let mut objects = BTreeMap::new();
+
+    let mut archive = tar::Archive::new(bundle);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+
+        if path == MANIFEST_NAME {
+            manifest = Some(toml::from_str(std::str::from_utf8(&contents)?)?);
+        } else if let Some(name) = path.strip_prefix(MIGRATION_PREFIX) {
+            if name.is_empty() || name.split('/').any(|s| s == ".." || s.is_empty()) {
+                return Err(anyhow!("invalid file '{}' in bundle", path));
+            }
+            files.insert(name.to_string(), contents);
+        } else if let Some(object) = path.strip_prefix(PINNED_PREFIX) {
+            let hash = object.replace('/', "");
+            let actual = format!("{:032x}", xxhash3_128::Hasher::oneshot(&contents));
+            if hash != actual || hash_to_path(&hash)? != object {
+                return Err(anyhow!("pinned object '{}' in bundle is corrupt", object));
+            }
+            objects.insert(hash, contents);
+        } else {
+            return Err(anyhow!("unexpected file '{}' in bundle", path));
+        }
+    }
+
+    let manifest = manifest.ok_or(anyhow!("bundle has no {}", MANIFEST_NAME))?;
+    if manifest.migration.is_empty()
+        || manifest.migration.contains('/')
+        || manifest.migration.contains("..")
+    {
+        return Err(anyhow!("invalid migration name '{}'", manifest.migration));
+    }
+    let lock_file = pather.layout.lock_file();
+    let bundled_lock: LockData = toml::from_str(std::str::from_utf8(
+        files
+            .get(lock_file)
+            .ok_or(anyhow!("bundle has no {}", lock_file))?,
+    )?)?;
+    if bundled_lock.pin != manifest.pin || bundled_lock.local != manifest.local {
+        return Err(anyhow!("bundle lock.toml does not match its manifest"));
+    }
+
+    let lock_file_path = pather.migration_lock_file_path(&manifest.migration);
+    if op.exists(&lock_file_path).await? {
+        let existing = config.load_lock_file(&lock_file_path).await?;
+        if existing.pin != manifest.pin || existing.local != manifest.local {
+            return Err(anyhow!(
+                "migration {} already exists with a different pin",
+                manifest.migration
+            ));
+        }
+    }
+
+    let pinned_folder = pather.pinned_folder();
+    for (hash, contents) in objects {
+        let path = format!("{}/{}", pinned_folder, hash_to_path(&hash)?);
+        if !op.exists(&path).await? {
+            op.write(&path, contents).await?;
+        }
+    }
+
+    // Make sure everything the migration refers to is now in the store
+    // before writing the migration itself.
+    let mut roots = vec![manifest.pin.clone()];
+    roots.extend(manifest.local.clone());
+    reachable_objects(op, &pinned_folder, &roots)
+        .await
+        .context("bundle is missing pinned objects")?;
+
+    let migration_folder = pather.migration_folder(&manifest.migration);
+    for (name, contents) in files {
+        op.write(&format!("{}/{}", migration_folder, name), contents)
+            .await?;
+    }
+
+    Ok(manifest)
+}
+
+/// Bundles carry a migration's whole folder, which the flat layout does not
+/// have.
+fn require_migration_folders(config: &Config) -> Result<()> {
+    if config.layout.flat {
+        return Err(anyhow!(
+            "bundles need a folder per migration, but layout.flat is set"
+        ));
+    }
+    Ok(())
+}
+
+/// Reads every file beneath `folder`, keyed by path relative to it.
+async fn read_folder(config: &Config, folder: &str) -> Result<BTreeMap<String, Vec<u8>>> {
+    let op = config.operator();
+    // opendal strips leading "./" and "/" from listed paths.
+    let normalized = folder.trim_start_matches("./").trim_start_matches('/');
+    let prefix = format!("{}/", normalized.trim_end_matches('/'));
+
+    let mut lister = op.lister_with(&prefix).recursive(true).await?;
+    let mut files = BTreeMap::new();
+    while let Some(entry) = lister.try_next().await? {
+        let Some(name) = entry.path().strip_prefix(&prefix) else {
+            continue;
+        };
+        if name.is_empty() || name.ends_with('/') {
+            continue;
+        }
+        let contents = op.read(entry.path()).await?.to_vec();
+        files.insert(name.to_string(), contents);
+    }
+
+    Ok(files)
+}
+
+/// Appends a file with fixed metadata, so that exporting the same migration
+/// twice produces identical archives.
+fn append_file<W: Write>(builder: &mut tar::Builder<W>, path: &str, contents: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    builder
+        .append_data(&mut header, path, contents)
+        .context(format!("could not add {} to bundle", path))
+}
+
+impl Command for PinExport {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let file = std::fs::File::create(&self.out)
+            .context(format!("could not create bundle {}", self.out))?;
+        let manifest =
+            export_bundle(config, &self.migration, std::io::BufWriter::new(file)).await?;
+
+        status!(
+            "Exported {} ({} pinned objects) to {}",
+            manifest.migration,
+            manifest.objects,
+            self.out
+        );
+
+        Ok(Outcome::Success)
+    }
+}
+
+impl Command for PinImport {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let file = std::fs::File::open(&self.bundle)
+            .context(format!("could not open bundle {}", self.bundle))?;
+        let manifest = import_bundle(config, std::io::BufReader::new(file)).await?;
+
+        status!(
+            "Imported {} ({} pinned objects) from {}",
+            manifest.migration,
+            manifest.objects,
+            self.bundle
+        );
+
+        Ok(Outcome::Success)
+    }
+}
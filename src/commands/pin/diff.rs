@@ -0,0 +1,182 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::packages::PACKAGE_PREFIX;
+use crate::store::pinner::{live_file_hashes, pinned_file_hashes, read_hash_bytes};
+use anyhow::{Context, Result};
+use console::style;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+
+/// Shows what re-pinning a migration would change, without writing
+/// anything.
+pub struct PinDiff {
+    pub migration: String,
+}
+
+impl TelemetryDescribe for PinDiff {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("pin diff")
+    }
+}
+
+/// A difference between a migration's pinned files and the current files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinChange {
+    Added(String),
+    Removed(String),
+    /// The file changed. `diff` is a unified diff of the contents, or None
+    /// when either side is not valid UTF-8.
+    Changed {
+        name: String,
+        diff: Option<String>,
+    },
+}
+
+/// Compare the files pinned in a migration's `lock.toml` against the current
+/// components (and files in the migration folder), returning what re-pinning
+/// would change. Files kept alongside the migration are prefixed with `./`.
+pub async fn pin_diff(config: &Config, migration: &str) -> Result<Vec<PinChange>> {
+    let op = config.operator();
+    let pather = config.pather();
+    let lock = config
+        .load_lock_file(&pather.migration_lock_file_path(migration))
+        .await
+        .context("could not load lock file, has this migration been pinned?")?;
+    let pinned_folder = pather.pinned_folder();
+
+    let mut pinned = pinned_file_hashes(op, &pinned_folder, &lock.pin).await?;
+    let mut live = live_file_hashes(op, &pather.components_folder(), &[]).await?;
+    let packages = live_file_hashes(op, &pather.vendor_folder(), &[]).await?;
+    live.extend(
+        packages
+            .into_iter()
+            .map(|(name, hash)| (format!("{}{}", PACKAGE_PREFIX, name), hash)),
+    );
+
+    if let Some(local) = &lock.local {
+        let local_pinned = pinned_file_hashes(op, &pinned_folder, local).await?;
+        pinned.extend(prefix_local(local_pinned));
+    }
+    let local_live = live_file_hashes(
+        op,
+        &pather.migration_folder(migration),
+        &pather.migration_folder_excludes(),
+    )
+    .await?;
+    live.extend(prefix_local(local_live));
+
+    let mut changes = Vec::new();
+    for (name, pinned_hash) in &pinned {
+        match live.get(name) {
+            None => changes.push(PinChange::Removed(name.clone())),
+            Some(live_hash) if live_hash != pinned_hash => {
+                let old = read_hash_bytes(op, &pinned_folder, pinned_hash).await?;
+                let new = read_live_file(config, migration, name).await?;
+                changes.push(PinChange::Changed {
+                    name: name.clone(),
+                    diff: text_diff(&old, &new),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for name in live.keys() {
+        if !pinned.contains_key(name) {
+            changes.push(PinChange::Added(name.clone()));
+        }
+    }
+    changes.sort_by(|a, b| change_name(a).cmp(change_name(b)));
+
+    Ok(changes)
+}
+
+fn prefix_local(files: BTreeMap<String, String>) -> BTreeMap<String, String> {
+    files
+        .into_iter()
+        .map(|(name, hash)| (format!("./{}", name), hash))
+        .collect()
+}
+
+fn change_name(change: &PinChange) -> &str {
+    match change {
+        PinChange::Added(name) | PinChange::Removed(name) => name,
+        PinChange::Changed { name, .. } => name,
+    }
+}
+
+async fn read_live_file(config: &Config, migration: &str, name: &str) -> Result<Vec<u8>> {
+    let path = if let Some(local) = name.strip_prefix("./") {
+        format!("{}/{}", config.pather().migration_folder(migration), local)
+    } else if let Some(package_file) = name.strip_prefix(PACKAGE_PREFIX) {
+        format!("{}/{}", config.pather().vendor_folder(), package_file)
+    } else {
+        format!("{}/{}", config.pather().components_folder(), name)
+    };
+    Ok(config.operator().read(&path).await?.to_bytes().to_vec())
+}
+
+fn text_diff(old: &[u8], new: &[u8]) -> Option<String> {
+    let old = std::str::from_utf8(old).ok()?;
+    let new = std::str::from_utf8(new).ok()?;
+    Some(
+        TextDiff::from_lines(old, new)
+            .unified_diff()
+            .context_radius(3)
+            .header("pinned", "current")
+            .to_string(),
+    )
+}
+
+fn print_pin_diff(migration: &str, changes: &[PinChange]) {
+    if changes.is_empty() {
+        println!("{} is pinned to the current components.", migration);
+        return;
+    }
+
+    for change in changes {
+        match change {
+            PinChange::Added(name) => println!("{} {}", style("added:  ").green(), name),
+            PinChange::Removed(name) => println!("{} {}", style("removed:").red(), name),
+            PinChange::Changed { name, diff } => {
+                println!("{} {}", style("changed:").yellow(), name);
+                match diff {
+                    Some(diff) => {
+                        for line in diff.lines() {
+                            let styled = match line.chars().next() {
+                                Some('+') if !line.starts_with("+++") => style(line).green(),
+                                Some('-') if !line.starts_with("---") => style(line).red(),
+                                _ => style(line).dim(),
+                            };
+                            println!("    {}", styled);
+                        }
+                    }
+                    None => println!("    (binary files differ)"),
+                }
+            }
+        }
+    }
+
+    let added = changes
+        .iter()
+        .filter(|c| matches!(c, PinChange::Added(_)))
+        .count();
+    let removed = changes
+        .iter()
+        .filter(|c| matches!(c, PinChange::Removed(_)))
+        .count();
+    println!();
+    println!(
+        "Re-pinning would add {}, remove {} and change {} file(s).",
+        added,
+        removed,
+        changes.len() - added - removed
+    );
+}
+
+impl Command for PinDiff {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let changes = pin_diff(config, &self.migration).await?;
+        print_pin_diff(&self.migration, &changes);
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,9 @@
+mod bundle;
+mod diff;
+mod stats;
+mod tag;
+
+pub use bundle::{export_bundle, import_bundle, BundleManifest, PinExport, PinImport};
+pub use diff::{pin_diff, PinChange, PinDiff};
+pub use stats::{collect_pin_stats, PinStats, PinStoreStats};
+pub use tag::{tag_components, PinTag, PinTags};
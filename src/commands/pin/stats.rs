@@ -0,0 +1,138 @@
+use crate::commands::stats::format_bytes;
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::store::pinner::refs::{list_refs, REFS_FOLDER};
+use crate::store::pinner::{reachable_objects, BlobSize};
+use crate::store::{folder_usage, list_migration_fs_status};
+use anyhow::{Context, Result};
+use console::style;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Number of blobs listed in the largest blobs section.
+const LARGEST_BLOBS: usize = 10;
+
+pub struct PinStats {
+    /// Print the statistics as JSON instead of a human readable summary
+    pub json: bool,
+}
+
+impl TelemetryDescribe for PinStats {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("pin stats").with_properties(vec![("opt_json", self.json.to_string())])
+    }
+}
+
+/// Statistics about the content-addressed pinned store.
+#[derive(Debug, Default, Serialize)]
+pub struct PinStoreStats {
+    /// Objects (trees and blobs) in the store
+    pub objects: u64,
+    /// Total size of the store, in bytes
+    pub bytes: u64,
+    /// Distinct roots referenced by migration lock files or tags
+    pub roots: u64,
+    /// Objects reachable from a root
+    pub referenced_objects: u64,
+    /// Objects no lock file refers to, which garbage collection could remove
+    pub unreferenced_objects: u64,
+    /// Bytes the pinned files would take up without deduplication
+    pub logical_bytes: u64,
+    /// Bytes taken up by the distinct blobs those files are stored as
+    pub blob_bytes: u64,
+    /// logical_bytes / blob_bytes. 1.0 means nothing is shared.
+    pub dedup_ratio: f64,
+    pub largest_blobs: Vec<BlobSize>,
+}
+
+/// Gather statistics for the pinned store, following the roots referenced
+/// by every migration's `lock.toml` and by every tag.
+pub async fn collect_pin_stats(config: &Config) -> Result<PinStoreStats> {
+    let op = config.operator();
+    let pather = config.pather();
+    let pinned_folder = pather.pinned_folder();
+
+    let mut usage = folder_usage(op, &pinned_folder).await?;
+    // Tags are kept in the store, but are not objects.
+    let refs_usage = folder_usage(op, &format!("{}/{}", pinned_folder, REFS_FOLDER)).await?;
+    usage.files -= refs_usage.files;
+    usage.bytes -= refs_usage.bytes;
+
+    let mut roots = BTreeSet::new();
+    for (name, status) in list_migration_fs_status(op, &pather, None).await? {
+        if !status.has_lock_toml {
+            continue;
+        }
+        let lock = config
+            .load_lock_file(&pather.migration_lock_file_path(&name))
+            .await
+            .context(format!("could not load lock file for {}", name))?;
+        roots.insert(lock.pin);
+        roots.extend(lock.local);
+    }
+    roots.extend(list_refs(op, &pinned_folder).await?.into_values());
+    let roots: Vec<String> = roots.into_iter().collect();
+
+    let reachable = reachable_objects(op, &pinned_folder, &roots).await?;
+    let blob_bytes: u64 = reachable.blobs.iter().map(|b| b.bytes).sum();
+    let referenced_objects = reachable.objects.len() as u64;
+
+    Ok(PinStoreStats {
+        objects: usage.files,
+        bytes: usage.bytes,
+        roots: roots.len() as u64,
+        referenced_objects,
+        unreferenced_objects: usage.files.saturating_sub(referenced_objects),
+        logical_bytes: reachable.logical_bytes,
+        blob_bytes,
+        dedup_ratio: if blob_bytes > 0 {
+            reachable.logical_bytes as f64 / blob_bytes as f64
+        } else {
+            1.0
+        },
+        largest_blobs: reachable.blobs.into_iter().take(LARGEST_BLOBS).collect(),
+    })
+}
+
+fn print_pin_stats(stats: &PinStoreStats) {
+    println!();
+    println!("{}", style("Pinned store").bold());
+    println!("  Objects:      {}", stats.objects);
+    println!("  Size:         {}", format_bytes(stats.bytes));
+    println!("  Roots:        {}", stats.roots);
+    println!("  Referenced:   {}", stats.referenced_objects);
+    println!("  Unreferenced: {}", stats.unreferenced_objects);
+    println!();
+    println!("{}", style("Deduplication").bold());
+    println!("  Pinned files: {}", format_bytes(stats.logical_bytes));
+    println!("  Stored blobs: {}", format_bytes(stats.blob_bytes));
+    println!("  Ratio:        {:.2}x", stats.dedup_ratio);
+
+    if !stats.largest_blobs.is_empty() {
+        println!();
+        println!("{}", style("Largest blobs").bold());
+        for blob in &stats.largest_blobs {
+            println!(
+                "  {:>10}  {}  {}",
+                format_bytes(blob.bytes),
+                style(&blob.hash[..12.min(blob.hash.len())]).dim(),
+                blob.name
+            );
+        }
+    }
+    println!();
+}
+
+impl Command for PinStats {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let stats = collect_pin_stats(config).await?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        } else {
+            print_pin_stats(&stats);
+        }
+
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,79 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::status;
+use crate::store::pinner::refs::{list_refs, validate_tag, write_ref};
+use crate::store::pinner::spawn::Spawn;
+use crate::store::pinner::Pinner;
+use anyhow::{Context, Result};
+use tabled::builder::Builder;
+use tabled::settings::Style;
+
+/// Pins the current components and names the snapshot with a tag, so that
+/// includes can refer to it as `"<component>@<tag>"`.
+pub struct PinTag {
+    pub tag: String,
+}
+
+impl TelemetryDescribe for PinTag {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("pin tag")
+    }
+}
+
+/// Snapshots the current components into the pinned store and records `tag`
+/// as referring to the snapshot. Returns the root hash.
+pub async fn tag_components(config: &Config, tag: &str) -> Result<String> {
+    validate_tag(tag)?;
+    let pinned_folder = config.pather().pinned_folder();
+    let mut pinner = Spawn::new(pinned_folder.clone(), config.pather().components_folder())?
+        .with_packages_path(config.pather().vendor_folder());
+    let root = pinner
+        .snapshot(config.operator())
+        .await
+        .context("error calling pinner snapshot")?;
+    write_ref(config.operator(), &pinned_folder, tag, &root).await?;
+    Ok(root)
+}
+
+impl Command for PinTag {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let root = tag_components(config, &self.tag).await?;
+        println!("{}", root);
+        status!("Tagged components as {}", self.tag);
+        Ok(Outcome::Success)
+    }
+}
+
+/// Lists the tags of the pinned store.
+pub struct PinTags {
+    pub json: bool,
+}
+
+impl TelemetryDescribe for PinTags {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("pin tags").with_properties(vec![("opt_json", self.json.to_string())])
+    }
+}
+
+impl Command for PinTags {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let refs = list_refs(config.operator(), &config.pather().pinned_folder()).await?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&refs)?);
+        } else if refs.is_empty() {
+            println!("No tags found");
+        } else {
+            let mut builder = Builder::new();
+            builder.push_record(["Tag", "Root"]);
+            for (tag, root) in &refs {
+                builder.push_record([tag.as_str(), root.as_str()]);
+            }
+            let mut table = builder.build();
+            table.with(Style::sharp());
+            println!("\n{}\n", table);
+        }
+
+        Ok(Outcome::Success)
+    }
+}
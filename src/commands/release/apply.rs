@@ -0,0 +1,82 @@
+use super::{load_release, unexpected_pending};
+use crate::commands::migration::{
+    check_migration_order, confirm_migrations, confirm_protected_target, get_pending_migrations,
+    ApplyMigration,
+};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::schedule::Schedule;
+use crate::status;
+use anyhow::{anyhow, Result};
+
+pub struct ReleaseApply {
+    pub release: String,
+    pub yes: bool,
+    /// With `yes`, change a protected target without typing its name
+    pub force: bool,
+    pub retry: bool,
+    /// Apply destructive statements to a protected target without each
+    /// migration acknowledging them
+    pub allow_destructive: bool,
+}
+
+impl TelemetryDescribe for ReleaseApply {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("release apply").with_properties(vec![
+            ("opt_retry", self.retry.to_string()),
+            ("opt_allow_destructive", self.allow_destructive.to_string()),
+        ])
+    }
+}
+
+impl Command for ReleaseApply {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let release = load_release(config, &self.release).await?;
+
+        if !confirm_protected_target(config, "apply", self.yes, self.force)? {
+            return Ok(Outcome::AppliedMigrations);
+        }
+
+        let pending = get_pending_migrations(config).await?;
+
+        let unexpected = unexpected_pending(&release, &pending);
+        if !unexpected.is_empty() {
+            return Err(anyhow!(
+                "release {} would leave earlier pending migrations unapplied: {}.\n\
+                 Add them to the release or apply them first.",
+                &self.release,
+                unexpected.join(", ")
+            ));
+        }
+
+        check_migration_order(config, &release.migrations).await?;
+
+        if let Some(description) = &release.description {
+            status!("Release {}: {}", &self.release, description);
+        }
+        if !confirm_migrations(config, "apply", &release.migrations, self.yes)? {
+            return Ok(Outcome::AppliedMigrations);
+        }
+        // Releases wait for the environment's maintenance window too.
+        Schedule::default().wait(config).await?;
+
+        ApplyMigration {
+            migration: None,
+            pinned: true,
+            variables: None,
+            yes: true,
+            force: self.force,
+            retry: self.retry,
+            reuse_connection: false,
+            require_signature: false,
+            from_file: None,
+            interactive: false,
+            promote_from: None,
+            allow_destructive: self.allow_destructive,
+            schedule: Schedule::default(),
+        }
+        .apply_migrations(config, release.migrations)
+        .await?;
+        Ok(Outcome::AppliedMigrations)
+    }
+}
@@ -0,0 +1,73 @@
+mod apply;
+
+pub use apply::ReleaseApply;
+
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A release, read from `releases/<name>.toml`: the migrations that make up
+/// one deploy, in the order they are applied.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ReleaseManifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub migrations: Vec<String>,
+}
+
+/// Load and validate a release manifest. Every listed migration must exist
+/// and appear only once.
+pub async fn load_release(config: &Config, release: &str) -> Result<ReleaseManifest> {
+    let path = config.pather().release_file_path(release);
+    let contents = config
+        .operator()
+        .read(&path)
+        .await
+        .context(format!("could not read release {}", path))?
+        .to_bytes();
+    let manifest: ReleaseManifest = toml::from_str(std::str::from_utf8(&contents)?)
+        .context(format!("could not parse release {}", path))?;
+
+    if manifest.migrations.is_empty() {
+        return Err(anyhow!("release {} lists no migrations", release));
+    }
+
+    let mut seen = HashSet::new();
+    for migration in &manifest.migrations {
+        if !seen.insert(migration) {
+            return Err(anyhow!(
+                "migration {} is listed more than once in release {}",
+                migration,
+                release
+            ));
+        }
+        let script = config.pather().migration_script_file_path(migration);
+        if !config.operator().exists(&script).await? {
+            return Err(anyhow!(
+                "release {} lists migration {}, which does not exist",
+                release,
+                migration
+            ));
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Pending migrations that are not in the release but sort before its last
+/// migration. Applying the release would leave these behind, so the release
+/// is refused while any exist. Pending migrations after the release are
+/// expected, as they belong to later releases.
+pub fn unexpected_pending(release: &ReleaseManifest, pending: &[String]) -> Vec<String> {
+    let listed: HashSet<&String> = release.migrations.iter().collect();
+    let Some(last) = release.migrations.iter().max() else {
+        return Vec::new();
+    };
+
+    pending
+        .iter()
+        .filter(|name| !listed.contains(name) && *name < last)
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,47 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::schema_docs::{schema_docs, write_schema_docs};
+use crate::status;
+use anyhow::Result;
+
+/// Writes Markdown docs of the target's tables, with a Mermaid ER diagram,
+/// to the spawn folder.
+pub struct SchemaDocs {
+    /// File to write, relative to the spawn folder, instead of
+    /// `[schema_docs] output`
+    pub output: Option<String>,
+    /// Schemas to document instead of `[schema_docs] schemas`
+    pub schemas: Vec<String>,
+    /// Print the docs instead of writing them
+    pub stdout: bool,
+}
+
+impl TelemetryDescribe for SchemaDocs {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("schema docs")
+            .with_properties(vec![("opt_stdout", self.stdout.to_string())])
+    }
+}
+
+impl Command for SchemaDocs {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let schemas = if self.schemas.is_empty() {
+            config.schema_docs.schemas()
+        } else {
+            self.schemas.clone()
+        };
+        if self.stdout {
+            print!("{}", schema_docs(config, &schemas).await?);
+            return Ok(Outcome::Success);
+        }
+
+        let mut docs_config = config.schema_docs.clone();
+        if self.output.is_some() {
+            docs_config.output = self.output.clone();
+        }
+        let path = docs_config.output_path(config);
+        write_schema_docs(config, &schemas, &path).await?;
+        status!("Wrote schema docs to {}", path);
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::scrub::{load_rules, render_scrub};
+use crate::status;
+use anyhow::{anyhow, Context, Result};
+use dialoguer::Confirm;
+
+/// Anonymizes a copy of a database with the rules of [`crate::scrub`].
+pub struct Scrub {
+    /// Rules file, relative to the spawn folder, instead of `[scrub] rules`
+    pub rules: Option<String>,
+    /// Print the SQL instead of running it
+    pub print: bool,
+    pub yes: bool,
+}
+
+impl TelemetryDescribe for Scrub {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("scrub").with_properties(vec![
+            ("opt_print", self.print.to_string()),
+            ("opt_yes", self.yes.to_string()),
+        ])
+    }
+}
+
+impl Command for Scrub {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let path = config.scrub.rules_path(config, self.rules.as_deref());
+        let rules = load_rules(config, &path).await?;
+        let sql = render_scrub(&rules)?;
+        if self.print {
+            print!("{}", sql);
+            return Ok(Outcome::Success);
+        }
+
+        let target = config
+            .target
+            .as_deref()
+            .ok_or(anyhow!("no target selected"))?;
+        if config.target_config()?.protected {
+            return Err(anyhow!(
+                "target '{}' is protected, and scrubbing rewrites its data",
+                target
+            ));
+        }
+        if !config.scrub.targets.iter().any(|t| t == target) {
+            return Err(anyhow!(
+                "target '{}' is not in [scrub] targets. Scrubbing rewrites data in place, \
+                 so list the targets that hold copies to scrub",
+                target
+            ));
+        }
+        status!(
+            "Scrubbing {} table{} of target {} with {}",
+            rules.tables.len(),
+            if rules.tables.len() == 1 { "" } else { "s" },
+            target,
+            path
+        );
+        if !self.yes
+            && !Confirm::new()
+                .with_prompt("Rewrite the data of these tables?")
+                .default(false)
+                .interact()?
+        {
+            status!("Aborted.");
+            return Ok(Outcome::Success);
+        }
+
+        let engine = config.new_engine().await?;
+        engine
+            .execute_with_writer(
+                Box::new(move |writer| writer.write_all(sql.as_bytes())),
+                None,
+                false,
+            )
+            .await
+            .context("scrub failed, and was rolled back")?;
+        status!("Scrubbed target {}", target);
+        Ok(Outcome::Success)
+    }
+}
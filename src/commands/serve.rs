@@ -0,0 +1,43 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::server::{serve, SERVE_TOKEN_ENV};
+use crate::spawn::Spawn;
+use crate::status;
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Serves the HTTP API of [`crate::server`] until Ctrl-C.
+pub struct Serve {
+    /// Address to listen on, such as `127.0.0.1:7878`
+    pub listen: String,
+}
+
+impl TelemetryDescribe for Serve {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("serve")
+    }
+}
+
+impl Command for Serve {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let token = std::env::var(SERVE_TOKEN_ENV)
+            .ok()
+            .filter(|token| !token.trim().is_empty());
+        let listener = TcpListener::bind(&self.listen)
+            .await
+            .context(format!("could not listen on {}", self.listen))?;
+        status!(
+            "Serving {} on http://{}{}",
+            config.target.as_deref().unwrap_or("(no target)"),
+            listener.local_addr()?,
+            if token.is_some() {
+                ""
+            } else {
+                " (build, apply and test are disabled without SPAWN_SERVE_TOKEN)"
+            }
+        );
+        serve(Arc::new(Spawn::new(config.clone())), listener, token).await?;
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,242 @@
+use crate::commands::migration::DEFAULT_NAMESPACE;
+use crate::commands::test::list_tests;
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::engine::{MigrationActivity, MigrationHistoryEntry, MigrationStatus};
+use crate::store::{folder_usage, list_migration_fs_status};
+use anyhow::Result;
+use console::style;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+pub struct Stats {
+    /// Print the statistics as JSON instead of a human readable summary
+    pub json: bool,
+}
+
+impl TelemetryDescribe for Stats {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("stats").with_properties(vec![("opt_json", self.json.to_string())])
+    }
+}
+
+/// Aggregate statistics for a spawn project.
+#[derive(Debug, Default, Serialize)]
+pub struct ProjectStats {
+    pub migrations: MigrationCounts,
+    pub components: u64,
+    pub tests: u64,
+    /// Average size of a migration's up.sql template, in bytes
+    pub average_migration_bytes: u64,
+    pub pinned_store: PinnedStoreStats,
+    /// Statistics from the target database. None when the database could
+    /// not be reached.
+    pub database: Option<DatabaseStats>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationCounts {
+    pub total: u64,
+    pub pinned: u64,
+    /// Only populated when the database is reachable
+    pub applied: Option<u64>,
+    /// Only populated when the database is reachable
+    pub pending: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PinnedStoreStats {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DatabaseStats {
+    pub successful_applies: u64,
+    pub failed_applies: u64,
+    pub average_apply_seconds: f64,
+    pub max_apply_seconds: f64,
+    /// Average successful apply duration per month (YYYY-MM), oldest first
+    pub apply_seconds_by_month: BTreeMap<String, f64>,
+}
+
+impl DatabaseStats {
+    fn from_history(history: &[MigrationHistoryEntry]) -> Self {
+        let mut stats = DatabaseStats::default();
+        let mut by_month: BTreeMap<String, (f64, u64)> = BTreeMap::new();
+        let mut total_seconds = 0.0;
+
+        for entry in history
+            .iter()
+            .filter(|e| e.activity == MigrationActivity::Apply.as_str())
+        {
+            match entry.status {
+                Some(MigrationStatus::Success) => {
+                    stats.successful_applies += 1;
+                    total_seconds += entry.execution_time;
+                    stats.max_apply_seconds = stats.max_apply_seconds.max(entry.execution_time);
+                    let month = entry.created_at.get(..7).unwrap_or("unknown").to_string();
+                    let slot = by_month.entry(month).or_insert((0.0, 0));
+                    slot.0 += entry.execution_time;
+                    slot.1 += 1;
+                }
+                Some(MigrationStatus::Failure) => stats.failed_applies += 1,
+                _ => {}
+            }
+        }
+
+        if stats.successful_applies > 0 {
+            stats.average_apply_seconds = total_seconds / stats.successful_applies as f64;
+        }
+        stats.apply_seconds_by_month = by_month
+            .into_iter()
+            .map(|(month, (total, count))| (month, total / count as f64))
+            .collect();
+
+        stats
+    }
+}
+
+/// Gather statistics from the filesystem and, when reachable, the database.
+pub async fn collect_stats(config: &Config) -> Result<ProjectStats> {
+    let op = config.operator();
+    let pather = config.pather();
+    let mut stats = ProjectStats::default();
+
+    let fs_status = list_migration_fs_status(op, &pather, None).await?;
+    let mut template_bytes = 0;
+    for (name, status) in &fs_status {
+        if !status.has_up_sql {
+            continue;
+        }
+        stats.migrations.total += 1;
+        if status.has_lock_toml {
+            stats.migrations.pinned += 1;
+        }
+        template_bytes += op
+            .stat(&pather.migration_script_file_path(name))
+            .await?
+            .content_length();
+    }
+    stats.average_migration_bytes = template_bytes
+        .checked_div(stats.migrations.total)
+        .unwrap_or_default();
+
+    stats.components = folder_usage(op, &pather.components_folder()).await?.files;
+
+    let pinned = folder_usage(op, &pather.pinned_folder()).await?;
+    stats.pinned_store = PinnedStoreStats {
+        objects: pinned.files,
+        bytes: pinned.bytes,
+    };
+
+    stats.tests = list_tests(config).await?.len() as u64;
+
+    match database_stats(config).await {
+        Ok((applied, history)) => {
+            let pending = fs_status
+                .iter()
+                .filter(|(name, s)| s.has_up_sql && !applied.contains(*name))
+                .count();
+            stats.migrations.applied = Some(applied.len() as u64);
+            stats.migrations.pending = Some(pending as u64);
+            stats.database = Some(DatabaseStats::from_history(&history));
+        }
+        Err(e) => {
+            eprintln!("Database statistics unavailable: {:#}", e);
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Returns the set of migrations whose latest activity succeeded, along with
+/// the full history for the default namespace.
+async fn database_stats(config: &Config) -> Result<(HashSet<String>, Vec<MigrationHistoryEntry>)> {
+    let engine = config.new_engine().await?;
+    let applied = engine
+        .get_migrations_from_db(Some(DEFAULT_NAMESPACE))
+        .await?
+        .into_iter()
+        .filter(|m| m.last_status == Some(MigrationStatus::Success))
+        .map(|m| m.migration_name)
+        .collect();
+    let history = engine
+        .get_migration_history(Some(DEFAULT_NAMESPACE))
+        .await?;
+    Ok((applied, history))
+}
+
+/// Formats a byte count using binary units, e.g. `1.5 KiB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn print_stats(stats: &ProjectStats) {
+    let m = &stats.migrations;
+    println!();
+    println!("{}", style("Migrations").bold());
+    println!("  Total:    {}", m.total);
+    println!("  Pinned:   {}", m.pinned);
+    match (m.applied, m.pending) {
+        (Some(applied), Some(pending)) => {
+            println!("  Applied:  {}", applied);
+            println!("  Pending:  {}", pending);
+        }
+        _ => println!("  Applied:  {}", style("unknown").dim()),
+    }
+    println!(
+        "  Average size: {}",
+        format_bytes(stats.average_migration_bytes)
+    );
+    println!();
+    println!("{}", style("Project").bold());
+    println!("  Components: {}", stats.components);
+    println!("  Tests:      {}", stats.tests);
+    println!(
+        "  Pinned store: {} objects, {}",
+        stats.pinned_store.objects,
+        format_bytes(stats.pinned_store.bytes)
+    );
+
+    if let Some(db) = &stats.database {
+        println!();
+        println!("{}", style("Apply history").bold());
+        println!("  Successful: {}", db.successful_applies);
+        println!("  Failed:     {}", db.failed_applies);
+        println!("  Average duration: {:.2}s", db.average_apply_seconds);
+        println!("  Longest duration: {:.2}s", db.max_apply_seconds);
+        if !db.apply_seconds_by_month.is_empty() {
+            println!("  Average duration by month:");
+            for (month, seconds) in &db.apply_seconds_by_month {
+                println!("    {}  {:.2}s", month, seconds);
+            }
+        }
+    }
+    println!();
+}
+
+impl Command for Stats {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let stats = collect_stats(config).await?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        } else {
+            print_stats(&stats);
+        }
+
+        Ok(Outcome::Success)
+    }
+}
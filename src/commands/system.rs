@@ -0,0 +1,40 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::status;
+use anyhow::Result;
+
+/// Applies pending migrations to spawn's own internal schema in the target
+/// database. Normal commands refuse to run until this has been done.
+pub struct SystemUpgrade;
+
+impl TelemetryDescribe for SystemUpgrade {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("system upgrade")
+    }
+}
+
+impl Command for SystemUpgrade {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let engine = config.new_engine_for_upgrade().await?;
+        let before = engine.internal_schema_version().await?;
+        let applied = engine.upgrade_internal_schema().await?;
+        let latest = engine.latest_internal_schema_version();
+
+        for name in &applied {
+            status!("Applied internal migration {}", name);
+        }
+        match before {
+            Some(version) if version == latest => {
+                status!("Internal schema is up to date (version {}).", latest)
+            }
+            Some(version) => status!(
+                "Upgraded internal schema from version {} to {}.",
+                version,
+                latest
+            ),
+            None => status!("Created internal schema at version {}.", latest),
+        }
+
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,165 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::status;
+use crate::telemetry::{TelemetrySink, TelemetryState};
+use anyhow::{anyhow, Context, Result};
+use opendal::Operator;
+
+pub struct TelemetryStatus;
+
+impl TelemetryDescribe for TelemetryStatus {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("telemetry status")
+    }
+}
+
+impl Command for TelemetryStatus {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let state = TelemetryState::detect(config.telemetry);
+        match state {
+            TelemetryState::Enabled => println!("Telemetry:   enabled"),
+            TelemetryState::DoNotTrack => {
+                println!("Telemetry:   disabled (DO_NOT_TRACK is set)")
+            }
+            TelemetryState::DisabledInConfig => {
+                println!("Telemetry:   disabled (telemetry = false in spawn.toml)")
+            }
+        }
+
+        match &config.project_id {
+            Some(id) => println!("Distinct id: {} (project_id in spawn.toml)", id),
+            None => println!("Distinct id: a new random id for every run (no project_id)"),
+        }
+
+        match TelemetrySink::from_endpoint(config.telemetry_endpoint.as_deref()) {
+            Some(TelemetrySink::PostHog(url)) if config.telemetry_endpoint.is_none() => {
+                println!("Endpoint:    {} (default)", url)
+            }
+            Some(TelemetrySink::PostHog(url)) => println!("Endpoint:    {}", url),
+            Some(TelemetrySink::File(path)) => {
+                println!("Endpoint:    {} (local file)", path.display())
+            }
+            None => println!("Endpoint:    unsupported"),
+        }
+
+        if state.is_enabled() {
+            println!();
+            println!("Run `spawn telemetry disable` or set DO_NOT_TRACK=1 to opt out.");
+        }
+
+        Ok(Outcome::Success)
+    }
+}
+
+/// Disable telemetry command - like init, this edits the config file
+/// directly rather than taking a loaded Config, so that it works even when
+/// the rest of the config does not load.
+pub struct TelemetryDisable {
+    pub config_file: String,
+}
+
+impl TelemetryDescribe for TelemetryDisable {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("telemetry disable")
+    }
+}
+
+impl TelemetryDisable {
+    pub async fn execute(&self, base_op: &Operator) -> Result<Outcome> {
+        let bytes = base_op
+            .read(&self.config_file)
+            .await
+            .context(format!("No config found at path '{}'", &self.config_file))?
+            .to_bytes();
+        let contents = String::from_utf8(bytes.to_vec())?;
+
+        let updated = disable_telemetry_setting(&contents)?;
+        if updated != contents {
+            base_op
+                .write(&self.config_file, updated)
+                .await
+                .context(format!("could not write {}", &self.config_file))?;
+        }
+
+        status!("Telemetry disabled in {}.", &self.config_file);
+
+        Ok(Outcome::Success)
+    }
+}
+
+/// Returns `contents` (a spawn.toml) with telemetry turned off, changing as
+/// little as possible so that comments and formatting are kept. An existing
+/// `telemetry` key or `[telemetry]` section is updated, otherwise
+/// `telemetry = false` is added before the first table.
+pub fn disable_telemetry_setting(contents: &str) -> Result<String> {
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+
+    let mut table: Option<String> = None;
+    let mut first_table_line = None;
+    let mut telemetry_table_line = None;
+    let mut edited = false;
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            let name = trimmed.trim_matches(|c| c == '[' || c == ']').trim();
+            first_table_line.get_or_insert(i);
+            if name == "telemetry" {
+                telemetry_table_line = Some(i);
+            }
+            table = Some(name.to_string());
+            continue;
+        }
+
+        let key = trimmed.split('=').next().unwrap_or_default().trim();
+        match (table.as_deref(), key) {
+            (None, "telemetry") => {
+                *line = "telemetry = false".to_string();
+                edited = true;
+            }
+            (Some("telemetry"), "enabled") => {
+                *line = "enabled = false".to_string();
+                edited = true;
+            }
+            _ => {}
+        }
+    }
+
+    if !edited {
+        match (telemetry_table_line, first_table_line) {
+            (Some(i), _) => lines.insert(i + 1, "enabled = false".to_string()),
+            (None, Some(mut i)) => {
+                // Keep comments describing the first table attached to it.
+                while i > 0 && lines[i - 1].trim_start().starts_with('#') {
+                    i -= 1;
+                }
+                lines.insert(i, String::new());
+                lines.insert(i, "telemetry = false".to_string());
+            }
+            (None, None) => lines.push("telemetry = false".to_string()),
+        }
+    }
+
+    let mut updated = lines.join("\n");
+    if contents.ends_with('\n') || contents.is_empty() {
+        updated.push('\n');
+    }
+
+    // Make sure the edit did what was intended before it is written.
+    let table: toml::Table = toml::from_str(&updated).map_err(|_| {
+        anyhow!(
+            "could not update the telemetry setting, set telemetry = false in spawn.toml by hand"
+        )
+    })?;
+    let disabled = match table.get("telemetry") {
+        Some(toml::Value::Boolean(enabled)) => !enabled,
+        Some(toml::Value::Table(t)) => t.get("enabled") == Some(&toml::Value::Boolean(false)),
+        _ => false,
+    };
+    if !disabled {
+        return Err(anyhow!(
+            "could not update the telemetry setting, set telemetry = false in spawn.toml by hand"
+        ));
+    }
+
+    Ok(updated)
+}
@@ -1,75 +1,224 @@
+use crate::annotations::Annotation;
+use crate::commands::migration::format_duration;
+use crate::commands::test::select_tests;
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
-use crate::sqltest::Tester;
+use crate::engine::{ServerMessage, ServerMessageLevel};
+use crate::sqltest::{TestKind, TestOutcome, Tester};
+use crate::status;
+use crate::telemetry::CommandStatus;
 use anyhow::Result;
-use futures::TryStreamExt;
+use console::style;
+use std::time::{Duration, Instant};
 
-const RESET: &str = "\x1b[0m";
-const BOLD: &str = "\x1b[1m";
-const RED: &str = "\x1b[31m";
-const GREEN: &str = "\x1b[32m";
+/// How many of the slowest tests the summary after a run lists.
+const SLOWEST_TESTS_SHOWN: usize = 5;
 
 pub struct CompareTests {
-    pub name: Option<String>,
+    /// Names of the tests to compare, or patterns such as `reports/*`.
+    /// Compares every test if empty.
+    pub names: Vec<String>,
+    /// Only compare render tests, skipping those that need a database
+    pub render_only: bool,
+    /// Re-run a failing test up to this many times before reporting it as
+    /// failed
+    pub retries: u32,
+    /// Run each test in a copy of the target's database, dropped once it
+    /// has run
+    pub isolate: bool,
+    /// Fail tests the server sent warnings about, even if their output
+    /// matches
+    pub deny_warnings: bool,
 }
 
 impl TelemetryDescribe for CompareTests {
     fn telemetry(&self) -> TelemetryInfo {
-        TelemetryInfo::new("test compare")
-            .with_properties(vec![("is_comparing_all", self.name.is_none().to_string())])
+        TelemetryInfo::new("test compare").with_properties(vec![
+            ("is_comparing_all", self.names.is_empty().to_string()),
+            ("opt_render_only", self.render_only.to_string()),
+            ("retries", self.retries.to_string()),
+            ("opt_isolate", self.isolate.to_string()),
+            ("opt_deny_warnings", self.deny_warnings.to_string()),
+        ])
     }
 }
 
 impl Command for CompareTests {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
-        let test_files: Vec<String> = match &self.name {
-            Some(name) => vec![name.clone()],
-            None => {
-                let mut tests: Vec<String> = Vec::new();
-                let mut fs_lister = config
-                    .operator()
-                    .lister(&config.pather().tests_folder())
-                    .await?;
-                while let Some(entry) = fs_lister.try_next().await? {
-                    let path = entry.path().to_string();
-                    if path.ends_with("/") {
-                        tests.push(path)
-                    }
-                }
-                tests
-            }
-        };
+        let test_files = select_tests(config, &self.names).await?;
 
         let mut failed = false;
+        let mut skipped = 0;
+        let suite_started = Instant::now();
+        let mut timings: Vec<(String, Duration)> = Vec::new();
+        let mut flaky: Vec<String> = Vec::new();
 
         for test_file in test_files {
-            let tester = Tester::new(config, &test_file);
+            let tester = Tester::new(config, &test_file).with_isolation(self.isolate);
+            if self.render_only && tester.settings().await?.kind != TestKind::Render {
+                skipped += 1;
+                continue;
+            }
+
+            let started = Instant::now();
+            let mut result = tester.run_compare(None).await;
+            let mut retried = 0;
+            while retried < self.retries && !matches!(&result, Ok(r) if r.diff.is_none()) {
+                retried += 1;
+                status!("Retrying {} ({} of {})", test_file, retried, self.retries);
+                result = tester.run_compare(None).await;
+            }
+            timings.push((test_file.clone(), started.elapsed()));
+            let denied = matches!(&result, Ok(r) if self.deny_warnings && warnings(r) > 0);
+            let passed = matches!(&result, Ok(r) if r.diff.is_none()) && !denied;
+            config.telemetry_session.record(
+                TelemetryInfo::new("test compare step")
+                    .with_properties(vec![("passed", passed.to_string())]),
+                started,
+                CommandStatus::of(&result),
+            );
+            config.telemetry_session.count("tests_run", 1);
+            if !passed {
+                config.telemetry_session.count("tests_failed", 1);
+            }
 
-            match tester.run_compare(None).await {
+            match result {
                 Ok(result) => match result.diff {
+                    None if denied => {
+                        failed = true;
+                        println!(
+                            "{} {} {}",
+                            style("[FAIL]").red(),
+                            test_file,
+                            style(format!(
+                                "(the server sent {} warning(s) and --deny-warnings is given)",
+                                warnings(&result)
+                            ))
+                            .yellow()
+                        );
+                        show_messages(&result.messages);
+                    }
+                    None if retried > 0 => {
+                        println!(
+                            "{} {} {}",
+                            style("[PASS]").green(),
+                            test_file,
+                            style(format!("(on retry {})", retried)).yellow()
+                        );
+                        show_messages(&result.messages);
+                        flaky.push(test_file);
+                    }
                     None => {
-                        println!("{}[PASS]{} {}", GREEN, RESET, test_file);
+                        println!("{} {}", style("[PASS]").green(), test_file);
+                        show_messages(&result.messages);
                     }
                     Some(diff) => {
                         failed = true;
-                        println!("\n{}[FAIL]{} {}{}{}", RED, RESET, BOLD, test_file, RESET);
-                        println!("{}--- Diff ---{}", BOLD, RESET);
-                        println!("{}", diff);
-                        println!("{}-------------{}\n", BOLD, RESET);
+                        let mut annotation = Annotation::error(
+                            "Test output differs from the expected output. Run `spawn test compare` for the diff, or `spawn test expect` to update it.",
+                        )
+                        .with_file(&tester.expected_file_path())
+                        .with_title(format!("Test {} failed", test_file.trim_end_matches('/')));
+                        if let Some(line) = result.first_diff_line {
+                            annotation = annotation.with_line(line);
+                        }
+                        annotation.emit(config.annotate);
+                        println!("\n{} {}", style("[FAIL]").red(), style(&test_file).bold());
+                        config.events.on_diff(&test_file, &diff);
+                        if let Some(error) = &result.error {
+                            println!("{}", style("--- SQL error ---").bold());
+                            println!("{}", error);
+                        }
+                        println!("{}\n", style("-------------").bold());
                     }
                 },
                 Err(e) => return Err(e),
             };
         }
 
+        if skipped > 0 {
+            status!("Skipped {} test(s) that need a database", skipped);
+        }
+        if !flaky.is_empty() {
+            status!(
+                "{} test(s) only passed on retry: {}",
+                flaky.len(),
+                flaky.join(", ")
+            );
+        }
+        if timings.len() > 1 {
+            status!("Slowest tests:");
+            for (name, duration) in slowest_tests(&timings, SLOWEST_TESTS_SHOWN) {
+                status!("  {:>7}  {}", format_test_duration(*duration), name);
+            }
+        }
+        if !timings.is_empty() {
+            status!(
+                "Ran {} test(s) in {}",
+                timings.len(),
+                format_test_duration(suite_started.elapsed())
+            );
+        }
+
         if failed {
-            return Err(anyhow::anyhow!(
-                "{}!{} Differences found in one or more tests",
-                RED,
-                RESET
-            ));
+            return Ok(Outcome::TestsFailed);
         }
 
         Ok(Outcome::Success)
     }
 }
+
+/// How many warnings the server sent while running a test.
+fn warnings(outcome: &TestOutcome) -> usize {
+    outcome
+        .messages
+        .iter()
+        .filter(|message| message.level == ServerMessageLevel::Warning)
+        .count()
+}
+
+/// Lists the notices and warnings the server sent under a test's result.
+fn show_messages(messages: &[ServerMessage]) {
+    for message in messages {
+        println!("    {}", style(&message.text).yellow());
+    }
+}
+
+/// The `count` slowest of `timings`, slowest first. Tests that took as long
+/// keep their run order.
+fn slowest_tests(timings: &[(String, Duration)], count: usize) -> Vec<&(String, Duration)> {
+    let mut slowest: Vec<&(String, Duration)> = timings.iter().collect();
+    slowest.sort_by_key(|(_, duration)| std::cmp::Reverse(*duration));
+    slowest.truncate(count);
+    slowest
+}
+
+/// Formats a test's duration, in milliseconds below a second as most tests
+/// take less.
+fn format_test_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format_duration(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slowest_tests() {
+        let timings: Vec<(String, Duration)> = [("a", 30), ("b", 1200), ("c", 30), ("d", 5)]
+            .into_iter()
+            .map(|(name, ms)| (name.to_string(), Duration::from_millis(ms)))
+            .collect();
+        let names: Vec<&str> = slowest_tests(&timings, 3)
+            .into_iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(vec!["b", "a", "c"], names);
+        assert_eq!("30ms", format_test_duration(timings[0].1));
+        assert_eq!("1.2s", format_test_duration(timings[1].1));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::commands::test::list_tests;
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::sqltest::Tester;
+use crate::store::list_migration_fs_status;
+use crate::template::{self, StreamingGeneration};
+use anyhow::{Context, Result};
+use console::style;
+use futures::TryStreamExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tabled::settings::Style;
+use tabled::{Table, Tabled};
+
+/// Reports which components the tests exercise, by rendering every test and
+/// migration and recording the components each reads.
+pub struct TestCoverage {
+    pub json: bool,
+}
+
+impl TelemetryDescribe for TestCoverage {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("test coverage")
+            .with_properties(vec![("opt_json", self.json.to_string())])
+    }
+}
+
+/// How often a component is used.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ComponentUsage {
+    pub component: String,
+    /// Tests that read the component
+    pub tests: Vec<String>,
+    /// Number of migrations that read the component
+    pub migrations: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ComponentCoverage {
+    /// Every component, by path in the components folder
+    pub components: Vec<ComponentUsage>,
+    /// Why each test or migration that could not be rendered failed, by
+    /// name, such as `test users`
+    pub errors: BTreeMap<String, String>,
+}
+
+impl ComponentCoverage {
+    /// Components no test reads.
+    pub fn untested(&self) -> Vec<&str> {
+        self.components
+            .iter()
+            .filter(|c| c.tests.is_empty())
+            .map(|c| c.component.as_str())
+            .collect()
+    }
+}
+
+#[derive(Tabled)]
+struct ComponentUsageDisplay {
+    #[tabled(rename = "Component")]
+    component: String,
+    #[tabled(rename = "Tests")]
+    tests: usize,
+    #[tabled(rename = "Migrations")]
+    migrations: usize,
+}
+
+/// Every file in the components folder, relative to it.
+async fn list_components(config: &Config) -> Result<Vec<String>> {
+    let folder = format!("{}/", config.pather().components_folder());
+    // Listed paths have opendal's leading "./" and "/" stripped.
+    let prefix = folder.trim_start_matches("./").trim_start_matches('/');
+    let mut lister = config
+        .operator()
+        .lister_with(&folder)
+        .recursive(true)
+        .await
+        .context("listing components")?;
+
+    let mut components = Vec::new();
+    while let Some(entry) = lister.try_next().await? {
+        let path = entry.path();
+        if path.ends_with('/') {
+            continue;
+        }
+        if let Some(component) = path.strip_prefix(prefix) {
+            components.push(component.to_string());
+        }
+    }
+    components.sort();
+    Ok(components)
+}
+
+/// The usage of `component`, added if listing the components folder did
+/// not find it.
+fn usage_of(
+    usage: &mut BTreeMap<String, ComponentUsage>,
+    component: String,
+) -> &mut ComponentUsage {
+    usage
+        .entry(component.clone())
+        .or_insert_with(|| ComponentUsage {
+            component,
+            ..Default::default()
+        })
+}
+
+/// Renders `generation`, discarding the output, and returns the components
+/// it read.
+fn render(generation: StreamingGeneration) -> Result<Vec<String>> {
+    let loaded = generation.loaded_components();
+    generation.render_to_writer(&mut std::io::sink())?;
+    Ok(loaded.names())
+}
+
+/// Renders every test and migration of the project from its current
+/// components, counting the components each reads. Tests and migrations
+/// that fail to render are listed in `errors` rather than failing the
+/// report.
+pub async fn component_coverage(config: &Config) -> Result<ComponentCoverage> {
+    let mut usage: BTreeMap<String, ComponentUsage> = list_components(config)
+        .await?
+        .into_iter()
+        .map(|component| {
+            let usage = ComponentUsage {
+                component: component.clone(),
+                ..Default::default()
+            };
+            (component, usage)
+        })
+        .collect();
+    let mut errors = BTreeMap::new();
+
+    for test in list_tests(config).await? {
+        let tester = Tester::new(config, &test);
+        let rendered = match tester.settings().await {
+            Ok(settings) => match tester.generation(&settings, None).await {
+                // A test of a component reads it as its template rather than
+                // through an include.
+                Ok(generation) => render(generation).map(|mut components| {
+                    components.extend(settings.component.clone());
+                    components
+                }),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match rendered {
+            Ok(components) => {
+                for component in components {
+                    usage_of(&mut usage, component).tests.push(test.clone());
+                }
+            }
+            Err(e) => {
+                errors.insert(format!("test {}", test), format!("{:#}", e));
+            }
+        }
+    }
+
+    let migrations = list_migration_fs_status(config.operator(), &config.pather(), None).await?;
+    for (migration, status) in migrations {
+        if !status.has_up_sql {
+            continue;
+        }
+        let path = config.pather().migration_script_file_path(&migration);
+        let rendered = match template::generate_streaming(config, None, &path, None).await {
+            Ok(generation) => render(generation),
+            Err(e) => Err(e),
+        };
+        match rendered {
+            Ok(components) => {
+                for component in components {
+                    usage_of(&mut usage, component).migrations += 1;
+                }
+            }
+            Err(e) => {
+                errors.insert(format!("migration {}", migration), format!("{:#}", e));
+            }
+        }
+    }
+
+    Ok(ComponentCoverage {
+        components: usage.into_values().collect(),
+        errors,
+    })
+}
+
+impl Command for TestCoverage {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let coverage = component_coverage(config).await?;
+        for (name, error) in &coverage.errors {
+            config
+                .events
+                .on_warning(&format!("could not render {}: {}", name, error));
+        }
+        let untested = coverage.untested();
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&coverage)?);
+        } else if coverage.components.is_empty() {
+            println!("No components found");
+        } else {
+            let display_rows: Vec<ComponentUsageDisplay> = coverage
+                .components
+                .iter()
+                .map(|c| ComponentUsageDisplay {
+                    component: c.component.clone(),
+                    tests: c.tests.len(),
+                    migrations: c.migrations,
+                })
+                .collect();
+            let mut table = Table::new(display_rows);
+            table.with(Style::sharp());
+            println!("\n{}\n", table);
+
+            if untested.is_empty() {
+                println!("{}", style("Every component is used by a test.").green());
+            } else {
+                println!("Components not used by any test:");
+                for component in &untested {
+                    println!("  {}", style(component).yellow());
+                }
+            }
+        }
+
+        if config.strict && !untested.is_empty() {
+            return Ok(Outcome::CheckFailed);
+        }
+        Ok(Outcome::Success)
+    }
+}
@@ -1,11 +1,122 @@
 mod build;
 mod compare;
+mod coverage;
 mod expect;
 mod new;
+mod record;
 mod run;
 
 pub use build::BuildTest;
 pub use compare::CompareTests;
+pub use coverage::{component_coverage, ComponentCoverage, ComponentUsage, TestCoverage};
 pub use expect::ExpectTest;
 pub use new::NewTest;
+pub use record::RecordTest;
 pub use run::RunTest;
+
+use crate::config::Config;
+use crate::sqltest::TEST_SETTINGS_FILE;
+use anyhow::{anyhow, Result};
+use futures::TryStreamExt;
+use regex::Regex;
+
+/// Names of the tests in the project's `tests/` folder: every folder holding
+/// a test.sql or test.toml, by its path beneath `tests/`, so tests can be
+/// grouped in subfolders such as `reports/daily`.
+pub async fn list_tests(config: &Config) -> Result<Vec<String>> {
+    let folder = format!("{}/", config.pather().tests_folder());
+    // Listed paths have opendal's leading "./" and "/" stripped.
+    let prefix = folder.trim_start_matches("./").trim_start_matches('/');
+    let mut tests: Vec<String> = Vec::new();
+    let mut fs_lister = config
+        .operator()
+        .lister_with(&folder)
+        .recursive(true)
+        .await?;
+    while let Some(entry) = fs_lister.try_next().await? {
+        let path = entry.path();
+        let Some((name, file)) = path
+            .strip_prefix(prefix)
+            .and_then(|relative| relative.rsplit_once('/'))
+        else {
+            continue;
+        };
+        if !name.is_empty() && (file == "test.sql" || file == TEST_SETTINGS_FILE) {
+            tests.push(name.to_string());
+        }
+    }
+    tests.sort();
+    tests.dedup();
+    Ok(tests)
+}
+
+/// The tests selected by `patterns`, or every test if there are none. A
+/// pattern with `*` or `?` is matched against the test names, where `*`
+/// matches within one folder, as in `reports/*`; any other pattern is taken
+/// as a test's name.
+pub async fn select_tests(config: &Config, patterns: &[String]) -> Result<Vec<String>> {
+    if patterns.is_empty() {
+        return list_tests(config).await;
+    }
+
+    let mut all: Option<Vec<String>> = None;
+    let mut selected: Vec<String> = Vec::new();
+    for pattern in patterns {
+        let pattern = pattern.trim_end_matches('/');
+        if !pattern.contains(['*', '?']) {
+            if !selected.iter().any(|s| s == pattern) {
+                selected.push(pattern.to_string());
+            }
+            continue;
+        }
+
+        let tests = match &mut all {
+            Some(tests) => tests,
+            None => all.insert(list_tests(config).await?),
+        };
+        let glob = glob_regex(pattern)?;
+        let matched: Vec<&String> = tests.iter().filter(|t| glob.is_match(t)).collect();
+        if matched.is_empty() {
+            return Err(anyhow!("no tests match '{}'", pattern));
+        }
+        for test in matched {
+            if !selected.contains(test) {
+                selected.push(test.clone());
+            }
+        }
+    }
+    Ok(selected)
+}
+
+/// A regex matching the names `pattern` selects, where `*` matches any run
+/// of characters other than `/` and `?` any one of them.
+fn glob_regex(pattern: &str) -> Result<Regex> {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Ok(Regex::new(&regex)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_regex() {
+        let glob = glob_regex("reports/*").unwrap();
+        assert!(glob.is_match("reports/daily"));
+        assert!(!glob.is_match("reports/daily/totals"));
+        assert!(!glob.is_match("reports"));
+        assert!(!glob.is_match("old_reports/daily"));
+
+        let glob = glob_regex("user?.v1").unwrap();
+        assert!(glob.is_match("users.v1"));
+        assert!(!glob.is_match("users_v1"));
+    }
+}
@@ -1,23 +1,33 @@
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
-use crate::sqltest::Tester;
+use crate::sqltest::{TestKind, Tester};
+use crate::status;
 use anyhow::Result;
 
 pub struct NewTest {
     pub name: String,
+    /// Create a render test, compared without a database
+    pub render: bool,
 }
 
 impl TelemetryDescribe for NewTest {
     fn telemetry(&self) -> TelemetryInfo {
         TelemetryInfo::new("test new")
+            .with_properties(vec![("opt_render", self.render.to_string())])
     }
 }
 
 impl Command for NewTest {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
-        println!("creating test with name {}", &self.name);
+        status!("creating test with name {}", &self.name);
         let tester = Tester::new(config, &self.name);
 
-        Ok(Outcome::NewTest(tester.create_test().await?))
+        let kind = if self.render {
+            TestKind::Render
+        } else {
+            TestKind::Run
+        };
+
+        Ok(Outcome::NewTest(tester.create_test(kind).await?))
     }
 }
@@ -0,0 +1,72 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::sqltest::Tester;
+use anyhow::{anyhow, Context, Result};
+use std::io::Read;
+
+/// Creates a test from an ad-hoc query, saving its current output as the
+/// expected output.
+pub struct RecordTest {
+    pub name: String,
+    /// File holding the query, or stdin if None or `-`
+    pub file: Option<String>,
+    /// Replace the test if it already exists
+    pub force: bool,
+}
+
+impl TelemetryDescribe for RecordTest {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("test record").with_properties(vec![
+            ("from_stdin", self.reads_stdin().to_string()),
+            ("opt_force", self.force.to_string()),
+        ])
+    }
+}
+
+impl RecordTest {
+    fn reads_stdin(&self) -> bool {
+        self.file.as_deref().is_none_or(|f| f == "-")
+    }
+
+    fn read_query(&self) -> Result<String> {
+        match self.file.as_deref().filter(|_| !self.reads_stdin()) {
+            Some(file) => {
+                std::fs::read_to_string(file).context(format!("could not read query from {}", file))
+            }
+            None => {
+                let mut query = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut query)
+                    .context("could not read query from stdin")?;
+                Ok(query)
+            }
+        }
+    }
+}
+
+impl Command for RecordTest {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let tester = Tester::new(config, &self.name);
+        if !self.force && config.operator().exists(&tester.test_file_path()).await? {
+            return Err(anyhow!(
+                "test '{}' already exists. Use --force to record over it.",
+                self.name
+            ));
+        }
+
+        let query = self.read_query()?;
+        if query.trim().is_empty() {
+            return Err(anyhow!("no query to record"));
+        }
+
+        let (_, error) = tester.record(&query).await?;
+        if let Some(error) = error {
+            config.events.on_warning(&format!(
+                "the query failed, and its error was recorded as the expected output: {}",
+                error.trim()
+            ));
+        }
+
+        Ok(Outcome::NewTest(self.name.clone()))
+    }
+}
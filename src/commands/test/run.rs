@@ -1,45 +1,74 @@
+use crate::commands::test::select_tests;
 use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
 use crate::config::Config;
+use crate::engine::{server_message, ServerMessageLevel};
 use crate::sqltest::Tester;
 use anyhow::Result;
-use futures::TryStreamExt;
+use console::style;
 
 pub struct RunTest {
-    pub name: Option<String>,
+    /// Names of the tests to run, or patterns such as `reports/*`. Runs
+    /// every test if empty.
+    pub names: Vec<String>,
+    /// Run each test in a copy of the target's database, dropped once it
+    /// has run
+    pub isolate: bool,
+    /// Fail when the server sends warnings while running a test
+    pub deny_warnings: bool,
 }
 
 impl TelemetryDescribe for RunTest {
     fn telemetry(&self) -> TelemetryInfo {
-        TelemetryInfo::new("test run")
+        TelemetryInfo::new("test run").with_properties(vec![
+            ("opt_isolate", self.isolate.to_string()),
+            ("opt_deny_warnings", self.deny_warnings.to_string()),
+        ])
     }
 }
 
 impl Command for RunTest {
     async fn execute(&self, config: &Config) -> Result<Outcome> {
-        let test_names: Vec<String> = match &self.name {
-            Some(name) => vec![name.clone()],
-            None => {
-                let mut tests: Vec<String> = Vec::new();
-                let mut fs_lister = config
-                    .operator()
-                    .lister(&config.pather().tests_folder())
-                    .await?;
-                while let Some(entry) = fs_lister.try_next().await? {
-                    let path = entry.path().to_string();
-                    if path.ends_with("/") {
-                        tests.push(path)
-                    }
-                }
-                tests
-            }
-        };
+        let test_names = select_tests(config, &self.names).await?;
 
+        let mut warned = Vec::new();
         for test_name in test_names {
-            let tester = Tester::new(config, &test_name);
-            let result = tester.run(None).await?;
-            println!("{}", result);
+            let tester = Tester::new(config, &test_name).with_isolation(self.isolate);
+            let (result, messages) = tester.run_with_messages(None).await?;
+            if messages.is_empty() {
+                println!("{}", result);
+            } else {
+                println!("{}", highlight_messages(&result));
+            }
+            if messages
+                .iter()
+                .any(|message| message.level == ServerMessageLevel::Warning)
+            {
+                warned.push(test_name);
+            }
         }
 
+        if self.deny_warnings && !warned.is_empty() {
+            eprintln!(
+                "The server sent warnings while running {}, and --deny-warnings is given",
+                warned.join(", ")
+            );
+            return Ok(Outcome::TestsFailed);
+        }
         Ok(Outcome::Success)
     }
 }
+
+/// `output` with the lines holding the server's notices and warnings in
+/// yellow.
+fn highlight_messages(output: &str) -> String {
+    output
+        .split_inclusive('\n')
+        .map(|line| {
+            let text = line.trim_end_matches('\n');
+            match server_message(text) {
+                Some(_) => format!("{}{}", style(text).yellow(), &line[text.len()..]),
+                None => line.to_string(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,140 @@
+use crate::commands::migration::{get_combined_migration_status, pin_migration, DEFAULT_NAMESPACE};
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::dashboard::{Action, Dashboard, TestLine};
+use crate::output;
+use crate::spawn::{ApplyOptions, Spawn};
+use anyhow::{anyhow, Result};
+use console::Term;
+
+/// A terminal dashboard of the selected target: the status of each
+/// migration, its history, and test results, with keys to apply, pin and
+/// inspect. The screen is drawn by [`crate::dashboard`].
+pub struct Ui;
+
+impl TelemetryDescribe for Ui {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("ui")
+    }
+}
+
+impl Command for Ui {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let term = Term::stdout();
+        if !term.is_term() {
+            return Err(anyhow!("spawn ui needs a terminal"));
+        }
+        // The dashboard shows what happened, so progress would only flash
+        // across it.
+        output::set_quiet(true);
+        let title = match (&config.target, config.policy_environment()) {
+            (Some(target), Some(env)) => format!("{} (environment {})", target, env),
+            (Some(target), None) => target.clone(),
+            (None, _) => "(no target)".to_string(),
+        };
+        let mut dashboard = Dashboard::new(&title);
+        let spawn = Spawn::new(config.clone());
+
+        term.hide_cursor()?;
+        let result = run(&term, &spawn, &mut dashboard).await;
+        term.clear_screen()?;
+        term.show_cursor()?;
+        result?;
+        Ok(Outcome::Success)
+    }
+}
+
+async fn run(term: &Term, spawn: &Spawn, dashboard: &mut Dashboard) -> Result<()> {
+    let config = spawn.config();
+    refresh(config, dashboard).await;
+    loop {
+        draw(term, dashboard)?;
+        let action = dashboard.handle_key(term.read_key_raw()?);
+        match action {
+            Action::None => {}
+            Action::Quit => return Ok(()),
+            Action::Refresh => refresh(config, dashboard).await,
+            // The allowlist only saw `ui`, so each action is checked as
+            // the command it stands in for.
+            Action::Apply(migration) => {
+                if let Err(e) = config.check_command_allowed("migration apply") {
+                    dashboard.set_message(format!("{:#}", e));
+                    continue;
+                }
+                dashboard.set_message(format!("Applying {}...", migration));
+                draw(term, dashboard)?;
+                match spawn.apply(Some(&migration), ApplyOptions::default()).await {
+                    Ok(report) if report.applied().is_empty() => {
+                        dashboard.set_message(format!("{} was already applied", migration))
+                    }
+                    Ok(_) => dashboard.set_message(format!("Applied {}", migration)),
+                    Err(e) => {
+                        dashboard.set_message(format!("Could not apply {}: {:#}", migration, e))
+                    }
+                }
+                refresh(config, dashboard).await;
+            }
+            Action::Pin(migration) => {
+                if let Err(e) = config.check_command_allowed("migration pin") {
+                    dashboard.set_message(format!("{:#}", e));
+                    continue;
+                }
+                config.audit_session.record_migration(&migration);
+                match pin_migration(config, &migration).await {
+                    Ok(hash) => dashboard.set_message(format!("Pinned {} at {}", migration, hash)),
+                    Err(e) => {
+                        dashboard.set_message(format!("Could not pin {}: {:#}", migration, e))
+                    }
+                }
+                refresh(config, dashboard).await;
+            }
+            Action::Inspect(migration) => match spawn.history().await {
+                Ok(history) => dashboard.show_history(
+                    history
+                        .into_iter()
+                        .filter(|record| record.migration == migration)
+                        .collect(),
+                ),
+                Err(e) => dashboard.set_message(format!("Could not read history: {:#}", e)),
+            },
+            Action::RunTests => {
+                dashboard.set_message("Running tests...");
+                draw(term, dashboard)?;
+                match spawn.test(None).await {
+                    Ok(results) => {
+                        dashboard.set_message("");
+                        dashboard.show_tests(
+                            results
+                                .into_iter()
+                                .map(|(name, outcome)| TestLine {
+                                    passed: outcome.diff.is_none(),
+                                    detail: outcome.error.or(outcome
+                                        .first_diff_line
+                                        .map(|line| format!("differs at line {}", line))),
+                                    name,
+                                })
+                                .collect(),
+                        )
+                    }
+                    Err(e) => dashboard.set_message(format!("Could not run tests: {:#}", e)),
+                }
+            }
+        }
+    }
+}
+
+/// Reads the status again, keeping the dashboard up if it fails.
+async fn refresh(config: &Config, dashboard: &mut Dashboard) {
+    match get_combined_migration_status(config, Some(DEFAULT_NAMESPACE)).await {
+        Ok(rows) => dashboard.set_rows(rows),
+        Err(e) => dashboard.set_message(format!("Could not read status: {:#}", e)),
+    }
+}
+
+fn draw(term: &Term, dashboard: &Dashboard) -> Result<()> {
+    let (rows, cols) = term.size();
+    term.clear_screen()?;
+    let lines = dashboard.render(cols as usize, rows as usize);
+    term.write_str(&lines.join("\r\n"))?;
+    Ok(())
+}
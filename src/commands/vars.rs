@@ -0,0 +1,49 @@
+use crate::commands::{Command, Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::errors::ErrorKind;
+use crate::migrator::Migrator;
+use crate::variables::{mask_secrets, Variables};
+use anyhow::Result;
+
+/// Prints the context a migration is rendered with, after every source of
+/// variables has been merged, to help find where a value comes from.
+pub struct VarsShow {
+    pub migration: String,
+    pub pinned: bool,
+    pub variables: Option<Variables>,
+}
+
+impl TelemetryDescribe for VarsShow {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("vars show").with_properties(vec![
+            ("opt_pinned", self.pinned.to_string()),
+            ("has_variables", self.variables.is_some().to_string()),
+        ])
+    }
+}
+
+/// The context `migration` is rendered with, with values that look like
+/// secrets masked.
+pub async fn migration_context(
+    config: &Config,
+    migration: &str,
+    pinned: bool,
+    variables: Option<Variables>,
+) -> Result<serde_json::Value> {
+    let mut context = Migrator::new(config, migration, pinned)
+        .generate_streaming(variables)
+        .await
+        .map_err(|e| ErrorKind::Render.wrap(e))?
+        .context()?;
+    mask_secrets(&mut context);
+    Ok(context)
+}
+
+impl Command for VarsShow {
+    async fn execute(&self, config: &Config) -> Result<Outcome> {
+        let context =
+            migration_context(config, &self.migration, self.pinned, self.variables.clone()).await?;
+        println!("{}", serde_json::to_string_pretty(&context)?);
+        Ok(Outcome::Success)
+    }
+}
@@ -0,0 +1,153 @@
+use crate::commands::migration::{get_combined_migration_status, DEFAULT_NAMESPACE};
+use crate::commands::{Outcome, TelemetryDescribe, TelemetryInfo};
+use crate::config::Config;
+use crate::engine::MigrationStatus as EngineStatus;
+use crate::workspace::Workspace;
+use anyhow::{anyhow, Result};
+use console::style;
+use opendal::Operator;
+use serde::Serialize;
+use tabled::settings::Style;
+use tabled::{Table, Tabled};
+
+/// The migration status of one project of a workspace.
+#[derive(Debug, Default, Serialize)]
+pub struct ProjectStatus {
+    pub project: String,
+    pub target: Option<String>,
+    pub applied: usize,
+    pub pending: usize,
+    pub failed: usize,
+    /// Why the status could not be read, when it could not
+    pub error: Option<String>,
+}
+
+#[derive(Tabled)]
+struct ProjectStatusDisplay {
+    #[tabled(rename = "Project")]
+    project: String,
+    #[tabled(rename = "Target")]
+    target: String,
+    #[tabled(rename = "Applied")]
+    applied: String,
+    #[tabled(rename = "Pending")]
+    pending: String,
+    #[tabled(rename = "Failed")]
+    failed: String,
+    #[tabled(rename = "Status")]
+    status: String,
+}
+
+/// Shows the migration status of every project of a workspace. Like init,
+/// this takes the operator rather than a loaded Config, as each project
+/// has its own.
+pub struct WorkspaceStatus {
+    pub workspace_file: String,
+    pub target: Option<String>,
+    pub json: bool,
+    pub strict: bool,
+}
+
+impl TelemetryDescribe for WorkspaceStatus {
+    fn telemetry(&self) -> TelemetryInfo {
+        TelemetryInfo::new("workspace status")
+            .with_properties(vec![("opt_json", self.json.to_string())])
+    }
+}
+
+/// The status of each project of `workspace`, in name order. A project
+/// whose config or database cannot be read is reported with its error
+/// rather than failing the others.
+pub async fn workspace_status(
+    workspace: &Workspace,
+    op: &Operator,
+    target: Option<String>,
+) -> Vec<ProjectStatus> {
+    let mut statuses = Vec::new();
+    for name in workspace.projects.keys() {
+        let status = match workspace.load_config(name, op, target.clone()).await {
+            Ok(config) => project_status(name, &config).await,
+            Err(e) => Err(e),
+        };
+        statuses.push(status.unwrap_or_else(|e| ProjectStatus {
+            project: name.clone(),
+            target: target.clone(),
+            error: Some(format!("{:#}", e)),
+            ..Default::default()
+        }));
+    }
+    statuses
+}
+
+async fn project_status(name: &str, config: &Config) -> Result<ProjectStatus> {
+    let rows = get_combined_migration_status(config, Some(DEFAULT_NAMESPACE)).await?;
+    let mut status = ProjectStatus {
+        project: name.to_string(),
+        target: config.target.clone(),
+        ..Default::default()
+    };
+    for row in rows {
+        match row.last_status {
+            Some(EngineStatus::Success) => status.applied += 1,
+            Some(EngineStatus::Failure | EngineStatus::Attempted) => status.failed += 1,
+            None if row.exists_in_filesystem => status.pending += 1,
+            None => {}
+        }
+    }
+    Ok(status)
+}
+
+impl WorkspaceStatus {
+    pub async fn execute(&self, base_op: &Operator) -> Result<Outcome> {
+        let workspace = Workspace::load(&self.workspace_file, base_op).await?;
+        let statuses = workspace_status(&workspace, base_op, self.target.clone()).await;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&statuses)?);
+        } else {
+            let display_rows: Vec<ProjectStatusDisplay> = statuses
+                .iter()
+                .map(|s| {
+                    let count = |n: usize| match &s.error {
+                        Some(_) => "-".to_string(),
+                        None => n.to_string(),
+                    };
+                    let status = match &s.error {
+                        Some(e) => style(format!("✗ {}", e)).red().to_string(),
+                        None if s.failed > 0 => style("✗ Failed").red().to_string(),
+                        None if s.pending > 0 => style("○ Pending").dim().to_string(),
+                        None => style("✓ Up to date").green().to_string(),
+                    };
+                    ProjectStatusDisplay {
+                        project: s.project.clone(),
+                        target: s.target.clone().unwrap_or_else(|| "-".to_string()),
+                        applied: count(s.applied),
+                        pending: count(s.pending),
+                        failed: count(s.failed),
+                        status,
+                    }
+                })
+                .collect();
+
+            let mut table = Table::new(display_rows);
+            table.with(Style::sharp());
+            println!("\n{}\n", table);
+        }
+
+        let errors = statuses.iter().filter(|s| s.error.is_some()).count();
+        if errors > 0 {
+            return Err(anyhow!(
+                "could not read the status of {} of {} projects",
+                errors,
+                statuses.len()
+            ));
+        }
+
+        let pending: usize = statuses.iter().map(|s| s.pending).sum();
+        if self.strict && pending > 0 {
+            return Ok(Outcome::PendingMigrations(pending));
+        }
+
+        Ok(Outcome::Success)
+    }
+}
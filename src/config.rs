@@ -1,16 +1,42 @@
-use crate::engine::{postgres_psql::PSQL, Engine, EngineType, TargetConfig};
+use crate::advisor::LockAdvisorConfig;
+use crate::agent::AgentConfig;
+use crate::annotations::AnnotationFormat;
+use crate::audit::{current_user, AuditConfig, AuditSession};
+use crate::engine::{
+    memory::MemoryEngine, postgres_psql::PSQL, Engine, EngineType, FileHistory, HistoryBackend,
+    TargetConfig,
+};
+use crate::events::Events;
+use crate::layout::{LayoutConfig, MigrationFile};
+use crate::meta_commands::MetaCommandPolicy;
+use crate::migrator::git_config;
+use crate::naming::NamingConfig;
+use crate::notify::NotificationsConfig;
+use crate::packages::PackageConfig;
 use crate::pinfile::LockData;
-use crate::variables::Variables;
+use crate::policy::PolicyConfig;
+use crate::schedule::MaintenanceWindow;
+use crate::schema_docs::SchemaDocsConfig;
+use crate::scrub::ScrubConfig;
+use crate::secrets::SecretSource;
+use crate::signing::SigningConfig;
+use crate::store::pinner::cache::{BlobCache, PinCacheConfig};
+use crate::store::pinner::refs;
+use crate::telemetry::{TelemetrySession, TelemetrySetting, TelemetrySink};
+use crate::template::TemplateLimits;
+use crate::tracing::{Tracer, TracingConfig};
+use crate::transcript::TranscriptConfig;
+use crate::validate::{self, ConfigDiagnostic, ConfigSource, Severity};
+use crate::variables::{self, VariableSources, Variables};
 use anyhow::{anyhow, Context, Result};
-use opendal::Operator;
-use std::collections::HashMap;
+use opendal::{Operator, Scheme};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
-static PINFILE_LOCK_NAME: &str = "lock.toml";
-
 // 1. The "Blueprint" struct. Use this for Deserialization.
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ConfigLoaderSaver {
     pub spawn_folder: String,
     pub target: Option<String>,
@@ -18,12 +44,163 @@ pub struct ConfigLoaderSaver {
     pub targets: Option<HashMap<String, TargetConfig>>,
     /// Unique project identifier for telemetry (UUID string)
     pub project_id: Option<String>,
-    /// Set to false to disable telemetry
+    /// Set to false to disable telemetry, or a `[telemetry]` section
     #[serde(default = "default_telemetry", skip_serializing_if = "Option::is_none")]
-    pub telemetry: Option<bool>,
+    pub telemetry: Option<TelemetrySetting>,
+    /// Source of the `tenants` template global
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenants: Option<TenantSource>,
+    /// Additional template helpers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<TemplateConfig>,
+    /// Pin unpinned migrations automatically the first time they are applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_on_apply: Option<bool>,
+    /// Open new migrations in the editor, as with `migration new --edit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edit_new_migrations: Option<bool>,
+    /// Naming policy for new migrations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub naming: Option<NamingConfig>,
+    /// Apply migrations older than already applied ones without
+    /// `--allow-out-of-order`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_out_of_order: Option<bool>,
+    /// Who is recorded as applying migrations, instead of git's
+    /// `user.email` or the OS user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Local cache for objects read from a remote pinned store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_cache: Option<PinCacheConfig>,
+    /// Keys for signing migrations and verifying their signatures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<SigningConfig>,
+    /// Write a transcript of each migration apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcripts: Option<TranscriptConfig>,
+    /// Settings shared by every target in an environment, by environment name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environments: Option<HashMap<String, EnvironmentConfig>>,
+    /// Default template variables, overridden by every other source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<toml::Table>,
+    /// External component packages, by name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packages: Option<BTreeMap<String, PackageConfig>>,
+    /// psql meta-commands that rendered migrations may not use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta_commands: Option<MetaCommandPolicy>,
+    /// Rules that the statements of rendered migrations must follow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyConfig>,
+    /// Warnings about statements that lock large tables, before apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_advisor: Option<LockAdvisorConfig>,
+    /// Webhooks told about apply and adopt outcomes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifications: Option<NotificationsConfig>,
+    /// Where OpenTelemetry spans of each command are sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracing: Option<TracingConfig>,
+    /// Local log of the commands that change the project or a database
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<AuditConfig>,
+    /// Names of the migrations folder and each migration's files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<LayoutConfig>,
+    /// Queue of apply requests for `spawn agent`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<AgentConfig>,
+    /// Docs of the target's tables written by `spawn schema docs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_docs: Option<SchemaDocsConfig>,
+    /// Rules and allowed targets of `spawn scrub`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scrub: Option<ScrubConfig>,
+}
+
+/// Settings for the targets of one environment, in the
+/// `[environments.<name>]` section.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EnvironmentConfig {
+    /// Commands that may be run against targets in this environment, such
+    /// as `"migration status"`, a group such as `"test"`, or a subcommand
+    /// such as `"status"`. Any command may be run when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_commands: Option<Vec<String>>,
+    /// Variables file (JSON, TOML or YAML) for targets in this environment,
+    /// overriding the `[variables]` defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables_file: Option<String>,
+    /// psql meta-command policy for this environment, replacing the
+    /// project's `[meta_commands]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta_commands: Option<MetaCommandPolicy>,
+    /// Daily UTC window, such as `"02:00-04:00"`, that `migration apply`
+    /// waits for before applying to targets in this environment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance_window: Option<MaintenanceWindow>,
+}
+
+impl EnvironmentConfig {
+    /// Whether `command`, a full command name such as `migration apply`, is
+    /// allowed.
+    pub fn allows(&self, command: &str) -> bool {
+        let Some(allowed) = &self.allowed_commands else {
+            return true;
+        };
+        allowed.iter().map(|a| a.trim()).any(|a| {
+            a == command
+                || command.rsplit(' ').next() == Some(a)
+                || command
+                    .strip_prefix(a)
+                    .is_some_and(|rest| rest.starts_with(' '))
+        })
+    }
+}
+
+/// Optional template helpers, configured in the `[template]` section.
+///
+/// Helpers that make output vary between renders (such as the time
+/// functions) are off by default, since they stop a pinned migration from
+/// producing the same SQL every time it is built.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TemplateConfig {
+    /// Values exposed to templates under `constants`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub constants: BTreeMap<String, toml::Value>,
+    /// Register the `now()` and `timestamp()` functions
+    #[serde(default)]
+    pub time_functions: bool,
+    /// Register the `sha256` and `xxh3` filters
+    #[serde(default)]
+    pub hash_filters: bool,
+    /// Refuse functions whose output differs between renders, such as
+    /// `gen_uuid_v4()` and `now()`, set by `--deterministic`
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Where `secret("PATH")` looks up values. Defaults to environment
+    /// variables.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<SecretSource>,
+    /// Limits on rendering, in the `[template.limits]` section
+    #[serde(default)]
+    pub limits: TemplateLimits,
+}
+
+/// Where the list of tenants exposed to templates as `tenants` comes from.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TenantSource {
+    /// A fixed list of tenant names
+    List { list: Vec<String> },
+    /// A query run against the target database. The first column of each
+    /// returned row is used as a tenant name.
+    Query { query: String },
 }
 
-fn default_telemetry() -> Option<bool> {
+fn default_telemetry() -> Option<TelemetrySetting> {
     None
 }
 
@@ -36,7 +213,44 @@ impl ConfigLoaderSaver {
             environment: self.environment,
             targets: self.targets.unwrap_or_default(),
             project_id: self.project_id,
-            telemetry: self.telemetry.unwrap_or(true),
+            telemetry: self.telemetry.as_ref().is_none_or(|t| t.enabled()),
+            telemetry_endpoint: self
+                .telemetry
+                .as_ref()
+                .and_then(|t| t.endpoint())
+                .map(str::to_string),
+            tenants: self.tenants,
+            tenant_filter: Vec::new(),
+            wait_for_lock: None,
+            deny_warnings: false,
+            template: self.template.unwrap_or_default(),
+            pin_on_apply: self.pin_on_apply.unwrap_or(false),
+            edit_new_migrations: self.edit_new_migrations.unwrap_or(false),
+            naming: self.naming.unwrap_or_default(),
+            allow_out_of_order: self.allow_out_of_order.unwrap_or(false),
+            identity: self.identity,
+            pin_cache: Some(self.pin_cache.unwrap_or_default()).filter(|c| c.enabled),
+            signing: self.signing.unwrap_or_default(),
+            transcripts: self.transcripts.filter(|t| t.enabled),
+            environments: self.environments.unwrap_or_default(),
+            variables: self.variables.unwrap_or_default(),
+            packages: self.packages.unwrap_or_default(),
+            meta_commands: self.meta_commands.unwrap_or_default(),
+            policy: self.policy.unwrap_or_default(),
+            lock_advisor: self.lock_advisor.unwrap_or_default(),
+            notifications: self.notifications.unwrap_or_default(),
+            tracer: Tracer::new(self.tracing.as_ref().filter(|t| t.enabled)),
+            audit: Some(self.audit.unwrap_or_default()).filter(|a| a.enabled),
+            audit_session: AuditSession::default(),
+            layout: self.layout.unwrap_or_default(),
+            agent: self.agent.unwrap_or_default(),
+            schema_docs: self.schema_docs.unwrap_or_default(),
+            scrub: self.scrub.unwrap_or_default(),
+            annotate: AnnotationFormat::None,
+            strict: false,
+            telemetry_session: TelemetrySession::default(),
+            events: Events::default(),
+            source: None,
             base_fs,
             spawn_fs,
         }
@@ -92,9 +306,10 @@ impl ConfigLoaderSaver {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct FolderPather {
     pub spawn_folder: String,
+    pub layout: LayoutConfig,
 }
 
 impl FolderPather {
@@ -114,9 +329,38 @@ impl FolderPather {
         s
     }
 
+    /// Folder that `spawn package update` fetches packages into.
+    pub fn vendor_folder(&self) -> String {
+        let mut s = self.spawn_folder_path().to_string();
+        s.push_str("/vendor");
+        s
+    }
+
+    pub fn packages_lock_path(&self) -> String {
+        let mut s = self.spawn_folder_path().to_string();
+        s.push('/');
+        s.push_str(crate::packages::PACKAGES_LOCK_FILE);
+        s
+    }
+
     pub fn migrations_folder(&self) -> String {
         let mut s = self.spawn_folder_path().to_string();
-        s.push_str("/migrations");
+        s.push('/');
+        s.push_str(self.layout.migrations_folder());
+        s
+    }
+
+    pub fn releases_folder(&self) -> String {
+        let mut s = self.spawn_folder_path().to_string();
+        s.push_str("/releases");
+        s
+    }
+
+    pub fn release_file_path(&self, release: &str) -> String {
+        let mut s = self.releases_folder();
+        s.push('/');
+        s.push_str(release);
+        s.push_str(".toml");
         s
     }
 
@@ -126,19 +370,65 @@ impl FolderPather {
         s
     }
 
+    pub fn templates_folder(&self) -> String {
+        let mut s = self.spawn_folder_path().to_string();
+        s.push_str("/templates");
+        s
+    }
+
+    pub fn template_file_path(&self, template: &str) -> String {
+        let mut s = self.templates_folder();
+        s.push('/');
+        s.push_str(template);
+        s.push_str(".sql");
+        s
+    }
+
+    /// The migration's own folder. With the flat layout this only holds
+    /// what is kept beside the script, such as includes and variables files.
+    /// The pather for archived migrations, which are laid out as the others
+    /// are but within the `_archive` folder of the migrations folder.
+    pub fn archive(&self) -> FolderPather {
+        let mut layout = self.layout.clone();
+        layout.migrations_folder = Some(format!(
+            "{}/{}",
+            self.layout.migrations_folder(),
+            crate::layout::ARCHIVE_FOLDER
+        ));
+        FolderPather {
+            layout,
+            ..self.clone()
+        }
+    }
+
+    /// Folder of the migration `script_path`, within its shard folder when
+    /// migrations are sharded. With the flat layout, the migration's files
+    /// are named after this path rather than kept in it.
     pub fn migration_folder(&self, script_path: &str) -> String {
         let mut s = self.migrations_folder();
         s.push('/');
+        if let Some(shard) = self.layout.shard_folder(script_path) {
+            s.push_str(&shard);
+            s.push('/');
+        }
         s.push_str(script_path);
         s
     }
 
-    pub fn migration_script_file_path(&self, script_path: &str) -> String {
+    /// Path of the file of a migration named `file`, such as `lock.toml`:
+    /// inside the migration's folder, or beside it as `<migration>.<file>`
+    /// with the flat layout.
+    pub fn migration_file_path(&self, script_path: &str, file: &str) -> String {
         let mut s = self.migration_folder(script_path);
-        s.push_str("/up.sql");
+        s.push(if self.layout.flat { '.' } else { '/' });
+        s.push_str(file);
         s
     }
 
+    pub fn migration_script_file_path(&self, script_path: &str) -> String {
+        self.migration_file_path(script_path, self.layout.script())
+    }
+
     pub fn test_folder(&self, test_path: &str) -> String {
         let mut s = self.tests_folder();
         s.push('/');
@@ -153,12 +443,277 @@ impl FolderPather {
     }
 
     pub fn migration_lock_file_path(&self, script_path: &str) -> String {
-        let mut s = self.migrations_folder();
-        s.push('/');
-        s.push_str(script_path);
-        s.push('/');
-        s.push_str(PINFILE_LOCK_NAME);
-        s
+        self.migration_file_path(script_path, self.layout.lock_file())
+    }
+
+    pub fn migration_metadata_file_path(&self, script_path: &str) -> String {
+        self.migration_file_path(script_path, crate::migrator::METADATA_FILE_NAME)
+    }
+
+    pub fn migration_signature_file_path(&self, script_path: &str) -> String {
+        self.migration_file_path(script_path, crate::signing::SIGNATURE_FILE_NAME)
+    }
+
+    /// Files in a migration's folder that are never pinned as local
+    /// includes.
+    pub fn migration_folder_excludes(&self) -> Vec<&str> {
+        vec![
+            self.layout.script(),
+            self.layout.lock_file(),
+            crate::signing::SIGNATURE_FILE_NAME,
+            crate::migrator::METADATA_FILE_NAME,
+        ]
+    }
+
+    /// Folder that includes starting with `./` are resolved in when
+    /// rendering `script_path`: the migration's own folder for a migration
+    /// script, otherwise the folder holding the script.
+    pub fn local_include_folder(&self, script_path: &str) -> Option<String> {
+        if self.layout.flat {
+            let prefix = format!("{}/", self.migrations_folder());
+            if let Some((name, MigrationFile::Script)) = script_path
+                .strip_prefix(&prefix)
+                .and_then(|rest| self.layout.parse_path(rest))
+            {
+                return Some(self.migration_folder(name));
+            }
+        }
+        script_path
+            .rsplit_once('/')
+            .map(|(folder, _)| folder.to_string())
+    }
+}
+
+/// Builds a [`Config`] in code, for tests and embedders that have no
+/// `spawn.toml`. Settings left unset take the same defaults as when they are
+/// missing from `spawn.toml`, except that no environment override is set.
+///
+/// ```
+/// # fn example(target: spawn_db::engine::TargetConfig) -> anyhow::Result<()> {
+/// use opendal::{services::Memory, Operator};
+/// use spawn_db::config::ConfigBuilder;
+///
+/// let config = ConfigBuilder::new(Operator::new(Memory::default())?.finish())
+///     .with_spawn_folder("/db")
+///     .with_target("local", target)
+///     .with_telemetry(false)
+///     .build()?;
+/// assert_eq!(Some("local".to_string()), config.target);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    loader: ConfigLoaderSaver,
+    base_fs: Operator,
+    spawn_fs: Option<Operator>,
+}
+
+impl ConfigBuilder {
+    /// A config reading the project from `spawn/` on `base_fs`.
+    pub fn new(base_fs: Operator) -> Self {
+        Self::from_loader(
+            base_fs,
+            ConfigLoaderSaver {
+                spawn_folder: "spawn".to_string(),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Starts from settings already read, such as from a `spawn.toml`.
+    pub fn from_loader(base_fs: Operator, loader: ConfigLoaderSaver) -> Self {
+        Self {
+            loader,
+            base_fs,
+            spawn_fs: None,
+        }
+    }
+
+    /// The operator given to [`ConfigBuilder::new`].
+    pub fn operator(&self) -> &Operator {
+        &self.base_fs
+    }
+
+    /// Folder holding the project, relative to the root of the operator.
+    pub fn with_spawn_folder(mut self, spawn_folder: &str) -> Self {
+        self.loader.spawn_folder = spawn_folder.to_string();
+        self
+    }
+
+    /// Reads the project through `spawn_fs` rather than the operator given
+    /// to [`ConfigBuilder::new`].
+    pub fn with_spawn_fs(mut self, spawn_fs: Operator) -> Self {
+        self.spawn_fs = Some(spawn_fs);
+        self
+    }
+
+    /// Adds a target, replacing any of the same name. The first target added
+    /// is selected unless [`ConfigBuilder::with_selected_target`] is used.
+    pub fn with_target(mut self, name: &str, target: TargetConfig) -> Self {
+        self.loader
+            .targets
+            .get_or_insert_default()
+            .insert(name.to_string(), target);
+        self.loader.target.get_or_insert_with(|| name.to_string());
+        self
+    }
+
+    pub fn with_selected_target(mut self, name: &str) -> Self {
+        self.loader.target = Some(name.to_string());
+        self
+    }
+
+    /// Overrides the environment of the selected target.
+    pub fn with_environment(mut self, environment: &str) -> Self {
+        self.loader.environment = Some(environment.to_string());
+        self
+    }
+
+    /// Settings for the targets of the environment `name`.
+    pub fn with_environment_config(mut self, name: &str, config: EnvironmentConfig) -> Self {
+        self.loader
+            .environments
+            .get_or_insert_default()
+            .insert(name.to_string(), config);
+        self
+    }
+
+    pub fn with_telemetry(mut self, setting: impl Into<TelemetrySetting>) -> Self {
+        self.loader.telemetry = Some(setting.into());
+        self
+    }
+
+    pub fn with_tenants(mut self, tenants: TenantSource) -> Self {
+        self.loader.tenants = Some(tenants);
+        self
+    }
+
+    pub fn with_template(mut self, template: TemplateConfig) -> Self {
+        self.loader.template = Some(template);
+        self
+    }
+
+    pub fn with_naming(mut self, naming: NamingConfig) -> Self {
+        self.loader.naming = Some(naming);
+        self
+    }
+
+    pub fn with_pin_on_apply(mut self, pin_on_apply: bool) -> Self {
+        self.loader.pin_on_apply = Some(pin_on_apply);
+        self
+    }
+
+    /// Who is recorded as applying and adopting migrations.
+    pub fn with_identity(mut self, identity: &str) -> Self {
+        self.loader.identity = Some(identity.to_string());
+        self
+    }
+
+    pub fn with_allow_out_of_order(mut self, allow_out_of_order: bool) -> Self {
+        self.loader.allow_out_of_order = Some(allow_out_of_order);
+        self
+    }
+
+    pub fn with_signing(mut self, signing: SigningConfig) -> Self {
+        self.loader.signing = Some(signing);
+        self
+    }
+
+    pub fn with_transcripts(mut self, transcripts: TranscriptConfig) -> Self {
+        self.loader.transcripts = Some(transcripts);
+        self
+    }
+
+    /// Default template variables, as in the `[variables]` table.
+    pub fn with_variables(mut self, variables: toml::Table) -> Self {
+        self.loader.variables = Some(variables);
+        self
+    }
+
+    /// Adds an external component package, replacing any of the same name.
+    pub fn with_package(mut self, name: &str, package: PackageConfig) -> Self {
+        self.loader
+            .packages
+            .get_or_insert_default()
+            .insert(name.to_string(), package);
+        self
+    }
+
+    /// Policy on psql meta-commands, as in the `[meta_commands]` section.
+    pub fn with_meta_commands(mut self, policy: MetaCommandPolicy) -> Self {
+        self.loader.meta_commands = Some(policy);
+        self
+    }
+
+    /// Rules on the statements of migrations, as in `[[policy.rule]]`
+    /// sections.
+    pub fn with_policy(mut self, policy: PolicyConfig) -> Self {
+        self.loader.policy = Some(policy);
+        self
+    }
+
+    /// Settings for the lock advisor, as in the `[lock_advisor]` section.
+    pub fn with_lock_advisor(mut self, lock_advisor: LockAdvisorConfig) -> Self {
+        self.loader.lock_advisor = Some(lock_advisor);
+        self
+    }
+
+    /// Webhooks to notify of apply and adopt outcomes, as in the
+    /// `[[notifications.webhook]]` sections.
+    pub fn with_notifications(mut self, notifications: NotificationsConfig) -> Self {
+        self.loader.notifications = Some(notifications);
+        self
+    }
+
+    /// Sends OpenTelemetry spans of the command, as in the `[tracing]`
+    /// section.
+    pub fn with_tracing(mut self, tracing: TracingConfig) -> Self {
+        self.loader.tracing = Some(tracing);
+        self
+    }
+
+    /// Where commands are logged, as in the `[audit]` section.
+    pub fn with_audit(mut self, audit: AuditConfig) -> Self {
+        self.loader.audit = Some(audit);
+        self
+    }
+
+    /// Where apply requests are queued, as in the `[agent]` section.
+    pub fn with_agent(mut self, agent: AgentConfig) -> Self {
+        self.loader.agent = Some(agent);
+        self
+    }
+
+    /// Where and when schema docs are written, as in the `[schema_docs]`
+    /// section.
+    pub fn with_schema_docs(mut self, schema_docs: SchemaDocsConfig) -> Self {
+        self.loader.schema_docs = Some(schema_docs);
+        self
+    }
+
+    /// Rules and allowed targets of `spawn scrub`, as in the `[scrub]`
+    /// section.
+    pub fn with_scrub(mut self, scrub: ScrubConfig) -> Self {
+        self.loader.scrub = Some(scrub);
+        self
+    }
+
+    /// How migrations are laid out, as in the `[layout]` section.
+    pub fn with_layout(mut self, layout: LayoutConfig) -> Self {
+        self.loader.layout = Some(layout);
+        self
+    }
+
+    /// Checks the settings and builds the config.
+    pub fn build(self) -> Result<Config> {
+        if let Some(endpoint) = self.loader.telemetry.as_ref().and_then(|t| t.endpoint()) {
+            TelemetrySink::from_endpoint(Some(endpoint)).ok_or(anyhow!(
+                "unsupported telemetry.endpoint '{}', expected an http(s):// or file:// URL",
+                endpoint
+            ))?;
+        }
+        Ok(self.loader.build(self.base_fs, self.spawn_fs))
     }
 }
 
@@ -172,6 +727,79 @@ pub struct Config {
     pub project_id: Option<String>,
     /// Whether telemetry is enabled in config
     pub telemetry: bool,
+    /// Where telemetry is sent, from `telemetry.endpoint`
+    pub telemetry_endpoint: Option<String>,
+    pub tenants: Option<TenantSource>,
+    /// Restrict the `tenants` template global to these tenants. Empty means
+    /// no restriction.
+    pub tenant_filter: Vec<String>,
+    /// How long applies wait for another apply to release the migration
+    /// lock, set from `--wait-for-lock`. None fails at once.
+    pub wait_for_lock: Option<std::time::Duration>,
+    /// Fail applies once a migration the server sent warnings about has
+    /// been applied, set from `--deny-warnings`
+    pub deny_warnings: bool,
+    pub template: TemplateConfig,
+    /// Pin unpinned migrations automatically the first time they are applied
+    pub pin_on_apply: bool,
+    /// Open new migrations in the editor unless `--no-edit` is given
+    pub edit_new_migrations: bool,
+    pub naming: NamingConfig,
+    /// Apply migrations older than already applied ones, with a warning
+    pub allow_out_of_order: bool,
+    /// Who is recorded as applying migrations, from `identity`. See
+    /// [`Config::operator_identity`]
+    pub identity: Option<String>,
+    /// Cache settings for pinned objects, or None when caching is disabled
+    /// (e.g. with `--no-cache`).
+    pub pin_cache: Option<PinCacheConfig>,
+    pub signing: SigningConfig,
+    /// Where apply transcripts are written, or None when they are not
+    pub transcripts: Option<TranscriptConfig>,
+    pub environments: HashMap<String, EnvironmentConfig>,
+    /// Default template variables, from `[variables]`
+    pub variables: toml::Table,
+    /// External component packages, from `[packages]`
+    pub packages: BTreeMap<String, PackageConfig>,
+    /// psql meta-command policy, from `[meta_commands]`. Environments may
+    /// replace it, see [`Config::meta_command_policy`]
+    pub meta_commands: MetaCommandPolicy,
+    /// Rules on the statements of migrations, from `[[policy.rule]]`
+    pub policy: PolicyConfig,
+    /// Lock advisor settings, from `[lock_advisor]`. `--advise-locks` enables
+    /// it for one apply
+    pub lock_advisor: LockAdvisorConfig,
+    /// Webhooks to notify, from `[[notifications.webhook]]`. Cleared by
+    /// `--no-notify`
+    pub notifications: NotificationsConfig,
+    /// Collects the spans of the running command when `[tracing]` is
+    /// enabled
+    pub tracer: Tracer,
+    /// Where commands that change the project or a database are logged, or
+    /// None when the audit log is disabled
+    pub audit: Option<AuditConfig>,
+    /// Collects the migrations touched by the running command, for its
+    /// audit entry
+    pub audit_session: AuditSession,
+    /// Names of the migrations folder and each migration's files, from
+    /// `[layout]`
+    pub layout: LayoutConfig,
+    /// Queue of apply requests, from `[agent]`
+    pub agent: AgentConfig,
+    /// Where and when schema docs are written, from `[schema_docs]`
+    pub schema_docs: SchemaDocsConfig,
+    /// Rules and allowed targets of `spawn scrub`, from `[scrub]`
+    pub scrub: ScrubConfig,
+    /// How commands annotate problems for CI, set from `--annotate` or `CI`
+    pub annotate: AnnotationFormat,
+    /// Treat warnings as failures, set from `--strict`
+    pub strict: bool,
+    /// Collects telemetry for the sub-operations of the running command
+    pub telemetry_session: TelemetrySession,
+    /// Where events of the running command are sent
+    pub events: Events,
+    /// The file the config was loaded from, for [`Config::validate`]
+    source: Option<ConfigSource>,
 
     // base_fs is the operator we used to load config, and may be the one we use
     // for all other interactions too.
@@ -186,17 +814,57 @@ impl Config {
     pub fn pather(&self) -> FolderPather {
         FolderPather {
             spawn_folder: self.spawn_folder.clone(),
+            layout: self.layout.clone(),
         }
     }
 
     pub async fn new_engine(&self) -> Result<Box<dyn Engine>> {
         let target_config = self.target_config()?;
+        let history = self.history_file(&target_config)?;
 
         match target_config.engine {
-            EngineType::PostgresPSQL => Ok(PSQL::new(&target_config).await?),
+            EngineType::PostgresPSQL => Ok(PSQL::new(&target_config, history).await?),
+            EngineType::Memory => Ok(Box::new(MemoryEngine::new(&target_config, history))),
         }
     }
 
+    /// Creates an engine without checking the version of its internal
+    /// schema, for `spawn system upgrade`.
+    pub async fn new_engine_for_upgrade(&self) -> Result<Box<dyn Engine>> {
+        let target_config = self.target_config()?;
+        let history = self.history_file(&target_config)?;
+
+        match target_config.engine {
+            EngineType::PostgresPSQL => Ok(PSQL::new_for_upgrade(&target_config, history).await?),
+            EngineType::Memory => Ok(Box::new(MemoryEngine::new(&target_config, history))),
+        }
+    }
+
+    /// The history file the target's `[history]` keeps its migration
+    /// history in, if any.
+    fn history_file(&self, target: &TargetConfig) -> Result<Option<Box<dyn HistoryBackend>>> {
+        let Some(history) = &target.history else {
+            return Ok(None);
+        };
+        let Some(file) = &history.file else {
+            return Ok(None);
+        };
+        if history.command.is_some()
+            || history.url.is_some()
+            || history.spawn_database.is_some()
+            || history.spawn_schema.is_some()
+        {
+            return Err(anyhow!(
+                "history.file keeps the history out of the database, so cannot be combined with history.command, url, spawn_database or spawn_schema"
+            ));
+        }
+        let path = format!("{}/{}", self.pather().spawn_folder_path(), file);
+        Ok(Some(Box::new(FileHistory::new(
+            self.operator().clone(),
+            &path,
+        ))))
+    }
+
     pub fn target_config(&self) -> Result<TargetConfig> {
         let target_name = self.target.as_ref().ok_or(anyhow!("no target selected"))?;
         let mut conf = self
@@ -208,13 +876,200 @@ impl Config {
         if let Some(env) = &self.environment {
             conf.environment = env.clone();
         }
+        conf.wait_for_lock = self.wait_for_lock;
 
         Ok(conf)
     }
 
+    /// The target called `name`, or else the only target in the environment
+    /// `name`, for commands that also read another target's database.
+    pub fn resolve_target(&self, name: &str) -> Result<String> {
+        if self.targets.contains_key(name) {
+            return Ok(name.to_string());
+        }
+        let mut in_environment: Vec<&String> = self
+            .targets
+            .iter()
+            .filter(|(_, t)| t.environment == name)
+            .map(|(target, _)| target)
+            .collect();
+        in_environment.sort();
+        match in_environment.as_slice() {
+            [target] => Ok(target.to_string()),
+            [] => Err(anyhow!(
+                "no target or environment named '{}' in the config",
+                name
+            )),
+            targets => Err(anyhow!(
+                "the {} environment has more than one target ({}), name one of them instead",
+                name,
+                targets
+                    .iter()
+                    .map(|t| t.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// This config with the target [`resolved`](Config::resolve_target) from
+    /// `name` selected instead, and its own environment.
+    /// Who is running spawn, as recorded in the migration history and the
+    /// audit log: `identity` from the config, or else git's `user.email`,
+    /// or else the OS user.
+    pub fn operator_identity(&self) -> Option<String> {
+        self.identity
+            .clone()
+            .filter(|identity| !identity.trim().is_empty())
+            .or_else(|| git_config("user.email"))
+            .or_else(current_user)
+    }
+
+    pub fn for_target(&self, name: &str) -> Result<Config> {
+        let mut config = self.clone();
+        config.target = Some(self.resolve_target(name)?);
+        config.environment = None;
+        Ok(config)
+    }
+
+    /// Refuses `command` when the selected target's environment does not
+    /// allow it, or when `--environment` names one that does not. Both are
+    /// checked, so that `--environment` cannot lift the restrictions of the
+    /// target's own environment. Commands run without a target are not
+    /// restricted.
+    pub fn check_command_allowed(&self, command: &str) -> Result<()> {
+        let Some(target_config) = self
+            .target
+            .as_ref()
+            .and_then(|target| self.targets.get(target))
+        else {
+            return Ok(());
+        };
+        let environments = std::iter::once(&target_config.environment).chain(
+            self.environment
+                .as_ref()
+                .filter(|environment| **environment != target_config.environment),
+        );
+        for environment in environments {
+            let Some(env_config) = self.environments.get(environment) else {
+                continue;
+            };
+            if env_config.allows(command) {
+                continue;
+            }
+            return Err(anyhow!(
+                "`spawn {}` is not allowed in the {} environment (allowed: {}). \
+                 Run it through your deployment pipeline instead.",
+                command,
+                environment,
+                env_config
+                    .allowed_commands
+                    .as_deref()
+                    .unwrap_or_default()
+                    .join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Finds the config file `path`. A bare file name missing from the
+    /// operator's root is looked for in each parent folder in turn, as git
+    /// and cargo do, and returned relative to the root, such as
+    /// `../../spawn.toml`. Only local filesystems have parents to search.
+    pub async fn find(path: &str, op: &Operator) -> Result<Option<String>> {
+        if op.exists(path).await? {
+            return Ok(Some(path.to_string()));
+        }
+        let name = path.trim_start_matches("./");
+        if name.contains('/') || op.info().scheme() != Scheme::Fs {
+            return Ok(None);
+        }
+
+        let root = std::path::absolute(op.info().root())?;
+        let mut candidate = name.to_string();
+        for _ in root.ancestors().skip(1) {
+            candidate = format!("../{}", candidate);
+            if op.exists(&candidate).await.unwrap_or(false) {
+                return Ok(Some(candidate));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Loads the config from `path`, or from the nearest parent folder that
+    /// has it (see [`Config::find`]), refusing it if
+    /// [`validate`](crate::validate) finds errors. Warnings are left to
+    /// [`Config::validate`]. A relative `spawn_folder` is resolved against
+    /// the folder of the config file.
     pub async fn load(path: &str, op: &Operator, target: Option<String>) -> Result<Config> {
+        let path = Config::find(path, op)
+            .await?
+            .unwrap_or_else(|| path.to_string());
+        let path = path.as_str();
+
+        // A missing file is reported by ConfigLoaderSaver::load.
+        let source = match op.read(path).await {
+            Ok(bytes) => Some(ConfigSource {
+                file: path.to_string(),
+                text: String::from_utf8(bytes.to_vec())?,
+            }),
+            Err(_) => None,
+        };
+        if let Some(source) = &source {
+            let errors: Vec<String> = validate::check(source)
+                .iter()
+                .filter(|d| d.severity == Severity::Error)
+                .map(|d| format!("  {}", d))
+                .collect();
+            if !errors.is_empty() {
+                return Err(anyhow!("invalid config:\n{}", errors.join("\n")));
+            }
+        }
+
         let config_loader = ConfigLoaderSaver::load(path, op, target).await?;
-        Ok(config_loader.build(op.clone(), None))
+        let mut config = ConfigBuilder::from_loader(op.clone(), config_loader).build()?;
+        config.source = source;
+        if let Some((folder, _)) = path.rsplit_once('/') {
+            config.relative_to(folder);
+        }
+        Ok(config)
+    }
+
+    /// Resolves a relative `spawn_folder`, the paths of local packages and
+    /// the directory of file secrets against `folder`, the folder of the
+    /// config file. A spawn folder on another store is left as it is.
+    fn relative_to(&mut self, folder: &str) {
+        let folder = folder.trim_end_matches('/');
+        if folder.is_empty() || folder == "." {
+            return;
+        }
+        let resolve = |path: &str| match path.starts_with('/') {
+            true => path.to_string(),
+            false => format!("{}/{}", folder, path.trim_start_matches("./")),
+        };
+        // Secret files are read from the local filesystem wherever the
+        // spawn folder is.
+        if let Some(SecretSource::File { directory }) = &mut self.template.secrets {
+            *directory = resolve(directory);
+        }
+        if self.spawn_fs.is_some() {
+            return;
+        }
+        self.spawn_folder = resolve(&self.spawn_folder);
+        for package in self.packages.values_mut() {
+            if let Some(path) = &package.path {
+                package.path = Some(resolve(path));
+            }
+        }
+    }
+
+    /// Problems with the config file this was loaded from, such as unknown
+    /// keys. Configs not loaded from a file have none.
+    pub fn validate(&self) -> Vec<ConfigDiagnostic> {
+        self.source
+            .as_ref()
+            .map(validate::check)
+            .unwrap_or_default()
     }
 
     pub fn operator(&self) -> &Operator {
@@ -225,14 +1080,78 @@ impl Config {
         }
     }
 
+    /// Returns the local cache to read pinned objects through. Only remote
+    /// stores are cached, since reading from a local folder is already as
+    /// fast as reading from the cache.
+    pub fn pin_cache(&self) -> Result<Option<Arc<BlobCache>>> {
+        let Some(cache_cfg) = &self.pin_cache else {
+            return Ok(None);
+        };
+        match self.operator().info().scheme() {
+            Scheme::Fs | Scheme::Memory => Ok(None),
+            _ => Ok(Some(Arc::new(BlobCache::new(cache_cfg)?))),
+        }
+    }
+
     pub async fn load_lock_file(&self, lock_file_path: &str) -> Result<LockData> {
         let contents = self.operator().read(lock_file_path).await?.to_bytes();
         let contents = String::from_utf8(contents.to_vec())?;
-        let lock_data: LockData = toml::from_str(&contents)?;
+        let mut lock_data: LockData = toml::from_str(&contents)?;
+
+        // A tag missing from this store, such as after `spawn pin import`,
+        // is fine as long as the lock file also records its root.
+        if let Some(tag) = &lock_data.tag {
+            let root = refs::read_ref(self.operator(), &self.pather().pinned_folder(), tag)
+                .await
+                .context(format!("could not resolve the tag of {}", lock_file_path))?;
+            match root {
+                Some(root) if lock_data.pin.is_empty() => lock_data.pin = root,
+                Some(root) if lock_data.pin != root => {
+                    return Err(anyhow!(
+                        "{} pins {}, but its tag '{}' refers to {}",
+                        lock_file_path,
+                        lock_data.pin,
+                        tag,
+                        root
+                    ));
+                }
+                Some(_) => {}
+                None if lock_data.pin.is_empty() => {
+                    return Err(anyhow!(
+                        "{} refers to tag '{}', which is not in the pinned store",
+                        lock_file_path,
+                        tag
+                    ));
+                }
+                None => {}
+            }
+        } else if lock_data.pin.is_empty() {
+            return Err(anyhow!("{} has neither a pin nor a tag", lock_file_path));
+        }
 
         Ok(lock_data)
     }
 
+    /// The psql meta-command policy for the selected target: its
+    /// environment's `meta_commands`, or the project's.
+    pub fn meta_command_policy(&self) -> Result<&MetaCommandPolicy> {
+        let environment = self.target_config()?.environment;
+        Ok(self
+            .environments
+            .get(&environment)
+            .and_then(|e| e.meta_commands.as_ref())
+            .unwrap_or(&self.meta_commands))
+    }
+
+    /// The environment policy rules are checked for: the selected target's,
+    /// or the `--environment` given without a target.
+    pub fn policy_environment(&self) -> Option<String> {
+        match &self.target {
+            Some(_) => self.target_config().ok().map(|t| t.environment),
+            None => self.environment.clone(),
+        }
+    }
+
     /// Load variables from a file path.
     /// The file type is determined by the file extension.
     pub async fn load_variables_from_path(&self, path: &str) -> Result<Variables> {
@@ -249,4 +1168,31 @@ impl Config {
         Variables::from_str(extension, &content_str)
             .context(format!("Failed to parse variables file '{}'", path))
     }
+
+    /// Every source of template variables for the selected target, other
+    /// than the migration folder, with `cli` given by `--variables`.
+    pub async fn variable_sources(&self, cli: Option<Variables>) -> Result<VariableSources> {
+        let environment = self.target_config()?.environment;
+        let environment_file = match self
+            .environments
+            .get(&environment)
+            .and_then(|e| e.variables_file.as_deref())
+        {
+            Some(path) => Some(self.load_variables_from_path(path).await.context(format!(
+                "could not load variables_file of environment '{}'",
+                environment
+            ))?),
+            None => None,
+        };
+
+        Ok(VariableSources {
+            defaults: Some(&self.variables)
+                .filter(|v| !v.is_empty())
+                .map(|v| Variables::Toml(toml::Value::Table(v.clone()))),
+            environment_file,
+            migration_file: None,
+            env: variables::from_env(std::env::vars()),
+            cli,
+        })
+    }
 }
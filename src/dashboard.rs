@@ -0,0 +1,420 @@
+//! State and drawing of `spawn ui`, the terminal dashboard. Kept apart from
+//! the terminal so that keys can be fed in and the drawn lines checked in
+//! tests; `commands::ui` reads the keys, carries out the [`Action`]s and
+//! draws the lines.
+
+use crate::commands::migration::{status_label, HistoryRecord, MigrationStatusRow};
+use crate::engine::MigrationStatus;
+use console::{style, truncate_str, Key};
+
+/// Lines above and below the list: the title, a blank line, and the
+/// message and key help at the bottom.
+const CHROME_LINES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    /// Every migration with its status
+    Status,
+    /// The history of the selected migration
+    Detail,
+    /// The results of the last test run
+    Tests,
+}
+
+/// What the dashboard asks the command to do after a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Quit,
+    /// Read the status again
+    Refresh,
+    /// Apply the migration, which the user has confirmed
+    Apply(String),
+    Pin(String),
+    /// Load the history of the migration for the detail view
+    Inspect(String),
+    RunTests,
+}
+
+/// How one test went, as shown in the tests view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestLine {
+    pub name: String,
+    pub passed: bool,
+    /// The error, or where the output first differed
+    pub detail: Option<String>,
+}
+
+pub struct Dashboard {
+    /// Shown in the title, such as `prod (environment prod)`
+    pub title: String,
+    rows: Vec<MigrationStatusRow>,
+    selected: usize,
+    view: View,
+    history: Vec<HistoryRecord>,
+    tests: Vec<TestLine>,
+    message: Option<String>,
+    /// Migration waiting for the user to confirm applying it
+    confirm_apply: Option<String>,
+}
+
+impl Dashboard {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            rows: Vec::new(),
+            selected: 0,
+            view: View::Status,
+            history: Vec::new(),
+            tests: Vec::new(),
+            message: None,
+            confirm_apply: None,
+        }
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    /// Replaces the migrations, keeping the same one selected if it is
+    /// still there.
+    pub fn set_rows(&mut self, rows: Vec<MigrationStatusRow>) {
+        let selected = self.selected_migration().map(str::to_string);
+        self.rows = rows;
+        self.selected = selected
+            .and_then(|name| self.rows.iter().position(|r| r.migration_name == name))
+            .unwrap_or(0)
+            .min(self.rows.len().saturating_sub(1));
+    }
+
+    pub fn selected_migration(&self) -> Option<&str> {
+        self.rows
+            .get(self.selected)
+            .map(|row| row.migration_name.as_str())
+    }
+
+    /// Shows the history of the selected migration.
+    pub fn show_history(&mut self, history: Vec<HistoryRecord>) {
+        self.history = history;
+        self.view = View::Detail;
+    }
+
+    pub fn show_tests(&mut self, tests: Vec<TestLine>) {
+        self.tests = tests;
+        self.view = View::Tests;
+    }
+
+    /// Sets the line shown above the key help, such as the outcome of an
+    /// apply.
+    pub fn set_message(&mut self, message: impl Into<String>) {
+        self.message = Some(message.into());
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> Action {
+        if let Some(migration) = self.confirm_apply.take() {
+            if key == Key::Char('y') || key == Key::Char('Y') {
+                return Action::Apply(migration);
+            }
+            self.set_message("Apply cancelled.");
+            return Action::None;
+        }
+        if matches!(key, Key::CtrlC | Key::Char('q')) {
+            return Action::Quit;
+        }
+
+        match (self.view, key) {
+            (_, Key::Char('r')) => Action::Refresh,
+            (_, Key::Char('t')) => Action::RunTests,
+            (View::Status, Key::Escape) => Action::Quit,
+            (View::Status, Key::ArrowUp | Key::Char('k')) => {
+                self.selected = self.selected.saturating_sub(1);
+                Action::None
+            }
+            (View::Status, Key::ArrowDown | Key::Char('j')) => {
+                self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1));
+                Action::None
+            }
+            (View::Status, Key::PageUp) => {
+                self.selected = self.selected.saturating_sub(10);
+                Action::None
+            }
+            (View::Status, Key::PageDown) => {
+                self.selected = (self.selected + 10).min(self.rows.len().saturating_sub(1));
+                Action::None
+            }
+            (View::Status, Key::Home | Key::Char('g')) => {
+                self.selected = 0;
+                Action::None
+            }
+            (View::Status, Key::End | Key::Char('G')) => {
+                self.selected = self.rows.len().saturating_sub(1);
+                Action::None
+            }
+            (View::Status, Key::Enter | Key::Char('i')) => self
+                .selected_migration()
+                .map(|name| Action::Inspect(name.to_string()))
+                .unwrap_or(Action::None),
+            (View::Status, Key::Char('p')) => self
+                .selected_migration()
+                .map(|name| Action::Pin(name.to_string()))
+                .unwrap_or(Action::None),
+            (View::Status, Key::Char('a')) => {
+                if let Some(name) = self.selected_migration().map(str::to_string) {
+                    self.set_message(format!(
+                        "Apply {} to {}? Press y to apply, any other key to cancel.",
+                        name, self.title
+                    ));
+                    self.confirm_apply = Some(name);
+                }
+                Action::None
+            }
+            (View::Status, Key::Tab) if !self.tests.is_empty() => {
+                self.view = View::Tests;
+                Action::None
+            }
+            (View::Detail | View::Tests, Key::Escape | Key::Backspace | Key::ArrowLeft)
+            | (View::Tests, Key::Tab) => {
+                self.view = View::Status;
+                Action::None
+            }
+            _ => Action::None,
+        }
+    }
+
+    /// The screen as lines at most `width` wide, filling `height` lines.
+    pub fn render(&self, width: usize, height: usize) -> Vec<String> {
+        let (pending, failed) = self.rows.iter().fold((0, 0), |(pending, failed), row| {
+            (
+                pending + usize::from(row.exists_in_filesystem && row.last_status.is_none()),
+                failed
+                    + usize::from(
+                        row.last_status.is_some()
+                            && row.last_status != Some(MigrationStatus::Success),
+                    ),
+            )
+        });
+        let mut lines = vec![
+            format!(
+                "{} {}  {} migrations, {} pending, {} failed",
+                style("spawn ui").bold(),
+                self.title,
+                self.rows.len(),
+                pending,
+                failed
+            ),
+            String::new(),
+        ];
+
+        let body_height = height.saturating_sub(CHROME_LINES).max(1);
+        let (body, help) = match self.view {
+            View::Status => (
+                self.status_lines(body_height),
+                "↑/↓ select  enter inspect  a apply  p pin  t run tests  r refresh  q quit",
+            ),
+            View::Detail => (
+                self.detail_lines(),
+                "esc back  t run tests  r refresh  q quit",
+            ),
+            View::Tests => (
+                self.test_lines(),
+                "esc back  t run again  r refresh  q quit",
+            ),
+        };
+        lines.extend(body.into_iter().take(body_height));
+        while lines.len() < height.saturating_sub(2) {
+            lines.push(String::new());
+        }
+        lines.push(self.message.clone().unwrap_or_default());
+        lines.push(style(help).dim().to_string());
+        lines
+            .into_iter()
+            .map(|line| truncate_str(&line, width, "…").to_string())
+            .collect()
+    }
+
+    fn status_lines(&self, height: usize) -> Vec<String> {
+        if self.rows.is_empty() {
+            return vec!["No migrations found".to_string()];
+        }
+        let name_width = self
+            .rows
+            .iter()
+            .map(|row| row.migration_name.len())
+            .max()
+            .unwrap_or_default();
+        // Scroll just far enough to keep the selection in view.
+        let offset = (self.selected + 1).saturating_sub(height);
+        self.rows
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .map(|(i, row)| {
+                let name = format!("{:width$}", row.migration_name, width = name_width);
+                let pinned = if row.is_pinned { "pinned" } else { "      " };
+                if i == self.selected {
+                    format!(
+                        "{} {}  {}  {}",
+                        style("›").cyan().bold(),
+                        style(name).bold(),
+                        pinned,
+                        status_label(row)
+                    )
+                } else {
+                    format!("  {}  {}  {}", name, pinned, status_label(row))
+                }
+            })
+            .collect()
+    }
+
+    fn detail_lines(&self) -> Vec<String> {
+        let Some(row) = self.rows.get(self.selected) else {
+            return Vec::new();
+        };
+        let mut lines = vec![
+            format!(
+                "{}  {}",
+                style(&row.migration_name).bold(),
+                status_label(row)
+            ),
+            format!(
+                "Pinned: {}   Checksum: {}",
+                if row.is_pinned { "yes" } else { "no" },
+                row.checksum.as_deref().unwrap_or("-")
+            ),
+            String::new(),
+        ];
+        if self.history.is_empty() {
+            lines.push("No history recorded for this migration".to_string());
+        }
+        for record in &self.history {
+            lines.push(format!(
+                "{}  {:<6} {:<9} {:>8.3}s  {}  {}",
+                record.created_at,
+                record.activity,
+                record.status.as_deref().unwrap_or("-"),
+                record.execution_time,
+                record.created_by.as_deref().unwrap_or("-"),
+                record.description.as_deref().unwrap_or_default()
+            ));
+        }
+        lines
+    }
+
+    fn test_lines(&self) -> Vec<String> {
+        if self.tests.is_empty() {
+            return vec!["No tests found".to_string()];
+        }
+        let passed = self.tests.iter().filter(|t| t.passed).count();
+        let mut lines = vec![
+            format!("{} of {} tests passed", passed, self.tests.len()),
+            String::new(),
+        ];
+        for test in &self.tests {
+            let mark = if test.passed {
+                style("✓").green()
+            } else {
+                style("✗").red()
+            };
+            match &test.detail {
+                Some(detail) => lines.push(format!("{} {}  {}", mark, test.name, detail)),
+                None => lines.push(format!("{} {}", mark, test.name)),
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, status: Option<MigrationStatus>) -> MigrationStatusRow {
+        MigrationStatusRow {
+            migration_name: name.to_string(),
+            exists_in_filesystem: true,
+            is_pinned: true,
+            exists_in_db: status.is_some(),
+            last_status: status,
+            last_activity: status.map(|_| "APPLY".to_string()),
+            checksum: None,
+            archived: false,
+        }
+    }
+
+    #[test]
+    fn test_dashboard_keys() {
+        console::set_colors_enabled(false);
+        let mut dashboard = Dashboard::new("prod");
+        dashboard.set_rows(vec![
+            row("20240101-users", Some(MigrationStatus::Success)),
+            row("20240102-orders", None),
+        ]);
+
+        assert_eq!(Action::None, dashboard.handle_key(Key::ArrowDown));
+        assert_eq!(Some("20240102-orders"), dashboard.selected_migration());
+        dashboard.handle_key(Key::ArrowDown);
+        assert_eq!(Some("20240102-orders"), dashboard.selected_migration());
+
+        // Applying needs confirming.
+        assert_eq!(Action::None, dashboard.handle_key(Key::Char('a')));
+        assert_eq!(Action::None, dashboard.handle_key(Key::Char('n')));
+        dashboard.handle_key(Key::Char('a'));
+        assert_eq!(
+            Action::Apply("20240102-orders".to_string()),
+            dashboard.handle_key(Key::Char('y'))
+        );
+
+        // The selection follows the migration when the rows change.
+        dashboard.set_rows(vec![
+            row("20231231-base", Some(MigrationStatus::Success)),
+            row("20240101-users", Some(MigrationStatus::Success)),
+            row("20240102-orders", Some(MigrationStatus::Success)),
+        ]);
+        assert_eq!(Some("20240102-orders"), dashboard.selected_migration());
+        assert_eq!(
+            Action::Inspect("20240102-orders".to_string()),
+            dashboard.handle_key(Key::Enter)
+        );
+        dashboard.show_history(Vec::new());
+        assert_eq!(View::Detail, dashboard.view());
+        dashboard.handle_key(Key::Escape);
+        assert_eq!(View::Status, dashboard.view());
+        assert_eq!(Action::Quit, dashboard.handle_key(Key::Char('q')));
+    }
+
+    #[test]
+    fn test_dashboard_render() {
+        console::set_colors_enabled(false);
+        let mut dashboard = Dashboard::new("prod");
+        dashboard.set_rows(
+            (1..=20)
+                .map(|i| row(&format!("202401{:02}-m", i), None))
+                .collect(),
+        );
+        for _ in 0..19 {
+            dashboard.handle_key(Key::ArrowDown);
+        }
+        let lines = dashboard.render(40, 10);
+        assert_eq!(10, lines.len());
+        assert_eq!("spawn ui prod  20 migrations, 20 pendin…", lines[0]);
+        // The list scrolls to keep the last migration in view.
+        assert!(lines[7].starts_with("› 20240120-m"), "{:?}", lines);
+        assert!(lines.iter().all(|l| console::measure_text_width(l) <= 40));
+
+        dashboard.show_tests(vec![
+            TestLine {
+                name: "users".to_string(),
+                passed: true,
+                detail: None,
+            },
+            TestLine {
+                name: "orders".to_string(),
+                passed: false,
+                detail: Some("differs at line 3".to_string()),
+            },
+        ]);
+        let lines = dashboard.render(80, 10);
+        assert_eq!("1 of 2 tests passed", lines[2]);
+        assert_eq!("✗ orders  differs at line 3", lines[5]);
+    }
+}
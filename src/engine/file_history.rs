@@ -0,0 +1,354 @@
+//! A migration history kept in a file in the spawn folder's store rather
+//! than in the database, for databases where spawn may not create its
+//! `_spawn` schema. Set with `[targets.<name>.history] file = "..."`.
+//!
+//! The file is JSON with a `revision` that goes up with every write. Writes
+//! are optimistic: the file is read, the entry added and the file written
+//! only if nobody else wrote it in between, using the store's conditional
+//! writes where it has them and comparing revisions where it does not.
+//!
+//! The format is versioned: fields may be added within a version, but
+//! anything that would change how an existing field is read needs a new one.
+
+use crate::engine::{
+    ExistingMigrationInfo, HistoryBackend, MigrationDbInfo, MigrationError, MigrationHistoryEntry,
+    MigrationHistoryStatus, MigrationResult, NewHistoryEntry,
+};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Version of the history file format written by this spawn.
+pub const FILE_HISTORY_VERSION: u32 = 1;
+
+/// How many times a write is tried again when someone else wrote the file
+/// first.
+const WRITE_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+struct HistoryFile {
+    version: u32,
+    /// Goes up by one with every write
+    revision: u64,
+    /// Every entry, oldest first
+    entries: Vec<FileHistoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct FileHistoryEntry {
+    migration: String,
+    namespace: String,
+    /// `APPLY` or `ADOPT`
+    activity: String,
+    /// `SUCCESS`, `ATTEMPTED` or `FAILURE`
+    status: String,
+    /// When the entry was recorded (RFC 3339, UTC)
+    created_at: String,
+    /// How long the migration ran, in seconds
+    execution_time: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pin_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+impl From<&FileHistoryEntry> for MigrationHistoryEntry {
+    fn from(entry: &FileHistoryEntry) -> Self {
+        MigrationHistoryEntry {
+            migration_name: entry.migration.clone(),
+            namespace: entry.namespace.clone(),
+            activity: entry.activity.clone(),
+            status: MigrationHistoryStatus::from_str(&entry.status),
+            created_at: entry.created_at.clone(),
+            execution_time: entry.execution_time,
+            checksum: entry.checksum.clone(),
+            pin_hash: entry.pin_hash.clone(),
+            created_by: entry.created_by.clone(),
+            description: entry.description.clone(),
+        }
+    }
+}
+
+/// A history file as read, with what is needed to write it back only if it
+/// has not changed since.
+struct Snapshot {
+    file: HistoryFile,
+    exists: bool,
+    etag: Option<String>,
+}
+
+/// The migration history in a file at `path` on `op`.
+pub struct FileHistory {
+    op: Operator,
+    path: String,
+}
+
+impl FileHistory {
+    pub fn new(op: Operator, path: &str) -> Self {
+        Self {
+            op,
+            path: path.to_string(),
+        }
+    }
+
+    async fn read(&self) -> Result<Snapshot> {
+        let etag = match self.op.stat(&self.path).await {
+            Ok(meta) => meta.etag().map(str::to_string),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+                return Ok(Snapshot {
+                    file: HistoryFile {
+                        version: FILE_HISTORY_VERSION,
+                        ..Default::default()
+                    },
+                    exists: false,
+                    etag: None,
+                })
+            }
+            Err(e) => return Err(e).context(format!("could not read history file {}", self.path)),
+        };
+        let contents = self
+            .op
+            .read(&self.path)
+            .await
+            .context(format!("could not read history file {}", self.path))?
+            .to_vec();
+        let value: serde_json::Value = serde_json::from_slice(&contents)
+            .context(format!("history file {} is not valid JSON", self.path))?;
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(v) if v == FILE_HISTORY_VERSION as u64 => {}
+            Some(v) => {
+                return Err(anyhow!(
+                    "history file {} is version {}, but this version of spawn reads version {}",
+                    self.path,
+                    v,
+                    FILE_HISTORY_VERSION
+                ))
+            }
+            None => return Err(anyhow!("history file {} has no version", self.path)),
+        }
+        let file = serde_json::from_value(value)
+            .context(format!("could not read history file {}", self.path))?;
+        Ok(Snapshot {
+            file,
+            exists: true,
+            etag,
+        })
+    }
+
+    /// Writes `file` over `snapshot` if the file has not changed since it
+    /// was read. Returns false if it had.
+    async fn write_over(&self, snapshot: &Snapshot, file: &HistoryFile) -> Result<bool> {
+        let contents = serde_json::to_vec_pretty(file)?;
+        let capability = self.op.info().full_capability();
+        let mut write = self.op.write_with(&self.path, contents);
+        match (&snapshot.etag, snapshot.exists) {
+            (Some(etag), true) if capability.write_with_if_match => {
+                write = write.if_match(etag);
+            }
+            (_, false) if capability.write_with_if_not_exists => {
+                write = write.if_not_exists(true);
+            }
+            _ => {
+                // The store cannot write conditionally, so check the
+                // revision as late as possible instead.
+                let current = self.read().await?;
+                if current.exists != snapshot.exists
+                    || current.file.revision != snapshot.file.revision
+                {
+                    return Ok(false);
+                }
+            }
+        }
+        match write.await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == opendal::ErrorKind::ConditionNotMatch => Ok(false),
+            Err(e) => Err(e).context(format!("could not write history file {}", self.path)),
+        }
+    }
+
+    fn entries(&self, file: &HistoryFile, namespace: Option<&str>) -> Vec<MigrationHistoryEntry> {
+        file.entries
+            .iter()
+            .filter(|e| namespace.is_none_or(|ns| e.namespace == ns))
+            .map(MigrationHistoryEntry::from)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl HistoryBackend for FileHistory {
+    async fn last_entry(
+        &self,
+        migration_name: &str,
+        namespace: &str,
+    ) -> Result<Option<ExistingMigrationInfo>> {
+        let snapshot = self.read().await?;
+        Ok(snapshot
+            .file
+            .entries
+            .iter()
+            .rev()
+            .find(|e| e.migration == migration_name && e.namespace == namespace)
+            .and_then(|entry| {
+                Some(ExistingMigrationInfo {
+                    migration_name: entry.migration.clone(),
+                    namespace: entry.namespace.clone(),
+                    last_status: MigrationHistoryStatus::from_str(&entry.status)?,
+                    last_activity: entry.activity.clone(),
+                    checksum: entry.checksum.clone().unwrap_or_default(),
+                })
+            }))
+    }
+
+    async fn record(&self, entry: NewHistoryEntry) -> Result<()> {
+        let entry = FileHistoryEntry {
+            migration: entry.migration_name,
+            namespace: entry.namespace,
+            activity: entry.activity.as_str().to_string(),
+            status: entry.status.as_str().to_string(),
+            created_at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            execution_time: entry.execution_time.unwrap_or_default(),
+            checksum: entry.checksum,
+            pin_hash: entry.details.pin_hash,
+            created_by: entry.details.created_by,
+            description: entry.details.description,
+        };
+        for _ in 0..WRITE_ATTEMPTS {
+            let snapshot = self.read().await?;
+            let mut file = snapshot.file.clone();
+            file.revision += 1;
+            file.entries.push(entry.clone());
+            if self.write_over(&snapshot, &file).await? {
+                return Ok(());
+            }
+        }
+        Err(anyhow!(
+            "history file {} kept changing while recording {}; is another spawn writing to it?",
+            self.path,
+            entry.migration
+        ))
+    }
+
+    async fn get_migrations(
+        &self,
+        namespace: Option<&str>,
+    ) -> MigrationResult<Vec<MigrationDbInfo>> {
+        let snapshot = self.read().await.map_err(MigrationError::Database)?;
+        let mut latest: HashMap<String, MigrationDbInfo> = HashMap::new();
+        for entry in self.entries(&snapshot.file, namespace) {
+            latest.insert(
+                entry.migration_name.clone(),
+                MigrationDbInfo {
+                    migration_name: entry.migration_name,
+                    last_status: entry.status,
+                    last_activity: Some(entry.activity),
+                    checksum: entry.checksum,
+                },
+            );
+        }
+
+        let mut results: Vec<MigrationDbInfo> = latest.into_values().collect();
+        results.sort_by(|a, b| a.migration_name.cmp(&b.migration_name));
+        Ok(results)
+    }
+
+    async fn get_history(
+        &self,
+        namespace: Option<&str>,
+    ) -> MigrationResult<Vec<MigrationHistoryEntry>> {
+        let snapshot = self.read().await.map_err(MigrationError::Database)?;
+        Ok(self.entries(&snapshot.file, namespace))
+    }
+
+    /// History files are created by the first write, so never need
+    /// creating or upgrading.
+    async fn schema_version(&self) -> Result<Option<u32>> {
+        Ok(Some(self.read().await?.file.version))
+    }
+
+    fn latest_schema_version(&self) -> u32 {
+        FILE_HISTORY_VERSION
+    }
+
+    async fn upgrade_schema(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{HistoryDetails, MigrationActivity, MigrationStatus};
+    use opendal::services::Memory;
+
+    fn applied(name: &str, status: MigrationStatus) -> NewHistoryEntry {
+        NewHistoryEntry {
+            migration_name: name.to_string(),
+            namespace: "default".to_string(),
+            status,
+            activity: MigrationActivity::Apply,
+            checksum: Some("abcd".to_string()),
+            execution_time: Some(0.5),
+            details: HistoryDetails::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_history() -> Result<()> {
+        let op = Operator::new(Memory::default())?.finish();
+        let history = FileHistory::new(op.clone(), "spawn/history.json");
+        assert!(history.get_history(None).await?.is_empty());
+
+        history
+            .record(applied("20240101-users", MigrationStatus::Failure))
+            .await?;
+        history
+            .record(applied("20240101-users", MigrationStatus::Success))
+            .await?;
+        history
+            .record(applied("20240102-orders", MigrationStatus::Success))
+            .await?;
+
+        let last = history
+            .last_entry("20240101-users", "default")
+            .await?
+            .expect("no entry recorded");
+        assert_eq!(MigrationStatus::Success, last.last_status);
+        assert_eq!(3, history.get_history(Some("default")).await?.len());
+        assert!(history.get_history(Some("other")).await?.is_empty());
+        assert_eq!(
+            vec!["20240101-users", "20240102-orders"],
+            history
+                .get_migrations(None)
+                .await?
+                .iter()
+                .map(|m| m.migration_name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(3, history.read().await?.file.revision);
+
+        // A write based on a revision someone else has since replaced is
+        // refused.
+        let stale = history.read().await?;
+        history
+            .record(applied("20240103-items", MigrationStatus::Success))
+            .await?;
+        assert!(!history.write_over(&stale, &stale.file).await?);
+        assert_eq!(4, history.read().await?.file.revision);
+
+        op.write(
+            "spawn/history.json",
+            r#"{"version": 2, "revision": 0, "entries": []}"#,
+        )
+        .await?;
+        let err = history.get_history(None).await.unwrap_err().to_string();
+        assert!(err.contains("version 2"), "{}", err);
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Spawn's bookkeeping of the migrations applied to a target: the `_spawn`
+//! tables recording each apply and adopt. By default they are kept in the
+//! target's own database, but `[targets.<name>.history]` can move them to
+//! another database or schema, such as a central control-plane database
+//! recording several targets, or to a file for databases where spawn may not
+//! create a schema. Engines run migrations and leave recording them to their
+//! [`HistoryBackend`].
+
+use crate::engine::{
+    CommandSpec, ExistingMigrationInfo, HistoryDetails, MigrationActivity, MigrationDbInfo,
+    MigrationHistoryEntry, MigrationResult, MigrationStatus, TargetConfig,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Where a target's migration history is kept, in the
+/// `[targets.<name>.history]` section. Anything left out is taken from the
+/// target.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HistoryConfig {
+    /// Command to connect to the history database with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<CommandSpec>,
+    /// Connection URL of the history database, used instead of `command`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Database to keep the history in. When `command` or `url` is set this
+    /// defaults to the one they connect to, otherwise to the target's
+    /// `spawn_database`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn_database: Option<String>,
+    /// Schema to keep the history in. Defaults to the target's
+    /// `spawn_schema`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn_schema: Option<String>,
+    /// Keep the history in this file, relative to the spawn folder, instead
+    /// of a database (see [`FileHistory`](crate::engine::FileHistory))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl HistoryConfig {
+    /// The target config to connect to the history database with, from
+    /// these settings and those of `target`.
+    pub fn target_config(&self, target: &TargetConfig) -> TargetConfig {
+        let own_connection = self.command.is_some() || self.url.is_some();
+        let mut config = target.clone();
+        config.history = None;
+        if own_connection {
+            config.command = self.command.clone();
+            config.url = self.url.clone();
+            config.spawn_database = self.spawn_database.clone();
+            // Session settings such as the role are the target's own.
+            config.session = None;
+        } else if self.spawn_database.is_some() {
+            config.spawn_database = self.spawn_database.clone();
+        }
+        if let Some(schema) = &self.spawn_schema {
+            config.spawn_schema = schema.clone();
+        }
+        config
+    }
+}
+
+/// An apply or adopt to add to the history.
+#[derive(Debug, Clone)]
+pub struct NewHistoryEntry {
+    pub migration_name: String,
+    pub namespace: String,
+    pub status: MigrationStatus,
+    pub activity: MigrationActivity,
+    /// Checksum of the SQL run, None when nothing was run
+    pub checksum: Option<String>,
+    /// How long the migration ran, in seconds
+    pub execution_time: Option<f64>,
+    pub details: HistoryDetails,
+}
+
+/// Keeps the migration history of a target.
+#[async_trait]
+pub trait HistoryBackend: Send + Sync {
+    /// The latest entry of a migration, or None if it has none.
+    async fn last_entry(
+        &self,
+        migration_name: &str,
+        namespace: &str,
+    ) -> Result<Option<ExistingMigrationInfo>>;
+
+    /// Adds an entry to the history.
+    async fn record(&self, entry: NewHistoryEntry) -> Result<()>;
+
+    /// Each migration in the given namespace (or all namespaces when None)
+    /// with its latest history entry, sorted by name.
+    async fn get_migrations(
+        &self,
+        namespace: Option<&str>,
+    ) -> MigrationResult<Vec<MigrationDbInfo>>;
+
+    /// Every history entry for migrations in the given namespace (or all
+    /// namespaces when None), oldest first.
+    async fn get_history(
+        &self,
+        namespace: Option<&str>,
+    ) -> MigrationResult<Vec<MigrationHistoryEntry>>;
+
+    /// Version of the internal schema holding the history, or None if it has
+    /// not been created.
+    async fn schema_version(&self) -> Result<Option<u32>>;
+
+    /// Version of the internal schema this build of spawn works with.
+    fn latest_schema_version(&self) -> u32;
+
+    /// Applies pending internal migrations, returning their names.
+    async fn upgrade_schema(&self) -> Result<Vec<String>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineType, SessionSettings};
+
+    fn target() -> TargetConfig {
+        TargetConfig {
+            engine: EngineType::PostgresPSQL,
+            spawn_database: Some("app".to_string()),
+            spawn_schema: "_spawn".to_string(),
+            environment: "prod".to_string(),
+            protected: false,
+            command: None,
+            url: Some("postgres://app@db:5432/app".to_string()),
+            session: Some(SessionSettings {
+                role: Some("migrator".to_string()),
+                ..Default::default()
+            }),
+            history: None,
+            retry: None,
+            wait_for_lock: None,
+        }
+    }
+
+    #[test]
+    fn test_history_target_config() {
+        // Another schema in the target's database.
+        let same_database = HistoryConfig {
+            spawn_schema: Some("_spawn_app".to_string()),
+            ..Default::default()
+        }
+        .target_config(&target());
+        assert_eq!(Some("app"), same_database.spawn_database.as_deref());
+        assert_eq!("_spawn_app", same_database.spawn_schema);
+        assert!(same_database.session.is_some());
+
+        // A control-plane database of its own.
+        let control = HistoryConfig {
+            url: Some("postgres://spawn@control:5432/control".to_string()),
+            ..Default::default()
+        }
+        .target_config(&target());
+        assert_eq!(
+            Some("postgres://spawn@control:5432/control"),
+            control.url.as_deref()
+        );
+        assert_eq!(None, control.spawn_database);
+        assert_eq!("_spawn", control.spawn_schema);
+        assert!(control.session.is_none());
+        assert!(control.history.is_none());
+    }
+}
@@ -0,0 +1,178 @@
+//! Connecting to a database running in Kubernetes by running psql in its pod
+//! with `kubectl exec`, for clusters that do not expose the database port.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+/// Where to run psql in a Kubernetes cluster, as a target's
+/// `command = { kind = "kubectl", ... }`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct KubectlCommand {
+    /// kubeconfig context to use, instead of the current context
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Name of the pod to run psql in. Either this or `selector` is required.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod: Option<String>,
+    /// Label selector (e.g. `app=postgres`) choosing the pod. The first
+    /// running pod that matches is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
+    /// Container in the pod, when it has more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// User to connect to the database as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+}
+
+impl KubectlCommand {
+    /// The `kubectl` arguments that pick the cluster and namespace.
+    fn cluster_args(&self) -> Vec<String> {
+        let mut args = vec!["kubectl".to_string()];
+        if let Some(context) = &self.context {
+            args.extend(["--context".to_string(), context.clone()]);
+        }
+        if let Some(namespace) = &self.namespace {
+            args.extend(["--namespace".to_string(), namespace.clone()]);
+        }
+        args
+    }
+
+    /// The command that runs psql in `pod`.
+    fn exec_command(&self, pod: &str) -> Vec<String> {
+        let mut args = self.cluster_args();
+        args.extend(["exec".to_string(), "-i".to_string(), pod.to_string()]);
+        if let Some(container) = &self.container {
+            args.extend(["--container".to_string(), container.clone()]);
+        }
+        args.extend(["--".to_string(), "psql".to_string()]);
+        if let Some(user) = &self.user {
+            args.extend(["-U".to_string(), user.clone()]);
+        }
+        args.extend(self.database.clone());
+        args
+    }
+
+    /// Resolves the command to run, looking up the pod by its selector if
+    /// no pod was named.
+    pub async fn resolve(&self) -> Result<Vec<String>> {
+        match (&self.pod, &self.selector) {
+            (Some(pod), None) => Ok(self.exec_command(pod)),
+            (None, Some(selector)) => {
+                let pod = self.find_pod(selector).await?;
+                Ok(self.exec_command(&pod))
+            }
+            _ => Err(anyhow!(
+                "kubectl command must set exactly one of pod or selector"
+            )),
+        }
+    }
+
+    async fn find_pod(&self, selector: &str) -> Result<String> {
+        let args = self.cluster_args();
+        let output = Command::new(&args[0])
+            .args(&args[1..])
+            .args([
+                "get",
+                "pods",
+                "--selector",
+                selector,
+                "--field-selector=status.phase=Running",
+                "--output=jsonpath={.items[*].metadata.name}",
+            ])
+            .output()
+            .await
+            .context("Failed to run kubectl to find the database pod")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "kubectl get pods failed (exit {}): {}",
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout
+            .split_whitespace()
+            .next()
+            .map(str::to_string)
+            .ok_or(anyhow!("no running pod matches selector '{}'", selector))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::CommandSpec;
+
+    #[test]
+    fn test_parses_from_target_command() {
+        let spec: CommandSpec = toml::from_str(
+            r#"
+            kind = "kubectl"
+            context = "prod"
+            namespace = "db"
+            pod = "postgres-0"
+            database = "app"
+            "#,
+        )
+        .unwrap();
+        let CommandSpec::Kubectl(kubectl) = spec else {
+            panic!("expected a kubectl command, got {:?}", spec);
+        };
+        assert_eq!(Some("postgres-0".to_string()), kubectl.pod);
+        assert_eq!(Some("app".to_string()), kubectl.database);
+    }
+
+    #[tokio::test]
+    async fn test_exec_command() {
+        let kubectl = KubectlCommand {
+            context: Some("prod".to_string()),
+            namespace: Some("db".to_string()),
+            pod: Some("postgres-0".to_string()),
+            container: Some("postgres".to_string()),
+            user: Some("postgres".to_string()),
+            database: Some("app".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "kubectl",
+                "--context",
+                "prod",
+                "--namespace",
+                "db",
+                "exec",
+                "-i",
+                "postgres-0",
+                "--container",
+                "postgres",
+                "--",
+                "psql",
+                "-U",
+                "postgres",
+                "app"
+            ],
+            kubectl.resolve().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requires_pod_or_selector() {
+        assert!(KubectlCommand::default().resolve().await.is_err());
+
+        let both = KubectlCommand {
+            pod: Some("postgres-0".to_string()),
+            selector: Some("app=postgres".to_string()),
+            ..Default::default()
+        };
+        assert!(both.resolve().await.is_err());
+    }
+}
@@ -0,0 +1,398 @@
+// An engine that keeps its database in memory, so that commands can be
+// tested without PostgreSQL. It runs no SQL: what would be sent to the
+// database is recorded, and the migration history is kept as the `_spawn`
+// tables would keep it.
+
+use crate::engine::postgres_psql::migration_checksum;
+use crate::engine::{
+    Engine, EngineError, ExistingMigrationInfo, HistoryBackend, HistoryDetails, MigrationActivity,
+    MigrationDbInfo, MigrationError, MigrationHistoryEntry, MigrationResult, MigrationStatus,
+    NewHistoryEntry, Rows, StdoutWriter, TargetConfig, WriterFn,
+};
+use crate::errors::ErrorKind;
+use crate::escape::EscapedQuery;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+
+/// Version of the internal schema memory databases report. They have no
+/// internal migrations, so are never out of date.
+const MEMORY_SCHEMA_VERSION: u32 = 1;
+
+/// Exit code psql stops with when a statement fails.
+const FAILED_EXIT_CODE: i32 = 3;
+
+static DATABASES: LazyLock<Mutex<HashMap<String, MemoryDatabase>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Debug, Default)]
+struct State {
+    /// SQL of each session, oldest first
+    executed: Vec<String>,
+    history: Vec<MigrationHistoryEntry>,
+    /// Sessions whose SQL contains one of these fail
+    fail_on: Vec<String>,
+    /// Queries containing the first of a pair return its rows
+    answers: Vec<(String, Rows)>,
+    /// Sessions whose SQL contains the first of a pair print the second
+    prints: Vec<(String, String)>,
+}
+
+/// A database kept in memory. Targets with `engine = "memory"` and the same
+/// `spawn_database` share one for as long as the process runs, so a test can
+/// look at what the commands it ran did.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDatabase(Arc<Mutex<State>>);
+
+impl MemoryDatabase {
+    /// The database called `name`, created empty if it does not exist.
+    pub fn named(name: &str) -> Self {
+        DATABASES
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// The SQL sent in each session, oldest first, including that of
+    /// migrations which failed.
+    pub fn executed(&self) -> Vec<String> {
+        self.0.lock().unwrap().executed.clone()
+    }
+
+    /// Fails every later session whose SQL contains `sql`, as if the
+    /// database had rejected it.
+    pub fn fail_on(&self, sql: &str) {
+        self.0.lock().unwrap().fail_on.push(sql.to_string());
+    }
+
+    /// Answers every later query containing `sql` with `rows`, as if the
+    /// database held them. Other queries return no rows.
+    pub fn answer(&self, sql: &str, rows: Rows) {
+        self.0.lock().unwrap().answers.push((sql.to_string(), rows));
+    }
+
+    /// Prints `output` from every later session whose SQL contains `sql`, as
+    /// psql prints the notices and warnings the server sends.
+    pub fn print_on(&self, sql: &str, output: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .prints
+            .push((sql.to_string(), output.to_string()));
+    }
+
+    /// Runs `write_fn` and records the SQL it writes, failing as set with
+    /// [`MemoryDatabase::fail_on`] and printing to `output` as set with
+    /// [`MemoryDatabase::print_on`]. Returns the checksum of the SQL, whether
+    /// or not it failed.
+    async fn execute(
+        &self,
+        write_fn: WriterFn,
+        output: StdoutWriter,
+    ) -> Result<(String, Result<(), EngineError>)> {
+        // Rendering reads templates with `block_on`, so must not run on the
+        // async runtime's own threads.
+        let sql = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<u8>> {
+            let mut sql = Vec::new();
+            write_fn(&mut sql)?;
+            Ok(sql)
+        })
+        .await??;
+
+        let checksum = migration_checksum(&sql);
+        let sql = String::from_utf8_lossy(&sql).into_owned();
+        let (result, printed) = {
+            let mut state = self.0.lock().unwrap();
+            state.executed.push(sql.clone());
+            let printed: String = state
+                .prints
+                .iter()
+                .filter(|(pattern, _)| sql.contains(pattern.as_str()))
+                .map(|(_, printed)| printed.as_str())
+                .collect();
+            let result = match state.fail_on.iter().find(|p| sql.contains(p.as_str())) {
+                Some(pattern) => Err(EngineError::ExecutionFailed {
+                    exit_code: FAILED_EXIT_CODE,
+                    stderr: format!("ERROR:  memory database set to fail on '{}'", pattern),
+                }),
+                None => Ok(()),
+            };
+            (result, printed)
+        };
+        if let Some(mut output) = output {
+            use tokio::io::AsyncWriteExt;
+            output.write_all(printed.as_bytes()).await?;
+            output.flush().await?;
+        }
+        Ok((checksum, result))
+    }
+}
+
+/// Memory databases keep the history as the `_spawn` tables would, and the
+/// history of a target may be kept in another memory database.
+#[async_trait]
+impl HistoryBackend for MemoryDatabase {
+    async fn last_entry(
+        &self,
+        migration_name: &str,
+        namespace: &str,
+    ) -> Result<Option<ExistingMigrationInfo>> {
+        let state = self.0.lock().unwrap();
+        Ok(state
+            .history
+            .iter()
+            .rev()
+            .find(|e| e.migration_name == migration_name && e.namespace == namespace)
+            .and_then(|entry| {
+                Some(ExistingMigrationInfo {
+                    migration_name: entry.migration_name.clone(),
+                    namespace: entry.namespace.clone(),
+                    last_status: entry.status?,
+                    last_activity: entry.activity.clone(),
+                    checksum: entry.checksum.clone().unwrap_or_default(),
+                })
+            }))
+    }
+
+    async fn record(&self, entry: NewHistoryEntry) -> Result<()> {
+        self.0.lock().unwrap().history.push(MigrationHistoryEntry {
+            migration_name: entry.migration_name,
+            namespace: entry.namespace,
+            activity: entry.activity.as_str().to_string(),
+            status: Some(entry.status),
+            created_at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            execution_time: entry.execution_time.unwrap_or_default(),
+            checksum: entry.checksum,
+            pin_hash: entry.details.pin_hash,
+            created_by: entry.details.created_by,
+            description: entry.details.description,
+        });
+        Ok(())
+    }
+
+    async fn get_migrations(
+        &self,
+        namespace: Option<&str>,
+    ) -> MigrationResult<Vec<MigrationDbInfo>> {
+        let mut latest: HashMap<String, MigrationDbInfo> = HashMap::new();
+        for entry in self.get_history(namespace).await? {
+            latest.insert(
+                entry.migration_name.clone(),
+                MigrationDbInfo {
+                    migration_name: entry.migration_name,
+                    last_status: entry.status,
+                    last_activity: Some(entry.activity),
+                    checksum: entry.checksum,
+                },
+            );
+        }
+
+        let mut results: Vec<MigrationDbInfo> = latest.into_values().collect();
+        results.sort_by(|a, b| a.migration_name.cmp(&b.migration_name));
+        Ok(results)
+    }
+
+    async fn get_history(
+        &self,
+        namespace: Option<&str>,
+    ) -> MigrationResult<Vec<MigrationHistoryEntry>> {
+        let state = self.0.lock().unwrap();
+        Ok(state
+            .history
+            .iter()
+            .filter(|e| namespace.is_none_or(|ns| e.namespace == ns))
+            .cloned()
+            .collect())
+    }
+
+    async fn schema_version(&self) -> Result<Option<u32>> {
+        Ok(Some(MEMORY_SCHEMA_VERSION))
+    }
+
+    fn latest_schema_version(&self) -> u32 {
+        MEMORY_SCHEMA_VERSION
+    }
+
+    async fn upgrade_schema(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+pub struct MemoryEngine {
+    database: MemoryDatabase,
+    /// Where the history is kept: `database` itself, the memory database
+    /// named by `[targets.<name>.history]`, or a history file
+    history: Box<dyn HistoryBackend>,
+}
+
+impl MemoryEngine {
+    /// An engine on the memory database named by the target's
+    /// `spawn_database`, keeping its history in `history` when given.
+    pub fn new(config: &TargetConfig, history: Option<Box<dyn HistoryBackend>>) -> Self {
+        let name = config.spawn_database.as_deref().unwrap_or("spawn");
+        let history = history.unwrap_or_else(|| {
+            let config = match &config.history {
+                Some(history) => history.target_config(config),
+                None => config.clone(),
+            };
+            let name = config.spawn_database.as_deref().unwrap_or("spawn");
+            Box::new(MemoryDatabase::named(name))
+        });
+        Self {
+            database: MemoryDatabase::named(name),
+            history,
+        }
+    }
+}
+
+#[async_trait]
+impl Engine for MemoryEngine {
+    async fn execute_with_writer(
+        &self,
+        write_fn: WriterFn,
+        stdout_writer: StdoutWriter,
+        _merge_stderr: bool,
+    ) -> Result<(), EngineError> {
+        match self.database.execute(write_fn, stdout_writer).await {
+            Ok((_, result)) => result,
+            Err(e) => Err(EngineError::Io(std::io::Error::other(e))),
+        }
+    }
+
+    async fn migration_apply(
+        &self,
+        migration_name: &str,
+        write_fn: WriterFn,
+        details: HistoryDetails,
+        namespace: &str,
+        retry: bool,
+        output: StdoutWriter,
+    ) -> MigrationResult<String> {
+        let existing = self
+            .history
+            .last_entry(migration_name, namespace)
+            .await
+            .map_err(MigrationError::Database)?;
+        if let Some(info) = existing {
+            if !retry {
+                let name = migration_name.to_string();
+                let namespace = namespace.to_string();
+                return Err(match info.last_status {
+                    MigrationStatus::Success => MigrationError::AlreadyApplied {
+                        name,
+                        namespace,
+                        info,
+                    },
+                    status => MigrationError::PreviousAttemptFailed {
+                        name,
+                        namespace,
+                        status,
+                        info,
+                    },
+                });
+            }
+        }
+
+        let start_time = Instant::now();
+        let (checksum, result) = self
+            .database
+            .execute(write_fn, output)
+            .await
+            .map_err(|e| MigrationError::Database(e.context("IO error running migration")))?;
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let status = match result {
+            Ok(()) => MigrationStatus::Success,
+            Err(_) => MigrationStatus::Failure,
+        };
+        self.history
+            .record(NewHistoryEntry {
+                migration_name: migration_name.to_string(),
+                namespace: namespace.to_string(),
+                status,
+                activity: MigrationActivity::Apply,
+                checksum: Some(checksum),
+                execution_time: Some(duration),
+                details,
+            })
+            .await
+            .map_err(|e| MigrationError::NotRecorded {
+                name: migration_name.to_string(),
+                migration_outcome: status,
+                migration_error: result.as_ref().err().map(|e| e.to_string()),
+                recording_error: format!("{}", e),
+            })?;
+
+        match result {
+            Ok(()) => Ok("Migration applied successfully".to_string()),
+            Err(EngineError::ExecutionFailed { exit_code, stderr }) => Err(
+                MigrationError::Database(ErrorKind::for_psql_exit(exit_code).wrap(anyhow!(
+                    "Migration '{}' failed: psql exited with code {}: {}",
+                    migration_name,
+                    exit_code,
+                    stderr
+                ))),
+            ),
+            Err(EngineError::Io(e)) => Err(MigrationError::Database(e.into())),
+        }
+    }
+
+    async fn migration_adopt(
+        &self,
+        migration_name: &str,
+        namespace: &str,
+        details: HistoryDetails,
+    ) -> MigrationResult<String> {
+        let existing = self
+            .history
+            .last_entry(migration_name, namespace)
+            .await
+            .map_err(MigrationError::Database)?;
+        if let Some(info) = existing {
+            if info.last_status == MigrationStatus::Success {
+                return Err(MigrationError::AlreadyApplied {
+                    name: migration_name.to_string(),
+                    namespace: namespace.to_string(),
+                    info,
+                });
+            }
+        }
+
+        self.history
+            .record(NewHistoryEntry {
+                migration_name: migration_name.to_string(),
+                namespace: namespace.to_string(),
+                status: MigrationStatus::Success,
+                activity: MigrationActivity::Adopt,
+                checksum: None,
+                execution_time: None,
+                details,
+            })
+            .await
+            .map_err(MigrationError::Database)?;
+        Ok(format!(
+            "Migration '{}' adopted successfully",
+            migration_name
+        ))
+    }
+
+    fn history(&self) -> &dyn HistoryBackend {
+        self.history.as_ref()
+    }
+
+    /// Memory databases have no tables, so a query returns the rows given
+    /// to `MemoryDatabase::answer` for the first SQL it contains, or no rows.
+    async fn query(&self, query: &EscapedQuery) -> Result<Rows> {
+        let state = self.database.0.lock().unwrap();
+        Ok(state
+            .answers
+            .iter()
+            .find(|(sql, _)| query.as_str().contains(sql.as_str()))
+            .map(|(_, rows)| rows.clone())
+            .unwrap_or_default())
+    }
+}
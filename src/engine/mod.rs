@@ -1,3 +1,5 @@
+use crate::escape::{EscapedIdentifier, EscapedLiteral, EscapedQuery, InsecureRawSql};
+use crate::sql_query;
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -5,10 +7,23 @@ use std::fmt;
 use thiserror::Error;
 use tokio::process::Command;
 
+mod file_history;
+mod history;
+mod kubectl;
+pub mod memory;
 pub mod postgres_psql;
+mod psql_output;
+mod retry;
+
+pub use file_history::{FileHistory, FILE_HISTORY_VERSION};
+pub use history::{HistoryBackend, HistoryConfig, NewHistoryEntry};
+pub use kubectl::KubectlCommand;
+pub use psql_output::{server_message, server_messages, ServerMessage, ServerMessageLevel};
+pub use retry::{transient_error, RetryPolicy, TransientError};
 
 /// Status of a migration in the tracking tables
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum MigrationStatus {
     Success,
     Attempted,
@@ -89,6 +104,88 @@ pub struct MigrationDbInfo {
     pub checksum: Option<String>,
 }
 
+/// The result of [`Engine::query`]: the column names, and each row's values
+/// as the database prints them as text. NULL values are None.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<String>>>,
+}
+
+impl Rows {
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Index of the named column, if present.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Row<'_>> {
+        self.rows
+            .iter()
+            .map(move |values| Row { rows: self, values })
+    }
+}
+
+/// A row of [`Rows`], with values looked up by column name.
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'a> {
+    rows: &'a Rows,
+    values: &'a [Option<String>],
+}
+
+impl<'a> Row<'a> {
+    /// The value of the named column, or None if it is NULL or there is no
+    /// such column.
+    pub fn get(&self, column: &str) -> Option<&'a str> {
+        let index = self.rows.column(column)?;
+        self.values.get(index)?.as_deref()
+    }
+
+    /// Like [`get`](Self::get), but an error if the value is missing.
+    pub fn require(&self, column: &str) -> Result<&'a str> {
+        self.get(column)
+            .ok_or_else(|| anyhow!("query returned no value for column '{}'", column))
+    }
+}
+
+/// A single row from the migration history table
+#[derive(Debug, Clone)]
+pub struct MigrationHistoryEntry {
+    pub migration_name: String,
+    pub namespace: String,
+    pub activity: String,
+    pub status: Option<MigrationHistoryStatus>,
+    /// Timestamp the entry was recorded, as reported by the database (RFC 3339)
+    pub created_at: String,
+    /// Execution time of the activity in seconds
+    pub execution_time: f64,
+    pub checksum: Option<String>,
+    pub pin_hash: Option<String>,
+    /// Who applied or adopted the migration
+    pub created_by: Option<String>,
+    pub description: Option<String>,
+}
+
+/// What is recorded about a migration alongside each history entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryDetails {
+    /// Pin the migration was applied with, if it was applied pinned
+    pub pin_hash: Option<String>,
+    /// Who applied or adopted the migration, from
+    /// [`Config::operator_identity`](crate::config::Config::operator_identity)
+    pub created_by: Option<String>,
+    /// Description (and ticket) of the migration, from its `migration.toml`,
+    /// or the reason given when adopting it
+    pub description: Option<String>,
+}
+
 /// Errors specific to migration operations
 #[derive(Debug, Error)]
 pub enum MigrationError {
@@ -117,6 +214,16 @@ pub enum MigrationError {
     #[error("could not get advisory lock: {0}")]
     AdvisoryLock(std::io::Error),
 
+    /// The migration was stopped with Ctrl-C. psql was stopped and the
+    /// attempt recorded where possible, but some of the migration may have
+    /// been applied.
+    #[error("migration '{name}' was interrupted")]
+    Interrupted {
+        name: String,
+        /// Whether the attempt could be recorded in the migration history
+        recorded: bool,
+    },
+
     /// CRITICAL: A migration was run but the result could not be recorded in
     /// spawn's migration tracking tables. Manual intervention is required.
     #[error("{}", format_not_recorded_error(.name, .migration_outcome, .migration_error, .recording_error))]
@@ -204,6 +311,9 @@ pub enum EngineError {
 pub enum EngineType {
     #[serde(rename = "postgres-psql")]
     PostgresPSQL,
+    /// Keeps the database in memory, for testing without PostgreSQL
+    #[serde(rename = "memory")]
+    Memory,
 }
 
 impl fmt::Display for EngineType {
@@ -212,6 +322,9 @@ impl fmt::Display for EngineType {
             EngineType::PostgresPSQL => {
                 write!(f, "postgres-psql")
             }
+            EngineType::Memory => {
+                write!(f, "memory")
+            }
         }
     }
 }
@@ -228,6 +341,8 @@ pub enum CommandSpec {
         #[serde(default)]
         append: Vec<String>,
     },
+    /// Run psql in a Kubernetes pod with `kubectl exec`.
+    Kubectl(KubectlCommand),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -238,9 +353,91 @@ pub struct TargetConfig {
     pub spawn_schema: String,
     #[serde(default = "default_environment")]
     pub environment: String,
+    /// Apply and adopt ask for the target's name to be typed before
+    /// changing it, and `--yes` alone is not enough.
+    #[serde(default)]
+    pub protected: bool,
 
     #[serde(default)]
     pub command: Option<CommandSpec>,
+
+    /// Connection URL such as `postgres://user@host:5432/db`, used instead
+    /// of `command`. The engine builds the command to connect with from it.
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Settings applied at the start of every session, in the
+    /// `[targets.<name>.session]` section.
+    #[serde(default)]
+    pub session: Option<SessionSettings>,
+
+    /// Where the migration history is kept when not in this database, in
+    /// the `[targets.<name>.history]` section.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistoryConfig>,
+
+    /// Retrying of connection failures and lock waits, in the
+    /// `[targets.<name>.retry]` section. Without it nothing is retried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+
+    /// How long apply waits for another apply to release the migration
+    /// lock, from [`Config::wait_for_lock`](crate::config::Config::wait_for_lock).
+    /// Not read from spawn.toml.
+    #[serde(skip)]
+    pub wait_for_lock: Option<std::time::Duration>,
+}
+
+/// Session settings set before any other SQL is run, for migrations, tests
+/// and spawn's own queries alike.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SessionSettings {
+    /// e.g. `"30s"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement_timeout: Option<String>,
+    /// e.g. `"5s"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_timeout: Option<String>,
+    /// Role to `SET ROLE` to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Schemas to search, in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_path: Option<Vec<String>>,
+}
+
+impl SessionSettings {
+    /// The `SET` statements for these settings.
+    pub fn to_sql(&self) -> String {
+        let mut statements = Vec::new();
+        if let Some(timeout) = &self.statement_timeout {
+            statements.push(sql_query!(
+                "SET statement_timeout = {};\n",
+                EscapedLiteral::new(timeout)
+            ));
+        }
+        if let Some(timeout) = &self.lock_timeout {
+            statements.push(sql_query!(
+                "SET lock_timeout = {};\n",
+                EscapedLiteral::new(timeout)
+            ));
+        }
+        if let Some(role) = &self.role {
+            statements.push(sql_query!("SET ROLE {};\n", EscapedIdentifier::new(role)));
+        }
+        if let Some(search_path) = &self.search_path {
+            let schemas = search_path
+                .iter()
+                .map(|schema| EscapedIdentifier::new(schema).as_str().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            statements.push(sql_query!(
+                "SET search_path TO {};\n",
+                InsecureRawSql::new(&schemas)
+            ));
+        }
+        statements.iter().map(EscapedQuery::as_str).collect()
+    }
 }
 
 fn default_environment() -> String {
@@ -263,6 +460,7 @@ pub async fn resolve_command_spec(spec: CommandSpec) -> Result<Vec<String>> {
             resolved.extend(append);
             Ok(resolved)
         }
+        CommandSpec::Kubectl(kubectl) => kubectl.resolve().await,
     }
 }
 
@@ -315,7 +513,8 @@ pub trait Engine: Send + Sync {
     /// - `stdout_writer`: Optional writer to capture stdout. If None, stdout is discarded.
     /// - `merge_stderr`: If true and stdout_writer is Some, stderr is merged into stdout
     ///                   at the OS level for true interleaving. Useful for tests.
-    ///                   Note: when merged, stderr is not separately available in errors.
+    ///                   When merged, the error message is picked out of the combined
+    ///                   output for the returned error.
     /// Engine-specific setup (like psql flags) is handled internally.
     /// Returns stderr content on failure.
     async fn execute_with_writer(
@@ -325,30 +524,86 @@ pub trait Engine: Send + Sync {
         merge_stderr: bool,
     ) -> Result<(), EngineError>;
 
+    /// Apply a migration and record the outcome along with `details`. When
+    /// `output` is Some, everything the database prints while running the
+    /// migration is written to it.
     async fn migration_apply(
         &self,
         migration_name: &str,
         write_fn: WriterFn,
-        pin_hash: Option<String>,
+        details: HistoryDetails,
         namespace: &str,
         retry: bool,
+        output: StdoutWriter,
     ) -> MigrationResult<String>;
 
     /// Adopt a migration without applying it.
     /// Creates a dummy table entry marking the migration as having been applied manually.
-    /// Sets checksum to empty and status to 'SUCCESS'.
+    /// Sets checksum to empty and status to 'SUCCESS', recording `details`
+    /// (who adopted it and why).
     async fn migration_adopt(
         &self,
         migration_name: &str,
         namespace: &str,
-        description: &str,
+        details: HistoryDetails,
     ) -> MigrationResult<String>;
 
+    /// Where this engine keeps the migration history.
+    fn history(&self) -> &dyn HistoryBackend;
+
     /// Get database information for all migrations in the given namespace.
     /// If namespace is None, returns migrations from all namespaces.
     /// Returns a list of migrations that exist in the database with their latest history entry.
     async fn get_migrations_from_db(
         &self,
         namespace: Option<&str>,
-    ) -> MigrationResult<Vec<MigrationDbInfo>>;
+    ) -> MigrationResult<Vec<MigrationDbInfo>> {
+        self.history().get_migrations(namespace).await
+    }
+
+    /// Version of the engine's internal schema in the database, or None if
+    /// it has not been created.
+    async fn internal_schema_version(&self) -> Result<Option<u32>> {
+        self.history().schema_version().await
+    }
+
+    /// Version of the internal schema this build of spawn works with.
+    fn latest_internal_schema_version(&self) -> u32 {
+        self.history().latest_schema_version()
+    }
+
+    /// Applies pending internal migrations, returning their names.
+    async fn upgrade_internal_schema(&self) -> Result<Vec<String>> {
+        self.history().upgrade_schema().await
+    }
+
+    /// Run a query against the target database and return its rows.
+    async fn query(&self, query: &EscapedQuery) -> Result<Rows>;
+
+    /// Run a query against the target database and return the first column
+    /// of each row as a string, skipping NULLs. Used for config-driven
+    /// lookups such as the tenant list, where the query comes from trusted
+    /// configuration.
+    async fn query_first_column(&self, query: &str) -> Result<Vec<String>> {
+        let query = sql_query!(
+            "{};",
+            InsecureRawSql::new(query.trim().trim_end_matches(';'))
+        );
+        let rows = self.query(&query).await?;
+
+        Ok(rows
+            .rows
+            .into_iter()
+            .filter_map(|row| row.into_iter().next().flatten())
+            .collect())
+    }
+
+    /// Get every history entry for migrations in the given namespace, ordered
+    /// oldest to newest. If namespace is None, returns entries from all namespaces.
+    async fn get_migration_history(
+        &self,
+        namespace: Option<&str>,
+    ) -> MigrationResult<Vec<MigrationHistoryEntry>> {
+        self.history().get_history(namespace).await
+    }
 }
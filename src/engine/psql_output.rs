@@ -0,0 +1,285 @@
+//! Parsing of psql output for the queries spawn runs for its own
+//! bookkeeping. Queries are run with `\pset format csv`, which quotes any
+//! value containing a comma, quote or newline, so results are read with a
+//! real CSV parser rather than by splitting lines.
+//!
+//! Also finds psql's error message, and the notices and warnings the server
+//! sent, in output where stderr was merged into stdout, as it is when
+//! running tests and applying migrations.
+
+use crate::engine::Rows;
+use anyhow::{anyhow, Result};
+
+/// Parses psql's CSV output into rows. The first record is the header.
+/// psql prints NULL as an empty unquoted field and an empty string as `""`,
+/// so the two can be told apart.
+pub(super) fn parse_csv(output: &str) -> Result<Rows> {
+    let mut records: Vec<Vec<Option<String>>> = Vec::new();
+    let mut record: Vec<Option<String>> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = output.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                quoted = true;
+                in_quotes = true;
+            }
+            ',' => {
+                record.push(csv_value(std::mem::take(&mut field), quoted));
+                quoted = false;
+            }
+            '\r' => {}
+            '\n' => {
+                record.push(csv_value(std::mem::take(&mut field), quoted));
+                quoted = false;
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(anyhow!("unterminated quoted field in query output"));
+    }
+    if !field.is_empty() || quoted || !record.is_empty() {
+        record.push(csv_value(field, quoted));
+        records.push(record);
+    }
+
+    let mut records = records.into_iter();
+    let columns = records
+        .next()
+        .unwrap_or_default()
+        .into_iter()
+        .map(Option::unwrap_or_default)
+        .collect::<Vec<_>>();
+    let rows: Vec<Vec<Option<String>>> = records.collect();
+    if let Some(row) = rows.iter().find(|r| r.len() != columns.len()) {
+        return Err(anyhow!(
+            "query returned a row with {} values for {} columns",
+            row.len(),
+            columns.len()
+        ));
+    }
+
+    Ok(Rows { columns, rows })
+}
+
+fn csv_value(field: String, quoted: bool) -> Option<String> {
+    if field.is_empty() && !quoted {
+        None
+    } else {
+        Some(field)
+    }
+}
+
+/// Reads a boolean as psql prints it (`t` or `f`).
+pub(super) fn parse_bool(value: Option<&str>) -> Result<bool> {
+    match value {
+        Some("t") => Ok(true),
+        Some("f") => Ok(false),
+        other => Err(anyhow!(
+            "expected a boolean from the database, got {:?}",
+            other
+        )),
+    }
+}
+
+/// Prefixes of the lines psql starts an error message with. Messages about
+/// input read from a file or stdin are preceded by a `psql:<file>:<line>:`
+/// location.
+const ERROR_PREFIXES: [&str; 4] = ["ERROR:", "FATAL:", "PANIC:", "error:"];
+
+fn is_message_line(line: &str, prefix: &str) -> bool {
+    line.starts_with(prefix)
+        || (line.starts_with("psql:") && line.contains(&format!(" {}", prefix)))
+}
+
+fn is_error_line(line: &str) -> bool {
+    ERROR_PREFIXES
+        .iter()
+        .any(|prefix| is_message_line(line, prefix))
+}
+
+/// Severity of a message the server sent while running SQL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerMessageLevel {
+    Notice,
+    Warning,
+}
+
+/// A notice or warning the server sent, such as from `RAISE WARNING` or
+/// `DROP TABLE IF EXISTS` on a missing table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerMessage {
+    pub level: ServerMessageLevel,
+    /// The message's line from its `NOTICE:` or `WARNING:` on, without
+    /// psql's `psql:<stdin>:N: ` prefix
+    pub text: String,
+}
+
+/// The notice or warning on `line` of psql's output, if it is one.
+pub fn server_message(line: &str) -> Option<ServerMessage> {
+    [
+        ("NOTICE:", ServerMessageLevel::Notice),
+        ("WARNING:", ServerMessageLevel::Warning),
+    ]
+    .into_iter()
+    .find(|(prefix, _)| is_message_line(line, prefix))
+    .map(|(prefix, level)| ServerMessage {
+        level,
+        text: line[line.find(prefix).unwrap_or(0)..].to_string(),
+    })
+}
+
+/// The notices and warnings the server sent in `output`, which holds both
+/// stdout and stderr, in the order sent.
+pub fn server_messages(output: &str) -> Vec<ServerMessage> {
+    output.lines().filter_map(server_message).collect()
+}
+
+/// The error psql printed in `output`, which holds both stdout and stderr.
+/// Scripts run with `ON_ERROR_STOP`, so psql stops at the first error and
+/// everything from its first line on (the `LINE`, `DETAIL` and `HINT` lines
+/// that follow) is the error message.
+pub(super) fn error_message(output: &str) -> Option<String> {
+    let mut offset = 0;
+    for line in output.split_inclusive('\n') {
+        if is_error_line(line) {
+            return Some(output[offset..].trim_end().to_string());
+        }
+        offset += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let rows =
+            parse_csv("name,note,n\nusers,,1\n\"a,b\",\"\",2\n\"multi\nline \"\"q\"\"\",x,3\n")
+                .unwrap();
+        assert_eq!(vec!["name", "note", "n"], rows.columns);
+        assert_eq!(3, rows.len());
+
+        let values: Vec<(Option<&str>, Option<&str>)> = rows
+            .iter()
+            .map(|row| (row.get("name"), row.get("note")))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some("users"), None),
+                (Some("a,b"), Some("")),
+                (Some("multi\nline \"q\""), Some("x")),
+            ],
+            values
+        );
+        assert_eq!(None, rows.iter().next().unwrap().get("missing"));
+    }
+
+    #[test]
+    fn test_parse_csv_empty_result() {
+        let rows = parse_csv("exists\n").unwrap();
+        assert_eq!(vec!["exists"], rows.columns);
+        assert!(rows.is_empty());
+
+        assert!(parse_csv("a,b\n1\n").is_err());
+        assert!(parse_csv("a\n\"open\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_csv_crlf_and_no_trailing_newline() {
+        let rows = parse_csv("a,b\r\n1,2\r\n3,").unwrap();
+        assert_eq!(
+            vec![
+                vec![Some("1".to_string()), Some("2".to_string())],
+                vec![Some("3".to_string()), None],
+            ],
+            rows.rows
+        );
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        assert!(parse_bool(Some("t")).unwrap());
+        assert!(!parse_bool(Some("f")).unwrap());
+        assert!(parse_bool(Some("true")).is_err());
+        assert!(parse_bool(None).is_err());
+    }
+
+    #[test]
+    fn test_error_message() {
+        let output = "\
+ id
+----
+  1
+(1 row)
+
+psql:<stdin>:7: ERROR:  relation \"missing\" does not exist
+LINE 1: SELECT * FROM missing;
+                      ^
+";
+        assert_eq!(
+            Some(
+                "psql:<stdin>:7: ERROR:  relation \"missing\" does not exist\nLINE 1: SELECT * FROM missing;\n                      ^"
+                    .to_string()
+            ),
+            error_message(output)
+        );
+
+        assert_eq!(
+            Some("ERROR:  division by zero".to_string()),
+            error_message("ERROR:  division by zero\n")
+        );
+        // Notices and values that merely mention an error are not errors.
+        assert_eq!(
+            None,
+            error_message("psql:<stdin>:2: NOTICE:  skipping\n note\n------\n ERROR: x\n")
+        );
+    }
+
+    #[test]
+    fn test_server_messages() {
+        let output = "\
+psql:<stdin>:3: NOTICE:  relation \"users\" already exists, skipping
+psql:<stdin>:5: WARNING:  there is no transaction in progress
+NOTICE:  table \"old\" does not exist, skipping
+ note
+------
+ NOTICE: not one
+";
+        let messages = server_messages(output);
+        assert_eq!(3, messages.len());
+        assert_eq!(ServerMessageLevel::Notice, messages[0].level);
+        assert_eq!(
+            "NOTICE:  relation \"users\" already exists, skipping",
+            messages[0].text
+        );
+        assert_eq!(ServerMessageLevel::Warning, messages[1].level);
+        assert_eq!(
+            "WARNING:  there is no transaction in progress",
+            messages[1].text
+        );
+        assert_eq!(
+            "NOTICE:  table \"old\" does not exist, skipping",
+            messages[2].text
+        );
+        assert!(server_messages("").is_empty());
+    }
+}
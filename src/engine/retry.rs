@@ -0,0 +1,184 @@
+//! Retrying psql sessions that failed for reasons that pass, such as the
+//! database restarting, as set in a target's `[targets.<name>.retry]`
+//! section.
+//!
+//! Only failures where no SQL of the session can have run are retried:
+//! connections that could not be made, and spawn's advisory lock being
+//! held by another apply. A lock timeout is also retried in sessions
+//! running a single statement of spawn's own, which either ran whole or
+//! not at all. Failures of migration SQL are never retried, as statements
+//! before the failing one may have committed.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// How often and how patiently to retry transient failures.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RetryPolicy {
+    /// Attempts in all, including the first. 1 turns retrying off.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled for each one after
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// Longest wait between attempts
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    10_000
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Only tries once, for targets without a `retry` section.
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait after failed attempt `attempt`, counted from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+/// A failure that may not happen again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransientError {
+    /// psql could not connect, so nothing ran
+    Connection,
+    /// Another apply holds spawn's advisory lock, which is taken before
+    /// anything else runs
+    AdvisoryLock,
+    /// A statement waited longer than `lock_timeout` for a lock
+    LockTimeout,
+}
+
+impl fmt::Display for TransientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransientError::Connection => "could not connect",
+            TransientError::AdvisoryLock => "another apply holds the migration lock",
+            TransientError::LockTimeout => "lock timeout",
+        })
+    }
+}
+
+/// Messages of libpq when a connection cannot be made. A connection lost
+/// while running (`server closed the connection unexpectedly`) is not one
+/// of them, as some of the SQL may have run.
+const CONNECT_FAILURES: &[&str] = &[
+    "could not connect to server",
+    "Connection refused",
+    "could not translate host name",
+    "No such file or directory",
+    "timeout expired",
+    "the database system is starting up",
+    "the database system is shutting down",
+    "the database system is in recovery mode",
+    "too many clients already",
+    "remaining connection slots are reserved",
+];
+
+/// The transient error a psql run that exited with `exit_code` and printed
+/// `stderr` failed with, if any. Lock timeouts are only counted when
+/// `lock_timeouts` is true, for sessions that are safe to run again after
+/// one.
+pub fn transient_error(
+    exit_code: i32,
+    stderr: &str,
+    lock_timeouts: bool,
+) -> Option<TransientError> {
+    // psql exits with 2 for connection problems and 3 for errors in the
+    // script with ON_ERROR_STOP.
+    match exit_code {
+        2 if CONNECT_FAILURES.iter().any(|m| stderr.contains(m)) => {
+            Some(TransientError::Connection)
+        }
+        3 if stderr.contains("Could not acquire advisory lock") => {
+            Some(TransientError::AdvisoryLock)
+        }
+        3 if lock_timeouts && stderr.contains("canceling statement due to lock timeout") => {
+            Some(TransientError::LockTimeout)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 6,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 3_000,
+        };
+        let waits: Vec<u64> = (1..=5)
+            .map(|attempt| policy.backoff(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(vec![500, 1_000, 2_000, 3_000, 3_000], waits);
+        assert_eq!(Duration::from_millis(3_000), policy.backoff(200));
+    }
+
+    #[test]
+    fn test_transient_error() {
+        let refused = "psql: error: connection to server at \"db\" (10.0.0.5), port 5432 failed: Connection refused\n\tIs the server running on that host and accepting TCP/IP connections?";
+        assert_eq!(
+            Some(TransientError::Connection),
+            transient_error(2, refused, false)
+        );
+        let starting = "psql: error: connection to server on socket \"/tmp/.s.PGSQL.5432\" failed: FATAL:  the database system is starting up";
+        assert_eq!(
+            Some(TransientError::Connection),
+            transient_error(2, starting, false)
+        );
+        // Losing the connection part way may have left SQL applied.
+        let lost = "server closed the connection unexpectedly\n\tThis probably means the server terminated abnormally";
+        assert_eq!(None, transient_error(2, lost, true));
+
+        assert_eq!(
+            Some(TransientError::AdvisoryLock),
+            transient_error(3, "ERROR:  Could not acquire advisory lock", false)
+        );
+        let timeout = "psql:<stdin>:4: ERROR:  canceling statement due to lock timeout";
+        assert_eq!(None, transient_error(3, timeout, false));
+        assert_eq!(
+            Some(TransientError::LockTimeout),
+            transient_error(3, timeout, true)
+        );
+        assert_eq!(
+            None,
+            transient_error(3, "ERROR:  relation \"users\" already exists", true)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Broad classes of failure, so that telemetry can report what kind of error
+//! ended a command and users get the same hint for the same kind of problem.
+//!
+//! Errors are classified by marking them where the kind is known, with
+//! [`ErrorKind::wrap`] or [`WithErrorKind::with_kind`]. The marker is
+//! invisible when the error is printed. Errors that were not marked are
+//! classified from well known error types in their chain.
+
+use crate::engine::EngineError;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// spawn.toml or another project file could not be loaded
+    Config,
+    /// A migration or test template could not be rendered
+    Render,
+    /// The database could not be reached
+    DbConnection,
+    /// SQL sent to the database failed
+    SqlFailure,
+    /// Another process holds the migration lock
+    LockContention,
+    /// Stopped with Ctrl-C
+    Interrupted,
+    Other,
+}
+
+impl ErrorKind {
+    /// Stable name reported as the telemetry `error_kind`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Config => "config",
+            ErrorKind::Render => "render",
+            ErrorKind::DbConnection => "db_connection",
+            ErrorKind::SqlFailure => "sql_failure",
+            ErrorKind::LockContention => "lock_contention",
+            ErrorKind::Interrupted => "interrupted",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// A suggestion printed after errors of this kind.
+    pub fn hint(self) -> Option<&'static str> {
+        match self {
+            ErrorKind::Config => Some(
+                "Check spawn.toml (or the file given with --config-file), or run `spawn init` to create one.",
+            ),
+            ErrorKind::Render => Some(
+                "Check the template and any --variables. `spawn migration build <name>` shows the rendered SQL.",
+            ),
+            ErrorKind::DbConnection => Some(
+                "Check that the database is running and that the target's command in spawn.toml can connect to it.",
+            ),
+            ErrorKind::SqlFailure => Some(
+                "Fix the SQL and try again. A failed migration is re-applied with `spawn migration apply --retry <name>`.",
+            ),
+            ErrorKind::LockContention => Some(
+                "Another spawn process is applying migrations. Wait for it to finish and try again.",
+            ),
+            ErrorKind::Interrupted | ErrorKind::Other => None,
+        }
+    }
+
+    /// The kind of a failed psql run, from its exit code. psql exits with 2
+    /// when the connection fails or is lost.
+    pub fn for_psql_exit(exit_code: i32) -> ErrorKind {
+        if exit_code == 2 {
+            ErrorKind::DbConnection
+        } else {
+            ErrorKind::SqlFailure
+        }
+    }
+
+    /// Marks `error` as being of this kind.
+    pub fn wrap(self, error: impl Into<anyhow::Error>) -> anyhow::Error {
+        anyhow::Error::new(Classified {
+            kind: self,
+            error: error.into(),
+        })
+    }
+
+    /// The kind of `error`: the outermost marker in its chain, or failing
+    /// that, the first error type that tells the kind apart.
+    pub fn classify(error: &anyhow::Error) -> ErrorKind {
+        for cause in error.chain() {
+            if let Some(classified) = cause.downcast_ref::<Classified>() {
+                return classified.kind;
+            }
+            if let Some(EngineError::ExecutionFailed { exit_code, .. }) = cause.downcast_ref() {
+                return ErrorKind::for_psql_exit(*exit_code);
+            }
+            if cause.is::<minijinja::Error>() {
+                return ErrorKind::Render;
+            }
+            if cause.is::<config::ConfigError>() || cause.is::<toml::de::Error>() {
+                return ErrorKind::Config;
+            }
+        }
+        ErrorKind::Other
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error marked with its kind. It displays as, and has the same source as,
+/// the error it wraps, so the marker does not show when printed.
+#[derive(Debug)]
+struct Classified {
+    kind: ErrorKind,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Classified {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+pub trait WithErrorKind<T> {
+    /// Marks the error, if any, as being of `kind`.
+    fn with_kind(self, kind: ErrorKind) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> WithErrorKind<T> for Result<T, E> {
+    fn with_kind(self, kind: ErrorKind) -> anyhow::Result<T> {
+        self.map_err(|e| kind.wrap(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_marker_survives_context_and_is_invisible() {
+        let error = Err::<(), _>(anyhow!("could not parse spawn.toml"))
+            .with_kind(ErrorKind::Config)
+            .context("could not load config")
+            .unwrap_err();
+
+        assert_eq!(ErrorKind::Config, ErrorKind::classify(&error));
+        let messages: Vec<String> = error.chain().map(|c| c.to_string()).collect();
+        assert_eq!(
+            vec!["could not load config", "could not parse spawn.toml"],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_classifies_engine_errors() {
+        let unreachable = anyhow::Error::from(EngineError::ExecutionFailed {
+            exit_code: 2,
+            stderr: "could not connect to server".to_string(),
+        })
+        .context("failed to apply migration");
+        assert_eq!(ErrorKind::DbConnection, ErrorKind::classify(&unreachable));
+
+        let failed = anyhow::Error::from(EngineError::ExecutionFailed {
+            exit_code: 3,
+            stderr: "syntax error".to_string(),
+        });
+        assert_eq!(ErrorKind::SqlFailure, ErrorKind::classify(&failed));
+
+        assert_eq!(ErrorKind::Other, ErrorKind::classify(&anyhow!("oops")));
+    }
+
+    #[test]
+    fn test_outermost_marker_wins() {
+        let error = ErrorKind::LockContention.wrap(ErrorKind::SqlFailure.wrap(anyhow!("lock")));
+        assert_eq!(ErrorKind::LockContention, ErrorKind::classify(&error));
+    }
+}
@@ -0,0 +1,310 @@
+//! Callbacks for what happens while a command runs, so that an embedder of
+//! [`Spawn`](crate::Spawn) can show progress in its own UI or log. The CLI
+//! installs [`ConsoleEvents`]; otherwise events are dropped.
+
+use console::style;
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Receives events as a command runs. Every method does nothing by default,
+/// so a sink only implements those it needs. Methods may be called from
+/// other threads than the one running the command.
+pub trait EventSink: Send + Sync {
+    /// A migration is about to be applied. `index` counts from 0 up to
+    /// `total`, the number of migrations being applied.
+    fn on_migration_start(&self, _migration: &str, _index: usize, _total: usize) {}
+
+    /// A statement of `migration` is being sent to the database.
+    fn on_statement(&self, _migration: &str, _statement: &str) {}
+
+    /// The output of `test` differs from what it expected.
+    fn on_diff(&self, _test: &str, _diff: &str) {}
+
+    /// Something is worth knowing but does not stop the command.
+    fn on_warning(&self, _message: &str) {}
+}
+
+/// Drops every event.
+pub struct NoEvents;
+
+impl EventSink for NoEvents {}
+
+/// Prints events for the CLI. Migration progress is left to the apply
+/// progress bar, and statements are not printed.
+pub struct ConsoleEvents;
+
+impl EventSink for ConsoleEvents {
+    fn on_diff(&self, _test: &str, diff: &str) {
+        println!("{}", style("--- Diff ---").bold());
+        println!("{}", diff);
+    }
+
+    fn on_warning(&self, message: &str) {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+/// The event sink of a [`Config`](crate::config::Config). Clones share the
+/// same sink.
+#[derive(Clone)]
+pub struct Events(Arc<dyn EventSink>);
+
+impl Events {
+    pub fn new(sink: impl EventSink + 'static) -> Self {
+        Self(Arc::new(sink))
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new(NoEvents)
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Events")
+    }
+}
+
+impl std::ops::Deref for Events {
+    type Target = dyn EventSink;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scan {
+    Code,
+    SingleQuoted,
+    DoubleQuoted,
+    LineComment,
+    /// Block comments nest in postgres
+    BlockComment(usize),
+    /// Inside a `$tag$` quoted string, with the length of its `$tag$`
+    DollarQuoted(usize),
+}
+
+/// Passes SQL through to `inner`, calling `on_statement` with each statement
+/// once its terminating `;` has been written. Semicolons in strings, quoted
+/// identifiers, comments and dollar quoted bodies do not end a statement.
+pub struct StatementWriter<'a, F: FnMut(&str)> {
+    inner: &'a mut dyn Write,
+    on_statement: F,
+    statement: Vec<u8>,
+    /// How far `statement` has been scanned
+    scanned: usize,
+    scan: Scan,
+    /// The `$tag$` that opened the current dollar quote
+    dollar_tag: Vec<u8>,
+}
+
+impl<'a, F: FnMut(&str)> StatementWriter<'a, F> {
+    pub fn new(inner: &'a mut dyn Write, on_statement: F) -> Self {
+        Self {
+            inner,
+            on_statement,
+            statement: Vec::new(),
+            scanned: 0,
+            scan: Scan::Code,
+            dollar_tag: Vec::new(),
+        }
+    }
+
+    /// Reports whatever follows the last `;`, if it is more than whitespace.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        let rest = std::mem::take(&mut self.statement);
+        self.emit(&rest);
+        self.inner.flush()
+    }
+
+    fn emit(&mut self, statement: &[u8]) {
+        let statement = String::from_utf8_lossy(statement);
+        let statement = statement.trim();
+        if !statement.is_empty() {
+            (self.on_statement)(statement);
+        }
+    }
+
+    /// Scans the buffered SQL for complete statements. Stops early when it
+    /// needs to see more input to decide, such as after a `-` or `$`.
+    fn scan(&mut self) {
+        let mut i = self.scanned;
+        while i < self.statement.len() {
+            let b = self.statement[i];
+            let next = self.statement.get(i + 1).copied();
+            match self.scan {
+                Scan::Code => match b {
+                    b'\'' => self.scan = Scan::SingleQuoted,
+                    b'"' => self.scan = Scan::DoubleQuoted,
+                    b'-' | b'/' if next.is_none() => break,
+                    b'-' if next == Some(b'-') => {
+                        self.scan = Scan::LineComment;
+                        i += 1;
+                    }
+                    b'/' if next == Some(b'*') => {
+                        self.scan = Scan::BlockComment(1);
+                        i += 1;
+                    }
+                    // `$` may also be part of an identifier, such as `a$b`.
+                    b'$' if i > 0 && is_identifier_byte(self.statement[i - 1]) => {}
+                    b'$' => match dollar_tag_len(&self.statement[i..]) {
+                        None => break,
+                        Some(0) => {}
+                        Some(len) => {
+                            self.dollar_tag = self.statement[i..i + len].to_vec();
+                            self.scan = Scan::DollarQuoted(len);
+                            i += len - 1;
+                        }
+                    },
+                    b';' => {
+                        let rest = self.statement.split_off(i + 1);
+                        let statement = std::mem::replace(&mut self.statement, rest);
+                        self.emit(&statement);
+                        i = 0;
+                        continue;
+                    }
+                    _ => {}
+                },
+                Scan::SingleQuoted if b == b'\'' => self.scan = Scan::Code,
+                Scan::DoubleQuoted if b == b'"' => self.scan = Scan::Code,
+                Scan::LineComment if b == b'\n' => self.scan = Scan::Code,
+                Scan::BlockComment(depth) if b == b'*' || b == b'/' => {
+                    let Some(next) = next else { break };
+                    if b == b'*' && next == b'/' {
+                        self.scan = if depth == 1 {
+                            Scan::Code
+                        } else {
+                            Scan::BlockComment(depth - 1)
+                        };
+                        i += 1;
+                    } else if b == b'/' && next == b'*' {
+                        self.scan = Scan::BlockComment(depth + 1);
+                        i += 1;
+                    }
+                }
+                Scan::DollarQuoted(len) if b == b'$' => {
+                    if self.statement.len() < i + len {
+                        break;
+                    }
+                    if self.statement[i..i + len] == self.dollar_tag[..] {
+                        self.scan = Scan::Code;
+                        i += len - 1;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        self.scanned = i;
+    }
+}
+
+pub(crate) fn is_identifier_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b >= 0x80
+}
+
+/// The length of the `$tag$` at the start of `sql`, Some(0) if it does not
+/// start a dollar quote (such as `$1`), or None if more input is needed.
+pub(crate) fn dollar_tag_len(sql: &[u8]) -> Option<usize> {
+    for (i, &b) in sql.iter().enumerate().skip(1) {
+        match b {
+            b'$' => return Some(i + 1),
+            b'0'..=b'9' if i == 1 => return Some(0),
+            b if is_identifier_byte(b) => {}
+            _ => return Some(0),
+        }
+    }
+    None
+}
+
+impl<F: FnMut(&str)> Write for StatementWriter<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.statement.extend_from_slice(&buf[..n]);
+        self.scan();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `chunks` one at a time, returning the statements reported and
+    /// everything passed through.
+    fn split(chunks: &[&str]) -> (Vec<String>, String) {
+        let mut out = Vec::new();
+        let mut statements = Vec::new();
+        let mut writer = StatementWriter::new(&mut out, |s| statements.push(s.to_string()));
+        for chunk in chunks {
+            writer.write_all(chunk.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        (statements, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_splits_statements() {
+        let sql = "BEGIN;\nCREATE TABLE t (id int);\nINSERT INTO t VALUES (1);\nCOMMIT;\n";
+        let (statements, out) = split(&[sql]);
+        assert_eq!(
+            vec![
+                "BEGIN;",
+                "CREATE TABLE t (id int);",
+                "INSERT INTO t VALUES (1);",
+                "COMMIT;"
+            ],
+            statements
+        );
+        assert_eq!(sql, out);
+    }
+
+    #[test]
+    fn test_ignores_semicolons_in_quotes_and_comments() {
+        let sql = "SELECT 'a;b', \"c;d\" -- e;f\n FROM t /* g; /* h; */ i; */;\n\
+                   CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql;\n\
+                   SELECT $1, $$x;y$$, a$b$c;\ntrailing";
+        let (statements, _) = split(&[sql]);
+        assert_eq!(
+            vec![
+                "SELECT 'a;b', \"c;d\" -- e;f\n FROM t /* g; /* h; */ i; */;",
+                "CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql;",
+                "SELECT $1, $$x;y$$, a$b$c;",
+                "trailing",
+            ],
+            statements
+        );
+    }
+
+    #[test]
+    fn test_statements_split_across_writes() {
+        let chunks = [
+            "SELECT 1 -",
+            "- a;\n;",
+            "SELECT $b",
+            "ody$ ;",
+            " $bo",
+            "dy$; SELECT '",
+            "'';'",
+            ";",
+        ];
+        let (statements, out) = split(&chunks);
+        assert_eq!(
+            vec![
+                "SELECT 1 -- a;\n;",
+                "SELECT $body$ ; $body$;",
+                "SELECT ''';';"
+            ],
+            statements
+        );
+        assert_eq!(chunks.concat(), out);
+    }
+}
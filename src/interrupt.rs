@@ -0,0 +1,52 @@
+//! Ctrl-C handling for work that should not be cut off part way through,
+//! such as applying migrations.
+//!
+//! Once [`install`]ed, the first Ctrl-C no longer ends the process. It is
+//! reported through [`interrupted`] and [`is_interrupted`] instead, so that
+//! the running command can stop its database session and record what
+//! happened. A second Ctrl-C exits immediately.
+
+use crate::commands::ExitCode;
+use std::sync::OnceLock;
+use tokio::sync::watch;
+
+static INTERRUPTED: OnceLock<watch::Receiver<bool>> = OnceLock::new();
+
+/// Starts listening for Ctrl-C. Calling this more than once is harmless.
+/// Must be called from within a tokio runtime.
+pub fn install() {
+    INTERRUPTED.get_or_init(|| {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            eprintln!("\nInterrupted, stopping. Press Ctrl-C again to exit immediately.");
+            let _ = sender.send(true);
+
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(ExitCode::Interrupted as i32);
+            }
+        });
+        receiver
+    });
+}
+
+/// Whether Ctrl-C has been pressed since [`install`].
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.get().is_some_and(|receiver| *receiver.borrow())
+}
+
+/// Completes once Ctrl-C is pressed. Never completes if [`install`] has not
+/// been called.
+pub async fn interrupted() {
+    match INTERRUPTED.get() {
+        Some(receiver) => {
+            let mut receiver = receiver.clone();
+            if receiver.wait_for(|interrupted| *interrupted).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
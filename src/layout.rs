@@ -0,0 +1,217 @@
+//! How migrations are laid out on disk, in the `[layout]` section. By default
+//! each migration is a folder beneath `migrations/` holding `up.sql` and
+//! `lock.toml`. Projects moving from other tools can rename the folder, the
+//! script and the lock file, or keep every migration as a flat file such as
+//! `migrations/20240501-add-users.sql`. Projects with many migrations can
+//! shard them into a folder per year or month of their timestamp, such as
+//! `migrations/2024/05/20240501120000-add-users/up.sql`.
+
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_MIGRATIONS_FOLDER: &str = "migrations";
+const DEFAULT_SCRIPT: &str = "up.sql";
+const DEFAULT_LOCK_FILE: &str = "lock.toml";
+
+/// Folder within the migrations folder that `spawn migration archive` moves
+/// old applied migrations to, laid out as the migrations folder is.
+pub const ARCHIVE_FOLDER: &str = "_archive";
+
+/// Layout of the migrations folder, in the `[layout]` section.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LayoutConfig {
+    /// Folder holding the migrations, relative to the spawn folder.
+    /// Defaults to `migrations`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migrations_folder: Option<String>,
+    /// Name of each migration's script. Defaults to `up.sql`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    /// Name of each migration's lock file. Defaults to `lock.toml`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_file: Option<String>,
+    /// Keep each migration's files directly in the migrations folder, named
+    /// `<migration>.<file>` (such as `20240501-add-users.up.sql`), rather
+    /// than in a folder per migration.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub flat: bool,
+    /// Group migrations into folders by the year or month of the timestamp
+    /// their name starts with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+}
+
+/// How migrations are grouped into folders by their timestamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shard {
+    /// `migrations/2024/<migration>`
+    Year,
+    /// `migrations/2024/05/<migration>`
+    Month,
+}
+
+/// A file of a migration recognised when listing the migrations folder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationFile {
+    Script,
+    Lock,
+}
+
+impl LayoutConfig {
+    pub fn migrations_folder(&self) -> &str {
+        self.migrations_folder
+            .as_deref()
+            .unwrap_or(DEFAULT_MIGRATIONS_FOLDER)
+    }
+
+    pub fn script(&self) -> &str {
+        self.script.as_deref().unwrap_or(DEFAULT_SCRIPT)
+    }
+
+    pub fn lock_file(&self) -> &str {
+        self.lock_file.as_deref().unwrap_or(DEFAULT_LOCK_FILE)
+    }
+
+    /// The shard folder `migration` is kept in, such as `2024/05`, or None
+    /// when migrations are not sharded or its name does not start with a
+    /// `YYYYMMDD` timestamp.
+    pub fn shard_folder(&self, migration: &str) -> Option<String> {
+        let shard = self.shard?;
+        let date = migration.get(..8)?;
+        if !date.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(match shard {
+            Shard::Year => date[..4].to_string(),
+            Shard::Month => format!("{}/{}", &date[..4], &date[4..6]),
+        })
+    }
+
+    /// The migration and file that `relative_path`, a path beneath the
+    /// migrations folder, belongs to. None for anything else, such as
+    /// variables files or includes kept beside the script.
+    pub fn parse_path<'a>(&self, relative_path: &'a str) -> Option<(&'a str, MigrationFile)> {
+        let parsed = self.parse_unsharded_path(relative_path);
+        if self.shard.is_none() {
+            return parsed;
+        }
+        if let Some((name, _)) = parsed {
+            // Only migrations without a timestamp are kept unsharded.
+            return parsed.filter(|_| self.shard_folder(name).is_none());
+        }
+        let depth = match self.shard? {
+            Shard::Year => 1,
+            Shard::Month => 2,
+        };
+        let mut parts = relative_path.splitn(depth + 1, '/');
+        let shard: Vec<&str> = parts.by_ref().take(depth).collect();
+        let rest = parts.next()?;
+        self.parse_unsharded_path(rest)
+            .filter(|(name, _)| self.shard_folder(name) == Some(shard.join("/")))
+    }
+
+    fn parse_unsharded_path<'a>(&self, relative_path: &'a str) -> Option<(&'a str, MigrationFile)> {
+        let files = [
+            (self.script(), MigrationFile::Script),
+            (self.lock_file(), MigrationFile::Lock),
+        ];
+        if self.flat {
+            if relative_path.contains('/') {
+                return None;
+            }
+            return files.into_iter().find_map(|(file, kind)| {
+                relative_path
+                    .strip_suffix(file)
+                    .and_then(|rest| rest.strip_suffix('.'))
+                    .filter(|name| !name.is_empty())
+                    .map(|name| (name, kind))
+            });
+        }
+        let (name, filename) = relative_path.split_once('/')?;
+        files
+            .into_iter()
+            .find(|(file, _)| *file == filename)
+            .map(|(_, kind)| (name, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_path() {
+        let folders = LayoutConfig::default();
+        assert_eq!(
+            Some(("20240501-add-users", MigrationFile::Script)),
+            folders.parse_path("20240501-add-users/up.sql")
+        );
+        assert_eq!(
+            Some(("20240501-add-users", MigrationFile::Lock)),
+            folders.parse_path("20240501-add-users/lock.toml")
+        );
+        assert_eq!(
+            None,
+            folders.parse_path("20240501-add-users/variables.toml")
+        );
+        assert_eq!(
+            None,
+            folders.parse_path("20240501-add-users/partials/up.sql")
+        );
+        assert_eq!(None, folders.parse_path("up.sql"));
+
+        let flat = LayoutConfig {
+            script: Some("sql".to_string()),
+            flat: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(("20240501-add-users", MigrationFile::Script)),
+            flat.parse_path("20240501-add-users.sql")
+        );
+        assert_eq!(
+            Some(("20240501-add-users", MigrationFile::Lock)),
+            flat.parse_path("20240501-add-users.lock.toml")
+        );
+        assert_eq!(None, flat.parse_path("20240501-add-users/partial.sql"));
+        assert_eq!(None, flat.parse_path(".sql"));
+        assert_eq!(None, flat.parse_path("notes.txt"));
+    }
+
+    #[test]
+    fn test_sharded_paths() {
+        let monthly = LayoutConfig {
+            shard: Some(Shard::Month),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some("2024/05".to_string()),
+            monthly.shard_folder("20240501120000-add-users")
+        );
+        assert_eq!(None, monthly.shard_folder("base-tables"));
+        assert_eq!(
+            Some(("20240501120000-add-users", MigrationFile::Script)),
+            monthly.parse_path("2024/05/20240501120000-add-users/up.sql")
+        );
+        // In the wrong shard, or not sharded at all.
+        assert_eq!(
+            None,
+            monthly.parse_path("2024/06/20240501120000-add-users/up.sql")
+        );
+        assert_eq!(None, monthly.parse_path("20240501120000-add-users/up.sql"));
+        assert_eq!(
+            Some(("base-tables", MigrationFile::Lock)),
+            monthly.parse_path("base-tables/lock.toml")
+        );
+
+        let yearly_flat = LayoutConfig {
+            shard: Some(Shard::Year),
+            flat: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(("20240501120000-add-users", MigrationFile::Script)),
+            yearly_flat.parse_path("2024/20240501120000-add-users.up.sql")
+        );
+    }
+}
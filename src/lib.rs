@@ -1,21 +1,51 @@
+pub mod advisor;
+pub mod agent;
+pub mod annotations;
+pub mod audit;
 pub mod cli;
 pub mod commands;
 pub mod config;
+pub mod dashboard;
 pub mod engine;
+pub mod errors;
 pub mod escape;
+pub mod events;
+pub mod interrupt;
+pub mod layout;
+pub mod locks;
+pub mod meta_commands;
 pub mod migrator;
+pub mod naming;
+pub mod notify;
+pub mod output;
+pub mod packages;
 pub mod pinfile;
+pub mod policy;
+pub mod scaffold;
+pub mod schedule;
+pub mod schema_docs;
+pub mod scrub;
+pub mod secrets;
+pub mod server;
+pub mod signing;
+pub mod spawn;
 pub mod sql_formatter;
 pub mod sqltest;
 pub mod store;
 pub mod telemetry;
 pub mod template;
+pub mod tracing;
+pub mod transcript;
+pub mod validate;
 pub mod variables;
 
+pub use spawn::{ApplyOptions, Spawn};
+
 /// Display the telemetry notice to stderr
 pub fn show_telemetry_notice() {
     eprintln!("▶ Spawn collects anonymous usage data.");
     eprintln!("  This helps us improve Spawn.");
-    eprintln!("  Set \"telemetry = false\" in spawn.toml or use DO_NOT_TRACK=1 to opt-out.");
+    eprintln!("  Run `spawn telemetry disable` or use DO_NOT_TRACK=1 to opt-out.");
     eprintln!();
 }
+pub mod workspace;
@@ -0,0 +1,271 @@
+//! Who holds the migration lock, for `spawn migration locks` and for
+//! explaining failures to get it.
+//!
+//! The lock itself is a session-level advisory lock, which says nothing
+//! about who took it. So that it can be told apart from a crashed one, an
+//! apply also records itself in the `lock` table of spawn's schema once it
+//! has the lock, and updates `heartbeat_at` every [`HEARTBEAT`] while the
+//! migration runs. The table is only written when spawn's schema is in the
+//! target's database, where the lock is taken.
+
+use crate::engine::postgres_psql::migration_lock_key;
+use crate::engine::Engine;
+use crate::escape::{EscapedIdentifier, EscapedLiteral, EscapedQuery, InsecureRawSql};
+use crate::sql_query;
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+/// How often a running apply updates its `heartbeat_at`.
+pub const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// A holder whose heartbeat is older than this is taken to have crashed.
+pub const STALE_AFTER: Duration = Duration::from_secs(60);
+
+/// A database session holding the migration lock.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LockHolder {
+    /// Process ID of the session's backend, as in `pg_stat_activity`
+    pub backend_pid: i32,
+    /// Address the session connected from, None for a Unix socket
+    pub client: Option<String>,
+    pub backend_start: Option<String>,
+    /// What the apply recorded about itself in the lock table, if it did
+    pub apply: Option<LockApply>,
+}
+
+/// What an apply records about itself once it holds the lock.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LockApply {
+    pub hostname: Option<String>,
+    /// Process ID of spawn on `hostname`
+    pub pid: Option<u32>,
+    pub migration_name: Option<String>,
+    pub started_at: String,
+    /// Seconds since the last heartbeat
+    pub heartbeat_age: u64,
+}
+
+impl LockHolder {
+    /// Whether the holder has stopped sending heartbeats. Holders that did
+    /// not record themselves are never taken to be stale, since there is
+    /// no telling.
+    pub fn is_stale(&self) -> bool {
+        self.apply
+            .as_ref()
+            .is_some_and(|apply| apply.heartbeat_age > STALE_AFTER.as_secs())
+    }
+
+    /// One line on who holds the lock, for messages.
+    pub fn describe(&self) -> String {
+        match &self.apply {
+            Some(apply) => format!(
+                "{}:{} applying {} since {} (last heartbeat {}s ago{})",
+                apply.hostname.as_deref().unwrap_or("unknown host"),
+                apply
+                    .pid
+                    .map(|pid| pid.to_string())
+                    .unwrap_or_else(|| "?".to_string()),
+                apply.migration_name.as_deref().unwrap_or("a migration"),
+                apply.started_at,
+                apply.heartbeat_age,
+                if self.is_stale() { ", stale" } else { "" }
+            ),
+            None => format!(
+                "session {} from {} since {}",
+                self.backend_pid,
+                self.client.as_deref().unwrap_or("a local socket"),
+                self.backend_start.as_deref().unwrap_or("an unknown time")
+            ),
+        }
+    }
+}
+
+/// The lock table in `spawn_schema`.
+fn lock_table(spawn_schema: &str) -> InsecureRawSql {
+    InsecureRawSql::new(&format!("{}.lock", EscapedIdentifier::new(spawn_schema)))
+}
+
+fn lock_key() -> InsecureRawSql {
+    InsecureRawSql::new(&migration_lock_key().to_string())
+}
+
+fn process_id() -> InsecureRawSql {
+    InsecureRawSql::new(&std::process::id().to_string())
+}
+
+/// psql lines running `statement` only when the lock table is in this
+/// database.
+fn if_lock_table(spawn_schema: &str, statement: EscapedQuery) -> EscapedQuery {
+    sql_query!(
+        "SELECT to_regclass({}) IS NOT NULL AS spawn_lock_table \\gset\n\\if :spawn_lock_table\n{}\n\\endif\n",
+        EscapedLiteral::new(lock_table(spawn_schema).as_str()),
+        InsecureRawSql::new(statement.as_str()),
+    )
+}
+
+/// SQL for the session that has just taken the lock to record itself as
+/// this process applying `migration_name`.
+pub fn record_holder_sql(spawn_schema: &str, migration_name: &str) -> EscapedQuery {
+    if_lock_table(
+        spawn_schema,
+        sql_query!(
+            "INSERT INTO {} (lock_key, hostname, pid, backend_pid, migration_name, started_at, heartbeat_at) \
+             VALUES ({}, {}, {}, pg_backend_pid(), {}, now(), now()) \
+             ON CONFLICT (lock_key) DO UPDATE SET hostname = EXCLUDED.hostname, pid = EXCLUDED.pid, \
+             backend_pid = EXCLUDED.backend_pid, migration_name = EXCLUDED.migration_name, \
+             started_at = EXCLUDED.started_at, heartbeat_at = EXCLUDED.heartbeat_at;",
+            lock_table(spawn_schema),
+            lock_key(),
+            EscapedLiteral::new(&hostname().unwrap_or_default()),
+            process_id(),
+            EscapedLiteral::new(migration_name),
+        ),
+    )
+}
+
+/// SQL updating the heartbeat of this process's apply, from a session of
+/// its own.
+pub fn heartbeat_sql(spawn_schema: &str) -> EscapedQuery {
+    if_lock_table(
+        spawn_schema,
+        sql_query!(
+            "UPDATE {} SET heartbeat_at = now() WHERE lock_key = {} AND hostname = {} AND pid = {};",
+            lock_table(spawn_schema),
+            lock_key(),
+            EscapedLiteral::new(&hostname().unwrap_or_default()),
+            process_id(),
+        ),
+    )
+}
+
+/// The sessions holding the migration lock in the engine's database, with
+/// what they recorded about themselves in the lock table of `spawn_schema`.
+pub async fn lock_holders(engine: &dyn Engine, spawn_schema: &str) -> Result<Vec<LockHolder>> {
+    // A bigint advisory lock key is split into classid (high half) and
+    // objid (low half), with objsubid 1.
+    let key = migration_lock_key() as u64;
+    let rows = engine
+        .query(&sql_query!(
+            "SELECT l.pid AS backend_pid, host(a.client_addr) AS client, a.backend_start \
+             FROM pg_locks l JOIN pg_stat_activity a ON a.pid = l.pid \
+             WHERE l.locktype = 'advisory' AND l.granted AND l.objsubid = 1 \
+             AND l.database = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+             AND l.classid = {} AND l.objid = {};",
+            InsecureRawSql::new(&(key >> 32).to_string()),
+            InsecureRawSql::new(&(key & 0xffff_ffff).to_string()),
+        ))
+        .await?;
+    let mut holders = Vec::new();
+    for row in rows.iter() {
+        holders.push(LockHolder {
+            backend_pid: row.require("backend_pid")?.parse()?,
+            client: row.get("client").map(str::to_string),
+            backend_start: row.get("backend_start").map(str::to_string),
+            apply: None,
+        });
+    }
+    if holders.is_empty() {
+        return Ok(holders);
+    }
+
+    let recorded = engine
+        .query(&sql_query!(
+            "SELECT to_regclass({}) IS NOT NULL AS recorded;",
+            EscapedLiteral::new(lock_table(spawn_schema).as_str()),
+        ))
+        .await?;
+    if !recorded.iter().any(|row| row.get("recorded") == Some("t")) {
+        return Ok(holders);
+    }
+    let applies = engine
+        .query(&sql_query!(
+            "SELECT backend_pid, hostname, pid, migration_name, started_at, \
+             extract(epoch FROM now() - heartbeat_at)::bigint AS heartbeat_age \
+             FROM {} WHERE lock_key = {};",
+            lock_table(spawn_schema),
+            lock_key(),
+        ))
+        .await?;
+    for row in applies.iter() {
+        let backend_pid: i32 = row.require("backend_pid")?.parse()?;
+        // A row left by an apply that has since finished names another
+        // session.
+        if let Some(holder) = holders.iter_mut().find(|h| h.backend_pid == backend_pid) {
+            holder.apply = Some(LockApply {
+                hostname: row.get("hostname").map(str::to_string),
+                pid: row.get("pid").and_then(|pid| pid.parse().ok()),
+                migration_name: row.get("migration_name").map(str::to_string),
+                started_at: row.require("started_at")?.to_string(),
+                heartbeat_age: row.require("heartbeat_age")?.parse().unwrap_or(0),
+            });
+        }
+    }
+    Ok(holders)
+}
+
+/// Ends the session of `holder`, releasing the lock.
+pub async fn steal(engine: &dyn Engine, holder: &LockHolder) -> Result<()> {
+    let rows = engine
+        .query(&sql_query!(
+            "SELECT pg_terminate_backend({}) AS terminated;",
+            InsecureRawSql::new(&holder.backend_pid.to_string()),
+        ))
+        .await?;
+    if !rows.iter().any(|row| row.get("terminated") == Some("t")) {
+        return Err(anyhow!(
+            "could not end session {}, which may have ended already",
+            holder.backend_pid
+        ));
+    }
+    Ok(())
+}
+
+/// Name of this machine, as recorded for lock holders.
+pub fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| {
+            std::process::Command::new("hostname")
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_holder() {
+        let mut holder = LockHolder {
+            backend_pid: 4242,
+            client: Some("10.0.0.7".to_string()),
+            backend_start: Some("2026-10-15 09:00:00+00".to_string()),
+            apply: None,
+        };
+        assert!(!holder.is_stale());
+        assert_eq!(
+            "session 4242 from 10.0.0.7 since 2026-10-15 09:00:00+00",
+            holder.describe()
+        );
+
+        holder.apply = Some(LockApply {
+            hostname: Some("ci-runner-3".to_string()),
+            pid: Some(811),
+            migration_name: Some("20261015-add-orders".to_string()),
+            started_at: "2026-10-15 09:00:01+00".to_string(),
+            heartbeat_age: 5,
+        });
+        assert!(!holder.is_stale());
+        assert_eq!(
+            "ci-runner-3:811 applying 20261015-add-orders since 2026-10-15 09:00:01+00 (last heartbeat 5s ago)",
+            holder.describe()
+        );
+        holder.apply.as_mut().unwrap().heartbeat_age = 600;
+        assert!(holder.is_stale());
+    }
+}
@@ -3,7 +3,9 @@ use clap::Parser;
 use opendal::services::Fs;
 use opendal::Operator;
 use spawn_db::cli::{run_cli, Cli};
-use spawn_db::commands::{Outcome, TelemetryDescribe};
+use spawn_db::commands::{ExitCode, Outcome, TelemetryDescribe};
+use spawn_db::errors::ErrorKind;
+use spawn_db::status;
 use spawn_db::telemetry::{self, CommandStatus, TelemetryRecorder};
 
 fn main() -> Result<()> {
@@ -42,28 +44,40 @@ async fn async_main(cli: Cli) -> Result<()> {
         result.telemetry_enabled,
         telemetry_info,
         start_time,
-    );
+    )
+    .with_endpoint(result.telemetry_endpoint.clone());
 
     // Finish telemetry based on outcome
     let (status, error_kind) = match &result.outcome {
         Ok(_) => (CommandStatus::Success, None),
-        Err(e) => {
-            let kind = extract_error_kind(e);
-            (CommandStatus::Error, Some(kind))
-        }
+        Err(e) => (CommandStatus::Error, Some(ErrorKind::classify(e).as_str())),
     };
 
     // This spawns a detached child process to send telemetry
     // The main process can exit immediately
-    recorder.finish(status, error_kind.as_deref());
+    recorder.finish_with_session(status, error_kind, &result.telemetry_session);
+
+    let outcome = match result.outcome {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            if let Some(hint) = ErrorKind::classify(&e).hint() {
+                eprintln!("\nHint: {}", hint);
+            }
+            std::process::exit(ExitCode::for_error(&e) as i32);
+        }
+    };
+    let exit_code = outcome.exit_code();
 
     // Handle the actual outcome
-    match result.outcome? {
+    match outcome {
+        // "Aborted." has already been printed.
+        Outcome::Aborted => {}
         Outcome::AdoptedMigration => {
-            println!("Migration has been adopted.");
+            status!("Migration has been adopted.");
         }
         Outcome::AppliedMigrations => {
-            println!("All migrations applied successfully.");
+            status!("All migrations applied successfully.");
         }
         Outcome::BuiltMigration {
             content,
@@ -75,37 +89,38 @@ async fn async_main(cli: Cli) -> Result<()> {
                 eprintln!("\n\n⚠️  This migration has been pinned. Run with --pinned to see how it will be generated when applied to a database.");
             }
         }
-        Outcome::CheckFailed => {
-            std::process::exit(1);
+        Outcome::CheckFailed => {}
+        Outcome::DriftDetected => {
+            eprintln!("Drift detected.");
+        }
+        Outcome::PendingMigrations(count) => {
+            eprintln!(
+                "{} pending migration{}.",
+                count,
+                if count == 1 { "" } else { "s" }
+            );
+        }
+        Outcome::TestsFailed => {
+            eprintln!("Differences found in one or more tests.");
         }
         Outcome::NewMigration(name) => {
-            println!("New migration created: {}", name);
+            status!("New migration created: {}", name);
         }
         Outcome::NewTest(name) => {
-            println!("New test created: {}", name);
+            status!("New test created: {}", name);
         }
         Outcome::PinnedMigration { hash } => {
-            println!("Migration pinned: {}", hash);
+            status!("Migration pinned: {}", hash);
         }
         Outcome::Success => {}
         Outcome::Unimplemented => {
-            println!("Unimplemented command.");
+            eprintln!("Unimplemented command.");
         }
     }
 
-    Ok(())
-}
-
-/// Extract a sanitized error kind from an anyhow::Error
-fn extract_error_kind(error: &anyhow::Error) -> String {
-    // Try to get the root cause type name
-    let root = error.root_cause();
-    let type_name = std::any::type_name_of_val(root);
+    if exit_code != ExitCode::Success {
+        std::process::exit(exit_code as i32);
+    }
 
-    // Extract just the type name without the full path
-    type_name
-        .rsplit("::")
-        .next()
-        .unwrap_or("Unknown")
-        .to_string()
+    Ok(())
 }
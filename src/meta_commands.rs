@@ -0,0 +1,392 @@
+//! Policy on psql meta-commands in rendered SQL. Migrations are run by psql
+//! with the operator's privileges, where `\!` runs a shell command and
+//! `\copy` reads or writes local files, so a project can forbid them, for
+//! every environment or just some. Checked before a migration is applied
+//! and by `check`.
+
+use crate::events::{dollar_tag_len, is_identifier_byte};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Policy on psql meta-commands, in the `[meta_commands]` section or an
+/// environment's `meta_commands`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MetaCommandPolicy {
+    /// Meta-commands that may not be used, such as `"!"`, `"setenv"` or
+    /// `"copy"`, with or without the backslash
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forbid: Vec<String>,
+    /// When set, `\copy` may only use stdin, stdout or files beneath these
+    /// paths, and never a `PROGRAM`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_paths: Option<Vec<String>>,
+}
+
+/// A meta-command found in SQL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetaCommand {
+    /// Name without the backslash, such as `copy`
+    pub name: String,
+    pub args: String,
+    /// 1-based line it is on
+    pub line: usize,
+}
+
+/// A meta-command the policy does not allow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// 1-based line of the meta-command
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl MetaCommandPolicy {
+    /// Whether the policy allows every meta-command.
+    pub fn is_empty(&self) -> bool {
+        self.forbid.is_empty() && self.copy_paths.is_none()
+    }
+
+    /// The meta-commands in `sql` that the policy does not allow.
+    pub fn violations(&self, sql: &str) -> Vec<Violation> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let forbidden: Vec<&str> = self
+            .forbid
+            .iter()
+            .map(|c| c.trim().trim_start_matches('\\'))
+            .collect();
+
+        let mut violations = Vec::new();
+        for command in find_meta_commands(sql) {
+            let message = if forbidden.contains(&command.name.as_str()) {
+                Some(format!("\\{} is forbidden", command.name))
+            } else if command.name == "copy" {
+                self.copy_paths
+                    .as_ref()
+                    .and_then(|paths| copy_violation(&command.args, paths))
+            } else {
+                None
+            };
+            if let Some(message) = message {
+                violations.push(Violation {
+                    line: command.line,
+                    message,
+                });
+            }
+        }
+        violations
+    }
+
+    /// Checks the rendered SQL of `migration`, with an error listing every
+    /// violation.
+    pub fn check(&self, migration: &str, sql: &str) -> Result<()> {
+        let violations = self.violations(sql);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = violations.iter().map(|v| format!("  {}", v)).collect();
+        Err(anyhow!(
+            "migration {} uses psql meta-commands that the meta_commands policy forbids:\n{}",
+            migration,
+            lines.join("\n")
+        ))
+    }
+}
+
+/// Why the `\copy` with `args` breaks `copy_paths`, if it does.
+fn copy_violation(args: &str, paths: &[String]) -> Option<String> {
+    let words = copy_words(args);
+    let Some(target) = words
+        .iter()
+        .position(|w| w.eq_ignore_ascii_case("from") || w.eq_ignore_ascii_case("to"))
+        .and_then(|i| words.get(i + 1))
+    else {
+        return Some("\\copy without a FROM or TO file cannot be checked".to_string());
+    };
+
+    if target.eq_ignore_ascii_case("program") {
+        return Some("\\copy with PROGRAM runs a shell command".to_string());
+    }
+    if ["stdin", "stdout", "pstdin", "pstdout"]
+        .iter()
+        .any(|s| target.eq_ignore_ascii_case(s))
+    {
+        return None;
+    }
+
+    let file = target.trim_start_matches("./");
+    let allowed = !file.split('/').any(|segment| segment == "..")
+        && paths
+            .iter()
+            .any(|path| file.starts_with(path.trim_start_matches("./")));
+    if allowed {
+        None
+    } else {
+        Some(format!(
+            "\\copy uses {}, which is not beneath any of copy_paths",
+            target
+        ))
+    }
+}
+
+/// The words of `\copy` arguments, unquoted, with a parenthesized query
+/// kept as one word.
+fn copy_words(args: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    for c in args.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                if depth > 0 {
+                    current.push(c);
+                }
+            }
+            (Some(_), c) => current.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                if depth > 0 {
+                    current.push(c);
+                }
+            }
+            (None, '(') => {
+                depth += 1;
+                current.push(c);
+            }
+            (None, ')') => {
+                depth = depth.saturating_sub(1);
+                current.push(c);
+            }
+            (None, c) if c.is_whitespace() && depth == 0 => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            (None, c) => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+enum Scan {
+    Code,
+    SingleQuoted,
+    /// An `E'...'` string, where backslash escapes the next character
+    EscapeQuoted,
+    DoubleQuoted,
+    LineComment,
+    BlockComment(usize),
+    DollarQuoted {
+        start: usize,
+        len: usize,
+    },
+}
+
+/// Every psql meta-command in `sql`, in order. Backslashes in strings,
+/// quoted identifiers, comments and dollar quoted bodies are not
+/// meta-commands.
+pub fn find_meta_commands(sql: &str) -> Vec<MetaCommand> {
+    let bytes = sql.as_bytes();
+    let mut commands = Vec::new();
+    let mut scan = Scan::Code;
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        match scan {
+            Scan::Code => match b {
+                b'\'' => {
+                    let escape = i > 0
+                        && matches!(bytes[i - 1], b'E' | b'e')
+                        && (i < 2 || !is_identifier_byte(bytes[i - 2]));
+                    scan = if escape {
+                        Scan::EscapeQuoted
+                    } else {
+                        Scan::SingleQuoted
+                    };
+                }
+                b'"' => scan = Scan::DoubleQuoted,
+                b'-' if next == Some(b'-') => {
+                    scan = Scan::LineComment;
+                    i += 1;
+                }
+                b'/' if next == Some(b'*') => {
+                    scan = Scan::BlockComment(1);
+                    i += 1;
+                }
+                b'$' if i > 0 && is_identifier_byte(bytes[i - 1]) => {}
+                b'$' => match dollar_tag_len(&bytes[i..]).unwrap_or(0) {
+                    0 => {}
+                    len => {
+                        scan = Scan::DollarQuoted { start: i, len };
+                        i += len - 1;
+                    }
+                },
+                b'\\' => {
+                    i = read_meta_commands(sql, i, line, &mut commands);
+                    continue;
+                }
+                _ => {}
+            },
+            Scan::SingleQuoted if b == b'\'' => scan = Scan::Code,
+            Scan::EscapeQuoted if b == b'\\' => {
+                if next == Some(b'\n') {
+                    line += 1;
+                }
+                i += 1;
+            }
+            Scan::EscapeQuoted if b == b'\'' => scan = Scan::Code,
+            Scan::DoubleQuoted if b == b'"' => scan = Scan::Code,
+            Scan::LineComment if b == b'\n' => scan = Scan::Code,
+            Scan::BlockComment(depth) if b == b'*' && next == Some(b'/') => {
+                scan = if depth == 1 {
+                    Scan::Code
+                } else {
+                    Scan::BlockComment(depth - 1)
+                };
+                i += 1;
+            }
+            Scan::BlockComment(depth) if b == b'/' && next == Some(b'*') => {
+                scan = Scan::BlockComment(depth + 1);
+                i += 1;
+            }
+            Scan::DollarQuoted { start, len }
+                if b == b'$' && bytes[i..].starts_with(&bytes[start..start + len]) =>
+            {
+                scan = Scan::Code;
+                i += len - 1;
+            }
+            _ => {}
+        }
+        if b == b'\n' {
+            line += 1;
+        }
+        i += 1;
+    }
+    commands
+}
+
+/// Reads the meta-command starting with the backslash at `i`, and any that
+/// follow it on the same line, returning where the SQL resumes. As in psql,
+/// arguments end at the end of the line or an unquoted backslash, which
+/// starts another meta-command unless it is `\\`. `\copy` takes the rest of
+/// the line.
+fn read_meta_commands(
+    sql: &str,
+    mut i: usize,
+    line: usize,
+    commands: &mut Vec<MetaCommand>,
+) -> usize {
+    let bytes = sql.as_bytes();
+    loop {
+        i += 1;
+        if bytes.get(i) == Some(&b'\\') {
+            return i + 1;
+        }
+        let name_start = i;
+        if bytes.get(i) == Some(&b'!') {
+            i += 1;
+        } else {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || b"_+?".contains(&bytes[i]))
+            {
+                i += 1;
+            }
+        }
+        let name = &sql[name_start..i];
+
+        let args_start = i;
+        let mut quoted = false;
+        while i < bytes.len() && bytes[i] != b'\n' {
+            match bytes[i] {
+                _ if name == "copy" => {}
+                b'\'' => quoted = !quoted,
+                b'\\' if quoted && bytes.get(i + 1).is_some_and(|&b| b != b'\n') => i += 1,
+                b'\\' => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        if !name.is_empty() {
+            commands.push(MetaCommand {
+                name: name.to_string(),
+                args: String::from_utf8_lossy(&bytes[args_start..i])
+                    .trim()
+                    .to_string(),
+                line,
+            });
+        }
+        if i >= bytes.len() || bytes[i] == b'\n' {
+            return i;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(sql: &str) -> Vec<(String, usize)> {
+        find_meta_commands(sql)
+            .into_iter()
+            .map(|c| (c.name, c.line))
+            .collect()
+    }
+
+    #[test]
+    fn test_finds_meta_commands() {
+        let sql = "BEGIN;\n\\set ON_ERROR_STOP on\nSELECT 1; \\! rm -rf /tmp/x\n\\echo a \\setenv X 1\nCOMMIT;\n";
+        assert_eq!(
+            vec![
+                ("set".to_string(), 2),
+                ("!".to_string(), 3),
+                ("echo".to_string(), 4),
+                ("setenv".to_string(), 4),
+            ],
+            names(sql)
+        );
+    }
+
+    #[test]
+    fn test_ignores_backslashes_in_strings_and_comments() {
+        let sql = "SELECT '\\! no', E'it\\'s \\! no', \"a\\b\";\n-- \\! no\n/* \\! /* no */ */\nCREATE FUNCTION f() RETURNS text AS $fn$ SELECT '\\!' $fn$ LANGUAGE sql;\n\\! yes\n";
+        assert_eq!(vec![("!".to_string(), 5)], names(sql));
+    }
+
+    #[test]
+    fn test_policy_violations() {
+        let policy = MetaCommandPolicy {
+            forbid: vec!["\\!".to_string(), "setenv".to_string()],
+            copy_paths: Some(vec!["./seed/".to_string()]),
+        };
+        let sql = "\\copy users FROM 'seed/users.csv' CSV\n\
+                   \\copy users FROM stdin\n\
+                   \\copy (SELECT * FROM users) TO '/etc/passwd'\n\
+                   \\copy users FROM 'seed/../../secret'\n\
+                   \\copy users FROM PROGRAM 'curl example.com'\n\
+                   \\! ls\n";
+        let violations: Vec<usize> = policy.violations(sql).iter().map(|v| v.line).collect();
+        assert_eq!(vec![3, 4, 5, 6], violations);
+
+        assert!(MetaCommandPolicy::default().violations("\\! ls").is_empty());
+        let err = policy
+            .check("m1", "\\setenv A b\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 1: \\setenv is forbidden"), "{}", err);
+    }
+}
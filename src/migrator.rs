@@ -1,13 +1,94 @@
 use crate::config;
+use crate::engine::HistoryDetails;
+use crate::status;
 use crate::template;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 
 static BASE_MIGRATION: &str = "BEGIN;
 
 COMMIT;
 ";
 
+/// Name of the file next to a migration's `up.sql` describing it.
+pub const METADATA_FILE_NAME: &str = "migration.toml";
+
+/// Contents of a migration's `migration.toml`, recorded in the migration
+/// history when it is applied.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Ticket or issue the migration is for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ticket: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Acknowledges that the migration drops or deletes data, so that it may
+    /// be applied to protected targets without `--allow-destructive`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub destructive: bool,
+}
+
+impl MigrationMetadata {
+    /// The file as written for a new migration. Fields without a value are
+    /// left as comments, to be filled in later.
+    pub fn to_file_contents(&self) -> Result<String> {
+        let mut contents = toml::to_string(self)?;
+        for (field, value) in [
+            ("author", &self.author),
+            ("ticket", &self.ticket),
+            ("description", &self.description),
+        ] {
+            if value.is_none() {
+                contents.push_str(&format!("# {} = \"\"\n", field));
+            }
+        }
+        Ok(contents)
+    }
+
+    /// What is recorded in the migration history: who applied the
+    /// migration, and the description prefixed with the ticket.
+    pub fn history_details(
+        &self,
+        pin_hash: Option<String>,
+        applied_by: Option<String>,
+    ) -> HistoryDetails {
+        let description = match (&self.ticket, &self.description) {
+            (Some(ticket), Some(description)) => Some(format!("{}: {}", ticket, description)),
+            (ticket, description) => ticket.clone().or(description.clone()),
+        };
+        HistoryDetails {
+            pin_hash,
+            created_by: applied_by,
+            description,
+        }
+    }
+}
+
+/// A value of git's config, such as `user.email`, or None when it is not
+/// set or git cannot be run.
+pub fn git_config(key: &str) -> Option<String> {
+    std::process::Command::new("git")
+        .args(["config", key])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// The author for new migrations, from git's `user.name` and `user.email`,
+/// or None when git is not configured.
+pub fn git_author() -> Option<String> {
+    match (git_config("user.name"), git_config("user.email")) {
+        (Some(name), Some(email)) => Some(format!("{} <{}>", name, email)),
+        (name, email) => name.or(email),
+    }
+}
+
 /// Final SQL output generator
 #[derive(Debug)]
 pub struct Migrator {
@@ -29,21 +110,53 @@ impl Migrator {
 
     /// Creates the migration folder with blank setup.
     pub async fn create_migration(&self) -> Result<String> {
-        // TODO: return error if migration already exists.
-        let path = self.config.pather().migration_folder(&self.name);
+        self.create_migration_with(BASE_MIGRATION).await
+    }
 
-        let script_path = format!("{}/up.sql", &path);
-        println!("creating migration at {}", &script_path);
+    /// Creates the migration folder with `script` as its `up.sql`.
+    pub async fn create_migration_with(&self, script: &str) -> Result<String> {
+        // TODO: return error if migration already exists.
+        let script_path = self.config.pather().migration_script_file_path(&self.name);
+        status!("creating migration at {}", &script_path);
         self.config
             .operator()
-            .write(&script_path, BASE_MIGRATION)
+            .write(&script_path, script.to_string())
             .await?;
 
         Ok(self.name.to_string())
     }
 
+    /// Writes the migration's `migration.toml`.
+    pub async fn write_metadata(&self, metadata: &MigrationMetadata) -> Result<()> {
+        let path = self
+            .config
+            .pather()
+            .migration_metadata_file_path(&self.name);
+        self.config
+            .operator()
+            .write(&path, metadata.to_file_contents()?)
+            .await?;
+        Ok(())
+    }
+
+    /// Reads the migration's `migration.toml`. Migrations without one have
+    /// no metadata.
+    pub async fn load_metadata(&self) -> Result<MigrationMetadata> {
+        let path = self
+            .config
+            .pather()
+            .migration_metadata_file_path(&self.name);
+        if !self.config.operator().exists(&path).await? {
+            return Ok(MigrationMetadata::default());
+        }
+        let contents = self.config.operator().read(&path).await?.to_vec();
+        toml::from_str(std::str::from_utf8(&contents)?).context(format!("could not parse {}", path))
+    }
+
     /// Opens the specified script file and returns a streaming generation that can
     /// render directly to a writer without materializing the entire SQL in memory.
+    /// A `variables.toml` and `variables.<environment>.toml` in the migration
+    /// folder are merged below `variables`.
     pub async fn generate_streaming(
         &self,
         variables: Option<crate::variables::Variables>,
@@ -58,3 +171,56 @@ impl Migrator {
         template::generate_streaming(&self.config, lock_file, script_path, variables).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_file_contents() {
+        let metadata = MigrationMetadata {
+            author: Some("Jo Bloggs <jo@example.com>".to_string()),
+            ticket: None,
+            description: Some("Add an email column".to_string()),
+            ..Default::default()
+        };
+        let contents = metadata.to_file_contents().unwrap();
+        assert_eq!(
+            "author = \"Jo Bloggs <jo@example.com>\"\ndescription = \"Add an email column\"\n# ticket = \"\"\n",
+            contents
+        );
+        // The commented out fields are read back as missing.
+        assert_eq!(metadata, toml::from_str(&contents).unwrap());
+    }
+
+    #[test]
+    fn test_history_details() {
+        let metadata = MigrationMetadata {
+            author: Some("jo".to_string()),
+            ticket: Some("DB-12".to_string()),
+            description: Some("Add an email column".to_string()),
+            ..Default::default()
+        };
+        let details =
+            metadata.history_details(Some("abc".to_string()), Some("deploy-bot".to_string()));
+        assert_eq!(Some("deploy-bot".to_string()), details.created_by);
+        assert_eq!(
+            Some("DB-12: Add an email column".to_string()),
+            details.description
+        );
+        assert_eq!(Some("abc".to_string()), details.pin_hash);
+
+        let ticket_only = MigrationMetadata {
+            ticket: Some("DB-12".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some("DB-12".to_string()),
+            ticket_only.history_details(None, None).description
+        );
+        assert_eq!(
+            HistoryDetails::default(),
+            MigrationMetadata::default().history_details(None, None)
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Naming policy for migrations, so that a project can require names such as
+//! `JIRA-123-add-users`. Checked when a migration is created and by `check`.
+
+use crate::errors::{ErrorKind, WithErrorKind};
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Length of the `YYYYMMDDHHMMSS-` prefix of migration folder names.
+const TIMESTAMP_PREFIX_LEN: usize = 15;
+
+/// Naming policy for migrations, in the `[naming]` section. Rules apply to
+/// the name given to `migration new`, without the timestamp.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NamingConfig {
+    /// Regex the whole name must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Regex the name must start with, such as a ticket `[A-Z]+-[0-9]+-`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Longest name allowed, in characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Shown with any violation, to explain the convention
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Migrations with an earlier timestamp (`YYYYMMDDHHMMSS`) are not
+    /// checked, so a policy can be introduced in an existing project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exempt_before: Option<String>,
+}
+
+impl NamingConfig {
+    /// Ways in which `name` breaks the policy. An error if the policy's
+    /// regexes are invalid.
+    pub fn violations(&self, name: &str) -> Result<Vec<String>> {
+        let mut violations = Vec::new();
+
+        if let Some(prefix) = &self.prefix {
+            let regex = Regex::new(&format!("^(?:{})", prefix))
+                .context(format!("invalid naming.prefix '{}'", prefix))
+                .with_kind(ErrorKind::Config)?;
+            if !regex.is_match(name) {
+                violations.push(format!("must start with a match for '{}'", prefix));
+            }
+        }
+        if let Some(pattern) = &self.pattern {
+            let regex = Regex::new(&format!("^(?:{})$", pattern))
+                .context(format!("invalid naming.pattern '{}'", pattern))
+                .with_kind(ErrorKind::Config)?;
+            if !regex.is_match(name) {
+                violations.push(format!("must match '{}'", pattern));
+            }
+        }
+        if let Some(max_length) = self.max_length {
+            let length = name.chars().count();
+            if length > max_length {
+                violations.push(format!(
+                    "is {} characters long, longer than the {} allowed",
+                    length, max_length
+                ));
+            }
+        }
+
+        Ok(violations)
+    }
+
+    /// Checks a name given to `migration new`, with an error describing
+    /// every violation.
+    pub fn check(&self, name: &str) -> Result<()> {
+        let violations = self.violations(name)?;
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(self.describe(name, &violations)))
+    }
+
+    /// Checks an existing migration folder name, skipping the timestamp and
+    /// migrations from before `exempt_before`.
+    pub fn violations_for_migration(&self, migration: &str) -> Result<Vec<String>> {
+        let (timestamp, name) = split_timestamp(migration);
+        if let (Some(timestamp), Some(exempt_before)) = (timestamp, &self.exempt_before) {
+            if timestamp < exempt_before.as_str() {
+                return Ok(Vec::new());
+            }
+        }
+        self.violations(name)
+    }
+
+    /// A message listing the violations of `name`, followed by the hint.
+    pub fn describe(&self, name: &str, violations: &[String]) -> String {
+        let mut message = format!(
+            "migration name '{}' does not follow the naming policy: it {}",
+            name,
+            violations.join(", and ")
+        );
+        if let Some(hint) = &self.hint {
+            message.push_str(&format!(". {}", hint));
+        }
+        message
+    }
+}
+
+/// Splits `YYYYMMDDHHMMSS-name` into its timestamp and name. Names without a
+/// timestamp are returned whole.
+pub fn split_timestamp(migration: &str) -> (Option<&str>, &str) {
+    match migration.get(..TIMESTAMP_PREFIX_LEN) {
+        Some(prefix)
+            if prefix.ends_with('-')
+                && prefix[..TIMESTAMP_PREFIX_LEN - 1]
+                    .bytes()
+                    .all(|b| b.is_ascii_digit()) =>
+        {
+            (
+                Some(&prefix[..TIMESTAMP_PREFIX_LEN - 1]),
+                &migration[TIMESTAMP_PREFIX_LEN..],
+            )
+        }
+        _ => (None, migration),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jira() -> NamingConfig {
+        NamingConfig {
+            prefix: Some("[A-Z]+-[0-9]+-".to_string()),
+            pattern: Some("[A-Za-z0-9-]+".to_string()),
+            max_length: Some(30),
+            hint: Some("Use names like JIRA-123-add-users.".to_string()),
+            exempt_before: Some("20250101000000".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_violations() {
+        let policy = jira();
+        assert!(policy.violations("DB-12-add-users").unwrap().is_empty());
+
+        assert_eq!(
+            vec!["must start with a match for '[A-Z]+-[0-9]+-'"],
+            policy.violations("add-users").unwrap()
+        );
+        // The pattern must match the whole name, not just part of it.
+        assert_eq!(
+            vec!["must match '[A-Za-z0-9-]+'"],
+            policy.violations("DB-12-add_users").unwrap()
+        );
+        assert_eq!(
+            vec!["is 34 characters long, longer than the 30 allowed"],
+            policy
+                .violations("DB-12-add-users-and-their-accounts")
+                .unwrap()
+        );
+
+        assert!(NamingConfig::default()
+            .violations("anything goes")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_message_includes_hint() {
+        let err = jira().check("add_users").unwrap_err();
+        assert_eq!(
+            "migration name 'add_users' does not follow the naming policy: it must start with a \
+             match for '[A-Z]+-[0-9]+-', and must match '[A-Za-z0-9-]+'. Use names like JIRA-123-add-users.",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_invalid_regex_is_an_error() {
+        let policy = NamingConfig {
+            pattern: Some("[".to_string()),
+            ..Default::default()
+        };
+        assert!(policy.violations("x").is_err());
+    }
+
+    #[test]
+    fn test_existing_migrations_skip_timestamp_and_exempt() {
+        let policy = jira();
+        assert!(policy
+            .violations_for_migration("20250301120000-DB-12-add-users")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            1,
+            policy
+                .violations_for_migration("20250301120000-add-users")
+                .unwrap()
+                .len()
+        );
+        assert!(policy
+            .violations_for_migration("20240907212659-initial")
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+//! Webhook notifications of apply and adopt outcomes, configured with
+//! `[[notifications.webhook]]` sections. Each migration applied, adopted or
+//! failed is posted to every webhook that wants it. A notification that
+//! cannot be sent is reported as a warning and never fails the command.
+//! Turned off for one run with `--no-notify`.
+
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use minijinja::{Environment, Value};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// Settings for notifications, in the `[notifications]` section.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NotificationsConfig {
+    #[serde(default, rename = "webhook", skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+}
+
+/// A URL that notifications are posted to, in a `[[notifications.webhook]]`
+/// section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Webhook {
+    /// URL to post to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Environment variable holding the URL, for URLs that are secrets
+    /// such as Slack incoming webhooks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_env: Option<String>,
+    /// Minijinja template of the JSON body. Defaults to a body with a
+    /// Slack-compatible `text` and each field of the notification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Outcomes to post. All of them when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on: Vec<NotifyStatus>,
+    /// Environments to post for. All of them when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+    /// Seconds to wait for the webhook to answer
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Self {
+            url: None,
+            url_env: None,
+            template: None,
+            on: Vec::new(),
+            environments: Vec::new(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+}
+
+/// How a migration ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyStatus {
+    Applied,
+    Adopted,
+    Failed,
+}
+
+impl fmt::Display for NotifyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            NotifyStatus::Applied => "applied",
+            NotifyStatus::Adopted => "adopted",
+            NotifyStatus::Failed => "failed",
+        })
+    }
+}
+
+/// What happened to one migration, as posted to webhooks.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notification {
+    pub migration: String,
+    pub status: NotifyStatus,
+    pub target: Option<String>,
+    pub environment: Option<String>,
+    pub duration_secs: f64,
+    /// Why the migration failed
+    pub error: Option<String>,
+}
+
+impl Notification {
+    /// A notification about `migration` on the selected target of `config`.
+    pub fn new(config: &Config, migration: &str, status: NotifyStatus, duration: Duration) -> Self {
+        Notification {
+            migration: migration.to_string(),
+            status,
+            target: config.target.clone(),
+            environment: config.policy_environment(),
+            duration_secs: duration.as_secs_f64(),
+            error: None,
+        }
+    }
+
+    pub fn with_error(mut self, error: &anyhow::Error) -> Self {
+        self.error = Some(format!("{:#}", error));
+        self
+    }
+
+    /// One line describing the notification, such as `Migration
+    /// 20240501-add-users applied on prod (production) in 1.2s`.
+    pub fn text(&self) -> String {
+        let mut text = format!("Migration {} {}", self.migration, self.status);
+        match (&self.target, &self.environment) {
+            (Some(target), Some(environment)) if target != environment => {
+                text.push_str(&format!(" on {} ({})", target, environment))
+            }
+            (Some(target), _) => text.push_str(&format!(" on {}", target)),
+            (None, Some(environment)) => text.push_str(&format!(" on {}", environment)),
+            (None, None) => {}
+        }
+        text.push_str(&format!(" in {:.1}s", self.duration_secs));
+        if let Some(error) = &self.error {
+            text.push_str(&format!(": {}", error));
+        }
+        text
+    }
+}
+
+impl Webhook {
+    fn wants(&self, notification: &Notification) -> bool {
+        (self.on.is_empty() || self.on.contains(&notification.status))
+            && (self.environments.is_empty()
+                || notification
+                    .environment
+                    .as_ref()
+                    .is_some_and(|e| self.environments.contains(e)))
+    }
+
+    fn url(&self) -> Result<String> {
+        match (&self.url, &self.url_env) {
+            (Some(url), _) => Ok(url.clone()),
+            (None, Some(var)) => {
+                std::env::var(var).with_context(|| format!("webhook url_env {} is not set", var))
+            }
+            (None, None) => Err(anyhow!("webhook has no url or url_env")),
+        }
+    }
+
+    /// The JSON body to post for `notification`.
+    pub fn body(&self, notification: &Notification) -> Result<String> {
+        let Some(template) = &self.template else {
+            let mut body = serde_json::to_value(notification)?;
+            body["text"] = notification.text().into();
+            return Ok(body.to_string());
+        };
+        let mut env = Environment::new();
+        env.add_filter("tojson", |value: Value| {
+            serde_json::to_string(&value).map_err(|e| {
+                minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, e.to_string())
+            })
+        });
+        let mut context = serde_json::to_value(notification)?;
+        context["text"] = notification.text().into();
+        env.render_str(template, context)
+            .context("could not render webhook template")
+    }
+
+    /// The host the webhook posts to, to name it in warnings without the
+    /// rest of its URL, which often holds a token.
+    fn host(&self) -> Option<String> {
+        let url = reqwest::Url::parse(&self.url().ok()?).ok()?;
+        url.host_str().map(str::to_string)
+    }
+
+    /// Posts `notification`. Errors leave out the URL, so that tokens in it
+    /// are not printed.
+    async fn post(&self, notification: &Notification) -> Result<()> {
+        let url = self.url()?;
+        let body = self.body(notification)?;
+        let response = reqwest::Client::new()
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .timeout(Duration::from_secs(self.timeout_secs))
+            .send()
+            .await
+            .map_err(|e| e.without_url())?;
+        response.error_for_status().map_err(|e| e.without_url())?;
+        Ok(())
+    }
+}
+
+/// Posts `notification` to each webhook that wants it, warning about any
+/// that fail.
+pub async fn notify(config: &Config, notification: Notification) {
+    for (i, webhook) in config.notifications.webhooks.iter().enumerate() {
+        if !webhook.wants(&notification) {
+            continue;
+        }
+        if let Err(e) = webhook.post(&notification).await {
+            let name = match webhook.host() {
+                Some(host) => format!("webhook {} ({})", i + 1, host),
+                None => format!("webhook {}", i + 1),
+            };
+            config.events.on_warning(&format!(
+                "could not send the notification for migration {} to {}: {:#}",
+                notification.migration, name, e
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification {
+            migration: "20240501-add-users".to_string(),
+            status: NotifyStatus::Failed,
+            target: Some("prod".to_string()),
+            environment: Some("production".to_string()),
+            duration_secs: 1.25,
+            error: Some("relation \"users\" already exists".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_webhook_body() {
+        let default: serde_json::Value =
+            serde_json::from_str(&Webhook::default().body(&notification()).unwrap()).unwrap();
+        assert_eq!(
+            "Migration 20240501-add-users failed on prod (production) in 1.2s: relation \"users\" already exists",
+            default["text"]
+        );
+        assert_eq!("failed", default["status"]);
+        assert_eq!(1.25, default["duration_secs"]);
+
+        let webhook = Webhook {
+            template: Some(
+                r#"{"content": {{ text|tojson }}, "env": {{ environment|tojson }}}"#.to_string(),
+            ),
+            ..Default::default()
+        };
+        let body: serde_json::Value =
+            serde_json::from_str(&webhook.body(&notification()).unwrap()).unwrap();
+        assert_eq!("production", body["env"]);
+        assert_eq!(default["text"], body["content"]);
+    }
+
+    #[test]
+    fn test_webhook_wants() {
+        let webhook = Webhook {
+            on: vec![NotifyStatus::Failed],
+            environments: vec!["production".to_string()],
+            ..Default::default()
+        };
+        assert!(webhook.wants(&notification()));
+        assert!(!webhook.wants(&Notification {
+            status: NotifyStatus::Applied,
+            ..notification()
+        }));
+        assert!(!webhook.wants(&Notification {
+            environment: Some("staging".to_string()),
+            ..notification()
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_post_errors_leave_out_url() {
+        let webhook = Webhook {
+            url: Some("http://127.0.0.1:1/hooks/T000/s3cr3t".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Some("127.0.0.1".to_string()), webhook.host());
+        let error = webhook.post(&notification()).await.unwrap_err();
+        assert!(!format!("{:#}", error).contains("s3cr3t"));
+    }
+}
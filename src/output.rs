@@ -0,0 +1,84 @@
+//! Where command output goes, so that stdout can be piped.
+//!
+//! A command's primary output (rendered SQL, tables, JSON, test results) is
+//! printed to stdout with `println!`. Everything else, such as progress and
+//! confirmations, goes to stderr through [`status!`](crate::status), and is
+//! left out with the global `--quiet` flag. Warnings and errors are always
+//! printed to stderr.
+//!
+//! Colors are written with [`console::style`], which follows the choice made
+//! here from `--color` and `NO_COLOR`, so output piped to a file or another
+//! program stays plain.
+
+use clap::ValueEnum;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set from `--quiet` before the command runs.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// When to color output, from the global `--color` flag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color output written to a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color output, or None to leave it to whether stdout and
+    /// stderr are terminals (and `CLICOLOR`). `no_color` is the value of the
+    /// `NO_COLOR` environment variable, which disables color when set to
+    /// anything but an empty string.
+    fn enabled(self, no_color: Option<&str>) -> Option<bool> {
+        match self {
+            ColorChoice::Always => Some(true),
+            ColorChoice::Never => Some(false),
+            ColorChoice::Auto if no_color.is_some_and(|v| !v.is_empty()) => Some(false),
+            ColorChoice::Auto => None,
+        }
+    }
+}
+
+/// Set from `--color` before the command runs.
+pub fn set_color(choice: ColorChoice) {
+    let no_color = std::env::var("NO_COLOR").ok();
+    if let Some(enabled) = choice.enabled(no_color.as_deref()) {
+        console::set_colors_enabled(enabled);
+        console::set_colors_enabled_stderr(enabled);
+    }
+}
+
+/// Prints a status message to stderr, unless `--quiet` was given. Takes the
+/// same arguments as `eprintln!`.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_choice() {
+        assert_eq!(None, ColorChoice::Auto.enabled(None));
+        assert_eq!(None, ColorChoice::Auto.enabled(Some("")));
+        assert_eq!(Some(false), ColorChoice::Auto.enabled(Some("1")));
+        assert_eq!(Some(true), ColorChoice::Always.enabled(Some("1")));
+        assert_eq!(Some(false), ColorChoice::Never.enabled(None));
+    }
+}
@@ -0,0 +1,360 @@
+//! External component packages, for sharing SQL components between
+//! repositories. Each package is declared in spawn.toml:
+//!
+//! ```toml
+//! [packages.audit]
+//! git = "https://github.com/example/sql-audit.git"
+//! rev = "v1.4.0"
+//! folder = "components"
+//!
+//! [packages.shared]
+//! path = "../shared-sql"
+//! ```
+//!
+//! `spawn package update` copies each package into `vendor/<name>/` and
+//! records what it fetched in `packages.lock`, so that builds never reach
+//! out to the package's source. Includes read packages under the `pkg/`
+//! prefix, such as `{% include "pkg/audit/triggers.sql" %}`.
+
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use futures::TryStreamExt;
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Prefix under which includes read vendored packages.
+pub const PACKAGE_PREFIX: &str = "pkg/";
+
+/// Lock file recording the fetched version of each package, in the spawn
+/// folder.
+pub const PACKAGES_LOCK_FILE: &str = "packages.lock";
+
+/// Where a package comes from, in the `[packages.<name>]` section. Exactly
+/// one of `git` and `path` is given.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PackageConfig {
+    /// URL of a git repository, fetched with the `git` command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<String>,
+    /// Branch, tag or commit of `git` to fetch. Defaults to the remote's HEAD
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// Local folder holding the package. Like `spawn_folder`, relative to
+    /// the folder of spawn.toml
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Folder within the source holding the components, if not its root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// Expected content hash. Updating fails if the fetched package differs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl PackageConfig {
+    /// Describes the source, such as `git https://... (main)`.
+    pub fn source(&self) -> Result<String> {
+        match (&self.git, &self.path) {
+            (Some(git), None) => Ok(match &self.rev {
+                Some(rev) => format!("git {} ({})", git, rev),
+                None => format!("git {}", git),
+            }),
+            (None, Some(path)) => Ok(format!("path {}", path)),
+            _ => Err(anyhow!("packages need exactly one of `git` or `path`")),
+        }
+    }
+}
+
+/// The `packages.lock` file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PackagesLock {
+    #[serde(default)]
+    pub packages: BTreeMap<String, LockedPackage>,
+}
+
+/// What `spawn package update` fetched for one package.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LockedPackage {
+    /// Source of the package, as described by [`PackageConfig::source`]
+    pub source: String,
+    /// Commit fetched, for git packages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Content hash of the vendored files
+    pub hash: String,
+}
+
+impl PackagesLock {
+    /// Reads the project's lock file, or an empty one if it has none.
+    pub async fn load(config: &Config) -> Result<Self> {
+        match config
+            .operator()
+            .read(&config.pather().packages_lock_path())
+            .await
+        {
+            Ok(contents) => {
+                let contents = String::from_utf8(contents.to_bytes().to_vec())?;
+                toml::from_str(&contents).context(format!("invalid {}", PACKAGES_LOCK_FILE))
+            }
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self, config: &Config) -> Result<()> {
+        config
+            .operator()
+            .write(
+                &config.pather().packages_lock_path(),
+                toml::to_string_pretty(self)?,
+            )
+            .await
+            .context(format!("could not write {}", PACKAGES_LOCK_FILE))?;
+        Ok(())
+    }
+}
+
+/// Checks that `name` can be used as a package name, and so as a folder of
+/// `vendor/`.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        return Err(anyhow!(
+            "invalid package name '{}': use only letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// SHA-256 of a set of files, from each file's path and SHA-256, as
+/// `sha256sum` would list them. Listing order does not matter.
+fn files_hash(file_hashes: &BTreeMap<String, String>) -> String {
+    let listing: String = file_hashes
+        .iter()
+        .map(|(name, hash)| format!("{}  {}\n", hash, name))
+        .collect();
+    content_hash(listing.as_bytes())
+}
+
+fn content_hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// Content hash of the files currently vendored for the package `name`, or
+/// None when nothing is.
+pub async fn vendored_hash(config: &Config, name: &str) -> Result<Option<String>> {
+    let folder = format!("{}/{}", config.pather().vendor_folder(), name);
+    let files =
+        crate::store::pinner::live_file_hashes_with(config.operator(), &folder, &[], content_hash)
+            .await?;
+    if files.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(files_hash(&files)))
+}
+
+/// Every file beneath `folder` of `op`, keyed by path relative to it.
+/// Anything in a `.git` folder is skipped.
+async fn read_files(op: &Operator, folder: &str) -> Result<BTreeMap<String, Vec<u8>>> {
+    // opendal strips leading "./" and "/" from listed paths.
+    let normalized = folder
+        .trim_start_matches("./")
+        .trim_start_matches('/')
+        .trim_end_matches('/');
+    let prefix = match normalized {
+        "" | "." => String::new(),
+        folder => format!("{}/", folder),
+    };
+    let list_path = if prefix.is_empty() { "/" } else { &prefix };
+
+    let mut lister = op.lister_with(list_path).recursive(true).await?;
+    let mut files = BTreeMap::new();
+    while let Some(entry) = lister.try_next().await? {
+        let Some(name) = entry.path().strip_prefix(&prefix) else {
+            continue;
+        };
+        if name.is_empty()
+            || name.ends_with('/')
+            || name.split('/').any(|segment| segment == ".git")
+        {
+            continue;
+        }
+        let contents = op.read(entry.path()).await?.to_vec();
+        files.insert(name.to_string(), contents);
+    }
+    Ok(files)
+}
+
+/// Fetches `rev` of the repository at `url` into `dir`, returning the commit
+/// fetched.
+fn git_checkout(url: &str, rev: Option<&str>, dir: &Path) -> Result<String> {
+    let git = |args: &[&str]| -> Result<String> {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .context("could not run git")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git {} failed: {}",
+                args[0],
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    std::fs::create_dir_all(dir)?;
+    git(&["init", "--quiet"])?;
+    git(&[
+        "fetch",
+        "--quiet",
+        "--depth",
+        "1",
+        url,
+        rev.unwrap_or("HEAD"),
+    ])?;
+    git(&["checkout", "--quiet", "FETCH_HEAD"])?;
+    git(&["rev-parse", "HEAD"])
+}
+
+/// Reads the files of a package from its source, with the commit fetched
+/// for git packages.
+async fn fetch_files(
+    config: &Config,
+    name: &str,
+    package: &PackageConfig,
+) -> Result<(BTreeMap<String, Vec<u8>>, Option<String>)> {
+    let folder = package.folder.as_deref().unwrap_or("");
+    match (&package.git, &package.path) {
+        (Some(url), None) => {
+            let dir =
+                std::env::temp_dir().join(format!("spawn-package-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            let fetched = match git_checkout(url, package.rev.as_deref(), &dir) {
+                Ok(commit) => {
+                    let op = Operator::new(
+                        opendal::services::Fs::default().root(&dir.to_string_lossy()),
+                    )?
+                    .finish();
+                    read_files(&op, folder)
+                        .await
+                        .map(|files| (files, Some(commit)))
+                }
+                Err(e) => Err(e),
+            };
+            let _ = std::fs::remove_dir_all(&dir);
+            fetched
+        }
+        (None, Some(path)) if path.starts_with('/') => {
+            let op = Operator::new(opendal::services::Fs::default().root(path))?.finish();
+            Ok((read_files(&op, folder).await?, None))
+        }
+        (None, Some(path)) => {
+            let folder = format!("{}/{}", path.trim_end_matches('/'), folder);
+            Ok((read_files(config.operator(), &folder).await?, None))
+        }
+        _ => Err(anyhow!("packages need exactly one of `git` or `path`")),
+    }
+}
+
+/// Fetches the package `name` from its source and replaces its folder in
+/// `vendor/` with it. Returns what was fetched, for the lock file. Nothing
+/// is written if the package is empty or does not match its `hash`.
+pub async fn update_package(
+    config: &Config,
+    name: &str,
+    package: &PackageConfig,
+) -> Result<LockedPackage> {
+    validate_name(name)?;
+    let source = package.source()?;
+    let (files, commit) = fetch_files(config, name, package)
+        .await
+        .context(format!("could not fetch package '{}'", name))?;
+    if files.is_empty() {
+        return Err(anyhow!("package '{}' has no files ({})", name, source));
+    }
+
+    let file_hashes: BTreeMap<String, String> = files
+        .iter()
+        .map(|(file, contents)| (file.clone(), content_hash(contents)))
+        .collect();
+    let hash = files_hash(&file_hashes);
+    if let Some(expected) = &package.hash {
+        if *expected != hash {
+            return Err(anyhow!(
+                "package '{}' has hash {}, but spawn.toml expects {}",
+                name,
+                hash,
+                expected
+            ));
+        }
+    }
+
+    let op = config.operator();
+    let folder = format!("{}/{}", config.pather().vendor_folder(), name);
+    op.remove_all(&format!("{}/", folder)).await?;
+    for (file, contents) in files {
+        op.write(&format!("{}/{}", folder, file), contents).await?;
+    }
+
+    Ok(LockedPackage {
+        source,
+        commit,
+        hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("audit-log_2").is_ok());
+        for name in ["", "a/b", "..", "pkg name"] {
+            assert!(validate_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_files_hash_is_sha256() {
+        let files = BTreeMap::from([("triggers.sql".to_string(), content_hash(b"select 1;\n"))]);
+        assert_eq!(
+            "4a45092ccf992ea92250053a80b931b787924ba61648f420555511b84f10ab6c",
+            files["triggers.sql"]
+        );
+        // The SHA-256 of `sha256sum triggers.sql`'s output.
+        assert_eq!(
+            "a18f356c6381c46cd42088a6d9c45a0b69c79d85579d09326835c3bdbdd783e7",
+            files_hash(&files)
+        );
+    }
+
+    #[test]
+    fn test_source_needs_git_or_path() {
+        let git = PackageConfig {
+            git: Some("https://example.com/sql.git".to_string()),
+            rev: Some("v1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "git https://example.com/sql.git (v1)",
+            git.source().unwrap()
+        );
+        assert!(PackageConfig::default().source().is_err());
+        let both = PackageConfig {
+            path: Some("../sql".to_string()),
+            ..git
+        };
+        assert!(both.source().is_err());
+    }
+}
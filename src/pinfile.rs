@@ -4,6 +4,16 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct LockData {
     // Reference to the pinned files.  Might be an xxhash for spawn's pinning
-    // system, or a specific git root object hash, etc.
+    // system, or a specific git root object hash, etc.  May be left out when
+    // `tag` is given, in which case it is the root the tag refers to.
+    #[serde(default)]
     pub pin: String,
+    // Tag of the pinned store that the components were pinned from, as
+    // created by `spawn pin tag`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    // Reference to the pinned files kept in the migration's own folder, if
+    // it has any besides up.sql.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
 }
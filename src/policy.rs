@@ -0,0 +1,503 @@
+//! Policies on the statements of rendered migrations.
+//!
+//! Migrations applied to protected targets are guarded against destructive
+//! statements: one that drops tables or columns, truncates a table or
+//! deletes every row must be acknowledged with `destructive = true` in its
+//! migration.toml, or applied with `--allow-destructive`.
+//!
+//! Projects can add their own rules in `[[policy.rule]]` sections, such as
+//! requiring `CREATE INDEX CONCURRENTLY`. Rules either warn or block, and may
+//! be limited to some environments. Both are checked by `migration apply`
+//! and `check`, and rules also by `migration build`.
+
+use crate::config::Config;
+use crate::errors::{ErrorKind, WithErrorKind};
+use crate::events::{dollar_tag_len, is_identifier_byte, StatementWriter};
+use anyhow::{anyhow, Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Statements that destroy data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DestructiveKind {
+    DropTable,
+    DropColumn,
+    Truncate,
+    DeleteWithoutWhere,
+}
+
+impl fmt::Display for DestructiveKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DestructiveKind::DropTable => "DROP TABLE",
+            DestructiveKind::DropColumn => "DROP COLUMN",
+            DestructiveKind::Truncate => "TRUNCATE",
+            DestructiveKind::DeleteWithoutWhere => "DELETE without WHERE",
+        })
+    }
+}
+
+/// A destructive statement found in a migration.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DestructiveStatement {
+    pub kind: DestructiveKind,
+    /// The first line of the statement
+    pub statement: String,
+}
+
+impl fmt::Display for DestructiveStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.statement)
+    }
+}
+
+/// `statement` without its comments, with the contents of string literals
+/// and dollar quoted bodies removed and runs of whitespace collapsed to a
+/// single space. Quoted identifiers are kept.
+pub(crate) fn code(statement: &str) -> String {
+    let bytes = statement.as_bytes();
+    let mut code = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        match b {
+            b'-' if next == Some(b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                code.push(b' ');
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 1;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+                code.push(b' ');
+            }
+            b'\'' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'\'' {
+                    i += 1;
+                }
+                code.extend_from_slice(b"''");
+            }
+            b'"' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += 1;
+                }
+                code.extend_from_slice(&bytes[start..(i + 1).min(bytes.len())]);
+            }
+            b'$' => match dollar_tag_len(&bytes[i..]).unwrap_or(0) {
+                0 => code.push(b),
+                len => {
+                    let tag = &bytes[i..i + len];
+                    code.extend_from_slice(tag);
+                    code.extend_from_slice(tag);
+                    i += len;
+                    while i < bytes.len() && !bytes[i..].starts_with(tag) {
+                        i += 1;
+                    }
+                    i += len - 1;
+                }
+            },
+            b if b.is_ascii_whitespace() => code.push(b' '),
+            b => code.push(b),
+        }
+        i += 1;
+    }
+    let code = String::from_utf8_lossy(&code);
+    code.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Words of the [`code`] of `statement`, in upper case. Quoted identifiers
+/// are kept with their quotes, and `(`, `)` and `,` are words of their own.
+fn words(statement: &str) -> Vec<String> {
+    let code = code(statement);
+    let bytes = code.as_bytes();
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match b {
+            b'\'' | b'"' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b {
+                    i += 1;
+                }
+                words.push(String::from_utf8_lossy(&bytes[start..(i + 1).min(bytes.len())]).into());
+            }
+            b'(' | b')' | b',' => words.push((b as char).to_string()),
+            b if is_identifier_byte(b) => {
+                let start = i;
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                words.push(String::from_utf8_lossy(&bytes[start..i]).to_uppercase());
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    words
+}
+
+/// What kind of destructive statement `statement` is, if it is one.
+fn destructive_kind(statement: &str) -> Option<DestructiveKind> {
+    let words = words(statement);
+    let word = |i: usize| words.get(i).map(String::as_str);
+    match (word(0), word(1)) {
+        (Some("DROP"), Some("TABLE")) => Some(DestructiveKind::DropTable),
+        (Some("TRUNCATE"), _) => Some(DestructiveKind::Truncate),
+        (Some("DELETE"), _) if !words.iter().any(|w| w == "WHERE") => {
+            Some(DestructiveKind::DeleteWithoutWhere)
+        }
+        (Some("ALTER"), Some("TABLE")) => {
+            // `DROP` alone drops a column, as in `ALTER TABLE t DROP c`, but
+            // not when it drops something else.
+            let drops_column = words.iter().enumerate().any(|(i, w)| {
+                w == "DROP"
+                    && !matches!(
+                        word(i + 1),
+                        Some("CONSTRAINT" | "DEFAULT" | "NOT" | "IDENTITY" | "EXPRESSION") | None
+                    )
+            });
+            drops_column.then_some(DestructiveKind::DropColumn)
+        }
+        _ => None,
+    }
+}
+
+/// Each statement of `sql`, in order.
+pub(crate) fn statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut sink = std::io::sink();
+    let mut writer = StatementWriter::new(&mut sink, |statement| {
+        statements.push(statement.to_string());
+    });
+    // Writing to a sink cannot fail.
+    let _ = std::io::Write::write_all(&mut writer, sql.as_bytes());
+    let _ = writer.finish();
+    statements
+}
+
+/// The first line of `statement` that is not blank or a comment.
+pub(crate) fn first_line(statement: &str) -> &str {
+    statement
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("--"))
+        .unwrap_or(statement)
+}
+
+/// Every destructive statement in `sql`, in order.
+pub fn destructive_statements(sql: &str) -> Vec<DestructiveStatement> {
+    statements(sql)
+        .iter()
+        .filter_map(|statement| {
+            Some(DestructiveStatement {
+                kind: destructive_kind(statement)?,
+                statement: first_line(statement).to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Checks the rendered SQL of `migration` for destructive statements, with
+/// an error listing them and how to acknowledge them.
+pub fn check_destructive(migration: &str, target: &str, sql: &str) -> Result<()> {
+    let found = destructive_statements(sql);
+    if found.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<String> = found.iter().map(|s| format!("  {}", s)).collect();
+    Err(anyhow!(
+        "migration {} has destructive statements and target '{}' is protected:\n{}\n\
+         Set `destructive = true` in its migration.toml to acknowledge them, or apply with --allow-destructive.",
+        migration,
+        target,
+        lines.join("\n")
+    ))
+}
+
+/// Custom rules on the statements of migrations, in `[[policy.rule]]`
+/// sections.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PolicyConfig {
+    #[serde(default, rename = "rule", skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<PolicyRule>,
+}
+
+/// What happens when a statement breaks a rule.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleSeverity {
+    /// Print a warning and carry on
+    #[default]
+    Warn,
+    /// Refuse to apply or build the migration
+    Block,
+}
+
+impl fmt::Display for RuleSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RuleSeverity::Warn => "warn",
+            RuleSeverity::Block => "block",
+        })
+    }
+}
+
+/// A rule that statements must follow, such as creating indexes
+/// concurrently. A statement breaks the rule when `match` finds it and
+/// `unless` does not.
+///
+/// Both are case-insensitive regexes, matched against each statement with
+/// comments removed, string literals and dollar quoted bodies emptied, and
+/// whitespace collapsed to single spaces, so `CREATE INDEX` matches however
+/// the statement is laid out.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyRule {
+    pub name: String,
+    #[serde(rename = "match")]
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unless: Option<String>,
+    /// Shown when a statement breaks the rule, to explain what to do instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub severity: RuleSeverity,
+    /// Environments the rule applies in. Empty applies it everywhere.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+}
+
+impl PolicyRule {
+    fn regex(&self, key: &str, pattern: &str) -> Result<Regex> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .context(format!(
+                "invalid policy rule '{}' {}: '{}'",
+                self.name, key, pattern
+            ))
+            .with_kind(ErrorKind::Config)
+    }
+
+    /// Whether the rule applies in `environment`. Rules limited to some
+    /// environments do not apply when there is none.
+    pub fn applies_in(&self, environment: Option<&str>) -> bool {
+        self.environments.is_empty()
+            || environment.is_some_and(|e| self.environments.iter().any(|r| r == e))
+    }
+}
+
+/// A statement that breaks a policy rule.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RuleViolation {
+    pub rule: String,
+    pub severity: RuleSeverity,
+    pub message: Option<String>,
+    /// The first line of the statement
+    pub statement: String,
+}
+
+impl fmt::Display for RuleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.rule, self.statement)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl PolicyConfig {
+    /// Every statement of `sql` that breaks a rule applying in
+    /// `environment`, in order. An error if a rule's regexes are invalid.
+    pub fn violations(&self, environment: Option<&str>, sql: &str) -> Result<Vec<RuleViolation>> {
+        let mut rules = Vec::new();
+        for rule in &self.rules {
+            if rule.applies_in(environment) {
+                let unless = match &rule.unless {
+                    Some(unless) => Some(rule.regex("unless", unless)?),
+                    None => None,
+                };
+                rules.push((rule, rule.regex("match", &rule.pattern)?, unless));
+            }
+        }
+        if rules.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut violations = Vec::new();
+        for statement in statements(sql) {
+            let code = code(&statement);
+            for (rule, pattern, unless) in &rules {
+                if pattern.is_match(&code) && !unless.as_ref().is_some_and(|u| u.is_match(&code)) {
+                    violations.push(RuleViolation {
+                        rule: rule.name.clone(),
+                        severity: rule.severity,
+                        message: rule.message.clone(),
+                        statement: first_line(&statement).to_string(),
+                    });
+                }
+            }
+        }
+        Ok(violations)
+    }
+}
+
+/// Checks the rendered SQL of `migration` against the policy rules of the
+/// selected environment. Rules that warn are reported to `config.events`,
+/// and an error lists the statements breaking rules that block.
+pub fn enforce_rules(config: &Config, migration: &str, sql: &str) -> Result<()> {
+    let violations = config
+        .policy
+        .violations(config.policy_environment().as_deref(), sql)?;
+    let mut blocked = Vec::new();
+    for violation in violations {
+        match violation.severity {
+            RuleSeverity::Warn => config.events.on_warning(&format!(
+                "migration {} breaks policy rule {}",
+                migration, violation
+            )),
+            RuleSeverity::Block => blocked.push(format!("  {}", violation)),
+        }
+    }
+    if blocked.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "migration {} breaks policy rules:\n{}",
+        migration,
+        blocked.join("\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(sql: &str) -> Vec<DestructiveKind> {
+        destructive_statements(sql)
+            .into_iter()
+            .map(|s| s.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_finds_destructive_statements() {
+        let sql = "BEGIN;
+-- DROP TABLE in a comment;
+DROP TABLE IF EXISTS old_users;
+ALTER TABLE users DROP COLUMN email;
+ALTER TABLE users DROP nickname, ADD COLUMN x int;
+truncate orders;
+DELETE FROM sessions;
+COMMIT;
+";
+        assert_eq!(
+            vec![
+                DestructiveKind::DropTable,
+                DestructiveKind::DropColumn,
+                DestructiveKind::DropColumn,
+                DestructiveKind::Truncate,
+                DestructiveKind::DeleteWithoutWhere,
+            ],
+            kinds(sql)
+        );
+        assert_eq!(
+            "DROP TABLE IF EXISTS old_users;",
+            destructive_statements(sql)[0].statement
+        );
+    }
+
+    #[test]
+    fn test_ignores_safe_statements() {
+        let sql = "DELETE FROM sessions WHERE expires < now();
+ALTER TABLE users ALTER COLUMN email DROP NOT NULL;
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+ALTER TABLE users ALTER COLUMN id DROP DEFAULT;
+DROP INDEX users_email_idx;
+INSERT INTO log VALUES ('DROP TABLE users;');
+CREATE FUNCTION f() RETURNS void AS $$ DELETE FROM t; $$ LANGUAGE sql;
+";
+        assert_eq!(Vec::<DestructiveKind>::new(), kinds(sql));
+    }
+
+    #[test]
+    fn test_policy_rules() {
+        let policy = PolicyConfig {
+            rules: vec![
+                PolicyRule {
+                    name: "concurrent-index".to_string(),
+                    pattern: r"^create (unique )?index".to_string(),
+                    unless: Some(r"\bconcurrently\b".to_string()),
+                    severity: RuleSeverity::Block,
+                    ..Default::default()
+                },
+                PolicyRule {
+                    name: "not-valid".to_string(),
+                    pattern: r"add constraint .* (foreign key|check)".to_string(),
+                    unless: Some(r"\bnot valid\b".to_string()),
+                    environments: vec!["prod".to_string()],
+                    ..Default::default()
+                },
+            ],
+        };
+        let sql = "-- create index in a comment
+CREATE INDEX
+    users_email_idx ON users (email);
+CREATE UNIQUE INDEX CONCURRENTLY users_name_idx ON users (name);
+INSERT INTO log VALUES ('create index x on y');
+ALTER TABLE orders ADD CONSTRAINT orders_user_fk FOREIGN KEY (user_id) REFERENCES users;
+ALTER TABLE orders ADD CONSTRAINT orders_total_check CHECK (total > 0) NOT VALID;
+";
+        let rules = |environment| -> Vec<String> {
+            policy
+                .violations(environment, sql)
+                .unwrap()
+                .into_iter()
+                .map(|v| format!("{} {}", v.severity, v.rule))
+                .collect()
+        };
+        assert_eq!(vec!["block concurrent-index"], rules(None));
+        assert_eq!(
+            vec!["block concurrent-index", "warn not-valid"],
+            rules(Some("prod"))
+        );
+        assert_eq!(
+            "CREATE INDEX",
+            policy.violations(None, sql).unwrap()[0].statement
+        );
+
+        let invalid = PolicyConfig {
+            rules: vec![PolicyRule {
+                name: "broken".to_string(),
+                pattern: "(".to_string(),
+                ..Default::default()
+            }],
+        };
+        assert!(invalid.violations(None, sql).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Migration templates kept in the project's `templates/` folder, used by
+//! `spawn migration new --template` to start a migration from a common
+//! pattern rather than an empty transaction.
+//!
+//! A template is copied into the new migration's `up.sql` with its
+//! placeholders, written `[[ name ]]`, replaced by the values given. Anything
+//! else, including `{{ ... }}` expressions, is copied as is and rendered when
+//! the migration is built.
+
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+
+const OPEN: &str = "[[";
+const CLOSE: &str = "]]";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a template into text and placeholders. Brackets that do not hold a
+/// plain name, such as `ARRAY[[1, 2]]`, are left as text.
+fn segments(template: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    let mut text_start = 0;
+    let mut offset = 0;
+
+    while let Some(open) = rest.find(OPEN) {
+        let after_open = &rest[open + OPEN.len()..];
+        let Some(close) = after_open.find(CLOSE) else {
+            break;
+        };
+        let name = after_open[..close].trim();
+        let end = open + OPEN.len() + close + CLOSE.len();
+        if is_name(name) {
+            let start = offset + open;
+            if start > text_start {
+                segments.push(Segment::Text(&template[text_start..start]));
+            }
+            segments.push(Segment::Placeholder(name));
+            text_start = offset + end;
+            offset += end;
+            rest = &rest[end..];
+        } else {
+            // Try again from the second bracket, so that `[[[ name ]]` still
+            // finds its placeholder.
+            offset += open + 1;
+            rest = &rest[open + 1..];
+        }
+    }
+
+    if text_start < template.len() {
+        segments.push(Segment::Text(&template[text_start..]));
+    }
+    segments
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Names of the placeholders in `template`, in order of first use.
+pub fn placeholders(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for segment in segments(template) {
+        if let Segment::Placeholder(name) = segment {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Replaces each placeholder in `template` with its value. Every placeholder
+/// must have a value.
+pub fn fill(template: &str, values: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    for segment in segments(template) {
+        match segment {
+            Segment::Text(text) => out.push_str(text),
+            Segment::Placeholder(name) => out.push_str(
+                values
+                    .get(name)
+                    .ok_or(anyhow!("no value for placeholder '{}'", name))?,
+            ),
+        }
+    }
+    Ok(out)
+}
+
+/// Parses a `NAME=VALUE` pair given with `--set`.
+pub fn parse_assignment(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or(format!("expected NAME=VALUE, got '{}'", s))?;
+    let name = name.trim();
+    if !is_name(name) {
+        return Err(format!("'{}' is not a valid placeholder name", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Names of the templates in the project's `templates/` folder.
+pub async fn list_templates(config: &Config) -> Result<Vec<String>> {
+    let folder = format!("{}/", config.pather().templates_folder());
+    let mut names: Vec<String> = match config.operator().list(&folder).await {
+        Ok(entries) => entries
+            .iter()
+            .filter_map(|e| e.name().strip_suffix(".sql").map(str::to_string))
+            .collect(),
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    names.sort();
+    Ok(names)
+}
+
+/// Reads the template `name` from the project's `templates/` folder.
+pub async fn load_template(config: &Config, name: &str) -> Result<String> {
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        return Err(anyhow!("invalid template name '{}'", name));
+    }
+
+    let path = config.pather().template_file_path(name);
+    if !config.operator().exists(&path).await? {
+        let available = list_templates(config).await?;
+        return Err(if available.is_empty() {
+            anyhow!(
+                "template '{}' not found: there are no templates in {}",
+                name,
+                config.pather().templates_folder()
+            )
+        } else {
+            anyhow!(
+                "template '{}' not found, available templates: {}",
+                name,
+                available.join(", ")
+            )
+        });
+    }
+
+    let contents = config
+        .operator()
+        .read(&path)
+        .await
+        .context(format!("could not read template {}", path))?
+        .to_vec();
+    String::from_utf8(contents).context(format!("template {} is not valid UTF-8", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders_in_order_of_first_use() {
+        let template = "CREATE TABLE [[ table ]] (\n    [[column]] [[ type ]]\n);\n\
+                        CREATE INDEX ON [[ table ]] ([[column]]);\n";
+        assert_eq!(vec!["table", "column", "type"], placeholders(template));
+    }
+
+    #[test]
+    fn test_fill_leaves_other_brackets_and_jinja_alone() {
+        let template = "SELECT ARRAY[[1, 2], [3, 4]] FROM {{ variables.schema }}.[[table]];\n\
+                        SELECT x[[[ index ]]];\n";
+        let values = HashMap::from([
+            ("table".to_string(), "users".to_string()),
+            ("index".to_string(), "1".to_string()),
+        ]);
+        assert_eq!(
+            "SELECT ARRAY[[1, 2], [3, 4]] FROM {{ variables.schema }}.users;\nSELECT x[1];\n",
+            fill(template, &values).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fill_requires_every_value() {
+        let err = fill("ALTER TABLE [[table]] ADD [[column]];", &HashMap::new()).unwrap_err();
+        assert_eq!("no value for placeholder 'table'", err.to_string());
+    }
+
+    #[test]
+    fn test_parse_assignment() {
+        assert_eq!(
+            Ok(("table".to_string(), "a=b".to_string())),
+            parse_assignment("table=a=b")
+        );
+        assert!(parse_assignment("table").is_err());
+        assert!(parse_assignment("1st=x").is_err());
+    }
+}
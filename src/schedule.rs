@@ -0,0 +1,264 @@
+//! Scheduling for `migration apply`: wait for a given time (`--at`) and
+//! for a maintenance window (`--window`, or an environment's
+//! `maintenance_window`) before applying anything.
+
+use crate::config::Config;
+use crate::status;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A daily window of UTC time, such as `02:00-04:00`. A window whose end is
+/// before its start, such as `22:00-02:00`, runs past midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MaintenanceWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl MaintenanceWindow {
+    /// Whether `time` is inside the window. The end is not.
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        let time = time.time();
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// `time` if it is inside the window, or else when the window next
+    /// opens.
+    pub fn next_open(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        if self.contains(time) {
+            return time;
+        }
+        let today = time.date_naive().and_time(self.start).and_utc();
+        if today > time {
+            today
+        } else {
+            today + Duration::days(1)
+        }
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "invalid maintenance window \"{}\", expected UTC times as HH:MM-HH:MM, such as 02:00-04:00",
+                s
+            )
+        };
+        let (start, end) = s.trim().split_once('-').ok_or_else(invalid)?;
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| invalid());
+        let window = MaintenanceWindow {
+            start: time(start)?,
+            end: time(end)?,
+        };
+        if window.start == window.end {
+            return Err(anyhow!(
+                "maintenance window \"{}\" starts and ends at the same time",
+                s
+            ));
+        }
+        Ok(window)
+    }
+}
+
+impl TryFrom<String> for MaintenanceWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<MaintenanceWindow> for String {
+    fn from(window: MaintenanceWindow) -> String {
+        window.to_string()
+    }
+}
+
+impl fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Parses a time given to `--at`, such as `2024-05-01T02:00Z` or
+/// `2024-05-01T04:00:00+02:00`. The time zone is required.
+pub fn parse_at(s: &str) -> Result<DateTime<Utc>> {
+    let s = s.trim();
+    let zoned = match s.strip_suffix(['Z', 'z']) {
+        Some(rest) => format!("{}+00:00", rest),
+        None => s.to_string(),
+    };
+    ["%Y-%m-%dT%H:%M:%S%:z", "%Y-%m-%dT%H:%M%:z"]
+        .iter()
+        .find_map(|format| DateTime::parse_from_str(&zoned, format).ok())
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| {
+            anyhow!(
+                "invalid time \"{}\", expected a date and time with a time zone, such as 2024-05-01T02:00Z",
+                s
+            )
+        })
+}
+
+/// Parses a timeout given to `--wait-for-lock`, such as `90s`, `5m` or
+/// `1h`. A number without a unit is seconds.
+pub fn parse_wait(s: &str) -> Result<std::time::Duration> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if seconds > 0 => Ok(std::time::Duration::from_secs(number * seconds)),
+        _ => Err(anyhow!(
+            "invalid timeout \"{}\", expected a number of seconds, minutes or hours, such as 90s, 5m or 1h",
+            s
+        )),
+    }
+}
+
+/// When a `migration apply` may start.
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    /// Do not start before this time
+    pub at: Option<DateTime<Utc>>,
+    /// Only start inside this window, instead of the environment's
+    /// `maintenance_window`
+    pub window: Option<MaintenanceWindow>,
+    /// Fail instead of waiting when it is too early to start
+    pub no_wait: bool,
+}
+
+impl Schedule {
+    /// The window to start in: the one given, or else the selected target's
+    /// environment's.
+    pub fn window(&self, config: &Config) -> Option<MaintenanceWindow> {
+        self.window.or_else(|| {
+            let environment = config.policy_environment()?;
+            config.environments.get(&environment)?.maintenance_window
+        })
+    }
+
+    /// `now`, or the time given with `at` if that is later.
+    fn earliest(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        self.at.map_or(now, |at| at.max(now))
+    }
+
+    /// The earliest time from `now` that an apply may start.
+    pub fn start(&self, config: &Config, now: DateTime<Utc>) -> DateTime<Utc> {
+        let earliest = self.earliest(now);
+        match self.window(config) {
+            Some(window) => window.next_open(earliest),
+            None => earliest,
+        }
+    }
+
+    /// Waits until an apply may start, or with `no_wait`, fails if it may
+    /// not start now.
+    pub async fn wait(&self, config: &Config) -> Result<()> {
+        let now = Utc::now();
+        let start = self.start(config, now);
+        if start <= now {
+            return Ok(());
+        }
+        let reason = match self.window(config) {
+            Some(window) if !window.contains(self.earliest(now)) => {
+                format!("outside the maintenance window {} UTC", window)
+            }
+            _ => "before the time given with --at".to_string(),
+        };
+        if self.no_wait {
+            return Err(anyhow!(
+                "not applying: it is {}. The earliest start is {}.",
+                reason,
+                start.to_rfc3339()
+            ));
+        }
+        status!(
+            "It is {}. Waiting until {} to apply...",
+            reason,
+            start.to_rfc3339()
+        );
+        let wait = (start - now).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        parse_at(s).unwrap()
+    }
+
+    #[test]
+    fn test_maintenance_window() {
+        let window: MaintenanceWindow = "02:00-04:00".parse().unwrap();
+        assert!(window.contains(time("2024-05-01T02:00Z")));
+        assert!(!window.contains(time("2024-05-01T04:00Z")));
+        assert_eq!(
+            time("2024-05-01T03:30Z"),
+            window.next_open(time("2024-05-01T03:30Z"))
+        );
+        assert_eq!(
+            time("2024-05-01T02:00Z"),
+            window.next_open(time("2024-05-01T01:00Z"))
+        );
+        assert_eq!(
+            time("2024-05-02T02:00Z"),
+            window.next_open(time("2024-05-01T05:00Z"))
+        );
+
+        let overnight: MaintenanceWindow = "22:00-02:00".parse().unwrap();
+        assert!(overnight.contains(time("2024-05-01T23:00Z")));
+        assert!(overnight.contains(time("2024-05-01T01:00Z")));
+        assert_eq!(
+            time("2024-05-01T22:00Z"),
+            overnight.next_open(time("2024-05-01T12:00Z"))
+        );
+        assert_eq!("22:00-02:00", overnight.to_string());
+
+        assert!("2am-4am".parse::<MaintenanceWindow>().is_err());
+        assert!("02:00-02:00".parse::<MaintenanceWindow>().is_err());
+    }
+
+    #[test]
+    fn test_parse_at() {
+        assert_eq!(time("2024-05-01T02:00:00Z"), time("2024-05-01T04:00+02:00"));
+        assert!(parse_at("2024-05-01T02:00").is_err());
+        assert!(parse_at("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_parse_wait() {
+        use std::time::Duration;
+        assert_eq!(Duration::from_secs(90), parse_wait("90").unwrap());
+        assert_eq!(Duration::from_secs(90), parse_wait("90s").unwrap());
+        assert_eq!(Duration::from_secs(300), parse_wait("5m").unwrap());
+        assert_eq!(Duration::from_secs(7200), parse_wait("2h").unwrap());
+        assert!(parse_wait("5 minutes").is_err());
+        assert!(parse_wait("m").is_err());
+        assert!(parse_wait("").is_err());
+    }
+}
@@ -0,0 +1,430 @@
+//! Documentation of the target database's tables, written by `spawn schema
+//! docs` and, with `[schema_docs] after_apply`, after each apply. The
+//! Markdown holds a Mermaid ER diagram followed by a section per table with
+//! its columns and foreign keys, read from the Postgres catalogs.
+
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::escape::{EscapedLiteral, InsecureRawSql};
+use crate::sql_query;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// File written when `output` is not set, relative to the spawn folder.
+const DEFAULT_OUTPUT: &str = "schema.md";
+
+/// Settings for `spawn schema docs`, in the `[schema_docs]` section.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SchemaDocsConfig {
+    /// Write the docs after every apply that applied a migration
+    #[serde(default)]
+    pub after_apply: bool,
+    /// File to write, relative to the spawn folder. Defaults to
+    /// `schema.md`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Schemas to document. Defaults to `public`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schemas: Vec<String>,
+}
+
+impl SchemaDocsConfig {
+    pub fn schemas(&self) -> Vec<String> {
+        if self.schemas.is_empty() {
+            vec!["public".to_string()]
+        } else {
+            self.schemas.clone()
+        }
+    }
+
+    /// Path of the docs in the spawn folder's store.
+    pub fn output_path(&self, config: &Config) -> String {
+        format!(
+            "{}/{}",
+            config.pather().spawn_folder_path(),
+            self.output.as_deref().unwrap_or(DEFAULT_OUTPUT)
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    /// The type as Postgres prints it, such as `character varying(255)`
+    pub data_type: String,
+    pub nullable: bool,
+    pub default: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForeignKey {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub references_schema: String,
+    pub references_table: String,
+    pub references_columns: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Table {
+    pub schema: String,
+    pub name: String,
+    pub comment: Option<String>,
+    pub columns: Vec<Column>,
+    pub primary_key: Vec<String>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+impl Table {
+    fn qualified_name(&self) -> String {
+        format!("{}.{}", self.schema, self.name)
+    }
+}
+
+/// `schemas` as a list of SQL literals, for `IN (...)`.
+fn schema_list(schemas: &[String]) -> InsecureRawSql {
+    let literals: Vec<String> = schemas
+        .iter()
+        .map(|s| EscapedLiteral::new(s).to_string())
+        .collect();
+    // Built only from escaped literals.
+    InsecureRawSql::new(&literals.join(", "))
+}
+
+/// A JSON array of names, as returned by `json_agg`.
+fn names(value: Option<&str>) -> Result<Vec<String>> {
+    match value {
+        Some(value) => serde_json::from_str(value).context("could not read the column names"),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Reads the tables of `schemas`, ordered by schema and name, with their
+/// columns in order.
+pub async fn introspect(engine: &dyn Engine, schemas: &[String]) -> Result<Vec<Table>> {
+    let columns = engine
+        .query(&sql_query!(
+            "SELECT n.nspname AS table_schema, c.relname AS table_name,
+       obj_description(c.oid, 'pg_class') AS table_comment,
+       a.attname AS column_name, format_type(a.atttypid, a.atttypmod) AS data_type,
+       a.attnotnull AS not_null, pg_get_expr(d.adbin, d.adrelid) AS column_default,
+       col_description(c.oid, a.attnum) AS column_comment
+FROM pg_attribute a
+JOIN pg_class c ON c.oid = a.attrelid
+JOIN pg_namespace n ON n.oid = c.relnamespace
+LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+WHERE c.relkind IN ('r', 'p') AND NOT c.relispartition
+  AND a.attnum > 0 AND NOT a.attisdropped AND n.nspname IN ({})
+ORDER BY n.nspname, c.relname, a.attnum;",
+            schema_list(schemas)
+        ))
+        .await
+        .context("could not read the tables")?;
+
+    let mut tables: Vec<Table> = Vec::new();
+    for row in columns.iter() {
+        let schema = row.require("table_schema")?;
+        let name = row.require("table_name")?;
+        if !tables
+            .last()
+            .is_some_and(|t| t.schema == schema && t.name == name)
+        {
+            tables.push(Table {
+                schema: schema.to_string(),
+                name: name.to_string(),
+                comment: row.get("table_comment").map(str::to_string),
+                columns: Vec::new(),
+                primary_key: Vec::new(),
+                foreign_keys: Vec::new(),
+            });
+        }
+        if let Some(table) = tables.last_mut() {
+            table.columns.push(Column {
+                name: row.require("column_name")?.to_string(),
+                data_type: row.require("data_type")?.to_string(),
+                nullable: row.get("not_null") != Some("t"),
+                default: row.get("column_default").map(str::to_string),
+                comment: row.get("column_comment").map(str::to_string),
+            });
+        }
+    }
+
+    let constraints = engine
+        .query(&sql_query!(
+            "SELECT n.nspname AS table_schema, c.relname AS table_name,
+       con.conname AS constraint_name, con.contype AS constraint_type,
+       (SELECT json_agg(a.attname ORDER BY k.ord)
+          FROM unnest(con.conkey) WITH ORDINALITY AS k(attnum, ord)
+          JOIN pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum) AS columns,
+       fn.nspname AS references_schema, fc.relname AS references_table,
+       (SELECT json_agg(a.attname ORDER BY k.ord)
+          FROM unnest(con.confkey) WITH ORDINALITY AS k(attnum, ord)
+          JOIN pg_attribute a ON a.attrelid = con.confrelid AND a.attnum = k.attnum) AS references_columns
+FROM pg_constraint con
+JOIN pg_class c ON c.oid = con.conrelid
+JOIN pg_namespace n ON n.oid = c.relnamespace
+LEFT JOIN pg_class fc ON fc.oid = con.confrelid
+LEFT JOIN pg_namespace fn ON fn.oid = fc.relnamespace
+WHERE con.contype IN ('p', 'f') AND n.nspname IN ({})
+ORDER BY n.nspname, c.relname, con.conname;",
+            schema_list(schemas)
+        ))
+        .await
+        .context("could not read the keys")?;
+
+    for row in constraints.iter() {
+        let schema = row.require("table_schema")?;
+        let name = row.require("table_name")?;
+        let Some(table) = tables
+            .iter_mut()
+            .find(|t| t.schema == schema && t.name == name)
+        else {
+            continue;
+        };
+        let columns = names(row.get("columns"))?;
+        match row.require("constraint_type")? {
+            "p" => table.primary_key = columns,
+            _ => table.foreign_keys.push(ForeignKey {
+                name: row.require("constraint_name")?.to_string(),
+                columns,
+                references_schema: row.require("references_schema")?.to_string(),
+                references_table: row.require("references_table")?.to_string(),
+                references_columns: names(row.get("references_columns"))?,
+            }),
+        }
+    }
+    Ok(tables)
+}
+
+/// Name of a table in the diagram. Mermaid names cannot hold a `.`, so
+/// tables outside `public` are named `<schema>__<table>`.
+fn entity(schema: &str, table: &str) -> String {
+    let name = if schema == "public" {
+        table.to_string()
+    } else {
+        format!("{}__{}", schema, table)
+    };
+    mermaid_word(&name)
+}
+
+/// `text` with the characters Mermaid does not allow in a name or type
+/// replaced by `_`.
+fn mermaid_word(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '(' | ')' | '[' | ']') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Escapes `text` for a Markdown table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// The docs of `tables` as Markdown, with a Mermaid ER diagram.
+pub fn render_markdown(target: &str, tables: &[Table]) -> String {
+    let mut out = String::new();
+    // Writing to a String cannot fail.
+    let _ = writeln!(out, "# Database schema of {}\n", target);
+    let _ = writeln!(
+        out,
+        "Generated by `spawn schema docs`. Changes made here are overwritten.\n"
+    );
+    if tables.is_empty() {
+        let _ = writeln!(out, "There are no tables.");
+        return out;
+    }
+
+    let _ = writeln!(out, "```mermaid\nerDiagram");
+    for table in tables {
+        let _ = writeln!(out, "    {} {{", entity(&table.schema, &table.name));
+        for column in &table.columns {
+            let mut keys = Vec::new();
+            if table.primary_key.contains(&column.name) {
+                keys.push("PK");
+            }
+            if table
+                .foreign_keys
+                .iter()
+                .any(|fk| fk.columns.contains(&column.name))
+            {
+                keys.push("FK");
+            }
+            let _ = writeln!(
+                out,
+                "        {} {}{}",
+                mermaid_word(&column.data_type),
+                mermaid_word(&column.name),
+                if keys.is_empty() {
+                    String::new()
+                } else {
+                    format!(" {}", keys.join(", "))
+                }
+            );
+        }
+        let _ = writeln!(out, "    }}");
+    }
+    for table in tables {
+        for fk in &table.foreign_keys {
+            let _ = writeln!(
+                out,
+                "    {} ||--o{{ {} : \"{}\"",
+                entity(&fk.references_schema, &fk.references_table),
+                entity(&table.schema, &table.name),
+                fk.columns.join(", ")
+            );
+        }
+    }
+    let _ = writeln!(out, "```");
+
+    for table in tables {
+        let _ = writeln!(out, "\n## {}\n", table.qualified_name());
+        if let Some(comment) = &table.comment {
+            let _ = writeln!(out, "{}\n", comment);
+        }
+        let _ = writeln!(out, "| Column | Type | Nullable | Default | Description |");
+        let _ = writeln!(out, "| ------ | ---- | -------- | ------- | ----------- |");
+        for column in &table.columns {
+            let name = if table.primary_key.contains(&column.name) {
+                format!("**{}**", column.name)
+            } else {
+                column.name.clone()
+            };
+            let _ = writeln!(
+                out,
+                "| {} | `{}` | {} | {} | {} |",
+                cell(&name),
+                cell(&column.data_type),
+                if column.nullable { "yes" } else { "no" },
+                column
+                    .default
+                    .as_deref()
+                    .map(|d| format!("`{}`", cell(d)))
+                    .unwrap_or_default(),
+                column.comment.as_deref().map(cell).unwrap_or_default()
+            );
+        }
+        if !table.primary_key.is_empty() {
+            let _ = writeln!(out, "\nPrimary key: {}", table.primary_key.join(", "));
+        }
+        if !table.foreign_keys.is_empty() {
+            let _ = writeln!(out, "\nForeign keys:\n");
+            for fk in &table.foreign_keys {
+                let _ = writeln!(
+                    out,
+                    "- `{}`: {} → {}.{} ({})",
+                    fk.name,
+                    fk.columns.join(", "),
+                    fk.references_schema,
+                    fk.references_table,
+                    fk.references_columns.join(", ")
+                );
+            }
+        }
+    }
+    out
+}
+
+/// Reads the target's tables and renders their docs.
+pub async fn schema_docs(config: &Config, schemas: &[String]) -> Result<String> {
+    let engine = config.new_engine().await?;
+    let tables = introspect(engine.as_ref(), schemas).await?;
+    Ok(render_markdown(
+        config.target.as_deref().unwrap_or("the target"),
+        &tables,
+    ))
+}
+
+/// Writes the docs to `path` in the spawn folder's store.
+pub async fn write_schema_docs(config: &Config, schemas: &[String], path: &str) -> Result<()> {
+    let docs = schema_docs(config, schemas).await?;
+    config
+        .operator()
+        .write(path, docs)
+        .await
+        .context(format!("could not write schema docs to {}", path))?;
+    Ok(())
+}
+
+/// With `[schema_docs] after_apply`, writes the docs once an apply has
+/// applied something. Failures are warnings, as the apply itself
+/// succeeded.
+pub async fn after_apply(config: &Config) {
+    if !config.schema_docs.after_apply {
+        return;
+    }
+    let path = config.schema_docs.output_path(config);
+    if let Err(e) = write_schema_docs(config, &config.schema_docs.schemas(), &path).await {
+        config
+            .events
+            .on_warning(&format!("could not update schema docs: {:#}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: &str, nullable: bool) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            nullable,
+            default: None,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let tables = vec![
+            Table {
+                schema: "public".to_string(),
+                name: "orders".to_string(),
+                comment: None,
+                columns: vec![
+                    column("id", "integer", false),
+                    column("user_id", "integer", true),
+                    column("total", "numeric(10,2)", true),
+                ],
+                primary_key: vec!["id".to_string()],
+                foreign_keys: vec![ForeignKey {
+                    name: "orders_user_id_fkey".to_string(),
+                    columns: vec!["user_id".to_string()],
+                    references_schema: "auth".to_string(),
+                    references_table: "users".to_string(),
+                    references_columns: vec!["id".to_string()],
+                }],
+            },
+            Table {
+                schema: "auth".to_string(),
+                name: "users".to_string(),
+                comment: Some("People who can sign in".to_string()),
+                columns: vec![Column {
+                    default: Some("'a|b'::text".to_string()),
+                    ..column("email", "character varying(255)", false)
+                }],
+                primary_key: Vec::new(),
+                foreign_keys: Vec::new(),
+            },
+        ];
+        let docs = render_markdown("prod", &tables);
+        assert!(docs.starts_with("# Database schema of prod\n"));
+        assert!(docs.contains("    orders {\n        integer id PK\n        integer user_id FK\n        numeric(10_2) total\n    }\n"));
+        assert!(docs.contains("        character_varying(255) email\n"));
+        assert!(docs.contains("    auth__users ||--o{ orders : \"user_id\"\n"));
+        assert!(docs.contains("| **id** | `integer` | no |  |  |\n"));
+        assert!(docs.contains("## auth.users\n\nPeople who can sign in\n"));
+        assert!(docs.contains("| email | `character varying(255)` | no | `'a\\|b'::text` |  |\n"));
+        assert!(docs.contains("- `orders_user_id_fkey`: user_id → auth.users (id)\n"));
+
+        assert!(render_markdown("prod", &[]).contains("There are no tables."));
+    }
+}
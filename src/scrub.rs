@@ -0,0 +1,323 @@
+//! Anonymization of copies of a database for testing, run by `spawn scrub`.
+//!
+//! Rules are kept in a TOML file in the spawn folder (`scrub.toml` unless
+//! `[scrub] rules` says otherwise), naming for each table how its columns
+//! are replaced:
+//!
+//! ```toml
+//! salt_env = "SCRUB_SALT"
+//!
+//! [[table]]
+//! name = "users"
+//! columns = { email = "faker.email", full_name = "faker.name", ssn = "null", api_token = "hash" }
+//!
+//! [[table]]
+//! name = "sessions"
+//! truncate = true
+//! ```
+//!
+//! The rules are rendered into SQL by [`SCRUB_TEMPLATE`] with the template
+//! engine, as one transaction. Fake values and hashes are derived from the
+//! original value and the salt, so equal values stay equal across tables
+//! and NULLs stay NULL.
+
+use crate::config::Config;
+use crate::template::{escape_identifier_filter, escape_literal_filter};
+use anyhow::{anyhow, Context, Result};
+use minijinja::{context, Environment};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Rules file read when `rules` is not set, relative to the spawn folder.
+const DEFAULT_RULES: &str = "scrub.toml";
+
+/// Settings for `spawn scrub`, in the `[scrub]` section.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScrubConfig {
+    /// Rules file, relative to the spawn folder. Defaults to `scrub.toml`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<String>,
+    /// Targets that may be scrubbed. Scrubbing rewrites data in place, so
+    /// every other target is refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+}
+
+impl ScrubConfig {
+    /// Path of the rules file `rules`, or of the configured one when None,
+    /// in the spawn folder's store.
+    pub fn rules_path(&self, config: &Config, rules: Option<&str>) -> String {
+        format!(
+            "{}/{}",
+            config.pather().spawn_folder_path(),
+            rules.or(self.rules.as_deref()).unwrap_or(DEFAULT_RULES)
+        )
+    }
+}
+
+/// Kinds of fake value, as `faker.<kind>`.
+pub const FAKER_KINDS: &[&str] = &[
+    "email",
+    "name",
+    "first_name",
+    "last_name",
+    "phone",
+    "address",
+    "company",
+    "text",
+    "uuid",
+    "ip",
+];
+
+/// How a column is anonymized.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ScrubMethod {
+    /// Set to NULL
+    Null,
+    /// Replaced by the salted MD5 of the value, as hex
+    Hash,
+    /// Replaced by a fake value of the kind, one of [`FAKER_KINDS`]
+    Faker(String),
+}
+
+impl TryFrom<String> for ScrubMethod {
+    type Error = anyhow::Error;
+
+    fn try_from(method: String) -> Result<Self> {
+        match method.as_str() {
+            "null" => Ok(ScrubMethod::Null),
+            "hash" => Ok(ScrubMethod::Hash),
+            _ => match method.strip_prefix("faker.") {
+                Some(kind) if FAKER_KINDS.contains(&kind) => {
+                    Ok(ScrubMethod::Faker(kind.to_string()))
+                }
+                Some(kind) => Err(anyhow!(
+                    "unknown faker '{}', expected one of {}",
+                    kind,
+                    FAKER_KINDS.join(", ")
+                )),
+                None => Err(anyhow!(
+                    "unknown method '{}', expected null, hash or faker.<kind>",
+                    method
+                )),
+            },
+        }
+    }
+}
+
+impl From<ScrubMethod> for String {
+    fn from(method: ScrubMethod) -> Self {
+        match method {
+            ScrubMethod::Null => "null".to_string(),
+            ScrubMethod::Hash => "hash".to_string(),
+            ScrubMethod::Faker(kind) => format!("faker.{}", kind),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrubTable {
+    pub name: String,
+    #[serde(default = "default_schema")]
+    pub schema: String,
+    /// Empty the table instead of rewriting columns
+    #[serde(default)]
+    pub truncate: bool,
+    /// Only rewrite the rows matching this SQL condition
+    #[serde(default, rename = "where", skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub columns: BTreeMap<String, ScrubMethod>,
+}
+
+fn default_schema() -> String {
+    "public".to_string()
+}
+
+/// A rules file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrubRules {
+    /// Environment variable holding a secret mixed into hashes and fake
+    /// values, so that they cannot be matched back to a guessed original
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt_env: Option<String>,
+    #[serde(default, rename = "table")]
+    pub tables: Vec<ScrubTable>,
+}
+
+impl ScrubRules {
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let rules: ScrubRules = toml::from_str(text)?;
+        for table in &rules.tables {
+            if table.truncate && (!table.columns.is_empty() || table.condition.is_some()) {
+                return Err(anyhow!(
+                    "table {}.{} is truncated, so cannot also have columns or where",
+                    table.schema,
+                    table.name
+                ));
+            }
+            if !table.truncate && table.columns.is_empty() {
+                return Err(anyhow!(
+                    "table {}.{} has no columns to scrub",
+                    table.schema,
+                    table.name
+                ));
+            }
+        }
+        Ok(rules)
+    }
+
+    /// The salt from `salt_env`, or an empty one without it.
+    fn salt(&self) -> Result<String> {
+        match &self.salt_env {
+            Some(var) => std::env::var(var).map_err(|_| anyhow!("{} is not set", var)),
+            None => Ok(String::new()),
+        }
+    }
+}
+
+/// Reads the rules file at `path` in the spawn folder's store.
+pub async fn load_rules(config: &Config, path: &str) -> Result<ScrubRules> {
+    let bytes = config
+        .operator()
+        .read(path)
+        .await
+        .context(format!("could not read scrub rules {}", path))?;
+    let text = String::from_utf8(bytes.to_vec()).context(format!("{} is not UTF-8", path))?;
+    ScrubRules::from_toml(&text).context(format!("{} is not valid", path))
+}
+
+/// Renders the SQL of the rules. Each expression is derived from `seed`,
+/// the salted MD5 of the original value, which is NULL for NULL values.
+pub const SCRUB_TEMPLATE: &str = r#"
+{%- macro seed(column) -%}
+md5({{ salt | escape_literal }} || {{ column | escape_identifier }}::text)
+{%- endmacro -%}
+{%- macro number(column, size, offset) -%}
+(('x' || substr({{ seed(column) }}, {{ offset }}, 7))::bit(28)::int % {{ size }})
+{%- endmacro -%}
+{%- macro pick(column, values, offset) -%}
+(ARRAY[{% for value in values %}{{ value | escape_literal }}{{ ", " if not loop.last }}{% endfor %}])[1 + {{ number(column, values | length, offset) }}]
+{%- endmacro -%}
+{%- set first_names = ["Alex", "Sam", "Jordan", "Taylor", "Morgan", "Casey", "Riley", "Jamie", "Avery", "Quinn"] -%}
+{%- set last_names = ["Smith", "Jones", "Brown", "Garcia", "Miller", "Davis", "Wilson", "Moore", "Clark", "Lewis"] -%}
+{%- set streets = ["Main Street", "High Street", "Park Avenue", "Oak Road", "Mill Lane", "Church Road"] -%}
+{%- set companies = ["Acme", "Globex", "Initech", "Umbrella", "Hooli", "Vandelay"] -%}
+{%- macro expression(column, method) -%}
+{%- if method == "null" -%}
+NULL
+{%- elif method == "hash" -%}
+{{ seed(column) }}
+{%- elif method == "faker.email" -%}
+'user-' || left({{ seed(column) }}, 12) || '@example.com'
+{%- elif method == "faker.first_name" -%}
+{{ pick(column, first_names, 1) }}
+{%- elif method == "faker.last_name" -%}
+{{ pick(column, last_names, 8) }}
+{%- elif method == "faker.name" -%}
+{{ pick(column, first_names, 1) }} || ' ' || {{ pick(column, last_names, 8) }}
+{%- elif method == "faker.phone" -%}
+'+1-555-01' || lpad({{ number(column, 100, 1) }}::text, 2, '0')
+{%- elif method == "faker.address" -%}
+(1 + {{ number(column, 9999, 1) }})::text || ' ' || {{ pick(column, streets, 8) }}
+{%- elif method == "faker.company" -%}
+{{ pick(column, companies, 1) }} || ' ' || left({{ seed(column) }}, 6)
+{%- elif method == "faker.text" -%}
+CASE WHEN {{ column | escape_identifier }} IS NULL THEN NULL ELSE 'Lorem ipsum dolor sit amet.' END
+{%- elif method == "faker.uuid" -%}
+{{ seed(column) }}::uuid
+{%- elif method == "faker.ip" -%}
+'192.0.2.' || (1 + {{ number(column, 254, 1) }})::text
+{%- endif -%}
+{%- endmacro -%}
+-- Scrubbed by spawn scrub
+BEGIN;
+{% for table in tables %}
+{%- set name = (table.schema | escape_identifier) ~ "." ~ (table.name | escape_identifier) %}
+{%- if table.truncate %}
+TRUNCATE {{ name }};
+{%- else %}
+UPDATE {{ name }} SET
+{%- for column, method in table.columns | items %}
+    {{ column | escape_identifier }} = {{ expression(column, method) }}{{ "," if not loop.last }}
+{%- endfor %}
+{%- if table.where %}
+WHERE {{ table.where }}
+{%- endif %};
+{%- endif %}
+{% endfor -%}
+COMMIT;
+"#;
+
+/// The SQL that applies `rules`, in one transaction.
+pub fn render_scrub(rules: &ScrubRules) -> Result<String> {
+    let mut env = Environment::new();
+    env.add_filter("escape_identifier", escape_identifier_filter);
+    env.add_filter("escape_literal", escape_literal_filter);
+    env.add_template("scrub.sql", SCRUB_TEMPLATE)?;
+    let sql = env
+        .get_template("scrub.sql")?
+        .render(context! {
+            salt => rules.salt()?,
+            tables => rules.tables,
+        })
+        .context("could not render the scrub rules")?;
+    Ok(format!("{}\n", sql.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_scrub() {
+        let rules = ScrubRules::from_toml(
+            r#"
+[[table]]
+name = "users"
+where = "email NOT LIKE '%@example.com'"
+columns = { email = "faker.email", "full name" = "faker.name", ssn = "null", token = "hash" }
+
+[[table]]
+schema = "audit"
+name = "sessions"
+truncate = true
+"#,
+        )
+        .unwrap();
+        let sql = render_scrub(&rules).unwrap();
+        assert!(
+            sql.starts_with("-- Scrubbed by spawn scrub\nBEGIN;\n"),
+            "{}",
+            sql
+        );
+        assert!(
+            sql.ends_with("TRUNCATE \"audit\".\"sessions\";\nCOMMIT;\n"),
+            "{}",
+            sql
+        );
+        assert!(sql.contains(
+            "UPDATE \"public\".\"users\" SET\n    \"email\" = 'user-' || left(md5('' || \"email\"::text), 12) || '@example.com',\n"
+        ), "{}", sql);
+        assert!(
+            sql.contains("    \"full name\" = (ARRAY['Alex', 'Sam', "),
+            "{}",
+            sql
+        );
+        assert!(sql.contains("    \"ssn\" = NULL,\n    \"token\" = md5('' || \"token\"::text)\nWHERE email NOT LIKE '%@example.com';\n"), "{}", sql);
+
+        let err = ScrubRules::from_toml(
+            "[[table]]\nname = \"users\"\ncolumns = { email = \"faker.mail\" }\n",
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("unknown faker 'mail'"),
+            "{:#}",
+            err
+        );
+        assert!(ScrubRules::from_toml("[[table]]\nname = \"users\"\n").is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// Every secret value resolved by this process, so that they can be kept out
+/// of what spawn writes to disk.
+static RESOLVED: LazyLock<Mutex<BTreeSet<String>>> = LazyLock::new(|| Mutex::new(BTreeSet::new()));
+
+/// Stands in for secret values in redacted text.
+pub const REDACTED: &str = "[secret]";
+
+/// Where values requested with the `secret("PATH")` template function come
+/// from.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SecretSource {
+    /// Read the environment variable named by the path, optionally prefixed
+    /// (e.g. prefix `APP_` turns `secret("DB_PASSWORD")` into `APP_DB_PASSWORD`).
+    Env {
+        #[serde(default)]
+        prefix: Option<String>,
+    },
+    /// Read the file at the path, relative to `directory`.
+    File { directory: String },
+    /// Run a command with the path appended as the final argument and use
+    /// its stdout, e.g. `["op", "read"]` for 1Password.
+    Command { command: Vec<String> },
+}
+
+impl Default for SecretSource {
+    fn default() -> Self {
+        SecretSource::Env { prefix: None }
+    }
+}
+
+/// Resolves secrets from a source, caching each value so that a template
+/// referring to the same secret several times only looks it up once. Clones
+/// share the cache.
+#[derive(Clone, Debug)]
+pub struct SecretResolver {
+    source: SecretSource,
+    cache: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl SecretResolver {
+    pub fn new(source: SecretSource) -> Self {
+        Self {
+            source,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn resolve(&self, path: &str) -> Result<String> {
+        if let Some(value) = self.cache.lock().unwrap().get(path) {
+            return Ok(value.clone());
+        }
+
+        let value = resolve_uncached(&self.source, path)
+            .context(format!("could not resolve secret '{}'", path))?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), value.clone());
+        if !value.is_empty() {
+            RESOLVED.lock().unwrap().insert(value.clone());
+        }
+
+        Ok(value)
+    }
+}
+
+/// Replaces each variable written as `{ secret = "PATH" }` in `value` with
+/// the secret at PATH, so that variables files can refer to secrets without
+/// holding them.
+pub fn resolve_secret_variables(
+    value: &mut serde_json::Value,
+    resolver: &SecretResolver,
+) -> Result<()> {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let (1, Some(serde_json::Value::String(path))) = (map.len(), map.get("secret")) {
+                *value = resolver.resolve(path)?.into();
+                return Ok(());
+            }
+            for item in map.values_mut() {
+                resolve_secret_variables(item, resolver)?;
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                resolve_secret_variables(item, resolver)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces every secret value resolved so far in `text` with [`REDACTED`],
+/// both as it is and as quoted in an SQL literal. Longer values go first, so
+/// that a secret containing another is redacted whole.
+pub fn redact(text: &str) -> String {
+    let resolved = RESOLVED.lock().unwrap();
+    let mut values: Vec<String> = resolved
+        .iter()
+        .flat_map(|value| [value.clone(), value.replace('\'', "''")])
+        .collect();
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+    values.iter().fold(text.to_string(), |text, value| {
+        text.replace(value, REDACTED)
+    })
+}
+
+fn resolve_uncached(source: &SecretSource, path: &str) -> Result<String> {
+    match source {
+        SecretSource::Env { prefix } => {
+            let name = format!("{}{}", prefix.as_deref().unwrap_or_default(), path);
+            std::env::var(&name).context(format!("environment variable '{}' not set", name))
+        }
+        SecretSource::File { directory } => {
+            check_secret_path(path)?;
+            let contents = std::fs::read_to_string(Path::new(directory).join(path))?;
+            Ok(trim_line_ending(contents))
+        }
+        SecretSource::Command { command } => {
+            if command.is_empty() {
+                return Err(anyhow!("secret command cannot be empty"));
+            }
+
+            let output = std::process::Command::new(&command[0])
+                .args(&command[1..])
+                .arg(path)
+                .output()
+                .context("failed to execute secret command")?;
+
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "secret command failed (exit {}): {}",
+                    output.status.code().unwrap_or(-1),
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+
+            let stdout =
+                String::from_utf8(output.stdout).context("secret command output is not UTF-8")?;
+            Ok(trim_line_ending(stdout))
+        }
+    }
+}
+
+/// Refuses paths that would read a file secret from outside its directory,
+/// as `check_component_path` does for components.
+fn check_secret_path(path: &str) -> Result<()> {
+    let escapes = path.is_empty()
+        || path.starts_with('/')
+        || path.starts_with('\\')
+        || Path::new(path).has_root()
+        || path.split(['/', '\\']).any(|segment| segment == "..");
+    if escapes {
+        return Err(anyhow!("'{}' is outside of the secrets directory", path));
+    }
+    Ok(())
+}
+
+/// Secret files and command output usually end with a newline that is not
+/// part of the value.
+fn trim_line_ending(mut s: String) -> String {
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_source_with_prefix() {
+        std::env::set_var("SPAWN_SECRET_TEST_DB_PASSWORD", "hunter2");
+        let resolver = SecretResolver::new(SecretSource::Env {
+            prefix: Some("SPAWN_SECRET_TEST_".to_string()),
+        });
+        assert_eq!(resolver.resolve("DB_PASSWORD").unwrap(), "hunter2");
+        assert!(resolver.resolve("MISSING").is_err());
+    }
+
+    #[test]
+    fn test_file_source_trims_trailing_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("db_password"), "s3cret\n").unwrap();
+        let resolver = SecretResolver::new(SecretSource::File {
+            directory: dir.path().to_string_lossy().into_owned(),
+        });
+        assert_eq!(resolver.resolve("db_password").unwrap(), "s3cret");
+    }
+
+    #[test]
+    fn test_file_source_stays_in_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("secrets")).unwrap();
+        std::fs::write(dir.path().join("outside"), "private").unwrap();
+        let resolver = SecretResolver::new(SecretSource::File {
+            directory: dir.path().join("secrets").to_string_lossy().into_owned(),
+        });
+        let outside = dir.path().join("outside").to_string_lossy().into_owned();
+        for path in ["../outside", "a/../../outside", "..\\outside", &outside, ""] {
+            let err = resolver.resolve(path).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("outside of the secrets directory"),
+                "{}: {:#}",
+                path,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolved_values_are_redacted() {
+        std::env::set_var("SPAWN_REDACT_TEST_PASSWORD", "o'hare-pw");
+        let resolver = SecretResolver::new(SecretSource::Env {
+            prefix: Some("SPAWN_REDACT_TEST_".to_string()),
+        });
+        resolver.resolve("PASSWORD").unwrap();
+        assert_eq!(
+            "ALTER ROLE app PASSWORD '[secret]'; -- [secret]",
+            redact("ALTER ROLE app PASSWORD 'o''hare-pw'; -- o'hare-pw")
+        );
+    }
+
+    #[test]
+    fn test_secret_variables() {
+        std::env::set_var("SPAWN_VARIABLE_TEST_TOKEN", "t0ken");
+        let resolver = SecretResolver::new(SecretSource::Env {
+            prefix: Some("SPAWN_VARIABLE_TEST_".to_string()),
+        });
+        let mut value = serde_json::json!({
+            "api": {"token": {"secret": "TOKEN"}, "host": "example.com"},
+            "keys": [{"secret": "TOKEN"}],
+            "other": {"secret": "TOKEN", "note": "not a secret reference"},
+        });
+        resolve_secret_variables(&mut value, &resolver).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "api": {"token": "t0ken", "host": "example.com"},
+                "keys": ["t0ken"],
+                "other": {"secret": "TOKEN", "note": "not a secret reference"},
+            }),
+            value
+        );
+
+        let mut missing = serde_json::json!({"password": {"secret": "MISSING"}});
+        assert!(resolve_secret_variables(&mut missing, &resolver).is_err());
+    }
+
+    #[test]
+    fn test_command_source_is_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("calls");
+        let script = format!("echo x >> {}; echo value-$0", counter.display());
+        let resolver = SecretResolver::new(SecretSource::Command {
+            command: vec!["sh".to_string(), "-c".to_string(), script],
+        });
+
+        assert_eq!(resolver.resolve("a").unwrap(), "value-a");
+        assert_eq!(resolver.resolve("a").unwrap(), "value-a");
+        assert_eq!(
+            std::fs::read_to_string(&counter).unwrap().lines().count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,528 @@
+//! A small HTTP API over the [`Spawn`] facade, started with `spawn serve`, so
+//! that internal platforms can check status, build, apply and run tests
+//! without shelling out to the CLI.
+//!
+//! Each connection is answered on its own task, with applies and test runs
+//! taking turns so that two of them never race on the same target. Every
+//! response is JSON, using the same structures as the CLI's `--json` output
+//! where it has one, and errors are `{"error": "..."}`.
+//!
+//! | Endpoint        | Body                                            | Returns                          |
+//! | --------------- | ----------------------------------------------- | -------------------------------- |
+//! | `GET /health`   |                                                 | `{"status": "ok"}`               |
+//! | `GET /status`   |                                                 | `migration status --json`        |
+//! | `GET /history`  |                                                 | `migration status --history --json` |
+//! | `POST /build`   | `{"migration", "pinned", "variables"}`          | `{"migration", "sql"}`           |
+//! | `POST /apply`   | `{"migration", "pinned", "retry", "variables"}` | `{"migrations": [...], "error"}` |
+//! | `POST /test`    | `{"name"}`                                      | `[{"name", "passed", ...}]`      |
+//!
+//! When [`SERVE_TOKEN_ENV`] is set, every request but `/health` needs an
+//! `Authorization: Bearer <token>` header. Without it, only `/health`,
+//! `/status` and `/history` are served, and only on a loopback address:
+//! `/build` renders secrets, and the others change the database.
+
+use crate::commands::migration::{HistoryRecord, MigrationStatusRow};
+use crate::interrupt;
+use crate::spawn::{ApplyOptions, Spawn};
+use crate::variables::Variables;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+/// Environment variable holding the token clients must send.
+pub const SERVE_TOKEN_ENV: &str = "SPAWN_SERVE_TOKEN";
+
+/// Largest request body accepted.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// Most header lines accepted in a request.
+const MAX_HEADERS: usize = 100;
+
+/// Longest request or header line accepted.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Held while an apply or test run is in progress, so that they take turns.
+static DATABASE_TURN: Mutex<()> = Mutex::const_new(());
+
+/// How long a client has to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A parsed HTTP request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// Path without the query string
+    pub path: String,
+    /// Headers by lowercase name
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, path: &str, body: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: HashMap::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers
+            .insert(name.to_ascii_lowercase(), value.to_string());
+        self
+    }
+
+    /// The body as JSON, or the default when it is empty.
+    fn json<T: for<'de> Deserialize<'de> + Default>(&self) -> Result<T> {
+        if self.body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(T::default());
+        }
+        serde_json::from_slice(&self.body).context("request body is not valid JSON")
+    }
+
+    fn bearer_token(&self) -> Option<&str> {
+        self.headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+    }
+}
+
+/// A JSON response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn ok(body: impl Serialize) -> Self {
+        match serde_json::to_value(body) {
+            Ok(body) => Self { status: 200, body },
+            Err(e) => Self::error(500, anyhow!(e)),
+        }
+    }
+
+    fn error(status: u16, error: anyhow::Error) -> Self {
+        Self {
+            status,
+            body: json!({ "error": format!("{:#}", error) }),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let body = serde_json::to_vec_pretty(&self.body).unwrap_or_default();
+        let mut bytes = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            body.len()
+        )
+        .into_bytes();
+        bytes.extend(body);
+        bytes
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct BuildBody {
+    migration: String,
+    pinned: bool,
+    variables: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ApplyBody {
+    /// Every pending migration when None
+    migration: Option<String>,
+    pinned: bool,
+    retry: bool,
+    variables: Option<Value>,
+}
+
+impl Default for ApplyBody {
+    fn default() -> Self {
+        Self {
+            migration: None,
+            pinned: true,
+            retry: false,
+            variables: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TestBody {
+    /// Every test when None
+    name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct AppliedStep {
+    migration: String,
+    result: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct TestResult {
+    name: String,
+    passed: bool,
+    diff: Option<String>,
+    first_diff_line: Option<usize>,
+    error: Option<String>,
+    duration_ms: u128,
+}
+
+fn variables(value: Option<Value>) -> Result<Option<Variables>> {
+    value
+        .map(|value| Variables::from_str("json", &value.to_string()))
+        .transpose()
+}
+
+/// Compares without stopping at the first difference, so the time taken
+/// does not hint at how much of the token was right.
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Answers `request`. `token` is the one clients must send, or None when
+/// the server was started without one.
+pub async fn handle(spawn: &Spawn, token: Option<&str>, request: &Request) -> Response {
+    let route = (request.method.as_str(), request.path.as_str());
+    if route == ("GET", "/health") {
+        return Response::ok(json!({ "status": "ok" }));
+    }
+
+    let needs_token = matches!(
+        route,
+        ("POST", "/build") | ("POST", "/apply") | ("POST", "/test")
+    );
+    let authorized = request
+        .bearer_token()
+        .zip(token)
+        .is_some_and(|(given, token)| token_matches(given, token));
+    if token.is_some() && !authorized {
+        return Response::error(401, anyhow!("a valid bearer token is required"));
+    }
+    if token.is_none() && needs_token {
+        return Response::error(
+            403,
+            anyhow!(
+                "{} is disabled: start spawn serve with {} set",
+                request.path,
+                SERVE_TOKEN_ENV
+            ),
+        );
+    }
+
+    // The environment's allowlist is checked against the command each
+    // endpoint stands in for, not just `serve`.
+    let command = match route {
+        ("GET", "/status" | "/history") => Some("migration status"),
+        ("POST", "/build") => Some("migration build"),
+        ("POST", "/apply") => Some("migration apply"),
+        ("POST", "/test") => Some("test compare"),
+        _ => None,
+    };
+    if let Some(Err(e)) = command.map(|command| spawn.config().check_command_allowed(command)) {
+        return Response::error(403, e);
+    }
+
+    let result = match route {
+        ("GET", "/status") => status(spawn).await,
+        ("GET", "/history") => history(spawn).await,
+        ("POST", "/build") => build(spawn, request).await,
+        ("POST", "/apply") => {
+            let _turn = DATABASE_TURN.lock().await;
+            apply(spawn, request).await
+        }
+        ("POST", "/test") => {
+            let _turn = DATABASE_TURN.lock().await;
+            test(spawn, request).await
+        }
+        (_, "/status" | "/history" | "/build" | "/apply" | "/test") => {
+            return Response::error(
+                405,
+                anyhow!("{} does not accept {}", request.path, request.method),
+            )
+        }
+        _ => return Response::error(404, anyhow!("no such endpoint {}", request.path)),
+    };
+    result.unwrap_or_else(|e| Response::error(500, e))
+}
+
+async fn status(spawn: &Spawn) -> Result<Response> {
+    let rows: Vec<MigrationStatusRow> = spawn.status().await?;
+    Ok(Response::ok(rows))
+}
+
+async fn history(spawn: &Spawn) -> Result<Response> {
+    let records: Vec<HistoryRecord> = spawn.history().await?;
+    Ok(Response::ok(records))
+}
+
+async fn build(spawn: &Spawn, request: &Request) -> Result<Response> {
+    let body: BuildBody = match request.json() {
+        Ok(body) => body,
+        Err(e) => return Ok(Response::error(400, e)),
+    };
+    if body.migration.is_empty() {
+        return Ok(Response::error(400, anyhow!("migration is required")));
+    }
+    let sql = spawn
+        .build(&body.migration, body.pinned, variables(body.variables)?)
+        .await?;
+    Ok(Response::ok(
+        json!({ "migration": body.migration, "sql": sql }),
+    ))
+}
+
+async fn apply(spawn: &Spawn, request: &Request) -> Result<Response> {
+    let body: ApplyBody = match request.json() {
+        Ok(body) => body,
+        Err(e) => return Ok(Response::error(400, e)),
+    };
+    let options = ApplyOptions {
+        pinned: body.pinned,
+        variables: variables(body.variables)?,
+        retry: body.retry,
+        ..Default::default()
+    };
+    // An apply that fails part way still reports the migrations before it.
+    let (report, error) = match spawn.apply(body.migration.as_deref(), options).await {
+        Ok(report) => (report, None),
+        Err(e) => (Default::default(), Some(format!("{:#}", e))),
+    };
+    let migrations: Vec<AppliedStep> = report
+        .migrations
+        .iter()
+        .map(|(migration, result)| AppliedStep {
+            migration: migration.clone(),
+            result: result.as_str(),
+        })
+        .collect();
+    Ok(Response {
+        status: if error.is_some() { 500 } else { 200 },
+        body: json!({ "migrations": migrations, "error": error }),
+    })
+}
+
+async fn test(spawn: &Spawn, request: &Request) -> Result<Response> {
+    let body: TestBody = match request.json() {
+        Ok(body) => body,
+        Err(e) => return Ok(Response::error(400, e)),
+    };
+    let results: Vec<TestResult> = spawn
+        .test(body.name.as_deref())
+        .await?
+        .into_iter()
+        .map(|(name, outcome)| TestResult {
+            name,
+            passed: outcome.diff.is_none(),
+            diff: outcome.diff,
+            first_diff_line: outcome.first_diff_line,
+            error: outcome.error,
+            duration_ms: outcome.duration.as_millis(),
+        })
+        .collect();
+    Ok(Response::ok(results))
+}
+
+/// Reads one request from `reader`. Returns a response to send instead
+/// when the request cannot be read.
+async fn read_request<R: tokio::io::AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> Result<std::result::Result<Request, Response>> {
+    let mut line = String::new();
+    if !read_line(reader, &mut line).await? {
+        return Ok(Err(Response::error(
+            400,
+            anyhow!("request line is too long"),
+        )));
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(Err(Response::error(400, anyhow!("malformed request line"))));
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or_default().to_string(),
+        ..Default::default()
+    };
+
+    loop {
+        line.clear();
+        if !read_line(reader, &mut line).await? {
+            return Ok(Err(Response::error(
+                400,
+                anyhow!("header line is too long"),
+            )));
+        }
+        if line.is_empty() {
+            return Ok(Err(Response::error(400, anyhow!("incomplete request"))));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if request.headers.len() >= MAX_HEADERS {
+            return Ok(Err(Response::error(400, anyhow!("too many headers"))));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            request
+                .headers
+                .insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = match request.headers.get("content-length") {
+        Some(length) => match length.parse::<usize>() {
+            Ok(length) => length,
+            Err(_) => return Ok(Err(Response::error(400, anyhow!("invalid Content-Length")))),
+        },
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Ok(Err(Response::error(
+            413,
+            anyhow!("request body is larger than {} bytes", MAX_BODY),
+        )));
+    }
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body).await?;
+    Ok(Ok(request))
+}
+
+/// Reads a line of at most [`MAX_LINE`] bytes into `line`. Returns false
+/// when the line is longer than that.
+async fn read_line<R: tokio::io::AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    line: &mut String,
+) -> Result<bool> {
+    let read = reader.take(MAX_LINE).read_line(line).await?;
+    Ok(read < MAX_LINE as usize || line.ends_with('\n'))
+}
+
+async fn handle_connection(spawn: &Spawn, token: Option<&str>, stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut reader))
+        .await
+        .map_err(|_| anyhow!("timed out reading the request"))??;
+    let response = match request {
+        Ok(request) => handle(spawn, token, &request).await,
+        Err(response) => response,
+    };
+    let mut stream = reader.into_inner();
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Serves requests from `listener` until Ctrl-C, answering each connection
+/// on its own task. Connections that fail are reported as warnings. Without
+/// a token, `listener` must be on a loopback address. On Ctrl-C, requests in
+/// progress are finished first.
+pub async fn serve(spawn: Arc<Spawn>, listener: TcpListener, token: Option<String>) -> Result<()> {
+    let address = listener.local_addr()?;
+    if token.is_none() && !address.ip().is_loopback() {
+        return Err(anyhow!(
+            "{} is not a loopback address, so {} must be set",
+            address,
+            SERVE_TOKEN_ENV
+        ));
+    }
+
+    interrupt::install();
+    let token: Option<Arc<str>> = token.map(Arc::from);
+    let mut connections = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = interrupt::interrupted() => break,
+        };
+        while connections.try_join_next().is_some() {}
+        let spawn = spawn.clone();
+        let token = token.clone();
+        connections.spawn(async move {
+            if let Err(e) = handle_connection(&spawn, token.as_deref(), stream).await {
+                spawn
+                    .config()
+                    .events
+                    .on_warning(&format!("could not answer a request: {:#}", e));
+            }
+        });
+    }
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request() -> Result<()> {
+        let raw = "POST /apply?dry=1 HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer abc\r\nContent-Length: 15\r\n\r\n{\"retry\": true}";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let request = read_request(&mut reader).await?.unwrap();
+        assert_eq!("POST", request.method);
+        assert_eq!("/apply", request.path);
+        assert_eq!(Some("abc"), request.bearer_token());
+        let body: ApplyBody = request.json()?;
+        assert!(body.retry);
+        assert!(body.pinned);
+
+        let raw = "GET /status HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let response = read_request(&mut reader).await?.unwrap_err();
+        assert_eq!(413, response.status);
+
+        let raw = format!(
+            "GET /status HTTP/1.1\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(10_000)
+        );
+        let mut reader = BufReader::new(raw.as_bytes());
+        let response = read_request(&mut reader).await?.unwrap_err();
+        assert_eq!(400, response.status);
+        assert_eq!("header line is too long", response.body["error"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secreT", "secret"));
+        assert!(!token_matches("secret2", "secret"));
+        assert!(!token_matches("", "secret"));
+    }
+}
@@ -0,0 +1,422 @@
+use crate::config::Config;
+use crate::store::pinner::pinned_tree_sha256;
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Name of the signature file written next to a migration's `lock.toml`.
+pub const SIGNATURE_FILE_NAME: &str = "signature.toml";
+
+/// Starts the last line of a build artifact signed with `build --sign`,
+/// followed by the signer's key and the signature.
+pub const ARTIFACT_SIGNATURE_PREFIX: &str = "-- spawn-signature: ";
+
+/// Environment variable holding a base64 signing key, used instead of
+/// `key_file` so that CI can keep the key in its secret store.
+pub const SIGNING_KEY_ENV: &str = "SPAWN_SIGNING_KEY";
+
+/// Signing configuration, in the `[signing]` section.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SigningConfig {
+    /// Base64 public keys whose signatures are accepted by
+    /// `apply --require-signature`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<String>,
+    /// Path of the private key used by `build --sign`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<String>,
+}
+
+/// What a migration signature vouches for: the pinned components and
+/// migration files, and the migration script itself. Variables are not
+/// covered, so the same signature holds in every environment.
+///
+/// Pinned objects are named by a hash that is not collision resistant, so
+/// the contents of the pinned trees are covered by their own SHA-256.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationManifest {
+    pub migration: String,
+    pub pin: String,
+    /// SHA-256 of the files pinned in `pin`, see [`pinned_tree_sha256`]
+    #[serde(default)]
+    pub pin_sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
+    /// SHA-256 of the files pinned in `local`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_sha256: Option<String>,
+    pub script_sha256: String,
+}
+
+impl MigrationManifest {
+    /// The bytes that are signed.
+    fn message(&self) -> Vec<u8> {
+        format!(
+            "spawn-migration-v2\nmigration:{}\npin:{}\npin-sha256:{}\nlocal:{}\nlocal-sha256:{}\nscript:{}\n",
+            self.migration,
+            self.pin,
+            self.pin_sha256,
+            self.local.as_deref().unwrap_or_default(),
+            self.local_sha256.as_deref().unwrap_or_default(),
+            self.script_sha256
+        )
+        .into_bytes()
+    }
+}
+
+/// Contents of a migration's `signature.toml`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SignatureFile {
+    #[serde(flatten)]
+    pub manifest: MigrationManifest,
+    /// Base64 public key of the signer
+    pub key: String,
+    /// Base64 ed25519 signature of the manifest
+    pub signature: String,
+}
+
+/// Generates a new signing key.
+pub fn generate_key() -> SigningKey {
+    SigningKey::generate(&mut rand_core::OsRng)
+}
+
+pub fn encode_signing_key(key: &SigningKey) -> String {
+    BASE64.encode(key.to_bytes())
+}
+
+pub fn encode_public_key(key: &VerifyingKey) -> String {
+    BASE64.encode(key.to_bytes())
+}
+
+fn decode_signing_key(encoded: &str) -> Result<SigningKey> {
+    let bytes: [u8; 32] = BASE64
+        .decode(encoded.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("signing key must be 32 bytes"))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+fn decode_public_key(encoded: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = BASE64
+        .decode(encoded.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("public key must be 32 bytes"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Loads the signing key from `SPAWN_SIGNING_KEY`, or failing that from the
+/// configured `key_file`.
+pub fn load_signing_key(cfg: &SigningConfig) -> Result<SigningKey> {
+    if let Ok(encoded) = std::env::var(SIGNING_KEY_ENV) {
+        return decode_signing_key(&encoded)
+            .context(format!("invalid signing key in {}", SIGNING_KEY_ENV));
+    }
+
+    let path = cfg.key_file.as_ref().ok_or(anyhow!(
+        "no signing key: set {} or signing.key_file in spawn.toml",
+        SIGNING_KEY_ENV
+    ))?;
+    let encoded =
+        std::fs::read_to_string(path).context(format!("could not read signing key {}", path))?;
+    decode_signing_key(&encoded).context(format!("invalid signing key in {}", path))
+}
+
+/// Signs `message`, returning the base64 signature.
+pub fn sign_message(message: &[u8], key: &SigningKey) -> String {
+    BASE64.encode(key.sign(message).to_bytes())
+}
+
+/// Checks that `signature` is a valid signature of `message` by `key`, and
+/// that `key` is one of the trusted keys.
+pub fn verify_message(
+    message: &[u8],
+    key: &str,
+    signature: &str,
+    trusted_keys: &[String],
+) -> Result<()> {
+    if trusted_keys.is_empty() {
+        return Err(anyhow!("no trusted keys configured in [signing]"));
+    }
+    if !trusted_keys.iter().any(|k| k.trim() == key.trim()) {
+        return Err(anyhow!("signed by untrusted key {}", key));
+    }
+    let public_key = decode_public_key(key)?;
+    let bytes: [u8; 64] = BASE64
+        .decode(signature)?
+        .try_into()
+        .map_err(|_| anyhow!("signature must be 64 bytes"))?;
+    public_key
+        .verify(message, &Signature::from_bytes(&bytes))
+        .map_err(|_| anyhow!("signature does not match"))
+}
+
+pub fn sign_manifest(manifest: MigrationManifest, key: &SigningKey) -> SignatureFile {
+    SignatureFile {
+        key: encode_public_key(&key.verifying_key()),
+        signature: sign_message(&manifest.message(), key),
+        manifest,
+    }
+}
+
+/// Checks that `file` is a valid signature of `expected` by one of the
+/// trusted keys.
+pub fn verify_manifest(
+    file: &SignatureFile,
+    expected: &MigrationManifest,
+    trusted_keys: &[String],
+) -> Result<()> {
+    if trusted_keys.is_empty() {
+        return Err(anyhow!("no trusted keys configured in [signing]"));
+    }
+    if !trusted_keys.iter().any(|k| k.trim() == file.key.trim()) {
+        return Err(anyhow!("signed by untrusted key {}", file.key));
+    }
+    if file.manifest.pin_sha256.is_empty() {
+        return Err(anyhow!(
+            "signed by an older version of spawn that did not cover the pinned contents. Sign it again with `spawn migration build --sign`"
+        ));
+    }
+    if file.manifest != *expected {
+        return Err(anyhow!(
+            "migration has changed since it was signed (signed pin {}, current pin {})",
+            file.manifest.pin,
+            expected.pin
+        ));
+    }
+    verify_message(
+        &expected.message(),
+        &file.key,
+        &file.signature,
+        trusted_keys,
+    )
+}
+
+/// The bytes signed for a build artifact: the migration it is to be applied
+/// as, and its SQL through its hash.
+fn artifact_message(migration: &str, sql: &str) -> Vec<u8> {
+    format!(
+        "spawn-artifact-v1\nmigration:{}\nsql:{:x}\n",
+        migration,
+        Sha256::digest(sql.as_bytes())
+    )
+    .into_bytes()
+}
+
+/// Signs `sql`, built from `migration`, returning it with a signature line
+/// added as an SQL comment, so that the artifact carries its own signature
+/// to `apply --from-file --require-signature`.
+pub fn sign_artifact(migration: &str, sql: &str, key: &SigningKey) -> String {
+    let mut sql = sql.to_string();
+    if !sql.is_empty() && !sql.ends_with('\n') {
+        sql.push('\n');
+    }
+    let signature = sign_message(&artifact_message(migration, &sql), key);
+    format!(
+        "{}{}{} {}\n",
+        sql,
+        ARTIFACT_SIGNATURE_PREFIX,
+        encode_public_key(&key.verifying_key()),
+        signature
+    )
+}
+
+/// Checks that `artifact` was signed with [`sign_artifact`] for `migration`
+/// by one of the trusted keys, returning the SQL without the signature line.
+pub fn verify_artifact<'a>(
+    migration: &str,
+    artifact: &'a str,
+    trusted_keys: &[String],
+) -> Result<&'a str> {
+    let body = artifact.trim_end_matches(['\n', '\r']);
+    let start = body.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let (sql, line) = body.split_at(start);
+    let (key, signature) = line
+        .strip_prefix(ARTIFACT_SIGNATURE_PREFIX)
+        .and_then(|rest| rest.split_once(' '))
+        .ok_or(anyhow!(
+            "file is not signed: build it with `spawn migration build --pinned --sign`"
+        ))?;
+    verify_message(
+        &artifact_message(migration, sql),
+        key,
+        signature,
+        trusted_keys,
+    )
+    .context(format!(
+        "signature of the file is not valid for {}",
+        migration
+    ))?;
+    Ok(sql)
+}
+
+/// Builds the manifest for a pinned migration from its lock file and script.
+pub async fn migration_manifest(config: &Config, migration: &str) -> Result<MigrationManifest> {
+    let pather = config.pather();
+    let lock = config
+        .load_lock_file(&pather.migration_lock_file_path(migration))
+        .await
+        .context("could not load lock file, has this migration been pinned?")?;
+    let script = config
+        .operator()
+        .read(&pather.migration_script_file_path(migration))
+        .await?
+        .to_bytes();
+    let pinned_folder = pather.pinned_folder();
+    let pin_sha256 = pinned_tree_sha256(config.operator(), &pinned_folder, &lock.pin).await?;
+    let local_sha256 = match &lock.local {
+        Some(local) => Some(pinned_tree_sha256(config.operator(), &pinned_folder, local).await?),
+        None => None,
+    };
+
+    Ok(MigrationManifest {
+        migration: migration.to_string(),
+        pin: lock.pin,
+        pin_sha256,
+        local: lock.local,
+        local_sha256,
+        script_sha256: format!("{:x}", Sha256::digest(&script)),
+    })
+}
+
+fn signature_path(config: &Config, migration: &str) -> String {
+    config.pather().migration_signature_file_path(migration)
+}
+
+/// Signs a pinned migration, writing its `signature.toml`.
+pub async fn sign_migration(config: &Config, migration: &str, key: &SigningKey) -> Result<()> {
+    let signed = sign_manifest(migration_manifest(config, migration).await?, key);
+    config
+        .operator()
+        .write(
+            &signature_path(config, migration),
+            toml::to_string_pretty(&signed)?,
+        )
+        .await
+        .context("failed writing signature file")?;
+    Ok(())
+}
+
+/// Checks a migration's `signature.toml` against its current contents and the
+/// configured trusted keys.
+pub async fn verify_migration(config: &Config, migration: &str) -> Result<()> {
+    let path = signature_path(config, migration);
+    let contents = config
+        .operator()
+        .read(&path)
+        .await
+        .context(format!("migration {} is not signed", migration))?
+        .to_bytes();
+    let file: SignatureFile = toml::from_str(std::str::from_utf8(&contents)?)
+        .context(format!("could not parse {}", path))?;
+
+    let expected = migration_manifest(config, migration).await?;
+    verify_manifest(&file, &expected, &config.signing.trusted_keys)
+        .context(format!("signature of migration {} is not valid", migration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> MigrationManifest {
+        MigrationManifest {
+            migration: "20240101000000-users".to_string(),
+            pin: "cb59728f".to_string(),
+            pin_sha256: "0a1b".to_string(),
+            local: None,
+            local_sha256: None,
+            script_sha256: "abc".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_signature_verifies_with_trusted_key() {
+        let key = generate_key();
+        let trusted = vec![encode_public_key(&key.verifying_key())];
+        let signed = sign_manifest(manifest(), &key);
+
+        verify_manifest(&signed, &manifest(), &trusted).unwrap();
+
+        // Round trips through the signature file.
+        let parsed: SignatureFile =
+            toml::from_str(&toml::to_string_pretty(&signed).unwrap()).unwrap();
+        verify_manifest(&parsed, &manifest(), &trusted).unwrap();
+    }
+
+    #[test]
+    fn test_signature_rejects_untrusted_key() {
+        let key = generate_key();
+        let other = generate_key();
+        let signed = sign_manifest(manifest(), &key);
+
+        let trusted = vec![encode_public_key(&other.verifying_key())];
+        assert!(verify_manifest(&signed, &manifest(), &trusted).is_err());
+        assert!(verify_manifest(&signed, &manifest(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_signature_rejects_changed_migration() {
+        let key = generate_key();
+        let trusted = vec![encode_public_key(&key.verifying_key())];
+        let mut signed = sign_manifest(manifest(), &key);
+
+        let mut changed = manifest();
+        changed.script_sha256 = "def".to_string();
+        assert!(verify_manifest(&signed, &changed, &trusted).is_err());
+
+        // Editing the file to match does not help, since the signature no
+        // longer matches.
+        signed.manifest = changed.clone();
+        assert!(verify_manifest(&signed, &changed, &trusted).is_err());
+
+        // Nor does changing the pinned contents behind the same pin.
+        let signed = sign_manifest(manifest(), &key);
+        let mut changed = manifest();
+        changed.pin_sha256 = "ffff".to_string();
+        assert!(verify_manifest(&signed, &changed, &trusted).is_err());
+    }
+
+    #[test]
+    fn test_signature_without_content_digest_is_rejected() {
+        let key = generate_key();
+        let trusted = vec![encode_public_key(&key.verifying_key())];
+        let old = MigrationManifest {
+            pin_sha256: String::new(),
+            ..manifest()
+        };
+        let signed = sign_manifest(old.clone(), &key);
+        let err = verify_manifest(&signed, &old, &trusted).unwrap_err();
+        assert!(err.to_string().contains("Sign it again"), "{}", err);
+    }
+
+    #[test]
+    fn test_artifact_signature() {
+        let key = generate_key();
+        let trusted = vec![encode_public_key(&key.verifying_key())];
+        let artifact = sign_artifact("20240101-users", "CREATE TABLE users ();", &key);
+        assert!(artifact.starts_with("CREATE TABLE users ();\n-- spawn-signature: "));
+        assert_eq!(
+            "CREATE TABLE users ();\n",
+            verify_artifact("20240101-users", &artifact, &trusted).unwrap()
+        );
+
+        // Bound to the migration it was built from.
+        assert!(verify_artifact("20240102-orders", &artifact, &trusted).is_err());
+        // And to its SQL.
+        let edited = artifact.replace("users ()", "users (id int)");
+        assert!(verify_artifact("20240101-users", &edited, &trusted).is_err());
+        // Unsigned files and untrusted keys are refused.
+        assert!(verify_artifact("20240101-users", "CREATE TABLE users ();\n", &trusted).is_err());
+        assert!(verify_artifact("20240101-users", &artifact, &[]).is_err());
+    }
+
+    #[test]
+    fn test_signing_key_round_trips() {
+        let key = generate_key();
+        let decoded = decode_signing_key(&encode_signing_key(&key)).unwrap();
+        assert_eq!(key.to_bytes(), decoded.to_bytes());
+    }
+}
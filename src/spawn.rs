@@ -0,0 +1,168 @@
+//! A facade for using spawn as a library. Each method does what the matching
+//! command does, but returns its result instead of printing it.
+//!
+//! Warnings, migrations starting, the statements sent and test diffs are
+//! sent to an [`EventSink`] given with [`Spawn::with_events`], and dropped
+//! otherwise. Progress is still written to stderr through
+//! [`status!`](crate::status); call [`output::set_quiet`](crate::output::set_quiet)
+//! to leave it out. Nothing prompts: migrations are applied as if `--yes` was
+//! given, and protected targets are refused. Each method is refused when the
+//! target's environment does not allow the matching command, as set by
+//! `allowed_commands`.
+//!
+//! ```no_run
+//! # async fn example(config: spawn_db::config::Config) -> anyhow::Result<()> {
+//! use spawn_db::{ApplyOptions, Spawn};
+//!
+//! let spawn = Spawn::new(config);
+//! let report = spawn.apply(None, ApplyOptions::default()).await?;
+//! for migration in report.applied() {
+//!     println!("applied {}", migration);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::commands::migration::{
+    get_combined_migration_status, migration_history, render_migration, ApplyMigration,
+    ApplyReport, HistoryRecord, MigrationStatusRow, DEFAULT_NAMESPACE,
+};
+use crate::commands::test::list_tests;
+use crate::config::Config;
+use crate::events::{EventSink, Events};
+use crate::policy::enforce_rules;
+use crate::schedule::Schedule;
+use crate::sqltest::{TestOutcome, Tester};
+use crate::variables::Variables;
+use anyhow::Result;
+
+/// How [`Spawn::apply`] renders and applies migrations.
+#[derive(Debug, Clone)]
+pub struct ApplyOptions {
+    /// Render from the pinned components, as `migration apply` does unless
+    /// given `--no-pin`
+    pub pinned: bool,
+    pub variables: Option<Variables>,
+    /// Retry a migration whose previous attempt failed
+    pub retry: bool,
+    /// Refuse migrations without a valid signature from a trusted key
+    pub require_signature: bool,
+    /// Refuse migrations that have not already succeeded on this target or
+    /// environment, as `migration apply --from` does
+    pub promote_from: Option<String>,
+    /// Apply destructive statements to a protected target without each
+    /// migration acknowledging them, as `migration apply --allow-destructive`
+    /// does
+    pub allow_destructive: bool,
+    /// When to start, as `migration apply --at` and `--window` set. The
+    /// environment's maintenance window applies either way.
+    pub schedule: Schedule,
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        Self {
+            pinned: true,
+            variables: None,
+            retry: false,
+            require_signature: false,
+            promote_from: None,
+            allow_destructive: false,
+            schedule: Schedule::default(),
+        }
+    }
+}
+
+pub struct Spawn {
+    config: Config,
+}
+
+impl Spawn {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Sends the events of every call to `sink`.
+    pub fn with_events(mut self, sink: impl EventSink + 'static) -> Self {
+        self.config.events = Events::new(sink);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Applies `migration`, or every pending migration when None.
+    pub async fn apply(
+        &self,
+        migration: Option<&str>,
+        options: ApplyOptions,
+    ) -> Result<ApplyReport> {
+        self.config.check_command_allowed("migration apply")?;
+        ApplyMigration {
+            migration: migration.map(str::to_string),
+            pinned: options.pinned,
+            variables: options.variables,
+            yes: true,
+            force: false,
+            retry: options.retry,
+            reuse_connection: false,
+            require_signature: options.require_signature,
+            from_file: None,
+            interactive: false,
+            promote_from: options.promote_from,
+            allow_destructive: options.allow_destructive,
+            schedule: options.schedule,
+        }
+        .run(&self.config)
+        .await
+    }
+
+    /// The status of every migration, from both the project and the
+    /// database, as shown by `migration status`.
+    pub async fn status(&self) -> Result<Vec<MigrationStatusRow>> {
+        self.config.check_command_allowed("migration status")?;
+        get_combined_migration_status(&self.config, Some(DEFAULT_NAMESPACE)).await
+    }
+
+    /// Every recorded activity, oldest first, as shown by
+    /// `migration status --history`.
+    pub async fn history(&self) -> Result<Vec<HistoryRecord>> {
+        self.config.check_command_allowed("migration status")?;
+        migration_history(&self.config).await
+    }
+
+    /// Renders a migration to SQL, as `migration build` does.
+    pub async fn build(
+        &self,
+        migration: &str,
+        pinned: bool,
+        variables: Option<Variables>,
+    ) -> Result<String> {
+        self.config.check_command_allowed("migration build")?;
+        let sql = render_migration(&self.config, migration, pinned, variables).await?;
+        enforce_rules(&self.config, migration, &sql)?;
+        Ok(sql)
+    }
+
+    /// Runs the test `name`, or every test when None, comparing each with
+    /// its expected output as `test compare` does. A test passed if its
+    /// outcome has no diff.
+    pub async fn test(&self, name: Option<&str>) -> Result<Vec<(String, TestOutcome)>> {
+        self.config.check_command_allowed("test compare")?;
+        let names = match name {
+            Some(name) => vec![name.to_string()],
+            None => list_tests(&self.config).await?,
+        };
+
+        let mut results = Vec::new();
+        for name in names {
+            let outcome = Tester::new(&self.config, &name).run_compare(None).await?;
+            if let Some(diff) = &outcome.diff {
+                self.config.events.on_diff(&name, diff);
+            }
+            results.push((name, outcome));
+        }
+        Ok(results)
+    }
+}
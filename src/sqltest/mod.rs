@@ -1,5 +1,9 @@
+use crate::commands::migration::{get_combined_migration_status, DEFAULT_NAMESPACE};
 use crate::config;
-use crate::engine::EngineError;
+use crate::engine::{server_messages, Engine, EngineError, MigrationStatus, ServerMessage};
+use crate::escape::EscapedIdentifier;
+use crate::sql_query;
+use crate::status;
 use crate::template;
 use console::{style, Style};
 
@@ -11,17 +15,136 @@ use similar::{ChangeTag, TextDiff};
 use std::fmt;
 use std::str;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Optional settings file in a test's folder.
+pub const TEST_SETTINGS_FILE: &str = "test.toml";
 
 pub struct Tester {
     config: config::Config,
     script_path: String,
+    isolate: bool,
 }
 
 #[derive(Debug)]
 pub struct TestOutcome {
     pub diff: Option<String>,
+    /// 1-based line of the expected file where the output first differs
+    pub first_diff_line: Option<usize>,
+    /// The error psql reported when the test's SQL failed. The output up to
+    /// the error is still compared, so a test may expect an error.
+    pub error: Option<String>,
+    /// Wall-clock time taken to run and compare the test
+    pub duration: Duration,
+    /// Notices and warnings the server sent while running the test, which
+    /// are also in its output. Always empty for render tests.
+    pub messages: Vec<ServerMessage>,
+}
+
+/// How a test produces the output compared with its expected file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestKind {
+    /// Runs the rendered SQL against the database and compares its output
+    #[default]
+    Run,
+    /// Compares the rendered SQL itself, without a database
+    Render,
+}
+
+/// The contents of a test's `test.toml`. Tests without one run test.sql
+/// against the database.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestSettings {
+    #[serde(default)]
+    pub kind: TestKind,
+    /// Migration to render instead of test.sql
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<String>,
+    /// Component to render instead of test.sql, by path in the components
+    /// folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub component: Option<String>,
+    /// Variables file to render with, relative to the test's folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<String>,
+    /// Migrations that must be applied to the target before the test runs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires_migrations: Vec<String>,
+}
+
+/// The 1-based line in `expected` where `generated` first differs from it.
+/// Output that only adds lines past the end points at the last line.
+/// The copy of the target's database an isolated test runs in.
+struct IsolatedCopy {
+    /// The target's database, which is copied
+    source: String,
+    name: String,
+}
+
+impl IsolatedCopy {
+    async fn of(engine: &dyn Engine) -> Result<Self> {
+        let rows = engine
+            .query(&sql_query!("SELECT current_database() AS database;"))
+            .await?;
+        let source = rows
+            .iter()
+            .find_map(|row| row.get("database").map(str::to_string))
+            .ok_or_else(|| anyhow!("could not find the target's database to copy for --isolate"))?;
+        Ok(Self {
+            source,
+            name: format!("spawn_isolated_{}", std::process::id()),
+        })
+    }
+
+    /// psql lines that make the copy and connect to it, ahead of the test.
+    /// They run quietly so the test's output is as it would be without
+    /// `--isolate`. The copy is made from `template1`, as a database cannot
+    /// be copied while connected to it.
+    fn enter(&self) -> String {
+        let name = EscapedIdentifier::new(&self.name);
+        format!(
+            "\\set spawn_quiet :QUIET\n\\set QUIET on\n\\c template1\n\
+             DROP DATABASE IF EXISTS {};\n\
+             CREATE DATABASE {} TEMPLATE {};\n\
+             \\c {}\n\\set QUIET :spawn_quiet\n",
+            name,
+            name,
+            EscapedIdentifier::new(&self.source),
+            name
+        )
+    }
+
+    /// Drops the copy once the test has run, from a session of its own so
+    /// it happens even when the test failed.
+    async fn remove(&self, engine: &dyn Engine) -> Result<()> {
+        let sql = format!(
+            "DROP DATABASE IF EXISTS {};\n",
+            EscapedIdentifier::new(&self.name)
+        );
+        engine
+            .execute_with_writer(
+                Box::new(move |writer| writer.write_all(sql.as_bytes())),
+                None,
+                false,
+            )
+            .await
+            .context(format!("could not drop the test database {}", self.name))
+    }
+}
+
+pub fn first_difference_line(expected: &str, generated: &str) -> Option<usize> {
+    let diff = TextDiff::from_lines(expected, generated);
+    let op = diff
+        .ops()
+        .iter()
+        .find(|op| op.tag() != similar::DiffTag::Equal)?;
+    let line = op.old_range().start + 1;
+    Some(line.min(expected.lines().count().max(1)))
 }
 
 impl Tester {
@@ -29,9 +152,20 @@ impl Tester {
         Tester {
             config: config.clone(),
             script_path: script_path.to_string(),
+            isolate: false,
         }
     }
 
+    /// Runs the test in a copy of the target's database, made with `CREATE
+    /// DATABASE ... TEMPLATE` just before the test and dropped after it, so
+    /// the database is left as it was for the tests after it whatever the
+    /// test does, including committing or creating databases. The target's
+    /// database must have no other sessions while it is copied.
+    pub fn with_isolation(mut self, isolate: bool) -> Self {
+        self.isolate = isolate;
+        self
+    }
+
     pub fn test_folder(&self) -> String {
         let mut s = self.config.pather().tests_folder();
         s.push('/');
@@ -49,18 +183,87 @@ impl Tester {
         format!("{}/expected", self.test_folder())
     }
 
-    /// Opens the specified script file and generates a test script, compiled
-    /// using minijinja.
-    pub async fn generate(&self, variables: Option<crate::variables::Variables>) -> Result<String> {
-        let lock_file = None;
+    pub fn settings_file_path(&self) -> String {
+        format!("{}/{}", self.test_folder(), TEST_SETTINGS_FILE)
+    }
 
-        let gen = template::generate_streaming(
+    /// Reads the test's `test.toml`, or the default settings if it has none.
+    pub async fn settings(&self) -> Result<TestSettings> {
+        match self
+            .config
+            .operator()
+            .read(&self.settings_file_path())
+            .await
+        {
+            Ok(contents) => {
+                let contents = String::from_utf8(contents.to_bytes().to_vec())?;
+                toml::from_str(&contents).context(format!("invalid {}", self.settings_file_path()))
+            }
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => Ok(TestSettings::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The template the test renders: test.sql, or the migration or
+    /// component named in its settings.
+    pub fn template_path(&self, settings: &TestSettings) -> Result<String> {
+        match (&settings.migration, &settings.component) {
+            (None, None) => Ok(self.test_file_path()),
+            (Some(migration), None) => {
+                Ok(self.config.pather().migration_script_file_path(migration))
+            }
+            (None, Some(component)) => Ok(format!(
+                "{}/{}",
+                self.config.pather().components_folder(),
+                component.trim_start_matches('/')
+            )),
+            (Some(_), Some(_)) => Err(anyhow!(
+                "{} sets both `migration` and `component`",
+                self.settings_file_path()
+            )),
+        }
+    }
+
+    /// Prepares the test's template for rendering from the current
+    /// components. Without `variables`, the settings' variables file is used,
+    /// if any.
+    pub async fn generation(
+        &self,
+        settings: &TestSettings,
+        variables: Option<crate::variables::Variables>,
+    ) -> Result<template::StreamingGeneration> {
+        let variables = match (variables, &settings.variables) {
+            (Some(variables), _) => Some(variables),
+            (None, Some(file)) => Some(
+                self.config
+                    .load_variables_from_path(&format!("{}/{}", self.test_folder(), file))
+                    .await?,
+            ),
+            (None, None) => None,
+        };
+
+        template::generate_streaming(
             &self.config,
-            lock_file,
-            &self.test_file_path(),
+            None,
+            &self.template_path(settings)?,
             variables,
         )
-        .await?;
+        .await
+    }
+
+    /// Opens the specified script file and generates a test script, compiled
+    /// using minijinja.
+    pub async fn generate(&self, variables: Option<crate::variables::Variables>) -> Result<String> {
+        let settings = self.settings().await?;
+        self.generate_with(&settings, variables).await
+    }
+
+    async fn generate_with(
+        &self,
+        settings: &TestSettings,
+        variables: Option<crate::variables::Variables>,
+    ) -> Result<String> {
+        let gen = self.generation(settings, variables).await?;
 
         let mut buffer = Vec::new();
         gen.render_to_writer(&mut buffer)
@@ -70,17 +273,56 @@ impl Tester {
         Ok(content)
     }
 
-    // Runs the test and compares the actual output to expected.
+    // Runs the test and returns its output.
     pub async fn run(&self, variables: Option<crate::variables::Variables>) -> Result<String> {
-        let content = self.generate(variables.clone()).await?;
+        let (generated, _) = self.run_with_error(variables).await?;
+        Ok(generated)
+    }
+
+    /// Runs the test, returning its output and the notices and warnings
+    /// the server sent, which render tests have none of.
+    pub async fn run_with_messages(
+        &self,
+        variables: Option<crate::variables::Variables>,
+    ) -> Result<(String, Vec<ServerMessage>)> {
+        let kind = self.settings().await?.kind;
+        let generated = self.run(variables).await?;
+        let messages = match kind {
+            TestKind::Run => server_messages(&generated),
+            TestKind::Render => Vec::new(),
+        };
+        Ok((generated, messages))
+    }
 
+    /// Runs the test, returning its output and the error psql reported, if
+    /// the test's SQL failed. Render tests return the rendered SQL without
+    /// touching the database.
+    async fn run_with_error(
+        &self,
+        variables: Option<crate::variables::Variables>,
+    ) -> Result<(String, Option<String>)> {
+        let settings = self.settings().await?;
+        let content = self.generate_with(&settings, variables).await?;
+        if settings.kind == TestKind::Render {
+            return Ok((content, None));
+        }
+
+        self.check_required_migrations(&settings).await?;
         let engine = self.config.new_engine().await?;
+        let copy = match self.isolate {
+            true => Some(IsolatedCopy::of(engine.as_ref()).await?),
+            false => None,
+        };
+        let content = match &copy {
+            Some(copy) => format!("{}{}", copy.enter(), content),
+            None => content,
+        };
 
         // Create a shared buffer to capture stdout
         let stdout_buf = Arc::new(Mutex::new(Vec::new()));
         let stdout_buf_clone = stdout_buf.clone();
 
-        match engine
+        let error = match engine
             .execute_with_writer(
                 Box::new(move |writer| {
                     writer.write_all(content.as_bytes())?;
@@ -91,26 +333,75 @@ impl Tester {
             )
             .await
         {
-            Ok(()) => {}
-            Err(EngineError::ExecutionFailed { .. }) => {
+            Ok(()) => None,
+            Err(EngineError::ExecutionFailed { exit_code, stderr }) => {
                 // psql exited non-zero (e.g. ON_ERROR_STOP triggered).
                 // The combined output buffer already has the error output,
                 // so we just continue and return it.
+                Some(if stderr.is_empty() {
+                    format!("psql exited with code {}", exit_code)
+                } else {
+                    stderr
+                })
             }
             Err(e) => return Err(e).context("failed to write content to test db"),
+        };
+        if let Some(copy) = &copy {
+            if let Err(e) = copy.remove(engine.as_ref()).await {
+                self.config.events.on_warning(&format!("{:#}", e));
+            }
         }
 
         let buf = stdout_buf.lock().unwrap();
         let generated = String::from_utf8_lossy(&buf).to_string();
 
-        Ok(generated)
+        Ok((generated, error))
+    }
+
+    /// Fails unless every migration in the test's `requires_migrations` was
+    /// applied successfully to the target, so a missing table is reported as
+    /// such rather than as an SQL error in the test's output.
+    async fn check_required_migrations(&self, settings: &TestSettings) -> Result<()> {
+        if settings.requires_migrations.is_empty() {
+            return Ok(());
+        }
+        let status = get_combined_migration_status(&self.config, Some(DEFAULT_NAMESPACE)).await?;
+        let missing: Vec<String> = settings
+            .requires_migrations
+            .iter()
+            .filter_map(|required| {
+                let row = status.iter().find(|row| &row.migration_name == required);
+                let reason = match row.and_then(|row| row.last_status) {
+                    Some(MigrationStatus::Success) => return None,
+                    Some(MigrationStatus::Failure) => "failed",
+                    Some(MigrationStatus::Attempted) => "attempted",
+                    None if row.is_some_and(|row| row.exists_in_filesystem) => "pending",
+                    None => "no such migration",
+                };
+                Some(format!("{} ({})", required, reason))
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "test {} requires migrations that are not applied to the target: {}",
+            self.script_path,
+            missing.join(", ")
+        ))
     }
 
     pub async fn run_compare(
         &self,
         variables: Option<crate::variables::Variables>,
     ) -> Result<TestOutcome> {
-        let generated = self.run(variables).await?;
+        let started = Instant::now();
+        let kind = self.settings().await?.kind;
+        let (generated, error) = self.run_with_error(variables).await?;
+        let messages = match kind {
+            TestKind::Run => server_messages(&generated),
+            TestKind::Render => Vec::new(),
+        };
         let expected_bytes = self
             .config
             .operator()
@@ -122,9 +413,19 @@ impl Tester {
             .context("expected file is not valid UTF-8")?;
 
         let outcome = match self.compare(&generated, &expected) {
-            Ok(()) => TestOutcome { diff: None },
+            Ok(()) => TestOutcome {
+                diff: None,
+                first_diff_line: None,
+                error,
+                duration: started.elapsed(),
+                messages,
+            },
             Err(differences) => TestOutcome {
                 diff: Some(differences.to_string()),
+                first_diff_line: first_difference_line(&expected, &generated),
+                error,
+                duration: started.elapsed(),
+                messages,
             },
         };
 
@@ -145,62 +446,101 @@ impl Tester {
         Ok(())
     }
 
-    /// Creates a new test folder with a blank test.sql file.
-    pub async fn create_test(&self) -> Result<String> {
+    /// Creates a new test folder with a blank test.sql file, and a
+    /// `test.toml` for `kind`s other than run.
+    pub async fn create_test(&self, kind: TestKind) -> Result<String> {
         let script_path = self.test_file_path();
-        println!("creating test at {}", &script_path);
+        status!("creating test at {}", &script_path);
         self.config
             .operator()
             .write(&script_path, BASE_TEST)
             .await?;
+        if kind != TestKind::Run {
+            let settings = TestSettings {
+                kind,
+                ..Default::default()
+            };
+            self.config
+                .operator()
+                .write(&self.settings_file_path(), toml::to_string(&settings)?)
+                .await?;
+        }
 
         Ok(self.script_path.clone())
     }
 
+    /// Creates a new test folder running `script`, runs it, and saves its
+    /// output as the expected file. Returns the output and the error psql
+    /// reported, if the script failed; the failure is recorded as expected
+    /// output all the same.
+    pub async fn record(&self, script: &str) -> Result<(String, Option<String>)> {
+        let script_path = self.test_file_path();
+        status!("recording test at {}", &script_path);
+        self.config
+            .operator()
+            .write(&script_path, script.to_string())
+            .await?;
+        let (generated, error) = self.run_with_error(None).await?;
+        self.config
+            .operator()
+            .write(&self.expected_file_path(), generated.clone())
+            .await
+            .context("unable to write expectation file")?;
+
+        Ok((generated, error))
+    }
+
     pub fn compare(&self, generated: &str, expected: &str) -> std::result::Result<(), String> {
-        let diff = TextDiff::from_lines(expected, generated);
+        match diff_lines(expected, generated) {
+            Some(diff) => Err(diff),
+            None => Ok(()),
+        }
+    }
+}
 
-        let mut diff_display = String::new();
+/// A colored, line by line diff of `generated` against `expected`, or None
+/// if they are the same.
+pub fn diff_lines(expected: &str, generated: &str) -> Option<String> {
+    let diff = TextDiff::from_lines(expected, generated);
 
-        for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
-            if idx > 0 {
-                diff_display.push_str(&format!("{:-^1$}", "-", 80));
-            }
-            for op in group {
-                for change in diff.iter_inline_changes(op) {
-                    let (sign, s) = match change.tag() {
-                        ChangeTag::Delete => ("-", Style::new().red()),
-                        ChangeTag::Insert => ("+", Style::new().green()),
-                        ChangeTag::Equal => (" ", Style::new().dim()),
-                    };
-                    diff_display.push_str(&format!(
-                        "{}{} |{}",
-                        style(Line(change.old_index())).dim(),
-                        style(Line(change.new_index())).dim(),
-                        s.apply_to(sign).bold(),
-                    ));
-                    for (emphasized, value) in change.iter_strings_lossy() {
-                        if emphasized {
-                            diff_display.push_str(&format!(
-                                "{}",
-                                s.apply_to(value).underlined().on_black()
-                            ));
-                        } else {
-                            diff_display.push_str(&format!("{}", s.apply_to(value)));
-                        }
-                    }
-                    if change.missing_newline() {
-                        diff_display.push('\n');
+    let mut diff_display = String::new();
+
+    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
+        if idx > 0 {
+            diff_display.push_str(&format!("{:-^1$}", "-", 80));
+        }
+        for op in group {
+            for change in diff.iter_inline_changes(op) {
+                let (sign, s) = match change.tag() {
+                    ChangeTag::Delete => ("-", Style::new().red()),
+                    ChangeTag::Insert => ("+", Style::new().green()),
+                    ChangeTag::Equal => (" ", Style::new().dim()),
+                };
+                diff_display.push_str(&format!(
+                    "{}{} |{}",
+                    style(Line(change.old_index())).dim(),
+                    style(Line(change.new_index())).dim(),
+                    s.apply_to(sign).bold(),
+                ));
+                for (emphasized, value) in change.iter_strings_lossy() {
+                    if emphasized {
+                        diff_display
+                            .push_str(&format!("{}", s.apply_to(value).underlined().on_black()));
+                    } else {
+                        diff_display.push_str(&format!("{}", s.apply_to(value)));
                     }
                 }
+                if change.missing_newline() {
+                    diff_display.push('\n');
+                }
             }
         }
+    }
 
-        if !diff_display.is_empty() {
-            return Err(diff_display);
-        }
-
-        Ok(())
+    if diff_display.is_empty() {
+        None
+    } else {
+        Some(diff_display)
     }
 }
 
@@ -242,3 +582,29 @@ impl tokio::io::AsyncWrite for SharedBufWriter {
         std::task::Poll::Ready(Ok(()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_difference_line() {
+        let expected = "a\nb\nc\n";
+        assert_eq!(None, first_difference_line(expected, expected));
+        assert_eq!(Some(2), first_difference_line(expected, "a\nx\nc\n"));
+        assert_eq!(Some(3), first_difference_line(expected, "a\nb\nc\nd\n"));
+        assert_eq!(Some(1), first_difference_line("", "a\n"));
+    }
+
+    #[test]
+    fn test_settings_parse() {
+        let settings: TestSettings =
+            toml::from_str("kind = \"render\"\ncomponent = \"tables/users.sql\"\n").unwrap();
+        assert_eq!(TestKind::Render, settings.kind);
+        assert_eq!(Some("tables/users.sql"), settings.component.as_deref());
+
+        let settings: TestSettings = toml::from_str("").unwrap();
+        assert_eq!(TestKind::Run, settings.kind);
+        assert!(toml::from_str::<TestSettings>("kind = \"render\"\nvars = \"x\"\n").is_err());
+    }
+}
@@ -1,16 +1,26 @@
 use crate::config::FolderPather;
+use crate::layout::MigrationFile;
 use anyhow::{Context, Result};
 use futures::TryStreamExt;
-use include_dir::{Dir, DirEntry};
+use include_dir::{include_dir, Dir, DirEntry};
 use opendal::services::Memory;
 use opendal::Operator;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
 
 use crate::store::pinner::Pinner;
 
 pub mod pinner;
 
+/// Components shipped with spawn, such as the backfill macros. Includes
+/// starting with `BUILTIN_PREFIX` are read from here instead of the project.
+static BUILTIN_COMPONENTS: Dir<'_> = include_dir!("./static/components");
+
+/// Prefix of includes that name a built-in component, as in
+/// `{% from "spawn/backfill.sql" import backfill %}`.
+pub const BUILTIN_PREFIX: &str = "spawn/";
+
 /// Filesystem-level status of a single migration.
 #[derive(Debug, Clone)]
 pub struct MigrationFileStatus {
@@ -19,7 +29,7 @@ pub struct MigrationFileStatus {
 }
 
 /// Get the filesystem status of a single migration.
-/// Returns the status indicating whether up.sql and lock.toml files exist.
+/// Returns the status indicating whether its script and lock file exist.
 pub async fn get_migration_fs_status(
     op: &Operator,
     pather: &FolderPather,
@@ -55,18 +65,25 @@ pub async fn list_migration_fs_status(
     let normalized_folder = migrations_folder
         .trim_start_matches("./")
         .trim_start_matches('/');
-    let migrations_prefix = if let Some(name) = migration_name {
+    let folder_prefix = format!("{}/", normalized_folder);
+    let migrations_prefix = match migration_name {
         // List only the specific migration folder
-        format!("{}/{}/", normalized_folder, name)
-    } else {
-        // List all migrations
-        format!("{}/", normalized_folder)
+        Some(name) if !pather.layout.flat => format!(
+            "{}/",
+            pather
+                .migration_folder(name)
+                .trim_start_matches("./")
+                .trim_start_matches('/')
+        ),
+        // List all migrations. Flat migrations are all directly in the
+        // migrations folder, so there is no narrower prefix for one.
+        _ => folder_prefix.clone(),
     };
 
     // Single recursive list - efficient for remote storage like S3
     let mut lister = op
         .lister_with(&migrations_prefix)
-        .recursive(true)
+        .recursive(!pather.layout.flat || pather.layout.shard.is_some())
         .await
         .context("listing migrations")?;
 
@@ -74,16 +91,18 @@ pub async fn list_migration_fs_status(
 
     while let Some(entry) = lister.try_next().await? {
         let path = entry.path().to_string();
-        let relative_path = path.strip_prefix(&migrations_prefix).unwrap_or(&path);
-
-        // When listing a specific migration, relative_path is just "up.sql" or "lock.toml".
-        // When listing all migrations, relative_path is "migration-name/up.sql" etc.
-        // Resolve the migration name and filename from the relative path.
-        let (name, filename) = match relative_path.split_once('/') {
-            Some((name, filename)) => (name, filename),
-            None if migration_name.is_some() => (migration_name.unwrap(), relative_path),
-            None => continue,
+        let Some(relative_path) = path.strip_prefix(&folder_prefix) else {
+            continue;
         };
+        // Resolve the migration and which of its files this is from the
+        // path relative to the migrations folder, such as
+        // "migration-name/up.sql".
+        let Some((name, file)) = pather.layout.parse_path(relative_path) else {
+            continue;
+        };
+        if migration_name.is_some_and(|m| m != name) {
+            continue;
+        }
 
         let status = result
             .entry(name.to_string())
@@ -92,20 +111,73 @@ pub async fn list_migration_fs_status(
                 has_lock_toml: false,
             });
 
-        if filename == "up.sql" {
-            status.has_up_sql = true;
-        } else if filename == "lock.toml" {
-            status.has_lock_toml = true;
+        match file {
+            MigrationFile::Script => status.has_up_sql = true,
+            MigrationFile::Lock => status.has_lock_toml = true,
         }
     }
 
     Ok(result)
 }
 
+/// Number of files and their combined size beneath a folder.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FolderUsage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Recursively count the files beneath `folder` and sum their sizes.
+/// A missing folder is reported as empty rather than an error.
+pub async fn folder_usage(op: &Operator, folder: &str) -> Result<FolderUsage> {
+    let normalized = folder.trim_start_matches("./").trim_start_matches('/');
+    let prefix = format!("{}/", normalized.trim_end_matches('/'));
+
+    let mut lister = op
+        .lister_with(&prefix)
+        .recursive(true)
+        .await
+        .context(format!("listing {}", &prefix))?;
+
+    let mut usage = FolderUsage::default();
+    while let Some(entry) = lister.try_next().await? {
+        if entry.path().ends_with('/') {
+            continue;
+        }
+        // Listing doesn't always populate content length, so stat each file.
+        let meta = op.stat(entry.path()).await?;
+        usage.files += 1;
+        usage.bytes += meta.content_length();
+    }
+
+    Ok(usage)
+}
+
+/// The components read through a [`Store`], such as by `{% include %}` or
+/// `read_file`. Clones share the same set.
+#[derive(Debug, Clone, Default)]
+pub struct LoadedComponents(Arc<Mutex<BTreeSet<String>>>);
+
+impl LoadedComponents {
+    fn record(&self, name: &str) {
+        self.0.lock().unwrap().insert(name.to_string());
+    }
+
+    /// Names of the components read so far, relative to the components
+    /// folder.
+    pub fn names(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
 pub struct Store {
     pinner: Box<dyn Pinner>,
     fs: Operator,
     pather: FolderPather,
+    /// Folder of the migration being rendered, used to resolve includes
+    /// starting with `./`.
+    migration_folder: Option<String>,
+    loaded: LoadedComponents,
 }
 
 impl Debug for Store {
@@ -116,17 +188,76 @@ impl Debug for Store {
 
 impl Store {
     pub fn new(pinner: Box<dyn Pinner>, fs: Operator, pather: FolderPather) -> Result<Store> {
-        Ok(Store { pinner, fs, pather })
+        Ok(Store {
+            pinner,
+            fs,
+            pather,
+            migration_folder: None,
+            loaded: LoadedComponents::default(),
+        })
+    }
+
+    pub fn pather(&self) -> &FolderPather {
+        &self.pather
+    }
+
+    /// Resolve names starting with `./` relative to this folder instead of
+    /// the components folder.
+    pub fn with_migration_folder(mut self, folder: &str) -> Self {
+        self.migration_folder = Some(folder.to_string());
+        self
     }
 
     pub async fn load_component(&self, name: &str) -> Result<Option<String>> {
-        let res = self.pinner.load(name, &self.fs).await?;
+        match self.load_component_bytes(name).await? {
+            Some(bytes) => Ok(Some(String::from_utf8(bytes)?)),
+            None => Ok(None),
+        }
+    }
 
-        Ok(res)
+    /// The components this store has read.
+    pub fn loaded_components(&self) -> LoadedComponents {
+        self.loaded.clone()
     }
 
     pub async fn load_component_bytes(&self, name: &str) -> Result<Option<Vec<u8>>> {
-        self.pinner.load_bytes(name, &self.fs).await
+        if let Some(builtin) = name.strip_prefix(BUILTIN_PREFIX) {
+            return Ok(BUILTIN_COMPONENTS
+                .get_file(builtin)
+                .map(|file| file.contents().to_vec()));
+        }
+        let Some(local) = name.strip_prefix("./") else {
+            if let Some((component, tag)) = name.rsplit_once('@') {
+                return self.load_tagged_component(component, tag).await;
+            }
+            let bytes = self.pinner.load_bytes(name, &self.fs).await?;
+            if bytes.is_some() {
+                self.loaded.record(name);
+            }
+            return Ok(bytes);
+        };
+
+        if local.split('/').any(|segment| segment == "..") {
+            return Err(anyhow::anyhow!(
+                "'{}' is outside of the migration folder",
+                name
+            ));
+        }
+        let folder = self.migration_folder.as_deref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "'{}' is relative to a migration, but none is being rendered",
+                name
+            )
+        })?;
+        self.pinner.load_local_bytes(local, folder, &self.fs).await
+    }
+
+    /// Loads `component` as it was when `tag` was created with `spawn pin
+    /// tag`, regardless of which pinner this store uses.
+    async fn load_tagged_component(&self, component: &str, tag: &str) -> Result<Option<Vec<u8>>> {
+        let pinned_folder = self.pather.pinned_folder();
+        let root = pinner::refs::resolve_ref(&self.fs, &pinned_folder, tag).await?;
+        pinner::refs::load_from_root(&self.fs, &pinned_folder, &root, component).await
     }
 
     pub async fn read_file_bytes(&self, path: &str) -> Result<Vec<u8>> {
@@ -143,23 +274,22 @@ impl Store {
         Ok(contents)
     }
 
+    /// Folders of the migrations with a script, oldest first, each ending
+    /// in `/`. Sharded migrations are found in their year or month folders.
     pub async fn list_migrations(&self) -> Result<Vec<String>> {
-        let mut migrations: Vec<String> = Vec::new();
-        let mut fs_lister = self
-            .fs
-            .lister(format!("{}/", &self.pather.migrations_folder()).as_ref())
-            .await?;
-        while let Some(entry) = fs_lister.try_next().await? {
-            let path = entry.path().to_string();
-            if path.ends_with("/") {
-                migrations.push(path)
-            }
-        }
-
-        // Sort migrations by name to ensure oldest to newest order
-        migrations.sort();
-
-        Ok(migrations)
+        // Keyed by name, so these are in oldest to newest order.
+        let migrations = list_migration_fs_status(&self.fs, &self.pather, None).await?;
+        Ok(migrations
+            .into_iter()
+            .filter(|(_, status)| status.has_up_sql)
+            .map(|(name, _)| {
+                let folder = self.pather.migration_folder(&name);
+                format!(
+                    "{}/",
+                    folder.trim_start_matches("./").trim_start_matches('/')
+                )
+            })
+            .collect())
     }
 }
 
@@ -348,6 +478,7 @@ mod tests {
         let pinner = Latest::new("").expect("Failed to create Latest pinner");
         let pather = FolderPather {
             spawn_folder: "".to_string(),
+            ..Default::default()
         };
 
         // Create the Store
@@ -404,6 +535,7 @@ mod tests {
 
         let pather = FolderPather {
             spawn_folder: "".to_string(),
+            ..Default::default()
         };
 
         // This migration should have both up.sql and lock.toml
@@ -428,6 +560,7 @@ mod tests {
 
         let pather = FolderPather {
             spawn_folder: "".to_string(),
+            ..Default::default()
         };
 
         let status = get_migration_fs_status(&op, &pather, "20240101000000-test")
@@ -438,6 +571,34 @@ mod tests {
         assert!(!status.has_lock_toml, "Migration should not have lock.toml");
     }
 
+    #[tokio::test]
+    async fn test_folder_usage() {
+        let mem_service = Memory::default();
+        let op = Operator::new(mem_service).unwrap().finish();
+
+        op.write("spawn/components/a.sql", "SELECT 1;")
+            .await
+            .unwrap();
+        op.write("spawn/components/nested/b.sql", "SELECT 22;")
+            .await
+            .unwrap();
+        op.write("spawn/migrations/m/up.sql", "ignored")
+            .await
+            .unwrap();
+
+        let usage = folder_usage(&op, "./spawn/components").await.unwrap();
+        assert_eq!(
+            usage,
+            FolderUsage {
+                files: 2,
+                bytes: 19
+            }
+        );
+
+        let missing = folder_usage(&op, "spawn/pinned").await.unwrap();
+        assert_eq!(missing, FolderUsage::default());
+    }
+
     #[tokio::test]
     async fn test_list_migration_fs_status() {
         // Test listing works for different spawn_folder formats, including
@@ -482,6 +643,7 @@ mod tests {
 
             let pather = FolderPather {
                 spawn_folder: spawn_folder.to_string(),
+                ..Default::default()
             };
 
             for filter in [None, Some("20240101-first")] {
@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use twox_hash::xxhash3_128;
+
+fn default_max_size_mb() -> u64 {
+    512
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Configuration for the local cache of pinned store objects, in the
+/// `[pin_cache]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PinCacheConfig {
+    /// Set to false to always read pinned objects from the store.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Folder to keep cached objects in. Defaults to `spawn-pin-cache` in the
+    /// system temporary folder.
+    #[serde(default)]
+    pub directory: Option<String>,
+    /// Once the cache grows past this size, the least recently written
+    /// objects are removed.
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+impl Default for PinCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            directory: None,
+            max_size_mb: default_max_size_mb(),
+        }
+    }
+}
+
+/// A read-through cache of pinned objects on local disk, keyed by hash.
+///
+/// Pinned objects are content-addressed and never change, so a cached copy
+/// is always valid as long as it still hashes to its name. This saves
+/// downloading the same objects again when the pinned store is remote.
+#[derive(Debug)]
+pub struct BlobCache {
+    directory: PathBuf,
+    max_bytes: u64,
+    /// Approximate size of the cache, tracked so that eviction only needs to
+    /// scan the folder once the limit is passed.
+    size: Mutex<u64>,
+}
+
+impl BlobCache {
+    pub fn new(cfg: &PinCacheConfig) -> Result<Self> {
+        let directory = match &cfg.directory {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::temp_dir().join("spawn-pin-cache"),
+        };
+        std::fs::create_dir_all(&directory).context(format!(
+            "could not create pin cache folder {}",
+            directory.display()
+        ))?;
+        let size = cached_files(&directory)?.iter().map(|f| f.bytes).sum();
+
+        Ok(Self {
+            directory,
+            max_bytes: cfg.max_size_mb * 1024 * 1024,
+            size: Mutex::new(size),
+        })
+    }
+
+    fn path(&self, hash: &str) -> Result<PathBuf> {
+        Ok(self.directory.join(super::hash_to_path(hash)?))
+    }
+
+    /// Returns the cached object, or None if it is missing or no longer
+    /// matches its hash.
+    pub async fn get(&self, hash: &str) -> Option<Vec<u8>> {
+        let bytes = tokio::fs::read(self.path(hash).ok()?).await.ok()?;
+        if format!("{:032x}", xxhash3_128::Hasher::oneshot(&bytes)) != hash {
+            return None;
+        }
+        Some(bytes)
+    }
+
+    /// Stores an object, evicting older objects if the cache is over its
+    /// size limit.
+    pub async fn put(&self, hash: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.path(hash)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write to a temporary name first so concurrent readers never see a
+        // partial object.
+        let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+
+        let over_limit = {
+            let mut size = self.size.lock().unwrap();
+            *size += bytes.len() as u64;
+            *size > self.max_bytes
+        };
+        if over_limit {
+            self.evict()?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the oldest objects until the cache is within its limit.
+    fn evict(&self) -> Result<()> {
+        let mut files = cached_files(&self.directory)?;
+        files.sort_by_key(|f| f.modified);
+
+        let mut size: u64 = files.iter().map(|f| f.bytes).sum();
+        for file in files {
+            if size <= self.max_bytes {
+                break;
+            }
+            if std::fs::remove_file(&file.path).is_ok() {
+                size -= file.bytes;
+            }
+        }
+        *self.size.lock().unwrap() = size;
+
+        Ok(())
+    }
+}
+
+struct CachedFile {
+    path: PathBuf,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+/// Lists the objects in the cache, which are stored two folders deep.
+fn cached_files(directory: &Path) -> Result<Vec<CachedFile>> {
+    let mut files = Vec::new();
+    for prefix in std::fs::read_dir(directory)? {
+        let prefix = prefix?;
+        if !prefix.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(prefix.path())? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_file() {
+                files.push(CachedFile {
+                    path: entry.path(),
+                    bytes: meta.len(),
+                    modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_of(bytes: &[u8]) -> String {
+        format!("{:032x}", xxhash3_128::Hasher::oneshot(bytes))
+    }
+
+    fn cache_in(dir: &Path, max_size_mb: u64) -> BlobCache {
+        BlobCache::new(&PinCacheConfig {
+            directory: Some(dir.to_string_lossy().into_owned()),
+            max_size_mb,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_returns_what_was_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(dir.path(), 1);
+        let hash = hash_of(b"SELECT 1;");
+
+        assert_eq!(None, cache.get(&hash).await);
+        cache.put(&hash, b"SELECT 1;").await.unwrap();
+        assert_eq!(Some(b"SELECT 1;".to_vec()), cache.get(&hash).await);
+    }
+
+    #[tokio::test]
+    async fn test_get_ignores_corrupted_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(dir.path(), 1);
+        let hash = hash_of(b"SELECT 1;");
+
+        cache.put(&hash, b"SELECT 2;").await.unwrap();
+        assert_eq!(None, cache.get(&hash).await);
+    }
+
+    #[tokio::test]
+    async fn test_put_evicts_when_over_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BlobCache {
+            max_bytes: 1000,
+            ..cache_in(dir.path(), 1)
+        };
+
+        let first = vec![b'a'; 600];
+        let second = vec![b'b'; 600];
+        cache.put(&hash_of(&first), &first).await.unwrap();
+        cache.put(&hash_of(&second), &second).await.unwrap();
+
+        let remaining = cached_files(dir.path()).unwrap();
+        assert_eq!(1, remaining.len());
+        assert!(*cache.size.lock().unwrap() <= 1000);
+    }
+}
@@ -26,7 +26,10 @@ impl Latest {
 impl Pinner for Latest {
     /// Returns the file from the live file system if it exists.
     async fn load_bytes(&self, name: &str, object_store: &Operator) -> Result<Option<Vec<u8>>> {
-        let path_str = format!("{}/components/{}", self.store_path, name);
+        let path_str = match name.strip_prefix(crate::packages::PACKAGE_PREFIX) {
+            Some(package_file) => format!("{}/vendor/{}", self.store_path, package_file),
+            None => format!("{}/components/{}", self.store_path, name),
+        };
 
         let get_result = object_store.read(&path_str).await?;
         let bytes = get_result.to_bytes();
@@ -34,6 +37,22 @@ impl Pinner for Latest {
         Ok(Some(bytes.to_vec()))
     }
 
+    /// Returns the file from the live migration folder if it exists.
+    async fn load_local_bytes(
+        &self,
+        name: &str,
+        migration_folder: &str,
+        object_store: &Operator,
+    ) -> Result<Option<Vec<u8>>> {
+        let path_str = format!("{}/{}", migration_folder, name);
+
+        match object_store.read(&path_str).await {
+            Ok(get_result) => Ok(Some(get_result.to_bytes().to_vec())),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     async fn snapshot(&mut self, _object_store: &Operator) -> Result<String> {
         Err(anyhow::anyhow!("Latest pinner does not support pinning"))
     }
@@ -5,10 +5,15 @@ use opendal::Operator;
 
 use serde::{Deserialize, Serialize};
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
+
+use cache::BlobCache;
 use twox_hash::xxhash3_128;
 
+pub mod cache;
 pub mod latest;
+pub mod refs;
 pub mod spawn;
 
 #[async_trait]
@@ -22,6 +27,15 @@ pub trait Pinner: Debug + Send + Sync {
         }
     }
 
+    /// Load a file that lives alongside the migration being rendered rather
+    /// than in components. `name` is relative to `migration_folder`.
+    async fn load_local_bytes(
+        &self,
+        name: &str,
+        migration_folder: &str,
+        fs: &Operator,
+    ) -> Result<Option<Vec<u8>>>;
+
     async fn snapshot(&mut self, fs: &Operator) -> Result<String>;
 }
 
@@ -43,13 +57,31 @@ pub struct Entry {
     pub name: String,
 }
 
+/// The name `contents` is pinned under.
+fn object_hash(contents: &[u8]) -> String {
+    format!("{:032x}", xxhash3_128::Hasher::oneshot(contents))
+}
+
+/// Fails unless `contents` are those of the object pinned as `hash`, so a
+/// pinned object edited in place is not used.
+fn check_object(hash: &str, contents: &[u8]) -> Result<()> {
+    let actual = object_hash(contents);
+    if actual != hash {
+        return Err(anyhow::anyhow!(
+            "pinned object {} has been modified: its contents hash to {}. Restore it, or pin the migration again",
+            hash,
+            actual
+        ));
+    }
+    Ok(())
+}
+
 pub(crate) async fn pin_contents(
     fs: &Operator,
     store_path: &str,
     contents: &[u8],
 ) -> Result<String> {
-    let hash = xxhash3_128::Hasher::oneshot(contents);
-    let hash = format!("{:032x}", hash);
+    let hash = object_hash(contents);
     let dir = format!("{}/{}", store_path, hash_to_path(&hash)?);
 
     fs.write(&dir, contents.to_vec()).await?;
@@ -69,12 +101,8 @@ pub(crate) fn hash_to_path(hash: &str) -> Result<String> {
 
 /// Reads the file corresponding to the hash from the given base path.
 pub(crate) async fn read_hash_file(fs: &Operator, base_path: &str, hash: &str) -> Result<String> {
-    let relative_path = hash_to_path(hash)?;
-    let file_path = format!("{}/{}", base_path, relative_path);
-
-    let get_result = fs.read(&file_path).await?;
-    let bytes = get_result.to_bytes();
-    let contents = String::from_utf8(bytes.to_vec())?;
+    let bytes = read_hash_bytes(fs, base_path, hash).await?;
+    let contents = String::from_utf8(bytes)?;
 
     Ok(contents)
 }
@@ -88,6 +116,37 @@ pub(crate) async fn snapshot(fs: &Operator, store_path: &str, mut prefix: &str)
         prefix = &fixed;
     }
 
+    let list_result = list_folder(fs, prefix).await?;
+    snapshot_entries(fs, store_path, list_result).await
+}
+
+/// Snapshots the files kept in a migration's own folder, so that includes
+/// relative to the migration (`{% include "./partials/x.sql" %}`) are pinned
+/// along with components. Files named in `exclude`, such as the migration
+/// script and lock file, are skipped. Returns None when the folder holds
+/// nothing else.
+pub(crate) async fn snapshot_migration_folder(
+    fs: &Operator,
+    store_path: &str,
+    folder: &str,
+    exclude: &[&str],
+) -> Result<Option<String>> {
+    let prefix = format!("{}/", folder.trim_end_matches('/'));
+    let list_result: Vec<opendal::Entry> = list_folder(fs, &prefix)
+        .await?
+        .into_iter()
+        .filter(|entry| !exclude.contains(&entry.name()))
+        .collect();
+
+    if list_result.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(snapshot_entries(fs, store_path, list_result).await?))
+}
+
+/// Lists the direct children of `prefix`, excluding the folder itself.
+async fn list_folder(fs: &Operator, prefix: &str) -> Result<Vec<opendal::Entry>> {
     let mut fs_lister = fs.lister(prefix).await?;
     let mut list_result: Vec<opendal::Entry> = Vec::new();
     while let Some(entry) = fs_lister.try_next().await? {
@@ -96,7 +155,65 @@ pub(crate) async fn snapshot(fs: &Operator, store_path: &str, mut prefix: &str)
         }
         list_result.push(entry);
     }
+    Ok(list_result)
+}
+
+/// Like [`snapshot`], but with the vendored packages beneath `packages`
+/// added to the tree as a `pkg` folder, where includes find them. Nothing is
+/// added when there are no packages, so the hash is the same as
+/// [`snapshot`]'s.
+pub(crate) async fn snapshot_with_packages(
+    fs: &Operator,
+    store_path: &str,
+    prefix: &str,
+    packages: &str,
+) -> Result<String> {
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    let mut entries = pin_entries(fs, store_path, list_folder(fs, &prefix).await?).await?;
+
+    let packages = format!("{}/", packages.trim_end_matches('/'));
+    if !list_folder(fs, &packages).await?.is_empty() {
+        let folder = crate::packages::PACKAGE_PREFIX;
+        if entries.iter().any(|(name, _)| name == folder) {
+            return Err(anyhow::anyhow!(
+                "components/{} is reserved for packages",
+                folder
+            ));
+        }
+        let hash = Box::pin(snapshot(fs, store_path, &packages))
+            .await
+            .context("failed to snapshot packages")?;
+        entries.push((
+            folder.to_string(),
+            Entry {
+                kind: EntryKind::Tree,
+                name: folder.trim_end_matches('/').to_string(),
+                hash,
+            },
+        ));
+    }
+
+    pin_tree(fs, store_path, entries).await
+}
 
+/// Pins each entry (recursing into folders) and returns the hash of the tree
+/// that lists them.
+async fn snapshot_entries(
+    fs: &Operator,
+    store_path: &str,
+    list_result: Vec<opendal::Entry>,
+) -> Result<String> {
+    let entries = pin_entries(fs, store_path, list_result).await?;
+    pin_tree(fs, store_path, entries).await
+}
+
+/// Pins each entry, recursing into folders. Each is returned with its
+/// listed name, which ends in `/` for folders.
+async fn pin_entries(
+    fs: &Operator,
+    store_path: &str,
+    list_result: Vec<opendal::Entry>,
+) -> Result<Vec<(String, Entry)>> {
     let mut entries = Vec::new();
 
     for entry in list_result {
@@ -135,6 +252,15 @@ pub(crate) async fn snapshot(fs: &Operator, store_path: &str, mut prefix: &str)
         }
     }
 
+    Ok(entries)
+}
+
+/// Pins the tree listing `entries` and returns its hash.
+async fn pin_tree(
+    fs: &Operator,
+    store_path: &str,
+    mut entries: Vec<(String, Entry)>,
+) -> Result<String> {
     // Sort entries by name for consistent ordering, and then return a hash for
     // this node.
     let mut tree = Tree::default();
@@ -149,8 +275,260 @@ pub(crate) async fn snapshot(fs: &Operator, store_path: &str, mut prefix: &str)
     Ok(hash)
 }
 
+/// Maps each file in a pinned tree to the hash of its contents, keyed by its
+/// path relative to the tree root.
+pub async fn pinned_file_hashes(
+    fs: &Operator,
+    store_path: &str,
+    root_hash: &str,
+) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    collect_tree_hashes(fs, store_path, root_hash, "", &mut files).await?;
+    Ok(files)
+}
+
+async fn collect_tree_hashes(
+    fs: &Operator,
+    store_path: &str,
+    hash: &str,
+    base_path: &str,
+    files: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let contents = read_hash_file(fs, store_path, hash)
+        .await
+        .context("cannot read tree")?;
+    let tree: Tree = toml::from_str(&contents).context("failed to parse tree TOML")?;
+
+    for entry in tree.entries {
+        let name = if base_path.is_empty() {
+            entry.name
+        } else {
+            format!("{}/{}", base_path, entry.name)
+        };
+        match entry.kind {
+            EntryKind::Blob => {
+                files.insert(name, entry.hash);
+            }
+            EntryKind::Tree => {
+                Box::pin(collect_tree_hashes(
+                    fs,
+                    store_path,
+                    &entry.hash,
+                    &name,
+                    files,
+                ))
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps each file currently beneath `folder` to the hash it would be pinned
+/// under, keyed by its path relative to `folder`. Top level files named in
+/// `exclude` are skipped.
+pub async fn live_file_hashes(
+    fs: &Operator,
+    folder: &str,
+    exclude: &[&str],
+) -> Result<BTreeMap<String, String>> {
+    live_file_hashes_with(fs, folder, exclude, object_hash).await
+}
+
+/// As `live_file_hashes`, hashing each file's contents with `hash`.
+pub async fn live_file_hashes_with(
+    fs: &Operator,
+    folder: &str,
+    exclude: &[&str],
+    hash: fn(&[u8]) -> String,
+) -> Result<BTreeMap<String, String>> {
+    // opendal strips leading "./" and "/" from listed paths.
+    let normalized = folder.trim_start_matches("./").trim_start_matches('/');
+    let prefix = format!("{}/", normalized.trim_end_matches('/'));
+
+    let mut lister = fs.lister_with(&prefix).recursive(true).await?;
+    let mut files = BTreeMap::new();
+    while let Some(entry) = lister.try_next().await? {
+        let Some(name) = entry.path().strip_prefix(&prefix) else {
+            continue;
+        };
+        if name.is_empty() || name.ends_with('/') || exclude.contains(&name) {
+            continue;
+        }
+        let contents = fs.read(entry.path()).await?.to_bytes();
+        files.insert(name.to_string(), hash(&contents));
+    }
+
+    Ok(files)
+}
+
+/// Reads a pinned object, going through the local cache when one is given.
+/// Cache failures are not fatal; the object is read from the store instead.
+pub(crate) async fn read_object(
+    fs: &Operator,
+    store_path: &str,
+    hash: &str,
+    cache: Option<&BlobCache>,
+) -> Result<Vec<u8>> {
+    if let Some(bytes) = match cache {
+        Some(cache) => cache.get(hash).await,
+        None => None,
+    } {
+        check_object(hash, &bytes)?;
+        return Ok(bytes);
+    }
+
+    let bytes = read_hash_bytes(fs, store_path, hash).await?;
+    if let Some(cache) = cache {
+        let _ = cache.put(hash, &bytes).await;
+    }
+    Ok(bytes)
+}
+
+/// Reads the raw bytes of a pinned blob, checking they still hash to
+/// `hash`.
+pub async fn read_hash_bytes(fs: &Operator, store_path: &str, hash: &str) -> Result<Vec<u8>> {
+    let file_path = format!("{}/{}", store_path, hash_to_path(hash)?);
+    let bytes = fs.read(&file_path).await?.to_bytes().to_vec();
+    check_object(hash, &bytes)?;
+    Ok(bytes)
+}
+
+/// SHA-256 over the path and contents of every file in the pinned tree
+/// `root_hash`. Object names are not collision resistant, so this is what
+/// signatures vouch for.
+pub async fn pinned_tree_sha256(
+    fs: &Operator,
+    store_path: &str,
+    root_hash: &str,
+) -> Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut digest = Sha256::new();
+    for (name, hash) in pinned_file_hashes(fs, store_path, root_hash).await? {
+        let contents = read_hash_bytes(fs, store_path, &hash).await?;
+        digest.update(name.as_bytes());
+        digest.update(b"\0");
+        digest.update(format!("{:x}\n", Sha256::digest(&contents)).as_bytes());
+    }
+    Ok(format!("{:x}", digest.finalize()))
+}
+
+/// Size and location of a single pinned blob.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlobSize {
+    pub hash: String,
+    /// A path the blob is pinned under. Identical content pinned under
+    /// several paths is stored once, so only the first path seen is kept.
+    pub name: String,
+    pub bytes: u64,
+}
+
+/// What is reachable from a set of pinned roots.
+#[derive(Debug, Default)]
+pub struct ReachableObjects {
+    /// Hashes of every tree and blob reachable from the roots
+    pub objects: HashSet<String>,
+    /// Bytes that would be stored if every file of every root were kept
+    /// separately, i.e. without content addressing
+    pub logical_bytes: u64,
+    /// Every reachable blob, largest first
+    pub blobs: Vec<BlobSize>,
+}
+
+/// Walks every tree reachable from `roots`, recording each object once and
+/// summing the size of each root's files.
+pub async fn reachable_objects(
+    fs: &Operator,
+    store_path: &str,
+    roots: &[String],
+) -> Result<ReachableObjects> {
+    let mut walk = StoreWalk {
+        fs,
+        store_path,
+        tree_bytes: HashMap::new(),
+        blobs: HashMap::new(),
+        objects: HashSet::new(),
+    };
+
+    let mut logical_bytes = 0;
+    for root in roots {
+        logical_bytes += walk.tree(root, "").await?;
+    }
+
+    let mut blobs: Vec<BlobSize> = walk.blobs.into_values().collect();
+    blobs.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.hash.cmp(&b.hash)));
+
+    Ok(ReachableObjects {
+        objects: walk.objects,
+        logical_bytes,
+        blobs,
+    })
+}
+
+struct StoreWalk<'a> {
+    fs: &'a Operator,
+    store_path: &'a str,
+    /// Logical size of each tree already walked
+    tree_bytes: HashMap<String, u64>,
+    blobs: HashMap<String, BlobSize>,
+    objects: HashSet<String>,
+}
+
+impl StoreWalk<'_> {
+    /// Returns the logical size of the tree, walking it on first sight.
+    async fn tree(&mut self, hash: &str, base_path: &str) -> Result<u64> {
+        if let Some(bytes) = self.tree_bytes.get(hash) {
+            return Ok(*bytes);
+        }
+        self.objects.insert(hash.to_string());
+
+        let contents = read_hash_file(self.fs, self.store_path, hash)
+            .await
+            .context(format!("cannot read tree {}", hash))?;
+        let tree: Tree = toml::from_str(&contents).context("failed to parse tree TOML")?;
+
+        let mut bytes = 0;
+        for entry in tree.entries {
+            let name = if base_path.is_empty() {
+                entry.name
+            } else {
+                format!("{}/{}", base_path, entry.name)
+            };
+            bytes += match entry.kind {
+                EntryKind::Blob => self.blob(&entry.hash, name).await?,
+                EntryKind::Tree => Box::pin(self.tree(&entry.hash, &name)).await?,
+            };
+        }
+
+        self.tree_bytes.insert(hash.to_string(), bytes);
+        Ok(bytes)
+    }
+
+    async fn blob(&mut self, hash: &str, name: String) -> Result<u64> {
+        if let Some(blob) = self.blobs.get(hash) {
+            return Ok(blob.bytes);
+        }
+        self.objects.insert(hash.to_string());
+
+        let path = format!("{}/{}", self.store_path, hash_to_path(hash)?);
+        let bytes = self.fs.stat(&path).await?.content_length();
+        self.blobs.insert(
+            hash.to_string(),
+            BlobSize {
+                hash: hash.to_string(),
+                name,
+                bytes,
+            },
+        );
+        Ok(bytes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::config::FolderPather;
     use crate::store;
 
     use super::*;
@@ -182,4 +560,106 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_snapshot_migration_folder_skips_script_and_lock() -> Result<()> {
+        let op = Operator::new(opendal::services::Memory::default())?.finish();
+        let folder = "migrations/20240101000000-test";
+        let pather = FolderPather::default();
+        let exclude = pather.migration_folder_excludes();
+        op.write(&format!("{}/up.sql", folder), "BEGIN;").await?;
+        op.write(&format!("{}/lock.toml", folder), "pin = \"abc\"")
+            .await?;
+
+        assert_eq!(
+            None,
+            snapshot_migration_folder(&op, "store", folder, &exclude).await?
+        );
+
+        op.write(&format!("{}/partials/a.sql", folder), "SELECT 1;")
+            .await?;
+        let local = snapshot_migration_folder(&op, "store", folder, &exclude)
+            .await?
+            .expect("partials should be pinned");
+
+        let tree: Tree = toml::from_str(&read_hash_file(&op, "store", &local).await?)?;
+        assert_eq!(1, tree.entries.len());
+        assert_eq!("partials", tree.entries[0].name);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reachable_objects_counts_shared_content_once() -> Result<()> {
+        let op = Operator::new(opendal::services::Memory::default())?.finish();
+        op.write("components/a.sql", "SELECT 1;").await?;
+        op.write("components/b.sql", "SELECT 1;").await?;
+        op.write("components/big.sql", "SELECT 1234567890;").await?;
+        let first = snapshot(&op, "store", "components/").await?;
+
+        op.delete("components/big.sql").await?;
+        let second = snapshot(&op, "store", "components/").await?;
+
+        let reachable = reachable_objects(&op, "store", &[first, second]).await?;
+
+        // Two trees plus two distinct blobs.
+        assert_eq!(4, reachable.objects.len());
+        // First root: 9 + 9 + 18 bytes, second root: 9 + 9 bytes.
+        assert_eq!(54, reachable.logical_bytes);
+        assert_eq!(
+            vec![18, 9],
+            reachable.blobs.iter().map(|b| b.bytes).collect::<Vec<_>>()
+        );
+        assert_eq!("big.sql", reachable.blobs[0].name);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_object_fills_cache() -> Result<()> {
+        let op = Operator::new(opendal::services::Memory::default())?.finish();
+        op.write("components/a.sql", "SELECT 1;").await?;
+        snapshot(&op, "store/", "components/").await?;
+        let hash = format!("{:032x}", xxhash3_128::Hasher::oneshot(b"SELECT 1;"));
+
+        let dir = tempfile::tempdir()?;
+        let cache = BlobCache::new(&cache::PinCacheConfig {
+            directory: Some(dir.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        })?;
+
+        let bytes = read_object(&op, "store", &hash, Some(&cache)).await?;
+        assert_eq!(b"SELECT 1;".to_vec(), bytes);
+
+        // Once cached, the object no longer needs to be in the store.
+        op.remove_all("store/").await?;
+        let bytes = read_object(&op, "store", &hash, Some(&cache)).await?;
+        assert_eq!(b"SELECT 1;".to_vec(), bytes);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_modified_objects_are_rejected() -> Result<()> {
+        let op = Operator::new(opendal::services::Memory::default())?.finish();
+        op.write("components/a.sql", "SELECT 1;").await?;
+        let root = snapshot(&op, "store/", "components/").await?;
+        let digest = pinned_tree_sha256(&op, "store", &root).await?;
+        let hash = object_hash(b"SELECT 1;");
+        assert_eq!(
+            b"SELECT 1;".to_vec(),
+            read_hash_bytes(&op, "store", &hash).await?
+        );
+
+        let path = format!("store/{}", hash_to_path(&hash)?);
+        op.write(&path, "DROP TABLE users;").await?;
+        let err = read_hash_bytes(&op, "store", &hash).await.unwrap_err();
+        assert!(err.to_string().contains("has been modified"), "{}", err);
+        assert!(pinned_tree_sha256(&op, "store", &root).await.is_err());
+
+        op.write(&path, "SELECT 1;").await?;
+        assert_eq!(digest, pinned_tree_sha256(&op, "store", &root).await?);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,190 @@
+//! Named references to pinned roots. A tag such as `v1.2.0` is stored as a
+//! small file under `refs/` in the pinned store holding the root hash of a
+//! components snapshot. Tags cannot be moved once written, so anything that
+//! refers to one always sees the same components.
+
+use super::{hash_to_path, read_object, EntryKind, Tree};
+use anyhow::{anyhow, Context, Result};
+use futures::TryStreamExt;
+use opendal::Operator;
+use std::collections::BTreeMap;
+
+/// Folder of the pinned store that holds tags. Object folders are named by
+/// two hex characters, so this cannot collide with them.
+pub const REFS_FOLDER: &str = "refs";
+
+fn ref_path(store_path: &str, tag: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        store_path.trim_end_matches('/'),
+        REFS_FOLDER,
+        tag
+    )
+}
+
+/// Checks that `tag` can be used as a tag name: letters, digits, `.`, `-`
+/// and `_`, starting with a letter or digit.
+pub fn validate_tag(tag: &str) -> Result<()> {
+    let valid = tag
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric())
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if !valid {
+        return Err(anyhow!(
+            "invalid tag '{}': tags may only contain letters, digits, '.', '-' and '_', and must start with a letter or digit",
+            tag
+        ));
+    }
+    Ok(())
+}
+
+/// Records `tag` as referring to the pinned root `hash`. Fails if the tag
+/// already refers to a different root.
+pub async fn write_ref(fs: &Operator, store_path: &str, tag: &str, hash: &str) -> Result<()> {
+    validate_tag(tag)?;
+    hash_to_path(hash)?;
+    if let Some(existing) = read_ref(fs, store_path, tag).await? {
+        if existing == hash {
+            return Ok(());
+        }
+        return Err(anyhow!(
+            "tag '{}' already refers to {}, tags cannot be moved",
+            tag,
+            existing
+        ));
+    }
+
+    fs.write(&ref_path(store_path, tag), format!("{}\n", hash))
+        .await
+        .context(format!("could not write tag '{}'", tag))?;
+    Ok(())
+}
+
+/// The root hash `tag` refers to, or None if there is no such tag.
+pub async fn read_ref(fs: &Operator, store_path: &str, tag: &str) -> Result<Option<String>> {
+    validate_tag(tag)?;
+    match fs.read(&ref_path(store_path, tag)).await {
+        Ok(contents) => {
+            let contents = String::from_utf8(contents.to_bytes().to_vec())?;
+            Ok(Some(contents.trim().to_string()))
+        }
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("could not read tag '{}'", tag)),
+    }
+}
+
+/// Like [`read_ref`], but a missing tag is an error.
+pub async fn resolve_ref(fs: &Operator, store_path: &str, tag: &str) -> Result<String> {
+    read_ref(fs, store_path, tag)
+        .await?
+        .ok_or_else(|| anyhow!("no tag named '{}' in the pinned store", tag))
+}
+
+/// Every tag in the store, mapped to the root hash it refers to.
+pub async fn list_refs(fs: &Operator, store_path: &str) -> Result<BTreeMap<String, String>> {
+    let folder = format!("{}/{}/", store_path.trim_end_matches('/'), REFS_FOLDER);
+    // opendal strips leading "./" and "/" from listed paths.
+    let prefix = folder.trim_start_matches("./").trim_start_matches('/');
+
+    let mut lister = fs.lister(&folder).await.context("listing tags")?;
+    let mut refs = BTreeMap::new();
+    while let Some(entry) = lister.try_next().await? {
+        let Some(tag) = entry.path().strip_prefix(prefix) else {
+            continue;
+        };
+        if tag.is_empty() || tag.ends_with('/') {
+            continue;
+        }
+        let contents = fs.read(entry.path()).await?.to_bytes();
+        let hash = String::from_utf8(contents.to_vec())?.trim().to_string();
+        refs.insert(tag.to_string(), hash);
+    }
+    Ok(refs)
+}
+
+/// Reads the file at `name`, a path relative to the root of the pinned tree
+/// `root_hash`. Returns None if the tree has no such file.
+pub async fn load_from_root(
+    fs: &Operator,
+    store_path: &str,
+    root_hash: &str,
+    name: &str,
+) -> Result<Option<Vec<u8>>> {
+    let mut hash = root_hash.to_string();
+    let mut segments = name.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        let contents = String::from_utf8(read_object(fs, store_path, &hash, None).await?)?;
+        let tree: Tree = toml::from_str(&contents).context("failed to parse tree TOML")?;
+        let want_blob = segments.peek().is_none();
+        let Some(entry) = tree.entries.into_iter().find(|entry| {
+            entry.name == segment && matches!(entry.kind, EntryKind::Blob) == want_blob
+        }) else {
+            return Ok(None);
+        };
+        hash = entry.hash;
+    }
+    Ok(Some(read_object(fs, store_path, &hash, None).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::pinner::snapshot;
+
+    #[test]
+    fn test_validate_tag() {
+        for tag in ["v1.2.0", "release_3", "2024-01"] {
+            assert!(validate_tag(tag).is_ok(), "{}", tag);
+        }
+        for tag in ["", ".hidden", "-v1", "a/b", "v1@2", "v 1"] {
+            assert!(validate_tag(tag).is_err(), "{}", tag);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tags_resolve_and_cannot_move() -> Result<()> {
+        let op = Operator::new(opendal::services::Memory::default())?.finish();
+        op.write("components/tables/users.sql", "CREATE TABLE users;")
+            .await?;
+        let first = snapshot(&op, "pinned", "components/").await?;
+
+        write_ref(&op, "pinned", "v1", &first).await?;
+        // Writing the same root again is fine.
+        write_ref(&op, "pinned", "v1", &first).await?;
+
+        op.write("components/tables/users.sql", "CREATE TABLE people;")
+            .await?;
+        let second = snapshot(&op, "pinned", "components/").await?;
+        let err = write_ref(&op, "pinned", "v1", &second)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("cannot be moved"), "{}", err);
+        write_ref(&op, "pinned", "v2", &second).await?;
+
+        let refs = list_refs(&op, "pinned").await?;
+        assert_eq!(
+            vec![("v1", first.as_str()), ("v2", second.as_str())],
+            refs.iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect::<Vec<_>>()
+        );
+
+        let root = resolve_ref(&op, "pinned", "v1").await?;
+        assert_eq!(
+            Some(b"CREATE TABLE users;".to_vec()),
+            load_from_root(&op, "pinned", &root, "tables/users.sql").await?
+        );
+        assert_eq!(
+            None,
+            load_from_root(&op, "pinned", &root, "tables/missing.sql").await?
+        );
+        assert_eq!(None, load_from_root(&op, "pinned", &root, "tables").await?);
+        assert!(resolve_ref(&op, "pinned", "v3").await.is_err());
+
+        Ok(())
+    }
+}
@@ -1,15 +1,21 @@
+use super::cache::BlobCache;
 use super::Pinner;
 use anyhow::Result;
 use anyhow::{anyhow, Context};
 use async_trait::async_trait;
 use opendal::Operator;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Spawn {
+    /// Maps each pinned file name to the hash of its contents.
     files: Option<HashMap<String, String>>,
     pin_path: String,
     source_path: String,
+    /// Folder of vendored packages, snapshotted along with components
+    packages_path: Option<String>,
+    cache: Option<Arc<BlobCache>>,
 }
 
 impl Spawn {
@@ -18,39 +24,102 @@ impl Spawn {
             files: None,
             pin_path,
             source_path,
+            packages_path: None,
+            cache: None,
         };
 
         Ok(store)
     }
 
+    /// Snapshot the packages vendored in `packages_path` too, so includes
+    /// of `pkg/<package>/...` are pinned with the components.
+    pub fn with_packages_path(mut self, packages_path: String) -> Self {
+        self.packages_path = Some(packages_path);
+        self
+    }
+
     pub async fn new_with_root_hash(
         pin_path: String,
         source_path: String,
         root_hash: &str,
         object_store: &Operator,
+        cache: Option<Arc<BlobCache>>,
     ) -> Result<Self> {
         let mut files = HashMap::new();
-        Self::read_root_hash(object_store, &pin_path, &mut files, "", root_hash).await?;
+        Self::read_root_hash(
+            object_store,
+            &pin_path,
+            cache.as_deref(),
+            &mut files,
+            "",
+            root_hash,
+        )
+        .await?;
 
         let store = Self {
             files: Some(files),
             pin_path: pin_path.clone(),
             source_path,
+            packages_path: None,
+            cache,
         };
 
         Ok(store)
     }
 
+    /// Adds the pinned files from a migration's own folder, keyed as
+    /// `./<name>` so they cannot collide with components.
+    pub async fn load_local_tree(
+        &mut self,
+        local_hash: &str,
+        object_store: &Operator,
+    ) -> Result<()> {
+        let files = self
+            .files
+            .as_mut()
+            .ok_or(anyhow!("files not initialized, was a root hash specified?"))?;
+        Self::read_root_hash(
+            object_store,
+            &self.pin_path,
+            self.cache.as_deref(),
+            files,
+            ".",
+            local_hash,
+        )
+        .await
+    }
+
+    /// Reads a pinned file by its key in the files map.
+    async fn read_pinned(&self, key: &str, object_store: &Operator) -> Result<Option<Vec<u8>>> {
+        // Borrow files from inside self.files, if not none:
+        let files = self
+            .files
+            .as_ref()
+            .ok_or(anyhow!("files not initialized, was a root hash specified?"))?;
+
+        match files.get(key) {
+            Some(hash) => {
+                let bytes =
+                    super::read_object(object_store, &self.pin_path, hash, self.cache.as_deref())
+                        .await;
+                Ok(bytes.ok())
+            }
+            None => Ok(None),
+        }
+    }
+
     async fn read_root_hash(
         object_store: &Operator,
         store_path: &str,
+        cache: Option<&BlobCache>,
         files: &mut HashMap<String, String>,
         base_path: &str,
         root_hash: &str,
     ) -> Result<()> {
-        let contents = super::read_hash_file(object_store, store_path, root_hash)
+        let contents = super::read_object(object_store, store_path, root_hash, cache)
             .await
             .context("cannot read root file")?;
+        let contents = String::from_utf8(contents)?;
         let tree: super::Tree = toml::from_str(&contents).context("failed to parse tree TOML")?;
 
         for (_, entry) in tree.entries.iter().enumerate() {
@@ -61,8 +130,7 @@ impl Spawn {
                     } else {
                         format!("{}/{}", base_path, &entry.name)
                     };
-                    let full_path = format!("{}/{}", store_path, super::hash_to_path(&entry.hash)?);
-                    files.insert(full_name, full_path);
+                    files.insert(full_name, entry.hash.clone());
                 }
                 super::EntryKind::Tree => {
                     let new_base = if base_path.is_empty() {
@@ -73,6 +141,7 @@ impl Spawn {
                     Box::pin(Self::read_root_hash(
                         object_store,
                         store_path,
+                        cache,
                         files,
                         &new_base,
                         &entry.hash,
@@ -90,26 +159,31 @@ impl Spawn {
 impl Pinner for Spawn {
     /// Returns the file from the store if it exists.
     async fn load_bytes(&self, name: &str, object_store: &Operator) -> Result<Option<Vec<u8>>> {
-        // Borrow files from inside self.files, if not none:
-        let files = self
-            .files
-            .as_ref()
-            .ok_or(anyhow!("files not initialized, was a root hash specified?"))?;
+        self.read_pinned(name, object_store).await
+    }
 
-        if let Some(path) = files.get(name) {
-            match object_store.read(path).await {
-                Ok(get_result) => {
-                    let bytes = get_result.to_bytes();
-                    Ok(Some(bytes.to_vec()))
-                }
-                Err(_) => Ok(None),
-            }
-        } else {
-            Ok(None)
-        }
+    /// Returns the file from the migration's pinned local tree if it exists.
+    async fn load_local_bytes(
+        &self,
+        name: &str,
+        _migration_folder: &str,
+        object_store: &Operator,
+    ) -> Result<Option<Vec<u8>>> {
+        self.read_pinned(&format!("./{}", name), object_store).await
     }
 
     async fn snapshot(&mut self, object_store: &Operator) -> Result<String> {
-        super::snapshot(object_store, &self.pin_path, &self.source_path).await
+        match &self.packages_path {
+            Some(packages_path) => {
+                super::snapshot_with_packages(
+                    object_store,
+                    &self.pin_path,
+                    &self.source_path,
+                    packages_path,
+                )
+                .await
+            }
+            None => super::snapshot(object_store, &self.pin_path, &self.source_path).await,
+        }
     }
 }
@@ -12,6 +12,12 @@
 //! 1. Setting the `DO_NOT_TRACK` environment variable (any value)
 //! 2. Setting `telemetry = false` in `spawn.toml`
 //!
+//! ## Sinks
+//!
+//! Events go to spawn's PostHog project unless `telemetry.endpoint` is set,
+//! either to another PostHog-compatible batch endpoint or to a `file://`
+//! path that events are appended to as JSON lines.
+//!
 //! ## Debugging
 //!
 //! Set `SPAWN_DEBUG_TELEMETRY=1` to enable debug output for telemetry.
@@ -20,7 +26,9 @@ use crate::commands::TelemetryInfo;
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 /// Check if telemetry debug mode is enabled
@@ -46,6 +54,91 @@ const POSTHOG_ENDPOINT: &str = "https://eu.i.posthog.com/batch/";
 /// Application version from Cargo.toml
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+fn default_enabled() -> bool {
+    true
+}
+
+/// The `telemetry` setting in `spawn.toml`: either `telemetry = false`, or a
+/// `[telemetry]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TelemetrySetting {
+    Enabled(bool),
+    Config(TelemetryConfig),
+}
+
+/// Telemetry configuration, in the `[telemetry]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TelemetryConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Where to send events: an `http(s)://` PostHog-compatible batch
+    /// endpoint, or a `file://` path to append JSON lines to. Defaults to
+    /// spawn's own PostHog project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+impl TelemetrySetting {
+    pub fn enabled(&self) -> bool {
+        match self {
+            TelemetrySetting::Enabled(enabled) => *enabled,
+            TelemetrySetting::Config(cfg) => cfg.enabled,
+        }
+    }
+
+    pub fn endpoint(&self) -> Option<&str> {
+        match self {
+            TelemetrySetting::Enabled(_) => None,
+            TelemetrySetting::Config(cfg) => cfg.endpoint.as_deref(),
+        }
+    }
+}
+
+impl From<bool> for TelemetrySetting {
+    fn from(enabled: bool) -> Self {
+        TelemetrySetting::Enabled(enabled)
+    }
+}
+
+/// Where telemetry events are sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TelemetrySink {
+    /// A PostHog-compatible batch endpoint
+    PostHog(String),
+    /// A local file that events are appended to, one JSON object per line
+    File(PathBuf),
+}
+
+impl TelemetrySink {
+    /// The sink for a configured endpoint, or None if the endpoint's scheme
+    /// is not supported. Without an endpoint, events go to spawn's PostHog
+    /// project.
+    pub fn from_endpoint(endpoint: Option<&str>) -> Option<Self> {
+        let Some(endpoint) = endpoint else {
+            return Some(TelemetrySink::PostHog(POSTHOG_ENDPOINT.to_string()));
+        };
+        if let Some(path) = endpoint.strip_prefix("file://") {
+            if path.is_empty() {
+                return None;
+            }
+            return Some(TelemetrySink::File(PathBuf::from(path)));
+        }
+        if endpoint.starts_with("https://") || endpoint.starts_with("http://") {
+            return Some(TelemetrySink::PostHog(endpoint.to_string()));
+        }
+        None
+    }
+}
+
+/// What the telemetry child process reads from stdin.
+#[derive(Debug, Serialize, Deserialize)]
+struct TelemetryBatch {
+    /// The configured `telemetry.endpoint`, if any
+    endpoint: Option<String>,
+    events: Vec<TelemetryEvent>,
+}
+
 /// A telemetry event to be sent (serializable for IPC)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelemetryEvent {
@@ -55,11 +148,14 @@ pub struct TelemetryEvent {
     pub status: CommandStatus,
     pub error_kind: Option<String>,
     pub properties: Vec<(String, String)>,
+    /// For a sub-operation, the command it was part of
+    #[serde(default)]
+    pub parent: Option<String>,
 }
 
 /// Spawn a detached child process to send telemetry events
-fn spawn_telemetry_child(events: &[TelemetryEvent]) {
-    if events.is_empty() {
+fn spawn_telemetry_child(batch: &TelemetryBatch) {
+    if batch.events.is_empty() {
         return;
     }
 
@@ -73,7 +169,7 @@ fn spawn_telemetry_child(events: &[TelemetryEvent]) {
     };
 
     // Serialize the events to JSON
-    let json = match serde_json::to_string(events) {
+    let json = match serde_json::to_string(batch) {
         Ok(j) => j,
         Err(e) => {
             debug_telemetry!("failed to serialize events: {:?}", e);
@@ -115,11 +211,91 @@ fn spawn_telemetry_child(events: &[TelemetryEvent]) {
 
     debug_telemetry!(
         "spawned telemetry child process for {} event(s)",
-        events.len()
+        batch.events.len()
     );
     // Do NOT call child.wait() - let it run independently
 }
 
+/// A sub-operation recorded during a session.
+#[derive(Debug, Clone)]
+struct SessionEvent {
+    command: String,
+    duration_ms: u64,
+    status: CommandStatus,
+    properties: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+struct SessionBuffer {
+    events: Vec<SessionEvent>,
+    counts: Vec<(String, u64)>,
+}
+
+/// Collects events for the sub-operations of a command (each migration
+/// applied, each test compared) and counts such as the number of migrations
+/// applied. They are sent along with the command's own event by
+/// [`TelemetryRecorder::finish_with_session`], in a single child process.
+///
+/// Clones share the same buffer, so a session can be handed to the command
+/// through its `Config` and read back once the command is done.
+#[derive(Debug, Clone, Default)]
+pub struct TelemetrySession {
+    buffer: Arc<Mutex<SessionBuffer>>,
+}
+
+impl TelemetrySession {
+    /// Records a sub-operation that started at `start_time`.
+    pub fn record(&self, info: TelemetryInfo, start_time: Instant, status: CommandStatus) {
+        let event = SessionEvent {
+            command: info.label,
+            duration_ms: start_time.elapsed().as_millis() as u64,
+            status,
+            properties: info
+                .properties
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        };
+        self.buffer.lock().unwrap().events.push(event);
+    }
+
+    /// Adds to a count that is sent as a property of the command's event.
+    pub fn count(&self, key: &str, n: u64) {
+        let counts = &mut self.buffer.lock().unwrap().counts;
+        match counts.iter_mut().find(|(k, _)| k == key) {
+            Some((_, total)) => *total += n,
+            None => counts.push((key.to_string(), n)),
+        }
+    }
+}
+
+/// Whether telemetry is enabled, and why.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TelemetryState {
+    /// Disabled by the `DO_NOT_TRACK` environment variable
+    DoNotTrack,
+    /// Disabled by `telemetry = false` in `spawn.toml`
+    DisabledInConfig,
+    Enabled,
+}
+
+impl TelemetryState {
+    /// `DO_NOT_TRACK` takes priority over the config setting.
+    pub fn detect(telemetry_enabled: bool) -> Self {
+        if env::var("DO_NOT_TRACK").is_ok() {
+            TelemetryState::DoNotTrack
+        } else if !telemetry_enabled {
+            TelemetryState::DisabledInConfig
+        } else {
+            TelemetryState::Enabled
+        }
+    }
+
+    pub fn is_enabled(self) -> bool {
+        self == TelemetryState::Enabled
+    }
+}
+
 /// Telemetry recorder for tracking command execution.
 ///
 /// Use `TelemetryRecorder::new()` at the start of command execution,
@@ -130,6 +306,7 @@ pub struct TelemetryRecorder {
     command: String,
     properties: Vec<(String, String)>,
     start_time: Instant,
+    endpoint: Option<String>,
 }
 
 /// Status of command execution for telemetry
@@ -139,6 +316,15 @@ pub enum CommandStatus {
     Error,
 }
 
+impl CommandStatus {
+    pub fn of<T, E>(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => CommandStatus::Success,
+            Err(_) => CommandStatus::Error,
+        }
+    }
+}
+
 impl std::fmt::Display for CommandStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -166,11 +352,7 @@ impl TelemetryRecorder {
         info: TelemetryInfo,
         start_time: Instant,
     ) -> Self {
-        // Check DO_NOT_TRACK env var first (highest priority)
-        let do_not_track = env::var("DO_NOT_TRACK").is_ok();
-
-        // Determine if telemetry is enabled
-        let enabled = !do_not_track && telemetry_enabled;
+        let enabled = TelemetryState::detect(telemetry_enabled).is_enabled();
 
         // Get or generate distinct_id
         // Ephemeral IDs are prefixed with "e-" to distinguish them in analytics
@@ -195,9 +377,17 @@ impl TelemetryRecorder {
             command: info.label,
             properties,
             start_time,
+            endpoint: None,
         }
     }
 
+    /// Sends events to the configured `telemetry.endpoint` instead of
+    /// spawn's PostHog project.
+    pub fn with_endpoint(mut self, endpoint: Option<String>) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
     /// Create a new telemetry recorder, starting the timer now.
     ///
     /// Checks opt-out settings in priority order:
@@ -215,103 +405,161 @@ impl TelemetryRecorder {
     /// This method consumes the recorder and spawns a background process.
     /// The main process can exit immediately without waiting.
     pub fn finish(self, status: CommandStatus, error_kind: Option<&str>) {
+        self.finish_with_session(status, error_kind, &TelemetrySession::default());
+    }
+
+    /// Like [`finish`](Self::finish), but also sends the sub-operations and
+    /// counts collected in `session`, all in the same child process.
+    pub fn finish_with_session(
+        self,
+        status: CommandStatus,
+        error_kind: Option<&str>,
+        session: &TelemetrySession,
+    ) {
         debug_telemetry!("finish() called, enabled={}", self.enabled);
         if !self.enabled {
             return;
         }
 
-        let event = TelemetryEvent {
-            distinct_id: self.distinct_id,
-            command: self.command,
-            duration_ms: self.start_time.elapsed().as_millis() as u64,
-            status,
-            error_kind: error_kind.map(|s| s.to_string()),
-            properties: self.properties,
-        };
+        let endpoint = self.endpoint.clone();
+        let events = self.into_events(status, error_kind, session);
 
         debug_telemetry!(
-            "spawning child for event: command={}, distinct_id={}, duration_ms={}",
-            event.command,
-            event.distinct_id,
-            event.duration_ms
+            "spawning child for event: command={}, distinct_id={}, duration_ms={}, sub-operations={}",
+            events[0].command,
+            events[0].distinct_id,
+            events[0].duration_ms,
+            events.len() - 1
         );
 
-        // Spawn detached child process to send the event
-        spawn_telemetry_child(&[event]);
+        // Spawn detached child process to send the events
+        send_events(events, endpoint);
+    }
+
+    /// The command's event, with the session's counts as properties,
+    /// followed by an event for each sub-operation.
+    fn into_events(
+        self,
+        status: CommandStatus,
+        error_kind: Option<&str>,
+        session: &TelemetrySession,
+    ) -> Vec<TelemetryEvent> {
+        let buffer = session.buffer.lock().unwrap();
+
+        let mut properties = self.properties;
+        properties.extend(
+            buffer
+                .counts
+                .iter()
+                .map(|(key, n)| (key.clone(), n.to_string())),
+        );
+
+        let mut events = vec![TelemetryEvent {
+            distinct_id: self.distinct_id.clone(),
+            command: self.command.clone(),
+            duration_ms: self.start_time.elapsed().as_millis() as u64,
+            status,
+            error_kind: error_kind.map(|s| s.to_string()),
+            properties,
+            parent: None,
+        }];
+        events.extend(buffer.events.iter().map(|event| TelemetryEvent {
+            distinct_id: self.distinct_id.clone(),
+            command: event.command.clone(),
+            duration_ms: event.duration_ms,
+            status: event.status,
+            error_kind: None,
+            properties: event.properties.clone(),
+            parent: Some(self.command.clone()),
+        }));
+
+        events
     }
 }
 
 /// Send multiple telemetry events via a detached child process.
 ///
 /// This is useful when you have collected multiple events and want to
-/// send them all in a single child process.
-pub fn send_events(events: Vec<TelemetryEvent>) {
+/// send them all in a single child process. `endpoint` is the configured
+/// `telemetry.endpoint`, if any.
+pub fn send_events(events: Vec<TelemetryEvent>, endpoint: Option<String>) {
     if events.is_empty() {
         return;
     }
     debug_telemetry!("sending {} event(s)", events.len());
-    spawn_telemetry_child(&events);
+    spawn_telemetry_child(&TelemetryBatch { endpoint, events });
 }
 
-/// Send telemetry events to PostHog using the batch API
-async fn send_events_to_posthog(events: &[TelemetryEvent]) -> Result<(), reqwest::Error> {
-    // Build batch payload
-    let batch: Vec<serde_json::Value> = events
-        .iter()
-        .map(|event| {
-            let mut props = serde_json::Map::new();
-            props.insert(
-                "distinct_id".to_string(),
-                serde_json::json!(event.distinct_id),
-            );
-            props.insert("app_version".to_string(), serde_json::json!(APP_VERSION));
-            props.insert(
-                "os_platform".to_string(),
-                serde_json::json!(std::env::consts::OS),
-            );
-            props.insert(
-                "os_arch".to_string(),
-                serde_json::json!(std::env::consts::ARCH),
-            );
-            props.insert("is_ci".to_string(), serde_json::json!(is_ci()));
-            props.insert("command".to_string(), serde_json::json!(event.command));
-            props.insert(
-                "duration_ms".to_string(),
-                serde_json::json!(event.duration_ms),
-            );
-            props.insert(
-                "status".to_string(),
-                serde_json::json!(event.status.to_string()),
-            );
-            props.insert("$lib".to_string(), serde_json::json!("spawn"));
-            props.insert("$lib_version".to_string(), serde_json::json!(APP_VERSION));
-            // Don't create person profiles for CLI telemetry
-            props.insert(
-                "$process_person_profile".to_string(),
-                serde_json::json!(false),
-            );
+/// The JSON for one event, as sent to PostHog and written to file sinks.
+fn event_payload(event: &TelemetryEvent) -> serde_json::Value {
+    let mut props = serde_json::Map::new();
+    props.insert(
+        "distinct_id".to_string(),
+        serde_json::json!(event.distinct_id),
+    );
+    props.insert("app_version".to_string(), serde_json::json!(APP_VERSION));
+    props.insert(
+        "os_platform".to_string(),
+        serde_json::json!(std::env::consts::OS),
+    );
+    props.insert(
+        "os_arch".to_string(),
+        serde_json::json!(std::env::consts::ARCH),
+    );
+    props.insert("is_ci".to_string(), serde_json::json!(is_ci()));
+    props.insert("command".to_string(), serde_json::json!(event.command));
+    props.insert(
+        "duration_ms".to_string(),
+        serde_json::json!(event.duration_ms),
+    );
+    props.insert(
+        "status".to_string(),
+        serde_json::json!(event.status.to_string()),
+    );
+    props.insert("$lib".to_string(), serde_json::json!("spawn"));
+    props.insert("$lib_version".to_string(), serde_json::json!(APP_VERSION));
+    // Don't create person profiles for CLI telemetry
+    props.insert(
+        "$process_person_profile".to_string(),
+        serde_json::json!(false),
+    );
 
-            if let Some(ref kind) = event.error_kind {
-                props.insert("error_kind".to_string(), serde_json::json!(kind));
-            }
+    if let Some(ref kind) = event.error_kind {
+        props.insert("error_kind".to_string(), serde_json::json!(kind));
+    }
 
-            for (key, value) in &event.properties {
-                props.insert(key.clone(), serde_json::json!(value));
-            }
+    for (key, value) in &event.properties {
+        props.insert(key.clone(), serde_json::json!(value));
+    }
+
+    // Sub-operations are kept apart so they don't count as commands.
+    let name = match &event.parent {
+        Some(parent) => {
+            props.insert("parent_command".to_string(), serde_json::json!(parent));
+            "operation_completed"
+        }
+        None => "command_completed",
+    };
 
-            serde_json::json!({
-                "event": "command_completed",
-                "properties": props
-            })
-        })
-        .collect();
+    serde_json::json!({
+        "event": name,
+        "properties": props
+    })
+}
+
+/// Send telemetry events to PostHog using the batch API
+async fn send_events_to_posthog(
+    endpoint: &str,
+    events: &[TelemetryEvent],
+) -> Result<(), reqwest::Error> {
+    let batch: Vec<serde_json::Value> = events.iter().map(event_payload).collect();
 
     let payload = serde_json::json!({
         "api_key": POSTHOG_API_KEY,
         "batch": batch
     });
 
-    debug_telemetry!("POST to {}", POSTHOG_ENDPOINT);
+    debug_telemetry!("POST to {}", endpoint);
     debug_telemetry!(
         "payload: {}",
         serde_json::to_string_pretty(&payload).unwrap_or_default()
@@ -319,7 +567,7 @@ async fn send_events_to_posthog(events: &[TelemetryEvent]) -> Result<(), reqwest
 
     let client = reqwest::Client::new();
     let response = client
-        .post(POSTHOG_ENDPOINT)
+        .post(endpoint)
         .header("Content-Type", "application/json")
         .json(&payload)
         .send()
@@ -333,6 +581,26 @@ async fn send_events_to_posthog(events: &[TelemetryEvent]) -> Result<(), reqwest
     Ok(())
 }
 
+/// Append telemetry events to a local file, one JSON object per line, with
+/// the time each was written.
+fn append_events_to_file(path: &std::path::Path, events: &[TelemetryEvent]) -> std::io::Result<()> {
+    let timestamp = chrono::Utc::now().to_rfc3339();
+    let mut lines = String::new();
+    for event in events {
+        let mut payload = event_payload(event);
+        payload["timestamp"] = serde_json::json!(timestamp);
+        lines.push_str(&payload.to_string());
+        lines.push('\n');
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    // A single write keeps lines from concurrent processes intact.
+    file.write_all(lines.as_bytes())
+}
+
 /// Check if running in a CI environment
 fn is_ci() -> bool {
     // Common CI environment variables
@@ -360,8 +628,8 @@ pub fn run_internal_telemetry() {
     }
 
     // Parse the events
-    let events: Vec<TelemetryEvent> = match serde_json::from_str(&input) {
-        Ok(e) => e,
+    let TelemetryBatch { endpoint, events } = match serde_json::from_str(&input) {
+        Ok(batch) => batch,
         Err(e) => {
             debug_telemetry!("failed to parse events JSON: {:?}", e);
             return;
@@ -375,6 +643,21 @@ pub fn run_internal_telemetry() {
 
     debug_telemetry!("child received {} event(s)", events.len());
 
+    let url = match TelemetrySink::from_endpoint(endpoint.as_deref()) {
+        Some(TelemetrySink::PostHog(url)) => url,
+        Some(TelemetrySink::File(path)) => {
+            match append_events_to_file(&path, &events) {
+                Ok(()) => debug_telemetry!("wrote {} event(s) to {}", events.len(), path.display()),
+                Err(e) => debug_telemetry!("failed to write events: {:?}", e),
+            }
+            return;
+        }
+        None => {
+            debug_telemetry!("unsupported telemetry endpoint {:?}", endpoint);
+            return;
+        }
+    };
+
     // Create a minimal tokio runtime just for the HTTP call
     let rt = match tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -388,7 +671,7 @@ pub fn run_internal_telemetry() {
     };
 
     // Send all events in a single batch request
-    let result = rt.block_on(send_events_to_posthog(&events));
+    let result = rt.block_on(send_events_to_posthog(&url, &events));
 
     match result {
         Ok(()) => debug_telemetry!("successfully sent {} event(s)", events.len()),
@@ -460,4 +743,99 @@ mod tests {
             ("opt_pinned".to_string(), "true".to_string())
         );
     }
+
+    #[test]
+    fn test_session_events_are_sent_with_command() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        env::remove_var("DO_NOT_TRACK"); // Ensure clean state
+        let recorder =
+            TelemetryRecorder::new(Some("test-id"), true, TelemetryInfo::new("migration apply"));
+
+        let session = TelemetrySession::default();
+        for _ in 0..2 {
+            session.record(
+                TelemetryInfo::new("migration apply step"),
+                Instant::now(),
+                CommandStatus::Success,
+            );
+            session.count("migrations_applied", 1);
+        }
+
+        let events = recorder.into_events(CommandStatus::Success, None, &session.clone());
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].command, "migration apply");
+        assert_eq!(events[0].parent, None);
+        assert!(events[0]
+            .properties
+            .contains(&("migrations_applied".to_string(), "2".to_string())));
+        for event in &events[1..] {
+            assert_eq!(event.command, "migration apply step");
+            assert_eq!(event.distinct_id, "test-id");
+            assert_eq!(event.parent.as_deref(), Some("migration apply"));
+        }
+    }
+
+    #[test]
+    fn test_sink_from_endpoint() {
+        assert_eq!(
+            TelemetrySink::from_endpoint(None),
+            Some(TelemetrySink::PostHog(POSTHOG_ENDPOINT.to_string()))
+        );
+        assert_eq!(
+            TelemetrySink::from_endpoint(Some("file:///var/log/spawn.jsonl")),
+            Some(TelemetrySink::File(PathBuf::from("/var/log/spawn.jsonl")))
+        );
+        assert_eq!(
+            TelemetrySink::from_endpoint(Some("https://posthog.internal/batch/")),
+            Some(TelemetrySink::PostHog(
+                "https://posthog.internal/batch/".to_string()
+            ))
+        );
+        assert_eq!(TelemetrySink::from_endpoint(Some("ftp://host/x")), None);
+        assert_eq!(TelemetrySink::from_endpoint(Some("file://")), None);
+    }
+
+    #[test]
+    fn test_file_sink_appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.jsonl");
+        let event = TelemetryEvent {
+            distinct_id: "test-id".to_string(),
+            command: "check".to_string(),
+            duration_ms: 5,
+            status: CommandStatus::Success,
+            error_kind: None,
+            properties: vec![],
+            parent: None,
+        };
+
+        append_events_to_file(&path, std::slice::from_ref(&event)).unwrap();
+        append_events_to_file(&path, &[event]).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "command_completed");
+        assert_eq!(lines[0]["properties"]["command"], "check");
+        assert!(lines[0]["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_telemetry_setting_accepts_bool_or_table() {
+        let setting: TelemetrySetting = toml::from_str::<toml::Value>("telemetry = false").unwrap()
+            ["telemetry"]
+            .clone()
+            .try_into()
+            .unwrap();
+        assert!(!setting.enabled());
+        assert_eq!(setting.endpoint(), None);
+
+        let setting: TelemetrySetting =
+            toml::from_str("endpoint = \"file://./telemetry.jsonl\"").unwrap();
+        assert!(setting.enabled());
+        assert_eq!(setting.endpoint(), Some("file://./telemetry.jsonl"));
+    }
 }
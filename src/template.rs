@@ -1,20 +1,137 @@
 use crate::config;
 use crate::engine::EngineType;
 use crate::escape::{EscapedIdentifier, EscapedLiteral};
+use crate::secrets::{resolve_secret_variables, SecretResolver};
 use crate::store::pinner::latest::Latest;
 use crate::store::pinner::spawn::Spawn;
 use crate::store::pinner::Pinner;
-use crate::store::Store;
-use crate::variables::Variables;
+use crate::store::{LoadedComponents, Store};
+use crate::variables::{self, VariableSources, Variables};
+use minijinja::value::Rest;
 use minijinja::{Environment, Value};
 
 use crate::sql_formatter::SqlDialect;
 use base64::{engine::general_purpose::STANDARD, Engine as _};
+use sha2::{Digest, Sha256};
+use twox_hash::XxHash3_64;
 use uuid::Uuid;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
 use minijinja::context;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Limits on rendering templates, so that a runaway loop in a component
+/// stops `migration build` or `apply` with an error rather than hanging.
+/// Set in the `[template.limits]` section; 0 turns a limit off.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TemplateLimits {
+    /// Largest migration script or component that will be rendered
+    #[serde(default = "default_max_template_size_kb")]
+    pub max_template_size_kb: u64,
+    /// How deeply includes, macro calls and blocks may nest
+    #[serde(default = "default_max_include_depth")]
+    pub max_include_depth: usize,
+    /// Longest a render may take, not counting time spent waiting for the
+    /// database to accept the SQL. Checked as output is written, so a loop
+    /// that writes nothing is stopped by `fuel` instead.
+    #[serde(default = "default_render_timeout_secs")]
+    pub render_timeout_secs: u64,
+    /// Instructions a render may run, counting every loop iteration
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+}
+
+fn default_max_template_size_kb() -> u64 {
+    10 * 1024
+}
+
+fn default_max_include_depth() -> usize {
+    100
+}
+
+fn default_render_timeout_secs() -> u64 {
+    300
+}
+
+fn default_fuel() -> u64 {
+    100_000_000
+}
+
+impl Default for TemplateLimits {
+    fn default() -> Self {
+        Self {
+            max_template_size_kb: default_max_template_size_kb(),
+            max_include_depth: default_max_include_depth(),
+            render_timeout_secs: default_render_timeout_secs(),
+            fuel: default_fuel(),
+        }
+    }
+}
+
+impl TemplateLimits {
+    /// Refuses `source` if it is larger than `max_template_size_kb`.
+    fn check_size(&self, name: &str, source: &str) -> Result<(), minijinja::Error> {
+        let limit = self.max_template_size_kb * 1024;
+        if limit > 0 && source.len() as u64 > limit {
+            return Err(minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                format!(
+                    "{} is {} KB, larger than the {} KB allowed by template.limits.max_template_size_kb",
+                    name,
+                    source.len().div_ceil(1024),
+                    self.max_template_size_kb
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Passes writes through to `inner`, failing once rendering has taken
+/// longer than `timeout`. Time spent in `inner`, such as waiting for psql to
+/// read what was written, is not counted.
+struct DeadlineWriter<'a, W: std::io::Write + ?Sized> {
+    inner: &'a mut W,
+    started: Instant,
+    waiting: Duration,
+    timeout: Duration,
+}
+
+impl<'a, W: std::io::Write + ?Sized> DeadlineWriter<'a, W> {
+    fn new(inner: &'a mut W, timeout: Duration) -> Self {
+        Self {
+            inner,
+            started: Instant::now(),
+            waiting: Duration::ZERO,
+            timeout,
+        }
+    }
+}
+
+impl<W: std::io::Write + ?Sized> std::io::Write for DeadlineWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.started.elapsed().saturating_sub(self.waiting) > self.timeout {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!(
+                    "rendering took longer than the {}s allowed by template.limits.render_timeout_secs",
+                    self.timeout.as_secs()
+                ),
+            ));
+        }
+        let write_started = Instant::now();
+        let result = self.inner.write(buf);
+        self.waiting += write_started.elapsed();
+        result
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
 
 /// Maps an EngineType to the appropriate SQL dialect for formatting.
 ///
@@ -24,6 +141,8 @@ use std::sync::Arc;
 fn engine_to_dialect(engine: &EngineType) -> SqlDialect {
     match engine {
         EngineType::PostgresPSQL => SqlDialect::Postgres,
+        // Memory databases stand in for postgres in tests.
+        EngineType::Memory => SqlDialect::Postgres,
         // Future engines:
         // EngineType::PostgresNative => SqlDialect::Postgres,
         // EngineType::MySQL => SqlDialect::MySQL,
@@ -31,18 +150,64 @@ fn engine_to_dialect(engine: &EngineType) -> SqlDialect {
     }
 }
 
-pub fn template_env(store: Store, engine: &EngineType) -> Result<Environment<'static>> {
+pub fn template_env(
+    store: Store,
+    engine: &EngineType,
+    template_cfg: &config::TemplateConfig,
+) -> Result<Environment<'static>> {
+    let secrets = SecretResolver::new(template_cfg.secrets.clone().unwrap_or_default());
+    template_env_with_secrets(store, engine, template_cfg, secrets)
+}
+
+/// As [`template_env`], with `secret()` looking secrets up through
+/// `secrets`, to share its cache with the variables that refer to them.
+fn template_env_with_secrets(
+    store: Store,
+    engine: &EngineType,
+    template_cfg: &config::TemplateConfig,
+    secrets: SecretResolver,
+) -> Result<Environment<'static>> {
     let mut env = Environment::new();
 
     let store = Arc::new(store);
 
     let mj_store = MiniJinjaLoader {
         store: Arc::clone(&store),
+        limits: template_cfg.limits.clone(),
     };
     env.set_loader(move |name: &str| mj_store.load(name));
-    env.add_function("gen_uuid_v4", gen_uuid_v4);
+
+    let limits = &template_cfg.limits;
+    env.set_fuel(Some(limits.fuel).filter(|f| *f > 0));
+    if limits.max_include_depth > 0 {
+        env.set_recursion_limit(limits.max_include_depth);
+    }
+    // Functions whose output differs between renders are refused in
+    // deterministic mode, rather than left undefined, to say why.
+    let deterministic = template_cfg.deterministic;
+    let refuse = |env: &mut Environment<'static>, name: &'static str| {
+        env.add_function(
+            name,
+            move |_: Rest<Value>| -> Result<Value, minijinja::Error> {
+                Err(minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    format!(
+                        "{}() gives a different result each render, so cannot be used in \
+                         deterministic mode; pass the value in with --variables instead",
+                        name
+                    ),
+                ))
+            },
+        );
+    };
+    if deterministic {
+        refuse(&mut env, "gen_uuid_v4");
+        refuse(&mut env, "gen_uuid_v7");
+    } else {
+        env.add_function("gen_uuid_v4", gen_uuid_v4);
+        env.add_function("gen_uuid_v7", gen_uuid_v7);
+    }
     env.add_function("gen_uuid_v5", gen_uuid_v5);
-    env.add_function("gen_uuid_v7", gen_uuid_v7);
     env.add_filter("escape_identifier", escape_identifier_filter);
     env.add_filter("escape_literal", escape_literal_filter);
 
@@ -87,6 +252,27 @@ pub fn template_env(store: Store, engine: &EngineType) -> Result<Environment<'st
         },
     );
 
+    env.add_function(
+        "secret",
+        move |path: &str| -> Result<String, minijinja::Error> {
+            secrets.resolve(path).map_err(|e| {
+                minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, format!("{:#}", e))
+            })
+        },
+    );
+
+    if template_cfg.time_functions && deterministic {
+        refuse(&mut env, "now");
+        refuse(&mut env, "timestamp");
+    } else if template_cfg.time_functions {
+        env.add_function("now", now_function);
+        env.add_function("timestamp", timestamp_function);
+    }
+    if template_cfg.hash_filters {
+        env.add_filter("sha256", sha256_filter);
+        env.add_filter("xxh3", xxh3_filter);
+    }
+
     // Get the appropriate dialect for this engine
     let dialect = engine_to_dialect(engine);
 
@@ -101,6 +287,7 @@ pub fn template_env(store: Store, engine: &EngineType) -> Result<Environment<'st
 
 struct MiniJinjaLoader {
     pub store: Arc<Store>,
+    pub limits: TemplateLimits,
 }
 
 impl MiniJinjaLoader {
@@ -110,12 +297,17 @@ impl MiniJinjaLoader {
                 .block_on(async { self.store.load_component(name).await })
         });
 
-        result.map_err(|e| {
+        let source = result.map_err(|e| {
             minijinja::Error::new(
                 minijinja::ErrorKind::InvalidOperation,
                 format!("Failed to load from object store: {}", e),
             )
-        })
+        })?;
+        if let Some(source) = &source {
+            self.limits
+                .check_size(&format!("component {}", name), source)?;
+        }
+        Ok(source)
     }
 }
 
@@ -137,7 +329,7 @@ fn gen_uuid_v7() -> Result<String, minijinja::Error> {
 /// making it safe to use in SQL statements where an identifier is expected.
 ///
 /// Usage in templates: `{{ dbname|escape_identifier }}`
-fn escape_identifier_filter(value: &Value) -> Result<Value, minijinja::Error> {
+pub(crate) fn escape_identifier_filter(value: &Value) -> Result<Value, minijinja::Error> {
     let s = value.to_string();
     let escaped = EscapedIdentifier::new(&s);
     // Return as a safe string so it won't be further escaped by the SQL formatter
@@ -151,15 +343,73 @@ fn escape_identifier_filter(value: &Value) -> Result<Value, minijinja::Error> {
 /// where auto-escaping might not apply (e.g., after `safe` or inside macros).
 ///
 /// Usage in templates: `{{ value|escape_literal }}`
-fn escape_literal_filter(value: &Value) -> Result<Value, minijinja::Error> {
+pub(crate) fn escape_literal_filter(value: &Value) -> Result<Value, minijinja::Error> {
     let s = value.to_string();
     let escaped = EscapedLiteral::new(&s);
     // Return as a safe string so it won't be further escaped by the SQL formatter
     Ok(Value::from_safe_string(escaped.to_string()))
 }
 
+/// Current time in UTC as an RFC 3339 string.
+///
+/// Usage in templates: `{{ now() }}`
+fn now_function() -> Result<String, minijinja::Error> {
+    Ok(chrono::Utc::now().to_rfc3339())
+}
+
+/// Current time as seconds since the Unix epoch.
+///
+/// Usage in templates: `{{ timestamp() }}`
+fn timestamp_function() -> Result<i64, minijinja::Error> {
+    Ok(chrono::Utc::now().timestamp())
+}
+
+/// Returns the raw bytes of a bytes or string value, for the hashing filters.
+fn hash_input<'a>(value: &'a Value, filter: &str) -> Result<&'a [u8], minijinja::Error> {
+    use minijinja::value::ValueKind;
+    match value.kind() {
+        ValueKind::Bytes => Ok(value.as_bytes().unwrap()),
+        ValueKind::String => Ok(value.as_str().unwrap().as_bytes()),
+        _ => Err(minijinja::Error::new(
+            minijinja::ErrorKind::InvalidOperation,
+            format!("{} filter expects bytes or string input", filter),
+        )),
+    }
+}
+
+/// Filter to hash a value with SHA-256, returned as lowercase hex.
+///
+/// Usage in templates: `{{ "path/to/file"|read_file|sha256 }}`
+fn sha256_filter(value: &Value) -> Result<Value, minijinja::Error> {
+    let digest = Sha256::digest(hash_input(value, "sha256")?);
+    Ok(Value::from(format!("{:x}", digest)))
+}
+
+/// Filter to hash a value with 64-bit XXH3, returned as lowercase hex.
+///
+/// Usage in templates: `{{ "path/to/file"|read_file|xxh3 }}`
+fn xxh3_filter(value: &Value) -> Result<Value, minijinja::Error> {
+    let hash = XxHash3_64::oneshot(hash_input(value, "xxh3")?);
+    Ok(Value::from(format!("{:016x}", hash)))
+}
+
+/// Rejects paths that could resolve outside of the components folder.
+fn check_component_path(path: &str) -> Result<(), minijinja::Error> {
+    let escapes = path.starts_with('/')
+        || path.starts_with('\\')
+        || path.split(['/', '\\']).any(|segment| segment == "..");
+    if escapes {
+        return Err(minijinja::Error::new(
+            minijinja::ErrorKind::InvalidOperation,
+            format!("'{}' is outside of the components folder", path),
+        ));
+    }
+    Ok(())
+}
+
 /// Reads raw bytes from a file in the components folder via the Store.
 fn read_file_bytes(path: &str, store: &Arc<Store>) -> Result<Vec<u8>, minijinja::Error> {
+    check_component_path(path)?;
     let bytes = tokio::task::block_in_place(|| {
         tokio::runtime::Handle::current().block_on(async { store.read_file_bytes(path).await })
     });
@@ -273,6 +523,106 @@ pub struct Generation {
     pub content: String,
 }
 
+/// Supplies a named global to the template context.
+///
+/// Providers are resolved once per generation, before rendering starts, so
+/// they are free to do async work such as querying the target database.
+#[async_trait]
+pub trait ContextProvider: Send + Sync {
+    /// Name of the global as seen by templates.
+    fn name(&self) -> &'static str;
+
+    /// Resolve the value of the global for this generation.
+    async fn provide(&self, cfg: &config::Config) -> Result<Value>;
+}
+
+/// Exposes the configured tenants as `tenants`, so that migrations can
+/// generate per-tenant DDL with `{% for t in tenants %}`.
+pub struct TenantsProvider;
+
+#[async_trait]
+impl ContextProvider for TenantsProvider {
+    fn name(&self) -> &'static str {
+        "tenants"
+    }
+
+    async fn provide(&self, cfg: &config::Config) -> Result<Value> {
+        let tenants = match &cfg.tenants {
+            Some(config::TenantSource::List { list }) => list.clone(),
+            Some(config::TenantSource::Query { query }) => {
+                let engine = cfg.new_engine().await?;
+                engine
+                    .query_first_column(query)
+                    .await
+                    .context("failed to query tenants")?
+            }
+            None => Vec::new(),
+        };
+
+        Ok(Value::from(filter_tenants(tenants, &cfg.tenant_filter)?))
+    }
+}
+
+/// Exposes the `[template.constants]` table from spawn.toml as `constants`.
+pub struct ConstantsProvider;
+
+#[async_trait]
+impl ContextProvider for ConstantsProvider {
+    fn name(&self) -> &'static str {
+        "constants"
+    }
+
+    async fn provide(&self, cfg: &config::Config) -> Result<Value> {
+        Ok(Value::from_serialize(&cfg.template.constants))
+    }
+}
+
+/// Restrict `tenants` to those named in `filter`, preserving the original
+/// order. An empty filter leaves the list unchanged.
+fn filter_tenants(tenants: Vec<String>, filter: &[String]) -> Result<Vec<String>> {
+    if filter.is_empty() {
+        return Ok(tenants);
+    }
+
+    if let Some(unknown) = filter.iter().find(|f| !tenants.contains(f)) {
+        return Err(anyhow!("unknown tenant '{}'", unknown));
+    }
+
+    Ok(tenants.into_iter().filter(|t| filter.contains(t)).collect())
+}
+
+/// Returns the context providers enabled by the project configuration.
+fn context_providers(cfg: &config::Config) -> Result<Vec<Box<dyn ContextProvider>>> {
+    let mut providers: Vec<Box<dyn ContextProvider>> = Vec::new();
+
+    if !cfg.template.constants.is_empty() {
+        providers.push(Box::new(ConstantsProvider));
+    }
+
+    if cfg.tenants.is_some() {
+        providers.push(Box::new(TenantsProvider));
+    } else if !cfg.tenant_filter.is_empty() {
+        return Err(anyhow!(
+            "--tenant was given but no tenants are configured in spawn.toml"
+        ));
+    }
+
+    Ok(providers)
+}
+
+/// Resolve every enabled context provider into a list of template globals.
+async fn resolve_globals(cfg: &config::Config) -> Result<Vec<(String, Value)>> {
+    let mut globals = Vec::new();
+    for provider in context_providers(cfg)? {
+        let value = provider.provide(cfg).await.context(format!(
+            "could not resolve template global '{}'",
+            provider.name()
+        ))?;
+        globals.push((provider.name().to_string(), value));
+    }
+    Ok(globals)
+}
+
 /// Holds all the data needed to render a template to a writer.
 /// This struct is Send and can be moved into a WriterFn closure.
 pub struct StreamingGeneration {
@@ -281,19 +631,75 @@ pub struct StreamingGeneration {
     environment: String,
     variables: Variables,
     engine: EngineType,
+    template_cfg: config::TemplateConfig,
+    globals: Vec<(String, Value)>,
 }
 
 impl StreamingGeneration {
+    /// Add globals (usually from context providers) to the render context.
+    pub fn with_globals(mut self, globals: Vec<(String, Value)>) -> Self {
+        self.globals.extend(globals);
+        self
+    }
+
+    /// Enable the optional template helpers configured in `[template]`.
+    pub fn with_template_config(mut self, template_cfg: config::TemplateConfig) -> Self {
+        self.template_cfg = template_cfg;
+        self
+    }
+
+    /// The context the template is rendered with: `env`, `variables` and
+    /// every global, by name.
+    pub fn context(&self) -> Result<serde_json::Value> {
+        let mut context = serde_json::Map::new();
+        context.insert("env".to_string(), self.environment.clone().into());
+        context.insert(
+            "variables".to_string(),
+            serde_json::to_value(&self.variables)?,
+        );
+        for (name, value) in &self.globals {
+            context.insert(name.clone(), serde_json::to_value(value)?);
+        }
+        Ok(serde_json::Value::Object(context))
+    }
+
+    /// The components read while rendering, filled in as the template is
+    /// rendered.
+    pub fn loaded_components(&self) -> LoadedComponents {
+        self.store.loaded_components()
+    }
+
     /// Render the template to the provided writer.
     /// This creates the minijinja environment and renders in one step.
     pub fn render_to_writer<W: std::io::Write + ?Sized>(self, writer: &mut W) -> Result<()> {
-        let mut env = template_env(self.store, &self.engine)?;
+        let limits = self.template_cfg.limits.clone();
+        limits.check_size("migration", &self.template_contents)?;
+        let secrets = SecretResolver::new(self.template_cfg.secrets.clone().unwrap_or_default());
+        let mut variables = serde_json::to_value(&self.variables)?;
+        resolve_secret_variables(&mut variables, &secrets)?;
+        let mut env =
+            template_env_with_secrets(self.store, &self.engine, &self.template_cfg, secrets)?;
+        for (name, value) in self.globals {
+            env.add_global(name, value);
+        }
         env.add_template("migration.sql", &self.template_contents)?;
         let tmpl = env.get_template("migration.sql")?;
-        tmpl.render_to_write(
-            context!(env => self.environment, variables => self.variables),
-            writer,
-        )?;
+        let ctx = context!(env => self.environment, variables => variables);
+        let result = if limits.render_timeout_secs > 0 {
+            let timeout = Duration::from_secs(limits.render_timeout_secs);
+            tmpl.render_to_write(ctx, DeadlineWriter::new(writer, timeout))
+        } else {
+            tmpl.render_to_write(ctx, writer)
+        };
+        if let Err(e) = result {
+            if e.kind() == minijinja::ErrorKind::OutOfFuel {
+                return Err(anyhow!(e).context(format!(
+                    "rendering ran more than the {} instructions allowed by template.limits.fuel",
+                    limits.fuel
+                )));
+            }
+            return Err(e.into());
+        }
         Ok(())
     }
 
@@ -319,14 +725,21 @@ pub async fn generate_streaming(
             .load_lock_file(&lock_file)
             .await
             .context("could not load pinned files lock file")?;
-        let pinner = Spawn::new_with_root_hash(
+        let mut pinner = Spawn::new_with_root_hash(
             cfg.pather().pinned_folder(),
             cfg.pather().components_folder(),
             &lock.pin,
             &cfg.operator(),
+            cfg.pin_cache()?,
         )
         .await
         .context("could not get new root with hash")?;
+        if let Some(local) = &lock.local {
+            pinner
+                .load_local_tree(local, cfg.operator())
+                .await
+                .context("could not load pinned migration files")?;
+        }
         Box::new(pinner)
     } else {
         let pinner = Latest::new(cfg.pather().spawn_folder_path())?;
@@ -339,24 +752,63 @@ pub async fn generate_streaming(
         .target_config()
         .context("could not get target config for generate")?;
 
-    generate_streaming_with_store(
+    let globals = resolve_globals(cfg).await?;
+    let sources = cfg.variable_sources(variables).await?;
+
+    Ok(generate_streaming_with_store(
         name,
-        variables,
+        sources,
         &target_config.environment,
         &target_config.engine,
         store,
     )
-    .await
+    .await?
+    .with_template_config(cfg.template.clone())
+    .with_globals(globals))
 }
 
-/// Generate a streaming migration with an existing store.
+/// The migration folder's `variables.toml`, with its
+/// `variables.<environment>.toml` merged over it, or None if it has neither.
+async fn migration_variables(store: &Store, environment: &str) -> Result<Option<Variables>> {
+    let mut merged: Option<Variables> = None;
+    for file in [
+        "./variables.toml".to_string(),
+        format!("./variables.{}.toml", environment),
+    ] {
+        let Some(contents) = store.load_component(&file).await? else {
+            continue;
+        };
+        let vars = Variables::from_str("toml", &contents)
+            .context(format!("could not read {}", &file[2..]))?;
+        merged = Some(match merged {
+            Some(base) => base.overlay(vars)?,
+            None => vars,
+        });
+    }
+
+    Ok(merged)
+}
+
+/// Generate a streaming migration with an existing store. The variables
+/// files of the migration folder are added to `sources` before resolving.
 pub async fn generate_streaming_with_store(
     name: &str,
-    variables: Option<Variables>,
+    mut sources: VariableSources,
     environment: &str,
     engine: &EngineType,
     store: Store,
 ) -> Result<StreamingGeneration> {
+    // Includes starting with "./" are resolved in the migration's folder.
+    let store = match store.pather().local_include_folder(name) {
+        Some(folder) => {
+            let store = store.with_migration_folder(&folder);
+            sources.migration_file = migration_variables(&store, environment).await?;
+            store
+        }
+        None => store,
+    };
+    let variables = variables::resolve(sources)?;
+
     // Read contents from our object store first:
     let contents = store
         .load_migration(name)
@@ -369,6 +821,8 @@ pub async fn generate_streaming_with_store(
         environment: environment.to_string(),
         variables: variables.unwrap_or_default(),
         engine: engine.clone(),
+        template_cfg: config::TemplateConfig::default(),
+        globals: Vec::new(),
     })
 }
 
@@ -482,6 +936,230 @@ mod tests {
         assert_eq!(result, "'alice', 'bob', 'charlie'");
     }
 
+    #[test]
+    fn test_hash_filters() {
+        assert_eq!(
+            sha256_filter(&Value::from("abc")).unwrap().to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256_filter(&Value::from_bytes(b"abc".to_vec()))
+                .unwrap()
+                .to_string(),
+            sha256_filter(&Value::from("abc")).unwrap().to_string()
+        );
+        assert_eq!(
+            xxh3_filter(&Value::from("")).unwrap().to_string(),
+            "2d06800538d394c2"
+        );
+        assert!(xxh3_filter(&Value::from(42)).is_err());
+    }
+
+    #[test]
+    fn test_check_component_path() {
+        assert!(check_component_path("data/file.json").is_ok());
+        assert!(check_component_path("file..name.txt").is_ok());
+        assert!(check_component_path("/etc/passwd").is_err());
+        assert!(check_component_path("../spawn.toml").is_err());
+        assert!(check_component_path("data/../../secret").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_optional_helpers_follow_template_config() {
+        use crate::config::FolderPather;
+        use crate::store::pinner::latest::Latest;
+        use opendal::services::Memory;
+        use opendal::Operator;
+
+        let new_store = || {
+            let op = Operator::new(Memory::default()).unwrap().finish();
+            let pather = FolderPather {
+                spawn_folder: "".to_string(),
+                ..Default::default()
+            };
+            Store::new(Box::new(Latest::new("").unwrap()), op, pather).unwrap()
+        };
+        let source = r#"{{ "abc"|sha256 }} {{ timestamp() > 0 }}"#;
+
+        let mut env = template_env(
+            new_store(),
+            &EngineType::PostgresPSQL,
+            &config::TemplateConfig::default(),
+        )
+        .unwrap();
+        env.add_template("test.sql", source).unwrap();
+        assert!(env
+            .get_template("test.sql")
+            .unwrap()
+            .render(context!())
+            .is_err());
+
+        let template_cfg = config::TemplateConfig {
+            time_functions: true,
+            hash_filters: true,
+            ..Default::default()
+        };
+        let mut env = template_env(new_store(), &EngineType::PostgresPSQL, &template_cfg).unwrap();
+        env.add_template("test.sql", source).unwrap();
+        let result = env
+            .get_template("test.sql")
+            .unwrap()
+            .render(context!())
+            .unwrap();
+        assert_eq!(
+            result,
+            "'ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad' TRUE"
+        );
+    }
+
+    fn render_with_limits(
+        source: &str,
+        components: &[(&str, &str)],
+        limits: TemplateLimits,
+    ) -> Result<String> {
+        use crate::config::FolderPather;
+        use crate::store::pinner::latest::Latest;
+        use opendal::services::Memory;
+        use opendal::Operator;
+
+        let op = Operator::new(Memory::default()).unwrap().finish();
+        let rt = tokio::runtime::Handle::current();
+        for (name, contents) in components {
+            rt.block_on(op.write(&format!("components/{}", name), contents.to_string()))
+                .unwrap();
+        }
+        let pather = FolderPather {
+            spawn_folder: "".to_string(),
+            ..Default::default()
+        };
+        let store = Store::new(Box::new(Latest::new("").unwrap()), op, pather).unwrap();
+        let generation = StreamingGeneration {
+            store,
+            template_contents: source.to_string(),
+            environment: "dev".to_string(),
+            variables: Variables::default(),
+            engine: EngineType::PostgresPSQL,
+            template_cfg: config::TemplateConfig {
+                limits,
+                ..Default::default()
+            },
+            globals: Vec::new(),
+        };
+        let mut out = Vec::new();
+        generation.render_to_writer(&mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_render_limits() {
+        tokio::task::spawn_blocking(|| {
+            let endless =
+                "{% for i in range(10000) %}{% for j in range(10000) %}{% endfor %}{% endfor %}";
+            let err = render_with_limits(
+                endless,
+                &[],
+                TemplateLimits {
+                    fuel: 10_000,
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("template.limits.fuel"),
+                "{:#}",
+                err
+            );
+
+            let recursive = "{% include 'loop.sql' %}";
+            let err = render_with_limits(
+                recursive,
+                &[("loop.sql", recursive)],
+                TemplateLimits {
+                    max_include_depth: 10,
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+            assert!(format!("{:#}", err).contains("recursion"), "{:#}", err);
+
+            let limits = TemplateLimits {
+                max_template_size_kb: 1,
+                ..Default::default()
+            };
+            let large = "x".repeat(2000);
+            let err = render_with_limits(&large, &[], limits.clone()).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("migration is 2 KB"),
+                "{:#}",
+                err
+            );
+            let err = render_with_limits(
+                "{% include 'large.sql' %}",
+                &[("large.sql", &large)],
+                limits,
+            )
+            .unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("component large.sql is 2 KB"),
+                "{:#}",
+                err
+            );
+
+            assert_eq!(
+                "SELECT 1;",
+                render_with_limits("SELECT 1;", &[], TemplateLimits::default()).unwrap()
+            );
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_deadline_writer_ignores_time_spent_writing() {
+        struct SlowWriter;
+        impl std::io::Write for SlowWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                std::thread::sleep(Duration::from_millis(30));
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        use std::io::Write;
+
+        let mut slow = SlowWriter;
+        let mut writer = DeadlineWriter::new(&mut slow, Duration::from_millis(20));
+        for _ in 0..3 {
+            writer.write_all(b"SELECT 1;").unwrap();
+        }
+
+        std::thread::sleep(Duration::from_millis(30));
+        let err = writer.write_all(b"SELECT 1;").unwrap_err();
+        assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn test_filter_tenants() {
+        let tenants = vec![
+            "acme".to_string(),
+            "globex".to_string(),
+            "initech".to_string(),
+        ];
+
+        let all = filter_tenants(tenants.clone(), &[]).unwrap();
+        assert_eq!(all, tenants);
+
+        let some = filter_tenants(
+            tenants.clone(),
+            &["initech".to_string(), "acme".to_string()],
+        )
+        .unwrap();
+        assert_eq!(some, vec!["acme".to_string(), "initech".to_string()]);
+
+        assert!(filter_tenants(tenants, &["umbrella".to_string()]).is_err());
+    }
+
     #[test]
     fn test_base64_encode_filter() {
         let bytes = Value::from_bytes(vec![0xDE, 0xAD, 0xBE, 0xEF]);
@@ -558,10 +1236,16 @@ mod tests {
         let pinner = Latest::new("").unwrap();
         let pather = FolderPather {
             spawn_folder: "".to_string(),
+            ..Default::default()
         };
         let store = Store::new(Box::new(pinner), op, pather).unwrap();
 
-        let mut env = template_env(store, &EngineType::PostgresPSQL).unwrap();
+        let mut env = template_env(
+            store,
+            &EngineType::PostgresPSQL,
+            &config::TemplateConfig::default(),
+        )
+        .unwrap();
         env.add_template(
             "test.sql",
             r#"{{ "test.txt"|read_file|to_string_lossy|safe }}"#,
@@ -588,10 +1272,16 @@ mod tests {
         let pinner = Latest::new("").unwrap();
         let pather = FolderPather {
             spawn_folder: "".to_string(),
+            ..Default::default()
         };
         let store = Store::new(Box::new(pinner), op, pather).unwrap();
 
-        let mut env = template_env(store, &EngineType::PostgresPSQL).unwrap();
+        let mut env = template_env(
+            store,
+            &EngineType::PostgresPSQL,
+            &config::TemplateConfig::default(),
+        )
+        .unwrap();
         env.add_template(
             "test.sql",
             r#"{{ "binary.dat"|read_file|base64_encode|safe }}"#,
@@ -615,10 +1305,16 @@ mod tests {
         let pinner = Latest::new("").unwrap();
         let pather = FolderPather {
             spawn_folder: "".to_string(),
+            ..Default::default()
         };
         let store = Store::new(Box::new(pinner), op, pather).unwrap();
 
-        let mut env = template_env(store, &EngineType::PostgresPSQL).unwrap();
+        let mut env = template_env(
+            store,
+            &EngineType::PostgresPSQL,
+            &config::TemplateConfig::default(),
+        )
+        .unwrap();
         env.add_template(
             "test.sql",
             r#"{{ "nonexistent.txt"|read_file|to_string_lossy }}"#,
@@ -655,16 +1351,23 @@ mod tests {
             "components/".to_string(),
             &root_hash,
             &op,
+            None,
         )
         .await
         .unwrap();
 
         let pather = FolderPather {
             spawn_folder: "".to_string(),
+            ..Default::default()
         };
         let store = Store::new(Box::new(pinner), op, pather).unwrap();
 
-        let mut env = template_env(store, &EngineType::PostgresPSQL).unwrap();
+        let mut env = template_env(
+            store,
+            &EngineType::PostgresPSQL,
+            &config::TemplateConfig::default(),
+        )
+        .unwrap();
         env.add_template(
             "test.sql",
             r#"{{ "test.txt"|read_file|to_string_lossy|safe }}"#,
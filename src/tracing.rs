@@ -0,0 +1,426 @@
+//! OpenTelemetry tracing, configured with the `[tracing]` section. Each
+//! command is a span, with a span for each migration it applies and each
+//! statement of those migrations. The spans are sent to an OTLP/HTTP
+//! collector when the command finishes. A trace started by deploy
+//! automation is continued when `TRACEPARENT` is set, so migrations show up
+//! in the same trace as the service deploy.
+
+use crate::engine::HistoryDetails;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_service_name() -> String {
+    "spawn".to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+/// Where spans are sent when neither `endpoint` nor
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+const DEFAULT_ENDPOINT: &str = "http://localhost:4318";
+
+/// Configuration for tracing, in the `[tracing]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TracingConfig {
+    /// Set to false to stop sending spans without removing the section.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// OTLP/HTTP endpoint of the collector. Spans are posted to
+    /// `<endpoint>/v1/traces`. Defaults to `OTEL_EXPORTER_OTLP_ENDPOINT`, or
+    /// else `http://localhost:4318`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// Headers sent with the spans, such as an API key
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// `service.name` of the spans
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Seconds to wait for the collector to answer
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            endpoint: None,
+            headers: BTreeMap::new(),
+            service_name: default_service_name(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+}
+
+impl TracingConfig {
+    /// The URL spans are posted to.
+    pub fn traces_url(&self) -> String {
+        let endpoint = self
+            .endpoint
+            .clone()
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok())
+            .filter(|e| !e.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+        let endpoint = endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/traces", endpoint)
+        }
+    }
+}
+
+/// What a span does, as OTLP's `SpanKind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal = 1,
+    Client = 3,
+}
+
+#[derive(Clone, Debug)]
+struct SpanData {
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+#[derive(Debug)]
+struct TracerInner {
+    config: TracingConfig,
+    trace_id: String,
+    /// Span of the command, which every other span is a child of
+    command_span_id: String,
+    /// Span of the caller, from `TRACEPARENT`
+    parent_span_id: Option<String>,
+    started: SystemTime,
+    spans: Mutex<Vec<SpanData>>,
+}
+
+/// Collects the spans of the running command. Does nothing unless tracing
+/// is enabled.
+#[derive(Clone, Debug, Default)]
+pub struct Tracer {
+    inner: Option<Arc<TracerInner>>,
+}
+
+impl Tracer {
+    /// A tracer for a command starting now, or one that does nothing when
+    /// `config` is None. Continues the trace in `TRACEPARENT` if it is set.
+    pub fn new(config: Option<&TracingConfig>) -> Self {
+        let Some(config) = config else {
+            return Self::default();
+        };
+        let parent = std::env::var("TRACEPARENT")
+            .ok()
+            .and_then(|t| parse_traceparent(&t));
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (new_trace_id(), None),
+        };
+        Self {
+            inner: Some(Arc::new(TracerInner {
+                config: config.clone(),
+                trace_id,
+                command_span_id: new_span_id(),
+                parent_span_id,
+                started: SystemTime::now(),
+                spans: Mutex::new(Vec::new()),
+            })),
+        }
+    }
+
+    /// The id of the trace, when tracing is enabled.
+    pub fn trace_id(&self) -> Option<&str> {
+        self.inner.as_ref().map(|inner| inner.trace_id.as_str())
+    }
+
+    /// Starts a span that is a child of the command's span.
+    pub fn span(&self, name: &str, kind: SpanKind) -> Span {
+        let parent = self.inner.as_ref().map(|i| i.command_span_id.clone());
+        Span::start(self.clone(), parent, name, kind)
+    }
+
+    /// Adds the trace id to what is recorded in the migration history, so
+    /// an entry can be found from its trace and the other way around.
+    pub fn annotate(&self, mut details: HistoryDetails) -> HistoryDetails {
+        if let Some(trace_id) = self.trace_id() {
+            details.description = Some(match details.description {
+                Some(description) => format!("{} (trace {})", description, trace_id),
+                None => format!("trace {}", trace_id),
+            });
+        }
+        details
+    }
+
+    fn record(&self, span: SpanData) {
+        if let Some(inner) = &self.inner {
+            inner.spans.lock().unwrap().push(span);
+        }
+    }
+
+    /// Ends the command's span, named `command`, and sends every span to
+    /// the collector.
+    pub async fn finish(
+        &self,
+        command: &str,
+        attributes: Vec<(String, String)>,
+        error: Option<String>,
+    ) -> Result<()> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+        self.record(SpanData {
+            span_id: inner.command_span_id.clone(),
+            parent_span_id: inner.parent_span_id.clone(),
+            name: format!("spawn {}", command),
+            kind: SpanKind::Internal,
+            start: inner.started,
+            end: SystemTime::now(),
+            attributes,
+            error,
+        });
+
+        let mut request = reqwest::Client::new()
+            .post(inner.config.traces_url())
+            .header("Content-Type", "application/json")
+            .timeout(Duration::from_secs(inner.config.timeout_secs))
+            .body(self.to_otlp().to_string());
+        for (name, value) in &inner.config.headers {
+            request = request.header(name, value);
+        }
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                anyhow!(
+                    "could not send spans to {}: {}",
+                    inner.config.traces_url(),
+                    e
+                )
+            })?;
+        Ok(())
+    }
+
+    /// The spans recorded so far, as an OTLP/JSON `ExportTraceServiceRequest`.
+    pub fn to_otlp(&self) -> Value {
+        let Some(inner) = &self.inner else {
+            return json!({ "resourceSpans": [] });
+        };
+        let spans: Vec<Value> = inner
+            .spans
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|span| {
+                let mut value = json!({
+                    "traceId": inner.trace_id,
+                    "spanId": span.span_id,
+                    "name": span.name,
+                    "kind": span.kind as i32,
+                    "startTimeUnixNano": unix_nanos(span.start),
+                    "endTimeUnixNano": unix_nanos(span.end),
+                    "attributes": attributes(&span.attributes),
+                    "status": match &span.error {
+                        Some(message) => json!({ "code": 2, "message": message }),
+                        None => json!({ "code": 1 }),
+                    },
+                });
+                if let Some(parent) = &span.parent_span_id {
+                    value["parentSpanId"] = parent.clone().into();
+                }
+                value
+            })
+            .collect();
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": attributes(&[
+                        ("service.name".to_string(), inner.config.service_name.clone()),
+                        ("service.version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+                    ]),
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "spawn", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+}
+
+/// A span that is recorded when it ends. Dropping it without ending it
+/// records nothing.
+#[derive(Clone, Debug)]
+pub struct Span {
+    tracer: Tracer,
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(String, String)>,
+}
+
+impl Span {
+    fn start(tracer: Tracer, parent_span_id: Option<String>, name: &str, kind: SpanKind) -> Self {
+        Span {
+            tracer,
+            span_id: new_span_id(),
+            parent_span_id,
+            name: name.to_string(),
+            kind,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Starts a span that is a child of this one.
+    pub fn child(&self, name: &str, kind: SpanKind) -> Span {
+        Span::start(self.tracer.clone(), Some(self.span_id.clone()), name, kind)
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: impl Into<String>) -> Self {
+        if self.tracer.inner.is_some() {
+            self.attributes.push((key.to_string(), value.into()));
+        }
+        self
+    }
+
+    /// Ends the span now, as failed when there is an `error`.
+    pub fn end(self, error: Option<String>) {
+        let tracer = self.tracer.clone();
+        tracer.record(SpanData {
+            span_id: self.span_id,
+            parent_span_id: self.parent_span_id,
+            name: self.name,
+            kind: self.kind,
+            start: self.start,
+            end: SystemTime::now(),
+            attributes: self.attributes,
+            error,
+        });
+    }
+}
+
+/// The trace id and span id of a W3C `traceparent`, such as
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+pub fn parse_traceparent(traceparent: &str) -> Option<(String, String)> {
+    let parts: Vec<&str> = traceparent.trim().split('-').collect();
+    let [_version, trace_id, span_id, _flags] = parts[..] else {
+        return None;
+    };
+    let hex = |s: &str, len: usize| {
+        s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit()) && s.chars().any(|c| c != '0')
+    };
+    if !hex(trace_id, 32) || !hex(span_id, 16) {
+        return None;
+    }
+    Some((trace_id.to_lowercase(), span_id.to_lowercase()))
+}
+
+fn new_trace_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+fn new_span_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn attributes(attributes: &[(String, String)]) -> Vec<Value> {
+    attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent() {
+        assert_eq!(
+            Some((
+                "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+                "00f067aa0ba902b7".to_string()
+            )),
+            parse_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01")
+        );
+        assert_eq!(
+            None,
+            parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01")
+        );
+        assert_eq!(None, parse_traceparent("00-4bf92f35-00f067aa0ba902b7-01"));
+        assert_eq!(None, parse_traceparent(""));
+    }
+
+    #[test]
+    fn test_spans_to_otlp() {
+        let tracer = Tracer::new(Some(&TracingConfig::default()));
+        let migration = tracer
+            .span("migration 20240501-add-users", SpanKind::Internal)
+            .with_attribute("spawn.migration", "20240501-add-users");
+        migration
+            .child("statement", SpanKind::Client)
+            .end(Some("syntax error".to_string()));
+        migration.end(None);
+
+        let otlp = tracer.to_otlp();
+        let spans = otlp["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .unwrap();
+        assert_eq!(2, spans.len());
+        let (statement, migration) = (&spans[0], &spans[1]);
+        assert_eq!(migration["spanId"], statement["parentSpanId"]);
+        assert_eq!(2, statement["status"]["code"]);
+        assert_eq!("syntax error", statement["status"]["message"]);
+        assert_eq!(
+            json!([{ "key": "spawn.migration", "value": { "stringValue": "20240501-add-users" } }]),
+            migration["attributes"]
+        );
+        assert_eq!(tracer.trace_id().unwrap(), migration["traceId"]);
+    }
+
+    #[test]
+    fn test_annotate_history() {
+        let details = HistoryDetails {
+            description: Some("ABC-1: add users".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(details, Tracer::default().annotate(details.clone()));
+
+        let tracer = Tracer::new(Some(&TracingConfig::default()));
+        let annotated = tracer.annotate(details);
+        assert_eq!(
+            Some(format!(
+                "ABC-1: add users (trace {})",
+                tracer.trace_id().unwrap()
+            )),
+            annotated.description
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Transcripts of migration applies: the SQL sent to the database, what
+//! psql printed, how long it took and how it ended. Written after each
+//! apply when enabled, so that operators have a record to look at when
+//! something goes wrong. Secret values are redacted, but transcripts still
+//! hold the rendered SQL and should be kept out of version control.
+
+use crate::config::Config;
+use crate::engine::{StdoutWriter, WriterFn};
+use crate::secrets::redact;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Configuration for apply transcripts, in the `[transcripts]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TranscriptConfig {
+    /// Set to false to stop writing transcripts without removing the section.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Folder to write transcripts to, relative to the spawn folder. There is
+    /// no default, as the folder should be one that is not committed.
+    pub folder: String,
+}
+
+/// Records what is sent to and printed by the database while a migration is
+/// applied.
+pub struct TranscriptRecorder {
+    started: DateTime<Utc>,
+    sql: Arc<Mutex<Vec<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl TranscriptRecorder {
+    /// Starts recording. Returns the recorder, along with `write_fn` wrapped
+    /// to keep a copy of the SQL and a writer for psql's output, to pass to
+    /// the engine in their place.
+    pub fn start(write_fn: WriterFn) -> (Self, WriterFn, StdoutWriter) {
+        let recorder = Self {
+            started: Utc::now(),
+            sql: Arc::new(Mutex::new(Vec::new())),
+            output: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        let sql = recorder.sql.clone();
+        let write_fn: WriterFn = Box::new(move |writer| {
+            let mut tee = TeeWriter {
+                inner: writer,
+                copy: sql,
+            };
+            write_fn(&mut tee)
+        });
+        let output: StdoutWriter = Some(Box::new(SharedBufWriter(recorder.output.clone())));
+
+        (recorder, write_fn, output)
+    }
+
+    /// Finishes the transcript with the outcome of the apply, with the
+    /// values of any secrets redacted.
+    pub fn finish(self, migration: &str, target: &str, result: Result<(), String>) -> Transcript {
+        let finished = Utc::now();
+        Transcript {
+            migration: migration.to_string(),
+            target: target.to_string(),
+            started: self.started,
+            duration: (finished - self.started).to_std().unwrap_or_default(),
+            sql: redact(&String::from_utf8_lossy(&self.sql.lock().unwrap())),
+            output: redact(&String::from_utf8_lossy(&self.output.lock().unwrap())),
+            result: result.map_err(|e| redact(&e)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    pub migration: String,
+    pub target: String,
+    pub started: DateTime<Utc>,
+    pub duration: Duration,
+    pub sql: String,
+    pub output: String,
+    /// Ok, or the error the apply failed with
+    pub result: Result<(), String>,
+}
+
+impl Transcript {
+    pub fn render(&self) -> String {
+        let result = match &self.result {
+            Ok(()) => "SUCCESS".to_string(),
+            Err(e) => format!("FAILURE\n{}", e),
+        };
+        format!(
+            "Migration: {}\nTarget: {}\nStarted: {}\nDuration: {:.3}s\nResult: {}\n\n--- SQL ---\n{}\n--- Output ---\n{}",
+            self.migration,
+            self.target,
+            self.started.to_rfc3339(),
+            self.duration.as_secs_f64(),
+            result,
+            self.sql.trim_end(),
+            self.output,
+        )
+    }
+
+    /// Path of the transcript, `<folder>/<migration>-<timestamp>.log`.
+    pub fn path(&self, config: &Config, cfg: &TranscriptConfig) -> String {
+        format!(
+            "{}/{}/{}-{}.log",
+            config.pather().spawn_folder_path(),
+            cfg.folder,
+            self.migration,
+            self.started.format("%Y%m%d%H%M%S")
+        )
+    }
+
+    /// Writes the transcript and returns its path.
+    pub async fn save(&self, config: &Config, cfg: &TranscriptConfig) -> Result<String> {
+        let path = self.path(config, cfg);
+        config
+            .operator()
+            .write(&path, self.render())
+            .await
+            .context(format!("could not write transcript {}", path))?;
+        Ok(path)
+    }
+}
+
+/// Passes writes through to `inner`, keeping a copy.
+struct TeeWriter<'a> {
+    inner: &'a mut dyn Write,
+    copy: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Write for TeeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.copy.lock().unwrap().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A simple AsyncWrite implementation that appends to a shared Vec<u8>
+pub(crate) struct SharedBufWriter(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+impl tokio::io::AsyncWrite for SharedBufWriter {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_records_sql_and_output() {
+        let write_fn: WriterFn = Box::new(|writer| writer.write_all(b"CREATE TABLE t ();\n"));
+        let (recorder, write_fn, output) = TranscriptRecorder::start(write_fn);
+
+        let mut sent = Vec::new();
+        write_fn(&mut sent).unwrap();
+        assert_eq!(b"CREATE TABLE t ();\n".to_vec(), sent);
+        use tokio::io::AsyncWriteExt;
+        output.unwrap().write_all(b"NOTICE: done\n").await.unwrap();
+
+        let transcript = recorder.finish("20240101-users", "local", Err("boom".to_string()));
+        let rendered = transcript.render();
+        assert!(rendered.starts_with("Migration: 20240101-users\nTarget: local\n"));
+        assert!(rendered.contains("Result: FAILURE\nboom\n"));
+        assert!(
+            rendered.ends_with("--- SQL ---\nCREATE TABLE t ();\n--- Output ---\nNOTICE: done\n")
+        );
+    }
+}
@@ -0,0 +1,781 @@
+//! Checks of the text of spawn.toml, so that mistakes are reported against
+//! the key that caused them rather than as a serde error about the whole
+//! file. Run when the config is loaded, where errors stop the load, and by
+//! `spawn check`, which also reports warnings.
+
+use crate::schedule::MaintenanceWindow;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The config cannot be used
+    Error,
+    /// The config loads, but probably not as intended, such as a misspelt
+    /// key that is ignored
+    Warning,
+}
+
+/// A problem with one key of the config file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigDiagnostic {
+    pub severity: Severity,
+    pub file: String,
+    /// 1-based line of the key, when it could be found
+    pub line: Option<usize>,
+    /// Dotted path of the key, e.g. `targets.prod.engine`
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: ", self.file, line)?,
+            None => write!(f, "{}: ", self.file)?,
+        }
+        if !self.key.is_empty() {
+            write!(f, "{}: ", self.key)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// The text of a config file, and where it was read from.
+#[derive(Clone, Debug)]
+pub struct ConfigSource {
+    pub file: String,
+    pub text: String,
+}
+
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "spawn_folder",
+    "target",
+    "environment",
+    "targets",
+    "project_id",
+    "telemetry",
+    "tenants",
+    "template",
+    "pin_on_apply",
+    "edit_new_migrations",
+    "naming",
+    "allow_out_of_order",
+    "identity",
+    "pin_cache",
+    "signing",
+    "transcripts",
+    "environments",
+    "variables",
+    "packages",
+    "meta_commands",
+    "policy",
+    "lock_advisor",
+    "notifications",
+    "tracing",
+    "audit",
+    "layout",
+    "agent",
+    "schema_docs",
+    "scrub",
+];
+const TARGET_KEYS: &[&str] = &[
+    "engine",
+    "spawn_database",
+    "spawn_schema",
+    "environment",
+    "protected",
+    "command",
+    "url",
+    "session",
+    "history",
+    "retry",
+];
+const HISTORY_KEYS: &[&str] = &["command", "url", "spawn_database", "spawn_schema", "file"];
+const SESSION_KEYS: &[&str] = &["statement_timeout", "lock_timeout", "role", "search_path"];
+const RETRY_KEYS: &[&str] = &["max_attempts", "initial_backoff_ms", "max_backoff_ms"];
+const ENGINES: &[&str] = &["postgres-psql", "memory"];
+const DIRECT_KEYS: &[&str] = &["kind", "direct"];
+const PROVIDER_KEYS: &[&str] = &["kind", "provider", "append"];
+const KUBECTL_KEYS: &[&str] = &[
+    "kind",
+    "context",
+    "namespace",
+    "pod",
+    "selector",
+    "container",
+    "user",
+    "database",
+];
+const ENVIRONMENT_KEYS: &[&str] = &[
+    "allowed_commands",
+    "variables_file",
+    "meta_commands",
+    "maintenance_window",
+];
+const TEMPLATE_KEYS: &[&str] = &[
+    "constants",
+    "time_functions",
+    "hash_filters",
+    "deterministic",
+    "secrets",
+    "limits",
+];
+const LIMITS_KEYS: &[&str] = &[
+    "max_template_size_kb",
+    "max_include_depth",
+    "render_timeout_secs",
+    "fuel",
+];
+const TELEMETRY_KEYS: &[&str] = &["enabled", "endpoint"];
+const NAMING_KEYS: &[&str] = &["pattern", "prefix", "max_length", "hint", "exempt_before"];
+const PIN_CACHE_KEYS: &[&str] = &["enabled", "directory", "max_size_mb"];
+const SIGNING_KEYS: &[&str] = &["trusted_keys", "key_file"];
+const TRANSCRIPTS_KEYS: &[&str] = &["enabled", "folder"];
+const PACKAGE_KEYS: &[&str] = &["git", "rev", "path", "folder", "hash"];
+const META_COMMANDS_KEYS: &[&str] = &["forbid", "copy_paths"];
+const POLICY_KEYS: &[&str] = &["rule"];
+const LOCK_ADVISOR_KEYS: &[&str] = &["enabled", "min_table_size_mb"];
+const NOTIFICATIONS_KEYS: &[&str] = &["webhook"];
+const WEBHOOK_KEYS: &[&str] = &[
+    "url",
+    "url_env",
+    "template",
+    "on",
+    "environments",
+    "timeout_secs",
+];
+const TRACING_KEYS: &[&str] = &[
+    "enabled",
+    "endpoint",
+    "headers",
+    "service_name",
+    "timeout_secs",
+];
+const AUDIT_KEYS: &[&str] = &["enabled", "path"];
+const LAYOUT_KEYS: &[&str] = &["migrations_folder", "script", "lock_file", "flat", "shard"];
+const AGENT_KEYS: &[&str] = &["queue"];
+const SCHEMA_DOCS_KEYS: &[&str] = &["after_apply", "output", "schemas"];
+const SCRUB_KEYS: &[&str] = &["rules", "targets"];
+const POLICY_RULE_KEYS: &[&str] = &[
+    "name",
+    "match",
+    "unless",
+    "message",
+    "severity",
+    "environments",
+];
+
+struct Checker<'a> {
+    source: &'a ConfigSource,
+    diagnostics: Vec<ConfigDiagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, path: &[&str], message: String) {
+        self.diagnostics.push(ConfigDiagnostic {
+            severity,
+            file: self.source.file.clone(),
+            line: find_key_line(&self.source.text, path),
+            key: path.join("."),
+            message,
+        });
+    }
+
+    /// Warns about keys of `table` that are not in `known`.
+    fn unknown_keys(&mut self, table: &toml::Table, path: &[&str], known: &[&str]) {
+        for key in table.keys() {
+            if known.contains(&key.as_str()) {
+                continue;
+            }
+            let message = match closest(key, known) {
+                Some(suggestion) => format!("unknown key, did you mean `{}`?", suggestion),
+                None => "unknown key".to_string(),
+            };
+            let mut key_path = path.to_vec();
+            key_path.push(key);
+            self.report(Severity::Warning, &key_path, message);
+        }
+    }
+
+    /// `value` as a table, reporting an error if it is not one.
+    fn expect_table<'t>(
+        &mut self,
+        value: &'t toml::Value,
+        path: &[&str],
+    ) -> Option<&'t toml::Table> {
+        if value.as_table().is_none() {
+            self.report(
+                Severity::Error,
+                path,
+                format!("expected a table, found {}", value.type_str()),
+            );
+        }
+        value.as_table()
+    }
+
+    /// The table at `key` of `parent`, if there is one, checked for
+    /// unknown keys.
+    fn table<'t>(
+        &mut self,
+        parent: &'t toml::Table,
+        path: &[&str],
+        key: &str,
+        known: &[&str],
+    ) -> Option<&'t toml::Table> {
+        let mut key_path = path.to_vec();
+        key_path.push(key);
+        let table = self.expect_table(parent.get(key)?, &key_path)?;
+        self.unknown_keys(table, &key_path, known);
+        Some(table)
+    }
+
+    fn target(&mut self, name: &str, target: &toml::Value) {
+        let path = ["targets", name];
+        let Some(target) = self.expect_table(target, &path) else {
+            return;
+        };
+        self.unknown_keys(target, &path, TARGET_KEYS);
+
+        match target.get("engine") {
+            None => self.report(
+                Severity::Error,
+                &path,
+                format!("missing `engine` (one of {})", ENGINES.join(", ")),
+            ),
+            Some(toml::Value::String(engine)) if ENGINES.contains(&engine.as_str()) => {}
+            Some(engine) => self.report(
+                Severity::Error,
+                &["targets", name, "engine"],
+                format!(
+                    "unknown engine {}, expected one of {}",
+                    engine,
+                    ENGINES.join(", ")
+                ),
+            ),
+        }
+
+        self.table(target, &path, "session", SESSION_KEYS);
+        self.table(target, &path, "retry", RETRY_KEYS);
+        if let Some(command) = target.get("command") {
+            self.command(&["targets", name, "command"], command);
+        }
+        if let Some(history) = self.table(target, &path, "history", HISTORY_KEYS) {
+            if let Some(command) = history.get("command") {
+                self.command(&["targets", name, "history", "command"], command);
+            }
+        }
+    }
+
+    fn command(&mut self, path: &[&str], command: &toml::Value) {
+        let at = |key| [path, &[key]].concat();
+        let Some(command) = self.expect_table(command, path) else {
+            return;
+        };
+
+        let kind = match command.get("kind") {
+            Some(toml::Value::String(kind)) => kind.as_str(),
+            Some(kind) => {
+                return self.report(
+                    Severity::Error,
+                    &at("kind"),
+                    format!("expected a string, found {}", kind.type_str()),
+                )
+            }
+            None => {
+                return self.report(
+                    Severity::Error,
+                    path,
+                    "missing `kind` (one of direct, provider, kubectl)".to_string(),
+                )
+            }
+        };
+        let (known, required) = match kind {
+            "direct" => (DIRECT_KEYS, Some("direct")),
+            "provider" => (PROVIDER_KEYS, Some("provider")),
+            "kubectl" => (KUBECTL_KEYS, None),
+            other => {
+                return self.report(
+                    Severity::Error,
+                    &at("kind"),
+                    format!(
+                        "unknown command kind \"{}\", expected one of direct, provider, kubectl",
+                        other
+                    ),
+                )
+            }
+        };
+        self.unknown_keys(command, path, known);
+
+        if let Some(required) = required {
+            match command.get(required) {
+                Some(toml::Value::Array(args)) if !args.is_empty() => {}
+                Some(toml::Value::Array(_)) => self.report(
+                    Severity::Error,
+                    &at(required),
+                    "must not be empty".to_string(),
+                ),
+                Some(other) => self.report(
+                    Severity::Error,
+                    &at(required),
+                    format!("expected an array of strings, found {}", other.type_str()),
+                ),
+                None => self.report(
+                    Severity::Error,
+                    path,
+                    format!("`kind = \"{}\"` needs `{}`", kind, required),
+                ),
+            }
+        } else if !command.contains_key("pod") && !command.contains_key("selector") {
+            self.report(
+                Severity::Error,
+                path,
+                "`kind = \"kubectl\"` needs `pod` or `selector`".to_string(),
+            );
+        }
+    }
+}
+
+/// Checks the config file in `source`.
+pub fn check(source: &ConfigSource) -> Vec<ConfigDiagnostic> {
+    let mut checker = Checker {
+        source,
+        diagnostics: Vec::new(),
+    };
+
+    let root: toml::Table = match toml::from_str(&source.text) {
+        Ok(root) => root,
+        Err(e) => {
+            let line = e
+                .span()
+                .map(|span| source.text[..span.start].matches('\n').count() + 1);
+            checker.diagnostics.push(ConfigDiagnostic {
+                severity: Severity::Error,
+                file: source.file.clone(),
+                line,
+                key: String::new(),
+                message: e.message().to_string(),
+            });
+            return checker.diagnostics;
+        }
+    };
+    checker.unknown_keys(&root, &[], TOP_LEVEL_KEYS);
+
+    let targets = match root.get("targets") {
+        Some(targets) => checker.expect_table(targets, &["targets"]),
+        None => None,
+    };
+    if let Some(targets) = targets {
+        for (name, target) in targets {
+            checker.target(name, target);
+        }
+    }
+
+    if let Some(toml::Value::String(target)) = root.get("target") {
+        if !targets.is_some_and(|t| t.contains_key(target)) {
+            let defined: Vec<&str> = targets
+                .map(|t| t.keys().map(String::as_str).collect())
+                .unwrap_or_default();
+            let message = if defined.is_empty() {
+                format!(
+                    "no target named \"{}\", and no [targets] are defined",
+                    target
+                )
+            } else {
+                format!(
+                    "no target named \"{}\" in [targets] (defined: {})",
+                    target,
+                    defined.join(", ")
+                )
+            };
+            // Only commands that use the target fail because of this.
+            checker.report(Severity::Warning, &["target"], message);
+        }
+    }
+
+    if let Some(environments) = root.get("environments").and_then(|e| e.as_table()) {
+        for (name, environment) in environments {
+            let path = ["environments", name.as_str()];
+            if let Some(environment) = checker.expect_table(environment, &path) {
+                checker.unknown_keys(environment, &path, ENVIRONMENT_KEYS);
+                checker.table(environment, &path, "meta_commands", META_COMMANDS_KEYS);
+                let window = environment.get("maintenance_window");
+                if let Some(window) = window.and_then(|w| w.as_str()) {
+                    if let Err(e) = window.parse::<MaintenanceWindow>() {
+                        checker.report(
+                            Severity::Error,
+                            &["environments", name.as_str(), "maintenance_window"],
+                            e.to_string(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    if let Some(packages) = root.get("packages").and_then(|p| p.as_table()) {
+        for (name, package) in packages {
+            let path = ["packages", name.as_str()];
+            if let Some(package) = checker.expect_table(package, &path) {
+                checker.unknown_keys(package, &path, PACKAGE_KEYS);
+                if package.contains_key("git") == package.contains_key("path") {
+                    checker.report(
+                        Severity::Error,
+                        &path,
+                        "packages need exactly one of `git` or `path`".to_string(),
+                    );
+                }
+            }
+        }
+    }
+    if let Some(template) = checker.table(&root, &[], "template", TEMPLATE_KEYS) {
+        checker.table(template, &["template"], "limits", LIMITS_KEYS);
+    }
+    // `telemetry = false` is also allowed.
+    if root.get("telemetry").is_some_and(|t| t.is_table()) {
+        checker.table(&root, &[], "telemetry", TELEMETRY_KEYS);
+    }
+    checker.table(&root, &[], "naming", NAMING_KEYS);
+    checker.table(&root, &[], "pin_cache", PIN_CACHE_KEYS);
+    checker.table(&root, &[], "signing", SIGNING_KEYS);
+    checker.table(&root, &[], "transcripts", TRANSCRIPTS_KEYS);
+    checker.table(&root, &[], "meta_commands", META_COMMANDS_KEYS);
+    checker.table(&root, &[], "lock_advisor", LOCK_ADVISOR_KEYS);
+    checker.table(&root, &[], "tracing", TRACING_KEYS);
+    checker.table(&root, &[], "audit", AUDIT_KEYS);
+    checker.table(&root, &[], "layout", LAYOUT_KEYS);
+    checker.table(&root, &[], "agent", AGENT_KEYS);
+    checker.table(&root, &[], "schema_docs", SCHEMA_DOCS_KEYS);
+    checker.table(&root, &[], "scrub", SCRUB_KEYS);
+    if let Some(notifications) = checker.table(&root, &[], "notifications", NOTIFICATIONS_KEYS) {
+        let webhooks = notifications.get("webhook").and_then(|w| w.as_array());
+        for webhook in webhooks.into_iter().flatten() {
+            let path = ["notifications", "webhook"];
+            if let Some(webhook) = checker.expect_table(webhook, &path) {
+                checker.unknown_keys(webhook, &path, WEBHOOK_KEYS);
+                if webhook.contains_key("url") == webhook.contains_key("url_env") {
+                    checker.report(
+                        Severity::Error,
+                        &path,
+                        "webhooks need exactly one of `url` or `url_env`".to_string(),
+                    );
+                }
+                let on = webhook.get("on").and_then(|o| o.as_array());
+                for status in on.into_iter().flatten().filter_map(|s| s.as_str()) {
+                    if !matches!(status, "applied" | "adopted" | "failed") {
+                        checker.report(
+                            Severity::Error,
+                            &["notifications", "webhook", "on"],
+                            format!(
+                                "unknown outcome \"{}\", expected one of applied, adopted, failed",
+                                status
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    if let Some(policy) = checker.table(&root, &[], "policy", POLICY_KEYS) {
+        let rules = policy.get("rule").and_then(|r| r.as_array());
+        for rule in rules.into_iter().flatten() {
+            let path = ["policy", "rule"];
+            if let Some(rule) = checker.expect_table(rule, &path) {
+                checker.unknown_keys(rule, &path, POLICY_RULE_KEYS);
+                for key in ["name", "match"] {
+                    if !rule.contains_key(key) {
+                        checker.report(Severity::Error, &path, format!("rules need a `{}`", key));
+                    }
+                }
+                if let Some(severity) = rule.get("severity").and_then(|s| s.as_str()) {
+                    if !matches!(severity, "warn" | "block") {
+                        checker.report(
+                            Severity::Error,
+                            &["policy", "rule", "severity"],
+                            format!(
+                                "unknown severity \"{}\", expected one of warn, block",
+                                severity
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    checker.diagnostics
+}
+
+/// The known key closest to `key`, if it is close enough to be a typo.
+fn closest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|k| (edit_distance(key, k), *k))
+        .filter(|(distance, k)| *distance <= k.len().max(key.len()) / 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, k)| k)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Splits a dotted key or table header into its segments, unquoting them.
+fn split_key(key: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    for c in key.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '.') => segments.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current.trim().to_string());
+    segments
+}
+
+/// The line that `path` is set on, or failing that, the line of the
+/// nearest table or key that contains it, such as an inline table.
+fn find_key_line(text: &str, path: &[&str]) -> Option<usize> {
+    let mut table: Vec<String> = Vec::new();
+    let mut best: Option<(usize, usize)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let key = if let Some(header) = line.strip_prefix('[') {
+            let header = header.trim_start_matches('[');
+            let Some(end) = header.find(']') else {
+                continue;
+            };
+            table = split_key(&header[..end]);
+            table.clone()
+        } else if let Some((key, _)) = line.split_once('=') {
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut full = table.clone();
+            full.extend(split_key(key));
+            full
+        } else {
+            continue;
+        };
+
+        let matched = key
+            .iter()
+            .zip(path)
+            .take_while(|(a, b)| a.as_str() == **b)
+            .count();
+        if matched == key.len() && matched > best.map_or(0, |(m, _)| m) {
+            best = Some((matched, i + 1));
+            if matched == path.len() {
+                break;
+            }
+        }
+    }
+    best.map(|(_, line)| line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_text(text: &str) -> Vec<String> {
+        check(&ConfigSource {
+            file: "spawn.toml".to_string(),
+            text: text.to_string(),
+        })
+        .iter()
+        .map(|d| d.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let text = r#"
+spawn_folder = "spawn"
+target = "local"
+telemetry = false
+
+[targets.local]
+engine = "postgres-psql"
+command = { kind = "direct", direct = ["psql"] }
+
+[targets.local.session]
+lock_timeout = "5s"
+
+[targets.local.retry]
+max_attempts = 5
+
+[targets.local.history]
+url = "postgres://spawn@control:5432/control"
+spawn_schema = "_spawn_local"
+
+[environments.prod]
+allowed_commands = ["status"]
+
+[environments.prod.meta_commands]
+forbid = ["!", "setenv"]
+
+[meta_commands]
+copy_paths = ["seed/"]
+
+[template.limits]
+fuel = 10
+
+[packages.audit]
+git = "https://example.com/audit.git"
+rev = "v1"
+
+[[policy.rule]]
+name = "concurrent-index"
+match = "CREATE (UNIQUE )?INDEX"
+unless = "CONCURRENTLY"
+severity = "block"
+environments = ["prod"]
+
+[lock_advisor]
+enabled = true
+min_table_size_mb = 500
+
+[[notifications.webhook]]
+url_env = "SLACK_WEBHOOK_URL"
+on = ["applied", "failed"]
+environments = ["prod"]
+
+[tracing]
+endpoint = "http://otel-collector:4318"
+service_name = "db-migrations"
+headers = { "x-api-key" = "secret" }
+
+[audit]
+path = "logs/audit.jsonl"
+
+[layout]
+migrations_folder = "db/migrations"
+script = "migrate.sql"
+flat = false
+shard = "month"
+
+[agent]
+queue = "queue"
+
+[schema_docs]
+after_apply = true
+output = "docs/schema.md"
+schemas = ["public", "auth"]
+
+[scrub]
+rules = "scrub/staging.toml"
+targets = ["staging_copy"]
+"#;
+        assert_eq!(Vec::<String>::new(), check_text(text));
+    }
+
+    #[test]
+    fn test_reports_keys_with_lines() {
+        let text = r#"spawn_folder = "spawn"
+target = "prod"
+pin_on_aply = true
+
+[targets.local]
+engine = "postgress"
+spawn_databse = "spawn"
+
+[targets.local.command]
+kind = "provider"
+provider = []
+
+[targets.k8s]
+engine = "postgres-psql"
+command = { kind = "kubectl", namespace = "db" }
+
+[template]
+time_function = true
+
+[packages.audit]
+git = "https://example.com/audit.git"
+path = "../audit"
+reb = "v1"
+
+[environments.prod.meta_commands]
+forbd = ["!"]
+
+[[policy.rule]]
+name = "concurrent-index"
+mach = "CREATE INDEX"
+severity = "error"
+"#;
+        assert_eq!(
+            vec![
+                "spawn.toml:3: pin_on_aply: unknown key, did you mean `pin_on_apply`?",
+                "spawn.toml:15: targets.k8s.command: `kind = \"kubectl\"` needs `pod` or `selector`",
+                "spawn.toml:7: targets.local.spawn_databse: unknown key, did you mean `spawn_database`?",
+                "spawn.toml:6: targets.local.engine: unknown engine \"postgress\", expected one of postgres-psql, memory",
+                "spawn.toml:11: targets.local.command.provider: must not be empty",
+                "spawn.toml:2: target: no target named \"prod\" in [targets] (defined: k8s, local)",
+                "spawn.toml:26: environments.prod.meta_commands.forbd: unknown key, did you mean `forbid`?",
+                "spawn.toml:23: packages.audit.reb: unknown key, did you mean `rev`?",
+                "spawn.toml:20: packages.audit: packages need exactly one of `git` or `path`",
+                "spawn.toml:18: template.time_function: unknown key, did you mean `time_functions`?",
+                "spawn.toml:30: policy.rule.mach: unknown key, did you mean `match`?",
+                "spawn.toml:28: policy.rule: rules need a `match`",
+                "spawn.toml:31: policy.rule.severity: unknown severity \"error\", expected one of warn, block",
+            ],
+            check_text(text)
+        );
+    }
+
+    #[test]
+    fn test_reports_bad_maintenance_window() {
+        let text = "[environments.prod]\nmaintenance_window = \"2am-4am\"\n\n[environments.staging]\nmaintenance_window = \"22:00-02:00\"\n";
+        assert_eq!(
+            vec![
+                "spawn.toml:2: environments.prod.maintenance_window: invalid maintenance window \"2am-4am\", expected UTC times as HH:MM-HH:MM, such as 02:00-04:00",
+            ],
+            check_text(text)
+        );
+    }
+
+    #[test]
+    fn test_reports_bad_webhooks() {
+        let text = "[[notifications.webhook]]\non = [\"succeeded\"]\n";
+        assert_eq!(
+            vec![
+                "spawn.toml:1: notifications.webhook: webhooks need exactly one of `url` or `url_env`",
+                "spawn.toml:2: notifications.webhook.on: unknown outcome \"succeeded\", expected one of applied, adopted, failed",
+            ],
+            check_text(text)
+        );
+    }
+
+    #[test]
+    fn test_reports_parse_errors_with_line() {
+        let diagnostics = check_text("spawn_folder = \"spawn\"\ntarget = \n");
+        assert_eq!(1, diagnostics.len());
+        assert!(
+            diagnostics[0].starts_with("spawn.toml:2: "),
+            "{}",
+            diagnostics[0]
+        );
+    }
+
+    #[test]
+    fn test_find_key_line() {
+        let text = "a = 1\n[t.\"x.y\"]\nb = 2\nc = { d = 1 }\n";
+        assert_eq!(Some(1), find_key_line(text, &["a"]));
+        assert_eq!(Some(2), find_key_line(text, &["t", "x.y"]));
+        assert_eq!(Some(3), find_key_line(text, &["t", "x.y", "b"]));
+        assert_eq!(Some(4), find_key_line(text, &["t", "x.y", "c", "d"]));
+        assert_eq!(None, find_key_line(text, &["z"]));
+    }
+}
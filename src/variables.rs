@@ -1,6 +1,25 @@
 use anyhow::Result;
 use serde::Serialize;
 
+/// Environment variables starting with this are given to templates as
+/// variables, e.g. `SPAWN_VAR_REGION` as `variables.region`.
+pub const ENV_PREFIX: &str = "SPAWN_VAR_";
+
+/// Shown by `spawn vars show` in place of values that look like secrets.
+pub const MASK: &str = "********";
+
+/// Variables whose name contains one of these are masked when shown.
+const SECRET_NAMES: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "api_key",
+    "apikey",
+    "private_key",
+    "credential",
+];
+
 #[derive(Clone, Debug)]
 pub enum Variables {
     Json(serde_json::Value),
@@ -50,4 +69,247 @@ impl Variables {
             )),
         }
     }
+
+    /// Reads JSON, or YAML if it is not JSON, from input without a file
+    /// extension to go by, such as stdin for `--variables -`.
+    pub fn from_reader(mut reader: impl std::io::Read) -> Result<Self> {
+        let mut s = String::new();
+        reader
+            .read_to_string(&mut s)
+            .map_err(|e| anyhow::anyhow!("Could not read variables: {}", e))?;
+        if let Ok(value) = serde_json::from_str(&s) {
+            return Ok(Variables::Json(value));
+        }
+        let value: serde_yaml::Value =
+            serde_yaml::from_str(&s).map_err(|e| anyhow::anyhow!("Invalid JSON or YAML: {}", e))?;
+        Ok(Variables::Yaml(value))
+    }
+
+    /// Merges `over` on top of these variables. Tables are merged key by
+    /// key, and any other value in `over` replaces the one here.
+    pub fn overlay(self, over: Variables) -> Result<Self> {
+        let mut base = serde_json::to_value(&self)?;
+        merge(&mut base, serde_json::to_value(&over)?);
+        Ok(Variables::Json(base))
+    }
+}
+
+/// The sources of the variables given to templates, from lowest to
+/// highest precedence. [`resolve`] merges each over those before it.
+#[derive(Clone, Debug, Default)]
+pub struct VariableSources {
+    /// The `[variables]` table of spawn.toml
+    pub defaults: Option<Variables>,
+    /// The `variables_file` of the target's environment
+    pub environment_file: Option<Variables>,
+    /// `variables.toml` and `variables.<environment>.toml` in the
+    /// migration folder
+    pub migration_file: Option<Variables>,
+    /// `SPAWN_VAR_*` environment variables
+    pub env: Option<Variables>,
+    /// The file given with `--variables`
+    pub cli: Option<Variables>,
+}
+
+/// Merges `sources` in order of precedence: CLI, then environment
+/// variables, the migration folder, the environment's file and finally
+/// the defaults in spawn.toml. None when no source has any.
+pub fn resolve(sources: VariableSources) -> Result<Option<Variables>> {
+    let layers = [
+        sources.defaults,
+        sources.environment_file,
+        sources.migration_file,
+        sources.env,
+        sources.cli,
+    ];
+    let mut merged: Option<Variables> = None;
+    for layer in layers.into_iter().flatten() {
+        merged = Some(match merged {
+            Some(base) => base.overlay(layer)?,
+            None => layer,
+        });
+    }
+    Ok(merged)
+}
+
+/// The variables set with `SPAWN_VAR_<NAME>` among `vars`, named by the
+/// lowercase `<name>`. None when there are none.
+pub fn from_env(vars: impl IntoIterator<Item = (String, String)>) -> Option<Variables> {
+    let values: serde_json::Map<String, serde_json::Value> = vars
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let name = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+            Some((name, serde_json::Value::String(value)))
+        })
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    Some(Variables::Json(serde_json::Value::Object(values)))
+}
+
+/// Replaces the values of keys that look like they hold secrets, such as
+/// `db_password` or `api_token`, with [`MASK`].
+pub fn mask_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_NAMES.iter().any(|name| key.contains(name)) {
+                    *value = serde_json::Value::String(MASK.to_string());
+                } else {
+                    mask_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(mask_secrets),
+        _ => {}
+    }
+}
+
+fn merge(base: &mut serde_json::Value, over: serde_json::Value) {
+    match (base, over) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlay_merges_tables() {
+        let base = Variables::from_str(
+            "toml",
+            "schema = \"app\"\n[db]\nhost = \"localhost\"\nport = 5432\n",
+        )
+        .unwrap();
+        let over = Variables::from_str("json", r#"{"db": {"port": 6543}, "seed": [1]}"#).unwrap();
+        let merged = serde_json::to_value(base.overlay(over).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "schema": "app",
+                "db": {"host": "localhost", "port": 6543},
+                "seed": [1],
+            }),
+            merged
+        );
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let json = Variables::from_reader(r#"{"a": [1, 2]}"#.as_bytes()).unwrap();
+        assert!(matches!(json, Variables::Json(_)));
+        assert_eq!(
+            serde_json::json!({"a": [1, 2]}),
+            serde_json::to_value(json).unwrap()
+        );
+
+        let yaml = Variables::from_reader("a:\n  - 1\n  - 2\n".as_bytes()).unwrap();
+        assert!(matches!(yaml, Variables::Yaml(_)));
+        assert_eq!(
+            serde_json::json!({"a": [1, 2]}),
+            serde_json::to_value(yaml).unwrap()
+        );
+
+        let err = Variables::from_reader("a: [1".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("Invalid JSON or YAML"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_precedence() {
+        let layer = |source: &str| {
+            Some(
+                Variables::from_str(
+                    "json",
+                    &format!(r#"{{"{}": 1, "winner": "{}"}}"#, source, source),
+                )
+                .unwrap(),
+            )
+        };
+        let all = VariableSources {
+            defaults: layer("defaults"),
+            environment_file: layer("environment_file"),
+            migration_file: layer("migration_file"),
+            env: layer("env"),
+            cli: layer("cli"),
+        };
+        let merged = serde_json::to_value(resolve(all.clone()).unwrap().unwrap()).unwrap();
+        assert_eq!("cli", merged["winner"]);
+        for source in [
+            "defaults",
+            "environment_file",
+            "migration_file",
+            "env",
+            "cli",
+        ] {
+            assert_eq!(1, merged[source], "{} was not merged", source);
+        }
+
+        let without_cli = VariableSources {
+            cli: None,
+            ..all.clone()
+        };
+        let merged = serde_json::to_value(resolve(without_cli).unwrap().unwrap()).unwrap();
+        assert_eq!("env", merged["winner"]);
+
+        let file_over_environment = VariableSources {
+            defaults: None,
+            env: None,
+            cli: None,
+            ..all
+        };
+        let merged =
+            serde_json::to_value(resolve(file_over_environment).unwrap().unwrap()).unwrap();
+        assert_eq!("migration_file", merged["winner"]);
+
+        assert!(resolve(VariableSources::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_from_env() {
+        let vars = [
+            ("SPAWN_VAR_REGION".to_string(), "eu".to_string()),
+            ("SPAWN_TARGET".to_string(), "prod".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        let variables = serde_json::to_value(from_env(vars).unwrap()).unwrap();
+        assert_eq!(serde_json::json!({"region": "eu"}), variables);
+        assert!(from_env([("HOME".to_string(), "/root".to_string())]).is_none());
+    }
+
+    #[test]
+    fn test_mask_secrets() {
+        let mut value = serde_json::json!({
+            "db": {"host": "localhost", "Password": "hunter2"},
+            "api_token": "abc",
+            "users": [{"name": "a", "secret": {"nested": 1}}],
+        });
+        mask_secrets(&mut value);
+        assert_eq!(
+            serde_json::json!({
+                "db": {"host": "localhost", "Password": MASK},
+                "api_token": MASK,
+                "users": [{"name": "a", "secret": MASK}],
+            }),
+            value
+        );
+    }
+
+    #[test]
+    fn test_overlay_on_default() {
+        let over = Variables::from_str("yaml", "a: 1").unwrap();
+        let merged = serde_json::to_value(Variables::default().overlay(over).unwrap()).unwrap();
+        assert_eq!(serde_json::json!({"a": 1}), merged);
+    }
 }
@@ -0,0 +1,146 @@
+//! Workspaces let one repository hold several spawn projects, each with its
+//! own `spawn.toml`. A `spawn.workspace.toml` at the top of the repository
+//! names each project and the folder it lives in:
+//!
+//! ```toml
+//! [projects.api]
+//! path = "services/api"
+//!
+//! [projects.billing]
+//! path = "services/billing"
+//! config_file = "spawn.billing.toml"
+//! ```
+//!
+//! As with any config file, a project's `spawn_folder` is relative to the
+//! folder of its config file, so the project works the same whether spawn
+//! is run from inside it or through the workspace with `--project`.
+
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use opendal::Operator;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// The workspace file read unless `--workspace-file` is given.
+pub const WORKSPACE_FILE: &str = "spawn.workspace.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    /// Projects by name
+    #[serde(default)]
+    pub projects: BTreeMap<String, WorkspaceProject>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceProject {
+    /// Folder of the project, relative to the workspace file
+    pub path: String,
+    /// Config file of the project, relative to `path`
+    #[serde(default = "default_config_file")]
+    pub config_file: String,
+}
+
+fn default_config_file() -> String {
+    "spawn.toml".to_string()
+}
+
+impl WorkspaceProject {
+    /// Path of the project's config file, relative to the workspace file.
+    pub fn config_path(&self) -> String {
+        join(&self.path, &self.config_file)
+    }
+}
+
+impl Workspace {
+    pub async fn load(path: &str, op: &Operator) -> Result<Self> {
+        let bytes = op
+            .read(path)
+            .await
+            .context(format!("No workspace found at path '{}'", path))?
+            .to_bytes();
+        let contents = String::from_utf8(bytes.to_vec())?;
+        let workspace: Workspace =
+            toml::from_str(&contents).context(format!("Invalid workspace file '{}'", path))?;
+        if workspace.projects.is_empty() {
+            return Err(anyhow!("workspace '{}' lists no projects", path));
+        }
+        Ok(workspace)
+    }
+
+    pub fn project(&self, name: &str) -> Result<&WorkspaceProject> {
+        self.projects.get(name).ok_or_else(|| {
+            anyhow!(
+                "no project named '{}' in the workspace, expected one of: {}",
+                name,
+                self.projects
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+    }
+
+    /// Loads the config of the project `name`.
+    pub async fn load_config(
+        &self,
+        name: &str,
+        op: &Operator,
+        target: Option<String>,
+    ) -> Result<Config> {
+        let project = self.project(name)?;
+        let config_path = project.config_path();
+        Config::load(&config_path, op, target)
+            .await
+            .context(format!("could not load config from {}", config_path))
+    }
+}
+
+/// Joins `path` onto `folder`, unless `path` is absolute.
+fn join(folder: &str, path: &str) -> String {
+    let folder = folder.trim_end_matches('/');
+    if path.starts_with('/') || folder.is_empty() || folder == "." {
+        path.to_string()
+    } else {
+        format!("{}/{}", folder, path.trim_start_matches("./"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_path() {
+        let project = |path: &str, config_file: &str| WorkspaceProject {
+            path: path.to_string(),
+            config_file: config_file.to_string(),
+        };
+        assert_eq!(
+            "services/api/spawn.toml",
+            project("services/api/", "spawn.toml").config_path()
+        );
+        assert_eq!("./spawn.toml", project(".", "./spawn.toml").config_path());
+        assert_eq!(
+            "/etc/spawn.toml",
+            project("services/api", "/etc/spawn.toml").config_path()
+        );
+    }
+
+    #[test]
+    fn test_parse_workspace() {
+        let workspace: Workspace = toml::from_str(
+            "[projects.api]\npath = \"api\"\n\n[projects.billing]\npath = \"billing\"\nconfig_file = \"db.toml\"\n",
+        )
+        .unwrap();
+        assert_eq!("api/spawn.toml", workspace.projects["api"].config_path());
+        assert_eq!(
+            "billing/db.toml",
+            workspace.projects["billing"].config_path()
+        );
+        let err = workspace.project("web").unwrap_err().to_string();
+        assert!(err.contains("expected one of: api, billing"), "{}", err);
+    }
+}
@@ -0,0 +1,63 @@
+{#-
+  Built-in backfill macros, shipped with spawn. Import with:
+
+    {% from "spawn/backfill.sql" import backfill %}
+
+  backfill(table, batch=10000, key="id", where=none, sleep=0)
+
+  Runs the caller's statement over `table` in batches of `batch` rows, walking
+  the `key` column in order. The statement is preceded by a `batch` CTE
+  holding the keys of the current batch, so it should only touch those rows:
+
+    {% call backfill("users", batch=5000, sleep=0.1) %}
+    UPDATE users SET email_lower = lower(email)
+    WHERE id IN (SELECT id FROM batch)
+    {% endcall %}
+
+  Each batch is committed on its own and reported with RAISE NOTICE, then
+  the loop sleeps for `sleep` seconds. `where` is an SQL condition limiting
+  which rows are put in batches. As the batches commit, the call must not be
+  inside BEGIN ... COMMIT.
+-#}
+{% macro backfill(table, batch=10000, key="id", where=none, sleep=0) -%}
+{%- set relation = table|split(".")|map("escape_identifier")|join(".") -%}
+{%- set column = key|escape_identifier -%}
+DO $spawn_backfill$
+DECLARE
+    last_key {{ relation|safe }}.{{ column }}%TYPE;
+    batch_end {{ relation|safe }}.{{ column }}%TYPE;
+    batch_rows bigint;
+    total_rows bigint := 0;
+    batches bigint := 0;
+BEGIN
+    LOOP
+        SELECT max(batch_key) INTO batch_end FROM (
+            SELECT {{ column }} AS batch_key FROM {{ relation|safe }}
+            WHERE (last_key IS NULL OR {{ column }} > last_key)
+            {%- if where %} AND ({{ where|safe }}){% endif %}
+            ORDER BY {{ column }}
+            LIMIT {{ batch|int }}
+        ) AS batch_keys;
+        EXIT WHEN batch_end IS NULL;
+
+        WITH batch AS (
+            SELECT {{ column }} FROM {{ relation|safe }}
+            WHERE (last_key IS NULL OR {{ column }} > last_key) AND {{ column }} <= batch_end
+            {%- if where %} AND ({{ where|safe }}){% endif %}
+        )
+        {{ caller()|trim|safe }};
+        GET DIAGNOSTICS batch_rows = ROW_COUNT;
+
+        last_key := batch_end;
+        batches := batches + 1;
+        total_rows := total_rows + batch_rows;
+        RAISE NOTICE 'backfill %: batch %, % rows (% total)', {{ table }}, batches, batch_rows, total_rows;
+        COMMIT;
+        {%- if sleep %}
+        PERFORM pg_sleep({{ sleep|float }});
+        {%- endif %}
+    END LOOP;
+    RAISE NOTICE 'backfill %: done, % rows in % batches', {{ table }}, total_rows, batches;
+END
+$spawn_backfill$;
+{%- endmacro %}
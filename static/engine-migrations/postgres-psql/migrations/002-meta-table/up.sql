@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS {{variables.schema|escape_identifier}}.meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
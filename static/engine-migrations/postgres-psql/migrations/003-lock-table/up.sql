@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS {{variables.schema|escape_identifier}}.lock (
+    lock_key BIGINT PRIMARY KEY,
+    hostname TEXT,
+    pid INTEGER,
+    backend_pid INTEGER NOT NULL,
+    migration_name TEXT,
+    started_at TIMESTAMPTZ NOT NULL,
+    heartbeat_at TIMESTAMPTZ NOT NULL
+);
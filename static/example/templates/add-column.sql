@@ -0,0 +1,5 @@
+BEGIN;
+
+ALTER TABLE [[ table ]] ADD COLUMN [[ column ]] [[ type ]];
+
+COMMIT;
@@ -0,0 +1,7 @@
+BEGIN;
+
+UPDATE [[ table ]]
+SET [[ column ]] = [[ value ]]
+WHERE [[ column ]] IS NULL;
+
+COMMIT;
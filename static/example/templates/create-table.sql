@@ -0,0 +1,8 @@
+BEGIN;
+
+CREATE TABLE [[ table ]] (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+COMMIT;
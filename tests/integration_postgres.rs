@@ -41,11 +41,18 @@ use migration_build::MigrationTestHelper;
 use opendal::services::Memory;
 use opendal::Operator;
 use spawn_db::{
-    commands::{AdoptMigration, ApplyMigration, Command, CompareTests, ExpectTest, Outcome},
-    config::ConfigLoaderSaver,
+    advisor::{advise, LockRiskKind},
+    commands::{
+        migration::explain_sql, AdoptMigration, ApplyMigration, Command, CompareTests, ExpectTest,
+        MigrationGate, MigrationStatus, Outcome, ReleaseApply, SystemUpgrade,
+    },
+    config::ConfigBuilder,
     engine::{CommandSpec, EngineType, TargetConfig},
+    events::EventSink,
+    sqltest::Tester,
+    transcript::TranscriptConfig,
+    ApplyOptions, Spawn,
 };
-use std::collections::HashMap;
 use std::env;
 use std::process::Command as ProcessCommand;
 use uuid::Uuid;
@@ -201,11 +208,10 @@ impl IntegrationTestHelper {
         };
 
         // Create the target config for this test
-        let config_loader = Self::create_config(&db_name, &connection_mode);
+        let config = Self::create_config(mem_op, &db_name, &connection_mode);
 
         // Use MigrationTestHelper for filesystem and config management
-        let migration_helper =
-            MigrationTestHelper::new_from_operator_with_config(mem_op, config_loader).await?;
+        let migration_helper = MigrationTestHelper::new_from_config(config);
 
         let helper = Self {
             migration_helper,
@@ -219,29 +225,32 @@ impl IntegrationTestHelper {
     }
 
     /// Creates a target config that points to our isolated test database
-    fn create_config(db_name: &str, connection_mode: &ConnectionMode) -> ConfigLoaderSaver {
-        let mut targets = HashMap::new();
-        targets.insert(
-            "postgres_psql".to_string(),
-            TargetConfig {
-                engine: EngineType::PostgresPSQL,
-                spawn_database: Some(db_name.to_string()),
-                spawn_schema: "_spawn".to_string(),
-                environment: "test".to_string(),
-                command: Some(CommandSpec::Direct {
-                    direct: connection_mode.psql_command(db_name),
-                }),
-            },
-        );
-
-        ConfigLoaderSaver {
-            spawn_folder: "/db".to_string(),
-            target: Some("postgres_psql".to_string()),
-            environment: None,
-            targets: Some(targets),
-            project_id: None,
-            telemetry: Some(false),
-        }
+    fn create_config(
+        op: Operator,
+        db_name: &str,
+        connection_mode: &ConnectionMode,
+    ) -> ConfigBuilder {
+        ConfigBuilder::new(op)
+            .with_spawn_folder("/db")
+            .with_target(
+                "postgres_psql",
+                TargetConfig {
+                    engine: EngineType::PostgresPSQL,
+                    spawn_database: Some(db_name.to_string()),
+                    spawn_schema: "_spawn".to_string(),
+                    environment: "test".to_string(),
+                    command: Some(CommandSpec::Direct {
+                        direct: connection_mode.psql_command(db_name),
+                    }),
+                    url: None,
+                    session: None,
+                    protected: false,
+                    history: None,
+                    retry: None,
+                    wait_for_lock: None,
+                },
+            )
+            .with_telemetry(false)
     }
 
     /// Creates an isolated test database using PostgreSQL's template feature
@@ -303,8 +312,15 @@ impl IntegrationTestHelper {
             pinned: false,
             variables: None,
             yes: true,
+            force: false,
             retry: false,
             reuse_connection: false,
+            require_signature: false,
+            from_file: None,
+            interactive: false,
+            promote_from: None,
+            allow_destructive: false,
+            schedule: Default::default(),
         };
 
         let outcome = cmd.execute(&config).await?;
@@ -321,6 +337,7 @@ impl IntegrationTestHelper {
         let cmd = AdoptMigration {
             migration: Some(migration_name.to_string()),
             yes: true,
+            force: false,
             description: Some("test adoption".to_string()),
         };
 
@@ -357,13 +374,24 @@ impl IntegrationTestHelper {
     /// Runs test compare using the CompareTests command
     pub async fn run_test_compare(&self, test_name: Option<String>) -> Result<(), anyhow::Error> {
         let config = self.migration_helper.load_config().await?;
-        let cmd = CompareTests { name: test_name };
+        let cmd = CompareTests {
+            names: test_name.into_iter().collect(),
+            render_only: false,
+            retries: 0,
+            isolate: false,
+            deny_warnings: false,
+        };
 
-        cmd.execute(&config)
+        let outcome = cmd
+            .execute(&config)
             .await
             .context("error calling test compare")?;
 
-        Ok(())
+        match outcome {
+            Outcome::TestsFailed => Err(anyhow!("Differences found in one or more tests")
+                .context("error calling test compare")),
+            _ => Ok(()),
+        }
     }
 
     /// Saves test expected output using the ExpectTest command
@@ -469,8 +497,15 @@ COMMIT;"#
                     pinned: false,
                     variables: None,
                     yes: true,
+                    force: false,
                     retry: false,
                     reuse_connection,
+                    require_signature: false,
+                    from_file: None,
+                    interactive: false,
+                    promote_from: None,
+                    allow_destructive: false,
+                    schedule: Default::default(),
                 };
                 let outcome = cmd.execute(&config).await?;
                 assert!(
@@ -484,6 +519,7 @@ COMMIT;"#
                 let cmd = AdoptMigration {
                     migration: None,
                     yes: true,
+                    force: false,
                     description: Some("mass adopt test".to_string()),
                 };
                 let outcome = cmd.execute(&config).await?;
@@ -1289,8 +1325,15 @@ COMMIT;"#;
         pinned: false,
         variables: None,
         yes: true,
+        force: false,
         retry: false,
         reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
     };
     let result = cmd.execute(&config).await;
     assert!(
@@ -1310,8 +1353,15 @@ COMMIT;"#;
         pinned: false,
         variables: None,
         yes: true,
+        force: false,
         retry: true,
         reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
     };
     let result = cmd.execute(&config).await;
     assert!(
@@ -1381,6 +1431,44 @@ async fn test_cli_test_compare() -> Result<()> {
     Ok(())
 }
 
+/// A test whose SQL fails reports psql's error alongside the output that
+/// was produced before it.
+#[tokio::test]
+#[ignore]
+async fn test_test_compare_reports_sql_error() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new(
+        "test_test_compare_reports_sql_error",
+        Some("./static/tests/test_cli_test"),
+    )
+    .await?;
+
+    let test_name = "20250113000000-simple-test";
+    helper
+        .migration_helper
+        .fs
+        .write(
+            &format!("/db/tests/{}/test.sql", test_name),
+            "select 1 as one;\nselect * from no_such_table;\nselect 2 as two;\n",
+        )
+        .await?;
+
+    let config = helper.migration_helper.load_config().await?;
+    let outcome = Tester::new(&config, test_name).run_compare(None).await?;
+
+    let error = outcome.error.expect("test should report the SQL error");
+    assert!(
+        error.contains("ERROR:") && error.contains("no_such_table"),
+        "unexpected error: {}",
+        error
+    );
+    let diff = outcome.diff.expect("output should differ from expected");
+    assert!(!diff.contains("two"), "psql should stop at the error");
+
+    Ok(())
+}
+
 /// Tests that migrations fail when another session holds the advisory lock.
 /// This verifies the concurrent migration protection works correctly.
 #[tokio::test]
@@ -1556,8 +1644,15 @@ COMMIT;"#;
         pinned: true,
         variables: None,
         yes: true,
+        force: false,
         retry: false,
         reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
     };
     let result = cmd.execute(&config).await;
     assert!(
@@ -1577,8 +1672,15 @@ COMMIT;"#;
         pinned: false,
         variables: None,
         yes: true,
+        force: false,
         retry: false,
         reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
     };
     let result = cmd.execute(&config).await;
     assert!(result.is_ok(), "apply with --no-pin should succeed");
@@ -1592,6 +1694,567 @@ COMMIT;"#;
     Ok(())
 }
 
+/// Tests that pin_on_apply pins a never-applied migration before applying it
+/// and records the pin hash in the migration history.
+#[tokio::test]
+#[ignore]
+async fn test_apply_pin_on_apply() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_apply_pin_on_apply", None).await?;
+
+    let migration_name = helper
+        .migration_helper
+        .create_migration_manual(
+            "pin-on-apply",
+            "BEGIN;\nCREATE TABLE pin_on_apply_test (id SERIAL PRIMARY KEY);\nCOMMIT;".to_string(),
+        )
+        .await?;
+
+    let mut config = helper.migration_helper.load_config().await?;
+    config.pin_on_apply = true;
+
+    let cmd = ApplyMigration {
+        migration: Some(migration_name.clone()),
+        pinned: true,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    };
+    cmd.execute(&config).await?;
+
+    let lock = config
+        .load_lock_file(&config.pather().migration_lock_file_path(&migration_name))
+        .await?;
+    let recorded = helper.execute_sql(&format!(
+        "SELECT mh.pin_hash FROM _spawn.migration m \
+         JOIN _spawn.migration_history mh ON m.migration_id = mh.migration_id_migration \
+         WHERE m.name = '{}';",
+        migration_name
+    ))?;
+    assert!(
+        recorded.contains(&lock.pin),
+        "pin hash {} should be recorded, got: {}",
+        lock.pin,
+        recorded
+    );
+
+    Ok(())
+}
+
+/// Tests that a migration built ahead of time can be applied from its SQL
+/// file without rendering, and is recorded under the given name.
+#[tokio::test]
+#[ignore]
+async fn test_apply_from_file() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_apply_from_file", None).await?;
+
+    let dir = tempfile::tempdir()?;
+    let artifact = dir.path().join("rendered.sql");
+    std::fs::write(
+        &artifact,
+        "BEGIN;\nCREATE TABLE from_file_test (id SERIAL PRIMARY KEY);\nCOMMIT;",
+    )?;
+
+    let config = helper.migration_helper.load_config().await?;
+    let cmd = ApplyMigration {
+        migration: Some("20240101000000-from-file".to_string()),
+        pinned: true,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: Some(artifact.to_string_lossy().into_owned()),
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    };
+    cmd.execute(&config).await?;
+
+    assert!(helper.table_exists("public", "from_file_test")?);
+    let recorded = helper.execute_sql(
+        "SELECT mh.status_id_status FROM _spawn.migration m \
+         JOIN _spawn.migration_history mh ON m.migration_id = mh.migration_id_migration \
+         WHERE m.name = '20240101000000-from-file';",
+    )?;
+    assert!(!recorded.trim().is_empty(), "apply should be recorded");
+
+    // Applying the same artifact again is reported as already applied.
+    cmd.execute(&config).await?;
+
+    Ok(())
+}
+
+/// With `[transcripts]` configured, each apply writes a transcript of the
+/// SQL run and how it ended.
+#[tokio::test]
+#[ignore]
+async fn test_apply_writes_transcript() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_apply_writes_transcript", None).await?;
+    let migration_name = helper
+        .migration_helper
+        .create_migration_manual(
+            "transcript",
+            "BEGIN;\nCREATE TABLE transcript_test (id SERIAL PRIMARY KEY);\nCOMMIT;".to_string(),
+        )
+        .await?;
+
+    let mut config = helper.migration_helper.load_config().await?;
+    config.transcripts = Some(TranscriptConfig {
+        enabled: true,
+        folder: "logs".to_string(),
+    });
+    let cmd = ApplyMigration {
+        migration: Some(migration_name.clone()),
+        pinned: false,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    };
+    cmd.execute(&config).await?;
+
+    let logs = helper.migration_helper.fs.list("/db/logs/").await?;
+    let log = logs
+        .iter()
+        .find(|entry| entry.name().starts_with(&migration_name))
+        .ok_or(anyhow!("no transcript written"))?;
+    let transcript =
+        String::from_utf8(helper.migration_helper.fs.read(log.path()).await?.to_vec())?;
+    assert!(transcript.contains("Result: SUCCESS"), "{}", transcript);
+    assert!(
+        transcript.contains("CREATE TABLE transcript_test"),
+        "{}",
+        transcript
+    );
+
+    Ok(())
+}
+
+/// Tests that `release apply` applies exactly the listed migrations and
+/// refuses to run while an earlier migration is pending outside the release.
+#[tokio::test]
+#[ignore]
+async fn test_release_apply() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_release_apply", None).await?;
+    let mh = &helper.migration_helper;
+
+    let skipped = mh
+        .create_migration_manual(
+            "a-release-skipped",
+            "BEGIN;\nCREATE TABLE release_skipped (id INT);\nCOMMIT;".to_string(),
+        )
+        .await?;
+    mh.pin_migration(&skipped).await?;
+    let included = mh
+        .create_migration_manual(
+            "b-release-included",
+            "BEGIN;\nCREATE TABLE release_included (id INT);\nCOMMIT;".to_string(),
+        )
+        .await?;
+    mh.pin_migration(&included).await?;
+
+    let config = mh.load_config().await?;
+    mh.fs
+        .write(
+            &config.pather().release_file_path("r1"),
+            format!("migrations = [\"{}\"]\n", included),
+        )
+        .await?;
+
+    let cmd = ReleaseApply {
+        release: "r1".to_string(),
+        yes: true,
+        force: false,
+        retry: false,
+        allow_destructive: false,
+    };
+    let err = match cmd.execute(&config).await {
+        Ok(_) => panic!("release with an earlier pending migration should fail"),
+        Err(e) => e.to_string(),
+    };
+    assert!(err.contains(&skipped), "got: {}", err);
+    assert!(!helper.table_exists("public", "release_included")?);
+
+    mh.fs
+        .write(
+            &config.pather().release_file_path("r1"),
+            format!("migrations = [\"{}\", \"{}\"]\n", skipped, included),
+        )
+        .await?;
+    cmd.execute(&config).await?;
+    assert!(helper.table_exists("public", "release_skipped")?);
+    assert!(helper.table_exists("public", "release_included")?);
+
+    Ok(())
+}
+
+/// Tests that the pin hash of a pinned apply is returned in the migration
+/// history, as shown by `migration status --history`.
+#[tokio::test]
+#[ignore]
+async fn test_migration_history_includes_pin_hash() -> Result<()> {
+    require_postgres()?;
+
+    let helper =
+        IntegrationTestHelper::new("test_migration_history_includes_pin_hash", None).await?;
+
+    let migration_name = helper
+        .migration_helper
+        .create_migration_manual(
+            "history-pin",
+            "BEGIN;\nCREATE TABLE history_pin_test (id SERIAL PRIMARY KEY);\nCOMMIT;".to_string(),
+        )
+        .await?;
+    let pin = helper
+        .migration_helper
+        .pin_migration(&migration_name)
+        .await?;
+
+    let config = helper.migration_helper.load_config().await?;
+    let cmd = ApplyMigration {
+        migration: Some(migration_name.clone()),
+        pinned: true,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    };
+    cmd.execute(&config).await?;
+
+    let engine = config.new_engine().await?;
+    let history = engine.get_migration_history(Some("default")).await?;
+    let entry = history
+        .iter()
+        .find(|e| e.migration_name == migration_name)
+        .expect("history entry for applied migration");
+    assert_eq!(Some(pin), entry.pin_hash);
+    assert!(entry.checksum.is_some());
+
+    Ok(())
+}
+
+/// The lock advisor reads the size of existing tables and reports only
+/// those at least as large as its threshold.
+#[tokio::test]
+#[ignore]
+async fn test_lock_advisor_reads_table_sizes() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_lock_advisor", None).await?;
+    let table = helper
+        .migration_helper
+        .create_migration_manual(
+            "advisor-users",
+            "CREATE TABLE advisor_users (id int, email text);\n\
+             INSERT INTO advisor_users SELECT i, 'user' || i FROM generate_series(1, 1000) i;\n"
+                .to_string(),
+        )
+        .await?;
+    helper.apply_migration(&table).await?;
+    let index = helper
+        .migration_helper
+        .create_migration_manual(
+            "advisor-users-index",
+            "CREATE INDEX advisor_users_email_idx ON advisor_users (email);\n\
+             CREATE INDEX new_table_idx ON not_created_yet (id);\n\
+             ALTER TABLE advisor_users ALTER COLUMN email SET NOT NULL;\n"
+                .to_string(),
+        )
+        .await?;
+
+    let mut config = helper.migration_helper.load_config().await?;
+    config.lock_advisor.min_table_size_mb = 0;
+    let engine = config.new_engine().await?;
+    let warnings = advise(
+        &config,
+        engine.as_ref(),
+        std::slice::from_ref(&index),
+        false,
+        None,
+    )
+    .await?;
+    let kinds: Vec<(LockRiskKind, &str)> = warnings
+        .iter()
+        .map(|w| (w.risk.kind, w.risk.table.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            (LockRiskKind::CreateIndex, "advisor_users"),
+            (LockRiskKind::SetNotNull, "advisor_users"),
+        ],
+        kinds
+    );
+    assert!(warnings[0].bytes > 0);
+
+    // A thousand rows are well below a megabyte.
+    config.lock_advisor.min_table_size_mb = 1;
+    assert!(advise(&config, engine.as_ref(), &[index], false, None)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+/// EXPLAIN previews the plans of a migration's data changes, and ANALYZE
+/// runs them without keeping their changes.
+#[tokio::test]
+#[ignore]
+async fn test_explain_data_changes() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_explain", None).await?;
+    helper.execute_sql(
+        "CREATE TABLE explain_users (id int, email text);
+         INSERT INTO explain_users SELECT i, 'User' || i FROM generate_series(1, 100) i;",
+    )?;
+    let sql = "ALTER TABLE explain_users ADD COLUMN created date;
+UPDATE explain_users SET email = lower(email);
+UPDATE explain_users SET created = now();
+";
+
+    let config = helper.migration_helper.load_config().await?;
+    let engine = config.new_engine().await?;
+    let explained = explain_sql(engine.as_ref(), sql, false).await;
+    assert_eq!(2, explained.len());
+    assert!(explained[0].plan[0].starts_with("Update on explain_users"));
+    assert!(explained[0].cost.is_some());
+    assert_eq!(None, explained[0].error);
+    // The column is only added once the migration runs.
+    assert!(explained[1]
+        .error
+        .as_deref()
+        .is_some_and(|e| e.contains("created")));
+
+    let analyzed = explain_sql(engine.as_ref(), sql, true).await;
+    assert!(analyzed[0].plan[0].contains("actual time"));
+    let lowered =
+        helper.execute_sql("SELECT count(*) FROM explain_users WHERE email = lower(email);")?;
+    assert!(
+        lowered.contains(" 0\n"),
+        "ANALYZE changes were kept: {}",
+        lowered
+    );
+
+    Ok(())
+}
+
+/// Tests that applying a migration older than an applied one is refused
+/// unless out of order applies are allowed.
+#[tokio::test]
+#[ignore]
+async fn test_apply_refuses_out_of_order() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_apply_refuses_out_of_order", None).await?;
+    let mut config = helper.migration_helper.load_config().await?;
+    let older = "20240101000000-older";
+    let newer = "20240201000000-newer";
+    for (name, table) in [(older, "out_of_order_older"), (newer, "out_of_order_newer")] {
+        helper
+            .migration_helper
+            .fs
+            .write(
+                &config.pather().migration_script_file_path(name),
+                format!(
+                    "BEGIN;\nCREATE TABLE {} (id SERIAL PRIMARY KEY);\nCOMMIT;",
+                    table
+                ),
+            )
+            .await?;
+    }
+
+    let apply = |migration: Option<&str>| ApplyMigration {
+        migration: migration.map(str::to_string),
+        pinned: false,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    };
+    apply(Some(newer)).execute(&config).await?;
+
+    // The older migration is now out of order, whether named or pending.
+    for migration in [Some(older), None] {
+        let err = apply(migration)
+            .execute(&config)
+            .await
+            .err()
+            .expect("out of order apply should be refused");
+        assert!(
+            err.to_string().contains("would be applied out of order"),
+            "{}",
+            err
+        );
+    }
+    assert!(!helper.table_exists("public", "out_of_order_older")?);
+
+    config.allow_out_of_order = true;
+    apply(None).execute(&config).await?;
+    assert!(helper.table_exists("public", "out_of_order_older")?);
+
+    Ok(())
+}
+
+/// Tests that who applied a migration, and the ticket and description in
+/// its migration.toml, are recorded in the migration history.
+#[tokio::test]
+#[ignore]
+async fn test_migration_history_includes_metadata() -> Result<()> {
+    require_postgres()?;
+
+    let helper =
+        IntegrationTestHelper::new("test_migration_history_includes_metadata", None).await?;
+
+    let migration_name = helper
+        .migration_helper
+        .create_migration_manual(
+            "history-metadata",
+            "BEGIN;\nCREATE TABLE history_metadata_test (id SERIAL PRIMARY KEY);\nCOMMIT;"
+                .to_string(),
+        )
+        .await?;
+    let mut config = helper.migration_helper.load_config().await?;
+    config.identity = Some("deploy-bot".to_string());
+    helper
+        .migration_helper
+        .fs
+        .write(
+            &config.pather().migration_metadata_file_path(&migration_name),
+            "author = \"Jo Bloggs <jo@example.com>\"\nticket = \"DB-12\"\ndescription = \"Add a table\"\n",
+        )
+        .await?;
+
+    let cmd = ApplyMigration {
+        migration: Some(migration_name.clone()),
+        pinned: false,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    };
+    cmd.execute(&config).await?;
+
+    let engine = config.new_engine().await?;
+    let history = engine.get_migration_history(Some("default")).await?;
+    let entry = history
+        .iter()
+        .find(|e| e.migration_name == migration_name)
+        .expect("history entry for applied migration");
+    assert_eq!(Some("deploy-bot".to_string()), entry.created_by);
+    assert_eq!(Some("DB-12: Add a table".to_string()), entry.description);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_engine_query_returns_typed_rows() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_engine_query_returns_typed_rows", None).await?;
+    let config = helper.migration_helper.load_config().await?;
+    let engine = config.new_engine().await?;
+
+    let rows = engine
+        .query(&spawn_db::sql_query!(
+            "SELECT 1 AS id, NULL::text AS missing, '' AS empty, 'a,\"b\"' AS quoted;"
+        ))
+        .await?;
+    assert_eq!(vec!["id", "missing", "empty", "quoted"], rows.columns);
+    let row = rows.iter().next().expect("one row");
+    assert_eq!(Some("1"), row.get("id"));
+    assert_eq!(None, row.get("missing"));
+    assert_eq!(Some(""), row.get("empty"));
+    assert_eq!(Some("a,\"b\""), row.get("quoted"));
+
+    let tenants = engine
+        .query_first_column("SELECT unnest(ARRAY['acme', NULL, 'globex'])")
+        .await?;
+    assert_eq!(vec!["acme", "globex"], tenants);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_internal_schema_version_requires_upgrade() -> Result<()> {
+    require_postgres()?;
+
+    let helper =
+        IntegrationTestHelper::new("test_internal_schema_version_requires_upgrade", None).await?;
+    let config = helper.migration_helper.load_config().await?;
+
+    // Connecting to a fresh database creates the internal schema at the
+    // latest version.
+    let engine = config.new_engine().await?;
+    let latest = engine.latest_internal_schema_version();
+    assert_eq!(Some(latest), engine.internal_schema_version().await?);
+    drop(engine);
+
+    // An older internal schema is refused until upgraded.
+    helper.execute_sql("UPDATE _spawn.meta SET value = '1' WHERE key = 'schema_version';")?;
+    let err = match config.new_engine().await {
+        Ok(_) => panic!("expected an outdated internal schema to be refused"),
+        Err(e) => format!("{:#}", e),
+    };
+    assert!(err.contains("spawn system upgrade"), "{}", err);
+
+    SystemUpgrade.execute(&config).await?;
+    let engine = config.new_engine().await?;
+    assert_eq!(Some(latest), engine.internal_schema_version().await?);
+
+    // Upgrading again has nothing to do.
+    assert!(engine.upgrade_internal_schema().await?.is_empty());
+
+    Ok(())
+}
+
 /// Tests that spawn_database config controls where migration tracking is recorded.
 ///
 /// When spawn_database is set to a different database, the migration
@@ -1627,31 +2290,29 @@ async fn test_spawn_database_config() -> Result<()> {
     let mem_op = Operator::new(mem_service)?.finish();
 
     // Config: psql connects to migration_db, but spawn_database = tracking_db
-    let mut targets = HashMap::new();
-    targets.insert(
-        "postgres_psql".to_string(),
-        TargetConfig {
-            engine: EngineType::PostgresPSQL,
-            spawn_database: Some(tracking_db.to_string()),
-            spawn_schema: "_spawn".to_string(),
-            environment: "test".to_string(),
-            command: Some(CommandSpec::Direct {
-                direct: connection_mode.psql_command(&migration_db),
-            }),
-        },
-    );
-
-    let config_loader = ConfigLoaderSaver {
-        spawn_folder: "/db".to_string(),
-        target: Some("postgres_psql".to_string()),
-        environment: None,
-        targets: Some(targets),
-        project_id: None,
-        telemetry: Some(false),
-    };
+    let config = ConfigBuilder::new(mem_op)
+        .with_spawn_folder("/db")
+        .with_target(
+            "postgres_psql",
+            TargetConfig {
+                engine: EngineType::PostgresPSQL,
+                spawn_database: Some(tracking_db.to_string()),
+                spawn_schema: "_spawn".to_string(),
+                environment: "test".to_string(),
+                command: Some(CommandSpec::Direct {
+                    direct: connection_mode.psql_command(&migration_db),
+                }),
+                url: None,
+                session: None,
+                protected: false,
+                history: None,
+                retry: None,
+                wait_for_lock: None,
+            },
+        )
+        .with_telemetry(false);
 
-    let migration_helper =
-        MigrationTestHelper::new_from_operator_with_config(mem_op, config_loader).await?;
+    let migration_helper = MigrationTestHelper::new_from_config(config);
 
     let _ = connection_mode.execute_sql(
         &migration_db,
@@ -1674,8 +2335,15 @@ COMMIT;"#
         pinned: false,
         variables: None,
         yes: true,
+        force: false,
         retry: false,
         reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
     };
     cmd.execute(&config).await?;
 
@@ -1727,8 +2395,15 @@ COMMIT;"#
         pinned: false,
         variables: None,
         yes: true,
+        force: false,
         retry: false,
         reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
     };
     cmd2.execute(&config2).await.expect(
         "Re-applying the same migration should succeed (detected as already applied), \
@@ -1760,3 +2435,186 @@ COMMIT;"#
 
     Ok(())
 }
+
+/// A status exported where the database can be reached lets `migration gate`
+/// check applied migrations without a connection.
+#[tokio::test]
+#[ignore]
+async fn test_gate_against_status_export() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_gate_against_status_export", None).await?;
+
+    let migration_name = helper
+        .migration_helper
+        .create_migration_manual(
+            "status-export",
+            "BEGIN;\nCREATE TABLE status_export_test (id SERIAL PRIMARY KEY);\nCOMMIT;".to_string(),
+        )
+        .await?;
+    helper
+        .migration_helper
+        .pin_migration(&migration_name)
+        .await?;
+    let config = helper.migration_helper.load_config().await?;
+
+    ApplyMigration {
+        migration: Some(migration_name.clone()),
+        pinned: true,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    }
+    .execute(&config)
+    .await?;
+
+    let dir = tempfile::tempdir()?;
+    let export = dir.path().join("status.json").to_string_lossy().to_string();
+    MigrationStatus {
+        history: false,
+        json: false,
+        export: Some(export.clone()),
+        filter: Default::default(),
+        compact: false,
+    }
+    .execute(&config)
+    .await?;
+
+    let gate = MigrationGate {
+        applied: vec![],
+        against: vec![export],
+        variables: None,
+    };
+    assert!(matches!(gate.execute(&config).await?, Outcome::Success));
+
+    helper
+        .migration_helper
+        .fs
+        .write(
+            &config.pather().migration_script_file_path(&migration_name),
+            "BEGIN;\nCREATE TABLE status_export_changed (id SERIAL PRIMARY KEY);\nCOMMIT;",
+        )
+        .await?;
+    assert!(matches!(gate.execute(&config).await?, Outcome::CheckFailed));
+
+    Ok(())
+}
+
+/// The library facade applies migrations and reports the result instead of
+/// printing it.
+#[tokio::test]
+#[ignore]
+async fn test_spawn_apply_and_status() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_spawn_apply_and_status", None).await?;
+    let migration_name = helper
+        .migration_helper
+        .create_migration_manual(
+            "spawn-facade",
+            "BEGIN;\nCREATE TABLE spawn_facade_test (id SERIAL PRIMARY KEY);\nCOMMIT;".to_string(),
+        )
+        .await?;
+    let spawn = Spawn::new(helper.migration_helper.load_config().await?);
+
+    let report = spawn
+        .apply(
+            None,
+            ApplyOptions {
+                pinned: false,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert_eq!(vec![migration_name.as_str()], report.applied());
+
+    let status = spawn.status().await?;
+    let row = status
+        .iter()
+        .find(|row| row.migration_name == migration_name)
+        .expect("status row for applied migration");
+    assert_eq!(
+        Some(spawn_db::engine::MigrationStatus::Success),
+        row.last_status
+    );
+    assert_eq!(1, spawn.history().await?.len());
+
+    // Nothing is left to apply.
+    let report = spawn
+        .apply(
+            None,
+            ApplyOptions {
+                pinned: false,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert!(report.migrations.is_empty());
+
+    Ok(())
+}
+
+/// Records the migrations started and statements sent while applying.
+#[derive(Clone, Default)]
+struct RecordedApply(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+impl EventSink for RecordedApply {
+    fn on_migration_start(&self, migration: &str, index: usize, total: usize) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("start {} {}/{}", migration, index + 1, total));
+    }
+
+    fn on_statement(&self, _migration: &str, statement: &str) {
+        self.0.lock().unwrap().push(statement.to_string());
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_spawn_apply_sends_events() -> Result<()> {
+    require_postgres()?;
+
+    let helper = IntegrationTestHelper::new("test_spawn_apply_sends_events", None).await?;
+    let migration_name = helper
+        .migration_helper
+        .create_migration_manual(
+            "spawn-events",
+            "BEGIN;\nCREATE TABLE spawn_events_test (id SERIAL PRIMARY KEY);\nCOMMIT;\n"
+                .to_string(),
+        )
+        .await?;
+    let recorded = RecordedApply::default();
+    let spawn =
+        Spawn::new(helper.migration_helper.load_config().await?).with_events(recorded.clone());
+
+    spawn
+        .apply(
+            None,
+            ApplyOptions {
+                pinned: false,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert_eq!(
+        vec![
+            format!("start {} 1/1", migration_name),
+            "BEGIN;".to_string(),
+            "CREATE TABLE spawn_events_test (id SERIAL PRIMARY KEY);".to_string(),
+            "COMMIT;".to_string(),
+        ],
+        *recorded.0.lock().unwrap()
+    );
+
+    Ok(())
+}
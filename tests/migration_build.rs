@@ -1,15 +1,62 @@
 use anyhow::{Context, Result};
 use futures::TryStreamExt;
-use opendal::services::Memory;
+use opendal::services::{Fs, Memory};
 use opendal::Operator;
 use pretty_assertions::assert_eq;
 use spawn_db::{
-    commands::{BuildMigration, Check, Command, NewMigration, Outcome, PinMigration},
-    config::{Config, ConfigLoaderSaver},
-    engine::{CommandSpec, EngineType, TargetConfig},
+    agent::{AgentQueue, ApplyRequest, ApplyResultStatus},
+    audit::{append_audit_entry, read_audit_log, AuditEntry, AuditOutcome},
+    commands::{
+        export::{Export, ExportFormat, EXPORT_INDEX_NAME},
+        import::{Import, ImportSource},
+        metrics::{collect_metrics, Metrics},
+        migration::{
+            archive_candidates, compare_golden, diff_envs, get_combined_migration_status,
+            get_pending_migrations, pin_migration_to_tag, AppliedMigration, BuildManifest,
+            EnvDifference, GoldenMismatch, HistoryRecord, StatusExport, StepResult,
+            BUILD_MANIFEST_NAME, DEFAULT_NAMESPACE, STATUS_EXPORT_VERSION,
+        },
+        package::{package_status, PackageState, PackageStatus, PackageUpdate},
+        pin::{
+            collect_pin_stats, export_bundle, import_bundle, pin_diff, tag_components, PinChange,
+        },
+        release::{load_release, unexpected_pending},
+        stats::collect_stats,
+        telemetry::disable_telemetry_setting,
+        test::{component_coverage, list_tests, select_tests},
+        vars::migration_context,
+        workspace::workspace_status,
+        AdoptMigration, Agent, ApplyMigration, ArchiveMigrations, AuditShow, BuildAllMigrations,
+        BuildMigration, Check, CheckBuiltMigrations, Command, CompareTests, ExitCode, ExpectTest,
+        ExplainMigration, MigrationGate, MigrationLocks, NewMigration, Outcome, PinDiff,
+        PinMigration, RecordTest, RunTest, SchemaDocs, Scrub, SubmitMigration, TelemetryDisable,
+    },
+    config::{Config, ConfigBuilder, EnvironmentConfig, TemplateConfig, TenantSource},
+    engine::{
+        memory::MemoryDatabase, postgres_psql::migration_checksum, CommandSpec, EngineError,
+        EngineType, HistoryBackend, HistoryConfig, MigrationStatus, Rows, TargetConfig,
+    },
+    events::{EventSink, Events},
+    layout::{LayoutConfig, Shard},
+    meta_commands::MetaCommandPolicy,
+    migrator::Migrator,
+    naming::NamingConfig,
+    notify::{NotificationsConfig, NotifyStatus, Webhook},
+    packages::PackageConfig,
+    policy::{PolicyConfig, PolicyRule, RuleSeverity},
+    schedule::{MaintenanceWindow, Schedule},
+    secrets::SecretSource,
+    server::{handle, serve, Request},
+    signing,
+    sqltest::{TestKind, Tester},
     store,
+    telemetry::{TelemetryConfig, TelemetrySetting},
+    tracing::TracingConfig,
+    variables::Variables,
+    workspace::Workspace,
+    ApplyOptions, Spawn,
 };
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use tokio;
 
 /// Expected default new migration content:
@@ -21,18 +68,12 @@ COMMIT;
 /// Reusable test helper structure for setting up migration tests
 pub struct MigrationTestHelper {
     pub fs: Operator,
-    config_path: String,
-}
-
-impl MigrationTestHelper {
-    pub fn config_path(&self) -> &str {
-        &self.config_path
-    }
+    config: ConfigBuilder,
 }
 
 impl MigrationTestHelper {
     pub async fn load_config(&self) -> Result<Config> {
-        Config::load(&self.config_path(), &self.fs, None).await
+        self.config.clone().build()
     }
 
     /// Creates a new test environment with no data
@@ -52,56 +93,63 @@ impl MigrationTestHelper {
     }
 
     pub async fn new_from_operator(op: Operator) -> Result<Self> {
-        Self::new_from_operator_with_config(op, Self::default_config_loadersaver()).await
+        Ok(Self::new_from_config(Self::default_config(op)))
     }
 
-    pub async fn new_from_operator_with_config(
-        op: Operator,
-        config_loader: ConfigLoaderSaver,
-    ) -> Result<Self> {
-        let config_path = "./spawn.toml".to_string();
-        let mth = Self {
-            fs: op,
-            config_path,
-        };
-
-        config_loader.save(&mth.config_path, &mth.fs).await?;
+    pub fn new_from_config(config: ConfigBuilder) -> Self {
+        Self {
+            fs: config.operator().clone(),
+            config,
+        }
+    }
 
-        Ok(mth)
+    fn default_target() -> TargetConfig {
+        TargetConfig {
+            engine: EngineType::PostgresPSQL,
+            spawn_database: Some("spawn".to_string()),
+            spawn_schema: "public".to_string(),
+            environment: "dev".to_string(),
+            command: Some(CommandSpec::Direct {
+                direct: vec![
+                    "docker".to_string(),
+                    "exec".to_string(),
+                    "-i".to_string(),
+                    "spawn-db".to_string(),
+                    "psql".to_string(),
+                    "-U".to_string(),
+                    "spawn".to_string(),
+                    "spawn".to_string(),
+                ],
+            }),
+            url: None,
+            session: None,
+            protected: false,
+            history: None,
+            retry: None,
+            wait_for_lock: None,
+        }
     }
 
-    fn default_config_loadersaver() -> ConfigLoaderSaver {
-        let mut targets = HashMap::new();
-        targets.insert(
-            "postgres_psql".to_string(),
+    /// A config whose target keeps its database in memory, in the memory
+    /// database `database`.
+    fn memory_config(op: Operator, database: &str) -> ConfigBuilder {
+        Self::default_config(op).with_target(
+            "postgres_psql",
             TargetConfig {
-                engine: EngineType::PostgresPSQL,
-                spawn_database: Some("spawn".to_string()),
-                spawn_schema: "public".to_string(),
-                environment: "dev".to_string(),
-                command: Some(CommandSpec::Direct {
-                    direct: vec![
-                        "docker".to_string(),
-                        "exec".to_string(),
-                        "-i".to_string(),
-                        "spawn-db".to_string(),
-                        "psql".to_string(),
-                        "-U".to_string(),
-                        "spawn".to_string(),
-                        "spawn".to_string(),
-                    ],
-                }),
+                engine: EngineType::Memory,
+                spawn_database: Some(database.to_string()),
+                command: None,
+                ..Self::default_target()
             },
-        );
+        )
+    }
 
-        ConfigLoaderSaver {
-            spawn_folder: "/db".to_string(),
-            target: Some("postgres_psql".to_string()),
-            environment: Some("dev".to_string()),
-            targets: Some(targets),
-            project_id: None,
-            telemetry: Some(false),
-        }
+    fn default_config(op: Operator) -> ConfigBuilder {
+        ConfigBuilder::new(op)
+            .with_spawn_folder("/db")
+            .with_target("postgres_psql", Self::default_target())
+            .with_environment("dev")
+            .with_telemetry(false)
     }
 
     /// Creates a new migration using the NewMigration command
@@ -109,6 +157,11 @@ impl MigrationTestHelper {
         let config = self.load_config().await?;
         let cmd = NewMigration {
             name: name.to_string(),
+            template: None,
+            set: vec![],
+            edit: false,
+            ticket: None,
+            description: None,
         };
 
         let outcome = cmd.execute(&config).await?;
@@ -166,6 +219,7 @@ impl MigrationTestHelper {
             migration: migration_name.to_string(),
             pinned,
             variables,
+            sign: false,
         };
 
         let outcome = cmd.execute(&config).await?;
@@ -184,6 +238,7 @@ impl MigrationTestHelper {
         let config = self.load_config().await?;
         let cmd = PinMigration {
             migration: migration_name.to_string(),
+            tag: None,
         };
 
         let outcome = cmd
@@ -437,6 +492,58 @@ async fn test_check_fails_with_unpinned_migration() -> Result<(), Box<dyn std::e
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_naming_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let mut config = helper.load_config().await?;
+    let old = "20200101000000-old-style";
+    helper
+        .fs
+        .write(
+            &config.pather().migration_script_file_path(old),
+            DEFAULT_MIGRATION_CONTENT,
+        )
+        .await?;
+    helper.pin_migration(old).await?;
+
+    config.naming = NamingConfig {
+        prefix: Some("[A-Z]+-[0-9]+-".to_string()),
+        ..Default::default()
+    };
+
+    // New migrations must follow the policy.
+    let new = |name: &str| NewMigration {
+        name: name.to_string(),
+        template: None,
+        set: vec![],
+        edit: false,
+        ticket: None,
+        description: None,
+    };
+    let err = new("add-users")
+        .execute(&config)
+        .await
+        .err()
+        .expect("name without a ticket should be refused");
+    assert!(err
+        .to_string()
+        .contains("does not follow the naming policy"));
+    let Outcome::NewMigration(name) = new("DB-12-add-users").execute(&config).await? else {
+        panic!("expected a new migration");
+    };
+    helper.pin_migration(&name).await?;
+
+    // check reports existing migrations that do not, unless exempt.
+    assert!(matches!(
+        Check.execute(&config).await?,
+        Outcome::CheckFailed
+    ));
+    config.naming.exempt_before = Some("20210101000000".to_string());
+    assert!(matches!(Check.execute(&config).await?, Outcome::Success));
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn test_check_passes_when_all_pinned() -> Result<(), Box<dyn std::error::Error>> {
     let helper = MigrationTestHelper::new_empty().await?;
@@ -511,6 +618,7 @@ COMMIT;"#;
         migration: migration_name.to_string(),
         pinned: false,
         variables: None,
+        sign: false,
     };
 
     let outcome = cmd.execute(&config).await?;
@@ -521,6 +629,7 @@ COMMIT;"#;
         migration: migration_name.to_string(),
         pinned: true,
         variables: None,
+        sign: false,
     };
 
     let outcome_pinned = cmd_pinned.execute(&config).await?;
@@ -528,3 +637,4134 @@ COMMIT;"#;
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_build_strict_requires_pinned_flag() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+
+    let migration_name = helper
+        .create_migration_manual("test-strict", "SELECT 1;".to_string())
+        .await?;
+    helper.pin_migration(&migration_name).await?;
+
+    let mut config = helper.load_config().await?;
+    config.strict = true;
+
+    let cmd = BuildMigration {
+        migration: migration_name.to_string(),
+        pinned: false,
+        variables: None,
+        sign: false,
+    };
+    assert!(cmd.execute(&config).await.is_err());
+
+    let cmd_pinned = BuildMigration {
+        pinned: true,
+        ..cmd
+    };
+    let outcome = cmd_pinned.execute(&config).await?;
+    assert!(matches!(outcome, Outcome::BuiltMigration { .. }));
+
+    Ok(())
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(ExitCode::Success, Outcome::Success.exit_code());
+    assert_eq!(ExitCode::CheckFailed, Outcome::CheckFailed.exit_code());
+    assert_eq!(
+        ExitCode::PendingMigrations,
+        Outcome::PendingMigrations(2).exit_code()
+    );
+    assert_eq!(ExitCode::TestsFailed, Outcome::TestsFailed.exit_code());
+    assert_eq!(ExitCode::Error, Outcome::Aborted.exit_code());
+
+    let unreachable = anyhow::Error::from(EngineError::ExecutionFailed {
+        exit_code: 2,
+        stderr: "could not connect to server".to_string(),
+    })
+    .context("failed to apply migration");
+    assert_eq!(
+        ExitCode::ConnectionFailed,
+        ExitCode::for_error(&unreachable)
+    );
+
+    let failed = anyhow::Error::from(EngineError::ExecutionFailed {
+        exit_code: 3,
+        stderr: "syntax error".to_string(),
+    });
+    assert_eq!(ExitCode::Error, ExitCode::for_error(&failed));
+    assert_eq!(ExitCode::Error as i32, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_stats_counts_project_contents() -> Result<(), Box<dyn std::error::Error>> {
+    let helper =
+        MigrationTestHelper::new_from_local_folder("./static/tests/two_migrations").await?;
+    helper.pin_migration("20240907212659-initial").await?;
+
+    let config = helper.load_config().await?;
+    let stats = collect_stats(&config).await?;
+
+    assert_eq!(stats.migrations.total, 2);
+    assert_eq!(stats.migrations.pinned, 1);
+    assert_eq!(stats.components, 1);
+    assert_eq!(stats.tests, 0);
+    assert!(stats.average_migration_bytes > 0);
+    assert!(stats.pinned_store.objects > 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_build_with_tenants() -> Result<(), Box<dyn std::error::Error>> {
+    let mem_op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(mem_op).with_tenants(TenantSource::List {
+            list: vec!["acme".to_string(), "globex".to_string()],
+        }),
+    );
+
+    let migration_name = helper
+        .create_migration_manual(
+            "per-tenant",
+            r#"{% for t in tenants %}CREATE SCHEMA {{ t|escape_identifier }};
+{% endfor %}"#
+                .to_string(),
+        )
+        .await?;
+
+    let built = helper.build_migration(&migration_name, false).await?;
+    assert_eq!(
+        "CREATE SCHEMA \"acme\";\nCREATE SCHEMA \"globex\";\n",
+        built
+    );
+
+    // Restricting to a single tenant only renders that tenant.
+    let mut config = helper.load_config().await?;
+    config.tenant_filter = vec!["globex".to_string()];
+    let cmd = BuildMigration {
+        migration: migration_name.clone(),
+        pinned: false,
+        variables: None,
+        sign: false,
+    };
+    match cmd.execute(&config).await? {
+        Outcome::BuiltMigration { content, .. } => {
+            assert_eq!("CREATE SCHEMA \"globex\";\n", content)
+        }
+        _ => panic!("unexpected outcome"),
+    }
+
+    // Unknown tenants are rejected rather than silently rendering nothing.
+    config.tenant_filter = vec!["initech".to_string()];
+    assert!(cmd.execute(&config).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_build_with_template_constants() -> Result<(), Box<dyn std::error::Error>> {
+    let mem_op = Operator::new(Memory::default())?.finish();
+    let mut constants = BTreeMap::new();
+    constants.insert(
+        "app_role".to_string(),
+        toml::Value::String("app_user".to_string()),
+    );
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(mem_op).with_template(TemplateConfig {
+            constants,
+            ..Default::default()
+        }),
+    );
+
+    let migration_name = helper
+        .create_migration_manual(
+            "grant-role",
+            "GRANT SELECT ON users TO {{ constants.app_role|escape_identifier }};".to_string(),
+        )
+        .await?;
+
+    let built = helper.build_migration(&migration_name, false).await?;
+    assert_eq!("GRANT SELECT ON users TO \"app_user\";", built);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_build_with_local_include() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let migration_name = helper
+        .create_migration_manual(
+            "local-include",
+            r#"BEGIN;
+{% include "./partials/indexes.sql" %}
+COMMIT;"#
+                .to_string(),
+        )
+        .await?;
+
+    let cfg = helper.load_config().await?;
+    let partial_path = format!(
+        "{}/partials/indexes.sql",
+        cfg.pather().migration_folder(&migration_name)
+    );
+    helper
+        .fs
+        .write(&partial_path, "CREATE INDEX users_email ON users (email);")
+        .await?;
+
+    let built = helper.build_migration(&migration_name, false).await?;
+    assert_eq!(
+        "BEGIN;\nCREATE INDEX users_email ON users (email);\nCOMMIT;",
+        built
+    );
+
+    // Pinning captures the partial, so later edits only show up unpinned.
+    helper.pin_migration(&migration_name).await?;
+    helper
+        .fs
+        .write(&partial_path, "CREATE INDEX users_name ON users (name);")
+        .await?;
+
+    let pinned = helper.build_migration(&migration_name, true).await?;
+    assert_eq!(
+        "BEGIN;\nCREATE INDEX users_email ON users (email);\nCOMMIT;",
+        pinned
+    );
+    let unpinned = helper.build_migration(&migration_name, false).await?;
+    assert_eq!(
+        "BEGIN;\nCREATE INDEX users_name ON users (name);\nCOMMIT;",
+        unpinned
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_pin_diff_reports_component_changes() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let cfg = helper.load_config().await?;
+    let components = cfg.pather().components_folder();
+    helper
+        .fs
+        .write(&format!("{}/keep.sql", components), "SELECT 1;\n")
+        .await?;
+    helper
+        .fs
+        .write(&format!("{}/edit.sql", components), "SELECT 1;\n")
+        .await?;
+    helper
+        .fs
+        .write(&format!("{}/gone.sql", components), "SELECT 1;\n")
+        .await?;
+
+    let migration_name = helper.create_migration("diff-me").await?;
+    helper.pin_migration(&migration_name).await?;
+    assert_eq!(
+        Vec::<PinChange>::new(),
+        pin_diff(&cfg, &migration_name).await?
+    );
+
+    helper
+        .fs
+        .write(&format!("{}/edit.sql", components), "SELECT 2;\n")
+        .await?;
+    helper
+        .fs
+        .delete(&format!("{}/gone.sql", components))
+        .await?;
+    helper
+        .fs
+        .write(&format!("{}/nested/new.sql", components), "SELECT 3;\n")
+        .await?;
+
+    let changes = pin_diff(&cfg, &migration_name).await?;
+    assert_eq!(
+        vec![
+            PinChange::Changed {
+                name: "edit.sql".to_string(),
+                diff: Some(
+                    "--- pinned\n+++ current\n@@ -1 +1 @@\n-SELECT 1;\n+SELECT 2;\n".to_string()
+                ),
+            },
+            PinChange::Removed("gone.sql".to_string()),
+            PinChange::Added("nested/new.sql".to_string()),
+        ],
+        changes
+    );
+
+    // `spawn pin diff` only reports, leaving the pin as it was.
+    let lock_path = cfg.pather().migration_lock_file_path(&migration_name);
+    let lock = helper.fs.read(&lock_path).await?.to_vec();
+    let outcome = PinDiff {
+        migration: migration_name.clone(),
+    }
+    .execute(&cfg)
+    .await?;
+    assert!(matches!(outcome, Outcome::Success));
+    assert_eq!(lock, helper.fs.read(&lock_path).await?.to_vec());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_pin_stats_reports_dedup() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let cfg = helper.load_config().await?;
+    let components = cfg.pather().components_folder();
+    helper
+        .fs
+        .write(&format!("{}/shared.sql", components), "SELECT 1;")
+        .await?;
+
+    let first = helper.create_migration("first").await?;
+    helper.pin_migration(&first).await?;
+
+    helper
+        .fs
+        .write(&format!("{}/extra.sql", components), "SELECT 22;")
+        .await?;
+    let second = helper.create_migration("second").await?;
+    helper.pin_migration(&second).await?;
+
+    let stats = collect_pin_stats(&cfg).await?;
+    assert_eq!(2, stats.roots);
+    // Two root trees and two blobs; shared.sql is stored once.
+    assert_eq!(4, stats.objects);
+    assert_eq!(4, stats.referenced_objects);
+    assert_eq!(0, stats.unreferenced_objects);
+    assert_eq!(9 + 9 + 10, stats.logical_bytes);
+    assert_eq!(9 + 10, stats.blob_bytes);
+    assert_eq!("extra.sql", stats.largest_blobs[0].name);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_pin_export_import_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let source = MigrationTestHelper::new_empty().await?;
+    let cfg = source.load_config().await?;
+    source
+        .fs
+        .write(
+            &format!("{}/grant.sql", cfg.pather().components_folder()),
+            "GRANT SELECT ON users TO app;",
+        )
+        .await?;
+    let migration_name = source
+        .create_migration_manual(
+            "exported",
+            r#"BEGIN;
+{% include "grant.sql" %}
+{% include "./partials/indexes.sql" %}
+COMMIT;"#
+                .to_string(),
+        )
+        .await?;
+    source
+        .fs
+        .write(
+            &format!(
+                "{}/partials/indexes.sql",
+                cfg.pather().migration_folder(&migration_name)
+            ),
+            "CREATE INDEX users_email ON users (email);",
+        )
+        .await?;
+    source.pin_migration(&migration_name).await?;
+
+    let mut bundle = Vec::new();
+    let exported = export_bundle(&cfg, &migration_name, &mut bundle).await?;
+    // Three trees (components, the migration folder and partials/) and two
+    // blobs.
+    assert_eq!(5, exported.objects);
+
+    let target = MigrationTestHelper::new_empty().await?;
+    let target_cfg = target.load_config().await?;
+    let imported = import_bundle(&target_cfg, bundle.as_slice()).await?;
+    assert_eq!(exported, imported);
+
+    let expected = source.build_migration(&migration_name, true).await?;
+    let built = target.build_migration(&migration_name, true).await?;
+    assert_eq!(expected, built);
+
+    // Importing again is harmless.
+    import_bundle(&target_cfg, bundle.as_slice()).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_signed_migration_verifies_until_changed() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    helper
+        .fs
+        .write("/db/components/users.sql", "SELECT * FROM users;")
+        .await?;
+    let migration_name = helper
+        .create_migration_manual("signed", "BEGIN;\nSELECT 1;\nCOMMIT;".to_string())
+        .await?;
+    helper.pin_migration(&migration_name).await?;
+
+    let key = signing::generate_key();
+    let mut cfg = helper.load_config().await?;
+    cfg.signing.trusted_keys = vec![signing::encode_public_key(&key.verifying_key())];
+
+    // Unsigned migrations are rejected.
+    assert!(signing::verify_migration(&cfg, &migration_name)
+        .await
+        .is_err());
+
+    signing::sign_migration(&cfg, &migration_name, &key).await?;
+    signing::verify_migration(&cfg, &migration_name).await?;
+
+    // The signature file is not part of the pin, so check stays clean.
+    let outcome = Check.execute(&cfg).await?;
+    assert!(matches!(outcome, Outcome::Success));
+
+    // Editing a pinned object in place is caught, rather than rendering
+    // other SQL under the same signature.
+    let mut tampered = None;
+    for entry in helper.fs.list_with("/db/pinned/").recursive(true).await? {
+        if entry.metadata().is_file()
+            && helper.fs.read(entry.path()).await?.to_vec() == b"SELECT * FROM users;"
+        {
+            tampered = Some(entry.path().to_string());
+        }
+    }
+    let tampered = tampered.expect("component is pinned");
+    helper.fs.write(&tampered, "DROP TABLE users;").await?;
+    let err = signing::verify_migration(&cfg, &migration_name)
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("has been modified"),
+        "{:#}",
+        err
+    );
+    helper.fs.write(&tampered, "SELECT * FROM users;").await?;
+    signing::verify_migration(&cfg, &migration_name).await?;
+
+    // Editing the script after signing invalidates the signature.
+    helper
+        .fs
+        .write(
+            &cfg.pather().migration_script_file_path(&migration_name),
+            "BEGIN;\nSELECT 2;\nCOMMIT;",
+        )
+        .await?;
+    assert!(signing::verify_migration(&cfg, &migration_name)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_apply_signed_artifact() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_apply_signed_artifact",
+    ));
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    helper.pin_migration(&users).await?;
+
+    let dir = tempfile::tempdir()?;
+    let key = signing::generate_key();
+    let key_file = dir.path().join("signing.key");
+    std::fs::write(&key_file, signing::encode_signing_key(&key))?;
+    let mut cfg = helper.load_config().await?;
+    cfg.signing.key_file = Some(key_file.display().to_string());
+    cfg.signing.trusted_keys = vec![signing::encode_public_key(&key.verifying_key())];
+
+    let Outcome::BuiltMigration { content, .. } = BuildMigration {
+        migration: users.clone(),
+        pinned: true,
+        variables: None,
+        sign: true,
+    }
+    .execute(&cfg)
+    .await?
+    else {
+        panic!("expected a built migration");
+    };
+    let write = |name: &str, contents: &str| -> std::io::Result<String> {
+        let path = dir.path().join(name);
+        // As written by redirecting `spawn migration build`.
+        std::fs::write(&path, format!("{}\n", contents))?;
+        Ok(path.display().to_string())
+    };
+    let signed = write("signed.sql", &content)?;
+    let edited = write("edited.sql", &content.replace("(id int)", "(id bigint)"))?;
+    let unsigned = write("unsigned.sql", "CREATE TABLE users (id int);\n")?;
+
+    let from_file = |path: &str, name: &str| ApplyMigration {
+        migration: Some(name.to_string()),
+        from_file: Some(path.to_string()),
+        require_signature: true,
+        ..apply_all()
+    };
+    for (path, name) in [
+        (&edited, &users),
+        (&unsigned, &users),
+        (&signed, &"orders".to_string()),
+    ] {
+        let err = from_file(path, name)
+            .execute(&cfg)
+            .await
+            .err()
+            .expect("refused");
+        assert!(
+            format!("{:#}", err).contains("refusing to apply"),
+            "{:#}",
+            err
+        );
+    }
+    from_file(&signed, &users).execute(&cfg).await?;
+    let history = Spawn::new(helper.load_config().await?).history().await?;
+    assert_eq!(1, history.len());
+
+    // Files are checked for order like any other migration.
+    let err = ApplyMigration {
+        require_signature: false,
+        ..from_file(&unsigned, "20000101000000-old")
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("out of order");
+    assert!(err.to_string().contains("out of order"), "{}", err);
+    assert!(
+        err.to_string().contains(
+            "\nThis usually means it was merged after newer migrations were applied. Check it does not"
+        ),
+        "{}",
+        err
+    );
+    assert!(!err.to_string().contains("  Check"), "{}", err);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_protected_target_refuses_yes_without_force() -> Result<(), Box<dyn std::error::Error>>
+{
+    let mem_service = Memory::default();
+    let op = Operator::new(mem_service)?.finish();
+    let helper =
+        MigrationTestHelper::new_from_config(MigrationTestHelper::default_config(op).with_target(
+            "postgres_psql",
+            TargetConfig {
+                protected: true,
+                ..MigrationTestHelper::default_target()
+            },
+        ));
+    let cfg = helper.load_config().await?;
+
+    // Both are refused before connecting to the database.
+    let err = ApplyMigration {
+        migration: Some("20240101000000-users".to_string()),
+        pinned: true,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("protected target should be refused");
+    assert!(err.to_string().contains("is protected"), "{}", err);
+
+    let err = AdoptMigration {
+        migration: Some("20240101000000-users".to_string()),
+        yes: true,
+        force: false,
+        description: Some("applied by hand".to_string()),
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("protected target should be refused");
+    assert!(err.to_string().contains("is protected"), "{}", err);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_environment_allowed_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let mem_service = Memory::default();
+    let op = Operator::new(mem_service)?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(op).with_environment_config(
+            "dev",
+            EnvironmentConfig {
+                allowed_commands: Some(vec![
+                    "status".to_string(),
+                    "check".to_string(),
+                    "migration build".to_string(),
+                    "test".to_string(),
+                ]),
+                ..Default::default()
+            },
+        ),
+    );
+    let mut cfg = helper.load_config().await?;
+
+    for allowed in ["migration status", "check", "migration build", "test run"] {
+        assert!(cfg.check_command_allowed(allowed).is_ok(), "{}", allowed);
+    }
+    for refused in [
+        "migration apply",
+        "migration adopt",
+        "release apply",
+        "stats",
+    ] {
+        let err = cfg.check_command_allowed(refused).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("not allowed in the dev environment"));
+    }
+
+    // Naming an environment without an allowlist does not lift the target's.
+    cfg.environment = Some("staging".to_string());
+    assert!(cfg.check_command_allowed("migration status").is_ok());
+    let err = cfg.check_command_allowed("migration apply").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("not allowed in the dev environment"));
+
+    // Nor does a target in an environment without one escape a stricter
+    // --environment.
+    let mut cfg = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(Operator::new(Memory::default())?.finish())
+            .with_environment_config(
+                "prod",
+                EnvironmentConfig {
+                    allowed_commands: Some(vec!["status".to_string()]),
+                    ..Default::default()
+                },
+            ),
+    )
+    .load_config()
+    .await?;
+    assert!(cfg.check_command_allowed("migration apply").is_ok());
+    cfg.environment = Some("prod".to_string());
+    let err = cfg.check_command_allowed("migration apply").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("not allowed in the prod environment"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_build_all_writes_files_and_manifest() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let first = helper
+        .create_migration_manual("first", "SELECT 1;".to_string())
+        .await?;
+    let second = helper
+        .create_migration_manual("second", "SELECT 2;".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+    let out = tempfile::tempdir()?;
+    let build_all = |pinned: bool| BuildAllMigrations {
+        pinned,
+        variables: None,
+        pending: false,
+        out_dir: out.path().to_string_lossy().into_owned(),
+    };
+
+    // Nothing is pinned yet, so a pinned build is refused.
+    assert!(build_all(true).execute(&cfg).await.is_err());
+
+    build_all(false).execute(&cfg).await?;
+    let manifest: BuildManifest = toml::from_str(&std::fs::read_to_string(
+        out.path().join(BUILD_MANIFEST_NAME),
+    )?)?;
+    let names: Vec<&str> = manifest
+        .migrations
+        .iter()
+        .map(|m| m.migration.as_str())
+        .collect();
+    assert_eq!(vec![first.as_str(), second.as_str()], names);
+    assert!(!manifest.pinned);
+    let built = &manifest.migrations[1];
+    assert_eq!(format!("{}.sql", second), built.file);
+    assert!(std::fs::read_to_string(out.path().join(&built.file))?.contains("SELECT 2;"));
+    assert_eq!(None, built.pin);
+
+    // Once pinned, each entry records its pin.
+    let first_pin = helper.pin_migration(&first).await?;
+    helper.pin_migration(&second).await?;
+    build_all(true).execute(&cfg).await?;
+    let manifest: BuildManifest = toml::from_str(&std::fs::read_to_string(
+        out.path().join(BUILD_MANIFEST_NAME),
+    )?)?;
+    assert!(manifest.pinned);
+    assert_eq!(Some(first_pin), manifest.migrations[0].pin);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_plain_sql() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let first = helper
+        .create_migration_manual("first", "SELECT 1;".to_string())
+        .await?;
+    let second = helper
+        .create_migration_manual("second", "SELECT 2;".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+    let out = tempfile::tempdir()?;
+    let export = || Export {
+        format: ExportFormat::PlainSql,
+        dir: out.path().to_string_lossy().into_owned(),
+        pinned: false,
+        variables: None,
+    };
+
+    export().execute(&cfg).await?;
+    let index: BuildManifest = toml::from_str(&std::fs::read_to_string(
+        out.path().join(EXPORT_INDEX_NAME),
+    )?)?;
+    let files: Vec<(&str, &str)> = index
+        .migrations
+        .iter()
+        .map(|m| (m.migration.as_str(), m.file.as_str()))
+        .collect();
+    let first_file = format!("0001-{}.sql", first);
+    let second_file = format!("0002-{}.sql", second);
+    assert_eq!(
+        vec![
+            (first.as_str(), first_file.as_str()),
+            (second.as_str(), second_file.as_str())
+        ],
+        files
+    );
+    assert_eq!(
+        "SELECT 2;",
+        std::fs::read_to_string(out.path().join(&second_file))?
+    );
+
+    // Exporting again replaces the earlier files.
+    helper
+        .fs
+        .delete(&cfg.pather().migration_script_file_path(&first))
+        .await?;
+    export().execute(&cfg).await?;
+    assert!(!out.path().join(&second_file).exists());
+    assert!(out.path().join(format!("0001-{}.sql", second)).exists());
+
+    // Folders not written by spawn export are left alone.
+    let other = tempfile::tempdir()?;
+    std::fs::write(other.path().join("notes.txt"), "keep")?;
+    let err = Export {
+        dir: other.path().to_string_lossy().into_owned(),
+        ..export()
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("a folder with other files is refused");
+    assert!(err.to_string().contains("not written by spawn export"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_build_check_golden_files() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    helper
+        .fs
+        .write("/db/components/users.sql", "CREATE TABLE users (id int);")
+        .await?;
+    let first = helper
+        .create_migration_manual("first", "{% include \"users.sql\" %}".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+    let golden = tempfile::tempdir()?;
+    let dir = golden.path().to_string_lossy().into_owned();
+    BuildAllMigrations {
+        pinned: false,
+        variables: None,
+        pending: false,
+        out_dir: dir.clone(),
+    }
+    .execute(&cfg)
+    .await?;
+
+    let check = CheckBuiltMigrations {
+        pinned: false,
+        variables: None,
+        dir: dir.clone(),
+    };
+    assert!(matches!(check.execute(&cfg).await?, Outcome::Success));
+
+    // Changing a component changes the old migration's output.
+    helper
+        .fs
+        .write(
+            "/db/components/users.sql",
+            "CREATE TABLE users (id bigint);",
+        )
+        .await?;
+    let second = helper
+        .create_migration_manual("second", "SELECT 2;".to_string())
+        .await?;
+    std::fs::write(golden.path().join("removed.sql"), "SELECT 0;")?;
+    let mismatches = compare_golden(&cfg, &dir, false, None).await?;
+    assert_eq!(3, mismatches.len());
+    assert!(matches!(
+        &mismatches[0],
+        GoldenMismatch::Differs { migration, first_diff_line: Some(1), .. } if *migration == first
+    ));
+    assert_eq!(GoldenMismatch::Missing { migration: second }, mismatches[1]);
+    assert_eq!(
+        GoldenMismatch::Orphaned {
+            migration: "removed".to_string()
+        },
+        mismatches[2]
+    );
+    assert!(matches!(check.execute(&cfg).await?, Outcome::CheckFailed));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_release_manifest_validation() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let first = helper.create_migration("first").await?;
+    let second = helper.create_migration("second").await?;
+    let cfg = helper.load_config().await?;
+
+    helper
+        .fs
+        .write(
+            &cfg.pather().release_file_path("2024.1"),
+            format!(
+                "description = \"Users\"\nmigrations = [\"{}\", \"{}\"]\n",
+                first, second
+            ),
+        )
+        .await?;
+    let release = load_release(&cfg, "2024.1").await?;
+    assert_eq!(vec![first.clone(), second.clone()], release.migrations);
+
+    // Pending migrations before the release's last migration are unexpected,
+    // later ones belong to a future release.
+    let pending = vec![
+        "00000000000000-older".to_string(),
+        second.clone(),
+        "99999999999999-newer".to_string(),
+    ];
+    assert_eq!(
+        vec!["00000000000000-older".to_string()],
+        unexpected_pending(&release, &pending)
+    );
+
+    helper
+        .fs
+        .write(
+            &cfg.pather().release_file_path("missing"),
+            "migrations = [\"20240101000000-nope\"]\n",
+        )
+        .await?;
+    assert!(load_release(&cfg, "missing").await.is_err());
+
+    helper
+        .fs
+        .write(
+            &cfg.pather().release_file_path("twice"),
+            format!("migrations = [\"{}\", \"{}\"]\n", first, first),
+        )
+        .await?;
+    assert!(load_release(&cfg, "twice").await.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_disable_telemetry_setting() -> Result<()> {
+    // Added before the first table when not set.
+    let contents =
+        "spawn_folder = \"spawn\"\n\n# Targets\n[targets.local]\nengine = \"postgres-psql\"\n";
+    assert_eq!(
+        "spawn_folder = \"spawn\"\n\ntelemetry = false\n\n# Targets\n[targets.local]\nengine = \"postgres-psql\"\n",
+        disable_telemetry_setting(contents)?
+    );
+
+    // An existing setting is replaced.
+    assert_eq!(
+        "spawn_folder = \"spawn\"\ntelemetry = false\n",
+        disable_telemetry_setting("spawn_folder = \"spawn\"\ntelemetry = true\n")?
+    );
+
+    // A [telemetry] section keeps its endpoint.
+    assert_eq!(
+        "[telemetry]\nenabled = false\nendpoint = \"file://./t.jsonl\"\n",
+        disable_telemetry_setting("[telemetry]\nendpoint = \"file://./t.jsonl\"\n")?
+    );
+
+    // Disabling twice changes nothing.
+    let once = disable_telemetry_setting(contents)?;
+    assert_eq!(once, disable_telemetry_setting(&once)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_config_builder() -> Result<()> {
+    let op = Operator::new(Memory::default())?.finish();
+    let config = ConfigBuilder::new(op.clone())
+        .with_target("first", MigrationTestHelper::default_target())
+        .with_target("second", MigrationTestHelper::default_target())
+        .build()?;
+    assert_eq!(Some("first".to_string()), config.target);
+    assert_eq!("spawn/migrations", config.pather().migrations_folder());
+    assert!(config.telemetry);
+    assert!(config.environment.is_none());
+    assert_eq!("dev", config.target_config()?.environment);
+
+    let config = ConfigBuilder::new(op.clone())
+        .with_target("first", MigrationTestHelper::default_target())
+        .with_target("second", MigrationTestHelper::default_target())
+        .with_selected_target("second")
+        .with_environment("staging")
+        .build()?;
+    assert_eq!(Some("second".to_string()), config.target);
+    assert_eq!("staging", config.target_config()?.environment);
+
+    let err = ConfigBuilder::new(op)
+        .with_telemetry(TelemetrySetting::Config(TelemetryConfig {
+            enabled: true,
+            endpoint: Some("ftp://example.com".to_string()),
+        }))
+        .build()
+        .unwrap_err();
+    assert!(err.to_string().contains("unsupported telemetry.endpoint"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_telemetry_disable_updates_config() -> Result<()> {
+    let fs = Operator::new(Memory::default())?.finish();
+    let config_path = "./spawn.toml";
+    let contents = "spawn_folder = \"/db\"\ntarget = \"memory\"\n";
+    fs.write(config_path, contents).await?;
+    assert!(Config::load(config_path, &fs, None).await?.telemetry);
+
+    TelemetryDisable {
+        config_file: config_path.to_string(),
+    }
+    .execute(&fs)
+    .await?;
+
+    assert!(!Config::load(config_path, &fs, None).await?.telemetry);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_new_migration_from_template() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let cfg = helper.load_config().await?;
+    helper
+        .fs
+        .write(
+            &cfg.pather().template_file_path("add-column"),
+            "BEGIN;\nALTER TABLE {{ variables.schema }}.[[ table ]] ADD COLUMN [[column]] text;\nCOMMIT;\n",
+        )
+        .await?;
+
+    let new = |set: Vec<(&str, &str)>| NewMigration {
+        name: "add-email".to_string(),
+        template: Some("add-column".to_string()),
+        set: set
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        edit: false,
+        ticket: None,
+        description: None,
+    };
+
+    let Outcome::NewMigration(name) = new(vec![("table", "users"), ("column", "email")])
+        .execute(&cfg)
+        .await?
+    else {
+        panic!("expected a new migration");
+    };
+    let script = helper
+        .fs
+        .read(&cfg.pather().migration_script_file_path(&name))
+        .await?
+        .to_vec();
+    assert_eq!(
+        "BEGIN;\nALTER TABLE {{ variables.schema }}.users ADD COLUMN email text;\nCOMMIT;\n",
+        String::from_utf8(script)?
+    );
+
+    // A value for a placeholder the template does not have is a mistake.
+    let err = new(vec![("table", "users"), ("colum", "email")])
+        .execute(&cfg)
+        .await
+        .err()
+        .expect("unknown placeholder should fail");
+    assert!(
+        err.to_string().contains("no placeholder 'colum'"),
+        "{}",
+        err
+    );
+
+    // Unknown templates list the ones that exist.
+    let err = NewMigration {
+        name: "x".to_string(),
+        template: Some("create-table".to_string()),
+        set: vec![],
+        edit: false,
+        ticket: None,
+        description: None,
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("missing template should fail");
+    assert!(
+        err.to_string().contains("available templates: add-column"),
+        "{}",
+        err
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_gate() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let config = helper.load_config().await?;
+    let gate = |applied: Vec<String>| MigrationGate {
+        applied,
+        against: vec![],
+        variables: None,
+    };
+
+    // Pending migrations must be pinned.
+    let migration = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    assert!(matches!(
+        gate(vec![]).execute(&config).await?,
+        Outcome::CheckFailed
+    ));
+    let pin_hash = helper.pin_migration(&migration).await?;
+    assert!(matches!(
+        gate(vec![]).execute(&config).await?,
+        Outcome::Success
+    ));
+
+    // Export the history of an environment the migration was applied to.
+    let mut sql = Vec::new();
+    Migrator::new(&config, &migration, true)
+        .generate_streaming(None)
+        .await?
+        .render_to_writer(&mut sql)?;
+    let export_dir = tempfile::tempdir()?;
+    let export = export_dir.path().join("production.json");
+    std::fs::write(
+        &export,
+        serde_json::to_string(&vec![HistoryRecord {
+            migration: migration.clone(),
+            namespace: "default".to_string(),
+            activity: "APPLY".to_string(),
+            status: Some("SUCCESS".to_string()),
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            execution_time: 0.1,
+            checksum: Some(migration_checksum(&sql)),
+            pin_hash: Some(pin_hash),
+            created_by: None,
+            description: None,
+        }])?,
+    )?;
+    let applied = vec![export.to_string_lossy().to_string()];
+    assert!(matches!(
+        gate(applied.clone()).execute(&config).await?,
+        Outcome::Success
+    ));
+
+    // Changing the applied migration is caught.
+    helper
+        .fs
+        .write(
+            &config.pather().migration_script_file_path(&migration),
+            "CREATE TABLE users (id bigint);\n",
+        )
+        .await?;
+    assert!(matches!(
+        gate(applied.clone()).execute(&config).await?,
+        Outcome::CheckFailed
+    ));
+    helper
+        .fs
+        .write(
+            &config.pather().migration_script_file_path(&migration),
+            "CREATE TABLE users (id int);\n",
+        )
+        .await?;
+
+    // As is a migration from another branch with the same timestamp.
+    let timestamp = &migration[..14];
+    let other = format!("{}-orders", timestamp);
+    helper
+        .fs
+        .write(
+            &config.pather().migration_script_file_path(&other),
+            DEFAULT_MIGRATION_CONTENT,
+        )
+        .await?;
+    helper.pin_migration(&other).await?;
+    assert!(matches!(
+        gate(applied).execute(&config).await?,
+        Outcome::CheckFailed
+    ));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_gate_against_status_export() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let config = helper.load_config().await?;
+    let migration = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+
+    // Applied unpinned, so it is rendered unpinned and need not be pinned.
+    let mut sql = Vec::new();
+    Migrator::new(&config, &migration, false)
+        .generate_streaming(None)
+        .await?
+        .render_to_writer(&mut sql)?;
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("status.json").to_string_lossy().to_string();
+    let write_export = |checksum: String| {
+        StatusExport {
+            version: STATUS_EXPORT_VERSION,
+            exported_at: "2024-01-02T00:00:00+00:00".to_string(),
+            namespace: "default".to_string(),
+            migrations: vec![AppliedMigration {
+                migration: migration.clone(),
+                activity: "APPLY".to_string(),
+                applied_at: "2024-01-01T00:00:00+00:00".to_string(),
+                checksum: Some(checksum),
+                pin_hash: None,
+            }],
+        }
+        .write(&path)
+    };
+    let gate = MigrationGate {
+        applied: vec![],
+        against: vec![path.clone()],
+        variables: None,
+    };
+
+    write_export(migration_checksum(&sql))?;
+    assert!(matches!(gate.execute(&config).await?, Outcome::Success));
+
+    write_export("0".repeat(32))?;
+    assert!(matches!(gate.execute(&config).await?, Outcome::CheckFailed));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_spawn_build_returns_sql() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let migration = helper
+        .create_migration_manual(
+            "users",
+            "CREATE TABLE users (id int, tier int DEFAULT {{ 1 + 1 }});\n".to_string(),
+        )
+        .await?;
+
+    let spawn = Spawn::new(helper.load_config().await?);
+    assert_eq!(
+        "CREATE TABLE users (id int, tier int DEFAULT 2);",
+        spawn.build(&migration, false, None).await?
+    );
+    // It was never pinned, so cannot be built from pinned components.
+    assert!(spawn.build(&migration, true, None).await.is_err());
+
+    Ok(())
+}
+
+/// Records warnings sent to it, for checking what a command reported.
+#[derive(Clone, Default)]
+struct RecordedWarnings(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+impl EventSink for RecordedWarnings {
+    fn on_warning(&self, message: &str) {
+        self.0.lock().unwrap().push(message.to_string());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_warnings_are_sent_to_event_sink() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let migration = helper
+        .create_migration_manual("first", "SELECT 1;".to_string())
+        .await?;
+    helper.pin_migration(&migration).await?;
+
+    let warnings = RecordedWarnings::default();
+    let mut config = helper.load_config().await?;
+    config.events = Events::new(warnings.clone());
+
+    let out = tempfile::tempdir()?;
+    BuildAllMigrations {
+        pinned: false,
+        variables: None,
+        pending: false,
+        out_dir: out.path().to_string_lossy().into_owned(),
+    }
+    .execute(&config)
+    .await?;
+    assert_eq!(
+        vec!["1 of the migrations are pinned. Run with --pinned to build them as they will be applied."],
+        *warnings.0.lock().unwrap()
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_meta_command_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_meta_command_policy")
+            .with_meta_commands(MetaCommandPolicy {
+                copy_paths: Some(vec!["seed/".to_string()]),
+                ..Default::default()
+            })
+            .with_environment_config(
+                "dev",
+                EnvironmentConfig {
+                    meta_commands: Some(MetaCommandPolicy {
+                        forbid: vec!["!".to_string()],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            ),
+    );
+    let shell = helper
+        .create_migration_manual(
+            "shell",
+            "SELECT '\\! is fine in a string';\n\\! rm -rf /tmp/data\n".to_string(),
+        )
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    // The dev environment's policy replaces the project's.
+    assert_eq!(vec!["!".to_string()], cfg.meta_command_policy()?.forbid);
+
+    let err = ApplyMigration {
+        migration: Some(shell.clone()),
+        ..apply_all()
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("forbidden meta-command was applied")
+    .to_string();
+    assert!(err.contains("line 2: \\! is forbidden"), "{}", err);
+    let database = MemoryDatabase::named("test_meta_command_policy");
+    assert!(!database.executed().join("").contains("rm -rf"));
+
+    assert!(matches!(Check.execute(&cfg).await?, Outcome::CheckFailed));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_destructive_statements_on_protected_target() -> Result<(), Box<dyn std::error::Error>>
+{
+    // Applying to a protected target with --yes needs --force and this.
+    std::env::set_var("SPAWN_ALLOW_PROTECTED", "1");
+    let op = Operator::new(Memory::default())?.finish();
+    let helper =
+        MigrationTestHelper::new_from_config(MigrationTestHelper::default_config(op).with_target(
+            "postgres_psql",
+            TargetConfig {
+                engine: EngineType::Memory,
+                spawn_database: Some("test_destructive_statements".to_string()),
+                command: None,
+                protected: true,
+                ..MigrationTestHelper::default_target()
+            },
+        ));
+    let cleanup = helper
+        .create_migration_manual(
+            "cleanup",
+            "DELETE FROM sessions WHERE expires < now();\nDROP TABLE old_users;\n".to_string(),
+        )
+        .await?;
+    let cfg = helper.load_config().await?;
+    let apply = || ApplyMigration {
+        migration: Some(cleanup.clone()),
+        force: true,
+        ..apply_all()
+    };
+
+    let err = apply()
+        .execute(&cfg)
+        .await
+        .err()
+        .expect("destructive migration was applied to a protected target")
+        .to_string();
+    assert!(err.contains("DROP TABLE: DROP TABLE old_users;"), "{}", err);
+    assert!(err.contains("--allow-destructive"), "{}", err);
+    let database = MemoryDatabase::named("test_destructive_statements");
+    assert!(!database.executed().join("").contains("old_users"));
+    assert!(matches!(Check.execute(&cfg).await?, Outcome::CheckFailed));
+
+    // Acknowledging the statements in migration.toml lets it through.
+    cfg.operator()
+        .write(
+            &cfg.pather().migration_metadata_file_path(&cleanup),
+            "destructive = true\n",
+        )
+        .await?;
+    assert!(matches!(
+        apply().execute(&cfg).await?,
+        Outcome::AppliedMigrations
+    ));
+    assert!(database
+        .executed()
+        .join("")
+        .contains("DROP TABLE old_users"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_policy_rules() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_policy_rules").with_policy(PolicyConfig {
+            rules: vec![
+                PolicyRule {
+                    name: "concurrent-index".to_string(),
+                    pattern: "^CREATE (UNIQUE )?INDEX".to_string(),
+                    unless: Some("CONCURRENTLY".to_string()),
+                    message: Some("create indexes CONCURRENTLY".to_string()),
+                    severity: RuleSeverity::Block,
+                    environments: vec!["dev".to_string()],
+                },
+                PolicyRule {
+                    name: "no-serial".to_string(),
+                    pattern: r"\bserial\b".to_string(),
+                    ..Default::default()
+                },
+                PolicyRule {
+                    name: "prod-only".to_string(),
+                    pattern: "CREATE TABLE".to_string(),
+                    severity: RuleSeverity::Block,
+                    environments: vec!["prod".to_string()],
+                    ..Default::default()
+                },
+            ],
+        }),
+    );
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id serial);\n".to_string())
+        .await?;
+    let index = helper
+        .create_migration_manual(
+            "users-index",
+            "CREATE INDEX users_id_idx ON users (id);\n".to_string(),
+        )
+        .await?;
+    let warnings = RecordedWarnings::default();
+    let mut cfg = helper.load_config().await?;
+    cfg.events = Events::new(warnings.clone());
+
+    // A rule that warns does not stop the migration, and rules for other
+    // environments do not apply.
+    ApplyMigration {
+        migration: Some(users.clone()),
+        ..apply_all()
+    }
+    .execute(&cfg)
+    .await?;
+    assert_eq!(
+        vec![format!(
+            "migration {} breaks policy rule no-serial (CREATE TABLE users (id serial);)",
+            users
+        )],
+        *warnings.0.lock().unwrap()
+    );
+
+    let err = ApplyMigration {
+        migration: Some(index.clone()),
+        ..apply_all()
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("migration breaking a blocking rule was applied")
+    .to_string();
+    assert!(
+        err.contains("concurrent-index (CREATE INDEX users_id_idx ON users (id);): create indexes CONCURRENTLY"),
+        "{}",
+        err
+    );
+    let database = MemoryDatabase::named("test_policy_rules");
+    assert!(!database.executed().join("").contains("users_id_idx"));
+
+    assert!(BuildMigration {
+        migration: index.clone(),
+        pinned: false,
+        variables: None,
+        sign: false,
+    }
+    .execute(&cfg)
+    .await
+    .is_err());
+    assert!(matches!(Check.execute(&cfg).await?, Outcome::CheckFailed));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_explain_needs_unprotected_scratch() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_explain").with_target(
+            "prod",
+            TargetConfig {
+                engine: EngineType::Memory,
+                command: None,
+                protected: true,
+                ..MigrationTestHelper::default_target()
+            },
+        ),
+    );
+    let backfill = helper
+        .create_migration_manual(
+            "backfill",
+            "ALTER TABLE users ADD COLUMN email_lower text;\nUPDATE users SET email_lower = lower(email);\n"
+                .to_string(),
+        )
+        .await?;
+    let cfg = helper.load_config().await?;
+    let explain = |analyze: bool, scratch: Option<&str>| ExplainMigration {
+        migration: backfill.clone(),
+        pinned: false,
+        variables: None,
+        analyze,
+        scratch: scratch.map(str::to_string),
+        json: true,
+    };
+
+    assert!(matches!(
+        explain(false, None).execute(&cfg).await?,
+        Outcome::Success
+    ));
+    let err = explain(true, None)
+        .execute(&cfg)
+        .await
+        .err()
+        .expect("ANALYZE ran without a scratch target")
+        .to_string();
+    assert!(err.contains("--scratch"), "{}", err);
+    let err = explain(true, Some("prod"))
+        .execute(&cfg)
+        .await
+        .err()
+        .expect("ANALYZE ran on a protected target")
+        .to_string();
+    assert!(err.contains("protected"), "{}", err);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_history_in_another_database() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper =
+        MigrationTestHelper::new_from_config(MigrationTestHelper::default_config(op).with_target(
+            "postgres_psql",
+            TargetConfig {
+                engine: EngineType::Memory,
+                spawn_database: Some("test_history_target".to_string()),
+                command: None,
+                history: Some(HistoryConfig {
+                    spawn_database: Some("test_history_control".to_string()),
+                    ..Default::default()
+                }),
+                ..MigrationTestHelper::default_target()
+            },
+        ));
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    assert!(matches!(
+        apply_all().execute(&cfg).await?,
+        Outcome::AppliedMigrations
+    ));
+
+    // The migration runs against the target, and is recorded in the
+    // history database.
+    let target = MemoryDatabase::named("test_history_target");
+    let control = MemoryDatabase::named("test_history_control");
+    assert!(target
+        .executed()
+        .join("")
+        .contains("CREATE TABLE users (id int);"));
+    assert!(control.executed().is_empty());
+    assert!(target.get_history(None).await?.is_empty());
+    let recorded: Vec<String> = control
+        .get_history(None)
+        .await?
+        .into_iter()
+        .map(|entry| entry.migration_name)
+        .collect();
+    assert_eq!(vec![users.clone()], recorded);
+
+    let status = get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE)).await?;
+    assert_eq!(Some(MigrationStatus::Success), status[0].last_status);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_history_file() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(op.clone()).with_target(
+            "postgres_psql",
+            TargetConfig {
+                engine: EngineType::Memory,
+                spawn_database: Some("test_history_file".to_string()),
+                command: None,
+                history: Some(HistoryConfig {
+                    file: Some("history/prod.json".to_string()),
+                    ..Default::default()
+                }),
+                ..MigrationTestHelper::default_target()
+            },
+        ),
+    );
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    assert!(matches!(
+        apply_all().execute(&cfg).await?,
+        Outcome::AppliedMigrations
+    ));
+
+    // Recorded in the file rather than the database.
+    assert!(MemoryDatabase::named("test_history_file")
+        .get_history(None)
+        .await?
+        .is_empty());
+    let file: serde_json::Value =
+        serde_json::from_slice(&op.read("/db/history/prod.json").await?.to_vec())?;
+    assert_eq!(1, file["revision"]);
+    assert_eq!(users, file["entries"][0]["migration"]);
+
+    let status = get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE)).await?;
+    assert_eq!(Some(MigrationStatus::Success), status[0].last_status);
+
+    // The file is read back, so the migration is not applied again.
+    apply_all().execute(&cfg).await?;
+    let history = cfg.new_engine().await?.get_migration_history(None).await?;
+    assert_eq!(1, history.len());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_agent_applies_submitted_requests() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op.clone(),
+        "test_agent_applies_submitted_requests",
+    ));
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let orders = helper
+        .create_migration_manual("orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+
+    let key = signing::generate_key();
+    let key_file = tempfile::NamedTempFile::new()?;
+    std::fs::write(key_file.path(), signing::encode_signing_key(&key))?;
+    let mut cfg = helper.load_config().await?;
+    cfg.signing.trusted_keys = vec![signing::encode_public_key(&key.verifying_key())];
+    cfg.signing.key_file = Some(key_file.path().to_string_lossy().to_string());
+
+    let submit = |migration: &str| SubmitMigration {
+        migration: migration.to_string(),
+        pinned: false,
+        variables: None,
+        stdout: false,
+    };
+    submit(&users).execute(&cfg).await?;
+    let queue = AgentQueue::new(&cfg);
+    assert_eq!(1, queue.pending().await?.len());
+
+    let agent = Agent {
+        once: true,
+        interval: 0,
+        stdin: false,
+    };
+    agent.execute(&cfg).await?;
+    let db = MemoryDatabase::named("test_agent_applies_submitted_requests");
+    assert!(db
+        .executed()
+        .iter()
+        .any(|sql| sql.contains("CREATE TABLE users")));
+    assert!(queue.pending().await?.is_empty());
+    let history = cfg.new_engine().await?.get_migration_history(None).await?;
+    assert_eq!(1, history.len());
+
+    let listed = op.list("/db/agent/results/").await?;
+    let id = listed[0].name().trim_end_matches(".json");
+    let result = queue.result(id).await?.expect("no result written");
+    assert_eq!(ApplyResultStatus::Applied, result.status);
+    assert_eq!(Some(users.as_str()), result.migration.as_deref());
+
+    // Nothing is left to do.
+    agent.execute(&cfg).await?;
+    assert_eq!(1, op.list("/db/agent/results/").await?.len());
+
+    // A request whose SQL was changed after signing is rejected.
+    submit(&orders).execute(&cfg).await?;
+    let pending = queue.pending().await?;
+    let mut request = pending[0]
+        .request
+        .as_ref()
+        .map_err(|e| e.to_string())?
+        .clone();
+    request.sql = "DROP TABLE users;\n".to_string();
+    queue.submit(&request).await?;
+    assert!(agent.execute(&cfg).await.is_err());
+    let result = queue.result(&request.id).await?.expect("no result written");
+    assert_eq!(ApplyResultStatus::Rejected, result.status);
+    assert!(!db.executed().iter().any(|sql| sql.contains("DROP TABLE")));
+
+    // As is a request that would apply a migration out of order.
+    let old = ApplyRequest {
+        id: "old".to_string(),
+        ..ApplyRequest::new(
+            &cfg,
+            "20000101000000-old",
+            "CREATE TABLE old (id int);\n".to_string(),
+        )?
+    }
+    .sign(&key);
+    queue.submit(&old).await?;
+    assert!(agent.execute(&cfg).await.is_err());
+    let result = queue.result("old").await?.expect("no result written");
+    assert_eq!(ApplyResultStatus::Rejected, result.status);
+    let error = result.error.unwrap();
+    assert!(error.contains("out of order"), "{}", error);
+    assert!(!db.executed().iter().any(|sql| sql.contains("TABLE old")));
+
+    // Requests for another target are left for that target's agent.
+    let other = ApplyRequest {
+        id: "other".to_string(),
+        target: "other".to_string(),
+        ..request
+    }
+    .sign(&key);
+    queue.submit(&other).await?;
+    agent.execute(&cfg).await?;
+    assert_eq!(1, queue.pending().await?.len());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_serve_api() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_serve_api",
+    ));
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let spawn = Spawn::new(helper.load_config().await?);
+
+    let status = handle(&spawn, None, &Request::new("GET", "/status", "")).await;
+    assert_eq!(200, status.status);
+    assert_eq!(users, status.body[0]["migration_name"]);
+    assert!(status.body[0]["last_status"].is_null());
+
+    // Building renders secrets, so needs a token like applying does.
+    let build = Request::new(
+        "POST",
+        "/build",
+        &format!(r#"{{"migration": "{}"}}"#, users),
+    );
+    assert_eq!(403, handle(&spawn, None, &build).await.status);
+    let build = handle(
+        &spawn,
+        Some("secret"),
+        &build.with_header("Authorization", "Bearer secret"),
+    )
+    .await;
+    assert_eq!(200, build.status);
+    assert_eq!("CREATE TABLE users (id int);", build.body["sql"]);
+
+    // Applying needs the server to have a token, and the client to send it.
+    let apply = Request::new("POST", "/apply", r#"{"pinned": false}"#);
+    assert_eq!(403, handle(&spawn, None, &apply).await.status);
+    assert_eq!(401, handle(&spawn, Some("secret"), &apply).await.status);
+    let wrong = apply.clone().with_header("Authorization", "Bearer wrong");
+    assert_eq!(401, handle(&spawn, Some("secret"), &wrong).await.status);
+
+    let applied = handle(
+        &spawn,
+        Some("secret"),
+        &apply.with_header("Authorization", "Bearer secret"),
+    )
+    .await;
+    assert_eq!(200, applied.status, "{}", applied.body);
+    assert_eq!(users, applied.body["migrations"][0]["migration"]);
+    assert_eq!("applied", applied.body["migrations"][0]["result"]);
+
+    let authorized = |request: Request| request.with_header("Authorization", "Bearer secret");
+    let status = handle(
+        &spawn,
+        Some("secret"),
+        &authorized(Request::new("GET", "/status", "")),
+    )
+    .await;
+    assert_eq!("SUCCESS", status.body[0]["last_status"]);
+    let history = handle(
+        &spawn,
+        Some("secret"),
+        &authorized(Request::new("GET", "/history", "")),
+    )
+    .await;
+    assert_eq!(users, history.body[0]["migration"]);
+
+    assert_eq!(
+        400,
+        handle(
+            &spawn,
+            Some("secret"),
+            &authorized(Request::new("POST", "/build", "{not json")),
+        )
+        .await
+        .status
+    );
+    assert_eq!(
+        405,
+        handle(
+            &spawn,
+            Some("secret"),
+            &authorized(Request::new("GET", "/apply", "")),
+        )
+        .await
+        .status
+    );
+    assert_eq!(
+        404,
+        handle(&spawn, None, &Request::new("GET", "/nope", ""))
+            .await
+            .status
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_serve_needs_token_off_loopback() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_serve_needs_token_off_loopback",
+    ));
+    let spawn = std::sync::Arc::new(Spawn::new(helper.load_config().await?));
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:0").await?;
+    let error = serve(spawn, listener, None).await.unwrap_err();
+    assert!(
+        error.to_string().contains("SPAWN_SERVE_TOKEN must be set"),
+        "{}",
+        error
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_serve_enforces_allowed_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_serve_enforces_allowed_commands")
+            .with_environment_config(
+                "dev",
+                EnvironmentConfig {
+                    allowed_commands: Some(vec!["status".to_string(), "serve".to_string()]),
+                    ..Default::default()
+                },
+            ),
+    );
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let spawn = Spawn::new(helper.load_config().await?);
+    let authorized = |request: Request| request.with_header("Authorization", "Bearer secret");
+
+    let status = authorized(Request::new("GET", "/status", ""));
+    assert_eq!(200, handle(&spawn, Some("secret"), &status).await.status);
+
+    for (path, body) in [
+        ("/apply", r#"{"pinned": false}"#.to_string()),
+        ("/test", "{}".to_string()),
+        ("/build", format!(r#"{{"migration": "{}"}}"#, users)),
+    ] {
+        let response = handle(
+            &spawn,
+            Some("secret"),
+            &authorized(Request::new("POST", path, &body)),
+        )
+        .await;
+        assert_eq!(403, response.status, "{}", path);
+        assert!(
+            response.body["error"]
+                .as_str()
+                .unwrap()
+                .contains("not allowed in the dev environment"),
+            "{}",
+            response.body
+        );
+    }
+
+    // The library is held to the same allowlist.
+    let err = spawn
+        .apply(None, ApplyOptions::default())
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("`spawn migration apply`"),
+        "{}",
+        err
+    );
+    assert!(spawn.status().await?[0].last_status.is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_schema_docs() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op.clone(),
+        "test_schema_docs",
+    ));
+    helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+    let values = |values: &[Option<&str>]| {
+        values
+            .iter()
+            .map(|v| v.map(str::to_string))
+            .collect::<Vec<_>>()
+    };
+    let db = MemoryDatabase::named("test_schema_docs");
+    db.answer(
+        "FROM pg_attribute",
+        Rows {
+            columns: strings(&[
+                "table_schema",
+                "table_name",
+                "table_comment",
+                "column_name",
+                "data_type",
+                "not_null",
+                "column_default",
+                "column_comment",
+            ]),
+            rows: vec![
+                values(&[
+                    Some("public"),
+                    Some("orders"),
+                    None,
+                    Some("id"),
+                    Some("integer"),
+                    Some("t"),
+                    None,
+                    None,
+                ]),
+                values(&[
+                    Some("public"),
+                    Some("orders"),
+                    None,
+                    Some("user_id"),
+                    Some("integer"),
+                    Some("f"),
+                    None,
+                    Some("Who ordered"),
+                ]),
+                values(&[
+                    Some("public"),
+                    Some("users"),
+                    None,
+                    Some("id"),
+                    Some("integer"),
+                    Some("t"),
+                    None,
+                    None,
+                ]),
+            ],
+        },
+    );
+    db.answer(
+        "FROM pg_constraint",
+        Rows {
+            columns: strings(&[
+                "table_schema",
+                "table_name",
+                "constraint_name",
+                "constraint_type",
+                "columns",
+                "references_schema",
+                "references_table",
+                "references_columns",
+            ]),
+            rows: vec![
+                values(&[
+                    Some("public"),
+                    Some("orders"),
+                    Some("orders_pkey"),
+                    Some("p"),
+                    Some("[\"id\"]"),
+                    None,
+                    None,
+                    None,
+                ]),
+                values(&[
+                    Some("public"),
+                    Some("orders"),
+                    Some("orders_user_id_fkey"),
+                    Some("f"),
+                    Some("[\"user_id\"]"),
+                    Some("public"),
+                    Some("users"),
+                    Some("[\"id\"]"),
+                ]),
+            ],
+        },
+    );
+
+    let mut cfg = helper.load_config().await?;
+    SchemaDocs {
+        output: Some("docs/schema.md".to_string()),
+        schemas: vec!["public".to_string(), "auth".to_string()],
+        stdout: false,
+    }
+    .execute(&cfg)
+    .await?;
+    let docs = String::from_utf8(op.read("/db/docs/schema.md").await?.to_vec())?;
+    assert!(
+        docs.contains("    users ||--o{ orders : \"user_id\"\n"),
+        "{}",
+        docs
+    );
+    assert!(docs.contains("| user_id | `integer` | yes |  | Who ordered |\n"));
+    assert!(docs.contains("Primary key: id\n"));
+
+    // With after_apply, applying a migration writes the docs.
+    cfg.schema_docs.after_apply = true;
+    assert!(op.read("/db/schema.md").await.is_err());
+    apply_all().execute(&cfg).await?;
+    let docs = String::from_utf8(op.read("/db/schema.md").await?.to_vec())?;
+    assert!(docs.contains("## public.orders"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_scrub() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op.clone(),
+        "test_scrub",
+    ));
+    op.write(
+        "/db/scrub.toml",
+        "[[table]]\nname = \"users\"\ncolumns = { email = \"faker.email\" }\n",
+    )
+    .await?;
+    let scrub = Scrub {
+        rules: None,
+        print: false,
+        yes: true,
+    };
+
+    // Only the targets listed in [scrub] targets are scrubbed.
+    let mut cfg = helper.load_config().await?;
+    let err = scrub
+        .execute(&cfg)
+        .await
+        .err()
+        .expect("target was scrubbed");
+    assert!(
+        err.to_string().contains("is not in [scrub] targets"),
+        "{}",
+        err
+    );
+    let db = MemoryDatabase::named("test_scrub");
+    assert!(db.executed().is_empty());
+
+    cfg.scrub.targets = vec![cfg.target.clone().unwrap()];
+    scrub.execute(&cfg).await?;
+    let executed = db.executed();
+    assert_eq!(1, executed.len());
+    assert!(executed[0].contains("UPDATE \"public\".\"users\" SET\n    \"email\" = 'user-'"));
+
+    let err = Scrub {
+        rules: Some("missing.toml".to_string()),
+        ..scrub
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("missing rules were read");
+    assert!(format!("{:#}", err).contains("could not read scrub rules /db/missing.toml"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_locks() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_migration_locks",
+    ));
+    let mut cfg = helper.load_config().await?;
+    let steal = MigrationLocks {
+        steal: true,
+        yes: true,
+    };
+    // Nothing to steal when nobody holds the lock.
+    steal.execute(&cfg).await?;
+
+    let rows = |columns: &[&str], values: &[&str]| Rows {
+        columns: columns.iter().map(|c| c.to_string()).collect(),
+        rows: vec![values.iter().map(|v| Some(v.to_string())).collect()],
+    };
+    let held = |database: &str, heartbeat_age: &str| {
+        let db = MemoryDatabase::named(database);
+        db.answer(
+            "FROM pg_locks",
+            rows(
+                &["backend_pid", "client", "backend_start"],
+                &["4242", "10.0.0.7", "2026-10-15 09:00:00+00"],
+            ),
+        );
+        db.answer("AS recorded", rows(&["recorded"], &["t"]));
+        db.answer(
+            "heartbeat_age",
+            rows(
+                &[
+                    "backend_pid",
+                    "hostname",
+                    "pid",
+                    "migration_name",
+                    "started_at",
+                    "heartbeat_age",
+                ],
+                &[
+                    "4242",
+                    "ci-runner-3",
+                    "811",
+                    "20261015-add-orders",
+                    "2026-10-15 09:00:01+00",
+                    heartbeat_age,
+                ],
+            ),
+        );
+        db.answer("pg_terminate_backend", rows(&["terminated"], &["t"]));
+    };
+
+    // A holder with a recent heartbeat is still running.
+    held("test_migration_locks", "5");
+    let err = steal.execute(&cfg).await.err().expect("lock was stolen");
+    assert!(err.to_string().contains("still running"), "{}", err);
+    MigrationLocks {
+        steal: false,
+        yes: false,
+    }
+    .execute(&cfg)
+    .await?;
+
+    // One that stopped sending them has crashed.
+    held("test_migration_locks_stale", "600");
+    let target = cfg.target.clone().unwrap();
+    cfg.targets.get_mut(&target).unwrap().spawn_database =
+        Some("test_migration_locks_stale".to_string());
+    steal.execute(&cfg).await?;
+
+    Ok(())
+}
+
+fn apply_all() -> ApplyMigration {
+    ApplyMigration {
+        migration: None,
+        pinned: false,
+        variables: None,
+        yes: true,
+        force: false,
+        retry: false,
+        reuse_connection: false,
+        require_signature: false,
+        from_file: None,
+        interactive: false,
+        promote_from: None,
+        allow_destructive: false,
+        schedule: Default::default(),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_memory_engine_apply_and_status() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_memory_engine_apply_and_status",
+    ));
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let orders = helper
+        .create_migration_manual("orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    assert!(matches!(
+        apply_all().execute(&cfg).await?,
+        Outcome::AppliedMigrations
+    ));
+
+    let database = MemoryDatabase::named("test_memory_engine_apply_and_status");
+    let executed = database.executed().join("");
+    assert!(executed.contains("CREATE TABLE users (id int);"));
+    assert!(executed.contains("CREATE TABLE orders (id int);"));
+
+    let status = get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE)).await?;
+    let applied: Vec<(&str, Option<MigrationStatus>)> = status
+        .iter()
+        .map(|row| (row.migration_name.as_str(), row.last_status))
+        .collect();
+    let mut expected = vec![
+        (users.as_str(), Some(MigrationStatus::Success)),
+        (orders.as_str(), Some(MigrationStatus::Success)),
+    ];
+    expected.sort_by_key(|(name, _)| *name);
+    assert_eq!(expected, applied);
+
+    // The recorded history stops it being applied again.
+    let report = Spawn::new(cfg)
+        .apply(
+            Some(&users),
+            ApplyOptions {
+                pinned: false,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert_eq!(
+        vec![(users.clone(), StepResult::AlreadyApplied)],
+        report.migrations
+    );
+    assert_eq!(2, database.executed().len());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_memory_engine_failed_migration_can_be_adopted(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let database_name = "test_memory_engine_failed_migration_can_be_adopted";
+    let helper =
+        MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(op, database_name));
+    let migration = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    let database = MemoryDatabase::named(database_name);
+    database.fail_on("CREATE TABLE users");
+    assert!(apply_all().execute(&cfg).await.is_err());
+
+    let history = Spawn::new(helper.load_config().await?).history().await?;
+    assert_eq!(1, history.len());
+    assert_eq!(Some("FAILURE"), history[0].status.as_deref());
+    assert_eq!(
+        Some(migration_checksum(b"CREATE TABLE users (id int);")),
+        history[0].checksum
+    );
+
+    // Without --retry, the failed attempt blocks applying it again.
+    let err = ApplyMigration {
+        migration: Some(migration.clone()),
+        ..apply_all()
+    }
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("failed migration should be refused");
+    assert!(err.to_string().contains("FAILURE"), "{}", err);
+
+    AdoptMigration {
+        migration: Some(migration.clone()),
+        yes: true,
+        force: false,
+        description: Some("fixed by hand".to_string()),
+    }
+    .execute(&cfg)
+    .await?;
+
+    let history = Spawn::new(helper.load_config().await?).history().await?;
+    let last = history.last().unwrap();
+    assert_eq!(
+        ("ADOPT", Some("SUCCESS")),
+        (last.activity.as_str(), last.status.as_deref())
+    );
+    assert_eq!(Some("fixed by hand"), last.description.as_deref());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_deterministic_mode_refuses_varying_functions(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(op).with_template(TemplateConfig {
+            time_functions: true,
+            deterministic: true,
+            ..Default::default()
+        }),
+    );
+
+    for (name, call) in [
+        ("uuid", "gen_uuid_v4()"),
+        ("uuid-v7", "gen_uuid_v7()"),
+        ("now", "now()"),
+    ] {
+        let migration = helper
+            .create_migration_manual(name, format!("SELECT {{{{ {} }}}};\n", call))
+            .await?;
+        let err = helper.build_migration(&migration, false).await.unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("deterministic mode"), "{}", message);
+    }
+
+    // Seeded functions and variables render the same every time.
+    let migration = helper
+        .create_migration_manual(
+            "seeded",
+            "SELECT {{ gen_uuid_v5('users') }}, {{ variables.id }};\n".to_string(),
+        )
+        .await?;
+    helper.fs.write("/vars.json", r#"{"id": 7}"#).await?;
+    let first = helper
+        .build_migration_with_variables(&migration, false, Some("/vars.json".to_string()))
+        .await?;
+    let second = helper
+        .build_migration_with_variables(&migration, false, Some("/vars.json".to_string()))
+        .await?;
+    assert_eq!(first, second);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_variables_files() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::default_config(op));
+    let migration = helper
+        .create_migration_manual(
+            "seed",
+            "SELECT {{ variables.schema }}, {{ variables.db.host }}, {{ variables.db.port }};\n"
+                .to_string(),
+        )
+        .await?;
+    let folder = helper
+        .load_config()
+        .await?
+        .pather()
+        .migration_folder(&migration);
+
+    helper
+        .fs
+        .write(
+            &format!("{}/variables.toml", folder),
+            "schema = \"app\"\n[db]\nhost = \"db.example.com\"\nport = 5432\n",
+        )
+        .await?;
+    helper
+        .fs
+        .write(
+            &format!("{}/variables.prod.toml", folder),
+            "[db]\nport = 6000\n",
+        )
+        .await?;
+    assert_eq!(
+        "SELECT 'app', 'db.example.com', 5432;",
+        helper.build_migration(&migration, false).await?
+    );
+
+    // The file for the current environment overrides the shared one, and
+    // --variables overrides both.
+    helper
+        .fs
+        .write(
+            &format!("{}/variables.dev.toml", folder),
+            "[db]\nhost = \"localhost\"\nport = 5433\n",
+        )
+        .await?;
+    assert_eq!(
+        "SELECT 'app', 'localhost', 5433;",
+        helper.build_migration(&migration, false).await?
+    );
+
+    helper
+        .fs
+        .write("/vars.json", r#"{"db": {"port": 7000}}"#)
+        .await?;
+    assert_eq!(
+        "SELECT 'app', 'localhost', 7000;",
+        helper
+            .build_migration_with_variables(&migration, false, Some("/vars.json".to_string()))
+            .await?
+    );
+
+    // Pinning keeps the values the migration was pinned with.
+    helper.pin_migration(&migration).await?;
+    helper
+        .fs
+        .write(
+            &format!("{}/variables.dev.toml", folder),
+            "[db]\nport = 1\n",
+        )
+        .await?;
+    assert_eq!(
+        "SELECT 'app', 'localhost', 5433;",
+        helper.build_migration(&migration, true).await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_variables_precedence_and_vars_show() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let defaults: toml::Table = toml::from_str(
+        "source = \"defaults\"\nschema = \"app\"\ndb_password = \"hunter2\"\n[db]\nhost = \"db\"\n",
+    )?;
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(op)
+            .with_variables(defaults)
+            .with_environment_config(
+                "dev",
+                EnvironmentConfig {
+                    variables_file: Some("/dev-vars.yaml".to_string()),
+                    ..Default::default()
+                },
+            ),
+    );
+    helper
+        .fs
+        .write("/dev-vars.yaml", "source: environment\ndb:\n  port: 5433\n")
+        .await?;
+    let migration = helper
+        .create_migration_manual(
+            "seed",
+            "SELECT {{ variables.source }}, {{ variables.db.host }}, {{ variables.db.port }};"
+                .to_string(),
+        )
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    assert_eq!(
+        "SELECT 'environment', 'db', 5433;",
+        helper.build_migration(&migration, false).await?
+    );
+
+    let folder = cfg.pather().migration_folder(&migration);
+    helper
+        .fs
+        .write(
+            &format!("{}/variables.toml", folder),
+            "source = \"migration\"\n[db]\nhost = \"localhost\"\n",
+        )
+        .await?;
+    assert_eq!(
+        "SELECT 'migration', 'localhost', 5433;",
+        helper.build_migration(&migration, false).await?
+    );
+
+    helper
+        .fs
+        .write("/vars.json", r#"{"source": "cli"}"#)
+        .await?;
+    assert_eq!(
+        "SELECT 'cli', 'localhost', 5433;",
+        helper
+            .build_migration_with_variables(&migration, false, Some("/vars.json".to_string()))
+            .await?
+    );
+
+    // `vars show` prints the merged context with secrets masked.
+    let context = migration_context(&cfg, &migration, false, None).await?;
+    assert_eq!(
+        serde_json::json!({
+            "source": "migration",
+            "schema": "app",
+            "db_password": "********",
+            "db": {"host": "localhost", "port": 5433},
+        }),
+        context["variables"]
+    );
+    assert_eq!("dev", context["env"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_config_validation() -> Result<()> {
+    let fs = Operator::new(Memory::default())?.finish();
+    let config_path = "./spawn.toml";
+
+    let contents =
+        "spawn_folder = \"/db\"\ntarget = \"local\"\n\n[targets.local]\nengine = \"mysql\"\n";
+    fs.write(config_path, contents).await?;
+    let err = Config::load(config_path, &fs, None)
+        .await
+        .expect_err("an unknown engine should not load");
+    assert!(
+        format!("{:#}", err).contains(
+            "./spawn.toml:5: targets.local.engine: unknown engine \"mysql\", expected one of postgres-psql, memory"
+        ),
+        "{:#}",
+        err
+    );
+
+    // Unknown keys load, but are reported.
+    let contents = "spawn_folder = \"/db\"\ntarget = \"local\"\ntelemetry = false\n\n\
+                    [targets.local]\nengine = \"memory\"\nspawn_databse = \"spawn\"\n";
+    fs.write(config_path, contents).await?;
+    let config = Config::load(config_path, &fs, None).await?;
+    let diagnostics: Vec<String> = config.validate().iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        vec!["./spawn.toml:7: targets.local.spawn_databse: unknown key, did you mean `spawn_database`?"],
+        diagnostics
+    );
+    assert!(matches!(
+        Check.execute(&config).await?,
+        Outcome::CheckFailed
+    ));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_workspace_projects() -> Result<(), Box<dyn std::error::Error>> {
+    let fs = Operator::new(Memory::default())?.finish();
+    fs.write(
+        "spawn.workspace.toml",
+        "[projects.api]\npath = \"services/api\"\n\n\
+         [projects.billing]\npath = \"services/billing\"\nconfig_file = \"db.toml\"\n",
+    )
+    .await?;
+    for (config_file, database) in [
+        ("services/api/spawn.toml", "test_workspace_api"),
+        ("services/billing/db.toml", "test_workspace_billing"),
+    ] {
+        fs.write(
+            config_file,
+            format!(
+                "spawn_folder = \"spawn\"\ntarget = \"local\"\ntelemetry = false\n\n\
+                 [targets.local]\nengine = \"memory\"\nspawn_database = \"{}\"\n",
+                database
+            ),
+        )
+        .await?;
+    }
+    for (folder, migration) in [
+        ("services/api", "20240101000000-users"),
+        ("services/api", "20240102000000-orders"),
+        ("services/billing", "20240101000000-invoices"),
+    ] {
+        fs.write(
+            &format!("{}/spawn/migrations/{}/up.sql", folder, migration),
+            "SELECT 1;\n",
+        )
+        .await?;
+    }
+
+    let workspace = Workspace::load("spawn.workspace.toml", &fs).await?;
+    let api = workspace.load_config("api", &fs, None).await?;
+    assert_eq!("services/api/spawn", api.pather().spawn_folder_path());
+    ApplyMigration {
+        migration: Some("20240101000000-users".to_string()),
+        ..apply_all()
+    }
+    .execute(&api)
+    .await?;
+
+    let statuses = workspace_status(&workspace, &fs, None).await;
+    let counts: Vec<(&str, usize, usize, Option<&str>)> = statuses
+        .iter()
+        .map(|s| (s.project.as_str(), s.applied, s.pending, s.error.as_deref()))
+        .collect();
+    assert_eq!(vec![("api", 1, 1, None), ("billing", 0, 1, None)], counts);
+
+    // A project that does not load is reported without hiding the others.
+    fs.delete("services/billing/db.toml").await?;
+    let statuses = workspace_status(&workspace, &fs, None).await;
+    assert!(statuses[0].error.is_none());
+    let error = statuses[1].error.as_deref().unwrap_or_default();
+    assert!(
+        error.contains("could not load config from services/billing/db.toml"),
+        "{}",
+        error
+    );
+
+    let err = workspace
+        .load_config("web", &fs, None)
+        .await
+        .expect_err("an unknown project should not load");
+    assert!(err.to_string().contains("expected one of: api, billing"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_config_found_in_parent_folder() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(
+        dir.path().join("spawn.toml"),
+        "spawn_folder = \"db\"\ntarget = \"local\"\ntelemetry = false\n\n\
+         [targets.local]\nengine = \"memory\"\n\n\
+         [template.secrets]\nkind = \"file\"\ndirectory = \"secrets\"\n",
+    )?;
+    let migration = dir.path().join("db/migrations/20240101000000-users");
+    std::fs::create_dir_all(&migration)?;
+    std::fs::write(migration.join("up.sql"), "CREATE TABLE users (id int);\n")?;
+    let subfolder = dir.path().join("app/src");
+    std::fs::create_dir_all(&subfolder)?;
+
+    let op = Operator::new(Fs::default().root(subfolder.to_str().unwrap()))?.finish();
+    assert_eq!(
+        Some("../../spawn.toml".to_string()),
+        Config::find("spawn.toml", &op).await?
+    );
+    assert_eq!(None, Config::find("config/spawn.toml", &op).await?);
+
+    // spawn_folder is relative to the config file, not where spawn runs.
+    let config = Config::load("spawn.toml", &op, None).await?;
+    assert_eq!("../../db", config.pather().spawn_folder_path());
+    assert!(matches!(
+        &config.template.secrets,
+        Some(SecretSource::File { directory }) if directory == "../../secrets"
+    ));
+    let sql = Spawn::new(config)
+        .build("20240101000000-users", false, None)
+        .await?;
+    assert!(sql.contains("CREATE TABLE users (id int);"), "{}", sql);
+
+    let op = Operator::new(Memory::default())?.finish();
+    op.write(
+        "config/spawn.toml",
+        "spawn_folder = \"db\"\ntarget = \"local\"\n\n[targets.local]\nengine = \"memory\"\n",
+    )
+    .await?;
+    let config = Config::load("config/spawn.toml", &op, None).await?;
+    assert_eq!("config/db", config.pather().spawn_folder_path());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_variables_refer_to_secrets() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_variables_refer_to_secrets",
+    ));
+    let migration = helper
+        .create_migration_manual(
+            "role",
+            "ALTER ROLE app PASSWORD {{ variables.app.password }};\n".to_string(),
+        )
+        .await?;
+    std::env::set_var("SPAWN_TEST_VARIABLE_SECRET", "pa55");
+    let variables = Variables::from_str(
+        "toml",
+        "[app]\npassword = { secret = \"SPAWN_TEST_VARIABLE_SECRET\" }\n",
+    )?;
+
+    let sql = Spawn::new(helper.load_config().await?)
+        .build(&migration, false, Some(variables))
+        .await?;
+    assert!(sql.contains("ALTER ROLE app PASSWORD 'pa55';"), "{}", sql);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_apply_from_requires_promotion() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let builder = MigrationTestHelper::memory_config(op, "test_promotion_prod").with_target(
+        "staging",
+        TargetConfig {
+            engine: EngineType::Memory,
+            spawn_database: Some("test_promotion_staging".to_string()),
+            command: None,
+            environment: "staging".to_string(),
+            ..MigrationTestHelper::default_target()
+        },
+    );
+    let helper = MigrationTestHelper::new_from_config(builder);
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let orders = helper
+        .create_migration_manual("orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    let prod = helper.load_config().await?;
+    let staging = helper
+        .config
+        .clone()
+        .with_selected_target("staging")
+        .build()?;
+
+    assert_eq!("postgres_psql", prod.resolve_target("dev")?);
+    assert!(prod.resolve_target("qa").is_err());
+
+    ApplyMigration {
+        migration: Some(orders.clone()),
+        ..apply_all()
+    }
+    .execute(&staging)
+    .await?;
+
+    let promote = ApplyMigration {
+        promote_from: Some("staging".to_string()),
+        allow_destructive: false,
+        schedule: Default::default(),
+        ..apply_all()
+    };
+    let err = promote
+        .run(&prod)
+        .await
+        .expect_err("users has not been applied to staging");
+    let message = err.to_string();
+    assert!(message.contains(&users), "{}", message);
+    assert!(!message.contains(&orders), "{}", message);
+    assert!(MemoryDatabase::named("test_promotion_prod")
+        .executed()
+        .is_empty());
+
+    apply_all().execute(&staging).await?;
+    promote.execute(&prod).await?;
+    let executed = MemoryDatabase::named("test_promotion_prod")
+        .executed()
+        .join("");
+    assert!(executed.contains("CREATE TABLE users (id int);"));
+    assert!(executed.contains("CREATE TABLE orders (id int);"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_diff_envs() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let builder = MigrationTestHelper::memory_config(op, "test_diff_envs_prod").with_target(
+        "staging",
+        TargetConfig {
+            engine: EngineType::Memory,
+            spawn_database: Some("test_diff_envs_staging".to_string()),
+            command: None,
+            environment: "staging".to_string(),
+            ..MigrationTestHelper::default_target()
+        },
+    );
+    let helper = MigrationTestHelper::new_from_config(builder);
+    let accounts = helper
+        .create_migration_manual("accounts", "CREATE TABLE accounts (id int);\n".to_string())
+        .await?;
+    let orders = helper
+        .create_migration_manual("orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let prod = helper.load_config().await?;
+    let staging = prod.for_target("staging")?;
+    let apply = |migration: &str| ApplyMigration {
+        migration: Some(migration.to_string()),
+        ..apply_all()
+    };
+
+    apply(&accounts).execute(&prod).await?;
+    helper
+        .fs
+        .write(
+            &prod.pather().migration_script_file_path(&accounts),
+            "CREATE TABLE accounts (id bigint);\n",
+        )
+        .await?;
+    apply_all().execute(&staging).await?;
+    apply(&users).execute(&prod).await?;
+
+    let diff = diff_envs(&prod, "staging", "postgres_psql").await?;
+    let differences: Vec<(&str, EnvDifference)> = diff
+        .migrations
+        .iter()
+        .map(|m| (m.migration.as_str(), m.difference))
+        .collect();
+    assert_eq!(
+        vec![
+            (accounts.as_str(), EnvDifference::Drifted),
+            (orders.as_str(), EnvDifference::MissingInB),
+        ],
+        differences
+    );
+    assert!(diff.migrations[1].a_checksum.is_some());
+    assert_eq!(None, diff.migrations[1].b_checksum);
+
+    // Environments name their only target.
+    let diff = diff_envs(&prod, "dev", "staging").await?;
+    assert_eq!(EnvDifference::MissingInA, diff.migrations[1].difference);
+    assert!(diff_envs(&staging, "staging", "staging")
+        .await?
+        .migrations
+        .is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_component_coverage() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    for (path, contents) in [
+        ("/db/components/users.sql", "CREATE TABLE users (id int);"),
+        ("/db/components/orders.sql", "CREATE TABLE orders (id int);"),
+        (
+            "/db/components/views/active.sql",
+            "CREATE VIEW active AS SELECT 1;",
+        ),
+        ("/db/tests/users/test.sql", "{% include \"users.sql\" %}"),
+        ("/db/tests/broken/test.sql", "{% include \"missing.sql\" %}"),
+    ] {
+        helper.fs.write(path, contents).await?;
+    }
+    helper
+        .create_migration_manual(
+            "schema",
+            "{% include \"users.sql\" %}\n{% include \"views/active.sql\" %}\n".to_string(),
+        )
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    assert_eq!(vec!["broken", "users"], list_tests(&cfg).await?);
+
+    let coverage = component_coverage(&cfg).await?;
+    let usage: Vec<(&str, Vec<&str>, usize)> = coverage
+        .components
+        .iter()
+        .map(|c| {
+            (
+                c.component.as_str(),
+                c.tests.iter().map(String::as_str).collect(),
+                c.migrations,
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            ("orders.sql", vec![], 0),
+            ("users.sql", vec!["users"], 1),
+            ("views/active.sql", vec![], 1),
+        ],
+        usage
+    );
+    assert_eq!(vec!["orders.sql", "views/active.sql"], coverage.untested());
+    assert_eq!(
+        vec!["test broken"],
+        coverage.errors.keys().collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_select_tests() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    for path in [
+        "/db/tests/users/test.sql",
+        "/db/tests/reports_daily/test.sql",
+        "/db/tests/reports_weekly/test.sql",
+    ] {
+        helper.fs.write(path, "SELECT 1;").await?;
+    }
+    let cfg = helper.load_config().await?;
+
+    assert_eq!(
+        vec!["users", "reports_daily", "reports_weekly"],
+        select_tests(
+            &cfg,
+            &[
+                "users".to_string(),
+                "reports_*".to_string(),
+                "*_daily".to_string()
+            ]
+        )
+        .await?
+    );
+    assert_eq!(
+        vec!["users"],
+        select_tests(&cfg, &["use?s".to_string()]).await?
+    );
+    let err = select_tests(&cfg, &["orders_*".to_string()])
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("no tests match 'orders_*'"), "{}", err);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_nested_tests() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    for path in [
+        "/db/tests/users/test.sql",
+        "/db/tests/reports/daily/test.sql",
+        "/db/tests/reports/monthly/totals/test.sql",
+        "/db/tests/reports/weekly/test.toml",
+        "/db/tests/reports/weekly/expected",
+        // Not a test: holds neither test.sql nor test.toml.
+        "/db/tests/reports/shared/setup.sql",
+    ] {
+        helper.fs.write(path, "SELECT 1;").await?;
+    }
+    let cfg = helper.load_config().await?;
+
+    assert_eq!(
+        vec![
+            "reports/daily",
+            "reports/monthly/totals",
+            "reports/weekly",
+            "users"
+        ],
+        list_tests(&cfg).await?
+    );
+    assert_eq!(
+        vec!["reports/daily", "reports/weekly"],
+        select_tests(&cfg, &["reports/*".to_string()]).await?
+    );
+    assert_eq!(
+        vec!["reports/monthly/totals"],
+        select_tests(&cfg, &["reports/*/*".to_string()]).await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_render_tests() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    for (path, contents) in [
+        (
+            "/db/components/tables/users.sql",
+            "INSERT INTO users VALUES ({{ variables.name }});",
+        ),
+        (
+            "/db/tests/users/test.toml",
+            "kind = \"render\"\ncomponent = \"tables/users.sql\"\nvariables = \"vars.json\"\n",
+        ),
+        ("/db/tests/users/vars.json", "{\"name\": \"app\"}"),
+        // Needs a database, so is skipped by --render-only.
+        ("/db/tests/queries/test.sql", "SELECT 1;"),
+    ] {
+        helper.fs.write(path, contents).await?;
+    }
+    let cfg = helper.load_config().await?;
+
+    let tester = Tester::new(&cfg, "users");
+    assert_eq!(TestKind::Render, tester.settings().await?.kind);
+    assert_eq!("INSERT INTO users VALUES ('app');", tester.run(None).await?);
+
+    ExpectTest {
+        name: "users".to_string(),
+    }
+    .execute(&cfg)
+    .await?;
+    assert_eq!(
+        "INSERT INTO users VALUES ('app');".as_bytes(),
+        helper.fs.read("/db/tests/users/expected").await?.to_vec()
+    );
+
+    let compare = CompareTests {
+        names: vec![],
+        render_only: true,
+        retries: 0,
+        isolate: false,
+        deny_warnings: false,
+    };
+    assert!(matches!(compare.execute(&cfg).await?, Outcome::Success));
+
+    helper
+        .fs
+        .write(
+            "/db/components/tables/users.sql",
+            "INSERT INTO people VALUES ({{ variables.name }});",
+        )
+        .await?;
+    assert!(matches!(compare.execute(&cfg).await?, Outcome::TestsFailed));
+
+    let coverage = component_coverage(&cfg).await?;
+    assert_eq!(vec!["users"], coverage.components[0].tests);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_record_test() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_record_test",
+    ));
+    let cfg = helper.load_config().await?;
+    let dir = tempfile::tempdir()?;
+    let query = dir.path().join("query.sql");
+    std::fs::write(&query, "SELECT count(*) FROM users;\n")?;
+
+    let record = RecordTest {
+        name: "user_count".to_string(),
+        file: Some(query.display().to_string()),
+        force: false,
+    };
+    let outcome = record.execute(&cfg).await?;
+    assert!(matches!(outcome, Outcome::NewTest(ref name) if name == "user_count"));
+    assert_eq!(
+        "SELECT count(*) FROM users;\n".as_bytes(),
+        helper
+            .fs
+            .read("/db/tests/user_count/test.sql")
+            .await?
+            .to_vec()
+    );
+    assert!(helper.fs.exists("/db/tests/user_count/expected").await?);
+    assert_eq!(
+        vec!["SELECT count(*) FROM users;".to_string()],
+        MemoryDatabase::named("test_record_test").executed()
+    );
+
+    // Recording over an existing test needs --force.
+    let err = record.execute(&cfg).await.err().expect("test exists");
+    assert!(err.to_string().contains("already exists"), "{}", err);
+    RecordTest {
+        force: true,
+        ..record
+    }
+    .execute(&cfg)
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_deny_warnings() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let database_name = "test_deny_warnings";
+    let helper =
+        MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(op, database_name));
+    helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    helper
+        .create_migration_manual("orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    let database = MemoryDatabase::named(database_name);
+    database.print_on(
+        "CREATE TABLE orders",
+        "psql:<stdin>:1: WARNING:  there is no transaction in progress\n",
+    );
+    database.print_on(
+        "SELECT count",
+        "psql:<stdin>:1: NOTICE:  counting users\n count\n-------\n     0\n",
+    );
+    database.print_on(
+        "SELECT max",
+        "psql:<stdin>:1: WARNING:  nonstandard use of escape in a string literal\n max\n-----\n\n",
+    );
+
+    // The migration the server warned about is applied, and the apply stops
+    // after it.
+    let mut cfg = helper.load_config().await?;
+    cfg.deny_warnings = true;
+    let err = apply_all().execute(&cfg).await.err().expect("apply warned");
+    assert!(err.to_string().contains("--deny-warnings"), "{}", err);
+    let history = Spawn::new(helper.load_config().await?).history().await?;
+    assert_eq!(1, history.len());
+    assert_eq!(Some("SUCCESS"), history[0].status.as_deref());
+
+    cfg.deny_warnings = false;
+    apply_all().execute(&cfg).await?;
+    assert_eq!(
+        2,
+        Spawn::new(helper.load_config().await?)
+            .history()
+            .await?
+            .len()
+    );
+
+    // Tests pass with notices, but not with warnings once they are denied.
+    let dir = tempfile::tempdir()?;
+    for (name, query) in [
+        ("counts", "SELECT count(*) FROM users;\n"),
+        ("maxes", "SELECT max(id) FROM users;\n"),
+    ] {
+        let file = dir.path().join(format!("{}.sql", name));
+        std::fs::write(&file, query)?;
+        RecordTest {
+            name: name.to_string(),
+            file: Some(file.display().to_string()),
+            force: false,
+        }
+        .execute(&cfg)
+        .await?;
+    }
+    let outcome = Tester::new(&cfg, "counts").run_compare(None).await?;
+    assert_eq!(1, outcome.messages.len());
+    assert_eq!("NOTICE:  counting users", outcome.messages[0].text);
+
+    let compare = |names: &[&str], deny_warnings| CompareTests {
+        names: names.iter().map(|n| n.to_string()).collect(),
+        render_only: false,
+        retries: 0,
+        isolate: false,
+        deny_warnings,
+    };
+    assert!(matches!(
+        compare(&[], false).execute(&cfg).await?,
+        Outcome::Success
+    ));
+    assert!(matches!(
+        compare(&["counts"], true).execute(&cfg).await?,
+        Outcome::Success
+    ));
+    assert!(matches!(
+        compare(&[], true).execute(&cfg).await?,
+        Outcome::TestsFailed
+    ));
+
+    let run = |deny_warnings| RunTest {
+        names: vec!["maxes".to_string()],
+        isolate: false,
+        deny_warnings,
+    };
+    assert!(matches!(run(false).execute(&cfg).await?, Outcome::Success));
+    assert!(matches!(
+        run(true).execute(&cfg).await?,
+        Outcome::TestsFailed
+    ));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_requires_migrations() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_requires_migrations",
+    ));
+    let users = helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    helper
+        .fs
+        .write(
+            "/db/tests/users/test.toml",
+            format!("requires_migrations = [\"{}\"]\n", users),
+        )
+        .await?;
+    helper
+        .fs
+        .write("/db/tests/users/test.sql", "SELECT * FROM users;")
+        .await?;
+    helper.fs.write("/db/tests/users/expected", "").await?;
+    let cfg = helper.load_config().await?;
+
+    let compare = CompareTests {
+        names: vec![],
+        render_only: false,
+        retries: 0,
+        isolate: false,
+        deny_warnings: false,
+    };
+    let err = compare.execute(&cfg).await.err().expect("users is pending");
+    assert!(
+        err.to_string()
+            .contains(&format!("not applied to the target: {} (pending)", users)),
+        "{}",
+        err
+    );
+    // Nothing was sent to the database for the test.
+    assert!(MemoryDatabase::named("test_requires_migrations")
+        .executed()
+        .is_empty());
+
+    apply_all().execute(&cfg).await?;
+    assert!(matches!(compare.execute(&cfg).await?, Outcome::Success));
+
+    helper
+        .fs
+        .write(
+            "/db/tests/users/test.toml",
+            "requires_migrations = [\"20240101000000-missing\"]\n",
+        )
+        .await?;
+    let err = compare
+        .execute(&cfg)
+        .await
+        .err()
+        .expect("no such migration");
+    assert!(
+        err.to_string()
+            .contains("20240101000000-missing (no such migration)"),
+        "{}",
+        err
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_compare_retries() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_compare_retries",
+    ));
+    helper
+        .fs
+        .write("/db/tests/flaky/test.sql", "SELECT 1;")
+        .await?;
+    // The memory engine prints nothing, so this never matches.
+    helper
+        .fs
+        .write("/db/tests/flaky/expected", "?column?\n")
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    let compare = CompareTests {
+        names: vec!["flaky".to_string()],
+        render_only: false,
+        retries: 2,
+        isolate: false,
+        deny_warnings: false,
+    };
+    assert!(matches!(compare.execute(&cfg).await?, Outcome::TestsFailed));
+    // The first run and both retries.
+    assert_eq!(
+        3,
+        MemoryDatabase::named("test_compare_retries")
+            .executed()
+            .len()
+    );
+
+    helper.fs.write("/db/tests/flaky/expected", "").await?;
+    assert!(matches!(compare.execute(&cfg).await?, Outcome::Success));
+    assert_eq!(
+        4,
+        MemoryDatabase::named("test_compare_retries")
+            .executed()
+            .len()
+    );
+
+    // Isolated tests run in a copy of the database, dropped once they have
+    // run.
+    let isolated = CompareTests {
+        isolate: true,
+        ..compare
+    };
+    MemoryDatabase::named("test_compare_retries").answer(
+        "current_database()",
+        Rows {
+            columns: vec!["database".to_string()],
+            rows: vec![vec![Some("app".to_string())]],
+        },
+    );
+    assert!(matches!(isolated.execute(&cfg).await?, Outcome::Success));
+    let executed = MemoryDatabase::named("test_compare_retries").executed();
+    let copy = format!("spawn_isolated_{}", std::process::id());
+    let test = &executed[executed.len() - 2];
+    assert!(
+        test.contains(&format!("CREATE DATABASE \"{}\" TEMPLATE \"app\";", copy)),
+        "{}",
+        test
+    );
+    assert!(
+        test.trim_end().ends_with(&format!(
+            "\\c \"{}\"\n\\set QUIET :spawn_quiet\nSELECT 1;",
+            copy
+        )),
+        "{}",
+        test
+    );
+    assert_eq!(
+        format!("DROP DATABASE IF EXISTS \"{}\";", copy),
+        executed.last().unwrap().trim()
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tagged_components() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    helper
+        .fs
+        .write("/db/components/tables/users.sql", "CREATE TABLE users;")
+        .await?;
+    let cfg = helper.load_config().await?;
+    let root = tag_components(&cfg, "v1").await?;
+    assert!(tag_components(&cfg, "not/valid").await.is_err());
+
+    helper
+        .fs
+        .write("/db/components/tables/users.sql", "CREATE TABLE people;")
+        .await?;
+    let err = tag_components(&cfg, "v1").await.unwrap_err().to_string();
+    assert!(err.contains("cannot be moved"), "{}", err);
+
+    let migration = helper
+        .create_migration_manual(
+            "tagged",
+            "{% include \"tables/users.sql@v1\" %}\n{% include \"tables/users.sql\" %}\n"
+                .to_string(),
+        )
+        .await?;
+    assert_eq!(
+        "CREATE TABLE users;\nCREATE TABLE people;",
+        helper.build_migration(&migration, false).await?
+    );
+
+    // Pinned to the tag, the untagged include also reads the tagged version.
+    assert_eq!(root, pin_migration_to_tag(&cfg, &migration, "v1").await?);
+    let lock_path = cfg.pather().migration_lock_file_path(&migration);
+    assert_eq!(
+        Some("v1".to_string()),
+        cfg.load_lock_file(&lock_path).await?.tag
+    );
+    assert_eq!(
+        "CREATE TABLE users;\nCREATE TABLE users;",
+        helper.build_migration(&migration, true).await?
+    );
+
+    // A lock file may name only the tag.
+    helper.fs.write(&lock_path, "tag = \"v1\"\n").await?;
+    assert_eq!(root, cfg.load_lock_file(&lock_path).await?.pin);
+    helper.fs.write(&lock_path, "tag = \"v2\"\n").await?;
+    assert!(cfg.load_lock_file(&lock_path).await.is_err());
+    helper.fs.write(&lock_path, "tag = \"v1\"\n").await?;
+
+    let missing = helper
+        .create_migration_manual(
+            "missing_tag",
+            "{% include \"tables/users.sql@v2\" %}\n".to_string(),
+        )
+        .await?;
+    let err = format!(
+        "{:#}",
+        helper.build_migration(&missing, false).await.unwrap_err()
+    );
+    assert!(err.contains("no tag named 'v2'"), "{}", err);
+
+    // The tag is a root of the store, but not an object in it. The snapshot
+    // taken for the rejected attempt to move v1 is left unreferenced.
+    let stats = collect_pin_stats(&cfg).await?;
+    assert_eq!(1, stats.roots);
+    assert_eq!(6, stats.objects);
+    assert_eq!(3, stats.referenced_objects);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_packages() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    op.write("shared/sql/triggers.sql", "CREATE TRIGGER audit;")
+        .await?;
+    op.write("shared/README.md", "Not a component").await?;
+    let audit = PackageConfig {
+        path: Some("shared".to_string()),
+        folder: Some("sql".to_string()),
+        ..Default::default()
+    };
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::default_config(op).with_package("audit", audit.clone()),
+    );
+    let cfg = helper.load_config().await?;
+    let state = |statuses: Vec<PackageStatus>| statuses[0].state;
+
+    assert_eq!(PackageState::Missing, state(package_status(&cfg).await?));
+    assert!(PackageUpdate {
+        packages: vec!["billing".to_string()]
+    }
+    .execute(&cfg)
+    .await
+    .is_err());
+
+    PackageUpdate { packages: vec![] }.execute(&cfg).await?;
+    assert!(helper.fs.exists("/db/vendor/audit/triggers.sql").await?);
+    assert!(!helper.fs.exists("/db/vendor/audit/README.md").await?);
+    let statuses = package_status(&cfg).await?;
+    assert_eq!(PackageState::Ok, statuses[0].state);
+    assert_eq!("path shared", statuses[0].source);
+    let hash = statuses[0].hash.clone().expect("package should be locked");
+
+    let migration = helper
+        .create_migration_manual(
+            "audit",
+            "{% include \"pkg/audit/triggers.sql\" %}\n".to_string(),
+        )
+        .await?;
+    assert_eq!(
+        "CREATE TRIGGER audit;",
+        helper.build_migration(&migration, false).await?
+    );
+
+    // Pinning keeps the package as it was vendored.
+    helper.pin_migration(&migration).await?;
+    helper
+        .fs
+        .write("/db/vendor/audit/triggers.sql", "CREATE TRIGGER changed;")
+        .await?;
+    assert_eq!(
+        "CREATE TRIGGER audit;",
+        helper.build_migration(&migration, true).await?
+    );
+    assert!(matches!(
+        pin_diff(&cfg, &migration).await?.as_slice(),
+        [PinChange::Changed { name, .. }] if name == "pkg/audit/triggers.sql"
+    ));
+    assert_eq!(PackageState::Modified, state(package_status(&cfg).await?));
+
+    // An expected hash that does not match leaves vendor/ alone.
+    let pinned_cfg = helper
+        .config
+        .clone()
+        .with_package(
+            "audit",
+            PackageConfig {
+                hash: Some("0".repeat(32)),
+                ..audit
+            },
+        )
+        .build()?;
+    let err = PackageUpdate { packages: vec![] }
+        .execute(&pinned_cfg)
+        .await
+        .err()
+        .expect("a different hash should fail")
+        .to_string();
+    assert!(err.contains(&format!("has hash {}", hash)), "{}", err);
+    assert_eq!(
+        PackageState::Modified,
+        state(package_status(&pinned_cfg).await?)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_git_package() -> Result<(), Box<dyn std::error::Error>> {
+    let repo = tempfile::tempdir()?;
+    std::fs::create_dir(repo.path().join("components"))?;
+    std::fs::write(
+        repo.path().join("components/triggers.sql"),
+        "CREATE TRIGGER audit;",
+    )?;
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(repo.path())
+            .args([
+                "-c",
+                "user.name=spawn",
+                "-c",
+                "user.email=spawn@example.com",
+            ])
+            .args(args)
+            .output()
+            .expect("git should run");
+        assert!(status.status.success(), "{:?}", status);
+        String::from_utf8(status.stdout).unwrap().trim().to_string()
+    };
+    git(&["init", "--quiet"]);
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "Add triggers"]);
+    let commit = git(&["rev-parse", "HEAD"]);
+
+    let op = Operator::new(Memory::default())?.finish();
+    let helper =
+        MigrationTestHelper::new_from_config(MigrationTestHelper::default_config(op).with_package(
+            "audit",
+            PackageConfig {
+                git: Some(repo.path().to_string_lossy().into_owned()),
+                rev: Some(commit.clone()),
+                folder: Some("components".to_string()),
+                ..Default::default()
+            },
+        ));
+    let cfg = helper.load_config().await?;
+
+    PackageUpdate { packages: vec![] }.execute(&cfg).await?;
+    let contents = helper
+        .fs
+        .read("/db/vendor/audit/triggers.sql")
+        .await?
+        .to_vec();
+    assert_eq!(b"CREATE TRIGGER audit;".to_vec(), contents);
+    let statuses = package_status(&cfg).await?;
+    assert_eq!(Some(commit), statuses[0].commit);
+    assert_eq!(PackageState::Ok, statuses[0].state);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_migration_build_with_builtin_backfill() -> Result<(), Box<dyn std::error::Error>> {
+    let helper = MigrationTestHelper::new_empty().await?;
+    let migration_name = helper
+        .create_migration_manual(
+            "backfill-emails",
+            r#"{% from "spawn/backfill.sql" import backfill %}
+{% call backfill("public.users", batch=500, where="email_lower IS NULL", sleep=0.5) %}
+UPDATE users SET email_lower = lower(email) WHERE id IN (SELECT id FROM batch)
+{% endcall %}"#
+                .to_string(),
+        )
+        .await?;
+
+    let built = helper.build_migration(&migration_name, false).await?;
+    assert!(built.contains("last_key \"public\".\"users\".\"id\"%TYPE;"));
+    assert!(
+        built.contains("WHERE (last_key IS NULL OR \"id\" > last_key) AND (email_lower IS NULL)")
+    );
+    assert!(built.contains("LIMIT 500"));
+    assert!(built.contains(
+        "UPDATE users SET email_lower = lower(email) WHERE id IN (SELECT id FROM batch);"
+    ));
+    assert!(built.contains("PERFORM pg_sleep(0.5);"));
+    assert!(built.contains("'backfill %: done, % rows in % batches', 'public.users'"));
+
+    // Built-in components come with spawn rather than the pinned store.
+    helper.pin_migration(&migration_name).await?;
+    assert_eq!(built, helper.build_migration(&migration_name, true).await?);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_apply_waits_for_maintenance_window() -> Result<(), Box<dyn std::error::Error>> {
+    // Windows that are closed and open for the next hour, whenever this runs.
+    let hour = |offset: i64| {
+        (chrono::Utc::now() + chrono::Duration::hours(offset))
+            .format("%H:00")
+            .to_string()
+    };
+    let closed: MaintenanceWindow = format!("{}-{}", hour(2), hour(3)).parse()?;
+    let open: MaintenanceWindow = format!("{}-{}", hour(-1), hour(2)).parse()?;
+
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_apply_waits_for_maintenance_window")
+            .with_environment_config(
+                "dev",
+                EnvironmentConfig {
+                    maintenance_window: Some(closed),
+                    ..Default::default()
+                },
+            ),
+    );
+    helper
+        .create_migration_manual("users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+
+    let scheduled = |schedule: Schedule| ApplyMigration {
+        schedule,
+        ..apply_all()
+    };
+    let err = scheduled(Schedule {
+        no_wait: true,
+        ..Default::default()
+    })
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("applied outside the maintenance window")
+    .to_string();
+    assert!(
+        err.contains(&format!("outside the maintenance window {}", closed)),
+        "{}",
+        err
+    );
+
+    let err = scheduled(Schedule {
+        at: Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+        window: Some(open),
+        no_wait: true,
+    })
+    .execute(&cfg)
+    .await
+    .err()
+    .expect("applied before --at")
+    .to_string();
+    assert!(err.contains("before the time given with --at"), "{}", err);
+    let database = MemoryDatabase::named("test_apply_waits_for_maintenance_window");
+    assert!(!database.executed().join("").contains("CREATE TABLE users"));
+
+    // --window replaces the environment's window.
+    assert!(matches!(
+        scheduled(Schedule {
+            window: Some(open),
+            no_wait: true,
+            ..Default::default()
+        })
+        .execute(&cfg)
+        .await?,
+        Outcome::AppliedMigrations
+    ));
+    assert!(database.executed().join("").contains("CREATE TABLE users"));
+
+    Ok(())
+}
+
+/// Accepts HTTP requests on a local port, answering each with 200 and
+/// sending its body to the returned channel.
+async fn webhook_receiver() -> Result<(String, tokio::sync::mpsc::UnboundedReceiver<String>)> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/hook", listener.local_addr()?);
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read the headers, then as much body as Content-Length says.
+            let body = loop {
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                if n == 0 {
+                    break None;
+                }
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap_or(0))
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break Some(body.to_string());
+                }
+            };
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await;
+            if let Some(body) = body {
+                let _ = sender.send(body);
+            }
+        }
+    });
+    Ok((url, receiver))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_apply_notifies_webhooks() -> Result<(), Box<dyn std::error::Error>> {
+    let (url, mut received) = webhook_receiver().await?;
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_apply_notifies_webhooks").with_notifications(
+            NotificationsConfig {
+                webhooks: vec![
+                    Webhook {
+                        url: Some(url.clone()),
+                        ..Default::default()
+                    },
+                    Webhook {
+                        url: Some(url),
+                        template: Some(r#"{"alert": {{ migration|tojson }}}"#.to_string()),
+                        on: vec![NotifyStatus::Failed],
+                        ..Default::default()
+                    },
+                ],
+            },
+        ),
+    );
+    let users = helper
+        .create_migration_manual("a-users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let orders = helper
+        .create_migration_manual("b-orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+    MemoryDatabase::named("test_apply_notifies_webhooks").fail_on("CREATE TABLE orders");
+
+    assert!(apply_all().execute(&cfg).await.is_err());
+
+    let applied: serde_json::Value = serde_json::from_str(&received.recv().await.unwrap())?;
+    assert_eq!(users.as_str(), applied["migration"]);
+    assert_eq!("applied", applied["status"]);
+    assert!(applied["text"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("Migration {} applied on postgres_psql", users)));
+
+    let failed: serde_json::Value = serde_json::from_str(&received.recv().await.unwrap())?;
+    assert_eq!(orders.as_str(), failed["migration"]);
+    assert_eq!("failed", failed["status"]);
+    assert!(failed["error"].as_str().unwrap().contains(&orders));
+
+    // Only the second webhook wants failures, with its own body.
+    let alert: serde_json::Value = serde_json::from_str(&received.recv().await.unwrap())?;
+    assert_eq!(serde_json::json!({ "alert": orders }), alert);
+    assert!(received.try_recv().is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_apply_sends_spans() -> Result<(), Box<dyn std::error::Error>> {
+    let (url, mut received) = webhook_receiver().await?;
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_apply_sends_spans").with_tracing(
+            TracingConfig {
+                endpoint: Some(url),
+                ..Default::default()
+            },
+        ),
+    );
+    let users = helper
+        .create_migration_manual(
+            "a-users",
+            "CREATE TABLE users (id int);\nCREATE INDEX users_id ON users (id);\n".to_string(),
+        )
+        .await?;
+    let cfg = helper.load_config().await?;
+    apply_all().execute(&cfg).await?;
+    cfg.tracer
+        .finish("migration apply", Vec::new(), None)
+        .await?;
+
+    let trace_id = cfg.tracer.trace_id().unwrap();
+    let history = cfg.new_engine().await?.get_migration_history(None).await?;
+    assert_eq!(Some(format!("trace {}", trace_id)), history[0].description);
+
+    let otlp: serde_json::Value = serde_json::from_str(&received.recv().await.unwrap())?;
+    let spans = otlp["resourceSpans"][0]["scopeSpans"][0]["spans"]
+        .as_array()
+        .unwrap();
+    let named = |name: &str| {
+        spans
+            .iter()
+            .filter(|span| span["name"] == name)
+            .collect::<Vec<_>>()
+    };
+    let command = named("spawn migration apply");
+    let migration = named(&format!("migration {}", users));
+    let statements = named("statement");
+    assert_eq!(
+        (1, 1, 2),
+        (command.len(), migration.len(), statements.len())
+    );
+    assert!(spans.iter().all(|span| span["traceId"] == trace_id));
+    assert_eq!(command[0]["spanId"], migration[0]["parentSpanId"]);
+    assert!(statements
+        .iter()
+        .all(|span| span["parentSpanId"] == migration[0]["spanId"]));
+    assert_eq!(
+        "CREATE INDEX users_id ON users (id);",
+        statements[1]["attributes"][0]["value"]["stringValue"]
+            .as_str()
+            .unwrap()
+            .trim()
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_metrics() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_metrics",
+    ));
+    helper
+        .create_migration_manual("a-users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+    apply_all().execute(&cfg).await?;
+    helper
+        .create_migration_manual("b-orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    helper
+        .create_migration_manual("c-items", "CREATE TABLE items (id int);\n".to_string())
+        .await?;
+
+    let metrics = collect_metrics(&cfg).await?;
+    assert_eq!(
+        (1, 2, 0, 1, 0),
+        (
+            metrics.applied,
+            metrics.pending,
+            metrics.failed,
+            metrics.successful_applies,
+            metrics.failed_applies
+        )
+    );
+    assert!(metrics.last_apply_timestamp.is_some());
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("spawn.prom");
+    Metrics {
+        output: Some(path.to_string_lossy().to_string()),
+    }
+    .execute(&cfg)
+    .await?;
+    let text = std::fs::read_to_string(&path)?;
+    assert!(
+        text.contains("spawn_migrations_pending{target=\"postgres_psql\",environment=\"dev\"} 2\n"),
+        "{}",
+        text
+    );
+    assert!(!dir.path().join("spawn.prom.tmp").exists());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_audit_log() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_audit_log",
+    ));
+    let users = helper
+        .create_migration_manual("a-users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let orders = helper
+        .create_migration_manual("b-orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    MemoryDatabase::named("test_audit_log").fail_on("CREATE TABLE orders");
+
+    // Each command gets its own config and audit session, as in the CLI.
+    let cfg = helper.load_config().await?;
+    let outcome = apply_all().execute(&cfg).await.map(|_| ());
+    let entry = AuditEntry::new(&cfg, "migration apply", outcome.as_ref().map(|_| ()));
+    append_audit_entry(&cfg, &entry).await?;
+
+    let cfg = helper.load_config().await?;
+    AdoptMigration {
+        migration: Some(orders.clone()),
+        description: Some("created by hand".to_string()),
+        yes: true,
+        force: false,
+    }
+    .execute(&cfg)
+    .await?;
+    append_audit_entry(&cfg, &AuditEntry::new(&cfg, "migration adopt", Ok(()))).await?;
+
+    let entries = read_audit_log(&cfg).await?;
+    assert_eq!(2, entries.len());
+    assert_eq!(vec![users.clone(), orders.clone()], entries[0].migrations);
+    assert_eq!(AuditOutcome::Failure, entries[0].outcome);
+    assert!(entries[0].error.as_ref().unwrap().contains(&orders));
+    assert_eq!(Some("postgres_psql".to_string()), entries[0].target);
+    assert_eq!(vec![orders.clone()], entries[1].migrations);
+    assert_eq!(AuditOutcome::Success, entries[1].outcome);
+
+    let show = |limit: Option<usize>, migration: Option<&str>| {
+        AuditShow {
+            limit,
+            migration: migration.map(str::to_string),
+            json: false,
+        }
+        .select(entries.clone())
+        .into_iter()
+        .map(|entry| entry.command)
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["migration adopt"], show(Some(1), None));
+    assert_eq!(vec!["migration apply"], show(None, Some(&users)));
+    assert_eq!(
+        vec!["migration apply", "migration adopt"],
+        show(None, Some(&orders))
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_history_records_operator_identity() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op, "test_history_records_operator_identity")
+            .with_identity("deploy-bot"),
+    );
+    let users = helper
+        .create_migration_manual("a-users", "CREATE TABLE users (id int);\n".to_string())
+        .await?;
+    let orders = helper
+        .create_migration_manual("b-orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+    assert_eq!(Some("deploy-bot".to_string()), cfg.operator_identity());
+
+    ApplyMigration {
+        migration: Some(users.clone()),
+        ..apply_all()
+    }
+    .execute(&cfg)
+    .await?;
+    AdoptMigration {
+        migration: Some(orders.clone()),
+        description: Some("created by hand".to_string()),
+        yes: true,
+        force: false,
+    }
+    .execute(&cfg)
+    .await?;
+
+    let history = cfg.new_engine().await?.get_migration_history(None).await?;
+    let recorded: Vec<(&str, Option<&str>, Option<&str>)> = history
+        .iter()
+        .map(|e| {
+            (
+                e.migration_name.as_str(),
+                e.created_by.as_deref(),
+                e.description.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            (users.as_str(), Some("deploy-bot"), None),
+            (orders.as_str(), Some("deploy-bot"), Some("created by hand")),
+        ],
+        recorded
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_flat_layout() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op.clone(), "test_flat_layout").with_layout(
+            LayoutConfig {
+                migrations_folder: Some("sql".to_string()),
+                script: Some("sql".to_string()),
+                flat: true,
+                ..Default::default()
+            },
+        ),
+    );
+    let users = helper
+        .create_migration_manual(
+            "a-users",
+            "CREATE TABLE users (id int);\n{% include \"./grants.sql\" %}\n".to_string(),
+        )
+        .await?;
+    // Files kept beside a flat migration live in a folder of its name.
+    op.write(
+        &format!("/db/sql/{}/grants.sql", users),
+        "GRANT SELECT ON users TO app;\n",
+    )
+    .await?;
+    helper.pin_migration(&users).await?;
+
+    assert!(op.exists(&format!("/db/sql/{}.sql", users)).await?);
+    assert!(op.exists(&format!("/db/sql/{}.lock.toml", users)).await?);
+    assert!(!op.exists(&format!("/db/sql/{}/lock.toml", users)).await?);
+
+    let cfg = helper.load_config().await?;
+    let fs_status = store::list_migration_fs_status(&op, &cfg.pather(), None).await?;
+    assert_eq!(vec![&users], fs_status.keys().collect::<Vec<_>>());
+    assert!(fs_status[&users].has_up_sql && fs_status[&users].has_lock_toml);
+
+    let built = helper.build_migration(&users, true).await?;
+    assert!(built.contains("GRANT SELECT ON users TO app;"));
+
+    apply_all().execute(&cfg).await?;
+    let status = get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE)).await?;
+    assert_eq!(1, status.len());
+    assert_eq!(Some(MigrationStatus::Success), status[0].last_status);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_sharded_layout() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(
+        MigrationTestHelper::memory_config(op.clone(), "test_sharded_layout").with_layout(
+            LayoutConfig {
+                shard: Some(Shard::Month),
+                ..Default::default()
+            },
+        ),
+    );
+    let users = helper
+        .create_migration_manual(
+            "users",
+            "CREATE TABLE users (id int);\n{% include \"./grants.sql\" %}\n".to_string(),
+        )
+        .await?;
+    let shard = format!("/db/migrations/{}/{}", &users[..4], &users[4..6]);
+    op.write(
+        &format!("{}/{}/grants.sql", shard, users),
+        "GRANT SELECT ON users TO app;\n",
+    )
+    .await?;
+    // An older migration, written by hand into its shard.
+    let old = "20230115090000-base";
+    op.write(
+        &format!("/db/migrations/2023/01/{}/up.sql", old),
+        "CREATE SCHEMA app;\n",
+    )
+    .await?;
+    helper.pin_migration(&users).await?;
+
+    assert!(op.exists(&format!("{}/{}/up.sql", shard, users)).await?);
+    assert!(op.exists(&format!("{}/{}/lock.toml", shard, users)).await?);
+
+    let cfg = helper.load_config().await?;
+    let fs_status = store::list_migration_fs_status(&op, &cfg.pather(), None).await?;
+    assert_eq!(
+        vec![old, users.as_str()],
+        fs_status.keys().map(|k| k.as_str()).collect::<Vec<_>>()
+    );
+    assert!(fs_status[&users].has_lock_toml);
+    let one = store::list_migration_fs_status(&op, &cfg.pather(), Some(&users)).await?;
+    assert_eq!(vec![&users], one.keys().collect::<Vec<_>>());
+
+    let built = helper.build_migration(&users, true).await?;
+    assert!(built.contains("GRANT SELECT ON users TO app;"));
+
+    apply_all().execute(&cfg).await?;
+    let status = get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE)).await?;
+    assert_eq!(2, status.len());
+    assert!(status
+        .iter()
+        .all(|row| row.last_status == Some(MigrationStatus::Success)));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_archive_migrations() -> Result<(), Box<dyn std::error::Error>> {
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op.clone(),
+        "test_archive_migrations",
+    ));
+    let old = "20220301000000-users";
+    op.write(
+        &format!("/db/migrations/{}/up.sql", old),
+        "CREATE TABLE users (id int);\n",
+    )
+    .await?;
+    op.write(&format!("/db/migrations/{}/variables.toml", old), "")
+        .await?;
+    let recent = helper
+        .create_migration_manual("orders", "CREATE TABLE orders (id int);\n".to_string())
+        .await?;
+    let cfg = helper.load_config().await?;
+    apply_all().execute(&cfg).await?;
+    // Old, but not applied, so it stays.
+    let pending = "20220401000000-pending";
+    op.write(&format!("/db/migrations/{}/up.sql", pending), "SELECT 1;\n")
+        .await?;
+
+    let before = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+    assert_eq!(vec![old], archive_candidates(&cfg, before).await?);
+    ArchiveMigrations {
+        before,
+        dry_run: false,
+        yes: true,
+    }
+    .execute(&cfg)
+    .await?;
+
+    assert!(!op.exists(&format!("/db/migrations/{}/up.sql", old)).await?);
+    assert!(
+        op.exists(&format!("/db/migrations/_archive/{}/up.sql", old))
+            .await?
+    );
+    assert!(
+        op.exists(&format!("/db/migrations/_archive/{}/variables.toml", old))
+            .await?
+    );
+
+    let status = get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE)).await?;
+    let archived: Vec<(&str, bool, bool)> = status
+        .iter()
+        .map(|row| {
+            (
+                row.migration_name.as_str(),
+                row.archived,
+                row.exists_in_filesystem,
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            (old, true, true),
+            (pending, false, true),
+            (recent.as_str(), false, true)
+        ],
+        archived
+    );
+    // Archived migrations are applied, so not pending.
+    assert_eq!(vec![pending], get_pending_migrations(&cfg).await?);
+    assert!(archive_candidates(&cfg, before).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_import_flyway() -> Result<(), Box<dyn std::error::Error>> {
+    let flyway = tempfile::tempdir()?;
+    std::fs::create_dir(flyway.path().join("sql"))?;
+    let write = |path: &str, contents: &str| std::fs::write(flyway.path().join(path), contents);
+    write("V1__Create_users.sql", "CREATE TABLE users (id int);\n")?;
+    write(
+        "sql/V1_1__Add_email.sql",
+        "ALTER TABLE users ADD email text;\n",
+    )?;
+    write("V2__Comment.sql", "-- {{ not a template }}\nSELECT 1;\n")?;
+    write("R__Views.sql", "CREATE OR REPLACE VIEW v AS SELECT 1;\n")?;
+    write("U2__Comment.sql", "SELECT 1;\n")?;
+    write("Users.sql", "SELECT 1;\n")?;
+
+    let op = Operator::new(Memory::default())?.finish();
+    let helper = MigrationTestHelper::new_from_config(MigrationTestHelper::memory_config(
+        op,
+        "test_import_flyway",
+    ));
+    MemoryDatabase::named("test_import_flyway").answer(
+        "FROM \"flyway_schema_history\"",
+        Rows {
+            columns: vec!["version".to_string()],
+            rows: vec![vec![Some("1".to_string())], vec![Some("1.1".to_string())]],
+        },
+    );
+    let report = flyway.path().join("report.md");
+    let import = || Import {
+        from: ImportSource::Flyway,
+        dir: flyway.path().to_string_lossy().into_owned(),
+        adopt: true,
+        history_table: None,
+        report: Some(report.to_string_lossy().into_owned()),
+        yes: true,
+        force: false,
+    };
+    import().execute(&helper.load_config().await?).await?;
+
+    let cfg = helper.load_config().await?;
+    let status = get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE)).await?;
+    let names: Vec<&str> = status
+        .iter()
+        .map(|row| row.migration_name.split_once('-').unwrap().1)
+        .collect();
+    assert_eq!(
+        vec!["v1-create-users", "v1_1-add-email", "v2-comment"],
+        names
+    );
+    let adopted: Vec<Option<MigrationStatus>> = status.iter().map(|row| row.last_status).collect();
+    assert_eq!(
+        vec![
+            Some(MigrationStatus::Success),
+            Some(MigrationStatus::Success),
+            None
+        ],
+        adopted
+    );
+
+    // Template syntax in imported scripts is kept as written.
+    let built = helper
+        .build_migration(&status[2].migration_name, false)
+        .await?;
+    assert!(built.contains("-- {{ not a template }}"));
+
+    let text = std::fs::read_to_string(&report)?;
+    assert!(text.contains("3 imported, 0 already imported, 2 adopted, 3 skipped."));
+    assert!(text.contains("`R__Views.sql`: repeatable migrations are not imported"));
+    assert!(text.contains("`U2__Comment.sql`: undo migrations are not imported"));
+    assert!(text.contains("`Users.sql`: not named like a Flyway migration"));
+
+    // Importing again adds nothing.
+    import().execute(&helper.load_config().await?).await?;
+    let text = std::fs::read_to_string(&report)?;
+    assert!(text.contains("0 imported, 3 already imported, 0 adopted, 3 skipped."));
+    assert_eq!(
+        3,
+        get_combined_migration_status(&cfg, Some(DEFAULT_NAMESPACE))
+            .await?
+            .len()
+    );
+
+    Ok(())
+}